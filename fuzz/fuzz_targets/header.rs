@@ -0,0 +1,11 @@
+#![no_main]
+
+use cloaksdb::header::Header;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = Header::deserialize(data) {
+        let again = Header::deserialize(&header.serialize()).expect("reencoded header decodes");
+        assert_eq!(format!("{:?}", again), format!("{:?}", header));
+    }
+});
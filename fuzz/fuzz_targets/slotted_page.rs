@@ -0,0 +1,19 @@
+#![no_main]
+
+use cloaksdb::slotted_page::SlottedPage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(page) = SlottedPage::<i64, Vec<u8>>::deserialize(data, data.len()) else {
+        return;
+    };
+
+    // Whatever decodes must be safe to read and to write back out
+    for idx in 0..page.slots.len() {
+        let _ = page.read_key_value(idx);
+    }
+    if let Ok(bytes) = page.serialize() {
+        SlottedPage::<i64, Vec<u8>>::deserialize(&bytes, bytes.len())
+            .expect("reencoded page decodes");
+    }
+});
@@ -0,0 +1,58 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use cloaksdb::BTree;
+use cloaksdb::config::TreeConfig;
+use cloaksdb::error::BTreeError;
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+
+// Values vary in length up to this, so splits and merges see entries of different sizes
+const MAX_VALUE_LEN: usize = 40;
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Insert(u16, Vec<u8>),
+    Delete(u16),
+    Search(u16),
+    Flush,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    subtree_counts: bool,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let config = TreeConfig {
+        subtree_counts: input.subtree_counts,
+        ..TreeConfig::with_page_size(256)
+    };
+    let mut btree = BTree::<u16, Vec<u8>>::in_memory(config).unwrap();
+    let mut model = BTreeMap::new();
+
+    for op in input.ops {
+        match op {
+            Op::Insert(key, mut value) => {
+                value.truncate(MAX_VALUE_LEN);
+                btree.insert(key, value.clone()).unwrap();
+                model.insert(key, value);
+            }
+            Op::Delete(key) => match (btree.delete(key), model.remove(&key)) {
+                (Ok(got), Some(expected)) => assert_eq!(got, expected),
+                (Err(BTreeError::KeyNotFound(_)), None) => {}
+                (got, expected) => panic!("delete {} gave {:?}, expected {:?}", key, got, expected),
+            },
+            Op::Search(key) => match (btree.search(key), model.get(&key)) {
+                (Ok(got), Some(expected)) => assert_eq!(&got, expected),
+                (Err(BTreeError::KeyNotFound(_)), None) => {}
+                (got, expected) => panic!("search {} gave {:?}, expected {:?}", key, got, expected),
+            },
+            Op::Flush => btree.flush().unwrap(),
+        }
+    }
+
+    btree.verify().unwrap();
+    assert_eq!(btree.len(), model.len() as u64);
+});
@@ -0,0 +1,518 @@
+use crate::btree::BTree;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+type BenchTree = BTree<String, Vec<u8>>;
+
+/// A single benchmark, named after its db_bench equivalent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// Insert `num` keys in ascending order into a fresh tree.
+    FillSeq,
+    /// Insert `num` keys in random order into a fresh tree.
+    FillRandom,
+    /// Rewrite `num` random keys of the existing tree.
+    Overwrite,
+    /// Look up `reads` random keys.
+    ReadRandom,
+    /// Scan up to `reads` entries in key order.
+    ReadSeq,
+    /// Look up `reads` random keys from `threads` readers at once, while one writer keeps
+    /// overwriting random keys until they are done. Readers and writer share the tree behind a
+    /// single lock, as users of a shared handle do.
+    ReadWhileWriting,
+}
+
+impl Workload {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::FillSeq => "fillseq",
+            Workload::FillRandom => "fillrandom",
+            Workload::Overwrite => "overwrite",
+            Workload::ReadRandom => "readrandom",
+            Workload::ReadSeq => "readseq",
+            Workload::ReadWhileWriting => "readwhilewriting",
+        }
+    }
+
+    fn starts_fresh(&self) -> bool {
+        matches!(self, Workload::FillSeq | Workload::FillRandom)
+    }
+}
+
+impl std::str::FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fillseq" => Ok(Workload::FillSeq),
+            "fillrandom" => Ok(Workload::FillRandom),
+            "overwrite" => Ok(Workload::Overwrite),
+            "readrandom" => Ok(Workload::ReadRandom),
+            "readseq" => Ok(Workload::ReadSeq),
+            "readwhilewriting" => Ok(Workload::ReadWhileWriting),
+            other => Err(format!("Unknown benchmark: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Run in order against the same database, like db_bench's `--benchmarks`.
+    pub workloads: Vec<Workload>,
+    /// Number of keys written by the fill and overwrite workloads.
+    pub num: usize,
+    /// Number of reads; defaults to `num`.
+    pub reads: Option<usize>,
+    pub value_size: usize,
+    pub page_size: u64,
+    pub cache_size: usize,
+    /// Reader threads of `readwhilewriting`.
+    pub threads: usize,
+    pub seed: u64,
+    /// Database file to use; a temporary file if unset. Its contents are replaced.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            workloads: vec![
+                Workload::FillSeq,
+                Workload::ReadRandom,
+                Workload::ReadSeq,
+                Workload::FillRandom,
+                Workload::Overwrite,
+            ],
+            num: 10_000,
+            reads: None,
+            value_size: 100,
+            page_size: TreeConfig::default().page_size,
+            cache_size: TreeConfig::default().cache_size,
+            threads: 1,
+            seed: 301,
+            path: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub workload: Workload,
+    pub ops: usize,
+    /// Reads that found their key; equal to `ops` for writes.
+    pub found: usize,
+    pub elapsed: Duration,
+    // Sorted ascending
+    latencies: Vec<Duration>,
+}
+
+impl BenchResult {
+    fn new(
+        workload: Workload,
+        found: usize,
+        elapsed: Duration,
+        mut latencies: Vec<Duration>,
+    ) -> Self {
+        latencies.sort_unstable();
+        BenchResult {
+            workload,
+            ops: latencies.len(),
+            found,
+            elapsed,
+            latencies,
+        }
+    }
+
+    pub fn ops_per_sec(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.ops as f64 / seconds
+    }
+
+    pub fn micros_per_op(&self) -> f64 {
+        if self.ops == 0 {
+            return 0.0;
+        }
+        self.elapsed.as_secs_f64() * 1e6 / self.ops as f64
+    }
+
+    /// Latency below which `percentile` percent of operations completed (nearest rank).
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl std::fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let micros = |d: Duration| d.as_secs_f64() * 1e6;
+        write!(
+            f,
+            "{:<12}: {:>10.3} micros/op {:>10.0} ops/sec; p50 {:.1} p99 {:.1} p99.9 {:.1} max {:.1} micros",
+            self.workload.name(),
+            self.micros_per_op(),
+            self.ops_per_sec(),
+            micros(self.percentile(50.0)),
+            micros(self.percentile(99.0)),
+            micros(self.percentile(99.9)),
+            micros(self.percentile(100.0)),
+        )?;
+        if self.found != self.ops {
+            write!(f, " ({} of {} found)", self.found, self.ops)?;
+        }
+        Ok(())
+    }
+}
+
+fn bench_key(i: usize) -> String {
+    format!("{:016}", i)
+}
+
+struct Bench {
+    options: BenchOptions,
+    file: File,
+    tree: BenchTree,
+    rng: StdRng,
+    // Kept alive so the temporary database is removed once the run finishes
+    _temp: Option<tempfile::NamedTempFile>,
+}
+
+impl Bench {
+    fn new(options: BenchOptions) -> Result<Self, BTreeError> {
+        let (file, temp) = match &options.path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?;
+                (file, None)
+            }
+            None => {
+                let temp = tempfile::NamedTempFile::new()?;
+                (temp.reopen()?, Some(temp))
+            }
+        };
+
+        let tree = Self::open_tree(&file, &options)?;
+        Ok(Bench {
+            rng: StdRng::seed_from_u64(options.seed),
+            options,
+            file,
+            tree,
+            _temp: temp,
+        })
+    }
+
+    fn open_tree(file: &File, options: &BenchOptions) -> Result<BenchTree, BTreeError> {
+        let config = TreeConfig {
+            cache_size: options.cache_size,
+            ..TreeConfig::with_page_size(options.page_size)
+        };
+        BTree::with_config(file.try_clone()?, config)
+    }
+
+    fn reset(&mut self) -> Result<(), BTreeError> {
+        self.file.set_len(0)?;
+        self.tree = Self::open_tree(&self.file, &self.options)?;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        let mut value = vec![0u8; self.options.value_size];
+        self.rng.fill_bytes(&mut value);
+        value
+    }
+
+    fn write(&mut self, keys: Vec<usize>, workload: Workload) -> Result<BenchResult, BTreeError> {
+        let mut latencies = Vec::with_capacity(keys.len());
+        let start = Instant::now();
+        for i in keys {
+            let value = self.value();
+            let op_start = Instant::now();
+            self.tree.insert(bench_key(i), value)?;
+            latencies.push(op_start.elapsed());
+        }
+        self.tree.flush()?;
+        let found = latencies.len();
+        Ok(BenchResult::new(
+            workload,
+            found,
+            start.elapsed(),
+            latencies,
+        ))
+    }
+
+    fn read_random(&mut self, reads: usize) -> Result<BenchResult, BTreeError> {
+        let mut latencies = Vec::with_capacity(reads);
+        let mut found = 0;
+        let start = Instant::now();
+        for _ in 0..reads {
+            let key = bench_key(self.rng.random_range(0..self.options.num.max(1)));
+            let op_start = Instant::now();
+            match self.tree.search(key) {
+                Ok(_) => found += 1,
+                Err(BTreeError::KeyNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            latencies.push(op_start.elapsed());
+        }
+        Ok(BenchResult::new(
+            Workload::ReadRandom,
+            found,
+            start.elapsed(),
+            latencies,
+        ))
+    }
+
+    fn read_seq(&mut self, reads: usize) -> Result<BenchResult, BTreeError> {
+        let mut latencies = Vec::with_capacity(reads);
+        let start = Instant::now();
+        let mut entries = self.tree.iter()?;
+        for _ in 0..reads {
+            let op_start = Instant::now();
+            match entries.next() {
+                Some(entry) => {
+                    entry?;
+                }
+                None => break,
+            }
+            latencies.push(op_start.elapsed());
+        }
+        let found = latencies.len();
+        Ok(BenchResult::new(
+            Workload::ReadSeq,
+            found,
+            start.elapsed(),
+            latencies,
+        ))
+    }
+
+    fn read_while_writing(&mut self, reads: usize) -> Result<BenchResult, BTreeError> {
+        let threads = self.options.threads.max(1);
+        let num = self.options.num.max(1);
+        let value_size = self.options.value_size;
+        let mut writer_rng = StdRng::seed_from_u64(self.rng.next_u64());
+        let reader_seeds: Vec<u64> = (0..threads).map(|_| self.rng.next_u64()).collect();
+        let tree = &Mutex::new(&mut self.tree);
+        let readers_done = &AtomicUsize::new(0);
+
+        let start = Instant::now();
+        let (written, outcomes) = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || -> Result<usize, BTreeError> {
+                let mut written = 0;
+                while readers_done.load(Ordering::Acquire) < threads {
+                    let key = bench_key(writer_rng.random_range(0..num));
+                    let mut value = vec![0u8; value_size];
+                    writer_rng.fill_bytes(&mut value);
+                    tree.lock().unwrap().insert(key, value)?;
+                    written += 1;
+                }
+                Ok(written)
+            });
+            let readers: Vec<_> = reader_seeds
+                .into_iter()
+                .enumerate()
+                .map(|(thread, seed)| {
+                    // Spreads the remainder over the first readers
+                    let share = reads / threads + usize::from(thread < reads % threads);
+                    scope.spawn(move || {
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        let mut latencies = Vec::with_capacity(share);
+                        let mut found = 0;
+                        let mut outcome = Ok(());
+                        for _ in 0..share {
+                            let key = bench_key(rng.random_range(0..num));
+                            let op_start = Instant::now();
+                            match tree.lock().unwrap().search(key) {
+                                Ok(_) => found += 1,
+                                Err(BTreeError::KeyNotFound(_)) => {}
+                                Err(e) => {
+                                    outcome = Err(e);
+                                    break;
+                                }
+                            }
+                            latencies.push(op_start.elapsed());
+                        }
+                        readers_done.fetch_add(1, Ordering::Release);
+                        outcome.map(|()| (found, latencies))
+                    })
+                })
+                .collect();
+            let outcomes: Vec<_> = readers.into_iter().map(|r| r.join().unwrap()).collect();
+            (writer.join().unwrap(), outcomes)
+        });
+        let elapsed = start.elapsed();
+
+        let mut latencies = Vec::with_capacity(reads);
+        let mut found = 0;
+        for outcome in outcomes {
+            let (reader_found, reader_latencies) = outcome?;
+            found += reader_found;
+            latencies.extend(reader_latencies);
+        }
+        info!("Wrote {} entries while {} readers ran", written?, threads);
+        self.tree.flush()?;
+        Ok(BenchResult::new(
+            Workload::ReadWhileWriting,
+            found,
+            elapsed,
+            latencies,
+        ))
+    }
+
+    fn run(&mut self, workload: Workload) -> Result<BenchResult, BTreeError> {
+        if workload.starts_fresh() {
+            self.reset()?;
+        }
+
+        let num = self.options.num;
+        let reads = self.options.reads.unwrap_or(num);
+        match workload {
+            Workload::FillSeq => self.write((0..num).collect(), workload),
+            Workload::FillRandom => {
+                let mut keys: Vec<usize> = (0..num).collect();
+                keys.shuffle(&mut self.rng);
+                self.write(keys, workload)
+            }
+            Workload::Overwrite => {
+                let keys = (0..num)
+                    .map(|_| self.rng.random_range(0..num.max(1)))
+                    .collect();
+                self.write(keys, workload)
+            }
+            Workload::ReadRandom => self.read_random(reads),
+            Workload::ReadSeq => self.read_seq(reads),
+            Workload::ReadWhileWriting => self.read_while_writing(reads),
+        }
+    }
+}
+
+/// Runs `options.workloads` in order against one database and returns a result per workload.
+pub fn run(options: BenchOptions) -> Result<Vec<BenchResult>, BTreeError> {
+    let workloads = options.workloads.clone();
+    let mut bench = Bench::new(options)?;
+
+    workloads
+        .into_iter()
+        .map(|workload| {
+            info!("Running {}", workload.name());
+            bench.run(workload)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_options(workloads: Vec<Workload>) -> BenchOptions {
+        BenchOptions {
+            workloads,
+            num: 300,
+            value_size: 16,
+            page_size: 512,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workload_names_roundtrip() {
+        for workload in BenchOptions::default().workloads {
+            assert_eq!(workload.name().parse::<Workload>(), Ok(workload));
+        }
+        assert!("fillsync".parse::<Workload>().is_err());
+    }
+
+    #[test]
+    fn reads_find_every_filled_key() {
+        let results = run(small_options(vec![
+            Workload::FillRandom,
+            Workload::ReadRandom,
+            Workload::ReadSeq,
+        ]))
+        .unwrap();
+
+        assert_eq!(results.len(), 3);
+        for result in &results {
+            assert_eq!(result.ops, 300);
+            assert_eq!(result.found, 300, "{}", result);
+        }
+    }
+
+    #[test]
+    fn fill_starts_from_an_empty_tree() {
+        let options = BenchOptions {
+            reads: Some(1000),
+            ..small_options(vec![
+                Workload::FillSeq,
+                Workload::Overwrite,
+                Workload::FillSeq,
+                Workload::ReadSeq,
+            ])
+        };
+        let results = run(options).unwrap();
+
+        // The scan stops at the end of the tree, which only holds one fill's worth of keys
+        assert_eq!(results[3].ops, 300);
+    }
+
+    #[test]
+    fn readers_find_every_key_while_it_is_overwritten() {
+        let options = BenchOptions {
+            threads: 3,
+            reads: Some(1000),
+            ..small_options(vec![Workload::FillSeq, Workload::ReadWhileWriting])
+        };
+        let results = run(options).unwrap();
+
+        assert_eq!(results[1].ops, 1000);
+        assert_eq!(results[1].found, 1000, "{}", results[1]);
+    }
+
+    #[test]
+    fn reads_of_an_empty_tree_find_nothing() {
+        let options = BenchOptions {
+            cache_size: 8,
+            ..small_options(vec![Workload::ReadRandom])
+        };
+        let results = run(options).unwrap();
+
+        assert_eq!(results[0].ops, 300);
+        assert_eq!(results[0].found, 0);
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let latencies = (1..=100).map(Duration::from_micros).collect();
+        let result = BenchResult::new(
+            Workload::ReadRandom,
+            100,
+            Duration::from_millis(1),
+            latencies,
+        );
+
+        assert_eq!(result.percentile(50.0), Duration::from_micros(50));
+        assert_eq!(result.percentile(99.0), Duration::from_micros(99));
+        assert_eq!(result.percentile(100.0), Duration::from_micros(100));
+        assert_eq!(result.percentile(0.0), Duration::from_micros(1));
+        assert_eq!(result.ops_per_sec(), 100_000.0);
+    }
+}
@@ -0,0 +1,77 @@
+use cloaksdb::BTree;
+use cloaksdb::config::TreeConfig;
+use cloaksdb::server::{Protocol, Server};
+
+const USAGE: &str = "Usage: cloaksdb-server [--path FILE] [--addr HOST:PORT] [--page-size BYTES] [--protocol binary|resp]";
+
+struct Args {
+    path: String,
+    addr: String,
+    page_size: u64,
+    protocol: Protocol,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        path: "cloaksdb.db".to_string(),
+        addr: "127.0.0.1:7878".to_string(),
+        page_size: TreeConfig::default().page_size,
+        protocol: Protocol::Binary,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("Missing value for {}", flag));
+        match flag.as_str() {
+            "--path" => args.path = value()?,
+            "--addr" => args.addr = value()?,
+            "--page-size" => {
+                args.page_size = value()?
+                    .parse()
+                    .map_err(|e| format!("Invalid --page-size: {}", e))?
+            }
+            "--protocol" => args.protocol = value()?.parse()?,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            other => return Err(format!("Unknown argument: {}\n{}", other, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let tree =
+        BTree::open(&args.path, TreeConfig::with_page_size(args.page_size)).unwrap_or_else(|e| {
+            eprintln!("Failed to open {}: {}", args.path, e);
+            std::process::exit(1);
+        });
+    let server = Server::bind(&args.addr, tree)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to listen on {}: {}", args.addr, e);
+            std::process::exit(1);
+        })
+        .with_protocol(args.protocol);
+
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown.shutdown()).expect("Failed to install signal handler");
+
+    println!(
+        "Serving {} on {} ({:?} protocol)",
+        args.path,
+        server.local_addr().expect("Listener has an address"),
+        args.protocol
+    );
+    if let Err(e) = server.run() {
+        eprintln!("Failed to flush {}: {}", args.path, e);
+        std::process::exit(1);
+    }
+}
@@ -1,19 +1,148 @@
+use crate::buffer_pool::BufferPool;
+use crate::clock::Instant;
+use crate::codec::{CodecError, Codecs, Encoding, ValueCodec};
+use crate::collections::{HashMap, HashSet};
+use crate::compression::ValueCompressor;
+use crate::config::{
+    Backpressure, ChecksumAlgorithm, CompressionAlgorithm, DeleteStrategy, DuplicatePolicy,
+    InvariantViolation, TreeConfig, WriteMode,
+};
+use crate::constants::SEQUENCE_VERSION;
+#[cfg(test)]
 use crate::constants::VERSION;
 use crate::error::BTreeError;
+use crate::events::{
+    CompactEvent, FlushEvent, MergeEvent, SplitEvent, StaleSnapshotEvent, TreeObserver,
+    WriteStallEvent,
+};
+use crate::free_space::{DEFAULT_MAX_FREE_REGIONS, FitPolicy};
 use crate::header::Header;
-use crate::page_manager::PageManager;
+use crate::import::{ImportOptions, ImportProgress};
+use crate::instrument::{self, op_span};
+#[cfg(feature = "std")]
+use crate::manifest::{Manifest, ManifestError};
+use crate::memory::MemoryUsage;
+use crate::merkle::{Hash, MerkleCache, PageHasher};
+use crate::page_manager::{PageManager, PageManagerError};
+use crate::pagination::{ContinuationToken, ScanPage};
+use crate::quarantine::{Backup, QuarantineReport, QuarantinedPage};
+use crate::range_lock::{LockOwner, RangeLock, RangeLocks};
+use crate::raw::RawEntry;
+use crate::recovery::HeaderRepair;
+#[cfg(feature = "std")]
+use crate::registry::{self, Registration};
+#[cfg(feature = "std")]
+use crate::scrub::{PageScrub, ScrubOptions, ScrubReport, Scrubber};
+use crate::search::InterpolationKey;
+use crate::sequence::{MAX_SEQUENCE_NAME, Sequences};
 use crate::slotted_page::SlottedPage;
-use crate::types::NodeType;
+#[cfg(feature = "std")]
+use crate::snapshot::Snapshot;
+use crate::stats::TreeStats;
+#[cfg(feature = "std")]
+use crate::storage;
+use crate::storage::{MemoryStorage, Storage};
+use crate::tiering::{TieringPolicy, TieringReport};
+use crate::types::{self, NodeType, PAGE_PREFIX_SIZE};
+use crate::value_log::{ValueLog, ValueLogCompactionOptions, ValueLogGc};
+use crate::watch::{KeyFilter, Watch, Watchers};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds};
+use core::time::Duration;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+use log::{debug, error, info, trace, warn};
+
+/// A key/value pair promoted out of a split page, along with the new right sibling.
+type SplitResult<K, V> = Option<(K, V, SlottedPage<K, V>)>;
+
+/// A leaf to quarantine: the (parent, child index) steps leading to it from the root, its id,
+/// and the separators bounding its keys.
+type LocatedLeaf<K> = (Vec<(u64, usize)>, u64, Option<K>, Option<K>);
+
+/// Combines the stored value of a key with the value being inserted for it into the value to
+/// store, for trees using `DuplicatePolicy::Resolve`. Called as `resolver(key, stored, new)`.
+pub type DuplicateResolver<K, V> = dyn Fn(&K, V, V) -> V + Send + Sync;
+
+/// Orders the values stored under one key, for trees using `DuplicatePolicy::KeepSorted`.
+pub type DuplicateOrder<V> = dyn Fn(&V, &V) -> core::cmp::Ordering + Send + Sync;
+
+/// Position of a write among all those committed to a tree, counting from 1. Each insert,
+/// delete, bulk load, clear and quarantine is given the next one.
+pub type Lsn = u64;
+
+// Source of tree epochs, shared by every tree in the process so no two handles ever have the
+// same one
+static NEXT_EPOCH: crate::sync::Mutex<u64> = crate::sync::Mutex::new(1);
 
-use log::{debug, error, info, trace};
+fn next_epoch() -> u64 {
+    let mut next = NEXT_EPOCH.lock();
+    *next += 1;
+    *next - 1
+}
 
 pub struct BTree<K, V> {
     header: Header,
+    // The header as last written, so it is only written again once it has changed
+    written_header: [u8; Header::SIZE],
     page_manager: PageManager,
+    compressor: Option<Arc<ValueCompressor>>,
+    // Encodes values in place of the header's encoding, for trees recording a custom codec
+    value_codec: Option<Arc<dyn ValueCodec<V>>>,
+    // Where values above the configured threshold are kept instead of in the pages
+    value_log: Option<Arc<ValueLog>>,
+    // Set when pages find keys by interpolation search
+    interpolator: Option<fn(&K) -> u64>,
+    // Whether pages are written with their slots in eytzinger order
+    eytzinger_layout: bool,
+    // How pages place entries in their free regions, and how many regions they keep
+    fit_policy: FitPolicy,
+    max_free_regions: usize,
+    observers: Vec<Arc<dyn TreeObserver>>,
+    watchers: Watchers<K, V>,
+    range_locks: RangeLocks<K>,
+    // How the header was rebuilt when the tree was opened, if it could not be read
+    header_repair: Option<HeaderRepair>,
+    duplicate_resolver: Option<Box<DuplicateResolver<K, V>>>,
+    duplicate_order: Option<Box<DuplicateOrder<V>>>,
+    writes_since_flush: u64,
+    // What a write does once the page manager's dirty page limit is reached
+    backpressure: Backpressure,
+    on_invariant_violation: InvariantViolation,
+    // Why the handle was poisoned, once it has been
+    poisoned: Option<String>,
+    // Subtree hashes kept between calls to `root_hash`, when turned on
+    merkle: Option<MerkleCache>,
+    // Age past which a snapshot keeping value log garbage around is reported to observers
+    snapshot_age_alert: Option<Duration>,
+    // Changes whenever the tree is rewritten as a whole, so that a `RangeCursor` can tell the
+    // tree is no longer the one it started on. Inserts and deletes leave it alone; cursors
+    // notice those through the versions of their pages.
+    epoch: u64,
+    stats: TreeStats,
+    // Kept in the stats page along with the stats
+    sequences: Sequences,
+    // Data file of a tree opened by path, whose manifest is rewritten on every flush
+    #[cfg(feature = "std")]
+    data_path: Option<PathBuf>,
+    #[cfg(feature = "std")]
+    checkpoint: u64,
+    // Entry in the process-wide record of open paths, removed when the tree is dropped
+    #[cfg(feature = "std")]
+    registration: Option<Registration>,
 
     _phantom: PhantomData<(K, V)>,
 }
@@ -23,1339 +152,10339 @@ where
     K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
     V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
 {
-    pub fn new(file: File, page_size: u64) -> Result<BTree<K, V>, BTreeError> {
-        debug!("Initialising BTree({:?}, {})", file, page_size);
-        let mut page_manager = PageManager::new(file, page_size, Header::SIZE as u64);
-        let mut header = match Self::read_header(&mut page_manager) {
-            Ok(header) => header,
-            Err(e) => {
-                error!("After attempting to read header: {:?}", e);
-                Header::new(1, VERSION, page_size, 0, 0)
-            }
-        };
-        info!("Initialised header: {:?}", header);
+    /// Pages whose live entries fill less than this percentage of them after a delete are
+    /// merged with a sibling.
+    pub const MERGE_OCCUPANCY: u8 = 25;
 
-        if header.pages_empty() {
-            // Called when header is initialised above or if, for some reason, the header is
-            // created without a root page
+    // Pages reserved at a time while bulk loading
+    const BULK_LOAD_EXTENT: u64 = 64;
 
-            let root_page = Self::create_page(&mut header, NodeType::LEAF, &mut page_manager);
-            header.add_root_page(root_page.page_id);
+    // Pages whose values are copied before the copies are synced and the pages written
+    const VALUE_LOG_REWRITE_BATCH: usize = 64;
 
-            info!("Adding root page: {}", root_page.page_id);
+    #[cfg(feature = "std")]
+    pub fn new(file: File, page_size: u64) -> Result<BTree<K, V>, BTreeError> {
+        Self::with_config(file, TreeConfig::with_page_size(page_size))
+    }
 
-            let mut btree = BTree::<K, V> {
-                header: header,
-                page_manager: page_manager,
-                _phantom: PhantomData,
-            };
+    /// Opens the tree in a file that is already open, such as a descriptor handed over by a
+    /// more privileged process, creating it with `config` if the file is empty. Unlike `new`,
+    /// nothing about the file is taken on trust: it must be a regular file open for reading and
+    /// writing, and a header already in it must be intact, of a format version this build
+    /// reads and for `config.page_size`. Reads and writes are positional, so where the file's
+    /// cursor is does not matter. The file is locked for as long as the tree is open.
+    #[cfg(feature = "std")]
+    pub fn from_file(file: File, config: TreeConfig) -> Result<BTree<K, V>, BTreeError> {
+        config.validate()?;
+        debug!("Initialising BTree from open file ({:?})", config);
+        let existing = file.metadata()?.len() > 0;
+        let mut page_manager = PageManager::from_file(file, config.page_size, Header::SIZE as u64)?;
+        if existing {
+            Self::read_header(&mut page_manager)?.validate(config.page_size)?;
+        }
+        Self::from_page_manager(page_manager, config, false)
+    }
 
-            BTree::<K, V>::write_header(&mut btree.header, &mut btree.page_manager)?;
-            BTree::<K, V>::write_page(&root_page, &mut btree.page_manager)?;
+    /// Opens the tree stored in `storage`, creating it with `config` if the storage is empty.
+    /// An existing tree keeps the configuration persisted in its header. Storage holding pages
+    /// under a header that cannot be read fails to open, rather than have a new tree started
+    /// over them; see [`open_with_recovery`](Self::open_with_recovery).
+    pub fn with_config<S: Storage + 'static>(
+        storage: S,
+        config: TreeConfig,
+    ) -> Result<BTree<K, V>, BTreeError> {
+        config.validate()?;
+        debug!("Initialising BTree({:?})", config);
+        let page_manager = PageManager::new(storage, config.page_size, Header::SIZE as u64)?;
+        Self::from_page_manager(page_manager, config, false)
+    }
 
-            Self::read_header(&mut btree.page_manager)?;
+    /// Creates a tree whose pages live only in memory, for tests and ephemeral indexes. It
+    /// behaves exactly like a file-backed tree but is lost when dropped.
+    pub fn in_memory(config: TreeConfig) -> Result<BTree<K, V>, BTreeError> {
+        let mut btree = Self::with_config(MemoryStorage::new(), config)?;
+        btree.value_log = Some(Arc::new(ValueLog::in_memory()));
+        Ok(btree)
+    }
 
-            return Ok(btree);
-        }
+    /// Creates an empty tree in an unnamed temporary file, which the OS removes once the tree
+    /// is dropped, for scratch indexes and tests.
+    #[cfg(feature = "std")]
+    pub fn temporary(page_size: u64) -> Result<BTree<K, V>, BTreeError> {
+        Self::new(tempfile::tempfile()?, page_size)
+    }
 
-        let btree = BTree::<K, V> {
-            header: header,
-            page_manager: page_manager,
-            _phantom: PhantomData,
-        };
+    /// Creates an empty tree in memory, as there is no filesystem to put a temporary file in.
+    #[cfg(not(feature = "std"))]
+    pub fn temporary(page_size: u64) -> Result<BTree<K, V>, BTreeError> {
+        Self::in_memory(TreeConfig::with_page_size(page_size))
+    }
+
+    /// Opens the tree at `path`, creating the file if needed. Unlike `new`, the file is locked
+    /// for as long as the tree is open, so a second `open` of the same path fails instead of
+    /// both handles corrupting each other's pages: with `BTreeError::AlreadyOpen` from within
+    /// this process, however the path is spelled, and with `PageManagerError::Locked` from
+    /// another.
+    ///
+    /// Every flush also rewrites a [`Manifest`] describing the database next to the file.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P, config: TreeConfig) -> Result<BTree<K, V>, BTreeError> {
+        let registration = registry::register(path.as_ref())?;
+        let mut btree = Self::open_unregistered(path, config, false)?;
+        btree.registration = Some(registration);
         Ok(btree)
     }
 
-    fn read_header(page_manager: &mut PageManager) -> Result<Header, BTreeError> {
-        let buffer = page_manager.read_header()?;
-        trace!("read_header: buffer {:?}", buffer);
-        Ok(Header::deserialize(&buffer)?)
+    /// Opens the tree at `path` as `open` does, except that a header that cannot be read is
+    /// rebuilt from a scan of the pages instead of failing, taking what the pages do not record
+    /// from `config`. What the rebuild found is reported by
+    /// [`header_repair`](Self::header_repair); see [`crate::recovery`].
+    #[cfg(feature = "std")]
+    pub fn open_with_recovery<P: AsRef<Path>>(
+        path: P,
+        config: TreeConfig,
+    ) -> Result<BTree<K, V>, BTreeError> {
+        let registration = registry::register(path.as_ref())?;
+        let mut btree = Self::open_unregistered(path, config, true)?;
+        btree.registration = Some(registration);
+        Ok(btree)
     }
 
-    fn create_page(
-        header: &mut Header,
-        node_type: NodeType,
-        page_manager: &mut PageManager,
-    ) -> SlottedPage<K, V> {
-        header.add_page();
-        Self::write_header(header, page_manager).unwrap();
+    /// Opens the tree at `path` as a handle that can be shared within this process. While it
+    /// is open, further calls for the same path return the same handle rather than failing
+    /// with `BTreeError::AlreadyOpen`; `open` of the path still fails. The tree is closed once
+    /// every clone of the handle has been dropped.
+    #[cfg(feature = "std")]
+    pub fn open_shared<P: AsRef<Path>>(
+        path: P,
+        config: TreeConfig,
+    ) -> Result<Arc<Mutex<BTree<K, V>>>, BTreeError>
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+    {
+        registry::open_shared(
+            path.as_ref(),
+            || Self::open_unregistered(path.as_ref(), config, false),
+            |btree, registration| btree.registration = Some(registration),
+        )
+    }
 
-        let page_id = page_manager.allocate_page().unwrap();
-        info!("Created new page id={}", page_id);
+    #[cfg(feature = "std")]
+    fn open_unregistered<P: AsRef<Path>>(
+        path: P,
+        config: TreeConfig,
+        recover: bool,
+    ) -> Result<BTree<K, V>, BTreeError> {
+        config.validate()?;
+        debug!("Opening BTree({:?}, {:?})", path.as_ref(), config);
+        if !path.as_ref().exists() {
+            Self::create_at(path.as_ref(), config)?;
+        }
+        let page_manager = PageManager::open(&path, config.page_size, Header::SIZE as u64)?;
+        let mut btree = Self::from_page_manager(page_manager, config, recover)?;
 
-        SlottedPage::new(page_id, node_type, header.page_size as usize)
+        let manifest_path = Manifest::path_for(path.as_ref());
+        btree.checkpoint = match Manifest::read(&manifest_path) {
+            Ok(manifest) => manifest.checkpoint,
+            Err(ManifestError::Io(e)) if e.kind() == crate::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                warn!("Ignoring unreadable manifest {:?}: {}", manifest_path, e);
+                0
+            }
+        };
+        let value_log_path = ValueLog::path_for(path.as_ref());
+        if btree.header.value_log_threshold > 0 || value_log_path.exists() {
+            btree.value_log = Some(Arc::new(ValueLog::open(value_log_path)?));
+        }
+        btree.data_path = Some(path.as_ref().to_path_buf());
+        Ok(btree)
     }
 
-    pub fn search(&mut self, key: K) -> Result<V, BTreeError> {
-        self.search_node(&key, self.header.root_page_id)
+    // Creates an empty tree at `path` by building it in a temporary file beside it and renaming
+    // that into place once its header and root are durable, so a crash part way through never
+    // leaves a half-written file at `path`. If another process creates the file first, its
+    // tree is kept.
+    #[cfg(feature = "std")]
+    fn create_at(path: &Path, config: TreeConfig) -> Result<(), BTreeError> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let temp = tempfile::Builder::new()
+            .prefix(".creating-")
+            .tempfile_in(dir)?;
+        Self::with_config(temp.reopen()?, config)?.flush()?;
+        match temp.persist_noclobber(path) {
+            Ok(_) => {}
+            Err(e) if e.error.kind() == crate::io::ErrorKind::AlreadyExists => {
+                debug!("{:?} was created while building a tree for it", path);
+                return Ok(());
+            }
+            Err(e) => return Err(e.error.into()),
+        }
+        // Makes the rename itself durable
+        storage::sync_dir(dir)?;
+        info!("Created tree at {:?}", path);
+        Ok(())
     }
 
-    fn search_node(&mut self, key: &K, page_id: u64) -> Result<V, BTreeError> {
-        let node = self.read_page(page_id)?;
-        match node.node_type {
-            NodeType::INTERNAL => {
-                let key_pos = node.find_exact_key(&key)?;
-                match key_pos {
-                    Some(key_pos) => node.read_value(key_pos),
-                    None => {
-                        let child_node_id = node.get_pointer(&key)?;
-                        self.search_node(key, child_node_id)
+    // Opens the tree in `page_manager`, rebuilding a header that cannot be read if `recover`
+    // is set and failing otherwise, unless there are no pages for a new header to orphan
+    fn from_page_manager(
+        mut page_manager: PageManager,
+        config: TreeConfig,
+        recover: bool,
+    ) -> Result<BTree<K, V>, BTreeError> {
+        page_manager.set_cache_capacity(config.cache_size);
+        page_manager.set_max_size(config.max_file_size);
+        page_manager.set_max_dirty_pages(config.max_dirty_pages);
+        page_manager.set_write_mode(config.write_mode)?;
+        // What is already in storage, or zeroes for a new tree, so the first commit of a new
+        // header writes it
+        let mut written_header = [0u8; Header::SIZE];
+        let mut header_repair = None;
+        let header = match Self::read_header(&mut page_manager) {
+            Ok(header) => {
+                header.check_page_counts()?;
+                written_header = header.serialize();
+                header
+            }
+            Err(_) if page_manager.page_count() == 0 => Header::create(&config),
+            Err(e) if !recover => {
+                error!("Refusing to open pages under an unreadable header: {}", e);
+                return Err(e);
+            }
+            Err(e) => {
+                error!("After attempting to read header: {:?}", e);
+                match Self::scan_for_header(&mut page_manager, &config, e.to_string())? {
+                    Some((header, repair)) => {
+                        header_repair = Some(repair);
+                        header
                     }
+                    None => Header::create(&config),
                 }
             }
-            NodeType::LEAF => {
-                let key_pos = node
-                    .find_exact_key(&key)?
-                    .ok_or(BTreeError::KeyNotFound(key.to_string()))?;
-                node.read_value(key_pos)
-            }
+        };
+        header.check_comparator(config.comparator_id)?;
+        info!("Initialised header: {:?}", header);
+        if !header.pages_empty() {
+            page_manager.restore_page_count(header.next_page_id)?;
         }
-    }
 
-    pub fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError> {
-        info!("Insert key={:?} value={:?}", key, value);
-        let mut root = self.read_page(self.header.root_page_id)?;
+        let mut btree = BTree::<K, V> {
+            header,
+            written_header,
+            page_manager,
+            compressor: None,
+            value_codec: None,
+            value_log: None,
+            interpolator: None,
+            eytzinger_layout: false,
+            fit_policy: FitPolicy::default(),
+            max_free_regions: DEFAULT_MAX_FREE_REGIONS,
+            observers: Vec::new(),
+            watchers: Watchers::default(),
+            range_locks: RangeLocks::default(),
+            header_repair,
+            duplicate_resolver: None,
+            duplicate_order: None,
+            writes_since_flush: 0,
+            backpressure: config.backpressure,
+            on_invariant_violation: config.on_invariant_violation,
+            poisoned: None,
+            merkle: None,
+            snapshot_age_alert: None,
+            epoch: next_epoch(),
+            stats: TreeStats::default(),
+            sequences: Sequences::default(),
+            #[cfg(feature = "std")]
+            data_path: None,
+            #[cfg(feature = "std")]
+            checkpoint: 0,
+            #[cfg(feature = "std")]
+            registration: None,
+            _phantom: PhantomData,
+        };
 
-        if let Some((promoted_key, promoted_value, right)) =
-            self.insert_into_page(&mut root, key.clone(), value.clone())?
-        {
-            let mut new_root =
-                Self::create_page(&mut self.header, NodeType::INTERNAL, &mut self.page_manager);
+        if btree.header.pages_empty() {
+            // Called when header is initialised above or if, for some reason, the header is
+            // created without a root page
+            let root_page =
+                Self::create_page(&mut btree.header, NodeType::LEAF, &mut btree.page_manager)?;
+            btree.header.root_page_id = root_page.page_id;
+            info!("Adding root page: {}", root_page.page_id);
 
-            new_root.insert(0, &promoted_key, &promoted_value)?;
-            new_root.pointers.push(self.header.root_page_id);
-            new_root.pointers.push(right.page_id);
+            BTree::<K, V>::write_page(&root_page, &mut btree.page_manager)?;
+            btree.commit_header()?;
+            return Ok(btree);
+        }
 
-            info!(
-                "Splitting root: promoted_key={:?} promoted_value={:?} new_root={:?}",
-                promoted_key, promoted_value, new_root
-            );
+        if btree.header.has_dictionary() {
+            let dictionary = btree.read_dictionary(btree.header.dictionary_page_id)?;
+            info!("Loaded compression dictionary: {} bytes", dictionary.len());
+            btree.compressor = Some(Arc::new(ValueCompressor::new(
+                dictionary,
+                ValueCompressor::DEFAULT_LEVEL,
+            )));
+        }
 
-            BTree::<K, V>::write_page(&new_root, &mut self.page_manager)?;
-            BTree::<K, V>::write_page(&root, &mut self.page_manager)?;
-            self.header.root_page_id = new_root.page_id;
+        if btree.header.has_stats() {
+            (btree.stats, btree.sequences) = btree.read_stats()?;
+            info!("Loaded tree stats: {:?}", btree.stats);
+        }
 
-            return Ok(());
+        if btree.header_repair.is_some() {
+            // Only the LSN and delete count are kept from the stats page found, as its entry
+            // counts may not have been written since the tree last changed
+            let mut counted = TreeStats::default();
+            let root = btree.header.root_page_id;
+            btree.count_entries(root, &mut counted)?;
+            if let Some(repair) = &mut btree.header_repair {
+                repair.entries = counted.entries;
+            }
+            btree.stats = TreeStats {
+                deletes: btree.stats.deletes,
+                commits: btree.stats.commits,
+                ..counted
+            };
+            btree.writes_since_flush += 1;
+            btree.flush()?;
+            warn!("Rebuilt unreadable header: {:?}", btree.header_repair);
         }
 
-        BTree::<K, V>::write_header(&self.header, &mut self.page_manager)?;
-        Ok(())
+        Ok(btree)
     }
 
-    fn insert_into_page(
-        &mut self,
-        page: &mut SlottedPage<K, V>,
-        key: K,
-        value: V,
-    ) -> Result<Option<(K, V, SlottedPage<K, V>)>, BTreeError> {
-        let result: Result<Option<(K, V, SlottedPage<K, V>)>, BTreeError> = match page.node_type {
-            NodeType::LEAF => {
-                // If leaf is overflowing, it should be split
-                // Parent should point to current node AND a new node
-                match page.find_exact_key(&key)? {
-                    Some(pos) => {
-                        page.update(pos, &key, &value)?;
-                        debug!(
-                            "Insert into leaf with exact key: pos={} page={:?}",
-                            pos, page
-                        );
-                        BTree::<K, V>::write_page(page, &mut self.page_manager)?;
-                        Ok(None)
-                    }
-                    None => {
-                        let key_len = bincode::serialize(&key)?.len();
-                        let value_len = bincode::serialize(&value)?.len();
-                        if page.can_insert(key_len, value_len) {
-                            let pos = page.find_key_position(&key)?;
-                            page.insert(pos, &key, &value)?;
-                            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
-                            debug!("Insert into leaf: pos={} page={:?}", pos, page);
-                            Ok(None)
-                        } else {
-                            let new_page_id = self.page_manager.allocate_page()?;
-                            debug!("Split leaf page: new_page_id={}", new_page_id);
-                            let (promoted_key, promoted_value, mut right) =
-                                page.split(new_page_id)?;
-
-                            if key < promoted_key {
-                                let pos = page.find_key_position(&key)?;
-                                page.insert(pos, &key, &value)?;
-                                debug!(
-                                    "Insert into split left page: pos={} promoted_key={:?} key={:?}, page={:?}",
-                                    pos, promoted_key, key, page
-                                );
-                            } else if promoted_key < key {
-                                let pos = right.find_key_position(&key)?;
-                                right.insert(pos, &key, &value)?;
-                                debug!(
-                                    "Insert into split right page: pos={} promoted_key={:?} key={:?} right={:?}",
-                                    pos, promoted_key, key, right
-                                );
-                            } else {
-                                panic!("Weird");
-                            }
+    /// How the header was rebuilt from the tree's pages when it was opened, because it could
+    /// not be read. `None` for a tree whose header was intact, or not opened with
+    /// [`open_with_recovery`](Self::open_with_recovery).
+    pub fn header_repair(&self) -> Option<&HeaderRepair> {
+        self.header_repair.as_ref()
+    }
 
-                            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
-                            BTree::<K, V>::write_page(&right, &mut self.page_manager)?;
+    /// Why the handle was poisoned, if it found the tree's invariants broken while set to
+    /// `InvariantViolation::Poison`. A poisoned handle stays poisoned; the file can be
+    /// inspected by opening it again, with `verify` or `scrub`.
+    pub fn poisoned(&self) -> Option<&str> {
+        self.poisoned.as_deref()
+    }
 
-                            self.header.add_page();
-                            Ok(Some((promoted_key, promoted_value, right)))
-                        }
-                    }
-                }
+    // Builds a header for the tree in `page_manager` from a scan of its pages, or returns
+    // `None` if there are no tree pages to build it for. See [`crate::recovery`].
+    fn scan_for_header(
+        page_manager: &mut PageManager,
+        config: &TreeConfig,
+        reason: String,
+    ) -> Result<Option<(Header, HeaderRepair)>, BTreeError> {
+        let mut header = Header::create(config);
+        let page_count = page_manager.page_count();
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut referenced: HashSet<u64> = HashSet::new();
+        let mut meta_pages = Vec::new();
+        let mut damaged_pages = Vec::new();
+        let mut compressed = false;
+        let mut checksummed = false;
+        let mut counted = false;
+        let mut page_id = 0;
+        while page_id < page_count {
+            let buffer = page_manager.read_page_uncached(page_id)?;
+            let (prefix_id, type_byte) = types::read_page_prefix(&buffer);
+            if buffer.iter().all(|&b| b == 0) {
+                // Allocated but never written
+                page_id += 1;
+                continue;
             }
-            NodeType::INTERNAL => {
-                let mut child = self.read_page(page.get_pointer(&key)?)?;
-                debug!("Inserting into internal node: child={:?}", child);
-
-                // In internal node, insert key into child
-                // The child can be split and therefore, the extra key is promoted and has to be
-                // inserted into the parent
-                // The parent can then be split in turn
-                match self.insert_into_page(&mut child, key.clone(), value.clone())? {
-                    Some((child_promoted_key, child_promoted_value, child_right)) => {
-                        let insert_pos = page.find_key_position(&child_promoted_key)?;
-                        debug!(
-                            "Inserting into internal node: position={:?} child_promoted_key={:?}",
-                            insert_pos, child_promoted_key
-                        );
-                        if page.can_insert(
-                            bincode::serialize(&child_promoted_key)?.len(),
-                            bincode::serialize(&child_promoted_value)?.len(),
-                        ) {
-                            page.insert(insert_pos, &child_promoted_key, &child_promoted_value)?;
-                            page.pointers.insert(insert_pos + 1, child_right.page_id);
-                            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
-                            BTree::<K, V>::write_page(&child_right, &mut self.page_manager)?;
-                            debug!(
-                                "Inserted into internal node: position={:?} child_promoted_key={:?} page={:?}, child_right={:?}",
-                                insert_pos, child_promoted_key, page, child_right
-                            );
-                            Ok(None)
-                        } else {
-                            let new_page_id = self.page_manager.allocate_page()?;
-                            debug!("Splitting internal node: new_page_id={:?}", new_page_id);
-                            let (to_promote_key, to_promote_value, mut right_of_current) =
-                                page.split(new_page_id)?;
-                            debug!(
-                                "Split internal node: to_promote_key={:?} right_of_current={:?} page={:?}",
-                                to_promote_key, right_of_current, page
-                            );
-
-                            if child_promoted_key < to_promote_key {
-                                let insert_pos = page.find_key_position(&child_promoted_key)?;
-                                page.insert(
-                                    insert_pos,
-                                    &child_promoted_key,
-                                    &child_promoted_value,
-                                )?;
-                                page.pointers.insert(insert_pos + 1, child_right.page_id);
-                                debug!(
-                                    "Insert into left split internal node: child_promoted_key={:?}, child_right.page_id={} insert_pos={:?} page={:?}",
-                                    child_promoted_key, child_right.page_id, insert_pos, page
-                                );
-                            } else if child_promoted_key > to_promote_key {
-                                let insert_pos =
-                                    right_of_current.find_key_position(&child_promoted_key)?;
-                                right_of_current.insert(
-                                    insert_pos,
-                                    &child_promoted_key,
-                                    &child_promoted_value,
-                                )?;
-                                right_of_current
-                                    .pointers
-                                    .insert(insert_pos + 1, child_right.page_id);
-                                debug!(
-                                    "Insert into right split internal node: child_promoted_key={:?}, child_right.page_id={} insert_pos={:?} right_of_current={:?}",
-                                    child_promoted_key,
-                                    child_right.page_id,
-                                    insert_pos,
-                                    right_of_current
-                                );
-                            } else {
-                                panic!("Weird")
+            let node_type = NodeType::from_byte(type_byte).filter(|_| prefix_id == page_id);
+            match node_type {
+                Some(node_type) if node_type.is_tree_node() => {
+                    let pages = header.node_pages(node_type);
+                    let node = match pages {
+                        1 => SlottedPage::<K, V>::from_buffer(buffer).ok(),
+                        _ => page_manager
+                            .read_pages_uncached(page_id, pages)
+                            .ok()
+                            .and_then(|bytes| SlottedPage::<K, V>::from_buffer(bytes).ok()),
+                    };
+                    match node {
+                        Some(node) => {
+                            checksummed |= node.is_checksummed();
+                            if node.node_type == NodeType::INTERNAL {
+                                counted |= node.is_counted();
                             }
-
-                            BTree::<K, V>::write_page(&page, &mut self.page_manager)?;
-                            BTree::<K, V>::write_page(&child_right, &mut self.page_manager)?;
-                            BTree::<K, V>::write_page(&right_of_current, &mut self.page_manager)?;
-                            self.header.add_page();
-                            Ok(Some((to_promote_key, to_promote_value, right_of_current)))
+                            compressed |= node.slots.iter().any(|slot| slot.compressed);
+                            referenced.extend(&node.pointers);
+                            children.insert(page_id, node.pointers);
+                            page_id += pages;
+                        }
+                        None => {
+                            damaged_pages.push(page_id);
+                            page_id += 1;
                         }
                     }
-                    None => Ok(None),
+                    continue;
                 }
+                Some(NodeType::META) => meta_pages.push(page_id),
+                Some(_) => {}
+                None => damaged_pages.push(page_id),
             }
+            page_id += 1;
+        }
+
+        // Of the pages nothing points to, the root is the one with the most pages beneath it
+        let mut roots: Vec<(u64, u64)> = children
+            .keys()
+            .filter(|page_id| !referenced.contains(*page_id))
+            .map(|&page_id| (Self::subtree_size(&children, page_id), page_id))
+            .collect();
+        roots.sort();
+        let Some((_, root_page_id)) = roots.pop() else {
+            return Ok(None);
+        };
+        header.checksum = match checksummed {
+            true => ChecksumAlgorithm::Crc32,
+            false => ChecksumAlgorithm::None,
         };
+        // Without internal nodes nothing says whether the tree keeps subtree counts
+        if children.values().any(|pointers| !pointers.is_empty()) {
+            header.subtree_counts = counted;
+        }
 
-        match result? {
-            Some((key, value, node)) => Ok(Some((key, value, node))),
-            None => Ok(None),
+        let capacity = (header.page_size as usize - PAGE_PREFIX_SIZE) as u64;
+        let mut dictionary = None;
+        let mut stats = None;
+        for &page_id in &meta_pages {
+            let page = page_manager.read_page_uncached(page_id)?;
+            let data = &page[PAGE_PREFIX_SIZE..];
+            let length = u32::from_le_bytes(data[0..4].try_into().unwrap()) as u64;
+            let pages = (4 + length).div_ceil(capacity);
+            let continued = (page_id + 1..page_id + pages).all(|overflow_id| {
+                page_manager
+                    .read_page_uncached(overflow_id)
+                    .is_ok_and(|page| page[8] == NodeType::OVERFLOW as u8)
+            });
+            if compressed && length > 0 && continued {
+                dictionary = Some(page_id);
+            } else if Sequences::deserialize(&data[TreeStats::SIZE..]).is_some() {
+                // Of several, the one with the latest LSN
+                let commits = TreeStats::deserialize(data).commits;
+                if stats.is_none_or(|(latest, _)| commits >= latest) {
+                    stats = Some((commits, page_id));
+                }
+            }
         }
+        if compressed {
+            let Some(dictionary_page_id) = dictionary else {
+                return Err(BTreeError::Corrupted {
+                    page_id: root_page_id,
+                    reason: "values are compressed but no dictionary page was found".to_string(),
+                });
+            };
+            header.dictionary_page_id = dictionary_page_id;
+            header.compression = CompressionAlgorithm::ZstdDictionary;
+        }
+        if let Some((_, stats_page_id)) = stats {
+            header.stats_page_id = stats_page_id;
+        }
+        header.root_page_id = root_page_id;
+        header.set_page_count(page_count);
+
+        let repair = HeaderRepair {
+            reason,
+            root_page_id,
+            page_count,
+            orphaned_pages: roots.into_iter().map(|(_, page_id)| page_id).collect(),
+            damaged_pages,
+            entries: 0,
+        };
+        Ok(Some((header, repair)))
     }
 
-    fn write_header(header: &Header, page_manager: &mut PageManager) -> Result<(), BTreeError> {
-        let buffer = header.serialize();
-        page_manager.write_header(&buffer)?;
-        Ok(())
+    // Pages in the subtree under `page_id`, as far as they were found
+    fn subtree_size(children: &HashMap<u64, Vec<u64>>, page_id: u64) -> u64 {
+        let mut size = 0;
+        let mut pending = vec![page_id];
+        while let Some(page_id) = pending.pop() {
+            if let Some(pointers) = children.get(&page_id) {
+                size += 1;
+                pending.extend(pointers);
+            }
+        }
+        size
     }
 
-    fn write_page(
-        page: &SlottedPage<K, V>,
-        page_manager: &mut PageManager,
-    ) -> Result<(), BTreeError> {
-        let data = page.serialize()?;
-        page_manager.write_page(page.page_id, &data)?;
-        Ok(())
+    /// How values are compressed: with a dictionary once one has been trained or set.
+    pub fn compression(&self) -> CompressionAlgorithm {
+        self.header.compression
     }
 
-    fn read_page(&mut self, page_id: u64) -> Result<SlottedPage<K, V>, BTreeError> {
-        let (buffer, _) = self.page_manager.read_page(page_id)?;
-        let node: SlottedPage<K, V> =
-            SlottedPage::deserialize(&buffer, self.header.page_size as usize);
-
-        Ok(node)
+    /// On-disk format version the tree's file was created with, which may be older than
+    /// [`crate::format_version`].
+    pub fn format_version(&self) -> u16 {
+        self.header.version
     }
 
-    fn print(&mut self, page_id: u64, level: usize, chars_prior: usize) {
-        if level == 10 {
-            panic!("Recursive limit");
+    pub fn config(&self) -> TreeConfig {
+        TreeConfig {
+            cache_size: self.page_manager.cache().capacity(),
+            max_file_size: self.page_manager.max_size(),
+            max_dirty_pages: self.page_manager.max_dirty_pages(),
+            backpressure: self.backpressure,
+            write_mode: self.page_manager.write_mode(),
+            on_invariant_violation: self.on_invariant_violation,
+            ..self.header.config()
         }
-        let node = self.read_page(page_id).unwrap();
-        let prior_char = if level == 0 {
-            ""
-        } else {
-            match node.pointers.is_empty() {
-                false => "└",
-                true => "├",
-            }
-        };
-        let post_char = match node.pointers.is_empty() {
-            false => "┐",
-            true => "",
-        };
-
-        let keys = node.read_keys().unwrap();
-        let stringified_keys = match keys.len() <= 200 {
-            true => format!("{:?}", keys),
-            false => {
-                let start = &keys[..2];
-                let end = &keys[keys.len() - 2..];
-                format!("{:?},...,{:?}", start, end)
-            }
-        };
-
-        println!(
-            "{}{}{}{}{:?} - {}",
-            " ".repeat(chars_prior),
-            prior_char,
-            stringified_keys,
-            post_char,
-            node.node_type,
-            node.page_id
-        );
-        node.pointers
-            .iter()
-            .for_each(|&ptr| self.print(ptr, level + 1, 1 + chars_prior + stringified_keys.len()));
     }
 
-    pub fn print_tree(&mut self) {
-        println!("BTREE: {}", self.header.root_page_id);
-        self.print(self.header.root_page_id, 0, 0);
-        println!("\n")
+    /// Changes the tuning knobs of this tree and persists them. The page size and checksum
+    /// algorithm cannot change, nor can a comparator id once set, and subtree counts can only
+    /// be turned on or off while the tree has no internal nodes.
+    pub fn set_config(&mut self, config: TreeConfig) -> Result<(), BTreeError> {
+        config.validate()?;
+        self.header.check_comparator(config.comparator_id)?;
+        if config.subtree_counts != self.header.subtree_counts
+            && self.page_type(self.header.root_page_id)? != NodeType::LEAF
+        {
+            return Err(BTreeError::TreeNotEmpty);
+        }
+        self.page_manager.set_cache_capacity(config.cache_size);
+        self.page_manager.set_max_size(config.max_file_size);
+        self.page_manager
+            .set_max_dirty_pages(config.max_dirty_pages);
+        self.page_manager.set_write_mode(config.write_mode)?;
+        self.backpressure = config.backpressure;
+        self.on_invariant_violation = config.on_invariant_violation;
+        #[cfg(feature = "std")]
+        if config.value_log_threshold > 0
+            && self.value_log.is_none()
+            && let Some(data_path) = &self.data_path
+        {
+            self.value_log = Some(Arc::new(ValueLog::open(ValueLog::path_for(data_path))?));
+        }
+        self.header.set_config(&config);
+        self.commit_header()
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::NamedTempFile;
-
-    // ─────────────────────────────────────────────────────────
-    // Test Helpers
-    // ─────────────────────────────────────────────────────────
 
-    fn create_temp_btree<K, V>(page_size: u64) -> BTree<K, V>
+    /// Sets how a tree using `DuplicatePolicy::Resolve` combines a stored value with a new one
+    /// inserted under the same key.
+    pub fn set_duplicate_resolver<F>(&mut self, resolver: F)
     where
-        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
-        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        F: Fn(&K, V, V) -> V + Send + Sync + 'static,
     {
-        let file = NamedTempFile::new().unwrap();
-        BTree::new(file.reopen().unwrap(), page_size).unwrap()
+        self.duplicate_resolver = Some(Box::new(resolver));
     }
 
-    fn create_btree_with_file<K, V>(
-        page_size: u64,
-    ) -> (BTree<K, V>, std::path::PathBuf, NamedTempFile)
+    /// Sets how a tree using `DuplicatePolicy::KeepSorted` orders the values stored under
+    /// one key, such as by a prefix of each value. It must be the order the tree was written
+    /// with.
+    pub fn set_duplicate_order<F>(&mut self, order: F)
     where
-        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
-        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        F: Fn(&V, &V) -> core::cmp::Ordering + Send + Sync + 'static,
     {
-        let file = NamedTempFile::new().unwrap();
-        let path = file.path().to_owned();
-        let btree = BTree::new(file.reopen().unwrap(), page_size).unwrap();
-        (btree, path, file)
+        self.duplicate_order = Some(Box::new(order));
     }
 
-    // ─────────────────────────────────────────────────────────
-    // Initialization Tests
-    // ─────────────────────────────────────────────────────────
-
-    mod initialization {
-        use super::*;
+    fn duplicate_order(&self) -> Result<&DuplicateOrder<V>, BTreeError> {
+        self.duplicate_order
+            .as_deref()
+            .ok_or(BTreeError::NoDuplicateOrder)
+    }
 
-        #[test_log::test]
-        fn new_btree_creates_root_page() {
-            let btree = create_temp_btree::<i64, String>(4096);
+    fn read_header(page_manager: &mut PageManager) -> Result<Header, BTreeError> {
+        let buffer = page_manager.read_header()?;
+        trace!("read_header: buffer {:?}", buffer);
+        Ok(Header::deserialize(&buffer)?)
+    }
 
-            assert_eq!(btree.header.page_count, 1);
-            assert_eq!(btree.header.root_page_id, 0);
-        }
+    fn create_page(
+        header: &mut Header,
+        node_type: NodeType,
+        page_manager: &mut PageManager,
+    ) -> Result<SlottedPage<K, V>, BTreeError> {
+        let page_id = page_manager
+            .allocate_pages(header.node_pages(node_type))?
+            .start;
+        info!("Created new page id={}", page_id);
 
-        #[test_log::test]
-        fn new_btree_sets_page_size() {
-            let btree = create_temp_btree::<i64, String>(8192);
+        let mut page = SlottedPage::new(page_id, node_type, header.node_size(node_type) as usize);
+        page.set_checksummed(header.checksums_pages());
+        page.set_counted(header.subtree_counts);
+        Ok(page)
+    }
 
-            assert_eq!(btree.header.page_size, 8192);
-        }
+    pub fn search(&mut self, key: K) -> Result<V, BTreeError> {
+        let _span = op_span!(
+            "search",
+            key_size = self.encoding().serialized_size(&key).unwrap_or(0)
+        );
+        self.search_node(&key, self.header.root_page_id)
+    }
 
-        #[test_log::test]
-        fn new_btree_sets_version() {
-            let btree = create_temp_btree::<i64, String>(4096);
+    /// Every value stored under `key`, in insertion order, or in the duplicate order for trees
+    /// using `DuplicatePolicy::KeepSorted`. Only trees keeping duplicates can hold more than
+    /// one.
+    pub fn get_all(&mut self, key: K) -> Result<Vec<V>, BTreeError> {
+        self.range(key.clone()..=key)?
+            .map(|entry| entry.map(|(_, value)| value))
+            .collect()
+    }
 
-            assert_eq!(btree.header.version, VERSION);
-        }
+    /// Looks up `key` and calls `f` with its serialized value, returning what `f` returns.
+    ///
+    /// Unlike `search` the value is not deserialized into an owned `V`: unless it was stored
+    /// compressed, `f` sees the bytes in place in the page. Borrowing types such as `&str` or
+    /// `&[u8]` can be deserialized from them with the tree's [`Encoding::deserialize`] without
+    /// copying, unless values go through a custom codec.
+    pub fn get_with<R, F>(&mut self, key: K, f: F) -> Result<R, BTreeError>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let _span = op_span!(
+            "search",
+            key_size = self.encoding().serialized_size(&key).unwrap_or(0)
+        );
+        let (node, key_pos) = self.find_entry(&key, self.header.root_page_id)?;
+        Ok(f(&node.value_bytes(key_pos)?))
+    }
 
-        #[test_log::test]
-        fn new_btree_root_is_leaf() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+    /// Looks up `key` and calls `f` with a [`RawEntry`] of its stored key and value bytes, for
+    /// handing the entry to code outside Rust as it is. The value is decompressed or read from
+    /// the value log first if need be.
+    pub fn get_raw<R, F>(&mut self, key: K, f: F) -> Result<R, BTreeError>
+    where
+        F: FnOnce(RawEntry<'_>) -> R,
+    {
+        let (node, key_pos) = self.find_entry(&key, self.header.root_page_id)?;
+        let value = node.value_bytes(key_pos)?;
+        Ok(f(RawEntry::from_page(&node, key_pos, &value)))
+    }
 
-            let root = btree.read_page(btree.header.root_page_id).unwrap();
-            assert_eq!(root.node_type, NodeType::LEAF);
-        }
+    fn search_node(&mut self, key: &K, page_id: u64) -> Result<V, BTreeError> {
+        let (node, key_pos) = self.find_entry(key, page_id)?;
+        node.read_value(key_pos)
+    }
 
-        #[test_log::test]
-        fn different_page_sizes() {
-            for page_size in [256, 512, 1024, 4096, 8192] {
-                let btree = create_temp_btree::<i64, i64>(page_size);
-                assert_eq!(btree.header.page_size, page_size);
+    // Returns the page holding the live entry for `key` below `page_id`, and its position there
+    fn find_entry(
+        &mut self,
+        key: &K,
+        page_id: u64,
+    ) -> Result<(SlottedPage<K, V>, usize), BTreeError> {
+        let mut page_id = page_id;
+        loop {
+            let node = self.read_page(page_id)?;
+            match node.node_type {
+                NodeType::INTERNAL => match node.find_exact_key(key)? {
+                    Some(key_pos) => {
+                        instrument::record_page(page_id);
+                        return Ok((node, key_pos));
+                    }
+                    None => page_id = node.get_pointer(key)?,
+                },
+                NodeType::LEAF => {
+                    instrument::record_page(page_id);
+                    let key_pos = node
+                        .find_live_key(key)?
+                        .ok_or(BTreeError::KeyNotFound(key.to_string()))?;
+                    return Ok((node, key_pos));
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
             }
         }
     }
 
-    // ─────────────────────────────────────────────────────────
-    // Header Management Tests
-    // ─────────────────────────────────────────────────────────
-
-    mod header_management {
-        use super::*;
-
-        #[test_log::test]
-        fn read_header_after_creation() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+    pub fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        self.insert_by(None, key, value)
+    }
 
-            let header = BTree::<i64, String>::read_header(&mut btree.page_manager).unwrap();
+    /// Inserts `key` on behalf of `owner`, which may write into the ranges it has locked with
+    /// [`lock_range`](Self::lock_range). Fails with `RangeLocked` if another owner has locked
+    /// a range holding `key`.
+    pub fn insert_as(&mut self, owner: LockOwner, key: K, value: V) -> Result<(), BTreeError> {
+        self.insert_by(Some(owner), key, value)
+    }
 
-            assert_eq!(header.page_count, 1);
-            assert_eq!(header.page_size, 4096);
+    fn insert_by(&mut self, owner: Option<LockOwner>, key: K, value: V) -> Result<(), BTreeError> {
+        self.check_range_locks(owner, &key)?;
+        // A split is only known to be sound once the separator it passes up has a place in the
+        // parent, so in write-through mode too its pages are held back until the insert is
+        // done. A violated invariant then discards them and leaves storage as it was.
+        let write_through = self.page_manager.write_mode() == WriteMode::WriteThrough;
+        if write_through {
+            self.page_manager.set_write_mode(WriteMode::WriteBack)?;
+        }
+        // Splits and count updates read pages on the path down again
+        self.page_manager.begin_pinned_operation();
+        let mut result = self.insert_entry(key, value);
+        self.page_manager.end_pinned_operation();
+        if write_through {
+            let written = self.page_manager.set_write_mode(WriteMode::WriteThrough);
+            result = result.and(written.map_err(BTreeError::from));
+        }
+        result
+    }
+
+    fn insert_entry(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        info!("Insert key={:?} value={:?}", key, value);
+        self.apply_backpressure()?;
+        let value = self.apply_duplicate_policy(&key, value)?;
+        let key_size = self.encoding().serialized_size(&key)?;
+        let value_size = self.codecs().value_len(&value)?;
+        self.check_entry_fits(&key, &value, (key_size + value_size) as usize)?;
+        let _span = op_span!("insert", key_size = key_size, value_size = value_size);
+        self.writes_since_flush += 1;
+        let watched = self
+            .watchers
+            .watching(&key)
+            .then(|| (key.clone(), value.clone()));
+
+        let mut path = Vec::new();
+        let (split, added) = self.descend_and_insert(&mut path, key, value)?;
+        self.carry_split(path, split, added)?;
+
+        self.stats.add_entry(key_size, value_size);
+        self.stats.commits += 1;
+        self.commit_header()?;
+        if let Some((key, value)) = watched {
+            self.watchers.put(self.stats.commits, key, value);
+        }
+        Ok(())
+    }
+
+    // Refuses an entry no page could hold before anything is changed for it. Compression and
+    // the value log only shrink a value, so the entry is only encoded as the page would store
+    // it when its `serialized_len` is over the limit.
+    fn check_entry_fits(
+        &mut self,
+        key: &K,
+        value: &V,
+        serialized_len: usize,
+    ) -> Result<(), BTreeError> {
+        let max = SlottedPage::<K, V>::max_entry_len(
+            self.header.node_size(NodeType::INTERNAL) as usize,
+            self.header.checksums_pages(),
+            self.header.subtree_counts,
+        );
+        if serialized_len <= max {
+            return Ok(());
+        }
+        let root = self.read_page(self.header.root_page_id)?;
+        let (key_len, value_len) = root.encoded_len(key, value)?;
+        match key_len + value_len {
+            size if size > max => Err(BTreeError::EntryTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    // Carries `split` of the last node on `path` up through its ancestors. A split promotes
+    // an entry into the parent, which can then split in turn. Above the last split, counted
+    // ancestors only need the new entry added to their counts if the tree `added` one.
+    fn carry_split(
+        &mut self,
+        mut path: Vec<(SlottedPage<K, V>, usize)>,
+        mut split: SplitResult<K, V>,
+        added: bool,
+    ) -> Result<(), BTreeError> {
+        while let Some((mut parent, child_idx)) = path.pop() {
+            let Some((promoted_key, promoted_value, right)) = split else {
+                if parent.is_counted() && added {
+                    parent.adjust_count(child_idx, 1);
+                    BTree::<K, V>::write_page(&parent, &mut self.page_manager)?;
+                }
+                continue;
+            };
+            if parent.is_counted() {
+                // The child's subtree lost the promoted entry and everything moved right of it
+                let left_entries =
+                    parent.counts[child_idx] + added as u64 - 1 - right.subtree_entries();
+                parent.set_count(child_idx, left_entries);
+            }
+            split = self.insert_separator(
+                &mut parent,
+                promoted_key,
+                promoted_value,
+                right.page_id,
+                right.subtree_entries(),
+            )?;
+        }
+        if let Some((promoted_key, promoted_value, right)) = split {
+            self.split_root(promoted_key, promoted_value, &right)?;
+        }
+        Ok(())
+    }
+
+    // Works out the value to store for `key` under policies that need to know whether it is
+    // already present: `Error` refuses the insert and `Resolve` merges the two values.
+    fn apply_duplicate_policy(&mut self, key: &K, value: V) -> Result<V, BTreeError> {
+        let policy = self.header.duplicate_policy;
+        if !matches!(policy, DuplicatePolicy::Error | DuplicatePolicy::Resolve) {
+            return Ok(value);
+        }
+        let stored = match self.search_node(key, self.header.root_page_id) {
+            Ok(stored) => stored,
+            Err(BTreeError::KeyNotFound(_)) => return Ok(value),
+            Err(e) => return Err(e),
+        };
+
+        match (policy, &self.duplicate_resolver) {
+            (DuplicatePolicy::Resolve, Some(resolver)) => Ok(resolver(key, stored, value)),
+            (DuplicatePolicy::Resolve, None) => Err(BTreeError::NoDuplicateResolver),
+            _ => Err(BTreeError::DuplicateKey(key.to_string())),
+        }
+    }
+
+    // Where `key` goes among the entries of `page`. Duplicates are kept in insertion order by
+    // placing each after the entries already stored under the same key, or when sorted, after
+    // those whose values do not come after `value`.
+    fn insert_position(
+        &self,
+        page: &SlottedPage<K, V>,
+        key: &K,
+        value: &V,
+    ) -> Result<usize, BTreeError> {
+        match self.header.duplicate_policy {
+            DuplicatePolicy::KeepBoth => page.find_upper_position(key),
+            DuplicatePolicy::KeepSorted => {
+                let upper = page.find_upper_position(key)?;
+                let mut pos = page.find_key_position(key)?;
+                while pos < upper {
+                    if self.duplicate_order()?(&page.read_value(pos)?, value).is_gt() {
+                        break;
+                    }
+                    pos += 1;
+                }
+                Ok(pos)
+            }
+            _ => page.find_key_position(key),
+        }
+    }
+
+    // Whether `key` and `value` come before the entry of `other_key` and `other_value`
+    // because duplicates are sorted and the key is the same
+    fn sorts_before(
+        &self,
+        key: &K,
+        value: &V,
+        other_key: &K,
+        other_value: &V,
+    ) -> Result<bool, BTreeError> {
+        if self.header.duplicate_policy != DuplicatePolicy::KeepSorted || key != other_key {
+            return Ok(false);
+        }
+        Ok(self.duplicate_order()?(value, other_value).is_lt())
+    }
+
+    // Grows the tree by one level, with the old root as the left child of the new one.
+    fn split_root(
+        &mut self,
+        key: K,
+        value: V,
+        right: &SlottedPage<K, V>,
+    ) -> Result<(), BTreeError> {
+        let mut new_root =
+            Self::create_page(&mut self.header, NodeType::INTERNAL, &mut self.page_manager)?;
+        self.attach_codecs(&mut new_root);
+
+        new_root.insert(0, &key, &value)?;
+        let left_entries = match new_root.is_counted() {
+            true => self.read_page(self.header.root_page_id)?.subtree_entries(),
+            false => 0,
+        };
+        new_root.insert_pointer(0, self.header.root_page_id, left_entries);
+        new_root.insert_pointer(1, right.page_id, right.subtree_entries());
+
+        info!(
+            "Splitting root: promoted_key={:?} promoted_value={:?} new_root={:?}",
+            key, value, new_root
+        );
+
+        BTree::<K, V>::write_page(&new_root, &mut self.page_manager)?;
+        self.header.root_page_id = new_root.page_id;
+        Ok(())
+    }
+
+    // Walks down from the root to the node `key` belongs in and stores it there. Every internal
+    // node passed through is pushed onto `path` with the index of the child taken, so the
+    // caller can carry a split back up. Also returns whether the tree gained an entry rather
+    // than having one replaced.
+    fn descend_and_insert(
+        &mut self,
+        path: &mut Vec<(SlottedPage<K, V>, usize)>,
+        key: K,
+        value: V,
+    ) -> Result<(SplitResult<K, V>, bool), BTreeError> {
+        let mut page = self.read_page(self.header.root_page_id)?;
+        loop {
+            let keep_both = self.header.duplicate_policy.keeps_duplicates();
+            match page.node_type {
+                NodeType::LEAF => {
+                    let existing = page.find_exact_key(&key)?.filter(|_| !keep_both);
+                    self.ensure_room_for(&page, existing, &key, &value, path.len())?;
+                    return self.insert_into_leaf(&mut page, key, value);
+                }
+                NodeType::INTERNAL => {
+                    // Keys stored in an internal node are updated there rather than duplicated
+                    // further down, unless duplicates are kept
+                    if let Some(pos) = page.find_exact_key(&key)?.filter(|_| !keep_both) {
+                        instrument::record_page(page.page_id);
+                        self.ensure_room_for(&page, Some(pos), &key, &value, path.len())?;
+                        self.forget_entry(&page, pos)?;
+                        if Self::holds_value(&page, pos, &value)? {
+                            debug!("Internal node entry unchanged: pos={}", pos);
+                            return Ok((None, false));
+                        }
+                        if Self::update_in_place(&mut page, pos, &key, &value)? {
+                            debug!("Update internal node entry: pos={} page={:?}", pos, page);
+                            BTree::<K, V>::write_page(&page, &mut self.page_manager)?;
+                            return Ok((None, false));
+                        }
+                        page.delete(pos)?;
+                        let (right_child, right_entries) = page.remove_pointer(pos + 1);
+                        let split = self.insert_separator(
+                            &mut page,
+                            key,
+                            value,
+                            right_child,
+                            right_entries,
+                        )?;
+                        return Ok((split, false));
+                    }
+
+                    let child_idx = self.insert_position(&page, &key, &value)?;
+                    let child = self.read_page(page.pointers[child_idx])?;
+                    debug!("Descending into child: child={:?}", child);
+                    path.push((core::mem::replace(&mut page, child), child_idx));
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
+            }
+        }
+    }
+
+    fn insert_into_leaf(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        key: K,
+        value: V,
+    ) -> Result<(SplitResult<K, V>, bool), BTreeError> {
+        instrument::record_page(page.page_id);
+        // If leaf is overflowing, it should be split
+        // Parent should point to current node AND a new node
+        let keep_both = self.header.duplicate_policy.keeps_duplicates();
+        let mut added = true;
+        if let Some(pos) = page.find_exact_key(&key)?.filter(|_| !keep_both) {
+            added = page.is_tombstoned(pos);
+            self.forget_entry(page, pos)?;
+            if Self::holds_value(page, pos, &value)? {
+                debug!("Leaf entry unchanged: pos={} page={}", pos, page.page_id);
+                return Ok((None, added));
+            }
+            if Self::update_in_place(page, pos, &key, &value)? {
+                debug!(
+                    "Insert into leaf with exact key: pos={} page={:?}",
+                    pos, page
+                );
+                BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+                return Ok((None, added));
+            }
+            // The larger value is inserted afresh below, splitting if it must
+            page.delete(pos)?;
+        }
+
+        let (key_len, value_len) = page.encoded_len(&key, &value)?;
+        if self.make_room(page, key_len, value_len)? {
+            let pos = self.insert_position(page, &key, &value)?;
+            page.insert(pos, &key, &value)?;
+            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+            debug!("Insert into leaf: pos={} page={:?}", pos, page);
+            return Ok((None, added));
+        }
+
+        let new_page_id = self.allocate_node(NodeType::LEAF)?;
+        debug!("Split leaf page: new_page_id={}", new_page_id);
+        let _span = op_span!("split", node_type = "leaf", new_page_id = new_page_id);
+        instrument::record_page(page.page_id);
+        let (promoted_key, promoted_value, mut right) = page.split(new_page_id)?;
+
+        if key < promoted_key || self.sorts_before(&key, &value, &promoted_key, &promoted_value)? {
+            // The entries moved out leave holes scattered over the left page, which may hold
+            // enough space in all but none large enough on its own
+            if !page.can_insert(key_len, value_len) {
+                self.compact_page(page)?;
+            }
+            let pos = self.insert_position(page, &key, &value)?;
+            page.insert(pos, &key, &value)?;
+            debug!(
+                "Insert into split left page: pos={} promoted_key={:?} key={:?}, page={:?}",
+                pos, promoted_key, key, page
+            );
+        } else if promoted_key < key || keep_both {
+            let pos = self.insert_position(&right, &key, &value)?;
+            right.insert(pos, &key, &value)?;
+            debug!(
+                "Insert into split right page: pos={} promoted_key={:?} key={:?} right={:?}",
+                pos, promoted_key, key, right
+            );
+        } else {
+            return Err(self.invariant_violated(format!(
+                "split of leaf {} promoted {:?}, level with the key being inserted",
+                page.page_id, promoted_key
+            )));
+        }
+        self.notify_split(page, &right);
+
+        BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+        BTree::<K, V>::write_page(&right, &mut self.page_manager)?;
+
+        Ok((Some((promoted_key, promoted_value, right)), added))
+    }
+
+    // Fails with `StorageFull` unless storing `key` in `page`, over the entry at `existing` if
+    // any, can go ahead within the size limit. An entry that might not fit could split `page`,
+    // each of the `depth` nodes above it and the root, so that many pages must be free before
+    // anything is changed. A page is also kept back for the stats if they have none yet.
+    fn ensure_room_for(
+        &self,
+        page: &SlottedPage<K, V>,
+        existing: Option<usize>,
+        key: &K,
+        value: &V,
+        depth: usize,
+    ) -> Result<(), BTreeError> {
+        if self.page_manager.max_size() == 0 {
+            return Ok(());
+        }
+        let (key_len, value_len) = page.encoded_len(key, value)?;
+        let fits_in_place =
+            existing.is_some_and(|pos| value_len <= page.slots[pos].value_length as usize);
+        if fits_in_place || page.can_insert(key_len, value_len) {
+            return Ok(());
+        }
+        let splits = depth as u64 + 2;
+        Ok(self
+            .page_manager
+            .ensure_room(splits + self.stats_pages_needed())?)
+    }
+
+    // The page the stats still need, which is allocated the first time they are written
+    fn stats_pages_needed(&self) -> u64 {
+        match self.header.has_stats() {
+            true => 0,
+            false => 1,
+        }
+    }
+
+    // Takes the entry at `pos`, which is about to be overwritten, out of the stats. A tombstone
+    // was already taken out when it was deleted.
+    fn forget_entry(&mut self, page: &SlottedPage<K, V>, pos: usize) -> Result<(), BTreeError> {
+        if !page.is_tombstoned(pos) {
+            let key_size = page.slots[pos].key_length as u64;
+            let value_size = page.value_len(pos)? as u64;
+            self.stats.remove_entry(key_size, value_size);
+        }
+        Ok(())
+    }
+
+    // Whether the live entry at `pos` already stores exactly `value`, making an update a no-op
+    fn holds_value(page: &SlottedPage<K, V>, pos: usize, value: &V) -> Result<bool, BTreeError> {
+        // A value in the value log is not read back just to compare it
+        if page.is_tombstoned(pos) || page.slots[pos].overflow || page.slots[pos].external {
+            return Ok(false);
+        }
+        Ok(page.read_value_bytes(pos)? == page.codecs().encode_value(value)?)
+    }
+
+    // Overwrites the entry at `pos` if the new value takes no more space than the old one.
+    fn update_in_place(
+        page: &mut SlottedPage<K, V>,
+        pos: usize,
+        key: &K,
+        value: &V,
+    ) -> Result<bool, BTreeError> {
+        let (_, value_len) = page.encoded_len(key, value)?;
+        if value_len > page.slots[pos].value_length as usize {
+            return Ok(false);
+        }
+        page.update(pos, key, value)?;
+        Ok(true)
+    }
+
+    // Inserts `key` into internal node `page` with `right_child`, holding `right_entries`
+    // entries, as the pointer following it, splitting `page` if it is full.
+    fn insert_separator(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        key: K,
+        value: V,
+        right_child: u64,
+        right_entries: u64,
+    ) -> Result<SplitResult<K, V>, BTreeError> {
+        let insert_pos = self.insert_position(page, &key, &value)?;
+        debug!(
+            "Inserting into internal node: position={:?} key={:?}",
+            insert_pos, key
+        );
+        let (key_len, value_len) = page.encoded_len(&key, &value)?;
+        if self.make_room(page, key_len, value_len)? {
+            page.insert(insert_pos, &key, &value)?;
+            page.insert_pointer(insert_pos + 1, right_child, right_entries);
+            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+            debug!(
+                "Inserted into internal node: position={:?} key={:?} page={:?}, right_child={}",
+                insert_pos, key, page, right_child
+            );
+            return Ok(None);
+        }
+
+        let new_page_id = self.allocate_node(NodeType::INTERNAL)?;
+        debug!("Splitting internal node: new_page_id={:?}", new_page_id);
+        let _span = op_span!("split", node_type = "internal", new_page_id = new_page_id);
+        instrument::record_page(page.page_id);
+        let (mut to_promote_key, mut to_promote_value, mut right_of_current) =
+            page.split(new_page_id)?;
+        debug!(
+            "Split internal node: to_promote_key={:?} right_of_current={:?} page={:?}",
+            to_promote_key, right_of_current, page
+        );
+
+        if key < to_promote_key
+            || self.sorts_before(&key, &value, &to_promote_key, &to_promote_value)?
+        {
+            // As in a leaf split, the moved entries may leave no hole large enough on its own
+            let (key_len, value_len) = page.encoded_len(&key, &value)?;
+            if !page.can_insert(key_len, value_len) {
+                self.compact_page(page)?;
+            }
+            let insert_pos = self.insert_position(page, &key, &value)?;
+            page.insert(insert_pos, &key, &value)?;
+            page.insert_pointer(insert_pos + 1, right_child, right_entries);
+            debug!(
+                "Insert into left split internal node: key={:?}, right_child={} insert_pos={:?} page={:?}",
+                key, right_child, insert_pos, page
+            );
+        } else if key > to_promote_key || self.header.duplicate_policy.keeps_duplicates() {
+            let insert_pos = self.insert_position(&right_of_current, &key, &value)?;
+            right_of_current.insert(insert_pos, &key, &value)?;
+            right_of_current.insert_pointer(insert_pos + 1, right_child, right_entries);
+            debug!(
+                "Insert into right split internal node: key={:?}, right_child={} insert_pos={:?} right_of_current={:?}",
+                key, right_child, insert_pos, right_of_current
+            );
+        } else {
+            return Err(self.invariant_violated(format!(
+                "split of internal node {} promoted {:?}, level with the separator being inserted",
+                page.page_id, to_promote_key
+            )));
+        }
+        // A node of two entries leaves none on the right when the new one goes left, so the
+        // last entry on the left is promoted instead and the one split off moves right
+        if right_of_current.num_keys == 0 {
+            let last = page.slots.len() - 1;
+            let (key, value) = page.read_key_value(last)?;
+            page.delete(last)?;
+            let (moved, entries) = page.remove_pointer(last + 1);
+            right_of_current.insert(0, &to_promote_key, &to_promote_value)?;
+            right_of_current.insert_pointer(0, moved, entries);
+            (to_promote_key, to_promote_value) = (key, value);
+        }
+        self.notify_split(page, &right_of_current);
+
+        BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+        BTree::<K, V>::write_page(&right_of_current, &mut self.page_manager)?;
+        Ok(Some((to_promote_key, to_promote_value, right_of_current)))
+    }
+
+    /// Removes `key` from the tree and returns its value.
+    ///
+    /// Pages left less than `MERGE_OCCUPANCY` percent full are merged with a sibling when the
+    /// two fit in a single page. With `DeleteStrategy::Tombstone` a leaf entry is only flagged
+    /// as deleted and no merging happens until its space is reclaimed by compaction.
+    pub fn delete(&mut self, key: K) -> Result<V, BTreeError> {
+        self.delete_by(None, key)
+    }
+
+    /// Removes `key` on behalf of `owner`, as [`insert_as`](Self::insert_as) inserts.
+    pub fn delete_as(&mut self, owner: LockOwner, key: K) -> Result<V, BTreeError> {
+        self.delete_by(Some(owner), key)
+    }
+
+    fn delete_by(&mut self, owner: Option<LockOwner>, key: K) -> Result<V, BTreeError> {
+        self.check_range_locks(owner, &key)?;
+        // Merges and borrows read siblings and parents on the path down again
+        self.page_manager.begin_pinned_operation();
+        let result = self.delete_entry(key, None);
+        self.page_manager.end_pinned_operation();
+        result
+    }
+
+    /// Removes every entry stored under `key` whose value falls within `range` in the order
+    /// set with [`set_duplicate_order`](Self::set_duplicate_order), returning how many there
+    /// were. Only trees using `DuplicatePolicy::KeepSorted` can delete by value.
+    pub fn delete_values<R: RangeBounds<V>>(
+        &mut self,
+        key: K,
+        range: R,
+    ) -> Result<u64, BTreeError> {
+        if self.header.duplicate_policy != DuplicatePolicy::KeepSorted {
+            return Err(BTreeError::Unsupported(
+                "values can only be deleted by range from trees sorting duplicates".to_string(),
+            ));
+        }
+        self.check_range_locks(None, &key)?;
+        let values = self.get_all(key.clone())?;
+        let order = self.duplicate_order()?;
+        let within = |value: &V| {
+            let after_start = match range.start_bound() {
+                Bound::Included(start) => order(value, start).is_ge(),
+                Bound::Excluded(start) => order(value, start).is_gt(),
+                Bound::Unbounded => true,
+            };
+            let before_end = match range.end_bound() {
+                Bound::Included(end) => order(value, end).is_le(),
+                Bound::Excluded(end) => order(value, end).is_lt(),
+                Bound::Unbounded => true,
+            };
+            after_start && before_end
+        };
+        let doomed: Vec<V> = values.into_iter().filter(within).collect();
+
+        self.page_manager.begin_pinned_operation();
+        let result = doomed
+            .iter()
+            .try_for_each(|value| self.delete_entry(key.clone(), Some(value)).map(|_| ()));
+        self.page_manager.end_pinned_operation();
+        result.map(|_| doomed.len() as u64)
+    }
+
+    // Removes an entry stored under `key`, the one whose value the duplicate order puts level
+    // with `value` if given.
+    fn delete_entry(&mut self, key: K, value: Option<&V>) -> Result<V, BTreeError> {
+        info!("Delete key={:?}", key);
+        self.apply_backpressure()?;
+        let target = value;
+        let mut root = self.read_page(self.header.root_page_id)?;
+        let mut displaced = Vec::new();
+        let value = loop {
+            match self.delete_from_page(&mut root, &key, target, &mut displaced) {
+                // The entry replacing a deleted separator did not fit in its node
+                Err(BTreeError::PageOverflow { page_id })
+                    if self.split_for_delete(&key, target, page_id)? =>
+                {
+                    root = self.read_page(self.header.root_page_id)?;
+                }
+                result => break result?,
+            }
+        };
+        self.writes_since_flush += 1;
+        self.stats.remove_entry(
+            self.encoding().serialized_size(&key)?,
+            self.codecs().value_len(&value)?,
+        );
+        self.stats.deletes += 1;
+        self.stats.commits += 1;
+
+        self.finish_rebalance(&root, displaced)?;
+
+        self.commit_header()?;
+        if self.watchers.watching(&key) {
+            self.watchers
+                .deleted(self.stats.commits, key, value.clone());
+        }
+        Ok(value)
+    }
+
+    // Replaces `root` by its only child if it was left without keys, then inserts again the
+    // entries `displaced` from subtrees taken apart to rebalance the tree
+    fn finish_rebalance(
+        &mut self,
+        root: &SlottedPage<K, V>,
+        displaced: Vec<(K, V)>,
+    ) -> Result<(), BTreeError> {
+        if root.node_type == NodeType::INTERNAL && root.num_keys == 0 {
+            info!(
+                "Collapsing root: old_root={} new_root={}",
+                root.page_id, root.pointers[0]
+            );
+            self.header.root_page_id = root.pointers[0];
+            self.free_node(root.page_id, root.node_type)?;
+        }
+        for (key, value) in displaced {
+            let mut path = Vec::new();
+            let (split, added) = self.descend_and_insert(&mut path, key, value)?;
+            self.carry_split(path, split, added)?;
+        }
+        Ok(())
+    }
+
+    // Splits internal node `page_id`, which holds the separator for `key` that `delete_from_page`
+    // found no room to replace, carrying the split up as an insert does. Returns false, leaving
+    // the tree unchanged, if `page_id` is not the node holding the separator.
+    fn split_for_delete(
+        &mut self,
+        key: &K,
+        target: Option<&V>,
+        page_id: u64,
+    ) -> Result<bool, BTreeError> {
+        let mut path = Vec::new();
+        let mut page = self.read_page(self.header.root_page_id)?;
+        while page.page_id != page_id {
+            if page.node_type != NodeType::INTERNAL {
+                return Ok(false);
+            }
+            let found = match target {
+                Some(target) => self.find_live_value(&page, key, target)?,
+                None => page.find_exact_key(key)?,
+            };
+            if found.is_some() {
+                return Ok(false);
+            }
+            let child_idx = match target {
+                Some(target) => self.insert_position(&page, key, target)?,
+                None => page.find_key_position(key)?,
+            };
+            let child = self.read_page(page.pointers[child_idx])?;
+            path.push((core::mem::replace(&mut page, child), child_idx));
+        }
+        if page.node_type != NodeType::INTERNAL || page.num_keys < 2 {
+            return Ok(false);
+        }
+
+        let new_page_id = self.allocate_node(NodeType::INTERNAL)?;
+        debug!(
+            "Splitting internal node to delete from it: page={} new_page_id={}",
+            page_id, new_page_id
+        );
+        let _span = op_span!("split", node_type = "internal", new_page_id = new_page_id);
+        let (promoted_key, promoted_value, right) = page.split(new_page_id)?;
+        self.notify_split(&page, &right);
+        BTree::<K, V>::write_page(&page, &mut self.page_manager)?;
+        BTree::<K, V>::write_page(&right, &mut self.page_manager)?;
+        self.carry_split(path, Some((promoted_key, promoted_value, right)), false)?;
+        Ok(true)
+    }
+
+    // Removes `key` from the subtree rooted at `page`, with `target` as `delete_entry` takes
+    // it. Every modified page, including `page`, is written before returning; on error nothing
+    // below `page` has been changed. Entries taken out of the tree to rebalance it are pushed
+    // onto `displaced` for the caller to insert again.
+    fn delete_from_page(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        key: &K,
+        target: Option<&V>,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<V, BTreeError> {
+        match page.node_type {
+            NodeType::LEAF => {
+                let pos = match target {
+                    Some(target) => self.find_live_value(page, key, target)?,
+                    None => page.find_live_key(key)?,
+                }
+                .ok_or(BTreeError::KeyNotFound(key.to_string()))?;
+                let value = page.read_value(pos)?;
+                match self.header.delete_strategy {
+                    DeleteStrategy::Immediate => page.delete(pos)?,
+                    DeleteStrategy::Tombstone => page.tombstone(pos),
+                }
+                BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+                debug!("Deleted from leaf: pos={} page={:?}", pos, page);
+                Ok(value)
+            }
+            NodeType::INTERNAL => {
+                let found = match target {
+                    Some(target) => self.find_live_value(page, key, target)?,
+                    None => page.find_exact_key(key)?,
+                };
+                let value = match found {
+                    Some(pos) => {
+                        let value = page.read_value(pos)?;
+                        self.delete_separator(page, pos, displaced)?;
+                        value
+                    }
+                    None => {
+                        let pos = match target {
+                            Some(target) => self.insert_position(page, key, target)?,
+                            None => page.find_key_position(key)?,
+                        };
+                        let mut child = self.read_page(page.pointers[pos])?;
+                        let value = self.delete_from_page(&mut child, key, target, displaced)?;
+                        page.set_count(pos, child.subtree_entries());
+                        match self.header.delete_strategy {
+                            DeleteStrategy::Immediate => {
+                                self.merge_if_underfull(page, pos, child, displaced)?
+                            }
+                            DeleteStrategy::Tombstone => {
+                                self.refill_if_keyless(page, pos, child, displaced)?
+                            }
+                        }
+                        value
+                    }
+                };
+                BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+                Ok(value)
+            }
+            other => Err(BTreeError::InvalidNodeType(other as u8)),
+        }
+    }
+
+    // Position of the live entry of `page` stored under `key` whose value the duplicate order
+    // puts level with `value`
+    fn find_live_value(
+        &self,
+        page: &SlottedPage<K, V>,
+        key: &K,
+        value: &V,
+    ) -> Result<Option<usize>, BTreeError> {
+        let order = self.duplicate_order()?;
+        for pos in page.find_key_position(key)?..page.slots.len() {
+            if &page.read_key(pos)? != key {
+                break;
+            }
+            if !page.is_tombstoned(pos) && order(&page.read_value(pos)?, value).is_eq() {
+                return Ok(Some(pos));
+            }
+        }
+        Ok(None)
+    }
+
+    // Removes the entry at `pos` of internal node `page`, replacing it with the largest entry
+    // of the subtree to its left.
+    fn delete_separator(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        pos: usize,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<(), BTreeError> {
+        let left_id = page.pointers[pos];
+        let Some((pred_key, pred_value)) = self.max_entry(left_id)? else {
+            // Nothing left of the separator, so it goes along with the empty subtree. A node
+            // left without keys is refilled by its parent, or collapsed if it is the root.
+            debug!("Dropping separator with empty left subtree: pos={}", pos);
+            page.delete(pos)?;
+            page.remove_pointer(pos);
+            self.free_subtree(left_id)?;
+            return Ok(());
+        };
+
+        // The replacement reuses the separator's pointers, so unlike an insert it needs no
+        // room for a new one. It can still be larger than the separator, in which case the
+        // caller splits the page and tries again.
+        page.delete(pos)?;
+        if let Err(BTreeError::PageOverflow { .. }) = page.insert(pos, &pred_key, &pred_value) {
+            if page.free_list.is_empty() {
+                return Err(BTreeError::PageOverflow {
+                    page_id: page.page_id,
+                });
+            }
+            self.compact_page(page)?;
+            page.insert(pos, &pred_key, &pred_value)?;
+        }
+
+        // The entry moved up is removed by position, since a search by key would find the
+        // first of any duplicates. It moves rather than being deleted, so no tombstone of it is
+        // left below an equal separator.
+        let mut left = self.read_page(left_id)?;
+        if !self.delete_max(&mut left, displaced)? {
+            return Err(BTreeError::Corrupted {
+                page_id: left_id,
+                reason: "largest entry vanished while replacing a separator".to_string(),
+            });
+        }
+        page.set_count(pos, left.subtree_entries());
+        self.merge_if_underfull(page, pos, left, displaced)
+    }
+
+    // Removes the entry `max_entry` returns from the subtree rooted at `page`, returning false
+    // if the subtree holds none. Every modified page, including `page`, is written.
+    fn delete_max(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<bool, BTreeError> {
+        if page.node_type == NodeType::LEAF {
+            let Some(pos) = (0..page.slots.len())
+                .rev()
+                .find(|&pos| !page.is_tombstoned(pos))
+            else {
+                return Ok(false);
+            };
+            page.delete(pos)?;
+            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+            debug!("Deleted largest entry of leaf: pos={} page={:?}", pos, page);
+            return Ok(true);
+        }
+
+        let last = page.pointers.len() - 1;
+        let mut child = self.read_page(page.pointers[last])?;
+        if self.delete_max(&mut child, displaced)? {
+            page.set_count(last, child.subtree_entries());
+            self.merge_if_underfull(page, last, child, displaced)?;
+        } else if page.num_keys > 0 {
+            // The last separator is the largest entry, and its empty right subtree goes with it
+            page.delete(last - 1)?;
+            page.remove_pointer(last);
+            self.free_subtree(child.page_id)?;
+        } else {
+            return Ok(false);
+        }
+        BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+        Ok(true)
+    }
+
+    // Largest live entry in the subtree rooted at `page_id`, if it holds any. Tombstones in the
+    // rightmost leaf are purged first so none is left behind to the right of a new separator.
+    fn max_entry(&mut self, page_id: u64) -> Result<Option<(K, V)>, BTreeError> {
+        let mut node = self.read_page(page_id)?;
+        if node.has_tombstones() {
+            self.compact_page(&mut node)?;
+            BTree::<K, V>::write_page(&node, &mut self.page_manager)?;
+        }
+        if node.node_type == NodeType::INTERNAL
+            && let Some(&last_child) = node.pointers.last()
+            && let Some(entry) = self.max_entry(last_child)?
+        {
+            return Ok(Some(entry));
+        }
+
+        match node.num_keys {
+            0 if node.node_type == NodeType::INTERNAL => self.max_entry(node.pointers[0]),
+            0 => Ok(None),
+            n => Ok(Some(node.read_key_value(n as usize - 1)?)),
+        }
+    }
+
+    // Merges `child`, found at `pos` in `parent`, with an adjacent sibling when it has become
+    // under-full and the two fit in one page. The separator between them moves down into the
+    // merged page. A child left without keys that cannot be merged is refilled instead.
+    // `parent` is updated in memory only; the caller writes it.
+    fn merge_if_underfull(
+        &mut self,
+        parent: &mut SlottedPage<K, V>,
+        pos: usize,
+        child: SlottedPage<K, V>,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<(), BTreeError> {
+        if child.live_occupancy() >= Self::MERGE_OCCUPANCY || parent.pointers.len() < 2 {
+            return Ok(());
+        }
+
+        let (sep, left, right) = if pos + 1 < parent.pointers.len() {
+            let right = self.read_page(parent.pointers[pos + 1])?;
+            (pos, child, right)
+        } else {
+            let left = self.read_page(parent.pointers[pos - 1])?;
+            (pos - 1, left, child)
+        };
+
+        let (sep_key, sep_value) = parent.read_key_value(sep)?;
+        let Some(merged) = self.merge_pages(&left, &sep_key, &sep_value, &right)? else {
+            let child = if sep == pos { left } else { right };
+            return self.refill_if_keyless(parent, pos, child, displaced);
+        };
+
+        BTree::<K, V>::write_page(&merged, &mut self.page_manager)?;
+        self.free_node(right.page_id, right.node_type)?;
+        parent.delete(sep)?;
+        let (_, right_entries) = parent.remove_pointer(sep + 1);
+        parent.adjust_count(sep, right_entries as i64 + 1);
+
+        let event = MergeEvent {
+            page_id: merged.page_id,
+            removed_page_id: right.page_id,
+            node_type: merged.node_type,
+            entries: merged.num_keys,
+        };
+        debug!("Merged pages: {:?}", event);
+        self.observers.iter().for_each(|o| o.on_merge(&event));
+        Ok(())
+    }
+
+    // Gives `child`, found at `pos` in `parent`, a key again if it is an internal node left
+    // with none. One is rotated in from a sibling through `parent` if the sibling can spare it
+    // and `parent` has room for the sibling's key, or else found by splitting the child's only
+    // child. Failing both, the child's subtree is taken apart and its entries, with the
+    // separator beside it in `parent`, are pushed onto `displaced` to be inserted again.
+    // `parent` is updated in memory only; the caller writes it.
+    fn refill_if_keyless(
+        &mut self,
+        parent: &mut SlottedPage<K, V>,
+        pos: usize,
+        mut child: SlottedPage<K, V>,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<(), BTreeError> {
+        if child.node_type != NodeType::INTERNAL || child.num_keys > 0 {
+            return Ok(());
+        }
+        if self.rotate_into(parent, pos, &mut child)? || self.split_only_child(&mut child)? {
+            parent.set_count(pos, child.subtree_entries());
+            return Ok(());
+        }
+
+        let sep = match pos + 1 < parent.pointers.len() {
+            true => pos,
+            false => pos - 1,
+        };
+        debug!(
+            "Taking apart subtree without keys: page={} parent={}",
+            child.page_id, parent.page_id
+        );
+        displaced.push(parent.read_key_value(sep)?);
+        self.take_subtree(child.page_id, displaced)?;
+        parent.delete(sep)?;
+        parent.remove_pointer(pos);
+        Ok(())
+    }
+
+    // Moves the separator beside keyless `child` in `parent` down into it, along with the
+    // nearest child of a sibling with keys to spare, and the sibling's nearest key up in its
+    // place. Returns false, changing nothing, if no sibling can spare one that fits in
+    // `parent`.
+    fn rotate_into(
+        &mut self,
+        parent: &mut SlottedPage<K, V>,
+        pos: usize,
+        child: &mut SlottedPage<K, V>,
+    ) -> Result<bool, BTreeError> {
+        let siblings = [
+            (pos + 1 < parent.pointers.len()).then_some(pos + 1),
+            pos.checked_sub(1),
+        ];
+        for sibling_pos in siblings.into_iter().flatten() {
+            let mut sibling = self.read_page(parent.pointers[sibling_pos])?;
+            if sibling.num_keys < 2 {
+                continue;
+            }
+            let from_right = sibling_pos > pos;
+            let sep = pos.min(sibling_pos);
+            let taken = match from_right {
+                true => 0,
+                false => sibling.slots.len() - 1,
+            };
+            let (up_key, up_value) = sibling.read_key_value(taken)?;
+            let (key_len, value_len) = parent.encoded_len(&up_key, &up_value)?;
+            if !parent.can_replace(sep, key_len, value_len) {
+                continue;
+            }
+
+            let (down_key, down_value) = parent.read_key_value(sep)?;
+            parent.delete(sep)?;
+            if !parent.can_insert(key_len, value_len) {
+                self.compact_page(parent)?;
+            }
+            parent.insert(sep, &up_key, &up_value)?;
+            sibling.delete(taken)?;
+            child.insert(0, &down_key, &down_value)?;
+            let (moved, entries) = match from_right {
+                true => sibling.remove_pointer(0),
+                false => sibling.remove_pointer(sibling.pointers.len() - 1),
+            };
+            child.insert_pointer(if from_right { 1 } else { 0 }, moved, entries);
+            parent.set_count(sibling_pos, sibling.subtree_entries());
+            debug!(
+                "Rotated into node without keys: page={} sibling={}",
+                child.page_id, sibling.page_id
+            );
+            BTree::<K, V>::write_page(&sibling, &mut self.page_manager)?;
+            BTree::<K, V>::write_page(child, &mut self.page_manager)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // Splits the only child of keyless `child`, moving the entry promoted by the split up into
+    // it. Returns false, changing nothing, if the only child is too small to split into two
+    // nodes that are not themselves without keys.
+    fn split_only_child(&mut self, child: &mut SlottedPage<K, V>) -> Result<bool, BTreeError> {
+        let mut only = self.read_page(child.pointers[0])?;
+        let needed = match only.node_type {
+            NodeType::INTERNAL => 3,
+            _ => 2,
+        };
+        let live = (0..only.slots.len())
+            .filter(|&idx| !only.is_tombstoned(idx))
+            .count();
+        if live < needed {
+            return Ok(false);
+        }
+        // The promoted entry must be a live one
+        if only.has_tombstones() {
+            self.compact_page(&mut only)?;
+        }
+
+        let new_page_id = self.allocate_node(only.node_type)?;
+        let (key, value, right) = only.split(new_page_id)?;
+        self.notify_split(&only, &right);
+        BTree::<K, V>::write_page(&only, &mut self.page_manager)?;
+        BTree::<K, V>::write_page(&right, &mut self.page_manager)?;
+        child.insert(0, &key, &value)?;
+        child.set_count(0, only.subtree_entries());
+        child.insert_pointer(1, right.page_id, right.subtree_entries());
+        debug!(
+            "Split only child of node without keys: page={} child={} new_page_id={}",
+            child.page_id, only.page_id, new_page_id
+        );
+        BTree::<K, V>::write_page(child, &mut self.page_manager)?;
+        Ok(true)
+    }
+
+    // Pushes the live entries of the subtree under `page_id` onto `entries` in order, freeing
+    // its nodes
+    fn take_subtree(&mut self, page_id: u64, entries: &mut Vec<(K, V)>) -> Result<(), BTreeError> {
+        let node = self.read_page(page_id)?;
+        for idx in 0..=node.slots.len() {
+            if let Some(&child) = node.pointers.get(idx) {
+                self.take_subtree(child, entries)?;
+            }
+            if idx < node.slots.len() && !node.is_tombstoned(idx) {
+                entries.push(node.read_key_value(idx)?);
+            }
+        }
+        self.free_node(page_id, node.node_type)
+    }
+
+    // Frees every node of the subtree under `page_id`, which holds no live entries
+    fn free_subtree(&mut self, page_id: u64) -> Result<(), BTreeError> {
+        let node = self.read_page(page_id)?;
+        for &child in &node.pointers {
+            self.free_subtree(child)?;
+        }
+        self.free_node(page_id, node.node_type)
+    }
+
+    // Builds `left`, the separator and `right` into a single page with `left`'s id, or returns
+    // `None` if they do not fit.
+    fn merge_pages(
+        &self,
+        left: &SlottedPage<K, V>,
+        sep_key: &K,
+        sep_value: &V,
+        right: &SlottedPage<K, V>,
+    ) -> Result<Option<SlottedPage<K, V>>, BTreeError> {
+        let page_size = self.header.node_size(left.node_type) as usize;
+        let mut merged = SlottedPage::new(left.page_id, left.node_type, page_size);
+        merged.set_checksummed(self.header.checksums_pages());
+        self.attach_codecs(&mut merged);
+        if left.node_type == NodeType::INTERNAL {
+            merged.pointers = left.pointers.clone();
+            merged.pointers.extend_from_slice(&right.pointers);
+            merged.set_counted(left.is_counted());
+            if merged.is_counted() {
+                merged.counts = left.counts.clone();
+                merged.counts.extend_from_slice(&right.counts);
+            }
+        }
+
+        // Tombstoned entries are dropped rather than carried over
+        let live = |page: &SlottedPage<K, V>| {
+            (0..page.slots.len())
+                .filter(|&i| !page.is_tombstoned(i))
+                .collect::<Vec<_>>()
+        };
+        let entries = live(left)
+            .into_iter()
+            .map(|i| left.read_key_value(i))
+            .chain(core::iter::once(Ok((sep_key.clone(), sep_value.clone()))))
+            .chain(live(right).into_iter().map(|i| right.read_key_value(i)));
+        for entry in entries {
+            let (key, value) = entry?;
+            let pos = merged.slots.len();
+            match merged.insert(pos, &key, &value) {
+                Ok(()) => {}
+                Err(BTreeError::PageOverflow { .. }) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(merged))
+    }
+
+    /// Removes every entry, leaving a single empty root. The file is truncated back to the
+    /// header and that one page, so the space goes back to the filesystem rather than being
+    /// kept for reuse. A compression dictionary, if any, is kept.
+    pub fn clear(&mut self) -> Result<(), BTreeError> {
+        // Truncates before reading or committing anything
+        self.check_poisoned()?;
+        info!("Clearing tree of {} pages", self.header.page_count);
+        self.advance_epoch();
+        self.page_manager.truncate()?;
+        if let Some(merkle) = &mut self.merkle {
+            merkle.clear();
+        }
+        self.header.stats_page_id = Header::NO_STATS;
+        // The LSN keeps rising, so tokens from before the clear are not met by an older tree
+        self.stats = TreeStats {
+            commits: self.stats.commits + 1,
+            ..TreeStats::default()
+        };
+
+        let root = Self::create_page(&mut self.header, NodeType::LEAF, &mut self.page_manager)?;
+        self.header.root_page_id = root.page_id;
+        BTree::<K, V>::write_page(&root, &mut self.page_manager)?;
+
+        if let Some(compressor) = self.compressor.clone() {
+            self.header.dictionary_page_id = self.write_dictionary(compressor.dictionary())?;
+        }
+        // Sequences must never go back, so they are not left waiting for the next flush
+        if !self.sequences.is_empty() {
+            write_stats(
+                &mut self.header,
+                &mut self.page_manager,
+                &self.stats,
+                &self.sequences,
+            )?;
+        }
+        self.commit_header()?;
+        self.watchers.reset(self.stats.commits);
+        Ok(())
+    }
+
+    /// Iterates over the entries whose keys fall within `range`, in key order.
+    ///
+    /// The iterator borrows the tree, so the tree cannot change while it is in use.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> Result<Range<'_, K, V>, BTreeError> {
+        Range::new(
+            self,
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+        )
+    }
+
+    /// Iterates over every entry in key order.
+    pub fn iter(&mut self) -> Result<Range<'_, K, V>, BTreeError> {
+        self.range(..)
+    }
+
+    /// Returns up to `limit` of the entries within `range` in key order, starting where the
+    /// page `token` was returned with ended, or at the start of the range without one. A
+    /// `limit` of 0 is taken as 1.
+    ///
+    /// The tree is not borrowed between pages, and writes made in between are seen by the
+    /// pages after them: an entry is never returned twice, and only one inserted behind the
+    /// position the scan has reached is missed. The exception is a run of duplicates split
+    /// across pages, where deleting one of those returned makes the next page skip another.
+    /// The token is only valid for the same range.
+    pub fn scan_page<R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+        limit: usize,
+        token: Option<&ContinuationToken>,
+    ) -> Result<ScanPage<K, V>, BTreeError> {
+        let limit = limit.max(1);
+        let (start, resume, mut skip) = match token {
+            None => (range.start_bound().cloned(), None, 0),
+            Some(token) => {
+                let (bytes, skip) = token.decode()?;
+                let key: K = self
+                    .encoding()
+                    .deserialize(&bytes)
+                    .map_err(|e| BTreeError::InvalidContinuationToken(e.to_string()))?;
+                if !range.contains(&key) {
+                    return Err(BTreeError::InvalidContinuationToken(format!(
+                        "key {} is outside the range scanned",
+                        key.to_string()
+                    )));
+                }
+                (Bound::Included(key.clone()), Some(key), skip)
+            }
+        };
+        // Entries with the key a page ended on returned by earlier pages
+        let returned = skip;
+
+        let mut entries = Vec::with_capacity(limit);
+        let mut more = false;
+        for entry in self.range((start, range.end_bound().cloned()))? {
+            let (key, value) = entry?;
+            if skip > 0 && resume.as_ref() == Some(&key) {
+                skip -= 1;
+                continue;
+            }
+            if entries.len() == limit {
+                more = true;
+                break;
+            }
+            entries.push((key, value));
+        }
+
+        let next = match entries.last() {
+            Some((last, _)) if more => {
+                let mut run = entries.iter().rev().take_while(|(k, _)| k == last).count() as u32;
+                if resume.as_ref() == Some(last) {
+                    run += returned;
+                }
+                Some(ContinuationToken::new(
+                    &self.encoding().serialize(last)?,
+                    run,
+                ))
+            }
+            _ => None,
+        };
+        Ok(ScanPage { entries, next })
+    }
+
+    /// Starts a walk over the entries whose keys fall within `range` that, unlike
+    /// [`BTree::range`], does not keep the tree borrowed between steps.
+    ///
+    /// Inserts and deletes in between steps are allowed: once one has rewritten a page the
+    /// cursor is on, as when splitting its leaf, the cursor finds its place again after the
+    /// last key it returned, so no entry is returned twice or skipped. Entries added or removed
+    /// ahead of it are seen as they are when reached. Operations that rewrite the tree as a
+    /// whole, such as `clear` or `rebuild`, make the walk fail with `BTreeError::TreeModified`
+    /// instead. To scan the tree as it was at one point, iterate a [`Snapshot`] taken with
+    /// [`BTree::freeze`].
+    pub fn range_cursor<R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<RangeCursor<K, V>, BTreeError> {
+        let root_page_id = self.header.root_page_id;
+        let start = range.start_bound().cloned();
+        let cursor = Cursor::new(
+            root_page_id,
+            &start,
+            range.end_bound().cloned(),
+            &mut |id| self.read_page(id),
+        )?;
+        Ok(RangeCursor {
+            cursor,
+            start,
+            last_key: None,
+            epoch: self.epoch,
+            version: self.page_manager.write_version(),
+        })
+    }
+
+    // Called before any change that rewrites the tree as a whole and so ends every `RangeCursor`
+    fn advance_epoch(&mut self) {
+        self.epoch = next_epoch();
+    }
+
+    /// Number of entries whose keys fall within `range`, found without reading any values or
+    /// collecting the entries. Trees keeping subtree counts only walk down to the two ends of
+    /// the range.
+    pub fn count_range<R: RangeBounds<K>>(&mut self, range: R) -> Result<u64, BTreeError> {
+        if self.header.subtree_counts {
+            let end = match range.end_bound() {
+                Bound::Included(key) => self.entries_before(key, true)?,
+                Bound::Excluded(key) => self.entries_before(key, false)?,
+                Bound::Unbounded => self.read_page(self.header.root_page_id)?.subtree_entries(),
+            };
+            let start = match range.start_bound() {
+                Bound::Included(key) => self.entries_before(key, false)?,
+                Bound::Excluded(key) => self.entries_before(key, true)?,
+                Bound::Unbounded => 0,
+            };
+            return Ok(end.saturating_sub(start));
+        }
+
+        let root_page_id = self.header.root_page_id;
+        let mut read_page = |id| self.read_page(id);
+        let mut cursor = Cursor::new(
+            root_page_id,
+            &range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            &mut read_page,
+        )?;
+        cursor.count_remaining(&mut read_page)
+    }
+
+    /// Number of entries whose keys are less than `key`, which is also the position `key` has
+    /// or would have in key order.
+    ///
+    /// Trees keeping subtree counts answer from a single walk down the tree; others count
+    /// every entry before `key`.
+    pub fn rank(&mut self, key: K) -> Result<u64, BTreeError> {
+        self.entries_before(&key, false)
+    }
+
+    /// The entry at position `index` in key order, counting from 0, or `None` if the tree has
+    /// no more than `index` entries.
+    ///
+    /// Trees keeping subtree counts find it with a single walk down the tree; others scan
+    /// every entry before it.
+    pub fn select(&mut self, index: u64) -> Result<Option<(K, V)>, BTreeError> {
+        if !self.header.subtree_counts {
+            return self.iter()?.nth(index as usize).transpose();
+        }
+
+        let mut remaining = index;
+        let mut page = self.read_page(self.header.root_page_id)?;
+        loop {
+            match page.node_type {
+                NodeType::LEAF => {
+                    return (0..page.slots.len())
+                        .filter(|&i| !page.is_tombstoned(i))
+                        .nth(remaining as usize)
+                        .map(|i| page.read_key_value(i))
+                        .transpose();
+                }
+                NodeType::INTERNAL => {
+                    // Children and the separators between them, left to right
+                    let mut child = None;
+                    for (i, &count) in page.counts.iter().enumerate() {
+                        if remaining < count {
+                            child = Some(page.pointers[i]);
+                            break;
+                        }
+                        remaining -= count;
+                        if i < page.slots.len() {
+                            if remaining == 0 {
+                                return page.read_key_value(i).map(Some);
+                            }
+                            remaining -= 1;
+                        }
+                    }
+                    match child {
+                        Some(child) => page = self.read_page(child)?,
+                        None => return Ok(None),
+                    }
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
+            }
+        }
+    }
+
+    // Number of entries with keys less than `key`, or no greater than it when `inclusive`
+    fn entries_before(&mut self, key: &K, inclusive: bool) -> Result<u64, BTreeError> {
+        if !self.header.subtree_counts {
+            return match inclusive {
+                true => self.count_range(..=key.clone()),
+                false => self.count_range(..key.clone()),
+            };
+        }
+
+        let mut before = 0;
+        let mut page = self.read_page(self.header.root_page_id)?;
+        loop {
+            let pos = match inclusive {
+                true => page.find_upper_position(key)?,
+                false => page.find_key_position(key)?,
+            };
+            before += (0..pos).filter(|&i| !page.is_tombstoned(i)).count() as u64;
+            match page.node_type {
+                NodeType::LEAF => return Ok(before),
+                NodeType::INTERNAL => {
+                    before += page.counts[..pos].iter().sum::<u64>();
+                    page = self.read_page(page.pointers[pos])?;
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
+            }
+        }
+    }
+
+    /// Calls `f` with a [`RawEntry`] of each entry within `range` in order, as
+    /// [`get_raw`](Self::get_raw) does for one, until it returns `false`. Returns the number of
+    /// entries `f` was called with. Keys are still decoded, to find the end of the range.
+    pub fn scan_raw<R, F>(&mut self, range: R, mut f: F) -> Result<u64, BTreeError>
+    where
+        R: RangeBounds<K>,
+        F: FnMut(RawEntry<'_>) -> bool,
+    {
+        let root_page_id = self.header.root_page_id;
+        let mut cursor = Cursor::new(
+            root_page_id,
+            &range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            &mut |id| self.read_page(id),
+        )?;
+        let mut visited = 0;
+        while let Some((_, index)) = cursor.next_entry(&mut |id| self.read_page(id))? {
+            let (node, _) = cursor.stack.last().unwrap();
+            let value = node.value_bytes(index)?;
+            visited += 1;
+            if !f(RawEntry::from_page(node, index, &value)) {
+                break;
+            }
+        }
+        Ok(visited)
+    }
+
+    /// Iterates over the keys within `range` in order without reading their values, for
+    /// existence checks and joins over trees whose values are large.
+    pub fn keys_in_range<R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<Keys<'_, K, V>, BTreeError> {
+        let root_page_id = self.header.root_page_id;
+        let cursor = Cursor::new(
+            root_page_id,
+            &range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            &mut |id| self.read_page(id),
+        )?;
+        Ok(Keys { tree: self, cursor })
+    }
+
+    /// Iterates over the entries within `range` whose keys satisfy `predicate`, in order. The
+    /// predicate sees each key as soon as it is decoded from its slot, and values are only
+    /// read for the keys it accepts, so a selective filter over large values costs little more
+    /// than a scan of the keys.
+    pub fn scan_where<R: RangeBounds<K>, F: FnMut(&K) -> bool>(
+        &mut self,
+        range: R,
+        predicate: F,
+    ) -> Result<ScanWhere<'_, K, V, F>, BTreeError> {
+        let root_page_id = self.header.root_page_id;
+        let cursor = Cursor::new(
+            root_page_id,
+            &range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            &mut |id| self.read_page(id),
+        )?;
+        Ok(ScanWhere {
+            tree: self,
+            cursor,
+            predicate,
+        })
+    }
+
+    /// Takes a read-only [`Snapshot`] of the tree as it is now, for scans that should neither
+    /// hold the tree nor disturb its page cache.
+    ///
+    /// Every page reachable from the root is copied into an unnamed temporary file, so this
+    /// costs a full pass over the tree and disk space for a copy of it.
+    #[cfg(feature = "std")]
+    pub fn freeze(&mut self) -> Result<Snapshot<K, V>, BTreeError> {
+        use std::io::Write;
+
+        let mut file = tempfile::tempfile()?;
+        let mut extents = HashMap::new();
+        let mut length = 0;
+        let mut pending = vec![self.header.root_page_id];
+        while let Some(page_id) = pending.pop() {
+            let buffer = self.read_node_bytes(page_id)?;
+            let node: SlottedPage<K, V> = SlottedPage::deserialize(&buffer, buffer.len())?;
+            pending.extend(&node.pointers);
+
+            extents.insert(page_id, length..length + buffer.len());
+            length += buffer.len();
+            file.write_all(&buffer)?;
+        }
+        info!("Froze {} pages into a snapshot", extents.len());
+
+        Snapshot::new(
+            file,
+            extents,
+            &self.header,
+            self.stats,
+            self.value_codec.clone(),
+            self.compressor.clone(),
+            self.value_log.as_ref().map(|value_log| value_log.pin()),
+        )
+    }
+
+    /// Removes and yields the entries whose keys fall within `range`, in key order.
+    ///
+    /// Entries are taken out of the tree a leaf at a time, with the tree rebalanced after each
+    /// leaf, so emptied pages are merged away and freed for reuse as the drain progresses.
+    /// Entries taken out but not yet yielded when the iterator is dropped are inserted again.
+    pub fn drain_range<R: RangeBounds<K>>(&mut self, range: R) -> Drain<'_, K, V> {
+        Drain {
+            tree: self,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Removes and yields every entry in key order.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        self.drain_range(..)
+    }
+
+    // Removes the first entries within `start` and `end`: the live ones in range on the leaf
+    // the range starts on, or if it has none the separator after that leaf
+    fn drain_run(&mut self, start: &Bound<K>, end: &Bound<K>) -> Result<Vec<(K, V)>, BTreeError> {
+        self.apply_backpressure()?;
+        self.page_manager.begin_pinned_operation();
+        let result = self.drain_run_pinned(start, end);
+        self.page_manager.end_pinned_operation();
+        result
+    }
+
+    fn drain_run_pinned(
+        &mut self,
+        start: &Bound<K>,
+        end: &Bound<K>,
+    ) -> Result<Vec<(K, V)>, BTreeError> {
+        let mut root = self.read_page(self.header.root_page_id)?;
+        let mut displaced = Vec::new();
+        let entries = match self.take_run(&mut root, start, end, &mut displaced)? {
+            DrainRun::Leaf(entries) => entries,
+            // Deleted by key, as it may have to make room for the entry replacing it
+            DrainRun::Separator(key) => {
+                let value = self.delete_entry(key.clone(), None)?;
+                return Ok(vec![(key, value)]);
+            }
+            DrainRun::Empty => return Ok(Vec::new()),
+        };
+
+        self.writes_since_flush += 1;
+        for (key, value) in &entries {
+            self.stats.remove_entry(
+                self.encoding().serialized_size(key)?,
+                self.codecs().value_len(value)?,
+            );
+        }
+        self.stats.deletes += entries.len() as u64;
+        self.stats.commits += 1;
+
+        self.finish_rebalance(&root, displaced)?;
+
+        self.commit_header()?;
+        for (key, value) in &entries {
+            if self.watchers.watching(key) {
+                self.watchers
+                    .deleted(self.stats.commits, key.clone(), value.clone());
+            }
+        }
+        Ok(entries)
+    }
+
+    // Removes the run `drain_run` takes from the subtree rooted at `page`, rebalancing it as
+    // `delete_from_page` does. Nothing is changed unless a leaf run is returned.
+    fn take_run(
+        &mut self,
+        page: &mut SlottedPage<K, V>,
+        start: &Bound<K>,
+        end: &Bound<K>,
+        displaced: &mut Vec<(K, V)>,
+    ) -> Result<DrainRun<K, V>, BTreeError> {
+        let before_end = |key: &K| match end {
+            Bound::Unbounded => true,
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+        };
+        let first = Cursor::first_index(page, start)?;
+        match page.node_type {
+            NodeType::LEAF => {
+                let mut run = Vec::new();
+                for pos in first..page.slots.len() {
+                    let key = page.read_key(pos)?;
+                    if !before_end(&key) {
+                        break;
+                    }
+                    if !page.is_tombstoned(pos) {
+                        self.check_range_locks(None, &key)?;
+                        run.push(pos);
+                    }
+                }
+                if run.is_empty() {
+                    return Ok(DrainRun::Empty);
+                }
+
+                let mut entries = Vec::with_capacity(run.len());
+                for &pos in &run {
+                    entries.push(page.read_key_value(pos)?);
+                }
+                for &pos in run.iter().rev() {
+                    match self.header.delete_strategy {
+                        DeleteStrategy::Immediate => page.delete(pos)?,
+                        DeleteStrategy::Tombstone => page.tombstone(pos),
+                    }
+                }
+                BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+                debug!(
+                    "Drained leaf: entries={} page={}",
+                    entries.len(),
+                    page.page_id
+                );
+                Ok(DrainRun::Leaf(entries))
+            }
+            NodeType::INTERNAL => {
+                let mut child = self.read_page(page.pointers[first])?;
+                match self.take_run(&mut child, start, end, displaced)? {
+                    DrainRun::Leaf(entries) => {
+                        page.set_count(first, child.subtree_entries());
+                        match self.header.delete_strategy {
+                            DeleteStrategy::Immediate => {
+                                self.merge_if_underfull(page, first, child, displaced)?
+                            }
+                            DeleteStrategy::Tombstone => {
+                                self.refill_if_keyless(page, first, child, displaced)?
+                            }
+                        }
+                        BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+                        Ok(DrainRun::Leaf(entries))
+                    }
+                    DrainRun::Empty if first < page.slots.len() => {
+                        let key = page.read_key(first)?;
+                        match before_end(&key) {
+                            true => Ok(DrainRun::Separator(key)),
+                            false => Ok(DrainRun::Empty),
+                        }
+                    }
+                    run => Ok(run),
+                }
+            }
+            other => Err(BTreeError::InvalidNodeType(other as u8)),
+        }
+    }
+
+    /// Returns whether an entry of the given size fits in `page`, compacting the page first if
+    /// fragmentation is what stops it fitting.
+    fn make_room(
+        &self,
+        page: &mut SlottedPage<K, V>,
+        key_len: usize,
+        value_len: usize,
+    ) -> Result<bool, BTreeError> {
+        if page.can_insert(key_len, value_len) {
+            return Ok(true);
+        }
+        // A page is never split while it holds tombstones, so they cannot move elsewhere
+        let fragmented =
+            !page.free_list.is_empty() && page.live_occupancy() < self.header.split_threshold;
+        if !fragmented && !page.has_tombstones() {
+            return Ok(false);
+        }
+
+        self.compact_page(page)?;
+        Ok(page.can_insert(key_len, value_len))
+    }
+
+    fn compact_page(&self, page: &mut SlottedPage<K, V>) -> Result<(), BTreeError> {
+        let event = CompactEvent {
+            page_id: page.page_id,
+            node_type: page.node_type,
+            holes_reclaimed: page.free_list.len(),
+            bytes_reclaimed: page
+                .free_list
+                .iter()
+                .map(|r| r.length as usize)
+                .sum::<usize>()
+                + page.tombstoned_bytes(),
+        };
+        page.compact()?;
+        debug!("Compacted page: {:?}", event);
+        self.observers.iter().for_each(|o| o.on_compact(&event));
+        Ok(())
+    }
+
+    /// Compacts every page whose holes make up a large share of its free space, rather than
+    /// waiting for an insert to run out of room on it. Returns the number of pages compacted.
+    pub fn compact_fragmented_pages(&mut self) -> Result<usize, BTreeError> {
+        let mut compacted = 0;
+        self.advance_epoch();
+        self.compact_node(self.header.root_page_id, &mut compacted)?;
+        Ok(compacted)
+    }
+
+    fn compact_node(&mut self, page_id: u64, compacted: &mut usize) -> Result<(), BTreeError> {
+        let mut node = self.read_page(page_id)?;
+        if node.should_compact() {
+            self.compact_page(&mut node)?;
+            BTree::<K, V>::write_page(&node, &mut self.page_manager)?;
+            *compacted += 1;
+        }
+
+        for child_id in node.pointers.clone() {
+            self.compact_node(child_id, compacted)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the tree into a new file at `dest_path` and renames that over the tree's own
+    /// file, giving back the space deletes, merges and freed pages leave behind, which page
+    /// compaction cannot. Live entries are streamed through the bulk loader, so the new file
+    /// is packed to the configured fill factors and no more than a few pages are held in
+    /// memory. Writes wait for the rebuild, as it holds the tree throughout.
+    ///
+    /// `dest_path` must not exist and must be on the same filesystem as the tree, as the
+    /// rename is what swaps the files in; a crash before it leaves the old file as it was.
+    /// Only trees opened by path can be rebuilt, and ones keeping duplicate keys or values in
+    /// a value log fail with `Unsupported`. Pages in a cold tier are brought back into the
+    /// file, which has none attached afterwards.
+    #[cfg(feature = "std")]
+    pub fn rebuild<P: AsRef<Path>>(&mut self, dest_path: P) -> Result<(), BTreeError> {
+        let Some(data_path) = self.data_path.clone() else {
+            return Err(BTreeError::Unsupported(
+                "only trees opened by path can be rebuilt".to_string(),
+            ));
+        };
+        if self.value_log.is_some() {
+            return Err(BTreeError::Unsupported(
+                "a tree and its value log cannot be swapped in one step".to_string(),
+            ));
+        }
+        if self.header.duplicate_policy.keeps_duplicates() {
+            return Err(BTreeError::Unsupported(
+                "the bulk loader cannot load duplicate keys".to_string(),
+            ));
+        }
+        let dest_path = dest_path.as_ref();
+        if dest_path.exists() {
+            return Err(BTreeError::Io(crate::io::Error::new(
+                crate::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", dest_path.display()),
+            )));
+        }
+
+        self.flush()?;
+        let pages_before = self.header.page_count;
+        if let Err(e) = self.write_rebuilt(dest_path) {
+            for path in [dest_path.to_path_buf(), Manifest::path_for(dest_path)] {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        std::fs::rename(dest_path, &data_path)?;
+        std::fs::remove_file(Manifest::path_for(dest_path))?;
+        if let Some(dir) = data_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            storage::sync_dir(dir)?;
+        }
+
+        // Takes over the new file's pages, keeping this handle's settings
+        let mut rebuilt = Self::open_unregistered(&data_path, self.config(), false)?;
+        rebuilt
+            .page_manager
+            .set_buffer_pool(self.page_manager.buffer_pool().clone());
+        core::mem::swap(&mut self.page_manager, &mut rebuilt.page_manager);
+        core::mem::swap(&mut self.header, &mut rebuilt.header);
+        core::mem::swap(&mut self.written_header, &mut rebuilt.written_header);
+        core::mem::swap(&mut self.compressor, &mut rebuilt.compressor);
+        core::mem::swap(&mut self.stats, &mut rebuilt.stats);
+        // Dropping it would otherwise write to the file that was replaced
+        rebuilt.writes_since_flush = 0;
+        self.advance_epoch();
+        if let Some(merkle) = &mut self.merkle {
+            merkle.clear();
+        }
+        info!(
+            "Rebuilt {:?}: {} pages, down from {}",
+            data_path, self.header.page_count, pages_before
+        );
+        Ok(())
+    }
+
+    // Bulk loads the live entries of the tree into a new tree at `dest_path` and flushes it
+    #[cfg(feature = "std")]
+    fn write_rebuilt(&mut self, dest_path: &Path) -> Result<(), BTreeError> {
+        let mut rebuilt = Self::open_unregistered(dest_path, self.config(), false)?;
+        if let Some(codec) = &self.value_codec {
+            rebuilt.set_value_codec(codec.clone())?;
+        }
+        if let Some(compressor) = &self.compressor {
+            rebuilt.set_compression_dictionary(compressor.dictionary().to_vec())?;
+        }
+        let mut error = None;
+        let entries = self
+            .iter()?
+            .map_while(|entry| entry.map_err(|e| error = Some(e)).ok());
+        rebuilt.bulk_load(entries)?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        rebuilt.trim_free_pages()?;
+        // The LSN keeps rising, so tokens handed out before the rebuild stay met
+        rebuilt.stats.commits = self.stats.commits;
+        rebuilt.stats.deletes = self.stats.deletes;
+        rebuilt.sequences = self.sequences.clone();
+        rebuilt.header.version = self.header.version.max(rebuilt.header.version);
+        rebuilt.flush()
+    }
+
+    // Shrinks the storage to end at the last page in use. The bulk loader leaves the rest of
+    // the last extent it reserved allocated but free.
+    #[cfg(feature = "std")]
+    pub(crate) fn trim_free_pages(&mut self) -> Result<(), BTreeError> {
+        let mut used = 0;
+        let mut page_id = 0;
+        while page_id < self.page_manager.page_count() {
+            let node_type = self.page_type(page_id)?;
+            let pages = self.header.node_pages(node_type).max(1);
+            if node_type != NodeType::FREE {
+                used = page_id + pages;
+            }
+            page_id += pages;
+        }
+        self.page_manager.shrink_to(used)?;
+        Ok(())
+    }
+
+    /// Keeps the hash of every subtree between calls to `root_hash` from now on, so each call
+    /// only hashes again the pages written since the last one and their ancestors. The hashes
+    /// are held in memory rather than persisted, so the first call after opening hashes the
+    /// whole tree.
+    pub fn set_merkle_hashes(&mut self, enabled: bool) {
+        self.merkle = enabled.then(MerkleCache::default);
+        self.page_manager.track_written(enabled);
+    }
+
+    /// A hash over every entry in the tree, built up from a hash of each page, which changes
+    /// with any insert, update or delete. The hashes follow the pages, so two trees holding
+    /// the same entries only hash alike if their pages split alike, as for replicas given the
+    /// same writes. Without `set_merkle_hashes` the whole tree is hashed on each call.
+    pub fn root_hash(&mut self) -> Result<Hash, BTreeError> {
+        self.subtree_hash(self.header.root_page_id)
+    }
+
+    /// ID of the page at the top of the tree.
+    pub fn root_page_id(&self) -> u64 {
+        self.header.root_page_id
+    }
+
+    /// The children of page `page_id` with the hash of each one's subtree, to narrow down
+    /// where two trees whose `root_hash` differs part ways, starting from `root_page_id`. A
+    /// leaf has none.
+    pub fn child_hashes(&mut self, page_id: u64) -> Result<Vec<(u64, Hash)>, BTreeError> {
+        let children = self.read_page(page_id)?.pointers;
+        children
+            .into_iter()
+            .map(|child| Ok((child, self.subtree_hash(child)?)))
+            .collect()
+    }
+
+    fn subtree_hash(&mut self, page_id: u64) -> Result<Hash, BTreeError> {
+        let enabled = self.merkle.is_some();
+        let mut merkle = self.merkle.take().unwrap_or_default();
+        for written in self.page_manager.take_written() {
+            merkle.invalidate(written);
+        }
+        let hash = self.hash_subtree(page_id, &mut merkle);
+        if enabled {
+            self.merkle = Some(merkle);
+        }
+        hash
+    }
+
+    fn hash_subtree(&mut self, page_id: u64, merkle: &mut MerkleCache) -> Result<Hash, BTreeError> {
+        if let Some(hash) = merkle.get(page_id) {
+            return Ok(hash);
+        }
+        let node = self.read_page(page_id)?;
+        let mut hasher = PageHasher::new(node.node_type == NodeType::LEAF);
+        for idx in 0..=node.slots.len() {
+            if let Some(&child) = node.pointers.get(idx) {
+                hasher.child(&self.hash_subtree(child, merkle)?);
+            }
+            if idx < node.slots.len() && !node.is_tombstoned(idx) {
+                let (key, value) = node.read_key_value(idx)?;
+                hasher.entry(&key, &value)?;
+            }
+        }
+        let hash = hasher.finish();
+        merkle.insert(page_id, hash, &node.pointers);
+        Ok(hash)
+    }
+
+    /// Estimates the memory held by this tree, so embedders can budget for it and size the
+    /// page cache.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        // Preparing the dictionary copies it into the encoder and the decoder
+        let compression = self
+            .compressor
+            .as_ref()
+            .map_or(0, |c| 3 * c.dictionary().len());
+        let observers = self.observers.len() * size_of::<Arc<dyn TreeObserver>>();
+        let merkle = self.merkle.as_ref().map_or(0, MerkleCache::memory_usage);
+
+        MemoryUsage {
+            page_cache: self.page_manager.cache().memory_usage()
+                + self.page_manager.held_memory_usage(),
+            buffer_pool: self.page_manager.buffer_pool().retained_bytes(),
+            compression,
+            auxiliary: self.page_manager.access_counts_memory_usage()
+                + self.page_manager.page_versions_memory_usage()
+                + observers
+                + merkle,
+        }
+    }
+
+    /// Number of inserts since the last `flush`.
+    pub fn writes_since_flush(&self) -> u64 {
+        self.writes_since_flush
+    }
+
+    /// Number of pages written since the last `flush`.
+    pub fn dirty_pages(&self) -> u64 {
+        self.page_manager.dirty_pages()
+    }
+
+    // Holds back a write once `TreeConfig::max_dirty_pages` pages were written since the last
+    // flush, flushing them first or turning the write away as configured
+    fn apply_backpressure(&mut self) -> Result<(), BTreeError> {
+        let limit = self.page_manager.max_dirty_pages();
+        let dirty_pages = self.page_manager.dirty_pages();
+        if limit == 0 || dirty_pages < limit {
+            return Ok(());
+        }
+
+        match self.backpressure {
+            Backpressure::Stall => {
+                debug!("Stalling write to flush {} dirty pages", dirty_pages);
+                let event = WriteStallEvent { dirty_pages };
+                self.observers.iter().for_each(|o| o.on_write_stall(&event));
+                self.flush()
+            }
+            Backpressure::Reject { retry_after } => {
+                debug!("Rejecting write with {} dirty pages", dirty_pages);
+                Err(BTreeError::Busy { retry_after })
+            }
+        }
+    }
+
+    // Deals with an invariant found broken part way through an operation as the handle is set
+    // to: panics, or poisons the handle and returns the error to fail the operation with
+    fn invariant_violated(&mut self, reason: String) -> BTreeError {
+        error!("Invariant violated: {}", reason);
+        match self.on_invariant_violation {
+            InvariantViolation::Panic => panic!("invariant violated: {}", reason),
+            InvariantViolation::Poison => {
+                self.page_manager.discard_held();
+                self.poisoned = Some(reason.clone());
+                BTreeError::Poisoned(reason)
+            }
+        }
+    }
+
+    fn notify_split(&self, left: &SlottedPage<K, V>, right: &SlottedPage<K, V>) {
+        let event = SplitEvent {
+            page_id: left.page_id,
+            new_page_id: right.page_id,
+            node_type: left.node_type,
+            left_entries: left.num_keys,
+            right_entries: right.num_keys,
+        };
+        self.observers.iter().for_each(|o| o.on_split(&event));
+    }
+
+    /// Registers an observer that is told about splits, merges, compactions and flushes.
+    pub fn register_observer(&mut self, observer: Arc<dyn TreeObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Watches `key`, returning a handle told about every insert and delete of it once applied,
+    /// and about clears and quarantines, which remove keys without listing them.
+    pub fn watch(&mut self, key: K) -> Watch<K, V> {
+        self.watch_range((Bound::Included(key.clone()), Bound::Included(key)))
+    }
+
+    /// Watches every key within `range`, as [`watch`](Self::watch) does a single key.
+    pub fn watch_range<R: RangeBounds<K>>(&mut self, range: R) -> Watch<K, V> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        self.watchers.add(KeyFilter::Range(start, end))
+    }
+
+    /// Open watches whose handles have not been dropped since a change was last sent to them.
+    pub fn watch_count(&self) -> usize {
+        self.watchers.len()
+    }
+
+    /// Locks the keys within `range` and the gaps between them for `owner`, so that no other
+    /// owner can insert or delete keys in it until the lock is released, as a serializable
+    /// transaction does for the ranges it scans. Writes through `insert` and `delete`, which
+    /// have no owner, are kept out too. See [`range_lock`](crate::range_lock).
+    pub fn lock_range<R: RangeBounds<K>>(&mut self, owner: LockOwner, range: R) -> RangeLock {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        self.range_locks.lock(owner, start, end)
+    }
+
+    pub fn unlock_range(&mut self, lock: RangeLock) {
+        self.range_locks.unlock(lock);
+    }
+
+    /// Releases every range locked by `owner`, as when its transaction ends, returning how
+    /// many there were.
+    pub fn unlock_owner(&mut self, owner: LockOwner) -> usize {
+        self.range_locks.unlock_owner(owner)
+    }
+
+    pub fn range_lock_count(&self) -> usize {
+        self.range_locks.len()
+    }
+
+    fn check_range_locks(&self, writer: Option<LockOwner>, key: &K) -> Result<(), BTreeError> {
+        match self.range_locks.conflict(writer, key) {
+            Some(owner) => Err(BTreeError::RangeLocked {
+                key: key.to_string(),
+                owner,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the header and syncs all written pages to disk.
+    pub fn flush(&mut self) -> Result<(), BTreeError> {
+        self.check_poisoned()?;
+        let _span = op_span!("flush", page_count = self.header.page_count);
+        write_stats(
+            &mut self.header,
+            &mut self.page_manager,
+            &self.stats,
+            &self.sequences,
+        )?;
+        self.commit_header()?;
+        // Values go first, so no synced page points at a value that was lost
+        if let Some(value_log) = &self.value_log {
+            value_log.sync()?;
+        }
+        self.page_manager.sync()?;
+        #[cfg(feature = "std")]
+        self.write_manifest()?;
+        self.writes_since_flush = 0;
+
+        let event = FlushEvent {
+            page_count: self.header.page_count,
+            page_size: self.header.page_size,
+        };
+        self.observers.iter().for_each(|o| o.on_flush(&event));
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn write_manifest(&mut self) -> Result<(), BTreeError> {
+        let Some(data_path) = &self.data_path else {
+            return Ok(());
+        };
+        self.checkpoint += 1;
+        let manifest = Manifest::new(data_path, &self.header, self.checkpoint);
+        manifest.write(&Manifest::path_for(data_path))?;
+        debug!("Wrote manifest for checkpoint {}", self.checkpoint);
+        Ok(())
+    }
+
+    /// Number of live entries in the tree.
+    pub fn len(&self) -> u64 {
+        self.stats.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stats.entries == 0
+    }
+
+    /// Next value of the sequence `name`, starting at 1 and increasing by one each call, for
+    /// generating IDs. Sequences are kept in the tree file and committed with it; see
+    /// [`crate::sequence`] for how they survive a crash. Each block of values reserved flushes
+    /// the tree.
+    pub fn next_sequence(&mut self, name: &str) -> Result<u64, BTreeError> {
+        if let Some(value) = self.sequences.next(name) {
+            return Ok(value);
+        }
+        if name.is_empty() || name.len() > MAX_SEQUENCE_NAME {
+            return Err(BTreeError::Unsupported(format!(
+                "sequence names must be 1 to {} bytes long",
+                MAX_SEQUENCE_NAME
+            )));
+        }
+        let mut sequences = self.sequences.clone();
+        sequences.reserve(name);
+        let room = self.header.page_size as usize - PAGE_PREFIX_SIZE - TreeStats::SIZE;
+        if sequences.serialized_size() > room {
+            return Err(BTreeError::Unsupported(
+                "the stats page has no room for another sequence".to_string(),
+            ));
+        }
+
+        // The block is durable before any value in it is handed out
+        let sequences = core::mem::replace(&mut self.sequences, sequences);
+        let version = self.header.version;
+        self.header.version = version.max(SEQUENCE_VERSION);
+        if let Err(e) = self.flush() {
+            self.sequences = sequences;
+            self.header.version = version;
+            return Err(e);
+        }
+        debug!("Reserved a block of sequence {}", name);
+        Ok(self.sequences.next(name).unwrap())
+    }
+
+    /// Entry count, key and value sizes and delete count of the tree. They are persisted on
+    /// `flush` and, if anything changed since, when the tree is dropped. The storage size and
+    /// its limit are those of the open handle.
+    /// LSN of the latest write, to hand back to a client as a token of it: reads given the
+    /// token with `search_at_least` then only succeed on a copy of the tree that has applied
+    /// the write, such as a replica that has caught up. 0 before any write.
+    pub fn commit_lsn(&self) -> Lsn {
+        self.stats.commits
+    }
+
+    /// Fails with `BTreeError::LsnNotReached` unless every write up to `lsn` has been applied.
+    pub fn require_lsn(&self, lsn: Lsn) -> Result<(), BTreeError> {
+        match self.stats.commits >= lsn {
+            true => Ok(()),
+            false => Err(BTreeError::LsnNotReached {
+                required: lsn,
+                applied: self.stats.commits,
+            }),
+        }
+    }
+
+    /// Like `search`, but fails with `BTreeError::LsnNotReached` instead of reading a tree
+    /// that has not yet applied every write up to `lsn`, so a client that wrote through
+    /// another copy reads its own writes or nothing.
+    pub fn search_at_least(&mut self, key: K, lsn: Lsn) -> Result<V, BTreeError> {
+        self.require_lsn(lsn)?;
+        self.search(key)
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        TreeStats {
+            file_size: self.page_manager.size(),
+            max_file_size: self.page_manager.max_size(),
+            ..self.stats
+        }
+    }
+
+    fn read_stats(&mut self) -> Result<(TreeStats, Sequences), BTreeError> {
+        let page_id = self.header.stats_page_id;
+        let page = self.page_manager.read_page(page_id)?;
+        let stats = TreeStats::deserialize(&page[PAGE_PREFIX_SIZE..]);
+        let sequences = Sequences::deserialize(&page[PAGE_PREFIX_SIZE + TreeStats::SIZE..])
+            .ok_or_else(|| BTreeError::Corrupted {
+                page_id,
+                reason: "sequences in the stats page are malformed".to_string(),
+            })?;
+        Ok((stats, sequences))
+    }
+
+    // Pages that have not changed since they were read are left alone
+    fn write_page(
+        page: &SlottedPage<K, V>,
+        page_manager: &mut PageManager,
+    ) -> Result<(), BTreeError> {
+        if !page.is_dirty() {
+            trace!("Skipping write of clean page {}", page.page_id);
+            return Ok(());
+        }
+        let mut data = page_manager.take_buffer(page.page_size());
+        page.serialize_into(&mut data)?;
+        page_manager.write_page(page.page_id, &data)?;
+        page_manager.give_back(data);
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_id: u64) -> Result<SlottedPage<K, V>, BTreeError> {
+        let buffer = self.read_node_bytes(page_id)?;
+        let (_, type_byte) = types::read_page_prefix(&buffer);
+        if !NodeType::from_byte(type_byte).is_some_and(|t| t.is_tree_node()) {
+            return Err(BTreeError::InvalidNodeType(type_byte));
+        }
+        // The buffer becomes the page's data rather than going back to the pool
+        let mut node: SlottedPage<K, V> = SlottedPage::from_buffer(buffer)?;
+        self.attach_codecs(&mut node);
+
+        Ok(node)
+    }
+
+    // Reads the whole of the node at `page_id`, whose first page says whether it is a leaf
+    // running on over the pages after it
+    fn read_node_bytes(&mut self, page_id: u64) -> Result<Vec<u8>, BTreeError> {
+        self.check_poisoned()?;
+        let buffer = self.page_manager.read_page(page_id)?;
+        let (_, type_byte) = types::read_page_prefix(&buffer);
+        let pages = NodeType::from_byte(type_byte).map_or(1, |t| self.header.node_pages(t));
+        if pages == 1 {
+            return Ok(buffer);
+        }
+        self.page_manager.give_back(buffer);
+        Ok(self.page_manager.read_pages(page_id, pages)?)
+    }
+
+    // Reserves the pages for a new node of `node_type`, returning the ID of the first
+    fn allocate_node(&mut self, node_type: NodeType) -> Result<u64, BTreeError> {
+        let pages = self.header.node_pages(node_type);
+        Ok(self.page_manager.allocate_pages(pages)?.start)
+    }
+
+    /// Reads the type recorded in the prefix of page `page_id`, so tools can classify every page
+    /// of the file.
+    pub fn page_type(&mut self, page_id: u64) -> Result<NodeType, BTreeError> {
+        let buffer = self.page_manager.read_page(page_id)?;
+        let (_, type_byte) = types::read_page_prefix(&buffer);
+        NodeType::from_byte(type_byte).ok_or(BTreeError::InvalidNodeType(type_byte))
+    }
+
+    // Marks a page the tree no longer refers to as free
+    fn free_page(&mut self, page_id: u64) -> Result<(), BTreeError> {
+        let mut page = vec![0u8; self.header.page_size as usize];
+        types::write_page_prefix(&mut page, page_id, NodeType::FREE);
+        self.page_manager.write_page(page_id, &page)?;
+        self.page_manager.release_pages(page_id..page_id + 1);
+        Ok(())
+    }
+
+    // Marks every page of a node the tree no longer refers to as free
+    fn free_node(&mut self, page_id: u64, node_type: NodeType) -> Result<(), BTreeError> {
+        for page_id in page_id..page_id + self.header.node_pages(node_type) {
+            self.free_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Trains a zstd dictionary from up to `max_samples` values already stored in the tree and
+    /// uses it to compress every value written from now on.
+    ///
+    /// Values written before training stay uncompressed until they are next updated.
+    pub fn train_compression_dictionary(
+        &mut self,
+        max_samples: usize,
+        max_dictionary_size: usize,
+    ) -> Result<(), BTreeError> {
+        let mut samples = Vec::new();
+        self.collect_value_samples(self.header.root_page_id, max_samples, &mut samples)?;
+        info!(
+            "Training compression dictionary from {} samples",
+            samples.len()
+        );
+
+        let compressor = ValueCompressor::train(&samples, max_dictionary_size)?;
+        self.install_compressor(compressor)
+    }
+
+    /// Uses a dictionary trained elsewhere (e.g. on another tree holding similar values).
+    pub fn set_compression_dictionary(&mut self, dictionary: Vec<u8>) -> Result<(), BTreeError> {
+        self.install_compressor(ValueCompressor::new(
+            dictionary,
+            ValueCompressor::DEFAULT_LEVEL,
+        ))
+    }
+
+    pub fn compression_dictionary(&self) -> Option<&[u8]> {
+        self.compressor.as_ref().map(|c| c.dictionary())
+    }
+
+    fn install_compressor(&mut self, compressor: ValueCompressor) -> Result<(), BTreeError> {
+        // Pages of a replaced dictionary are freed once the new one is written, so the header
+        // never points at a dictionary being overwritten
+        let dictionary_page_id = self.write_dictionary(compressor.dictionary())?;
+        if let Some(old) = self.compressor.clone() {
+            let per_page = self.header.page_size as usize - PAGE_PREFIX_SIZE;
+            let pages = (4 + old.dictionary().len()).div_ceil(per_page) as u64;
+            for page_id in self.header.dictionary_page_id..self.header.dictionary_page_id + pages {
+                self.free_page(page_id)?;
+            }
+        }
+        self.header.dictionary_page_id = dictionary_page_id;
+        self.header.compression = CompressionAlgorithm::ZstdDictionary;
+        self.commit_header()?;
+
+        self.compressor = Some(Arc::new(compressor));
+        Ok(())
+    }
+
+    // Gives a page read or created by the tree what it needs to encode and decode values, how
+    // to search its keys and how to place new ones
+    fn attach_codecs(&self, page: &mut SlottedPage<K, V>) {
+        page.set_codecs(self.codecs());
+        page.set_compressor(self.compressor.clone());
+        page.set_value_log(self.value_log.clone(), self.header.value_log_threshold);
+        page.set_interpolator(self.interpolator);
+        page.set_eytzinger(self.eytzinger_layout);
+        page.set_free_list_policy(self.fit_policy, self.max_free_regions);
+    }
+
+    /// Experimental: writes the slot directory of each page from now on in eytzinger order, as
+    /// a binary search tree laid out breadth first, so searches of pages read back visit slots
+    /// that sit together. Key order is restored when a page is read, and pages are searched
+    /// this way until they change, whatever this handle writes. The choice is not persisted, and
+    /// builds from before this layout misread pages written with it.
+    pub fn set_eytzinger_layout(&mut self, enabled: bool) {
+        self.eytzinger_layout = enabled;
+    }
+
+    /// Places entries in the free regions left within pages by deletes and shrinking updates
+    /// using `policy`, and compacts a page once it would keep more than `max_regions` of them.
+    /// Best fit with [`DEFAULT_MAX_FREE_REGIONS`] unless set; neither is persisted.
+    pub fn set_free_list_policy(&mut self, policy: FitPolicy, max_regions: usize) {
+        self.fit_policy = policy;
+        self.max_free_regions = max_regions;
+    }
+
+    /// Keeps page `page_id` in the page cache, whatever else is read, until it has been
+    /// unpinned as many times, so the working set of a long scan or a hot part of the tree
+    /// stays resident. Only has an effect with a `cache_size`. Pages on the path of an insert
+    /// or delete are pinned by the tree itself while it runs. Pins are not persisted.
+    pub fn pin_page(&mut self, page_id: u64) {
+        self.page_manager.pin_page(page_id);
+    }
+
+    pub fn unpin_page(&mut self, page_id: u64) {
+        self.page_manager.unpin_page(page_id);
+    }
+
+    /// Reads pages into, and serializes them to, buffers taken from `buffers` rather than
+    /// fresh allocations, such as a shared [`crate::buffer_pool::PagePool`] that reuses
+    /// them. Only applies to the open handle.
+    pub fn set_buffer_pool(&mut self, buffers: Arc<dyn BufferPool>) {
+        self.page_manager.set_buffer_pool(buffers);
+    }
+
+    /// How the tree's keys, and its values unless it has a custom codec, are serialized.
+    pub fn encoding(&self) -> Encoding {
+        self.header.encoding
+    }
+
+    /// Encodes values with `codec` rather than the tree's encoding. The codec's id is recorded
+    /// in the header, so a tree that already holds entries only takes the codec it was written
+    /// with, failing with `CodecError::WrongCodec` otherwise, or `TreeNotEmpty` if it has none.
+    /// A tree recording a codec fails with `CodecError::NotAttached` on every value it reads or
+    /// writes until the codec is set again after opening it.
+    pub fn set_value_codec(&mut self, codec: Arc<dyn ValueCodec<V>>) -> Result<(), BTreeError> {
+        let id = codec.id();
+        if id == 0 {
+            return Err(CodecError::ReservedId.into());
+        }
+        match self.header.value_codec_id {
+            0 if !self.is_empty() => return Err(BTreeError::TreeNotEmpty),
+            0 => {
+                self.header.value_codec_id = id;
+                self.commit_header()?;
+            }
+            expected if expected != id => {
+                return Err(CodecError::WrongCodec { expected, got: id }.into());
+            }
+            _ => {}
+        }
+        self.value_codec = Some(codec);
+        Ok(())
+    }
+
+    fn codecs(&self) -> Codecs<V> {
+        Codecs::new(
+            self.header.encoding,
+            self.header.value_codec_id,
+            self.value_codec.clone(),
+        )
+    }
+
+    /// Keeps values larger than `TreeConfig::value_log_threshold` in `value_log`, for trees
+    /// that have no path to put one next to, such as those opened with `with_config`. It must
+    /// be the log any values already moved out of the tree were written to.
+    pub fn set_value_log(&mut self, value_log: ValueLog) {
+        self.value_log = Some(Arc::new(value_log));
+    }
+
+    pub fn value_log(&self) -> Option<&Arc<ValueLog>> {
+        self.value_log.as_ref()
+    }
+
+    /// How long the oldest [`Snapshot`] still alive has been keeping the value log from being
+    /// collected. Snapshots of trees without a value log hold nothing back and are not counted.
+    pub fn oldest_snapshot_age(&self) -> Option<Duration> {
+        self.value_log.as_ref()?.oldest_pin_age()
+    }
+
+    /// Tells observers with a [`StaleSnapshotEvent`] whenever collecting or compacting the
+    /// value log keeps garbage around for a snapshot held longer than `threshold`, such as one
+    /// leaked by a reader, as the log otherwise grows without bound. Off unless set, and not
+    /// persisted.
+    pub fn set_snapshot_age_alert(&mut self, threshold: Option<Duration>) {
+        self.snapshot_age_alert = threshold;
+    }
+
+    /// Deletes the segments of the value log holding no value the tree still refers to, such as
+    /// those of values since overwritten or deleted. Segments are only ever removed whole and
+    /// nothing is removed while a [`Snapshot`] may still read from the log.
+    ///
+    /// Every page is read to find the values still in use, so this costs a full pass over the
+    /// tree.
+    pub fn collect_value_log_garbage(&mut self) -> Result<ValueLogGc, BTreeError> {
+        let Some(value_log) = self.value_log.clone() else {
+            return Ok(ValueLogGc::default());
+        };
+        let mut live = HashMap::new();
+        self.count_live_values(self.header.root_page_id, &mut live)?;
+        self.remove_dead_segments(&value_log, &live, ValueLogGc::default())
+    }
+
+    /// Like [`collect_value_log_garbage`](Self::collect_value_log_garbage), but once the log
+    /// has grown past `options.max_space_amplification` times the values still in use, the live
+    /// values of mostly dead segments are first copied to the head of the log so those segments
+    /// can be removed too.
+    ///
+    /// The copies are synced before any page points at them, and the tree is flushed before the
+    /// old segments are removed, so a crash part way through leaves at worst both copies behind.
+    /// Nothing is rewritten while a [`Snapshot`] may still read from the log.
+    pub fn compact_value_log(
+        &mut self,
+        options: ValueLogCompactionOptions,
+    ) -> Result<ValueLogGc, BTreeError> {
+        let Some(value_log) = self.value_log.clone() else {
+            return Ok(ValueLogGc::default());
+        };
+        let mut live = HashMap::new();
+        self.count_live_values(self.header.root_page_id, &mut live)?;
+
+        let segments = value_log.segments();
+        let live_bytes: u64 = live.values().sum();
+        let total_bytes: u64 = segments.iter().map(|segment| segment.size).sum();
+        let head = value_log.head_segment();
+        let mut gc = ValueLogGc::default();
+        if total_bytes as f64 > live_bytes as f64 * options.max_space_amplification
+            && !value_log.is_pinned()
+        {
+            let rewrite: HashSet<u32> = segments
+                .iter()
+                .filter(|segment| {
+                    let live_bytes = live.get(&segment.id).copied().unwrap_or(0);
+                    Some(segment.id) != head
+                        && live_bytes > 0
+                        && (live_bytes as f64) < segment.size as f64 * options.max_live_ratio
+                })
+                .map(|segment| segment.id)
+                .collect();
+            if !rewrite.is_empty() {
+                gc.bytes_rewritten = self.rewrite_values(&value_log, &rewrite, &mut live)?;
+                self.flush()?;
+                gc.segments_rewritten = rewrite.into_iter().collect();
+                gc.segments_rewritten.sort();
+            }
+        }
+        self.remove_dead_segments(&value_log, &live, gc)
+    }
+
+    // Removes the segments other than the head with no live bytes, unless the log is pinned,
+    // and adds what is left to `gc`
+    fn remove_dead_segments(
+        &mut self,
+        value_log: &ValueLog,
+        live: &HashMap<u32, u64>,
+        mut gc: ValueLogGc,
+    ) -> Result<ValueLogGc, BTreeError> {
+        let head = value_log.head_segment();
+        for segment in value_log.segments() {
+            let live_bytes = live.get(&segment.id).copied().unwrap_or(0);
+            if live_bytes == 0 && Some(segment.id) != head {
+                if value_log.is_pinned() {
+                    gc.bytes_held += segment.size;
+                } else if value_log.remove_segment(segment.id)? {
+                    gc.segments_removed.push(segment.id);
+                    gc.bytes_reclaimed += segment.size;
+                    continue;
+                }
+            }
+            gc.live_bytes += live_bytes;
+            gc.total_bytes += segment.size;
+        }
+        if gc.bytes_held > 0 {
+            self.report_stale_snapshots(value_log, gc.bytes_held);
+        }
+        info!(
+            "Collected value log garbage: removed segments {:?}, {} of {} bytes live",
+            gc.segments_removed, gc.live_bytes, gc.total_bytes
+        );
+        Ok(gc)
+    }
+
+    fn report_stale_snapshots(&self, value_log: &ValueLog, bytes_held: u64) {
+        let (Some(threshold), Some(age)) = (self.snapshot_age_alert, value_log.oldest_pin_age())
+        else {
+            return;
+        };
+        if age < threshold {
+            return;
+        }
+        let event = StaleSnapshotEvent {
+            age,
+            snapshots: value_log.pin_count(),
+            bytes_held,
+        };
+        warn!(
+            "Snapshot held for {:?} keeps {} bytes of value log garbage",
+            age, bytes_held
+        );
+        self.observers
+            .iter()
+            .for_each(|o| o.on_stale_snapshot(&event));
+    }
+
+    // Copies the live values in `segments` to the head of the log, moving their bytes in `live`
+    // to the segments they now live in, and returns the bytes copied
+    fn rewrite_values(
+        &mut self,
+        value_log: &ValueLog,
+        segments: &HashSet<u32>,
+        live: &mut HashMap<u32, u64>,
+    ) -> Result<u64, BTreeError> {
+        self.advance_epoch();
+        let mut rewritten = 0;
+        let mut batch = Vec::new();
+        let mut pending = vec![self.header.root_page_id];
+        while let Some(page_id) = pending.pop() {
+            let mut page = self.read_page(page_id)?;
+            pending.extend(&page.pointers);
+            for idx in 0..page.slots.len() {
+                let Some(old) = page
+                    .value_pointer(idx)
+                    .filter(|pointer| segments.contains(&pointer.segment))
+                    .filter(|_| !page.is_tombstoned(idx))
+                else {
+                    continue;
+                };
+                let Some(copy) = page.rewrite_external_value(idx)? else {
+                    continue;
+                };
+                let record_length = old.record_length(page.slots[idx].key_length as usize);
+                *live.entry(old.segment).or_insert(0) -= record_length;
+                *live.entry(copy.segment).or_insert(0) += record_length;
+                rewritten += record_length;
+            }
+            if page.is_dirty() {
+                batch.push(page);
+            }
+            if batch.len() >= Self::VALUE_LOG_REWRITE_BATCH {
+                self.write_rewritten(value_log, &mut batch)?;
+            }
+        }
+        self.write_rewritten(value_log, &mut batch)?;
+        Ok(rewritten)
+    }
+
+    // Syncs the copies written so far before the pages pointing at them are written
+    fn write_rewritten(
+        &mut self,
+        value_log: &ValueLog,
+        batch: &mut Vec<SlottedPage<K, V>>,
+    ) -> Result<(), BTreeError> {
+        value_log.sync()?;
+        for page in batch.drain(..) {
+            BTree::<K, V>::write_page(&page, &mut self.page_manager)?;
+        }
+        Ok(())
+    }
+
+    // Adds up, per value log segment, the bytes of the records the subtree under `page_id`
+    // refers to
+    fn count_live_values(
+        &mut self,
+        page_id: u64,
+        live: &mut HashMap<u32, u64>,
+    ) -> Result<(), BTreeError> {
+        let page = self.read_page(page_id)?;
+        for idx in (0..page.slots.len()).filter(|&idx| !page.is_tombstoned(idx)) {
+            if let Some(pointer) = page.value_pointer(idx) {
+                let key_length = page.slots[idx].key_length as usize;
+                *live.entry(pointer.segment).or_insert(0) += pointer.record_length(key_length);
+            }
+        }
+        for &child in &page.pointers {
+            self.count_live_values(child, live)?;
+        }
+        Ok(())
+    }
+
+    fn collect_value_samples(
+        &mut self,
+        page_id: u64,
+        max_samples: usize,
+        samples: &mut Vec<Vec<u8>>,
+    ) -> Result<(), BTreeError> {
+        let node = self.read_page(page_id)?;
+        for idx in (0..node.slots.len()).filter(|&idx| !node.is_tombstoned(idx)) {
+            if samples.len() >= max_samples {
+                return Ok(());
+            }
+            samples.push(node.read_value_bytes(idx)?);
+        }
+
+        for &child in node.pointers.iter() {
+            if samples.len() >= max_samples {
+                break;
+            }
+            self.collect_value_samples(child, max_samples, samples)?;
+        }
+        Ok(())
+    }
+
+    // The dictionary is stored as a u32 length followed by its bytes, spread over as many
+    // consecutive pages as needed: a META page followed by OVERFLOW pages.
+    fn write_dictionary(&mut self, dictionary: &[u8]) -> Result<u64, BTreeError> {
+        let page_size = self.header.page_size as usize;
+        let mut bytes = Vec::with_capacity(4 + dictionary.len());
+        bytes.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(dictionary);
+
+        let chunks = bytes.chunks(page_size - PAGE_PREFIX_SIZE);
+        let page_ids = self.page_manager.allocate_pages(chunks.len() as u64)?;
+        let first_page_id = page_ids.start;
+        for (page_id, chunk) in page_ids.zip(chunks) {
+            let node_type = match page_id == first_page_id {
+                true => NodeType::META,
+                false => NodeType::OVERFLOW,
+            };
+
+            let mut page = vec![0u8; page_size];
+            types::write_page_prefix(&mut page, page_id, node_type);
+            page[PAGE_PREFIX_SIZE..PAGE_PREFIX_SIZE + chunk.len()].copy_from_slice(chunk);
+            self.page_manager.write_page(page_id, &page)?;
+        }
+
+        debug!(
+            "Wrote compression dictionary: {} bytes at page {}",
+            dictionary.len(),
+            first_page_id
+        );
+        Ok(first_page_id)
+    }
+
+    fn read_dictionary(&mut self, first_page_id: u64) -> Result<Vec<u8>, BTreeError> {
+        let first_page = self.page_manager.read_page(first_page_id)?;
+        let data = &first_page[PAGE_PREFIX_SIZE..];
+        let length = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+
+        let mut bytes = data[4..].to_vec();
+        let mut page_id = first_page_id;
+        while bytes.len() < length {
+            page_id += 1;
+            let page = self.page_manager.read_page(page_id)?;
+            bytes.extend_from_slice(&page[PAGE_PREFIX_SIZE..]);
+        }
+        bytes.truncate(length);
+
+        Ok(bytes)
+    }
+
+    /// Loads `entries`, which must be sorted by strictly increasing key, into an empty tree.
+    ///
+    /// Pages are filled left to right up to the configured leaf and internal fill factors and
+    /// written once each, instead of being split repeatedly as with individual inserts.
+    /// Returns the number of entries loaded.
+    pub fn bulk_load<I>(&mut self, entries: I) -> Result<u64, BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let root = self.read_page(self.header.root_page_id)?;
+        if root.node_type != NodeType::LEAF || root.num_keys > 0 {
+            return Err(BTreeError::TreeNotEmpty);
+        }
+        self.append_sorted(vec![root], None, entries.into_iter())
+    }
+
+    /// Bulk loads `entries`, sorted by strictly increasing key, in batches of
+    /// `options.batch_size`, flushing after each and passing `progress` how far it has got.
+    ///
+    /// Unlike `bulk_load`, the tree need not be empty: entries are appended after its largest
+    /// key, filling its rightmost pages. An import that was interrupted is resumed by calling
+    /// this again with the same source. The entries it flushed are in the tree, so those up to
+    /// its largest key are skipped rather than loaded again, and only the batch that was in
+    /// progress is lost. Any other key in the source no larger than the tree's largest fails
+    /// with `UnsortedBulkLoad`.
+    pub fn import<I, F>(
+        &mut self,
+        entries: I,
+        options: ImportOptions,
+        mut progress: F,
+    ) -> Result<ImportProgress, BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(&ImportProgress),
+    {
+        let started = Instant::now();
+        let mut report = ImportProgress::default();
+        let mut entries = entries.into_iter().peekable();
+        if let Some(high_water) = Self::largest_key(&self.right_spine()?)? {
+            while entries.next_if(|(key, _)| *key <= high_water).is_some() {
+                report.entries_skipped += 1;
+            }
+            info!(
+                "Resuming import after {:?}, skipped {} entries",
+                high_water, report.entries_skipped
+            );
+        }
+
+        while entries.peek().is_some() {
+            let bytes_before = self.stats.key_bytes + self.stats.value_bytes;
+            let spine = self.right_spine()?;
+            let last_key = Self::largest_key(&spine)?;
+            let batch = entries.by_ref().take(options.batch_size.max(1) as usize);
+            report.entries_loaded += self.append_sorted(spine, last_key, batch)?;
+            self.flush()?;
+
+            report.bytes_loaded += self.stats.key_bytes + self.stats.value_bytes - bytes_before;
+            report.elapsed = started.elapsed();
+            report.estimate_eta(options.total_entries);
+            debug!("Import progress: {:?}", report);
+            progress(&report);
+        }
+        Ok(report)
+    }
+
+    // The rightmost page of each level, leaf first, where entries larger than every key go
+    fn right_spine(&mut self) -> Result<Vec<SlottedPage<K, V>>, BTreeError> {
+        let mut spine = vec![self.read_page(self.header.root_page_id)?];
+        while let Some(&child) = spine.last().unwrap().pointers.last() {
+            spine.push(self.read_page(child)?);
+        }
+        spine.reverse();
+        Ok(spine)
+    }
+
+    // The largest key of the tree whose right spine is `spine`: the last of the lowest page
+    // on it with any keys, as a leaf emptied by deletes or left empty by a bulk load is
+    // preceded by its parent's last separator
+    fn largest_key(spine: &[SlottedPage<K, V>]) -> Result<Option<K>, BTreeError> {
+        match spine.iter().find(|page| !page.slots.is_empty()) {
+            Some(page) => Ok(Some(page.read_key(page.slots.len() - 1)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Appends `entries` after `last_key`, the largest key in the tree, filling the pages of
+    // `levels` and the new pages to their right as `bulk_load` does, then commits. `levels[0]`
+    // is the leaf being filled and `levels[n]` its ancestor n levels up, up to the root.
+    fn append_sorted<I>(
+        &mut self,
+        mut levels: Vec<SlottedPage<K, V>>,
+        mut last_key: Option<K>,
+        entries: I,
+    ) -> Result<u64, BTreeError>
+    where
+        I: Iterator<Item = (K, V)>,
+    {
+        self.advance_epoch();
+        let mut reserved = 0..0;
+        let mut loaded = 0;
+        let mut watched = Vec::new();
+
+        for (key, value) in entries {
+            if let Some(last_key) = &last_key
+                && *last_key >= key
+            {
+                return Err(BTreeError::UnsortedBulkLoad(key.to_string()));
+            }
+            self.check_range_locks(None, &key)?;
+            last_key = Some(key.clone());
+            if self.watchers.watching(&key) {
+                watched.push((key.clone(), value.clone()));
+            }
+
+            let leaf = &mut levels[0];
+            let (key_len, value_len) = leaf.encoded_len(&key, &value)?;
+            self.stats
+                .add_entry(key_len as u64, self.codecs().value_len(&value)?);
+            if leaf.fits_within(key_len, value_len, self.header.leaf_fill_factor) {
+                let pos = leaf.slots.len();
+                leaf.insert(pos, &key, &value)?;
+            } else {
+                // The entry that does not fit separates this leaf from the next one
+                let next_leaf = self.reserve_page(&mut reserved, NodeType::LEAF)?;
+                self.bulk_load_separator(
+                    &mut levels,
+                    &mut reserved,
+                    1,
+                    key,
+                    value,
+                    next_leaf.page_id,
+                )?;
+
+                let full_leaf = core::mem::replace(&mut levels[0], next_leaf);
+                BTree::<K, V>::write_page(&full_leaf, &mut self.page_manager)?;
+            }
+            loaded += 1;
+        }
+
+        // The last child of each node was still being filled when it was linked in
+        for level in 1..levels.len() {
+            let entries = levels[level - 1].subtree_entries();
+            let last = levels[level].pointers.len() - 1;
+            levels[level].set_count(last, entries);
+        }
+        for page in levels.iter() {
+            BTree::<K, V>::write_page(page, &mut self.page_manager)?;
+        }
+        for page_id in reserved {
+            self.free_page(page_id)?;
+        }
+        self.header.root_page_id = levels.last().unwrap().page_id;
+        self.refill_right_spine(&levels)?;
+        self.commit_header()?;
+        self.stats.commits += 1;
+        for (key, value) in watched {
+            self.watchers.put(self.stats.commits, key, value);
+        }
+
+        info!(
+            "Bulk loaded {} entries: height={} pages={}",
+            loaded,
+            levels.len(),
+            self.header.page_count
+        );
+        Ok(loaded)
+    }
+
+    // The internal node started at each level for the last separator passed up may have been
+    // given no key of its own before the entries ran out. Each such node on the right spine
+    // `levels` is refilled as a delete would refill it, top down so that every one has a
+    // sibling to take from by the time it is reached.
+    fn refill_right_spine(&mut self, levels: &[SlottedPage<K, V>]) -> Result<(), BTreeError> {
+        let mut displaced = Vec::new();
+        for level in (1..levels.len() - 1).rev() {
+            let child = self.read_page(levels[level].page_id)?;
+            if child.num_keys > 0 {
+                continue;
+            }
+            let child_id = child.page_id;
+            let mut parent = self.read_page(levels[level + 1].page_id)?;
+            let last = parent.pointers.len() - 1;
+            self.refill_if_keyless(&mut parent, last, child, &mut displaced)?;
+            BTree::<K, V>::write_page(&parent, &mut self.page_manager)?;
+
+            let mut entries = parent.subtree_entries();
+            for above in &levels[level + 2..] {
+                let mut page = self.read_page(above.page_id)?;
+                let last = page.pointers.len() - 1;
+                page.set_count(last, entries);
+                BTree::<K, V>::write_page(&page, &mut self.page_manager)?;
+                entries = page.subtree_entries();
+            }
+            // A subtree taken apart takes the rest of the spine below with it
+            if parent.pointers.last() != Some(&child_id) {
+                break;
+            }
+        }
+        let root = self.read_page(self.header.root_page_id)?;
+        self.finish_rebalance(&root, displaced)
+    }
+
+    // Appends a separator followed by a pointer to `right_child` to the internal node being
+    // filled at `level`, starting a new node (and promoting the separator) when it is full.
+    fn bulk_load_separator(
+        &mut self,
+        levels: &mut Vec<SlottedPage<K, V>>,
+        reserved: &mut core::ops::Range<u64>,
+        level: usize,
+        key: K,
+        value: V,
+        right_child: u64,
+    ) -> Result<(), BTreeError> {
+        if levels.len() == level {
+            let mut parent = self.reserve_page(reserved, NodeType::INTERNAL)?;
+            parent.insert_pointer(0, levels[level - 1].page_id, 0);
+            levels.push(parent);
+        }
+        // The child left of the separator is full, so its count is final
+        let entries = levels[level - 1].subtree_entries();
+        let node = &mut levels[level];
+        node.set_count(node.pointers.len() - 1, entries);
+
+        let (key_len, value_len) = node.encoded_len(&key, &value)?;
+        if node.fits_within(key_len, value_len, self.header.internal_fill_factor) {
+            let pos = node.slots.len();
+            node.insert(pos, &key, &value)?;
+            node.insert_pointer(pos + 1, right_child, 0);
+            return Ok(());
+        }
+
+        let mut next_node = self.reserve_page(reserved, NodeType::INTERNAL)?;
+        next_node.insert_pointer(0, right_child, 0);
+        self.bulk_load_separator(levels, reserved, level + 1, key, value, next_node.page_id)?;
+
+        let full_node = core::mem::replace(&mut levels[level], next_node);
+        BTree::<K, V>::write_page(&full_node, &mut self.page_manager)?;
+        Ok(())
+    }
+
+    // Takes the next page from `reserved`, reserving a fresh extent once it runs out. Pages
+    // still reserved when a bulk load finishes are freed.
+    fn reserve_page(
+        &mut self,
+        reserved: &mut core::ops::Range<u64>,
+        node_type: NodeType,
+    ) -> Result<SlottedPage<K, V>, BTreeError> {
+        let pages = self.header.node_pages(node_type);
+        if reserved.end - reserved.start < pages {
+            // What is left is too short for a leaf spanning several pages
+            for page_id in reserved.clone() {
+                self.free_page(page_id)?;
+            }
+            // Near the size limit only what is left is reserved, keeping a page for the stats
+            let left = self.page_manager.pages_left();
+            let extent = left.saturating_sub(self.stats_pages_needed());
+            *reserved = self
+                .page_manager
+                .allocate_pages(Self::BULK_LOAD_EXTENT.min(extent).max(pages))?;
+        }
+        let page_id = reserved.start;
+        reserved.start += pages;
+
+        let page_size = self.header.node_size(node_type) as usize;
+        let mut page = SlottedPage::new(page_id, node_type, page_size);
+        page.set_checksummed(self.header.checksums_pages());
+        self.attach_codecs(&mut page);
+        page.set_counted(self.header.subtree_counts);
+        Ok(page)
+    }
+
+    /// Stores rarely-read leaf pages in `storage`. A tree with cold pages needs the same
+    /// storage attached again every time it is reopened.
+    pub fn attach_cold_tier<S: Storage + 'static>(&mut self, storage: S) -> Result<(), BTreeError> {
+        Ok(self.page_manager.attach_cold_tier(storage)?)
+    }
+
+    /// Moves leaf pages between the primary file and the cold tier according to how often each
+    /// was read since the previous rebalance.
+    pub fn rebalance_tiers(&mut self, policy: &TieringPolicy) -> Result<TieringReport, BTreeError> {
+        if !self.page_manager.has_cold_tier() {
+            return Err(PageManagerError::ColdTierNotAttached.into());
+        }
+        self.advance_epoch();
+
+        let access_counts = self.page_manager.take_access_counts();
+        let mut report = TieringReport::default();
+        self.rebalance_node(
+            self.header.root_page_id,
+            policy,
+            &access_counts,
+            &mut report,
+        )?;
+
+        // Reads made while rebalancing say nothing about the workload
+        self.page_manager.take_access_counts();
+        self.commit_header()?;
+
+        info!("Rebalanced tiers: {:?}", report);
+        Ok(report)
+    }
+
+    fn rebalance_node(
+        &mut self,
+        page_id: u64,
+        policy: &TieringPolicy,
+        access_counts: &HashMap<u64, u64>,
+        report: &mut TieringReport,
+    ) -> Result<(), BTreeError> {
+        let mut node = self.read_page(page_id)?;
+        let mut modified = false;
+
+        for idx in 0..node.pointers.len() {
+            let child_id = node.pointers[idx];
+            let mut child = self.read_page(child_id)?;
+            if child.node_type == NodeType::INTERNAL {
+                self.rebalance_node(child_id, policy, access_counts, report)?;
+                continue;
+            }
+
+            let accesses = access_counts.get(&child_id).copied().unwrap_or(0);
+            let new_page_id = if PageManager::is_cold(child_id) {
+                if !policy.should_promote(accesses) {
+                    continue;
+                }
+                let page_id = self.allocate_node(NodeType::LEAF)?;
+                report.promoted += 1;
+                page_id
+            } else {
+                if !policy.should_demote(accesses) {
+                    continue;
+                }
+                report.demoted += 1;
+                let pages = self.header.node_pages(NodeType::LEAF);
+                self.page_manager.allocate_cold_pages(pages)?.start
+            };
+
+            debug!(
+                "Moving leaf {} to page {} (accesses={})",
+                child_id, new_page_id, accesses
+            );
+            child.page_id = new_page_id;
+            child.mark_dirty();
+            BTree::<K, V>::write_page(&child, &mut self.page_manager)?;
+            self.free_node(child_id, NodeType::LEAF)?;
+            node.pointers[idx] = new_page_id;
+            node.mark_dirty();
+            modified = true;
+        }
+
+        if modified {
+            BTree::<K, V>::write_page(&node, &mut self.page_manager)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn print(&mut self) {
+        // (page_id, level, chars_prior), popped in pre-order
+        let mut stack = vec![(self.header.root_page_id, 0, 0)];
+        while let Some((page_id, level, chars_prior)) = stack.pop() {
+            let node = self.read_page(page_id).unwrap();
+            let prior_char = if level == 0 {
+                ""
+            } else {
+                match node.pointers.is_empty() {
+                    false => "└",
+                    true => "├",
+                }
+            };
+            let post_char = match node.pointers.is_empty() {
+                false => "┐",
+                true => "",
+            };
+
+            let keys = node.read_keys().unwrap();
+            let stringified_keys = match keys.len() <= 200 {
+                true => format!("{:?}", keys),
+                false => {
+                    let start = &keys[..2];
+                    let end = &keys[keys.len() - 2..];
+                    format!("{:?},...,{:?}", start, end)
+                }
+            };
+
+            println!(
+                "{}{}{}{}{:?} - {}",
+                " ".repeat(chars_prior),
+                prior_char,
+                stringified_keys,
+                post_char,
+                node.node_type,
+                node.page_id
+            );
+            let child_chars_prior = 1 + chars_prior + stringified_keys.len();
+            stack.extend(
+                node.pointers
+                    .iter()
+                    .rev()
+                    .map(|&ptr| (ptr, level + 1, child_chars_prior)),
+            );
+        }
+    }
+
+    /// Walks every page of the tree, checking that keys are in order and within the bounds set
+    /// by their parents, that every leaf is at the same depth, that subtree counts and the
+    /// entry count match what is stored, and that no page is reachable twice. Fails with
+    /// `Corrupted` naming the first page found breaking one of these.
+    pub fn verify(&mut self) -> Result<(), BTreeError> {
+        let mut visited = HashSet::new();
+        let mut leaf_depth = None;
+        let root = self.header.root_page_id;
+        let entries = self.verify_page(root, (None, None), 0, &mut visited, &mut leaf_depth)?;
+        if entries != self.stats.entries {
+            return Err(BTreeError::Corrupted {
+                page_id: root,
+                reason: format!(
+                    "tree holds {} entries but its stats count {}",
+                    entries, self.stats.entries
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    // Checks the subtree under `page_id`, whose keys must fall within `bounds`, and returns the
+    // live entries it holds.
+    fn verify_page(
+        &mut self,
+        page_id: u64,
+        bounds: (Option<&K>, Option<&K>),
+        depth: usize,
+        visited: &mut HashSet<u64>,
+        leaf_depth: &mut Option<usize>,
+    ) -> Result<u64, BTreeError> {
+        let corrupted = |reason: &str| BTreeError::Corrupted {
+            page_id,
+            reason: reason.to_string(),
+        };
+        if !visited.insert(page_id) {
+            return Err(corrupted("page is reachable more than once"));
+        }
+        let page = self.read_page(page_id)?;
+        let keys = page.read_keys()?;
+
+        // Duplicates that are kept may sit on either side of an equal separator
+        let keep_both = self.header.duplicate_policy.keeps_duplicates();
+        let ordered = |a: &K, b: &K| a < b || (keep_both && a == b);
+        if keys.windows(2).any(|pair| !ordered(&pair[0], &pair[1])) {
+            return Err(corrupted("keys are out of order"));
+        }
+        let (lower, upper) = bounds;
+        if let (Some(lower), Some(first)) = (lower, keys.first())
+            && !ordered(lower, first)
+        {
+            return Err(corrupted("key is below its parent's separator"));
+        }
+        if let (Some(upper), Some(last)) = (upper, keys.last())
+            && !ordered(last, upper)
+        {
+            return Err(corrupted("key is above its parent's separator"));
+        }
+
+        let mut entries = (0..page.slots.len())
+            .filter(|&idx| !page.is_tombstoned(idx))
+            .count() as u64;
+        if page.node_type == NodeType::LEAF {
+            if *leaf_depth.get_or_insert(depth) != depth {
+                return Err(corrupted("leaf is at a different depth from the others"));
+            }
+            return Ok(entries);
+        }
+        if keys.is_empty() {
+            return Err(corrupted("internal node has no keys"));
+        }
+
+        for (idx, &child) in page.pointers.iter().enumerate() {
+            let child_bounds = (
+                idx.checked_sub(1).map(|prev| &keys[prev]).or(lower),
+                keys.get(idx).or(upper),
+            );
+            let child_entries =
+                self.verify_page(child, child_bounds, depth + 1, visited, leaf_depth)?;
+            if page.is_counted() && page.counts[idx] != child_entries {
+                return Err(corrupted("subtree count does not match its child"));
+            }
+            entries += child_entries;
+        }
+        Ok(entries)
+    }
+
+    /// Reads every allocated page from storage, in both tiers, checking its checksum and that
+    /// its contents are well formed, and reports the pages found damaged. Nothing is changed
+    /// or repaired. Unlike `verify` it does not follow the tree, so it also checks pages the
+    /// tree does not reach and keeps going past damage.
+    ///
+    /// Reads are spread out to keep to `options.pages_per_second`. To scrub a tree other
+    /// threads are using, run a [`Scrubber`] over it instead.
+    #[cfg(feature = "std")]
+    pub fn scrub(&mut self, options: ScrubOptions) -> Result<ScrubReport, BTreeError> {
+        let mut scrubber = Scrubber::new(options);
+        while !scrubber.step(self, options.batch_size.max(1))? {
+            scrubber.throttle();
+        }
+        let report = scrubber.into_report();
+        info!(
+            "Scrubbed {} pages, {} damaged",
+            report.pages_scanned,
+            report.damaged.len()
+        );
+        Ok(report)
+    }
+
+    // Pages allocated in the primary file and in the cold tier
+    #[cfg(feature = "std")]
+    pub(crate) fn scrub_page_counts(&self) -> (u64, u64) {
+        (
+            self.page_manager.page_count(),
+            self.page_manager.cold_page_count(),
+        )
+    }
+
+    // Checks page `page_id` as it is stored, bypassing the cache, returning what was found and
+    // how many pages were checked, as a leaf may span several
+    #[cfg(feature = "std")]
+    pub(crate) fn scrub_page(
+        &mut self,
+        page_id: u64,
+        decode_entries: bool,
+    ) -> Result<(PageScrub, u64), BTreeError> {
+        let mut pages = 1;
+        let mut buffer = self.page_manager.read_page_uncached(page_id);
+        if let Ok(first) = &buffer {
+            let (stored_id, type_byte) = types::read_page_prefix(first);
+            if let Some(node_type) = NodeType::from_byte(type_byte)
+                && stored_id == page_id
+                && self.header.node_pages(node_type) > 1
+            {
+                pages = self.header.node_pages(node_type);
+                buffer = self.page_manager.read_pages_uncached(page_id, pages);
+            }
+        }
+        let buffer = match buffer {
+            Ok(buffer) => buffer,
+            Err(e @ PageManagerError::ShortRead { .. }) => {
+                return Ok((PageScrub::Damaged(e.to_string()), pages));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok((self.scrub_node(page_id, &buffer, decode_entries)?, pages))
+    }
+
+    #[cfg(feature = "std")]
+    fn scrub_node(
+        &mut self,
+        page_id: u64,
+        buffer: &[u8],
+        decode_entries: bool,
+    ) -> Result<PageScrub, BTreeError> {
+        // Allocated ahead of use and never written
+        if buffer.iter().all(|&byte| byte == 0) {
+            return Ok(PageScrub::Other);
+        }
+
+        let (stored_id, type_byte) = types::read_page_prefix(buffer);
+        if stored_id != page_id {
+            return Ok(PageScrub::Damaged(format!("page records id {}", stored_id)));
+        }
+        let Some(node_type) = NodeType::from_byte(type_byte) else {
+            return Ok(PageScrub::Damaged(format!(
+                "unknown page type {}",
+                type_byte
+            )));
+        };
+        if !node_type.is_tree_node() {
+            return Ok(PageScrub::Other);
+        }
+
+        let mut page = match SlottedPage::<K, V>::deserialize(buffer, buffer.len()) {
+            Ok(page) => page,
+            Err(e) => return Ok(PageScrub::Damaged(e.to_string())),
+        };
+        if !page.is_checksummed() && self.header.checksums_pages() {
+            return Ok(PageScrub::Damaged("page has no checksum".to_string()));
+        }
+
+        if decode_entries {
+            self.attach_codecs(&mut page);
+            let mut keys = Vec::with_capacity(page.slots.len());
+            for idx in 0..page.slots.len() {
+                // The value of a tombstone may be in a value log segment since removed
+                let entry = match page.is_tombstoned(idx) {
+                    true => page.read_key(idx),
+                    false => page.read_key_value(idx).map(|(key, _)| key),
+                };
+                match entry {
+                    Ok(key) => keys.push(key),
+                    Err(e) => {
+                        return Ok(PageScrub::Damaged(format!("entry {}: {}", idx, e)));
+                    }
+                }
+            }
+            let keep_both = self.header.duplicate_policy.keeps_duplicates();
+            if keys
+                .windows(2)
+                .any(|pair| !(pair[0] < pair[1] || (keep_both && pair[0] == pair[1])))
+            {
+                return Ok(PageScrub::Damaged("keys are out of order".to_string()));
+            }
+        }
+
+        Ok(match page.is_checksummed() {
+            true => PageScrub::Checksummed,
+            false => PageScrub::WithoutChecksum,
+        })
+    }
+
+    /// Replaces each leaf in `page_ids`, such as those a scrub found damaged, with an empty
+    /// one, so operations touching its keys work again instead of failing. The entries it held
+    /// are lost; the report gives the keys they lay between and how many there were. The
+    /// leaves themselves are never read, so they may be damaged beyond decoding, but the
+    /// internal nodes leading to them must be intact. Fails with `Corrupted`, before changing
+    /// anything, if one of the pages is an internal node.
+    pub fn quarantine(&mut self, page_ids: &[u64]) -> Result<QuarantineReport<K>, BTreeError> {
+        let targets: HashSet<u64> = page_ids.iter().copied().collect();
+        let mut located = Vec::new();
+        let root = self.header.root_page_id;
+        if targets.contains(&root) && self.page_type(root)? != NodeType::LEAF {
+            return Err(BTreeError::Corrupted {
+                page_id: root,
+                reason: "only leaves can be quarantined".to_string(),
+            });
+        }
+        self.locate_leaves(root, (None, None), &targets, &mut Vec::new(), &mut located)?;
+
+        self.advance_epoch();
+        let entries_before = self.stats.entries;
+        let page_size = self.header.node_size(NodeType::LEAF) as usize;
+        let mut pages = Vec::new();
+        for (path, page_id, lower, upper) in located {
+            let mut leaf = SlottedPage::new(page_id, NodeType::LEAF, page_size);
+            leaf.set_checksummed(self.header.checksums_pages());
+            self.attach_codecs(&mut leaf);
+            BTree::<K, V>::write_page(&leaf, &mut self.page_manager)?;
+
+            // Every count on the way down included whatever the leaf held
+            let mut lost = None;
+            for &(parent_id, idx) in path.iter().rev() {
+                let mut parent = self.read_page(parent_id)?;
+                if !parent.is_counted() {
+                    break;
+                }
+                let held = *lost.get_or_insert(parent.counts[idx]);
+                parent.adjust_count(idx, -(held as i64));
+                BTree::<K, V>::write_page(&parent, &mut self.page_manager)?;
+            }
+            warn!(
+                "Quarantined page {}, dropping keys between {:?} and {:?}",
+                page_id, lower, upper
+            );
+            pages.push(QuarantinedPage {
+                page_id,
+                lower,
+                upper,
+            });
+        }
+
+        let mut counted = TreeStats::default();
+        self.count_entries(root, &mut counted)?;
+        self.stats = TreeStats {
+            entries: counted.entries,
+            key_bytes: counted.key_bytes,
+            value_bytes: counted.value_bytes,
+            commits: self.stats.commits + 1,
+            ..self.stats
+        };
+        self.writes_since_flush += 1;
+        self.watchers.reset(self.stats.commits);
+
+        let reached: HashSet<u64> = pages.iter().map(|page| page.page_id).collect();
+        let mut unreachable: Vec<u64> = targets.difference(&reached).copied().collect();
+        unreachable.sort();
+        Ok(QuarantineReport {
+            pages,
+            unreachable,
+            entries_lost: entries_before.saturating_sub(self.stats.entries),
+            entries_restored: 0,
+        })
+    }
+
+    /// Quarantines the leaves in `page_ids` as [`quarantine`](Self::quarantine) does, then
+    /// rebuilds each from the entries `backup` holds within its keys, leaving the rest of the
+    /// tree as it is. Writes to those keys made since the backup was taken are still lost.
+    pub fn recover<B: Backup<K, V>>(
+        &mut self,
+        page_ids: &[u64],
+        backup: &mut B,
+    ) -> Result<QuarantineReport<K>, BTreeError> {
+        let mut report = self.quarantine(page_ids)?;
+        for page in &report.pages {
+            let entries = backup.entries_between(page.lower.as_ref(), page.upper.as_ref())?;
+            info!(
+                "Restoring {} entries of page {} from backup",
+                entries.len(),
+                page.page_id
+            );
+            for (key, value) in entries {
+                self.insert(key, value)?;
+                report.entries_restored += 1;
+            }
+        }
+        report.entries_lost = report.entries_lost.saturating_sub(report.entries_restored);
+        Ok(report)
+    }
+
+    // Finds the leaves in `targets` beneath `page_id`, whose keys fall within `bounds`. Leaves
+    // not in `targets` are not read.
+    fn locate_leaves(
+        &mut self,
+        page_id: u64,
+        bounds: (Option<&K>, Option<&K>),
+        targets: &HashSet<u64>,
+        path: &mut Vec<(u64, usize)>,
+        located: &mut Vec<LocatedLeaf<K>>,
+    ) -> Result<(), BTreeError> {
+        let (lower, upper) = bounds;
+        if targets.contains(&page_id) {
+            located.push((path.clone(), page_id, lower.cloned(), upper.cloned()));
+            return Ok(());
+        }
+        let page = self.read_page(page_id)?;
+        if page.node_type == NodeType::LEAF {
+            return Ok(());
+        }
+
+        // Judged by a child that is not to be quarantined where there is one, as a damaged
+        // page may not even record its type correctly
+        let sample = page
+            .pointers
+            .iter()
+            .find(|child| !targets.contains(*child))
+            .unwrap_or(&page.pointers[0]);
+        let children_are_leaves = self.page_type(*sample)? == NodeType::LEAF;
+
+        let keys = page.read_keys()?;
+        for (idx, &child) in page.pointers.iter().enumerate() {
+            if children_are_leaves && !targets.contains(&child) {
+                continue;
+            }
+            if !children_are_leaves && targets.contains(&child) {
+                return Err(BTreeError::Corrupted {
+                    page_id: child,
+                    reason: "only leaves can be quarantined".to_string(),
+                });
+            }
+            let child_bounds = (
+                idx.checked_sub(1).map(|prev| &keys[prev]).or(lower),
+                keys.get(idx).or(upper),
+            );
+            path.push((page_id, idx));
+            self.locate_leaves(child, child_bounds, targets, path, located)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    // Adds the live entries of the subtree under `page_id` to `stats`, for when they can no
+    // longer be kept up to date entry by entry
+    fn count_entries(&mut self, page_id: u64, stats: &mut TreeStats) -> Result<(), BTreeError> {
+        let page = self.read_page(page_id)?;
+        for idx in (0..page.slots.len()).filter(|&idx| !page.is_tombstoned(idx)) {
+            let value_size = page.value_len(idx)? as u64;
+            stats.add_entry(page.slots[idx].key_length as u64, value_size);
+        }
+        for &child in &page.pointers {
+            self.count_entries(child, stats)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn print_tree(&mut self) {
+        println!("BTREE: {}", self.header.root_page_id);
+        self.print();
+        println!("\n")
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: InterpolationKey
+        + Clone
+        + PartialOrd
+        + Debug
+        + Serialize
+        + for<'de> Deserialize<'de>
+        + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Finds keys within each page by interpolation search, which guesses where a key lies from
+    /// its value, rather than by binary search. On evenly spread keys it reads fewer keys per
+    /// page, which matters most with large pages; on skewed keys it reads at most about twice
+    /// as many. The choice is not persisted, so it applies to this handle only.
+    pub fn set_interpolation_search(&mut self, enabled: bool) {
+        self.interpolator = enabled.then_some(K::interpolation_position as fn(&K) -> u64);
+    }
+}
+
+impl<V> BTree<String, V>
+where
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Watches every key starting with `prefix`, as [`watch`](Self::watch) does a single key.
+    pub fn watch_prefix(&mut self, prefix: &str) -> Watch<String, V> {
+        self.watchers.add(KeyFilter::Prefix(prefix.to_string()))
+    }
+
+    /// Iterates over the entries whose keys start with `prefix`, in key order.
+    ///
+    /// Keys sharing a prefix are contiguous in the tree, so this descends straight to the first
+    /// of them and stops at the first key past them: a prefix with no keys costs one descent.
+    pub fn scan_prefix(
+        &mut self,
+        prefix: &str,
+    ) -> Result<impl Iterator<Item = Result<(String, V), BTreeError>> + '_, BTreeError> {
+        let prefix = prefix.to_string();
+        let entries = self.range(prefix.clone()..)?;
+        Ok(entries.take_while(move |entry| match entry {
+            Ok((key, _)) => key.starts_with(&prefix),
+            Err(_) => true,
+        }))
+    }
+}
+
+/// Iterator over a key range of a [`BTree`], returned by [`BTree::range`].
+///
+/// Pages are read lazily as the iteration reaches them, so only the path to the current entry
+/// is held in memory.
+pub struct Range<'a, K, V> {
+    tree: &'a mut BTree<K, V>,
+    cursor: Cursor<K, V>,
+}
+
+impl<'a, K, V> Range<'a, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn new(tree: &'a mut BTree<K, V>, start: Bound<K>, end: Bound<K>) -> Result<Self, BTreeError> {
+        let root_page_id = tree.header.root_page_id;
+        let cursor = Cursor::new(root_page_id, &start, end, &mut |id| tree.read_page(id))?;
+        Ok(Range { tree, cursor })
+    }
+
+    fn advance(&mut self) -> Result<Option<(K, V)>, BTreeError> {
+        let tree = &mut *self.tree;
+        self.cursor.advance(&mut |id| tree.read_page(id))
+    }
+
+    /// Bytes of the pages this iterator holds on to: one per level of the tree.
+    pub fn memory_usage(&self) -> usize {
+        self.cursor
+            .stack
+            .iter()
+            .map(|(node, _)| node.page_size())
+            .sum()
+    }
+}
+
+impl<K, V> Iterator for Range<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<(K, V), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.cursor.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Walk over a key range of a [`BTree`] that does not borrow it, returned by
+/// [`BTree::range_cursor`].
+///
+/// The tree is passed to each step instead, so it can be released in between, for instance to
+/// let other threads write to a tree shared behind a lock. Before each step the cursor checks
+/// the versions of the pages it holds, and if a write has changed any of them since they were
+/// read it seeks again from the root to just after the last key it returned. A step taken
+/// after the tree has been rewritten as a whole fails with `BTreeError::TreeModified`; the
+/// scan can go on from a new cursor starting after the last key returned.
+pub struct RangeCursor<K, V> {
+    cursor: Cursor<K, V>,
+    start: Bound<K>,
+    // Last key returned, which the cursor seeks past when its pages have changed
+    last_key: Option<K>,
+    // Epoch of the tree when the cursor was created
+    epoch: u64,
+    // Write version of the tree's pages when the cursor last read them
+    version: u64,
+}
+
+impl<K, V> RangeCursor<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Returns the next entry of the range, reading it from `tree`, which must be the tree
+    /// the cursor was created on. A cursor used with any other tree fails as if that tree
+    /// had been rewritten.
+    pub fn advance(&mut self, tree: &mut BTree<K, V>) -> Result<Option<(K, V)>, BTreeError> {
+        if tree.epoch != self.epoch {
+            self.cursor.stack.clear();
+            return Err(BTreeError::TreeModified);
+        }
+        if self.is_stale(tree) {
+            self.reseek(tree)?;
+        }
+        let result = self.cursor.advance(&mut |id| tree.read_page(id));
+        match &result {
+            Ok(Some((key, _))) => self.last_key = Some(key.clone()),
+            Ok(None) => {}
+            Err(_) => self.cursor.stack.clear(),
+        }
+        self.version = tree.page_manager.write_version();
+        result
+    }
+
+    // Whether a page on the cursor's path has been written since it was read
+    fn is_stale(&self, tree: &BTree<K, V>) -> bool {
+        self.cursor
+            .stack
+            .iter()
+            .any(|(page, _)| tree.page_manager.page_version(page.page_id) > self.version)
+    }
+
+    // Finds the cursor's place again from the current root, just after the last key returned
+    fn reseek(&mut self, tree: &mut BTree<K, V>) -> Result<(), BTreeError> {
+        let start = match &self.last_key {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => self.start.clone(),
+        };
+        debug!("Range cursor seeking again from {:?}", start);
+        let end = core::mem::replace(&mut self.cursor.end, Bound::Unbounded);
+        self.cursor.stack.clear();
+        self.cursor = Cursor::new(tree.header.root_page_id, &start, end, &mut |id| {
+            tree.read_page(id)
+        })?;
+        Ok(())
+    }
+}
+
+/// Position of an in-order walk over a tree, leaving where its pages come from to the caller so
+/// that both a live tree and a [`Snapshot`](crate::snapshot::Snapshot) can be iterated.
+pub(crate) struct Cursor<K, V> {
+    // Path from the root to the page being visited. For a leaf the index is the next slot to
+    // return; for an internal node it counts steps, where even step 2i descends into child i
+    // and odd step 2i + 1 returns slot i.
+    pub(crate) stack: Vec<(SlottedPage<K, V>, usize)>,
+    end: Bound<K>,
+}
+
+pub(crate) type ReadPage<'a, K, V> = dyn FnMut(u64) -> Result<SlottedPage<K, V>, BTreeError> + 'a;
+
+impl<K, V> Cursor<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    pub(crate) fn new(
+        root_page_id: u64,
+        start: &Bound<K>,
+        end: Bound<K>,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<Self, BTreeError> {
+        let mut cursor = Cursor {
+            stack: Vec::new(),
+            end,
+        };
+        cursor.descend(root_page_id, start, read_page)?;
+        Ok(cursor)
+    }
+
+    // Pushes the path from `page_id` down to the first entry satisfying `start`.
+    fn descend(
+        &mut self,
+        mut page_id: u64,
+        start: &Bound<K>,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<(), BTreeError> {
+        loop {
+            let node = read_page(page_id)?;
+            let first = Self::first_index(&node, start)?;
+            match node.node_type {
+                NodeType::LEAF => {
+                    self.stack.push((node, first));
+                    return Ok(());
+                }
+                NodeType::INTERNAL => {
+                    page_id = node.pointers[first];
+                    self.stack.push((node, 2 * first + 1));
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
+            }
+        }
+    }
+
+    fn first_index(node: &SlottedPage<K, V>, start: &Bound<K>) -> Result<usize, BTreeError> {
+        match start {
+            Bound::Unbounded => Ok(0),
+            Bound::Included(key) => node.find_key_position(key),
+            Bound::Excluded(key) => match node.find_exact_key(key)? {
+                Some(pos) => Ok(pos + 1),
+                None => node.find_key_position(key),
+            },
+        }
+    }
+
+    fn before_end(&self, key: &K) -> bool {
+        match &self.end {
+            Bound::Unbounded => true,
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+        }
+    }
+
+    pub(crate) fn advance(
+        &mut self,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<Option<(K, V)>, BTreeError> {
+        let Some((key, index)) = self.next_entry(read_page)? else {
+            return Ok(None);
+        };
+        let (node, _) = self.stack.last().unwrap();
+        Ok(Some((key, node.read_value(index)?)))
+    }
+
+    /// Like `advance`, but leaves the value bytes untouched.
+    pub(crate) fn advance_key(
+        &mut self,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<Option<K>, BTreeError> {
+        Ok(self.next_entry(read_page)?.map(|(key, _)| key))
+    }
+
+    /// Like `advance`, but skips the entries whose key `predicate` rejects without reading
+    /// their values.
+    pub(crate) fn advance_where<F: FnMut(&K) -> bool>(
+        &mut self,
+        read_page: &mut ReadPage<'_, K, V>,
+        predicate: &mut F,
+    ) -> Result<Option<(K, V)>, BTreeError> {
+        while let Some((key, index)) = self.next_entry(read_page)? {
+            if predicate(&key) {
+                let (node, _) = self.stack.last().unwrap();
+                return Ok(Some((key, node.read_value(index)?)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Position in `node` of the first entry past the end of the range.
+    fn end_index(&self, node: &SlottedPage<K, V>) -> Result<usize, BTreeError> {
+        match &self.end {
+            Bound::Unbounded => Ok(node.slots.len()),
+            Bound::Included(end) => node.find_upper_position(end),
+            Bound::Excluded(end) => node.find_key_position(end),
+        }
+    }
+
+    /// Counts the live entries left in the range, consuming the cursor. Within a leaf the end
+    /// of the range is found by binary search, so only a few keys per leaf are deserialized and
+    /// no values at all.
+    pub(crate) fn count_remaining(
+        &mut self,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<u64, BTreeError> {
+        let mut count = 0;
+        while let Some((node, step)) = self.stack.last_mut() {
+            let num_keys = node.num_keys as usize;
+            match node.node_type {
+                NodeType::LEAF => {
+                    let (node, step) = self.stack.pop().unwrap();
+                    let end = self.end_index(&node)?;
+                    count += (step..end.max(step))
+                        .filter(|&i| !node.is_tombstoned(i))
+                        .count() as u64;
+                    if end < num_keys {
+                        break;
+                    }
+                }
+                NodeType::INTERNAL if *step <= 2 * num_keys => {
+                    *step += 1;
+                    if *step % 2 == 0 {
+                        let key = node.read_key(*step / 2 - 1)?;
+                        if !self.before_end(&key) {
+                            break;
+                        }
+                        count += 1;
+                    } else {
+                        let child = node.pointers[*step / 2];
+                        self.descend(child, &Bound::Unbounded, read_page)?;
+                    }
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+        self.stack.clear();
+        Ok(count)
+    }
+
+    // Moves to the next live entry within the range and returns its key along with its slot in
+    // the page now on top of the stack.
+    fn next_entry(
+        &mut self,
+        read_page: &mut ReadPage<'_, K, V>,
+    ) -> Result<Option<(K, usize)>, BTreeError> {
+        while let Some((node, step)) = self.stack.last_mut() {
+            let num_keys = node.num_keys as usize;
+            let index = match node.node_type {
+                NodeType::LEAF if *step < num_keys => {
+                    *step += 1;
+                    if node.is_tombstoned(*step - 1) {
+                        continue;
+                    }
+                    Some(*step - 1)
+                }
+                NodeType::INTERNAL if *step <= 2 * num_keys => {
+                    *step += 1;
+                    if *step % 2 == 0 {
+                        Some(*step / 2 - 1)
+                    } else {
+                        let child = node.pointers[*step / 2];
+                        self.descend(child, &Bound::Unbounded, read_page)?;
+                        None
+                    }
+                }
+                _ => {
+                    self.stack.pop();
+                    None
+                }
+            };
+
+            if let Some(index) = index {
+                let (node, _) = self.stack.last().unwrap();
+                let key = node.read_key(index)?;
+                if !self.before_end(&key) {
+                    self.stack.clear();
+                    return Ok(None);
+                }
+                return Ok(Some((key, index)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Iterator over the keys in a range of a [`BTree`], returned by [`BTree::keys_in_range`].
+///
+/// Only keys are deserialized; value bytes are never read out of the pages.
+pub struct Keys<'a, K, V> {
+    tree: &'a mut BTree<K, V>,
+    cursor: Cursor<K, V>,
+}
+
+impl<K, V> Iterator for Keys<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<K, BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = &mut *self.tree;
+        match self.cursor.advance_key(&mut |id| tree.read_page(id)) {
+            Ok(key) => key.map(Ok),
+            Err(e) => {
+                self.cursor.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Iterator over the entries in a range of a [`BTree`] whose keys satisfy a predicate,
+/// returned by [`BTree::scan_where`].
+pub struct ScanWhere<'a, K, V, F> {
+    tree: &'a mut BTree<K, V>,
+    cursor: Cursor<K, V>,
+    predicate: F,
+}
+
+impl<K, V, F> Iterator for ScanWhere<'_, K, V, F>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    F: FnMut(&K) -> bool,
+{
+    type Item = Result<(K, V), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = &mut *self.tree;
+        match self
+            .cursor
+            .advance_where(&mut |id| tree.read_page(id), &mut self.predicate)
+        {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.cursor.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Consuming iterator returned by [`BTree::drain_range`] and [`BTree::drain`].
+pub struct Drain<'a, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    tree: &'a mut BTree<K, V>,
+    start: Bound<K>,
+    end: Bound<K>,
+    // Entries taken out of the tree that have not been yielded yet
+    pending: VecDeque<(K, V)>,
+    finished: bool,
+}
+
+// First entries of a range in a subtree, as `BTree::take_run` finds them
+enum DrainRun<K, V> {
+    // Removed from the leaf the range starts on
+    Leaf(Vec<(K, V)>),
+    // Held by an internal node after that leaf, which had none in range
+    Separator(K),
+    Empty,
+}
+
+impl<K, V> Drain<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn advance(&mut self) -> Result<Option<(K, V)>, BTreeError> {
+        if self.pending.is_empty() {
+            // Everything before the range's first entry has been taken out, so each run is
+            // found from the original start bound
+            let run = self.tree.drain_run(&self.start, &self.end)?;
+            self.pending.extend(run);
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<K, V> Drop for Drain<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn drop(&mut self) {
+        for (key, value) in self.pending.drain(..) {
+            if let Err(e) = self.tree.insert(key, value) {
+                error!("Failed to put back entry not yielded by drain: {}", e);
+            }
+        }
+    }
+}
+
+impl<K, V> Iterator for Drain<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<(K, V), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.advance() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// The stats live in a META page of their own, allocated the first time they are written, with
+// the sequences after them.
+fn write_stats(
+    header: &mut Header,
+    page_manager: &mut PageManager,
+    stats: &TreeStats,
+    sequences: &Sequences,
+) -> Result<(), BTreeError> {
+    if !header.has_stats() {
+        header.stats_page_id = page_manager.allocate_page()?;
+    }
+
+    let mut page = vec![0u8; header.page_size as usize];
+    types::write_page_prefix(&mut page, header.stats_page_id, NodeType::META);
+    page[PAGE_PREFIX_SIZE..PAGE_PREFIX_SIZE + TreeStats::SIZE].copy_from_slice(&stats.serialize());
+    let sequences = sequences.serialize();
+    let at = PAGE_PREFIX_SIZE + TreeStats::SIZE;
+    page[at..at + sequences.len()].copy_from_slice(&sequences);
+    page_manager.write_page(header.stats_page_id, &page)?;
+    Ok(())
+}
+
+// Needs no bounds on the key and value types, so dropping the tree can commit the header too
+impl<K, V> BTree<K, V> {
+    // Fails once the handle is poisoned. Checked wherever pages are read and the header is
+    // written, which every operation on the tree does.
+    fn check_poisoned(&self) -> Result<(), BTreeError> {
+        match &self.poisoned {
+            Some(reason) => Err(BTreeError::Poisoned(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // The one place the header is written. Its page counts are taken from the page manager
+    // rather than kept alongside it, and it is only written when it differs from what is in
+    // storage, so every operation can end with a commit.
+    fn commit_header(&mut self) -> Result<(), BTreeError> {
+        self.check_poisoned()?;
+        self.header.set_page_count(self.page_manager.page_count());
+        let buffer = self.header.serialize();
+        if buffer != self.written_header {
+            self.page_manager.write_header(&buffer)?;
+            self.written_header = buffer;
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Drop for BTree<K, V> {
+    fn drop(&mut self) {
+        if self.poisoned.is_some() {
+            return;
+        }
+        if self.writes_since_flush > 0 {
+            let result = write_stats(
+                &mut self.header,
+                &mut self.page_manager,
+                &self.stats,
+                &self.sequences,
+            )
+            .and_then(|_| self.commit_header());
+            if let Err(e) = result {
+                error!("Failed to persist tree stats on drop: {}", e);
+            }
+        }
+        // Pages held back in write-back mode reach storage, though unsynced, as they would
+        // have when written in write-through mode
+        if let Err(e) = self.page_manager.write_back() {
+            error!("Failed to write back pages on drop: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    // ─────────────────────────────────────────────────────────
+    // Test Helpers
+    // ─────────────────────────────────────────────────────────
+
+    fn create_temp_btree<K, V>(page_size: u64) -> BTree<K, V>
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    {
+        BTree::temporary(page_size).unwrap()
+    }
+
+    fn create_btree_with_file<K, V>(
+        page_size: u64,
+    ) -> (BTree<K, V>, std::path::PathBuf, NamedTempFile)
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_owned();
+        let btree = BTree::new(file.reopen().unwrap(), page_size).unwrap();
+        (btree, path, file)
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Initialization Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod initialization {
+        use super::*;
+
+        #[test_log::test]
+        fn temporary_trees_start_empty_and_independent() {
+            let mut first = BTree::<i64, i64>::temporary(256).unwrap();
+            let mut second = BTree::<i64, i64>::temporary(256).unwrap();
+
+            for i in 0..300 {
+                first.insert(i, i).unwrap();
+            }
+            first.flush().unwrap();
+
+            assert_eq!(first.len(), 300);
+            assert!(second.is_empty());
+            assert!(second.search(1).is_err());
+        }
+
+        #[test_log::test]
+        fn tree_over_memory_storage() {
+            let mut btree = BTree::<i64, i64>::with_config(
+                crate::storage::MemoryStorage::new(),
+                TreeConfig::with_page_size(256),
+            )
+            .unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i * 3).unwrap();
+            }
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i * 3);
+            }
+        }
+
+        #[test_log::test]
+        fn in_memory_tree_supports_full_interface() {
+            let mut btree =
+                BTree::<String, i64>::in_memory(TreeConfig::with_page_size(256)).unwrap();
+
+            for i in 0..100 {
+                btree.insert(format!("key-{:03}", i), i).unwrap();
+            }
+            assert_eq!(btree.delete("key-050".to_string()).unwrap(), 50);
+            assert_eq!(btree.search("key-051".to_string()).unwrap(), 51);
+
+            let keys: Vec<String> = btree
+                .range("key-048".to_string().."key-053".to_string())
+                .unwrap()
+                .map(|e| e.unwrap().0)
+                .collect();
+            assert_eq!(keys, ["key-048", "key-049", "key-051", "key-052"]);
+        }
+
+        #[test_log::test]
+        fn new_btree_creates_root_page() {
+            let btree = create_temp_btree::<i64, String>(4096);
+
+            assert_eq!(btree.header.page_count, 1);
+            assert_eq!(btree.header.root_page_id, 0);
+        }
+
+        #[test_log::test]
+        fn new_btree_sets_page_size() {
+            let btree = create_temp_btree::<i64, String>(8192);
+
+            assert_eq!(btree.header.page_size, 8192);
+        }
+
+        #[test_log::test]
+        fn new_btree_sets_version() {
+            let btree = create_temp_btree::<i64, String>(4096);
+
+            assert_eq!(btree.header.version, VERSION);
+            assert_eq!(btree.format_version(), crate::format_version());
+        }
+
+        #[test_log::test]
+        fn new_btree_root_is_leaf() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::LEAF);
+        }
+
+        #[test_log::test]
+        fn different_page_sizes() {
+            for page_size in [256, 512, 1024, 4096, 8192] {
+                let btree = create_temp_btree::<i64, i64>(page_size);
+                assert_eq!(btree.header.page_size, page_size);
+            }
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Header Management Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod header_management {
+        use super::*;
+
+        #[test_log::test]
+        fn read_header_after_creation() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            let header = BTree::<i64, String>::read_header(&mut btree.page_manager).unwrap();
+
+            assert_eq!(header.page_count, 1);
+            assert_eq!(header.page_size, 4096);
+        }
+
+        #[test_log::test]
+        fn header_persists_after_write() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.insert(2, "two".to_string()).unwrap();
+
+            let page_count = btree.header.page_count;
+
+            // Reopen
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+
+            let btree_copy = BTree::<i64, String>::new(file, 4096).unwrap();
+            assert_eq!(btree_copy.header.page_count, page_count);
+        }
+
+        #[test_log::test]
+        fn page_count_increments_on_create_page() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            let initial_count = btree.header.page_count;
+
+            // Insert enough to cause splits
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            assert!(btree.header.page_count > initial_count);
+        }
+
+        #[test_log::test]
+        fn root_page_id_changes_on_root_split() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            let initial_root = btree.header.root_page_id;
+
+            // Insert enough to cause root split
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Root should change when tree grows taller
+            assert_ne!(btree.header.root_page_id, initial_root);
+        }
+
+        #[test_log::test]
+        fn stored_header_follows_splits_without_a_flush() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..300 {
+                btree.insert(i, i).unwrap();
+
+                let stored = BTree::<i64, i64>::read_header(&mut btree.page_manager).unwrap();
+                assert_eq!(stored.root_page_id, btree.header.root_page_id);
+                assert_eq!(stored.page_count, btree.page_manager.page_count());
+                assert_eq!(stored.next_page_id, stored.page_count);
+            }
+        }
+
+        #[test_log::test]
+        fn opening_a_header_naming_unallocated_pages_fails() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.flush().unwrap();
+            btree.header.root_page_id = btree.page_manager.page_count();
+            btree.commit_header().unwrap();
+            drop(btree);
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            assert!(matches!(
+                BTree::<i64, i64>::new(file, 256),
+                Err(BTreeError::Header(
+                    crate::header::HeaderError::CorruptedData(_)
+                ))
+            ));
+        }
+
+        #[test_log::test]
+        fn unreadable_header_is_rebuilt_from_the_pages() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(256);
+            for i in 0..300 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            for i in 100..150 {
+                btree.delete(i).unwrap();
+            }
+            btree.flush().unwrap();
+            let root_page_id = btree.root_page_id();
+            let page_count = btree.page_manager.page_count();
+            let commits = btree.stats().commits;
+            drop(btree);
+
+            let reopen = || {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap();
+                BTree::<i64, String>::new(file, 256)
+            };
+            let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            std::io::Write::write_all(&mut file, &[0xFF; Header::SIZE]).unwrap();
+
+            // Only opening with recovery touches pages under a damaged header
+            assert!(matches!(reopen(), Err(BTreeError::Header(_))));
+            let mut btree =
+                BTree::<i64, String>::open_with_recovery(&path, TreeConfig::with_page_size(256))
+                    .unwrap();
+            let repair = btree.header_repair().unwrap().clone();
+            assert_eq!(repair.root_page_id, root_page_id);
+            assert_eq!(repair.page_count, page_count);
+            assert_eq!(repair.entries, 250);
+            assert!(repair.damaged_pages.is_empty());
+            assert_eq!(btree.len(), 250);
+            assert_eq!(btree.stats().commits, commits);
+            assert_eq!(btree.search(299).unwrap(), "value-299");
+            btree.insert(1000, "new".to_string()).unwrap();
+            drop(btree);
+
+            // The rebuilt header was written back
+            let mut btree = reopen().unwrap();
+            assert!(btree.header_repair().is_none());
+            assert_eq!(btree.search(1000).unwrap(), "new");
+            assert_eq!(btree.len(), 251);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Search Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod search {
+        use super::*;
+
+        #[test_log::test]
+        fn search_single_key() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(42, "answer".to_string()).unwrap();
+
+            assert_eq!(btree.search(42).unwrap(), "answer");
+        }
+
+        #[test_log::test]
+        fn get_with_borrows_value_bytes() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            for i in 0..100 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+
+            for i in 0..100 {
+                let length = btree
+                    .get_with(i, |bytes| {
+                        let value: &str = bincode1::deserialize(bytes).unwrap();
+                        assert_eq!(value, format!("value-{}", i));
+                        value.len()
+                    })
+                    .unwrap();
+                assert_eq!(length, format!("value-{}", i).len());
+            }
+            assert!(matches!(
+                btree.get_with(1000, |_| ()),
+                Err(BTreeError::KeyNotFound(_))
+            ));
+        }
+
+        #[test_log::test]
+        fn raw_views_hold_the_stored_bytes() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            for i in 0..100 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+
+            let meta = btree
+                .get_raw(7, |entry| {
+                    assert_eq!(entry.key(), bincode1::serialize(&7i64).unwrap());
+                    assert_eq!(entry.value(), bincode1::serialize("value-7").unwrap());
+                    entry.meta
+                })
+                .unwrap();
+            assert_eq!(meta.encoding, Encoding::Bincode.to_byte());
+            assert_eq!(meta.flags, 0);
+
+            let mut keys = Vec::new();
+            let visited = btree
+                .scan_raw(40..60, |entry| {
+                    let key: i64 = bincode1::deserialize(entry.key()).unwrap();
+                    let value: &str = bincode1::deserialize(entry.value()).unwrap();
+                    assert_eq!(value, format!("value-{}", key));
+                    keys.push(key);
+                    key < 49
+                })
+                .unwrap();
+            assert_eq!(visited, 10);
+            assert_eq!(keys, (40..50).collect::<Vec<_>>());
+        }
+
+        #[test_log::test]
+        fn search_nonexistent_key_returns_error() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+
+            let result = btree.search(999);
+            assert!(matches!(result, Err(BTreeError::KeyNotFound(_))));
+        }
+
+        #[test_log::test]
+        fn search_empty_tree_returns_error() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            let result = btree.search(1);
+            assert!(matches!(result, Err(BTreeError::KeyNotFound(_))));
+        }
+
+        #[test_log::test]
+        fn search_first_key() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            assert_eq!(btree.search(0).unwrap(), 0);
+        }
+
+        #[test_log::test]
+        fn search_last_key() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            assert_eq!(btree.search(99).unwrap(), 990);
+        }
+
+        #[test_log::test]
+        fn search_middle_key() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            assert_eq!(btree.search(50).unwrap(), 500);
+        }
+
+        #[test_log::test]
+        fn search_after_splits() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            // Insert enough to cause multiple splits
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // All keys should still be findable
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+
+        #[test_log::test]
+        fn search_with_string_keys() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            btree.insert("apple".to_string(), 1).unwrap();
+            btree.insert("banana".to_string(), 2).unwrap();
+            btree.insert("cherry".to_string(), 3).unwrap();
+
+            assert_eq!(btree.search("banana".to_string()).unwrap(), 2);
+        }
+
+        #[test_log::test]
+        fn search_negative_keys() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(-100, "negative".to_string()).unwrap();
+            btree.insert(0, "zero".to_string()).unwrap();
+            btree.insert(100, "positive".to_string()).unwrap();
+
+            assert_eq!(btree.search(-100).unwrap(), "negative");
+            assert_eq!(btree.search(0).unwrap(), "zero");
+            assert_eq!(btree.search(100).unwrap(), "positive");
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Insert Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod insert {
+        use super::*;
+
+        #[test_log::test]
+        fn insert_single_entry() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+
+            assert_eq!(btree.search(1).unwrap(), "one");
+        }
+
+        #[test_log::test]
+        fn insert_multiple_sequential() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..50 {
+                btree.insert(i, i * 2).unwrap();
+            }
+
+            for i in 0..50 {
+                assert_eq!(btree.search(i).unwrap(), i * 2);
+            }
+        }
+
+        #[test_log::test]
+        fn insert_multiple_reverse() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in (0..50).rev() {
+                btree.insert(i, i * 2).unwrap();
+            }
+
+            for i in 0..50 {
+                assert_eq!(btree.search(i).unwrap(), i * 2);
+            }
+        }
+
+        #[test_log::test]
+        fn insert_random_order() {
+            use rand::rng;
+            use rand::seq::SliceRandom;
+
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            let mut keys: Vec<i64> = (0..100).collect();
+            keys.shuffle(&mut rng());
+
+            for &k in &keys {
+                btree.insert(k, k * 10).unwrap();
+            }
+
+            for k in 0..100 {
+                assert_eq!(btree.search(k).unwrap(), k * 10);
+            }
+        }
+
+        #[test_log::test]
+        fn insert_updates_existing_key() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "original".to_string()).unwrap();
+            btree.insert(1, "updated".to_string()).unwrap();
+
+            assert_eq!(btree.search(1).unwrap(), "updated");
+        }
+
+        #[test_log::test]
+        fn insert_update_preserves_other_keys() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.insert(2, "two".to_string()).unwrap();
+            btree.insert(3, "three".to_string()).unwrap();
+
+            btree.insert(2, "TWO".to_string()).unwrap();
+
+            assert_eq!(btree.search(1).unwrap(), "one");
+            assert_eq!(btree.search(2).unwrap(), "TWO");
+            assert_eq!(btree.search(3).unwrap(), "three");
+        }
+
+        #[test_log::test]
+        fn insert_many_updates_same_key() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            btree.insert(1, 0).unwrap();
+
+            for i in 1..=100 {
+                btree.insert(1, i).unwrap();
+            }
+
+            assert_eq!(btree.search(1).unwrap(), 100);
+        }
+
+        #[test_log::test]
+        fn insert_different_value_types() {
+            // String values
+            let mut btree1 = create_temp_btree::<i64, String>(4096);
+            btree1.insert(1, "hello".to_string()).unwrap();
+            assert_eq!(btree1.search(1).unwrap(), "hello");
+
+            // Vector values
+            let mut btree2 = create_temp_btree::<i64, Vec<u8>>(4096);
+            btree2.insert(1, vec![1, 2, 3, 4, 5]).unwrap();
+            assert_eq!(btree2.search(1).unwrap(), vec![1, 2, 3, 4, 5]);
+
+            // Tuple values
+            let mut btree3 = create_temp_btree::<i64, (i64, String)>(4096);
+            btree3.insert(1, (42, "answer".to_string())).unwrap();
+            assert_eq!(btree3.search(1).unwrap(), (42, "answer".to_string()));
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Split Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod split {
+        use super::*;
+
+        #[test_log::test]
+        fn insert_causes_leaf_split() {
+            let mut btree = create_temp_btree::<i64, String>(512);
+
+            let initial_page_count = btree.header.page_count;
+
+            for i in 0..30 {
+                btree.insert(i, format!("value_{}", i)).unwrap();
+            }
+
+            assert!(
+                btree.header.page_count > initial_page_count,
+                "Page count should increase after splits"
+            );
+        }
+
+        #[test_log::test]
+        fn split_maintains_all_data() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            // Verify all data is still accessible
+            for i in 0..100 {
+                assert_eq!(
+                    btree.search(i).unwrap(),
+                    i * 10,
+                    "Key {} should have value {}",
+                    i,
+                    i * 10
+                );
+            }
+        }
+
+        #[test_log::test]
+        fn multiple_splits_maintain_order() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            // Insert in random order
+            let keys = vec![50, 25, 75, 10, 30, 60, 90, 5, 15, 27, 35, 55, 70, 80, 95];
+
+            for &k in &keys {
+                btree.insert(k, k).unwrap();
+            }
+
+            // All should be findable
+            for &k in &keys {
+                assert_eq!(btree.search(k).unwrap(), k);
+            }
+        }
+
+        #[test_log::test]
+        fn root_split_creates_new_root() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            let initial_root = btree.header.root_page_id;
+
+            // Insert enough to cause root to split
+            for i in 0..50 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Verify root changed
+            assert_ne!(btree.header.root_page_id, initial_root);
+
+            // New root should be internal node
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::INTERNAL);
+        }
+
+        #[test_log::test]
+        fn split_with_sequential_inserts() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+                // Verify all previous inserts still work
+                for j in 0..=i {
+                    assert_eq!(btree.search(j).unwrap(), j, "Failed after inserting {}", i);
+                }
+            }
+        }
+
+        #[test_log::test]
+        fn split_with_reverse_inserts() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in (0..200).rev() {
+                btree.insert(i, i).unwrap();
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Internal Node Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod internal_nodes {
+        use super::*;
+
+        #[test_log::test]
+        fn internal_node_pointers_correct() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            // Insert enough to create internal nodes
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+
+            if root.node_type == NodeType::INTERNAL {
+                // Internal node should have pointers
+                assert!(!root.pointers.is_empty());
+                // Should have one more pointer than keys
+                assert_eq!(root.pointers.len(), root.num_keys as usize + 1);
+            }
+        }
+
+        #[test_log::test]
+        fn search_traverses_internal_nodes() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            // These searches should traverse internal nodes
+            assert_eq!(btree.search(0).unwrap(), 0);
+            assert_eq!(btree.search(50).unwrap(), 500);
+            assert_eq!(btree.search(99).unwrap(), 990);
+        }
+
+        #[test_log::test]
+        fn internal_node_split_preserves_data() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            // Insert many entries to cause internal node splits
+            for i in 0..500 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Verify all data
+            for i in 0..500 {
+                assert_eq!(btree.search(i).unwrap(), i, "Key {} not found", i);
+            }
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Page I/O Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod page_io {
+        use super::*;
+
+        // Counts writes reaching the storage
+        #[derive(Default)]
+        struct CountingStorage {
+            inner: MemoryStorage,
+            writes: Arc<std::sync::atomic::AtomicUsize>,
+        }
+
+        impl Storage for CountingStorage {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> crate::io::Result<usize> {
+                self.inner.read_at(buf, offset)
+            }
+
+            fn write_at(&self, buf: &[u8], offset: u64) -> crate::io::Result<()> {
+                self.writes
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                self.inner.write_at(buf, offset)
+            }
+
+            fn size(&self) -> crate::io::Result<u64> {
+                self.inner.size()
+            }
+
+            fn set_size(&self, size: u64) -> crate::io::Result<()> {
+                self.inner.set_size(size)
+            }
+
+            fn sync(&self) -> crate::io::Result<()> {
+                self.inner.sync()
+            }
+        }
+
+        #[test_log::test]
+        fn unchanged_pages_are_not_rewritten() {
+            let storage = CountingStorage::default();
+            let writes = storage.writes.clone();
+            let mut btree =
+                BTree::<i64, String>::with_config(storage, TreeConfig::with_page_size(4096))
+                    .unwrap();
+            let count = || writes.load(std::sync::atomic::Ordering::SeqCst);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            let before = count();
+            btree.insert(2, "two".to_string()).unwrap();
+            // Only the root leaf; the header has not changed
+            assert_eq!(count() - before, 1);
+
+            let before = count();
+            btree.insert(1, "one".to_string()).unwrap();
+            assert_eq!(count(), before);
+
+            btree.insert(1, "uno".to_string()).unwrap();
+            assert_eq!(btree.search(1).unwrap(), "uno");
+            assert_eq!(btree.len(), 2);
+        }
+
+        #[test_log::test]
+        fn unchanged_internal_entry_is_not_rewritten() {
+            let storage = CountingStorage::default();
+            let writes = storage.writes.clone();
+            let mut btree =
+                BTree::<i64, i64>::with_config(storage, TreeConfig::with_page_size(256)).unwrap();
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::INTERNAL);
+            let separator = root.read_key(0).unwrap();
+
+            let before = writes.load(std::sync::atomic::Ordering::SeqCst);
+            btree.insert(separator, separator).unwrap();
+            assert_eq!(writes.load(std::sync::atomic::Ordering::SeqCst), before);
+            assert_eq!(btree.len(), 100);
+        }
+
+        #[test_log::test]
+        fn allocation_ignores_preallocated_file_space() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+            drop(btree);
+
+            // Preallocate room for many more pages than the tree has used
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let length = file.metadata().unwrap().len();
+            file.set_len(length + 64 * 256).unwrap();
+
+            let mut btree = BTree::<i64, i64>::new(file, 256).unwrap();
+            let next_page_id = btree.header.next_page_id;
+            assert_eq!(btree.page_manager.page_count(), next_page_id);
+            assert_eq!(btree.page_manager.allocate_page().unwrap(), next_page_id);
+
+            for i in 100..200 {
+                btree.insert(i, i).unwrap();
+            }
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+
+        #[test_log::test]
+        fn open_rejects_file_shorter_than_its_pages() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+            drop(btree);
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let length = file.metadata().unwrap().len();
+            file.set_len(length - 100).unwrap();
+
+            assert!(matches!(
+                BTree::<i64, i64>::new(file, 256),
+                Err(BTreeError::PageManager(
+                    PageManagerError::StorageTooShort { .. }
+                ))
+            ));
+        }
+
+        #[test_log::test]
+        fn pages_are_classified_by_type() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            btree.set_compression_dictionary(vec![3u8; 400]).unwrap();
+            let dictionary_page_id = btree.header.dictionary_page_id;
+
+            assert_eq!(btree.page_type(dictionary_page_id).unwrap(), NodeType::META);
+            assert_eq!(
+                btree.page_type(dictionary_page_id + 1).unwrap(),
+                NodeType::OVERFLOW
+            );
+            assert_eq!(btree.compression_dictionary().unwrap().len(), 400);
+
+            for i in 0..300 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            for i in 0..300 {
+                btree.delete(i).unwrap();
+            }
+            let types: Vec<NodeType> = (0..btree.header.page_count)
+                .map(|page_id| btree.page_type(page_id).unwrap())
+                .collect();
+            assert!(types.contains(&NodeType::FREE));
+            assert!(types.contains(&NodeType::LEAF));
+
+            let free_page = types.iter().position(|t| *t == NodeType::FREE).unwrap();
+            assert!(matches!(
+                btree.read_page(free_page as u64),
+                Err(BTreeError::InvalidNodeType(4))
+            ));
+        }
+
+        #[test_log::test]
+        fn replaced_dictionary_pages_are_freed() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            btree.set_compression_dictionary(vec![1u8; 100]).unwrap();
+            let old_page_id = btree.header.dictionary_page_id;
+
+            btree.set_compression_dictionary(vec![2u8; 100]).unwrap();
+
+            assert_eq!(btree.page_type(old_page_id).unwrap(), NodeType::FREE);
+            assert_eq!(
+                btree.page_type(btree.header.dictionary_page_id).unwrap(),
+                NodeType::META
+            );
+        }
+
+        #[test_log::test]
+        fn read_page_returns_correct_data() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.insert(2, "two".to_string()).unwrap();
+
+            let page = btree.read_page(btree.header.root_page_id).unwrap();
+
+            assert_eq!(page.num_keys, 2);
+        }
+
+        #[test_log::test]
+        fn write_and_read_page_roundtrip() {
+            let (mut btree, path, _original_file) = create_btree_with_file::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.insert(2, "two".to_string()).unwrap();
+            btree.insert(3, "three".to_string()).unwrap();
+
+            let root_id = btree.header.root_page_id;
+            drop(btree);
+
+            // Reopen and read
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+
+            let mut btree = BTree::<i64, String>::new(file, 4096).unwrap();
+            let page = btree.read_page(root_id).unwrap();
+
+            assert_eq!(page.num_keys, 3);
+        }
+
+        #[test_log::test]
+        fn create_page_increments_count() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            let initial = btree.header.page_count;
+
+            // Force page creation via splits
+            for i in 0..50 {
+                btree.insert(i, i).unwrap();
+            }
+
+            assert!(btree.header.page_count > initial);
+        }
+
+        #[test_log::test]
+        fn open_creates_and_reopens_tree() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            {
+                let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+                btree.insert(7, 49).unwrap();
+            }
+
+            let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            assert_eq!(btree.search(7).unwrap(), 49);
+        }
+
+        #[test_log::test]
+        fn open_creates_tree_whole_under_its_name() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            // Left behind by a creation that crashed before its rename
+            std::fs::write(dir.path().join(".creating-crashed"), [0u8; 10]).unwrap();
+
+            let btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            let stored = std::fs::read(&path).unwrap();
+            let header = Header::deserialize(&stored[..Header::SIZE]).unwrap();
+            assert_eq!(header.root_page_id, btree.root_page_id());
+
+            let creating: Vec<_> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .filter(|name| name.to_string_lossy().starts_with(".creating-"))
+                .collect();
+            assert_eq!(creating, [".creating-crashed"]);
+        }
+
+        #[test_log::test]
+        fn second_open_of_locked_file_fails() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let _first = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            let second = BTree::<i64, i64>::open(&path, TreeConfig::default());
+            assert!(matches!(second, Err(BTreeError::AlreadyOpen(_))));
+
+            // Handles that bypass the registry still find the file locked
+            let page_manager = PageManager::open(&path, 4096, Header::SIZE as u64);
+            assert!(matches!(page_manager, Err(PageManagerError::Locked)));
+        }
+
+        #[test_log::test]
+        fn open_recognises_other_spellings_of_path() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let _first = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+
+            let other = dir.path().join(".").join("tree.db");
+            match BTree::<i64, i64>::open(&other, TreeConfig::default()) {
+                Err(BTreeError::AlreadyOpen(open)) => {
+                    assert_eq!(open, path.canonicalize().unwrap())
+                }
+                other => panic!("expected AlreadyOpen, got {:?}", other.err()),
+            }
+        }
+
+        #[test_log::test]
+        fn open_shared_hands_out_one_handle() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let first = BTree::<i64, i64>::open_shared(&path, TreeConfig::default()).unwrap();
+            let second = BTree::<i64, i64>::open_shared(&path, TreeConfig::default()).unwrap();
+            assert!(Arc::ptr_eq(&first, &second));
+            first.lock().unwrap().insert(1, 10).unwrap();
+            assert_eq!(second.lock().unwrap().search(1).unwrap(), 10);
+
+            // Neither an exclusive handle nor one of another type can join in
+            assert!(matches!(
+                BTree::<i64, i64>::open(&path, TreeConfig::default()),
+                Err(BTreeError::AlreadyOpen(_))
+            ));
+            assert!(matches!(
+                BTree::<i64, String>::open_shared(&path, TreeConfig::default()),
+                Err(BTreeError::AlreadyOpen(_))
+            ));
+
+            // The tree closes with its last handle
+            drop(first);
+            assert!(BTree::<i64, i64>::open(&path, TreeConfig::default()).is_err());
+            drop(second);
+            let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            assert_eq!(btree.search(1).unwrap(), 10);
+        }
+
+        #[test_log::test]
+        fn open_shared_refuses_exclusively_open_path() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let _exclusive = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            assert!(matches!(
+                BTree::<i64, i64>::open_shared(&path, TreeConfig::default()),
+                Err(BTreeError::AlreadyOpen(_))
+            ));
+        }
+
+        fn open_read_write(path: &std::path::Path) -> File {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap()
+        }
+
+        #[test_log::test]
+        fn from_file_reads_a_tree_whatever_the_cursor() {
+            use std::io::{Seek, SeekFrom};
+
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(4096);
+            for i in 0..500 {
+                btree.insert(i, i * 3).unwrap();
+            }
+            drop(btree);
+
+            let mut file = open_read_write(&path);
+            file.seek(SeekFrom::Start(1234)).unwrap();
+            let mut btree =
+                BTree::<i64, i64>::from_file(file, TreeConfig::with_page_size(4096)).unwrap();
+
+            assert_eq!(btree.len(), 500);
+            assert_eq!(btree.search(321).unwrap(), 963);
+            btree.insert(500, 1500).unwrap();
+        }
+
+        #[test_log::test]
+        fn from_file_creates_a_tree_in_an_empty_file() {
+            let file = NamedTempFile::new().unwrap();
+            let mut btree =
+                BTree::<i64, i64>::from_file(file.reopen().unwrap(), TreeConfig::default())
+                    .unwrap();
+
+            btree.insert(1, 1).unwrap();
+            assert_eq!(btree.search(1).unwrap(), 1);
+        }
+
+        #[test_log::test]
+        fn from_file_rejects_descriptors_without_write_access() {
+            let file = NamedTempFile::new().unwrap();
+            let read_only = File::open(file.path()).unwrap();
+
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(read_only, TreeConfig::default()),
+                Err(BTreeError::PageManager(PageManagerError::ReadOnly))
+            ));
+        }
+
+        #[cfg(unix)]
+        #[test_log::test]
+        fn from_file_rejects_directories() {
+            let dir = tempfile::tempdir().unwrap();
+            let handle = File::open(dir.path()).unwrap();
+
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(handle, TreeConfig::default()),
+                Err(BTreeError::PageManager(PageManagerError::NotAFile))
+            ));
+        }
+
+        #[test_log::test]
+        fn from_file_validates_an_existing_header() {
+            let (btree, path, _file) = create_btree_with_file::<i64, i64>(4096);
+            drop(btree);
+
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(
+                    open_read_write(&path),
+                    TreeConfig::with_page_size(8192)
+                ),
+                Err(BTreeError::Header(
+                    crate::header::HeaderError::PageSizeMismatch { .. }
+                ))
+            ));
+
+            let mut header = [0u8; Header::SIZE];
+            Storage::read_exact_at(&open_read_write(&path), &mut header, 0).unwrap();
+            header[2..4].copy_from_slice(&(VERSION + 1).to_le_bytes());
+            Storage::write_at(&open_read_write(&path), &header, 0).unwrap();
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(
+                    open_read_write(&path),
+                    TreeConfig::with_page_size(4096)
+                ),
+                Err(BTreeError::Header(
+                    crate::header::HeaderError::UnsupportedVersion(_)
+                ))
+            ));
+
+            Storage::write_at(&open_read_write(&path), &[0u8; Header::SIZE], 0).unwrap();
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(
+                    open_read_write(&path),
+                    TreeConfig::with_page_size(4096)
+                ),
+                Err(BTreeError::Header(
+                    crate::header::HeaderError::InvalidMagicNumber(0)
+                ))
+            ));
+        }
+
+        #[test_log::test]
+        fn from_file_respects_the_lock_of_an_open_tree() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let _open = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            assert!(matches!(
+                BTree::<i64, i64>::from_file(open_read_write(&path), TreeConfig::default()),
+                Err(BTreeError::PageManager(PageManagerError::Locked))
+            ));
+        }
+
+        #[test_log::test]
+        fn lock_is_released_on_drop() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let first = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            drop(first);
+
+            assert!(BTree::<i64, i64>::open(&path, TreeConfig::default()).is_ok());
+        }
+
+        #[test_log::test]
+        fn concurrent_open_attempts_admit_one_handle() {
+            use std::sync::Barrier;
+
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            // Create the file up front so every thread races on the lock, not on creation
+            drop(BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap());
+
+            let barrier = Arc::new(Barrier::new(8));
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let path = path.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        let result = BTree::<i64, i64>::open(&path, TreeConfig::default());
+                        // Hold any acquired lock until every thread has tried
+                        barrier.wait();
+                        result.is_ok()
+                    })
+                })
+                .collect();
+
+            let opened = handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|ok| *ok)
+                .count();
+            assert_eq!(opened, 1);
+        }
+
+        #[test_log::test]
+        fn flush_writes_manifest_with_increasing_checkpoints() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let manifest_path = Manifest::path_for(&path);
+
+            {
+                let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+                assert!(!manifest_path.exists());
+                btree.insert(1, 1).unwrap();
+                btree.flush().unwrap();
+                btree.flush().unwrap();
+            }
+
+            let manifest = Manifest::read(&manifest_path).unwrap();
+            assert_eq!(manifest.data_file, "tree.db");
+            assert_eq!(manifest.format_version, VERSION);
+            assert_eq!(manifest.checkpoint, 2);
+
+            // Numbering carries on across reopens
+            let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            btree.flush().unwrap();
+            let manifest = Manifest::read(&manifest_path).unwrap();
+            assert_eq!(manifest.checkpoint, 3);
+            assert_eq!(manifest.page_count, btree.header.page_count);
+            assert_eq!(manifest.root_page_id, btree.header.root_page_id);
+        }
+
+        #[test_log::test]
+        fn corrupt_manifest_is_replaced_on_flush() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let manifest_path = Manifest::path_for(&path);
+            std::fs::write(&manifest_path, b"CLMF garbage").unwrap();
+
+            let mut btree = BTree::<i64, i64>::open(&path, TreeConfig::default()).unwrap();
+            btree.flush().unwrap();
+
+            assert_eq!(Manifest::read(&manifest_path).unwrap().checkpoint, 1);
+        }
+
+        #[test_log::test]
+        fn pages_reuse_buffers_from_the_pool() {
+            let pool = Arc::new(crate::buffer_pool::PagePool::new(8));
+            let config = TreeConfig {
+                leaf_page_size: 1024,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::<i64, String>::in_memory(config).unwrap();
+            btree.set_buffer_pool(pool.clone());
+
+            for i in 0..300 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            for i in 0..300 {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
+            }
+            btree.verify().unwrap();
+            assert!(pool.reused() > 300, "{} reused", pool.reused());
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Edge Cases
+    // ─────────────────────────────────────────────────────────
+
+    mod edge_cases {
+        use super::*;
+
+        #[test_log::test]
+        fn empty_string_key() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            btree.insert("".to_string(), 42).unwrap();
+
+            assert_eq!(btree.search("".to_string()).unwrap(), 42);
+        }
+
+        #[test_log::test]
+        fn empty_string_value() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "".to_string()).unwrap();
+
+            assert_eq!(btree.search(1).unwrap(), "");
+        }
+
+        #[test_log::test]
+        fn large_key() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            let large_key = "x".repeat(500);
+            btree.insert(large_key.clone(), 42).unwrap();
+
+            assert_eq!(btree.search(large_key).unwrap(), 42);
+        }
+
+        #[test_log::test]
+        fn large_value() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            let large_value = "y".repeat(1000);
+            btree.insert(1, large_value.clone()).unwrap();
+
+            assert_eq!(btree.search(1).unwrap(), large_value);
+        }
+
+        #[test_log::test]
+        fn min_max_i64_keys() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(i64::MIN, "min".to_string()).unwrap();
+            btree.insert(i64::MAX, "max".to_string()).unwrap();
+            btree.insert(0, "zero".to_string()).unwrap();
+
+            assert_eq!(btree.search(i64::MIN).unwrap(), "min");
+            assert_eq!(btree.search(i64::MAX).unwrap(), "max");
+            assert_eq!(btree.search(0).unwrap(), "zero");
+        }
+
+        #[test_log::test]
+        fn unicode_keys() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            btree.insert("こんにちは".to_string(), 1).unwrap();
+            btree.insert("مرحبا".to_string(), 2).unwrap();
+            btree.insert("🎉🎊🎁".to_string(), 3).unwrap();
+
+            assert_eq!(btree.search("こんにちは".to_string()).unwrap(), 1);
+            assert_eq!(btree.search("مرحبا".to_string()).unwrap(), 2);
+            assert_eq!(btree.search("🎉🎊🎁".to_string()).unwrap(), 3);
+        }
+
+        #[test_log::test]
+        fn special_characters_in_keys() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            let special_keys = [
+                "\n\t\r",
+                "key with spaces",
+                "key\0with\0nulls",
+                "key'with\"quotes",
+                "key\\with\\backslashes",
+            ];
+
+            for (i, key) in special_keys.iter().enumerate() {
+                btree.insert(key.to_string(), i as i64).unwrap();
+            }
+
+            for (i, key) in special_keys.iter().enumerate() {
+                assert_eq!(btree.search(key.to_string()).unwrap(), i as i64);
+            }
+        }
+
+        #[test_log::test]
+        fn duplicate_inserts_idempotent() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for _ in 0..100 {
+                btree.insert(1, 42).unwrap();
+            }
+
+            assert_eq!(btree.search(1).unwrap(), 42);
+
+            // Should only have one entry in root
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.num_keys, 1);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Stress Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod stress {
+        use super::*;
+
+        #[test_log::test]
+        fn overwrites_of_mixed_sizes_keep_pages_intact() {
+            use rand::Rng;
+
+            let mut rng = rand::rng();
+            let mut btree = create_temp_btree::<i64, String>(512);
+            let mut expected = std::collections::BTreeMap::new();
+            for _ in 0..3000 {
+                let key = rng.random_range(0..300);
+                let value = "x".repeat(rng.random_range(0..60));
+                btree.insert(key, value.clone()).unwrap();
+                expected.insert(key, value);
+            }
+
+            btree.verify().unwrap();
+            for (key, value) in expected {
+                assert_eq!(btree.search(key).unwrap(), value);
+            }
+        }
+
+        #[test_log::test]
+        fn deletes_of_separators_replaced_by_larger_entries() {
+            use rand::{Rng, SeedableRng};
+
+            // Seeded to delete a separator whose predecessor is too large to take its place
+            // until the node holding it is split
+            let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+            let mut btree = create_temp_btree::<i64, String>(512);
+            let mut expected = std::collections::BTreeMap::new();
+            for _ in 0..3000 {
+                let key = rng.random_range(0..300);
+                if rng.random_bool(0.3) {
+                    assert_eq!(btree.delete(key).ok(), expected.remove(&key));
+                } else {
+                    let value = "x".repeat(rng.random_range(0..60));
+                    btree.insert(key, value.clone()).unwrap();
+                    expected.insert(key, value);
+                }
+            }
+
+            btree.verify().unwrap();
+            let entries: Vec<(i64, String)> = btree.iter().unwrap().map(Result::unwrap).collect();
+            assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+        }
+
+        #[test_log::test]
+        fn inserts_after_splits_that_fragment_the_left_page() {
+            use rand::{Rng, SeedableRng};
+
+            // Seeded to split a page whose remaining entries leave no hole large enough for the
+            // entry being inserted
+            let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+            let mut btree = create_temp_btree::<i64, String>(512);
+            let mut expected = std::collections::BTreeMap::new();
+            for _ in 0..1000 {
+                let key = rng.random_range(0..2000);
+                let value = "x".repeat(rng.random_range(0..60));
+                btree.insert(key, value.clone()).unwrap();
+                expected.insert(key, value);
+            }
+
+            btree.verify().unwrap();
+            let entries: Vec<(i64, String)> = btree.iter().unwrap().map(Result::unwrap).collect();
+            assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+        }
+
+        #[test_log::test]
+        fn insert_one_thousand_sequential() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..1000 {
+                btree.insert(i, i * 2).unwrap();
+            }
+
+            for i in 0..1000 {
+                assert_eq!(btree.search(i).unwrap(), i * 2);
+            }
+        }
+
+        #[test_log::test]
+        fn insert_one_hundred_random() {
+            use rand::rng;
+            use rand::seq::SliceRandom;
+
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let mut keys: Vec<i64> = (0..100).collect();
+            keys.shuffle(&mut rng());
+
+            for &k in &keys {
+                btree.insert(k, k).unwrap();
+                println!("Insert: {:?}", k);
+                btree.print_tree();
+            }
+
+            btree.print_tree();
+
+            for &k in &keys {
+                assert_eq!(btree.search(k).unwrap(), k);
+            }
+        }
+
+        #[test_log::test]
+        fn insert_one_thousand_random() {
+            use rand::rng;
+            use rand::seq::SliceRandom;
+
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            let mut keys: Vec<i64> = (0..1000).collect();
+            keys.shuffle(&mut rng());
+
+            for &k in &keys {
+                btree.insert(k, k).unwrap();
+            }
+            btree.print_tree();
+
+            for &k in &keys {
+                assert_eq!(btree.search(k).unwrap(), k);
+            }
+        }
+
+        #[test_log::test]
+        fn small_page_many_splits() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..500 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Verify tree integrity
+            for i in 0..500 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+
+            // Should have created many pages
+            assert!(btree.header.page_count > 10);
+        }
+
+        #[test_log::test]
+        fn alternating_insert_and_search() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..500 {
+                btree.insert(i, i * 10).unwrap();
+
+                // Verify this and some previous inserts
+                assert_eq!(btree.search(i).unwrap(), i * 10);
+
+                if i > 0 {
+                    assert_eq!(btree.search(i / 2).unwrap(), (i / 2) * 10);
+                }
+            }
+        }
+
+        #[test_log::test]
+        fn many_updates() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            // Insert initial values
+            for i in 0..100 {
+                btree.insert(i, 0).unwrap();
+            }
+
+            // Update each key many times
+            for round in 1..=50 {
+                for i in 0..100 {
+                    btree.insert(i, round).unwrap();
+                }
+            }
+
+            // Verify final values
+            for i in 0..100 {
+                assert_eq!(btree.search(i).unwrap(), 50);
+            }
+        }
+
+        #[test_log::test]
+        #[ignore] // Run with: cargo test -- --ignored
+        fn insert_ten_thousand() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            for i in 0..10_000 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Spot check
+            assert_eq!(btree.search(0).unwrap(), 0);
+            assert_eq!(btree.search(5000).unwrap(), 5000);
+            assert_eq!(btree.search(9999).unwrap(), 9999);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Tree Structure Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod tree_structure {
+        use super::*;
+
+        #[test_log::test]
+        fn verify_accepts_trees_after_mixed_operations() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree.verify().unwrap();
+
+            for i in 0..400 {
+                btree.insert((i * 37) % 400, i).unwrap();
+            }
+            for i in (0..400).step_by(3) {
+                btree.delete(i).unwrap();
+            }
+            btree.verify().unwrap();
+
+            btree.clear().unwrap();
+            btree.bulk_load((0..300).map(|i| (i, i))).unwrap();
+            btree.verify().unwrap();
+        }
+
+        #[test_log::test]
+        fn verify_reports_keys_out_of_order() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            // Swap the first and last keys of the leftmost leaf
+            let mut page = btree.read_page(btree.header.root_page_id).unwrap();
+            while page.node_type == NodeType::INTERNAL {
+                page = btree.read_page(page.pointers[0]).unwrap();
+            }
+            let last = page.slots.len() - 1;
+            let (first_key, first_value) = page.read_key_value(0).unwrap();
+            let (last_key, last_value) = page.read_key_value(last).unwrap();
+            page.update(0, &last_key, &last_value).unwrap();
+            page.update(last, &first_key, &first_value).unwrap();
+            BTree::<i64, i64>::write_page(&page, &mut btree.page_manager).unwrap();
+
+            assert!(matches!(
+                btree.verify(),
+                Err(BTreeError::Corrupted { page_id, .. }) if page_id == page.page_id
+            ));
+        }
+
+        #[test_log::test]
+        fn verify_reports_stats_that_disagree_with_the_tree() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..50 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.stats.entries += 1;
+
+            assert!(matches!(btree.verify(), Err(BTreeError::Corrupted { .. })));
+        }
+
+        #[test_log::test]
+        fn single_entry_stays_in_root() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            btree.insert(1, "one".to_string()).unwrap();
+            assert_eq!(btree.header.page_count, 1);
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::LEAF);
+            assert_eq!(root.num_keys, 1);
+        }
+
+        #[test_log::test]
+        fn tree_grows_in_height() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            // Track when root changes (indicates height increase)
+            let mut root_changes = 0;
+            let mut last_root = btree.header.root_page_id;
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+
+                if btree.header.root_page_id != last_root {
+                    root_changes += 1;
+                    last_root = btree.header.root_page_id;
+                }
+            }
+
+            assert!(
+                root_changes >= 2,
+                "Tree should have grown in height multiple times"
+            );
+        }
+
+        #[test_log::test]
+        fn deep_tree_has_no_depth_limit() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+
+            // Few entries fit in a page, so the tree grows deeper than the old recursion limit
+            for i in 0..3000 {
+                btree.insert(i, "v".repeat(60)).unwrap();
+            }
+
+            let mut depth = 1;
+            let mut page = btree.read_page(btree.header.root_page_id).unwrap();
+            while let Some(&child) = page.pointers.first() {
+                page = btree.read_page(child).unwrap();
+                depth += 1;
+            }
+            assert!(depth > 10, "depth was {}", depth);
+
+            for i in (0..3000).step_by(97) {
+                assert_eq!(btree.search(i).unwrap(), "v".repeat(60));
+            }
+            btree.print_tree();
+        }
+
+        #[test_log::test]
+        fn internal_node_has_correct_pointer_count() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            fn check_pointers<K, V>(btree: &mut BTree<K, V>, page_id: u64)
+            where
+                K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+                V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+            {
+                let page = btree.read_page(page_id).unwrap();
+
+                if page.node_type == NodeType::INTERNAL {
+                    // Internal nodes should have num_keys + 1 pointers
+                    assert_eq!(
+                        page.pointers.len(),
+                        page.num_keys as usize + 1,
+                        "Page {} has {} keys but {} pointers",
+                        page_id,
+                        page.num_keys,
+                        page.pointers.len()
+                    );
+
+                    // Recursively check children
+                    for &ptr in &page.pointers {
+                        check_pointers(btree, ptr);
+                    }
+                } else {
+                    // Leaf nodes should have no pointers
+                    assert!(
+                        page.pointers.is_empty(),
+                        "Leaf page {} should have no pointers",
+                        page_id
+                    );
+                }
+            }
+
+            let root_page_id = btree.header.root_page_id;
+            check_pointers(&mut btree, root_page_id);
+        }
+
+        #[test_log::test]
+        fn all_leaves_at_same_depth() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            fn get_leaf_depths<K, V>(
+                btree: &mut BTree<K, V>,
+                page_id: u64,
+                depth: usize,
+            ) -> Vec<usize>
+            where
+                K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+                V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+            {
+                let page = btree.read_page(page_id).unwrap();
+
+                if page.node_type == NodeType::LEAF {
+                    vec![depth]
+                } else {
+                    page.pointers
+                        .iter()
+                        .flat_map(|&ptr| get_leaf_depths(btree, ptr, depth + 1))
+                        .collect()
+                }
+            }
+
+            let root_page_id = btree.header.root_page_id;
+            let depths = get_leaf_depths(&mut btree, root_page_id, 0);
+
+            // All leaves should be at the same depth
+            let first_depth = depths[0];
+            assert!(
+                depths.iter().all(|&d| d == first_depth),
+                "Leaf depths are not uniform: {:?}",
+                depths
+            );
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Scrub Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod scrub {
+        use super::*;
+        use crate::clock::Instant;
+        use crate::scrub::{DamagedPage, ScrubOptions, Scrubber};
+        use core::time::Duration;
+
+        fn create_scrub_btree() -> BTree<i64, String> {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            for i in 0..300 {
+                btree
+                    .insert((i * 37) % 300, format!("value-{:04}", i))
+                    .unwrap();
+            }
+            for i in (0..300).step_by(4) {
+                btree.delete(i).unwrap();
+            }
+            btree.flush().unwrap();
+            btree
+        }
+
+        fn leftmost_leaf(btree: &mut BTree<i64, String>) -> u64 {
+            let mut page = btree.read_page(btree.header.root_page_id).unwrap();
+            while page.node_type == NodeType::INTERNAL {
+                page = btree.read_page(page.pointers[0]).unwrap();
+            }
+            page.page_id
+        }
+
+        #[test_log::test]
+        fn scrub_finds_no_damage_in_a_healthy_tree() {
+            let mut btree = create_scrub_btree();
+
+            let report = btree.scrub(ScrubOptions::default()).unwrap();
+
+            assert!(report.is_clean(), "{:?}", report.damaged);
+            assert_eq!(report.pages_scanned, btree.page_manager.page_count());
+            assert!(report.pages_checksummed > 1);
+            assert_eq!(report.pages_without_checksum, 0);
+        }
+
+        #[test_log::test]
+        fn scrub_reports_damaged_pages_without_changing_them() {
+            let mut btree = create_scrub_btree();
+            let leaf = leftmost_leaf(&mut btree);
+            let mut bytes = btree.page_manager.read_page(leaf).unwrap();
+            let last = bytes.len() - 1;
+            bytes[last] ^= 0x40;
+            btree.page_manager.write_page(leaf, &bytes).unwrap();
+
+            let report = btree.scrub(ScrubOptions::default()).unwrap();
+
+            assert_eq!(report.damaged.len(), 1);
+            let DamagedPage { page_id, reason } = &report.damaged[0];
+            assert_eq!(*page_id, leaf);
+            assert!(reason.contains("Checksum mismatch"), "{}", reason);
+            assert_eq!(btree.page_manager.read_page_uncached(leaf).unwrap(), bytes);
+        }
+
+        #[test_log::test]
+        fn scrub_reports_pages_recording_the_wrong_id() {
+            let mut btree = create_scrub_btree();
+            let leaf = leftmost_leaf(&mut btree);
+            let mut page = btree.read_page(leaf).unwrap();
+            page.page_id = leaf + 1;
+            let bytes = page.serialize().unwrap();
+            btree.page_manager.write_page(leaf, &bytes).unwrap();
+
+            let report = btree.scrub(ScrubOptions::default()).unwrap();
+
+            assert_eq!(report.damaged.len(), 1);
+            assert_eq!(report.damaged[0].page_id, leaf);
+        }
+
+        #[test_log::test]
+        fn scrub_keeps_to_its_page_rate() {
+            let mut btree = create_scrub_btree();
+            let pages = btree.page_manager.page_count();
+            let options = ScrubOptions {
+                pages_per_second: Some(pages * 10),
+                batch_size: 2,
+                ..ScrubOptions::default()
+            };
+
+            let started = Instant::now();
+            let report = btree.scrub(options).unwrap();
+
+            assert!(report.is_clean());
+            assert!(started.elapsed() >= Duration::from_millis(90));
+        }
+
+        #[test_log::test]
+        fn scrubber_runs_alongside_writers() {
+            let tree = Arc::new(Mutex::new(create_scrub_btree()));
+            let scrubber = {
+                let tree = Arc::clone(&tree);
+                std::thread::spawn(move || {
+                    let options = ScrubOptions {
+                        pages_per_second: Some(2000),
+                        batch_size: 4,
+                        ..ScrubOptions::default()
+                    };
+                    Scrubber::new(options).run_shared(&tree).unwrap()
+                })
+            };
+
+            for i in 300..600 {
+                tree.lock()
+                    .unwrap()
+                    .insert(i, format!("value-{:04}", i))
+                    .unwrap();
+            }
+            let report = scrubber.join().unwrap();
+
+            assert!(report.is_clean(), "{:?}", report.damaged);
+            tree.lock().unwrap().verify().unwrap();
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Quarantine Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod quarantine {
+        use super::*;
+        use crate::scrub::ScrubOptions;
+
+        fn create_quarantine_btree(subtree_counts: bool) -> BTree<i64, String> {
+            let config = TreeConfig {
+                subtree_counts,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::in_memory(config).unwrap();
+            for i in 0..300 {
+                btree
+                    .insert((i * 37) % 300, format!("value-{:04}", i))
+                    .unwrap();
+            }
+            btree
+        }
+
+        // Flips a bit in the leftmost leaf, returning its id and the keys it held
+        fn damage_leftmost_leaf(btree: &mut BTree<i64, String>) -> (u64, Vec<i64>) {
+            let mut page = btree.read_page(btree.header.root_page_id).unwrap();
+            while page.node_type == NodeType::INTERNAL {
+                page = btree.read_page(page.pointers[0]).unwrap();
+            }
+            let keys = page.read_keys().unwrap();
+            let mut bytes = page.serialize().unwrap();
+            let last = bytes.len() - 1;
+            bytes[last] ^= 0x01;
+            btree.page_manager.write_page(page.page_id, &bytes).unwrap();
+            (page.page_id, keys)
+        }
+
+        #[test_log::test]
+        fn quarantine_makes_a_damaged_leaf_usable_again() {
+            for subtree_counts in [false, true] {
+                let mut btree = create_quarantine_btree(subtree_counts);
+                let (leaf, lost_keys) = damage_leftmost_leaf(&mut btree);
+                assert!(btree.search(lost_keys[0]).is_err());
+
+                let damaged: Vec<u64> = btree
+                    .scrub(ScrubOptions::default())
+                    .unwrap()
+                    .damaged
+                    .iter()
+                    .map(|page| page.page_id)
+                    .collect();
+                let report = btree.quarantine(&damaged).unwrap();
+
+                assert_eq!(report.pages.len(), 1);
+                assert_eq!(report.pages[0].page_id, leaf);
+                assert_eq!(report.pages[0].lower, None);
+                assert!(report.pages[0].upper.unwrap() > *lost_keys.last().unwrap());
+                assert_eq!(report.entries_lost, lost_keys.len() as u64);
+                assert_eq!(btree.len(), 300 - lost_keys.len() as u64);
+                btree.verify().unwrap();
+
+                assert!(matches!(
+                    btree.search(lost_keys[0]),
+                    Err(BTreeError::KeyNotFound(_))
+                ));
+                btree.insert(lost_keys[0], "again".to_string()).unwrap();
+                assert_eq!(btree.search(lost_keys[0]).unwrap(), "again");
+                assert!(btree.search(299).is_ok());
+                btree.verify().unwrap();
+            }
+        }
+
+        #[test_log::test]
+        fn recover_rebuilds_a_leaf_from_a_snapshot() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            for i in 0..300 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            let mut backup = btree.freeze().unwrap();
+            let expected: Vec<(i64, String)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+
+            let (leaf, lost_keys) = damage_leftmost_leaf(&mut btree);
+            let report = btree.recover(&[leaf], &mut backup).unwrap();
+
+            assert_eq!(report.entries_restored, lost_keys.len() as u64);
+            assert_eq!(report.entries_lost, 0);
+            btree.verify().unwrap();
+            let entries: Vec<(i64, String)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+            assert_eq!(entries, expected);
+        }
+
+        #[test_log::test]
+        fn quarantine_refuses_internal_nodes_and_skips_pages_outside_the_tree() {
+            let mut btree = create_quarantine_btree(false);
+            btree.flush().unwrap();
+            let root = btree.header.root_page_id;
+
+            assert!(matches!(
+                btree.quarantine(&[root]),
+                Err(BTreeError::Corrupted { page_id, .. }) if page_id == root
+            ));
+
+            let stats_page = btree.header.stats_page_id;
+            let report = btree.quarantine(&[stats_page]).unwrap();
+            assert!(report.pages.is_empty());
+            assert_eq!(report.unreachable, vec![stats_page]);
+            assert_eq!(btree.len(), 300);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Compression Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod compression {
+        use super::*;
+
+        fn json_value(i: i64) -> String {
+            format!(
+                "{{\"id\":{},\"name\":\"user_{}\",\"email\":\"user_{}@example.com\",\"active\":{}}}",
+                i,
+                i,
+                i,
+                i % 2 == 0
+            )
+        }
+
+        #[test_log::test]
+        fn train_dictionary_from_existing_values() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            for i in 0..500 {
+                btree.insert(i, json_value(i)).unwrap();
+            }
+
+            assert_eq!(btree.compression(), CompressionAlgorithm::None);
+            btree.train_compression_dictionary(500, 2048).unwrap();
+
+            assert!(btree.compression_dictionary().is_some());
+            assert!(btree.header.has_dictionary());
+            assert_eq!(btree.compression(), CompressionAlgorithm::ZstdDictionary);
+        }
+
+        #[test_log::test]
+        fn get_with_sees_decompressed_bytes() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+            for i in 0..500 {
+                btree.insert(i, json_value(i)).unwrap();
+            }
+            btree.train_compression_dictionary(500, 2048).unwrap();
+            btree.insert(10_000, json_value(10_000)).unwrap();
+
+            let value = btree
+                .get_with(10_000, |bytes| {
+                    bincode1::deserialize::<&str>(bytes).unwrap().to_string()
+                })
+                .unwrap();
+            assert_eq!(value, json_value(10_000));
+        }
+
+        #[test_log::test]
+        fn values_written_after_training_are_compressed() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            for i in 0..500 {
+                btree.insert(i, json_value(i)).unwrap();
+            }
+            btree.train_compression_dictionary(500, 2048).unwrap();
+
+            btree.insert(10_000, json_value(10_000)).unwrap();
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            let leaf_id = if root.node_type == NodeType::LEAF {
+                root.page_id
+            } else {
+                *root.pointers.last().unwrap()
+            };
+            let leaf = btree.read_page(leaf_id).unwrap();
+            assert!(leaf.slots.iter().any(|slot| slot.compressed));
+
+            assert_eq!(btree.search(10_000).unwrap(), json_value(10_000));
+            assert_eq!(btree.search(42).unwrap(), json_value(42));
+        }
+
+        #[test_log::test]
+        fn compression_reduces_page_count() {
+            let mut plain = create_temp_btree::<i64, String>(4096);
+            let mut compressed = create_temp_btree::<i64, String>(4096);
+
+            for i in 0..500 {
+                compressed.insert(i, json_value(i)).unwrap();
+            }
+            compressed.train_compression_dictionary(500, 2048).unwrap();
+            let pages_before = compressed.header.page_count;
+
+            for i in 1000..3000 {
+                plain.insert(i, json_value(i)).unwrap();
+                compressed.insert(i, json_value(i)).unwrap();
+            }
+
+            let compressed_growth = compressed.header.page_count - pages_before;
+            assert!(
+                compressed_growth < plain.header.page_count,
+                "compressed tree grew by {} pages, plain tree has {}",
+                compressed_growth,
+                plain.header.page_count
+            );
+        }
+
+        #[test_log::test]
+        fn dictionary_persists_across_reopen() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(512);
+
+            for i in 0..300 {
+                btree.insert(i, json_value(i)).unwrap();
+            }
+            btree.train_compression_dictionary(300, 1024).unwrap();
+            for i in 300..600 {
+                btree.insert(i, json_value(i)).unwrap();
+            }
+            let dictionary = btree.compression_dictionary().unwrap().to_vec();
+            drop(btree);
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut btree = BTree::<i64, String>::new(file, 512).unwrap();
+
+            assert_eq!(
+                btree.compression_dictionary().unwrap(),
+                dictionary.as_slice()
+            );
+            for i in 0..600 {
+                assert_eq!(btree.search(i).unwrap(), json_value(i));
+            }
+        }
+
+        #[test_log::test]
+        fn training_an_empty_tree_fails() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+
+            let result = btree.train_compression_dictionary(100, 1024);
+            assert!(matches!(result, Err(BTreeError::Compression(_))));
+            assert!(btree.compression_dictionary().is_none());
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Tiering Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod tiering {
+        use super::*;
+
+        fn collect_leaf_ids(btree: &mut BTree<i64, i64>, page_id: u64, leaves: &mut Vec<u64>) {
+            let page = btree.read_page(page_id).unwrap();
+            if page.node_type == NodeType::LEAF {
+                leaves.push(page_id);
+            }
+            for &ptr in &page.pointers {
+                collect_leaf_ids(btree, ptr, leaves);
+            }
+        }
+
+        fn leaf_ids(btree: &mut BTree<i64, i64>) -> Vec<u64> {
+            let mut leaves = Vec::new();
+            let root_page_id = btree.header.root_page_id;
+            collect_leaf_ids(btree, root_page_id, &mut leaves);
+            leaves
+        }
+
+        #[test_log::test]
+        fn rebalance_without_cold_tier_fails() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+
+            let result = btree.rebalance_tiers(&TieringPolicy::default());
+            assert!(matches!(
+                result,
+                Err(BTreeError::PageManager(
+                    PageManagerError::ColdTierNotAttached
+                ))
+            ));
+        }
+
+        #[test_log::test]
+        fn untouched_leaves_move_to_cold_tier() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree
+                .attach_cold_tier(tempfile::tempfile().unwrap())
+                .unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.page_manager.take_access_counts();
+
+            let report = btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+
+            assert!(report.demoted > 0);
+            assert!(
+                leaf_ids(&mut btree)
+                    .iter()
+                    .all(|&id| PageManager::is_cold(id))
+            );
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+
+        #[test_log::test]
+        fn frequently_read_leaves_stay_hot() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree
+                .attach_cold_tier(tempfile::tempfile().unwrap())
+                .unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.page_manager.take_access_counts();
+
+            for _ in 0..10 {
+                btree.search(0).unwrap();
+            }
+            btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+
+            let leaves = leaf_ids(&mut btree);
+            assert!(!PageManager::is_cold(leaves[0]));
+            assert!(leaves[1..].iter().all(|&id| PageManager::is_cold(id)));
+        }
+
+        #[test_log::test]
+        fn hot_cold_leaves_are_promoted_back() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree
+                .attach_cold_tier(tempfile::tempfile().unwrap())
+                .unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.page_manager.take_access_counts();
+            btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+
+            for _ in 0..10 {
+                btree.search(199).unwrap();
+            }
+            let report = btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+
+            assert_eq!(report.promoted, 1);
+            assert!(!PageManager::is_cold(*leaf_ids(&mut btree).last().unwrap()));
+            assert_eq!(btree.search(199).unwrap(), 199);
+        }
+
+        #[test_log::test]
+        fn cold_pages_freed_by_promotion_are_reused() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree
+                .attach_cold_tier(tempfile::tempfile().unwrap())
+                .unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.page_manager.take_access_counts();
+            btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+            let cold_pages = btree.page_manager.cold_page_count();
+
+            for _ in 0..10 {
+                btree.search(199).unwrap();
+            }
+            let report = btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+            assert_eq!(report.promoted, 1);
+            let report = btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+            assert_eq!(report.demoted, 1);
+
+            // Moved back into the page it was promoted from
+            assert!(PageManager::is_cold(*leaf_ids(&mut btree).last().unwrap()));
+            assert_eq!(btree.page_manager.cold_page_count(), cold_pages);
+            btree.verify().unwrap();
+        }
+
+        #[test_log::test]
+        fn inserts_into_cold_leaves_work() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            btree
+                .attach_cold_tier(tempfile::tempfile().unwrap())
+                .unwrap();
+
+            for i in (0..400).step_by(2) {
+                btree.insert(i, i).unwrap();
+            }
+            btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+
+            for i in (1..400).step_by(2) {
+                btree.insert(i, i).unwrap();
+            }
+            for i in 0..400 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+
+        #[test_log::test]
+        fn cold_tier_must_be_reattached_on_reopen() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            let cold = NamedTempFile::new().unwrap();
+            btree.attach_cold_tier(cold.reopen().unwrap()).unwrap();
+
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.page_manager.take_access_counts();
+            btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+            drop(btree);
+
+            let open = || {
+                std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+            };
+
+            let mut without_tier = BTree::<i64, i64>::new(open(), 256).unwrap();
+            assert!(matches!(
+                without_tier.search(0),
+                Err(BTreeError::PageManager(
+                    PageManagerError::ColdTierNotAttached
+                ))
+            ));
+            drop(without_tier);
+
+            let mut btree = BTree::<i64, i64>::new(open(), 256).unwrap();
+            btree.attach_cold_tier(cold.reopen().unwrap()).unwrap();
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Event Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod events {
+        use super::*;
+        use crate::events::{CompactEvent, FlushEvent, SplitEvent};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct RecordingObserver {
+            splits: Mutex<Vec<SplitEvent>>,
+            compactions: Mutex<Vec<CompactEvent>>,
+            flushes: Mutex<Vec<FlushEvent>>,
+        }
+
+        impl TreeObserver for RecordingObserver {
+            fn on_split(&self, event: &SplitEvent) {
+                self.splits.lock().unwrap().push(event.clone());
+            }
+
+            fn on_compact(&self, event: &CompactEvent) {
+                self.compactions.lock().unwrap().push(event.clone());
+            }
+
+            fn on_flush(&self, event: &FlushEvent) {
+                self.flushes.lock().unwrap().push(event.clone());
+            }
+        }
+
+        #[test_log::test]
+        fn no_events_without_restructuring() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            btree.insert(1, 1).unwrap();
+
+            assert!(observer.splits.lock().unwrap().is_empty());
+            assert!(observer.compactions.lock().unwrap().is_empty());
+        }
+
+        #[test_log::test]
+        fn splits_are_reported_with_page_ids() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            let splits = observer.splits.lock().unwrap();
+            assert!(!splits.is_empty());
+            assert_eq!(splits[0].page_id, 0);
+            assert_eq!(splits[0].node_type, NodeType::LEAF);
+            assert!(splits.iter().all(|e| e.page_id != e.new_page_id));
+            assert!(
+                splits
+                    .iter()
+                    .all(|e| e.left_entries > 0 && e.right_entries > 0)
+            );
+        }
+
+        #[test_log::test]
+        fn internal_splits_are_reported() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..500 {
+                btree.insert(i, i).unwrap();
+            }
+
+            let splits = observer.splits.lock().unwrap();
+            assert!(splits.iter().any(|e| e.node_type == NodeType::INTERNAL));
+        }
+
+        #[test_log::test]
+        fn fragmented_page_is_compacted_instead_of_split() {
+            let mut btree = create_temp_btree::<i64, String>(512);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..4 {
+                btree.insert(i, "x".repeat(80)).unwrap();
+            }
+            // Shrinking values leaves a hole behind each entry
+            for i in 0..4 {
+                btree.insert(i, "y".to_string()).unwrap();
+            }
+            // Only fits once the holes are merged into contiguous space
+            btree.insert(4, "z".repeat(150)).unwrap();
+
+            let compactions = observer.compactions.lock().unwrap();
+            assert_eq!(compactions.len(), 1);
+            assert_eq!(compactions[0].page_id, btree.header.root_page_id);
+            assert_eq!(compactions[0].holes_reclaimed, 4);
+            assert_eq!(compactions[0].bytes_reclaimed, 4 * 79);
+            assert!(observer.splits.lock().unwrap().is_empty());
+
+            for k in 0..4 {
+                assert_eq!(btree.search(k).unwrap(), "y");
+            }
+            assert_eq!(btree.search(4).unwrap(), "z".repeat(150));
+        }
+
+        #[test_log::test]
+        fn fragmented_pages_are_compacted_on_request() {
+            let mut btree = create_temp_btree::<i64, String>(512);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..4 {
+                btree.insert(i, "x".repeat(80)).unwrap();
+            }
+            for i in 0..4 {
+                btree.insert(i, "y".to_string()).unwrap();
+            }
+
+            assert_eq!(btree.compact_fragmented_pages().unwrap(), 1);
+            assert_eq!(observer.compactions.lock().unwrap().len(), 1);
+            // Nothing left to reclaim
+            assert_eq!(btree.compact_fragmented_pages().unwrap(), 0);
+            for k in 0..4 {
+                assert_eq!(btree.search(k).unwrap(), "y");
+            }
+        }
+
+        #[test_log::test]
+        fn flush_is_reported() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            let observer = Arc::new(RecordingObserver::default());
+            btree.register_observer(observer.clone());
+
+            btree.insert(1, 1).unwrap();
+            btree.flush().unwrap();
+
+            // The root and the stats page written by the first flush
+            let flushes = observer.flushes.lock().unwrap();
+            assert_eq!(
+                *flushes,
+                vec![FlushEvent {
+                    page_count: 2,
+                    page_size: 4096
+                }]
+            );
+        }
+
+        #[test_log::test]
+        fn every_registered_observer_is_notified() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            let first = Arc::new(RecordingObserver::default());
+            let second = Arc::new(RecordingObserver::default());
+            btree.register_observer(first.clone());
+            btree.register_observer(second.clone());
+
+            btree.flush().unwrap();
+
+            assert_eq!(first.flushes.lock().unwrap().len(), 1);
+            assert_eq!(second.flushes.lock().unwrap().len(), 1);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Configuration & Bulk Load Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod config {
+        use super::*;
+        use crate::config::{ChecksumAlgorithm, WriteMode};
+        use crate::events::{CompactEvent, SplitEvent, TreeObserver};
+        use crate::header::HeaderError;
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct CountingObserver {
+            splits: Mutex<usize>,
+            compactions: Mutex<usize>,
+        }
+
+        impl TreeObserver for CountingObserver {
+            fn on_split(&self, _event: &SplitEvent) {
+                *self.splits.lock().unwrap() += 1;
+            }
+
+            fn on_compact(&self, _event: &CompactEvent) {
+                *self.compactions.lock().unwrap() += 1;
+            }
+        }
+
+        fn create_btree_with_config<K, V>(config: TreeConfig) -> BTree<K, V>
+        where
+            K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+            V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        {
+            let file = NamedTempFile::new().unwrap();
+            BTree::with_config(file.reopen().unwrap(), config).unwrap()
+        }
+
+        fn fragment_root(btree: &mut BTree<i64, String>) {
+            for i in 0..4 {
+                btree.insert(i, "x".repeat(80)).unwrap();
+            }
+            for i in 0..4 {
+                btree.insert(i, "y".to_string()).unwrap();
+            }
+            btree.insert(4, "z".repeat(150)).unwrap();
+        }
+
+        #[test_log::test]
+        fn invalid_config_is_rejected() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                leaf_fill_factor: 0,
+                ..Default::default()
+            };
+
+            let result = BTree::<i64, i64>::with_config(file.reopen().unwrap(), config);
+            assert!(matches!(result, Err(BTreeError::Config(_))));
+        }
+
+        #[test_log::test]
+        fn config_persists_across_reopen() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                page_size: 4096,
+                leaf_fill_factor: 60,
+                internal_fill_factor: 75,
+                split_threshold: 50,
+                ..Default::default()
+            };
+
+            {
+                let mut btree =
+                    BTree::<i64, i64>::with_config(file.reopen().unwrap(), config).unwrap();
+                btree.insert(1, 1).unwrap();
+            }
+
+            let btree = BTree::<i64, i64>::new(file.reopen().unwrap(), 4096).unwrap();
+            assert_eq!(btree.config(), config);
+        }
+
+        #[test_log::test]
+        fn set_config_is_persisted() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(4096);
+            let config = TreeConfig {
+                split_threshold: 100,
+                ..btree.config()
+            };
+            btree.set_config(config).unwrap();
+            drop(btree);
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let btree = BTree::<i64, i64>::new(file, 4096).unwrap();
+            assert_eq!(btree.config().split_threshold, 100);
+        }
+
+        #[test_log::test]
+        fn reopening_with_defaults_restores_the_stored_config() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                page_size: 512,
+                leaf_fill_factor: 70,
+                duplicate_policy: DuplicatePolicy::KeepBoth,
+                delete_strategy: DeleteStrategy::Tombstone,
+                comparator_id: 3,
+                checksum: ChecksumAlgorithm::None,
+                ..Default::default()
+            };
+            {
+                let mut btree =
+                    BTree::<i64, i64>::with_config(file.reopen().unwrap(), config).unwrap();
+                btree.insert(1, 1).unwrap();
+                btree.insert(1, 2).unwrap();
+            }
+
+            let mut btree = BTree::<i64, i64>::new(file.reopen().unwrap(), 512).unwrap();
+            assert_eq!(btree.config(), config);
+            assert_eq!(btree.get_all(1).unwrap().len(), 2);
+        }
+
+        #[test_log::test]
+        fn tree_without_checksums_keeps_writing_pages_without_them() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                checksum: ChecksumAlgorithm::None,
+                ..TreeConfig::with_page_size(256)
+            };
+            {
+                let mut btree =
+                    BTree::<i64, String>::with_config(file.reopen().unwrap(), config).unwrap();
+                for i in 0..100 {
+                    btree.insert(i, format!("value-{:04}", i)).unwrap();
+                }
+            }
+
+            let mut btree = BTree::<i64, String>::new(file.reopen().unwrap(), 256).unwrap();
+            for i in 100..200 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            let report = btree.scrub(ScrubOptions::default()).unwrap();
+            assert!(report.is_clean(), "{:?}", report.damaged);
+            assert_eq!(report.pages_checksummed, 0);
+            assert!(report.pages_without_checksum > 1);
+        }
+
+        #[test_log::test]
+        fn opening_with_another_comparator_is_rejected() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                comparator_id: 2,
+                ..TreeConfig::with_page_size(256)
+            };
+            BTree::<i64, i64>::with_config(file.reopen().unwrap(), config).unwrap();
+
+            let other = TreeConfig {
+                comparator_id: 5,
+                ..config
+            };
+            assert!(matches!(
+                BTree::<i64, i64>::with_config(file.reopen().unwrap(), other),
+                Err(BTreeError::Header(HeaderError::ComparatorMismatch {
+                    expected: 5,
+                    got: 2
+                }))
+            ));
+            // Leaving the comparator unspecified opens the tree with the stored one
+            let btree = BTree::<i64, i64>::new(file.reopen().unwrap(), 256).unwrap();
+            assert_eq!(btree.config().comparator_id, 2);
+        }
+
+        #[test_log::test]
+        fn cached_tree_matches_uncached() {
+            let mut cached = create_btree_with_config::<i64, String>(TreeConfig {
+                page_size: 256,
+                cache_size: 4,
+                ..Default::default()
+            });
+            let mut uncached =
+                create_btree_with_config::<i64, String>(TreeConfig::with_page_size(256));
+
+            for i in 0..200 {
+                let value = format!("value-{}", i);
+                cached.insert(i, value.clone()).unwrap();
+                uncached.insert(i, value).unwrap();
+            }
+            for i in (0..200).step_by(3) {
+                cached.delete(i).unwrap();
+                uncached.delete(i).unwrap();
+            }
+            for i in 0..200 {
+                assert_eq!(cached.search(i).ok(), uncached.search(i).ok());
+            }
+
+            assert_eq!(cached.config().cache_size, 4);
+            assert!(cached.page_manager.cache().hits() > 0);
+            assert!(cached.page_manager.cache().len() <= 4);
+        }
+
+        #[test_log::test]
+        fn pinned_pages_stay_cached_through_scans() {
+            let mut btree = create_btree_with_config::<i64, String>(TreeConfig {
+                page_size: 256,
+                cache_size: 4,
+                ..Default::default()
+            });
+            for i in 0..300 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            let root = btree.root_page_id();
+            let first_leaf = btree.read_page(root).unwrap().pointers[0];
+            btree.pin_page(first_leaf);
+            btree.read_page(first_leaf).unwrap();
+
+            assert_eq!(btree.iter().unwrap().count(), 300);
+            btree.delete(150).unwrap();
+            let cache = btree.page_manager.cache();
+            assert!(cache.contains(first_leaf));
+            // Pages pinned by the delete are let go once it is done
+            assert_eq!(cache.pinned(), 1);
+            assert!(cache.len() <= 4);
+
+            btree.unpin_page(first_leaf);
+            assert_eq!(btree.page_manager.cache().pinned(), 0);
+        }
+
+        #[test_log::test]
+        fn write_back_holds_pages_until_flushed_or_dropped() {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                write_mode: WriteMode::WriteBack,
+                ..TreeConfig::with_page_size(256)
+            };
+            let stored = || std::fs::read(file.path()).unwrap();
+            let mut btree =
+                BTree::<i64, String>::with_config(file.reopen().unwrap(), config).unwrap();
+            btree.flush().unwrap();
+            let flushed = stored();
+            for i in 0..200 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            // Reads see the held pages while storage still has the tree as of the flush
+            assert_eq!(btree.search(150).unwrap(), "value-150");
+            assert_eq!(stored()[..Header::SIZE], flushed[..Header::SIZE]);
+            assert!(btree.memory_usage().page_cache > 200 * 8);
+
+            btree.flush().unwrap();
+            assert_eq!(btree.page_manager.held_memory_usage(), 0);
+            btree.insert(200, "value-200".to_string()).unwrap();
+            assert_eq!(btree.config(), config);
+            drop(btree);
+
+            let mut btree = BTree::<i64, String>::new(file.reopen().unwrap(), 256).unwrap();
+            assert_eq!(btree.config().write_mode, WriteMode::WriteThrough);
+            assert_eq!(btree.len(), 201);
+            assert_eq!(btree.search(200).unwrap(), "value-200");
+        }
+
+        #[test_log::test]
+        fn memory_usage_counts_cache_and_dictionary() {
+            let mut btree = create_btree_with_config::<i64, String>(TreeConfig {
+                page_size: 256,
+                cache_size: 8,
+                ..Default::default()
+            });
+            assert_eq!(btree.memory_usage().compression, 0);
+
+            for i in 0..200 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            let usage = btree.memory_usage();
+            assert!(usage.page_cache >= 8 * 256);
+            assert_eq!(usage.buffer_pool, 0);
+
+            btree.set_compression_dictionary(vec![7u8; 1000]).unwrap();
+            let usage = btree.memory_usage();
+            assert!(usage.compression >= 1000);
+            assert_eq!(
+                usage.total(),
+                usage.page_cache + usage.compression + usage.auxiliary
+            );
+
+            let pool = Arc::new(crate::buffer_pool::PagePool::new(4));
+            btree.set_buffer_pool(pool.clone());
+            for i in 200..400 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            assert!(!pool.is_empty());
+            let usage = btree.memory_usage();
+            assert_eq!(usage.buffer_pool, pool.retained_bytes());
+            assert!(usage.buffer_pool >= pool.len() * 256);
+
+            let range = btree.range(10..20).unwrap();
+            assert!(range.memory_usage() >= 256);
+        }
+
+        #[test_log::test]
+        fn low_split_threshold_splits_fragmented_page() {
+            let mut btree = create_btree_with_config::<i64, String>(TreeConfig {
+                split_threshold: 1,
+                ..TreeConfig::with_page_size(512)
+            });
+            let observer = Arc::new(CountingObserver::default());
+            btree.register_observer(observer.clone());
+
+            fragment_root(&mut btree);
+
+            assert_eq!(*observer.compactions.lock().unwrap(), 0);
+            assert_eq!(*observer.splits.lock().unwrap(), 1);
+            assert_eq!(btree.search(4).unwrap(), "z".repeat(150));
+        }
+
+        #[test_log::test]
+        fn high_split_threshold_compacts_fragmented_page() {
+            let mut btree = create_btree_with_config::<i64, String>(TreeConfig {
+                split_threshold: 100,
+                ..TreeConfig::with_page_size(512)
+            });
+            let observer = Arc::new(CountingObserver::default());
+            btree.register_observer(observer.clone());
+
+            fragment_root(&mut btree);
+
+            assert_eq!(*observer.compactions.lock().unwrap(), 1);
+            assert_eq!(*observer.splits.lock().unwrap(), 0);
+        }
+
+        #[test_log::test]
+        fn bulk_load_makes_every_key_searchable() {
+            let mut btree = create_temp_btree::<i64, String>(512);
+
+            let loaded = btree
+                .bulk_load((0..2000).map(|i| (i, format!("value_{}", i))))
+                .unwrap();
+
+            assert_eq!(loaded, 2000);
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::INTERNAL);
+            for i in 0..2000 {
+                assert_eq!(btree.search(i).unwrap(), format!("value_{}", i));
+            }
+        }
+
+        #[test_log::test]
+        fn lower_fill_factor_uses_more_pages() {
+            let entries = || (0..1000i64).map(|i| (i, i));
+
+            let mut dense = create_temp_btree::<i64, i64>(512);
+            dense.bulk_load(entries()).unwrap();
+
+            let mut sparse = create_btree_with_config::<i64, i64>(TreeConfig {
+                leaf_fill_factor: 50,
+                internal_fill_factor: 50,
+                ..TreeConfig::with_page_size(512)
+            });
+            sparse.bulk_load(entries()).unwrap();
+
+            assert!(sparse.header.page_count > dense.header.page_count);
+            for i in 0..1000 {
+                assert_eq!(sparse.search(i).unwrap(), i);
+            }
+        }
+
+        #[test_log::test]
+        fn bulk_load_rejects_unsorted_input() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+
+            let result = btree.bulk_load(vec![(1, 1), (3, 3), (2, 2)]);
+            match result {
+                Err(BTreeError::UnsortedBulkLoad(key)) => assert_eq!(key, "2"),
+                other => panic!("Expected UnsortedBulkLoad, got {:?}", other),
+            }
+        }
+
+        #[test_log::test]
+        fn bulk_load_rejects_non_empty_tree() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            btree.insert(1, 1).unwrap();
+
+            let result = btree.bulk_load(vec![(2, 2)]);
+            assert!(matches!(result, Err(BTreeError::TreeNotEmpty)));
+        }
+
+        #[test_log::test]
+        fn bulk_load_frees_unused_reserved_pages() {
+            let mut btree = create_temp_btree::<i64, i64>(512);
+            btree.bulk_load((0..1000).map(|i| (i, i))).unwrap();
+
+            // The root leaf, then whole extents
+            let page_count = btree.page_manager.page_count();
+            assert_eq!((page_count - 1) % BTree::<i64, i64>::BULK_LOAD_EXTENT, 0);
+
+            let mut free = 0;
+            for page_id in 0..page_count {
+                if btree.page_type(page_id).unwrap() == NodeType::FREE {
+                    free += 1;
+                }
+            }
+            assert!(free > 0);
+            // The header counts every allocated page, free or not
+            assert_eq!(btree.header.page_count, page_count);
+        }
+
+        #[test_log::test]
+        fn insert_after_bulk_load() {
+            let mut btree = create_temp_btree::<i64, i64>(512);
+            btree.bulk_load((0..500).map(|i| (i * 2, i))).unwrap();
+
+            for i in 0..500 {
+                btree.insert(i * 2 + 1, -i).unwrap();
+            }
+
+            for i in 0..500 {
+                assert_eq!(btree.search(i * 2).unwrap(), i);
+                assert_eq!(btree.search(i * 2 + 1).unwrap(), -i);
+            }
+        }
+
+        #[test_log::test]
+        fn interrupted_import_resumes_after_the_last_batch() {
+            use crate::import::{ImportOptions, ImportProgress};
+
+            let file = NamedTempFile::new().unwrap();
+            let entries = |n: i64| (0..n).map(|i| (i, format!("value_{}", i)));
+            let options = ImportOptions {
+                batch_size: 1000,
+                total_entries: Some(5000),
+            };
+            let mut reports: Vec<ImportProgress> = Vec::new();
+            {
+                // The source runs dry part way, as if the process had stopped there
+                let mut btree = BTree::<i64, String>::new(file.reopen().unwrap(), 512).unwrap();
+                let report = btree
+                    .import(entries(3500), options, |p| reports.push(p.clone()))
+                    .unwrap();
+                assert_eq!(report.entries_loaded, 3500);
+                assert!(report.bytes_loaded > 3500 * 8);
+            }
+            let loaded: Vec<u64> = reports.iter().map(|p| p.entries_loaded).collect();
+            assert_eq!(loaded, [1000, 2000, 3000, 3500]);
+            assert!(reports.iter().all(|p| p.eta.is_some()));
+
+            let mut btree = BTree::<i64, String>::new(file.reopen().unwrap(), 512).unwrap();
+            let report = btree.import(entries(5000), options, |_| {}).unwrap();
+            assert_eq!(report.entries_skipped, 3500);
+            assert_eq!(report.entries_loaded, 1500);
+            assert_eq!(report.eta, Some(Duration::ZERO));
+
+            btree.verify().unwrap();
+            assert_eq!(btree.len(), 5000);
+            for i in (0..5000).step_by(7) {
+                assert_eq!(btree.search(i).unwrap(), format!("value_{}", i));
+            }
+            assert!(matches!(
+                btree.import(
+                    [(6000, String::new()), (5500, String::new())],
+                    options,
+                    |_| {}
+                ),
+                Err(BTreeError::UnsortedBulkLoad(_))
+            ));
+        }
+
+        #[test_log::test]
+        fn rebuild_packs_the_tree_into_a_new_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let dest = dir.path().join("tree.db.rebuild");
+            let mut btree = BTree::<i64, String>::open(&path, TreeConfig::default()).unwrap();
+            for i in 0..3000 {
+                btree.insert(i, format!("value_{}", i)).unwrap();
+            }
+            for i in (0..3000).filter(|i| i % 4 != 0) {
+                btree.delete(i).unwrap();
+            }
+            btree.flush().unwrap();
+            let size_before = std::fs::metadata(&path).unwrap().len();
+            let lsn = btree.commit_lsn();
+
+            btree.rebuild(&dest).unwrap();
+            assert!(!dest.exists());
+            assert!(std::fs::metadata(&path).unwrap().len() < size_before / 2);
+            assert_eq!(btree.len(), 750);
+            assert_eq!(btree.commit_lsn(), lsn);
+            btree.verify().unwrap();
+            btree.insert(1, "again".to_string()).unwrap();
+            drop(btree);
+
+            let mut btree = BTree::<i64, String>::open(&path, TreeConfig::default()).unwrap();
+            assert_eq!(btree.len(), 751);
+            assert_eq!(btree.search(1).unwrap(), "again");
+            for i in (0..3000).step_by(4) {
+                assert_eq!(btree.search(i).unwrap(), format!("value_{}", i));
+            }
+
+            let mut scratch = create_temp_btree::<i64, i64>(512);
+            assert!(matches!(
+                scratch.rebuild(&dest),
+                Err(BTreeError::Unsupported(_))
+            ));
+        }
+
+        #[test_log::test]
+        fn rebuild_replaces_the_file_the_tree_has_open() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let mut btree = BTree::<i64, String>::open(&path, TreeConfig::default()).unwrap();
+            for i in 0..1000 {
+                btree.insert(i, format!("value_{}", i)).unwrap();
+            }
+
+            // Each rebuild renames over the file the previous one swapped in, which Windows
+            // only allows if the tree opened it with delete sharing
+            for round in 0..2 {
+                for i in (0..1000).filter(|i| i % 3 == round) {
+                    btree.delete(i).unwrap();
+                }
+                btree.rebuild(dir.path().join("tree.db.rebuild")).unwrap();
+                btree.verify().unwrap();
+            }
+            assert_eq!(btree.len(), 333);
+            assert_eq!(btree.search(998).unwrap(), "value_998");
+
+            // The file swapped in is locked like the one it replaced
+            let page_manager = PageManager::open(&path, 4096, Header::SIZE as u64);
+            assert!(matches!(page_manager, Err(PageManagerError::Locked)));
+        }
+
+        // Stores strings as their bare UTF-8 bytes
+        struct Utf8Codec;
+
+        impl ValueCodec<String> for Utf8Codec {
+            fn id(&self) -> u8 {
+                7
+            }
+
+            fn encode(&self, value: &String) -> Result<Vec<u8>, CodecError> {
+                Ok(value.as_bytes().to_vec())
+            }
+
+            fn decode(&self, bytes: &[u8]) -> Result<String, CodecError> {
+                String::from_utf8(bytes.to_vec()).map_err(|e| CodecError::Custom(e.to_string()))
+            }
+        }
+
+        #[test_log::test]
+        fn encoding_and_value_codec_are_kept_in_the_header() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let config = TreeConfig {
+                encoding: Encoding::BincodeVarint,
+                ..TreeConfig::with_page_size(512)
+            };
+            let mut btree = BTree::<u64, String>::open(&path, config).unwrap();
+            btree.set_value_codec(Arc::new(Utf8Codec)).unwrap();
+            for i in 0..500 {
+                btree.insert(i, format!("value_{}", i)).unwrap();
+            }
+            let len = btree.get_with(3, |bytes| bytes.len()).unwrap();
+            assert_eq!(len, "value_3".len());
+            drop(btree);
+
+            // The encoding comes from the header, whatever the configuration says
+            let mut btree =
+                BTree::<u64, String>::open(&path, TreeConfig::with_page_size(512)).unwrap();
+            assert_eq!(btree.encoding(), Encoding::BincodeVarint);
+            assert!(matches!(
+                btree.search(3),
+                Err(BTreeError::Codec(CodecError::NotAttached(7)))
+            ));
+            btree.set_value_codec(Arc::new(Utf8Codec)).unwrap();
+            assert_eq!(btree.search(3).unwrap(), "value_3");
+            btree.verify().unwrap();
+
+            let mut other = create_temp_btree::<u64, String>(512);
+            other.insert(1, "one".to_string()).unwrap();
+            assert!(matches!(
+                other.set_value_codec(Arc::new(Utf8Codec)),
+                Err(BTreeError::TreeNotEmpty)
+            ));
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Duplicate Key Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod duplicates {
+        use super::*;
+
+        fn create_btree_with_policy<V>(page_size: u64, policy: DuplicatePolicy) -> BTree<i64, V>
+        where
+            V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        {
+            BTree::in_memory(TreeConfig {
+                duplicate_policy: policy,
+                ..TreeConfig::with_page_size(page_size)
+            })
+            .unwrap()
+        }
+
+        #[test_log::test]
+        fn error_policy_rejects_existing_key() {
+            let mut btree = create_btree_with_policy::<i64>(256, DuplicatePolicy::Error);
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+
+            for i in 0..100 {
+                assert!(matches!(
+                    btree.insert(i, -i),
+                    Err(BTreeError::DuplicateKey(key)) if key == i.to_string()
+                ));
+                assert_eq!(btree.search(i).unwrap(), i);
+            }
+            assert_eq!(btree.len(), 100);
+
+            // A deleted key may be inserted again
+            btree.delete(5).unwrap();
+            btree.insert(5, 50).unwrap();
+            assert_eq!(btree.search(5).unwrap(), 50);
+        }
+
+        #[test_log::test]
+        fn keep_both_stores_every_value_in_insertion_order() {
+            let mut btree = create_btree_with_policy::<String>(256, DuplicatePolicy::KeepBoth);
+            for round in 0..5 {
+                for i in 0..60 {
+                    btree.insert(i, format!("{}-{}", i, round)).unwrap();
+                }
+            }
+
+            assert_eq!(btree.len(), 300);
+            for i in 0..60 {
+                let expected: Vec<String> =
+                    (0..5).map(|round| format!("{}-{}", i, round)).collect();
+                assert_eq!(btree.get_all(i).unwrap(), expected);
+            }
+            let keys: Vec<i64> = btree.iter().unwrap().map(|e| e.unwrap().0).collect();
+            assert_eq!(keys.len(), 300);
+            assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+        }
+
+        #[test_log::test]
+        fn keep_sorted_orders_values_and_deletes_them_by_range() {
+            let mut btree = create_btree_with_policy::<i64>(256, DuplicatePolicy::KeepSorted);
+            btree.insert(1, 10).unwrap();
+            assert!(matches!(
+                btree.insert(1, 5),
+                Err(BTreeError::NoDuplicateOrder)
+            ));
+            btree.set_duplicate_order(|a: &i64, b: &i64| a.cmp(b));
+
+            // Values arrive out of order, in runs spanning many pages
+            for i in 0..400 {
+                btree.insert(i % 4, (i * 37) % 400).unwrap();
+            }
+            btree.verify().unwrap();
+            for key in 0..4 {
+                let values = btree.get_all(key).unwrap();
+                assert!(values.windows(2).all(|w| w[0] <= w[1]), "{:?}", values);
+            }
+
+            let before = btree.get_all(2).unwrap();
+            let deleted = btree.delete_values(2, 100..300).unwrap();
+            let expected: Vec<i64> = before
+                .iter()
+                .copied()
+                .filter(|v| !(100..300).contains(v))
+                .collect();
+            assert_eq!(deleted, (before.len() - expected.len()) as u64);
+            assert_eq!(btree.get_all(2).unwrap(), expected);
+            assert_eq!(btree.len(), 401 - deleted);
+            btree.verify().unwrap();
+
+            let mut unsorted = create_btree_with_policy::<i64>(256, DuplicatePolicy::KeepBoth);
+            assert!(matches!(
+                unsorted.delete_values(1, ..),
+                Err(BTreeError::Unsupported(_))
+            ));
+        }
+
+        #[test_log::test]
+        fn keep_both_handles_runs_spanning_many_pages() {
+            let mut btree = create_btree_with_policy::<i64>(256, DuplicatePolicy::KeepBoth);
+            for i in 0..400 {
+                btree.insert(i % 4, i).unwrap();
+            }
+
+            for key in 0..4 {
+                let expected: Vec<i64> = (0..100).map(|n| n * 4 + key).collect();
+                assert_eq!(btree.get_all(key).unwrap(), expected);
+            }
+            for _ in 0..100 {
+                btree.delete(2).unwrap();
+            }
+            assert!(btree.get_all(2).unwrap().is_empty());
+            assert_eq!(btree.get_all(3).unwrap().len(), 100);
+        }
+
+        #[test_log::test]
+        fn keep_both_delete_removes_exactly_the_entry_returned() {
+            let mut btree = create_btree_with_policy::<i64>(256, DuplicatePolicy::KeepBoth);
+            for value in 0..30 {
+                btree.insert(0, value).unwrap();
+            }
+
+            // Deleting a duplicate held in an internal node moves another duplicate up in its
+            // place, which must not cost a second one
+            let deleted: Vec<i64> = (0..15).map(|_| btree.delete(0).unwrap()).collect();
+            let expected: Vec<i64> = (0..30).filter(|v| !deleted.contains(v)).collect();
+            assert_eq!(expected.len(), 15, "{:?}", deleted);
+            assert_eq!(btree.get_all(0).unwrap(), expected);
+            btree.verify().unwrap();
+        }
+
+        #[test_log::test]
+        fn keep_both_delete_removes_one_entry() {
+            for strategy in [DeleteStrategy::Immediate, DeleteStrategy::Tombstone] {
+                let mut btree = BTree::<i64, i64>::in_memory(TreeConfig {
+                    duplicate_policy: DuplicatePolicy::KeepBoth,
+                    delete_strategy: strategy,
+                    ..TreeConfig::with_page_size(256)
+                })
+                .unwrap();
+                for i in 0..50 {
+                    btree.insert(i, i).unwrap();
+                    btree.insert(i, -i).unwrap();
+                }
+
+                for i in 0..50 {
+                    btree.delete(i).unwrap();
+                    assert_eq!(btree.get_all(i).unwrap().len(), 1);
+                    btree.delete(i).unwrap();
+                    assert!(btree.get_all(i).unwrap().is_empty());
+                    assert!(matches!(btree.delete(i), Err(BTreeError::KeyNotFound(_))));
+                }
+                assert!(btree.is_empty());
+            }
+        }
+
+        #[test_log::test]
+        fn resolve_policy_combines_values() {
+            let mut btree = create_btree_with_policy::<i64>(256, DuplicatePolicy::Resolve);
+            btree.insert(1, 10).unwrap();
+            assert!(matches!(
+                btree.insert(1, 5),
+                Err(BTreeError::NoDuplicateResolver)
+            ));
+
+            btree.set_duplicate_resolver(|_, stored, new| stored + new);
+            for i in 0..200 {
+                btree.insert(i % 20, 1).unwrap();
+            }
+
+            assert_eq!(btree.search(1).unwrap(), 20);
+            assert_eq!(btree.search(2).unwrap(), 10);
+            assert_eq!(btree.len(), 20);
+        }
+
+        #[test_log::test]
+        fn policy_is_persisted() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(4096);
+            btree
+                .set_config(TreeConfig {
+                    duplicate_policy: DuplicatePolicy::Error,
+                    ..btree.config()
+                })
+                .unwrap();
+            btree.insert(1, 1).unwrap();
+            drop(btree);
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut btree = BTree::<i64, i64>::new(file, 4096).unwrap();
+            assert_eq!(btree.config().duplicate_policy, DuplicatePolicy::Error);
+            assert!(matches!(
+                btree.insert(1, 2),
+                Err(BTreeError::DuplicateKey(_))
+            ));
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Subtree Count Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod subtree_counts {
+        use super::*;
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::{SeedableRng, rng};
+
+        fn create_counted_btree(config: TreeConfig) -> BTree<i64, String> {
+            BTree::in_memory(TreeConfig {
+                subtree_counts: true,
+                ..config
+            })
+            .unwrap()
+        }
+
+        // Checks the counts kept for every child against the subtree itself, returning the
+        // number of live entries beneath `page_id`
+        fn check_counts(btree: &mut BTree<i64, String>, page_id: u64) -> u64 {
+            let page = btree.read_page(page_id).unwrap();
+            if page.node_type == NodeType::LEAF {
+                return page.subtree_entries();
+            }
+            assert!(page.is_counted());
+            assert_eq!(page.counts.len(), page.pointers.len());
+            for (child, count) in page.pointers.iter().zip(&page.counts) {
+                assert_eq!(check_counts(btree, *child), *count, "child {}", child);
+            }
+            page.subtree_entries()
+        }
+
+        fn assert_counts_match(btree: &mut BTree<i64, String>) {
+            let root = btree.header.root_page_id;
+            assert_eq!(check_counts(btree, root), btree.len());
+        }
+
+        fn insert_and_delete_randomly(strategy: DeleteStrategy) {
+            let mut btree = create_counted_btree(TreeConfig {
+                delete_strategy: strategy,
+                ..TreeConfig::with_page_size(256)
+            });
+            let mut keys: Vec<i64> = (0..600).collect();
+            keys.shuffle(&mut rng());
+            for &i in &keys {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            assert_eq!(
+                btree.page_type(btree.header.root_page_id).unwrap(),
+                NodeType::INTERNAL
+            );
+            assert_counts_match(&mut btree);
+
+            // Overwrites add nothing
+            for &i in keys.iter().take(100) {
+                btree.insert(i, format!("VALUE-{}", i)).unwrap();
+            }
+            assert_counts_match(&mut btree);
+
+            keys.shuffle(&mut rng());
+            for &i in keys.iter().take(400) {
+                btree.delete(i).unwrap();
+            }
+            assert_eq!(btree.len(), 200);
+            assert_counts_match(&mut btree);
+
+            // Keys deleted earlier come back, reusing tombstones where they are kept
+            for &i in keys.iter().take(150) {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            assert_eq!(btree.len(), 350);
+            assert_counts_match(&mut btree);
         }
 
         #[test_log::test]
-        fn header_persists_after_write() {
-            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(4096);
+        fn counts_track_inserts_and_deletes() {
+            insert_and_delete_randomly(DeleteStrategy::Immediate);
+        }
 
-            btree.insert(1, "one".to_string()).unwrap();
-            btree.insert(2, "two".to_string()).unwrap();
+        #[test_log::test]
+        fn counts_track_tombstone_deletes() {
+            insert_and_delete_randomly(DeleteStrategy::Tombstone);
+        }
 
-            let page_count = btree.header.page_count;
+        #[test_log::test]
+        fn counts_include_kept_duplicates() {
+            let mut btree = create_counted_btree(TreeConfig {
+                duplicate_policy: DuplicatePolicy::KeepBoth,
+                ..TreeConfig::with_page_size(256)
+            });
+            for round in 0..4 {
+                for i in 0..100 {
+                    btree.insert(i, format!("{}-{}", i, round)).unwrap();
+                }
+            }
+            assert_eq!(btree.len(), 400);
+            assert_counts_match(&mut btree);
+
+            for i in 0..100 {
+                btree.delete(i).unwrap();
+            }
+            assert_counts_match(&mut btree);
+        }
+
+        #[test_log::test]
+        fn bulk_load_fills_in_counts() {
+            let mut btree = create_counted_btree(TreeConfig::with_page_size(256));
+            btree
+                .bulk_load((0..2000).map(|i| (i, format!("value-{}", i))))
+                .unwrap();
+            assert_counts_match(&mut btree);
+
+            for i in (0..2000).step_by(3) {
+                btree.delete(i).unwrap();
+            }
+            btree.insert(5000, "last".to_string()).unwrap();
+            assert_counts_match(&mut btree);
+        }
+
+        #[test_log::test]
+        fn import_onto_inserted_entries_fills_in_counts() {
+            let mut btree = create_counted_btree(TreeConfig::with_page_size(256));
+            let mut keys: Vec<i64> = (0..300).collect();
+            keys.shuffle(&mut StdRng::seed_from_u64(4974));
+            for &i in &keys {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            let entries = (300..2000).map(|i| (i, format!("value-{}", i)));
+            let options = crate::import::ImportOptions {
+                batch_size: 250,
+                ..Default::default()
+            };
+            btree.import(entries, options, |_| {}).unwrap();
+
+            btree.verify().unwrap();
+            assert_counts_match(&mut btree);
+            assert_eq!(btree.select(1234).unwrap().unwrap().0, 1234);
+        }
+
+        #[test_log::test]
+        fn trees_without_counts_keep_none() {
+            let mut btree: BTree<i64, String> = create_temp_btree(256);
+            for i in 0..300 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::INTERNAL);
+            assert!(!root.is_counted());
+            assert!(root.counts.is_empty());
+        }
+
+        #[test_log::test]
+        fn counts_can_only_be_toggled_before_the_root_splits() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(256);
+            btree
+                .set_config(TreeConfig {
+                    subtree_counts: true,
+                    ..btree.config()
+                })
+                .unwrap();
+            for i in 0..300 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+
+            let result = btree.set_config(TreeConfig {
+                subtree_counts: false,
+                ..btree.config()
+            });
+            assert!(matches!(result, Err(BTreeError::TreeNotEmpty)));
+            drop(btree);
 
-            // Reopen
             let file = std::fs::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .open(&path)
                 .unwrap();
+            let mut btree = BTree::<i64, String>::new(file, 256).unwrap();
+            assert!(btree.config().subtree_counts);
+            btree.insert(300, "value-300".to_string()).unwrap();
+            assert_counts_match(&mut btree);
+        }
 
-            let btree_copy = BTree::<i64, String>::new(file, 4096).unwrap();
-            assert_eq!(btree_copy.header.page_count, page_count);
+        // Keys 0, 2, 4, .. 598 with every third one deleted, and the sorted keys left
+        fn filled_with_gaps(
+            subtree_counts: bool,
+            strategy: DeleteStrategy,
+        ) -> (BTree<i64, String>, Vec<i64>) {
+            let mut btree = BTree::in_memory(TreeConfig {
+                subtree_counts,
+                delete_strategy: strategy,
+                ..TreeConfig::with_page_size(256)
+            })
+            .unwrap();
+            let mut keys: Vec<i64> = (0..300).map(|i| i * 2).collect();
+            keys.shuffle(&mut rand::rng());
+            for &i in &keys {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            for i in (0..300).step_by(3) {
+                btree.delete(i * 2).unwrap();
+            }
+            let left = (0..300).filter(|i| i % 3 != 0).map(|i| i * 2).collect();
+            (btree, left)
         }
 
         #[test_log::test]
-        fn page_count_increments_on_create_page() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        fn rank_and_select_follow_key_order() {
+            for (counted, strategy) in [
+                (true, DeleteStrategy::Immediate),
+                (true, DeleteStrategy::Tombstone),
+                (false, DeleteStrategy::Immediate),
+            ] {
+                let (mut btree, keys) = filled_with_gaps(counted, strategy);
+
+                for (position, &key) in keys.iter().enumerate() {
+                    assert_eq!(btree.rank(key).unwrap(), position as u64);
+                    // A missing key ranks where it would be inserted
+                    assert_eq!(btree.rank(key + 1).unwrap(), position as u64 + 1);
+                    assert_eq!(
+                        btree.select(position as u64).unwrap(),
+                        Some((key, format!("value-{}", key)))
+                    );
+                }
+                assert_eq!(btree.rank(-1).unwrap(), 0);
+                assert_eq!(btree.select(keys.len() as u64).unwrap(), None);
+            }
+        }
 
-            let initial_count = btree.header.page_count;
+        #[test_log::test]
+        fn counted_count_range_matches_scan() {
+            let (mut counted, _) = filled_with_gaps(true, DeleteStrategy::Tombstone);
+            let (mut scanned, _) = filled_with_gaps(false, DeleteStrategy::Tombstone);
+
+            assert_eq!(counted.count_range(..).unwrap(), 200);
+            for (start, end) in [
+                (0, 600),
+                (7, 8),
+                (10, 100),
+                (99, 100),
+                (301, 302),
+                (598, 1000),
+            ] {
+                assert_eq!(
+                    counted.count_range(start..end).unwrap(),
+                    scanned.count_range(start..end).unwrap()
+                );
+                assert_eq!(
+                    counted.count_range(start..=end).unwrap(),
+                    scanned.count_range(start..=end).unwrap()
+                );
+                assert_eq!(
+                    counted
+                        .count_range((Bound::Excluded(start), Bound::Unbounded))
+                        .unwrap(),
+                    scanned
+                        .count_range((Bound::Excluded(start), Bound::Unbounded))
+                        .unwrap()
+                );
+            }
+        }
 
-            // Insert enough to cause splits
+        #[test_log::test]
+        fn rank_counts_every_duplicate() {
+            let mut btree = create_counted_btree(TreeConfig {
+                duplicate_policy: DuplicatePolicy::KeepBoth,
+                ..TreeConfig::with_page_size(256)
+            });
+            for round in 0..3 {
+                for i in 0..100 {
+                    btree.insert(i, format!("{}-{}", i, round)).unwrap();
+                }
+            }
+
+            for i in 0..100 {
+                assert_eq!(btree.rank(i).unwrap(), i as u64 * 3);
+                assert_eq!(btree.count_range(i..=i).unwrap(), 3);
+            }
+            assert_eq!(btree.select(31).unwrap(), Some((10, "10-1".to_string())));
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Delete Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod delete {
+        use super::*;
+        use crate::events::{MergeEvent, TreeObserver};
+        use rand::seq::SliceRandom;
+        use rand::{Rng, SeedableRng, rngs::StdRng};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct MergeRecorder {
+            merges: Mutex<Vec<MergeEvent>>,
+        }
+
+        impl TreeObserver for MergeRecorder {
+            fn on_merge(&self, event: &MergeEvent) {
+                self.merges.lock().unwrap().push(event.clone());
+            }
+        }
+
+        #[test_log::test]
+        fn delete_returns_value_and_removes_key() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.insert(2, "two".to_string()).unwrap();
+
+            assert_eq!(btree.delete(1).unwrap(), "one");
+
+            assert!(matches!(btree.search(1), Err(BTreeError::KeyNotFound(_))));
+            assert_eq!(btree.search(2).unwrap(), "two");
+        }
+
+        #[test_log::test]
+        fn delete_missing_key_fails() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            btree.insert(1, 1).unwrap();
+
+            match btree.delete(2) {
+                Err(BTreeError::KeyNotFound(key)) => assert_eq!(key, "2"),
+                other => panic!("Expected KeyNotFound, got {:?}", other),
+            }
+            assert_eq!(btree.search(1).unwrap(), 1);
+        }
+
+        #[test_log::test]
+        fn delete_key_stored_in_internal_node() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
             for i in 0..100 {
+                btree.insert(i, i * 10).unwrap();
+            }
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::INTERNAL);
+            let separator = root.read_key(0).unwrap();
+
+            assert_eq!(btree.delete(separator).unwrap(), separator * 10);
+            assert!(btree.search(separator).is_err());
+            for i in (0..100).filter(|i| *i != separator) {
+                assert_eq!(btree.search(i).unwrap(), i * 10);
+            }
+        }
+
+        #[test_log::test]
+        fn delete_everything_in_random_order() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let mut keys: Vec<i64> = (0..500).collect();
+            for &k in &keys {
+                btree.insert(k, -k).unwrap();
+            }
+
+            keys.shuffle(&mut rand::rng());
+            for (deleted, &k) in keys.iter().enumerate() {
+                assert_eq!(btree.delete(k).unwrap(), -k);
+                if deleted % 50 == 0 {
+                    for &remaining in &keys[deleted + 1..] {
+                        assert_eq!(btree.search(remaining).unwrap(), -remaining);
+                    }
+                }
+            }
+
+            assert_eq!(btree.iter().unwrap().count(), 0);
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.node_type, NodeType::LEAF);
+        }
+
+        #[test_log::test]
+        fn underfull_pages_are_merged() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let observer = Arc::new(MergeRecorder::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..200 {
                 btree.insert(i, i).unwrap();
             }
+            for i in 0..180 {
+                btree.delete(i).unwrap();
+            }
 
-            assert!(btree.header.page_count > initial_count);
+            assert!(!observer.merges.lock().unwrap().is_empty());
+            let remaining: Vec<i64> = btree.iter().unwrap().map(|e| e.unwrap().0).collect();
+            assert_eq!(remaining, (180..200).collect::<Vec<_>>());
         }
 
         #[test_log::test]
-        fn root_page_id_changes_on_root_split() {
+        fn reinsert_after_delete() {
             let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
+            }
+            for i in (0..200).step_by(2) {
+                btree.delete(i).unwrap();
+            }
+            for i in (0..200).step_by(2) {
+                btree.insert(i, i + 1000).unwrap();
+            }
 
-            let initial_root = btree.header.root_page_id;
+            for i in 0..200 {
+                let expected = if i % 2 == 0 { i + 1000 } else { i };
+                assert_eq!(btree.search(i).unwrap(), expected);
+            }
+        }
 
-            // Insert enough to cause root split
-            for i in 0..100 {
+        #[test_log::test]
+        fn updating_internal_key_does_not_duplicate_it() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..200 {
                 btree.insert(i, i).unwrap();
             }
+            for i in 0..200 {
+                btree.insert(i, i * 2).unwrap();
+            }
 
-            // Root should change when tree grows taller
-            assert_ne!(btree.header.root_page_id, initial_root);
+            let entries: Vec<(i64, i64)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+            assert_eq!(entries, (0..200).map(|i| (i, i * 2)).collect::<Vec<_>>());
+        }
+
+        // Pages the tree reaches, after checking each internal node among them has a key
+        fn reachable_pages(btree: &mut BTree<i64, String>, page_id: u64, pages: &mut HashSet<u64>) {
+            let page = btree.read_page(page_id).unwrap();
+            assert!(
+                page.node_type == NodeType::LEAF || page.num_keys > 0,
+                "internal node {} has no keys",
+                page_id
+            );
+            pages.insert(page_id);
+            for &child in &page.pointers {
+                reachable_pages(btree, child, pages);
+            }
+        }
+
+        #[test_log::test]
+        fn deletes_free_the_pages_they_detach() {
+            for delete_strategy in [DeleteStrategy::Immediate, DeleteStrategy::Tombstone] {
+                let config = TreeConfig {
+                    delete_strategy,
+                    subtree_counts: true,
+                    ..TreeConfig::with_page_size(256)
+                };
+                let mut btree = BTree::<i64, String>::in_memory(config).unwrap();
+                let mut rng = StdRng::seed_from_u64(4899);
+                let mut keys: Vec<i64> = (0..1500).collect();
+                keys.shuffle(&mut rng);
+                for &k in &keys {
+                    let len = rng.random_range(0..30);
+                    btree.insert(k, "v".repeat(len)).unwrap();
+                }
+                keys.shuffle(&mut rng);
+                for (deleted, &k) in keys.iter().take(1450).enumerate() {
+                    btree.delete(k).unwrap();
+                    if deleted % 100 == 0 {
+                        btree.verify().unwrap();
+                    }
+                }
+                btree.verify().unwrap();
+                assert_eq!(btree.len(), 50);
+
+                let mut reachable = HashSet::new();
+                let root = btree.header.root_page_id;
+                reachable_pages(&mut btree, root, &mut reachable);
+                let detached: Vec<u64> = (1..btree.header.page_count)
+                    .filter(|page_id| !reachable.contains(page_id))
+                    .filter(|&page_id| {
+                        matches!(
+                            btree.page_type(page_id),
+                            Ok(NodeType::LEAF | NodeType::INTERNAL)
+                        )
+                    })
+                    .collect();
+                assert!(detached.is_empty(), "{:?}: {:?}", delete_strategy, detached);
+            }
+        }
+
+        #[test_log::test]
+        fn growing_values_split_instead_of_overflowing() {
+            let mut btree = create_temp_btree::<i64, String>(512);
+            for i in 0..100 {
+                btree.insert(i, "a".to_string()).unwrap();
+            }
+            for i in 0..100 {
+                btree.insert(i, "b".repeat(40)).unwrap();
+            }
+
+            for i in 0..100 {
+                assert_eq!(btree.search(i).unwrap(), "b".repeat(40));
+            }
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Search Tests
+    // Range Scan Tests
     // ─────────────────────────────────────────────────────────
 
-    mod search {
+    mod range {
         use super::*;
+        use rand::seq::SliceRandom;
+        use std::collections::BTreeMap;
+
+        fn keys<I: Iterator<Item = Result<(i64, i64), BTreeError>>>(iter: I) -> Vec<i64> {
+            iter.map(|e| e.unwrap().0).collect()
+        }
+
+        fn populated(count: i64) -> BTree<i64, i64> {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            for i in 0..count {
+                btree.insert(i, i).unwrap();
+            }
+            btree
+        }
 
         #[test_log::test]
-        fn search_single_key() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn iter_on_empty_tree() {
+            let mut btree = create_temp_btree::<i64, i64>(4096);
+            assert_eq!(btree.iter().unwrap().count(), 0);
+        }
 
-            btree.insert(42, "answer".to_string()).unwrap();
+        #[test_log::test]
+        fn iter_returns_all_entries_in_order() {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let mut input: Vec<i64> = (0..300).collect();
+            input.shuffle(&mut rand::rng());
+            for &k in &input {
+                btree.insert(k, k * 7).unwrap();
+            }
 
-            assert_eq!(btree.search(42).unwrap(), "answer");
+            let entries: Vec<(i64, i64)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+            assert_eq!(entries, (0..300).map(|k| (k, k * 7)).collect::<Vec<_>>());
         }
 
         #[test_log::test]
-        fn search_nonexistent_key_returns_error() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn scan_prefix_returns_only_matching_keys() {
+            let mut btree = create_temp_btree::<String, i64>(256);
+            for (i, table) in ["orders", "order_items", "users", "user"]
+                .iter()
+                .enumerate()
+            {
+                for row in 0..60 {
+                    btree
+                        .insert(format!("{}:{:03}", table, row), i as i64)
+                        .unwrap();
+                }
+            }
 
-            btree.insert(1, "one".to_string()).unwrap();
+            let found: Vec<(String, i64)> = btree
+                .scan_prefix("user:")
+                .unwrap()
+                .map(|e| e.unwrap())
+                .collect();
+            assert_eq!(found.len(), 60);
+            assert!(
+                found
+                    .iter()
+                    .all(|(key, value)| key.starts_with("user:") && *value == 3)
+            );
+            assert!(found.windows(2).all(|pair| pair[0].0 < pair[1].0));
 
-            let result = btree.search(999);
-            assert!(matches!(result, Err(BTreeError::KeyNotFound(_))));
+            assert_eq!(btree.scan_prefix("order").unwrap().count(), 120);
+            assert_eq!(btree.scan_prefix("").unwrap().count(), 240);
+            assert_eq!(btree.scan_prefix("products:").unwrap().count(), 0);
+            assert_eq!(btree.scan_prefix("zzz").unwrap().count(), 0);
         }
 
         #[test_log::test]
-        fn search_empty_tree_returns_error() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn keys_in_range_matches_range() {
+            let mut btree = populated(300);
+            btree.delete(20).unwrap();
+
+            for (start, end) in [(0, 300), (10, 30), (150, 151), (299, 400)] {
+                let expected = keys(btree.range(start..end).unwrap());
+                let found: Vec<i64> = btree
+                    .keys_in_range(start..end)
+                    .unwrap()
+                    .map(|k| k.unwrap())
+                    .collect();
+                assert_eq!(found, expected);
+            }
+            assert_eq!(btree.keys_in_range(..).unwrap().count(), 299);
+        }
 
-            let result = btree.search(1);
-            assert!(matches!(result, Err(BTreeError::KeyNotFound(_))));
+        #[test_log::test]
+        fn count_range_matches_range() {
+            let mut btree = populated(500);
+            let ranges: Vec<(Bound<i64>, Bound<i64>)> = vec![
+                (Bound::Unbounded, Bound::Unbounded),
+                (Bound::Included(10), Bound::Excluded(30)),
+                (Bound::Excluded(10), Bound::Included(30)),
+                (Bound::Included(250), Bound::Included(250)),
+                (Bound::Included(499), Bound::Unbounded),
+                (Bound::Included(600), Bound::Unbounded),
+                (Bound::Unbounded, Bound::Excluded(0)),
+            ];
+
+            for range in ranges {
+                let expected = btree.range(range).unwrap().count() as u64;
+                assert_eq!(btree.count_range(range).unwrap(), expected, "{:?}", range);
+            }
+            assert_eq!(btree.count_range(100..200).unwrap(), 100);
         }
 
         #[test_log::test]
-        fn search_first_key() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn count_range_skips_tombstones() {
+            let mut btree = BTree::<i64, i64>::in_memory(TreeConfig {
+                delete_strategy: DeleteStrategy::Tombstone,
+                ..TreeConfig::with_page_size(256)
+            })
+            .unwrap();
+            for i in 0..300 {
+                btree.insert(i, i).unwrap();
+            }
+            for i in (0..300).step_by(3) {
+                btree.delete(i).unwrap();
+            }
+
+            assert_eq!(btree.count_range(..).unwrap(), 200);
+            assert_eq!(btree.count_range(0..30).unwrap(), 20);
+        }
 
+        #[test_log::test]
+        fn keys_in_range_never_reads_values() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(256);
             for i in 0..100 {
-                btree.insert(i, i * 10).unwrap();
+                btree.insert(i, "short".to_string()).unwrap();
             }
+            drop(btree);
 
-            assert_eq!(btree.search(0).unwrap(), 0);
+            // Values that cannot be decoded as the declared value type
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut btree = BTree::<i64, [u64; 8]>::new(file, 256).unwrap();
+            assert!(btree.iter().unwrap().any(|e| e.is_err()));
+
+            let found: Vec<i64> = btree
+                .keys_in_range(..)
+                .unwrap()
+                .map(|k| k.unwrap())
+                .collect();
+            assert_eq!(found, (0..100).collect::<Vec<_>>());
         }
 
         #[test_log::test]
-        fn search_last_key() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-
-            for i in 0..100 {
-                btree.insert(i, i * 10).unwrap();
+        fn scan_where_only_reads_values_of_matching_keys() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, Vec<u8>>(256);
+            for i in 0..300 {
+                // Only the values of multiples of 7 are valid UTF-8
+                let value = if i % 7 == 0 {
+                    format!("value-{}", i).into_bytes()
+                } else {
+                    vec![0xff; 4]
+                };
+                btree.insert(i, value).unwrap();
             }
+            drop(btree);
 
-            assert_eq!(btree.search(99).unwrap(), 990);
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut btree = BTree::<i64, String>::new(file, 256).unwrap();
+            assert!(btree.iter().unwrap().any(|e| e.is_err()));
+
+            let found: Vec<(i64, String)> = btree
+                .scan_where(10..200, |k| k % 7 == 0)
+                .unwrap()
+                .map(|e| e.unwrap())
+                .collect();
+            let expected: Vec<(i64, String)> = (14..200)
+                .step_by(7)
+                .map(|i| (i, format!("value-{}", i)))
+                .collect();
+            assert_eq!(found, expected);
+            assert!(
+                btree
+                    .scan_where(.., |k| k % 7 == 1)
+                    .unwrap()
+                    .next()
+                    .unwrap()
+                    .is_err()
+            );
         }
 
         #[test_log::test]
-        fn search_middle_key() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn inclusive_and_exclusive_bounds() {
+            let mut btree = populated(200);
+
+            assert_eq!(keys(btree.range(50..55).unwrap()), vec![50, 51, 52, 53, 54]);
+            assert_eq!(keys(btree.range(50..=52).unwrap()), vec![50, 51, 52]);
+            assert_eq!(keys(btree.range(..3).unwrap()), vec![0, 1, 2]);
+            assert_eq!(keys(btree.range(197..).unwrap()), vec![197, 198, 199]);
+            assert_eq!(
+                keys(
+                    btree
+                        .range((Bound::Excluded(10), Bound::Included(12)))
+                        .unwrap()
+                ),
+                vec![11, 12]
+            );
+        }
 
-            for i in 0..100 {
-                btree.insert(i, i * 10).unwrap();
-            }
+        #[test_log::test]
+        fn empty_and_out_of_range_bounds() {
+            let mut btree = populated(50);
 
-            assert_eq!(btree.search(50).unwrap(), 500);
+            assert!(keys(btree.range(20..20).unwrap()).is_empty());
+            assert!(keys(btree.range(100..).unwrap()).is_empty());
+            assert_eq!(keys(btree.range(-10..2).unwrap()), vec![0, 1]);
         }
 
         #[test_log::test]
-        fn search_after_splits() {
+        fn matches_std_btreemap_on_sparse_keys() {
             let mut btree = create_temp_btree::<i64, i64>(256);
+            let mut model = BTreeMap::new();
+            for i in 0..400 {
+                let k = (i * 37) % 1000;
+                btree.insert(k, i).unwrap();
+                model.insert(k, i);
+            }
 
-            // Insert enough to cause multiple splits
-            for i in 0..200 {
-                btree.insert(i, i).unwrap();
+            for (start, end) in [(0, 1000), (13, 14), (100, 350), (999, 1000), (500, 499)] {
+                let expected: Vec<(i64, i64)> = if start <= end {
+                    model.range(start..end).map(|(k, v)| (*k, *v)).collect()
+                } else {
+                    Vec::new()
+                };
+                let actual: Vec<(i64, i64)> = btree
+                    .range((Bound::Included(start), Bound::Excluded(end)))
+                    .unwrap()
+                    .map(|e| e.unwrap())
+                    .collect();
+                assert_eq!(actual, expected, "range {}..{}", start, end);
             }
+        }
 
-            // All keys should still be findable
-            for i in 0..200 {
-                assert_eq!(btree.search(i).unwrap(), i);
+        #[test_log::test]
+        fn range_cursor_walks_range_between_borrows() {
+            let mut btree = populated(500);
+            let mut cursor = btree.range_cursor(100..400).unwrap();
+
+            // Reads interleave with the tree being borrowed for other lookups
+            let mut seen = Vec::new();
+            while let Some((key, value)) = cursor.advance(&mut btree).unwrap() {
+                assert_eq!(btree.search(key).unwrap(), value);
+                seen.push(key);
             }
+            assert_eq!(seen, (100..400).collect::<Vec<_>>());
+            assert!(cursor.advance(&mut btree).unwrap().is_none());
         }
 
         #[test_log::test]
-        fn search_with_string_keys() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
+        fn range_cursor_carries_on_across_splits() {
+            let mut btree = populated(500);
+            let mut cursor = btree.range_cursor(..1000).unwrap();
+            let mut seen = Vec::new();
+            for _ in 0..100 {
+                seen.push(cursor.advance(&mut btree).unwrap().unwrap().0);
+            }
 
-            btree.insert("apple".to_string(), 1).unwrap();
-            btree.insert("banana".to_string(), 2).unwrap();
-            btree.insert("cherry".to_string(), 3).unwrap();
+            // Fill the leaf the cursor is on and those around it until they split, and add
+            // keys both behind and ahead of it
+            for i in 0..200 {
+                btree.insert(1000 + i, 0).unwrap();
+                btree.insert(-1 - i, 0).unwrap();
+            }
+            btree.delete(99).unwrap();
+            btree.delete(100).unwrap();
+            btree.insert(150, 1).unwrap();
+            while let Some((key, _)) = cursor.advance(&mut btree).unwrap() {
+                seen.push(key);
+            }
+            let expected: Vec<i64> = (0..100).chain(101..500).collect();
+            assert_eq!(seen, expected);
+        }
 
-            assert_eq!(btree.search("banana".to_string()).unwrap(), 2);
+        #[test_log::test]
+        fn range_cursor_fails_once_tree_is_rewritten() {
+            let mut btree = populated(300);
+            let mut cursor = btree.range_cursor(..).unwrap();
+            btree.search(5).unwrap();
+            btree.iter().unwrap().count();
+            assert_eq!(cursor.advance(&mut btree).unwrap(), Some((0, 0)));
+            // Writes to pages the cursor does not hold leave it where it is
+            let version = btree.page_manager.write_version();
+            btree.delete(200).unwrap();
+            assert!(btree.page_manager.write_version() > version);
+            assert_eq!(cursor.advance(&mut btree).unwrap(), Some((1, 1)));
+
+            btree.clear().unwrap();
+            assert!(matches!(
+                cursor.advance(&mut btree),
+                Err(BTreeError::TreeModified)
+            ));
+            // The failure sticks rather than resuming over a changed tree
+            assert!(matches!(
+                cursor.advance(&mut btree),
+                Err(BTreeError::TreeModified)
+            ));
         }
 
         #[test_log::test]
-        fn search_negative_keys() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn range_cursor_rejects_other_tree() {
+            let mut btree = populated(10);
+            let mut other = populated(10);
+            let mut cursor = btree.range_cursor(..).unwrap();
+
+            assert!(matches!(
+                cursor.advance(&mut other),
+                Err(BTreeError::TreeModified)
+            ));
+        }
 
-            btree.insert(-100, "negative".to_string()).unwrap();
-            btree.insert(0, "zero".to_string()).unwrap();
-            btree.insert(100, "positive".to_string()).unwrap();
+        #[test_log::test]
+        fn range_cursor_over_shared_tree() {
+            use std::sync::Mutex;
+
+            let tree = Arc::new(Mutex::new(populated(200)));
+            let mut cursor = tree.lock().unwrap().range_cursor(..).unwrap();
+            let writer = {
+                let tree = tree.clone();
+                std::thread::spawn(move || tree.lock().unwrap().insert(500, 500).unwrap())
+            };
+            writer.join().unwrap();
 
-            assert_eq!(btree.search(-100).unwrap(), "negative");
-            assert_eq!(btree.search(0).unwrap(), "zero");
-            assert_eq!(btree.search(100).unwrap(), "positive");
+            let mut tree = tree.lock().unwrap();
+            let mut count = 0;
+            while cursor.advance(&mut tree).unwrap().is_some() {
+                count += 1;
+            }
+            assert_eq!(count, 201);
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Insert Tests
+    // Tombstone Delete Tests
     // ─────────────────────────────────────────────────────────
 
-    mod insert {
+    mod tombstone_delete {
         use super::*;
+        use rand::seq::SliceRandom;
+        use std::collections::BTreeMap;
+
+        fn tombstone_btree<V>(page_size: u64) -> (BTree<i64, V>, NamedTempFile)
+        where
+            V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        {
+            let file = NamedTempFile::new().unwrap();
+            let config = TreeConfig {
+                delete_strategy: DeleteStrategy::Tombstone,
+                ..TreeConfig::with_page_size(page_size)
+            };
+            let btree = BTree::with_config(file.reopen().unwrap(), config).unwrap();
+            (btree, file)
+        }
 
         #[test_log::test]
-        fn insert_single_entry() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn deleted_key_is_hidden_but_space_kept() {
+            let (mut btree, _file) = tombstone_btree::<String>(4096);
+            for i in 0..10 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            let free_before = btree
+                .read_page(btree.header.root_page_id)
+                .unwrap()
+                .total_free;
 
-            btree.insert(1, "one".to_string()).unwrap();
+            assert_eq!(btree.delete(3).unwrap(), "value-3");
 
-            assert_eq!(btree.search(1).unwrap(), "one");
+            assert!(matches!(btree.search(3), Err(BTreeError::KeyNotFound(_))));
+            assert!(matches!(btree.delete(3), Err(BTreeError::KeyNotFound(_))));
+            let keys: Vec<i64> = btree.iter().unwrap().map(|e| e.unwrap().0).collect();
+            assert_eq!(keys, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(root.total_free, free_before);
+            assert!(root.has_tombstones());
         }
 
         #[test_log::test]
-        fn insert_multiple_sequential() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn reinsert_revives_deleted_key() {
+            let (mut btree, _file) = tombstone_btree::<String>(4096);
+            btree.insert(1, "one".to_string()).unwrap();
+            btree.delete(1).unwrap();
 
-            for i in 0..50 {
-                btree.insert(i, i * 2).unwrap();
-            }
+            btree.insert(1, "uno".to_string()).unwrap();
 
-            for i in 0..50 {
-                assert_eq!(btree.search(i).unwrap(), i * 2);
-            }
+            assert_eq!(btree.search(1).unwrap(), "uno");
+            assert_eq!(btree.iter().unwrap().count(), 1);
         }
 
         #[test_log::test]
-        fn insert_multiple_reverse() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-
-            for i in (0..50).rev() {
-                btree.insert(i, i * 2).unwrap();
+        fn compaction_reclaims_tombstones() {
+            let (mut btree, _file) = tombstone_btree::<String>(256);
+            for i in 0..200 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            for i in (0..200).step_by(2) {
+                btree.delete(i).unwrap();
             }
 
-            for i in 0..50 {
-                assert_eq!(btree.search(i).unwrap(), i * 2);
+            assert!(btree.compact_fragmented_pages().unwrap() > 0);
+            assert_eq!(btree.compact_fragmented_pages().unwrap(), 0);
+            for i in 0..200 {
+                assert_eq!(btree.search(i).is_ok(), i % 2 == 1);
             }
         }
 
         #[test_log::test]
-        fn insert_random_order() {
-            use rand::rng;
-            use rand::seq::SliceRandom;
+        fn strategy_persists_across_reopen() {
+            let (mut btree, file) = tombstone_btree::<i64>(4096);
+            btree.insert(1, 1).unwrap();
+            btree.delete(1).unwrap();
+            btree.flush().unwrap();
+            drop(btree);
 
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-            let mut keys: Vec<i64> = (0..100).collect();
-            keys.shuffle(&mut rng());
+            let mut btree = BTree::<i64, i64>::new(file.reopen().unwrap(), 4096).unwrap();
+            assert_eq!(btree.config().delete_strategy, DeleteStrategy::Tombstone);
+            assert!(matches!(btree.search(1), Err(BTreeError::KeyNotFound(_))));
+        }
 
-            for &k in &keys {
-                btree.insert(k, k * 10).unwrap();
+        #[test_log::test]
+        fn deleting_a_separator_leaves_no_tombstone_of_its_replacement() {
+            let (mut btree, _file) = tombstone_btree::<i64>(256);
+            for i in 0..200 {
+                btree.insert(i, i).unwrap();
             }
+            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            let separator = root.read_key(0).unwrap();
 
-            for k in 0..100 {
-                assert_eq!(btree.search(k).unwrap(), k * 10);
-            }
+            btree.delete(separator).unwrap();
+
+            // The predecessor that took the separator's place must not linger below it
+            btree.verify().unwrap();
+            assert!(btree.search(separator).is_err());
+            assert_eq!(btree.search(separator - 1).unwrap(), separator - 1);
         }
 
         #[test_log::test]
-        fn insert_updates_existing_key() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn random_workload_matches_model() {
+            let (mut btree, _file) = tombstone_btree::<i64>(256);
+            let mut model = BTreeMap::new();
+            let mut keys: Vec<i64> = (0..400).collect();
+
+            for round in 0..4 {
+                keys.shuffle(&mut rand::rng());
+                for &key in &keys[..300] {
+                    btree.insert(key, key + round).unwrap();
+                    model.insert(key, key + round);
+                }
+                keys.shuffle(&mut rand::rng());
+                for &key in &keys[..200] {
+                    assert_eq!(btree.delete(key).ok(), model.remove(&key));
+                }
+            }
 
-            btree.insert(1, "original".to_string()).unwrap();
-            btree.insert(1, "updated".to_string()).unwrap();
+            let entries: Vec<(i64, i64)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+            assert_eq!(entries, model.into_iter().collect::<Vec<_>>());
+        }
+    }
 
-            assert_eq!(btree.search(1).unwrap(), "updated");
+    // ─────────────────────────────────────────────────────────
+    // Drain Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod drain {
+        use super::*;
+        use rand::seq::SliceRandom;
+
+        fn populated(count: i64) -> BTree<i64, i64> {
+            let mut btree = create_temp_btree::<i64, i64>(256);
+            let mut keys: Vec<i64> = (0..count).collect();
+            keys.shuffle(&mut rand::rng());
+            for i in keys {
+                btree.insert(i, i * 10).unwrap();
+            }
+            btree
         }
 
         #[test_log::test]
-        fn insert_update_preserves_other_keys() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
-
-            btree.insert(1, "one".to_string()).unwrap();
-            btree.insert(2, "two".to_string()).unwrap();
-            btree.insert(3, "three".to_string()).unwrap();
+        fn drain_yields_everything_in_order_and_empties_tree() {
+            let mut btree = populated(500);
 
-            btree.insert(2, "TWO".to_string()).unwrap();
+            let drained: Vec<(i64, i64)> = btree.drain().map(|e| e.unwrap()).collect();
 
-            assert_eq!(btree.search(1).unwrap(), "one");
-            assert_eq!(btree.search(2).unwrap(), "TWO");
-            assert_eq!(btree.search(3).unwrap(), "three");
+            assert_eq!(drained, (0..500).map(|i| (i, i * 10)).collect::<Vec<_>>());
+            assert!(btree.iter().unwrap().next().is_none());
+            btree.insert(1, 1).unwrap();
+            assert_eq!(btree.search(1).unwrap(), 1);
         }
 
         #[test_log::test]
-        fn insert_many_updates_same_key() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn drain_range_leaves_other_entries() {
+            let mut btree = populated(400);
 
-            btree.insert(1, 0).unwrap();
+            let drained: Vec<i64> = btree.drain_range(100..300).map(|e| e.unwrap().0).collect();
 
-            for i in 1..=100 {
-                btree.insert(1, i).unwrap();
+            assert_eq!(drained, (100..300).collect::<Vec<_>>());
+            let remaining: Vec<i64> = btree.iter().unwrap().map(|e| e.unwrap().0).collect();
+            assert_eq!(remaining, (0..100).chain(300..400).collect::<Vec<_>>());
+            for i in (0..100).chain(300..400) {
+                assert_eq!(btree.search(i).unwrap(), i * 10);
             }
+        }
 
-            assert_eq!(btree.search(1).unwrap(), 100);
+        #[test_log::test]
+        fn dropped_drain_keeps_unyielded_entries() {
+            let mut btree = populated(200);
+
+            let taken: Vec<i64> = btree.drain().take(5).map(|e| e.unwrap().0).collect();
+
+            assert_eq!(taken, vec![0, 1, 2, 3, 4]);
+            assert_eq!(btree.iter().unwrap().count(), 195);
+            assert_eq!(btree.search(5).unwrap(), 50);
         }
 
         #[test_log::test]
-        fn insert_different_value_types() {
-            // String values
-            let mut btree1 = create_temp_btree::<i64, String>(4096);
-            btree1.insert(1, "hello".to_string()).unwrap();
-            assert_eq!(btree1.search(1).unwrap(), "hello");
+        fn drained_pages_are_reused() {
+            let mut btree = populated(500);
+            assert_eq!(btree.drain_range(..400).count(), 400);
+            btree.verify().unwrap();
+
+            let page_count = btree.page_manager.page_count();
+            let free_pages = |btree: &mut BTree<i64, i64>| {
+                (0..page_count)
+                    .filter(|&page_id| btree.page_type(page_id).unwrap() == NodeType::FREE)
+                    .count()
+            };
+            assert!(free_pages(&mut btree) > 0);
+            for i in 0..400 {
+                btree.insert(i, i * 10).unwrap();
+                // The file only grows once every freed page is back in use
+                if btree.page_manager.page_count() > page_count {
+                    assert_eq!(free_pages(&mut btree), 0);
+                    break;
+                }
+            }
+            btree.verify().unwrap();
+        }
 
-            // Vector values
-            let mut btree2 = create_temp_btree::<i64, Vec<u8>>(4096);
-            btree2.insert(1, vec![1, 2, 3, 4, 5]).unwrap();
-            assert_eq!(btree2.search(1).unwrap(), vec![1, 2, 3, 4, 5]);
+        #[test_log::test]
+        fn drain_of_empty_range_yields_nothing() {
+            let mut btree = populated(50);
 
-            // Tuple values
-            let mut btree3 = create_temp_btree::<i64, (i64, String)>(4096);
-            btree3.insert(1, (42, "answer".to_string())).unwrap();
-            assert_eq!(btree3.search(1).unwrap(), (42, "answer".to_string()));
+            assert!(btree.drain_range(1000..).next().is_none());
+            assert_eq!(btree.iter().unwrap().count(), 50);
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Split Tests
+    // Stats Tests
     // ─────────────────────────────────────────────────────────
 
-    mod split {
+    mod stats {
         use super::*;
 
-        #[test_log::test]
-        fn insert_causes_leaf_split() {
-            let mut btree = create_temp_btree::<i64, String>(512);
-
-            let initial_page_count = btree.header.page_count;
-
-            for i in 0..30 {
-                btree.insert(i, format!("value_{}", i)).unwrap();
-            }
-
-            assert!(
-                btree.header.page_count > initial_page_count,
-                "Page count should increase after splits"
-            );
+        fn reopen<K, V>(path: &std::path::Path, page_size: u64) -> BTree<K, V>
+        where
+            K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+            V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+        {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap();
+            BTree::new(file, page_size).unwrap()
         }
 
         #[test_log::test]
-        fn split_maintains_all_data() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        fn stats_follow_inserts_overwrites_and_deletes() {
+            let mut btree = create_temp_btree::<i64, String>(256);
+            assert!(btree.is_empty());
 
-            for i in 0..100 {
-                btree.insert(i, i * 10).unwrap();
+            for i in 0..200 {
+                btree.insert(i, "abc".to_string()).unwrap();
             }
-
-            // Verify all data is still accessible
-            for i in 0..100 {
-                assert_eq!(
-                    btree.search(i).unwrap(),
-                    i * 10,
-                    "Key {} should have value {}",
-                    i,
-                    i * 10
-                );
+            // Overwrites with a longer value, which cannot be done in place
+            for i in 0..50 {
+                btree.insert(i, "abcdef".to_string()).unwrap();
             }
+            for i in 150..200 {
+                btree.delete(i).unwrap();
+            }
+            assert!(btree.delete(500).is_err());
+
+            // A String serializes as an 8-byte length followed by its bytes
+            let stats = btree.stats();
+            assert_eq!(btree.len(), 150);
+            assert_eq!(stats.key_bytes, 150 * 8);
+            assert_eq!(stats.value_bytes, 50 * 14 + 100 * 11);
+            assert_eq!(stats.deletes, 50);
         }
 
         #[test_log::test]
-        fn multiple_splits_maintain_order() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
-            // Insert in random order
-            let keys = vec![50, 25, 75, 10, 30, 60, 90, 5, 15, 27, 35, 55, 70, 80, 95];
-
-            for &k in &keys {
-                btree.insert(k, k).unwrap();
+        fn tombstoned_keys_are_not_counted() {
+            let config = TreeConfig {
+                delete_strategy: DeleteStrategy::Tombstone,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::<i64, i64>::in_memory(config).unwrap();
+            for i in 0..100 {
+                btree.insert(i, i).unwrap();
+            }
+            for i in 0..100 {
+                btree.delete(i).unwrap();
             }
+            assert_eq!(btree.len(), 0);
 
-            // All should be findable
-            for &k in &keys {
-                assert_eq!(btree.search(k).unwrap(), k);
+            // Reinserting revives the tombstoned slots
+            for i in 0..10 {
+                btree.insert(i, i).unwrap();
             }
+            assert_eq!(btree.len(), 10);
+            assert_eq!(btree.stats().value_bytes, 80);
         }
 
         #[test_log::test]
-        fn root_split_creates_new_root() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
-            let initial_root = btree.header.root_page_id;
-
-            // Insert enough to cause root to split
-            for i in 0..50 {
+        fn stats_survive_reopen_after_flush() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            for i in 0..300 {
                 btree.insert(i, i).unwrap();
             }
+            btree.delete(0).unwrap();
+            btree.flush().unwrap();
+            let stats = btree.stats();
+            drop(btree);
 
-            // Verify root changed
-            assert_ne!(btree.header.root_page_id, initial_root);
-
-            // New root should be internal node
-            let root = btree.read_page(btree.header.root_page_id).unwrap();
-            assert_eq!(root.node_type, NodeType::INTERNAL);
+            let mut btree = reopen::<i64, i64>(&path, 256);
+            assert_eq!(btree.stats(), stats);
+            assert_eq!(btree.len(), 299);
+            assert_eq!(
+                btree.page_type(btree.header.stats_page_id).unwrap(),
+                NodeType::META
+            );
         }
 
         #[test_log::test]
-        fn split_with_sequential_inserts() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        fn unflushed_stats_are_written_on_drop() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            btree.insert(1, 1).unwrap();
+            btree.flush().unwrap();
+            btree.insert(2, 2).unwrap();
+            drop(btree);
 
-            for i in 0..200 {
-                btree.insert(i, i).unwrap();
-                // Verify all previous inserts still work
-                for j in 0..=i {
-                    assert_eq!(btree.search(j).unwrap(), j, "Failed after inserting {}", i);
-                }
-            }
+            assert_eq!(reopen::<i64, i64>(&path, 256).len(), 2);
         }
 
         #[test_log::test]
-        fn split_with_reverse_inserts() {
+        fn bulk_load_and_clear_update_stats() {
             let mut btree = create_temp_btree::<i64, i64>(256);
+            btree.bulk_load((0..500).map(|i| (i, i))).unwrap();
+            assert_eq!(btree.len(), 500);
+            assert_eq!(btree.stats().key_bytes, 500 * 8);
+
+            btree.flush().unwrap();
+            btree.clear().unwrap();
+
+            assert!(btree.is_empty());
+            // The bulk load and the clear were both writes
+            let expected = TreeStats {
+                file_size: btree.page_manager.size(),
+                commits: 2,
+                ..TreeStats::default()
+            };
+            assert_eq!(btree.stats(), expected);
+            assert!(!btree.header.has_stats());
+        }
 
-            for i in (0..200).rev() {
+        #[test_log::test]
+        fn commit_lsn_counts_writes_across_reopens() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            assert_eq!(btree.commit_lsn(), 0);
+            for i in 0..100 {
                 btree.insert(i, i).unwrap();
-                assert_eq!(btree.search(i).unwrap(), i);
             }
+            btree.delete(0).unwrap();
+            assert!(btree.delete(0).is_err());
+            assert_eq!(btree.commit_lsn(), 101);
+            drop(btree);
 
-            for i in 0..200 {
-                assert_eq!(btree.search(i).unwrap(), i);
-            }
+            let mut btree = reopen::<i64, i64>(&path, 256);
+            assert_eq!(btree.commit_lsn(), 101);
+            btree.insert(0, 0).unwrap();
+            assert_eq!(btree.commit_lsn(), 102);
         }
-    }
-
-    // ─────────────────────────────────────────────────────────
-    // Internal Node Tests
-    // ─────────────────────────────────────────────────────────
 
-    mod internal_nodes {
-        use super::*;
+        #[test_log::test]
+        fn reads_wait_for_their_lsn() {
+            let mut primary = create_temp_btree::<i64, i64>(256);
+            let mut replica = create_temp_btree::<i64, i64>(256);
+            primary.insert(1, 10).unwrap();
+            replica.insert(1, 10).unwrap();
+            primary.insert(1, 11).unwrap();
+            let token = primary.commit_lsn();
+
+            assert_eq!(primary.search_at_least(1, token).unwrap(), 11);
+            assert!(matches!(
+                replica.search_at_least(1, token),
+                Err(BTreeError::LsnNotReached {
+                    required: 2,
+                    applied: 1
+                })
+            ));
+
+            replica.insert(1, 11).unwrap();
+            assert_eq!(replica.search_at_least(1, token).unwrap(), 11);
+            replica.require_lsn(0).unwrap();
+        }
 
         #[test_log::test]
-        fn internal_node_pointers_correct() {
+        fn drain_counts_deletes() {
             let mut btree = create_temp_btree::<i64, i64>(256);
-
-            // Insert enough to create internal nodes
             for i in 0..100 {
                 btree.insert(i, i).unwrap();
             }
 
-            let root = btree.read_page(btree.header.root_page_id).unwrap();
+            assert_eq!(btree.drain_range(..40).count(), 40);
 
-            if root.node_type == NodeType::INTERNAL {
-                // Internal node should have pointers
-                assert!(!root.pointers.is_empty());
-                // Should have one more pointer than keys
-                assert_eq!(root.pointers.len(), root.num_keys as usize + 1);
+            assert_eq!(btree.len(), 60);
+            assert_eq!(btree.stats().deletes, 40);
+        }
+
+        fn create_limited_btree(max_pages: u64) -> BTree<i64, String> {
+            BTree::in_memory(TreeConfig {
+                max_file_size: Header::SIZE as u64 + max_pages * 256,
+                ..TreeConfig::with_page_size(256)
+            })
+            .unwrap()
+        }
+
+        // Inserts ascending keys until the size limit stops one, returning how many went in
+        fn fill_until_full(btree: &mut BTree<i64, String>) -> i64 {
+            for i in 0.. {
+                match btree.insert(i, format!("value-{:04}", i)) {
+                    Ok(()) => {}
+                    Err(BTreeError::PageManager(PageManagerError::StorageFull)) => return i,
+                    Err(e) => panic!("unexpected error: {}", e),
+                }
             }
+            unreachable!()
         }
 
         #[test_log::test]
-        fn search_traverses_internal_nodes() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        fn storage_full_leaves_tree_consistent() {
+            let mut btree = create_limited_btree(12);
+            let inserted = fill_until_full(&mut btree);
+            let stats = btree.stats();
+
+            assert!(inserted > 0);
+            assert_eq!(btree.len(), inserted as u64);
+            assert!(stats.file_size <= stats.max_file_size);
+            assert_eq!(btree.header.page_count, btree.page_manager.page_count());
+            for i in 0..inserted {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
+            }
+            assert_eq!(btree.iter().unwrap().count() as i64, inserted);
+
+            // Still full, but values that fit where they are can be rewritten
+            assert!(matches!(
+                btree.insert(inserted, "late".to_string()),
+                Err(BTreeError::PageManager(PageManagerError::StorageFull))
+            ));
+            btree.insert(0, "VALUE-0000".to_string()).unwrap();
+            assert_eq!(btree.search(0).unwrap(), "VALUE-0000");
+            assert_eq!(btree.stats().entries, stats.entries);
+
+            // A page was kept back for the stats
+            btree.flush().unwrap();
+        }
 
-            for i in 0..100 {
-                btree.insert(i, i * 10).unwrap();
+        #[test_log::test]
+        fn raising_the_limit_lets_inserts_resume() {
+            let mut btree = create_limited_btree(8);
+            let inserted = fill_until_full(&mut btree);
+
+            btree
+                .set_config(TreeConfig {
+                    max_file_size: 0,
+                    ..btree.config()
+                })
+                .unwrap();
+            for i in inserted..inserted + 100 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
             }
 
-            // These searches should traverse internal nodes
-            assert_eq!(btree.search(0).unwrap(), 0);
-            assert_eq!(btree.search(50).unwrap(), 500);
-            assert_eq!(btree.search(99).unwrap(), 990);
+            assert_eq!(btree.len(), inserted as u64 + 100);
+            assert_eq!(btree.stats().max_file_size, 0);
         }
 
         #[test_log::test]
-        fn internal_node_split_preserves_data() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
-            // Insert many entries to cause internal node splits
-            for i in 0..500 {
-                btree.insert(i, i).unwrap();
-            }
+        fn bulk_load_stops_at_the_limit() {
+            let mut btree = create_limited_btree(8);
+            let result = btree.bulk_load((0..1000).map(|i| (i, format!("value-{:04}", i))));
+
+            assert!(matches!(
+                result,
+                Err(BTreeError::PageManager(PageManagerError::StorageFull))
+            ));
+            let stats = btree.stats();
+            assert!(stats.file_size <= stats.max_file_size);
+        }
 
-            // Verify all data
-            for i in 0..500 {
-                assert_eq!(btree.search(i).unwrap(), i, "Key {} not found", i);
-            }
+        #[test_log::test]
+        fn storage_full_io_errors_are_reported_as_storage_full() {
+            let err: BTreeError = crate::io::Error::from(crate::io::ErrorKind::StorageFull).into();
+            assert!(matches!(
+                err,
+                BTreeError::PageManager(PageManagerError::StorageFull)
+            ));
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Page I/O Tests
+    // Clear Tests
     // ─────────────────────────────────────────────────────────
 
-    mod page_io {
+    mod clear {
         use super::*;
 
         #[test_log::test]
-        fn read_page_returns_correct_data() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
-
-            btree.insert(1, "one".to_string()).unwrap();
-            btree.insert(2, "two".to_string()).unwrap();
+        fn clear_empties_tree_and_truncates_file() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(256);
+            for i in 0..500 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            assert!(std::fs::metadata(&path).unwrap().len() > 10 * 256);
 
-            let page = btree.read_page(btree.header.root_page_id).unwrap();
+            btree.clear().unwrap();
 
-            assert_eq!(page.num_keys, 2);
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().len(),
+                Header::SIZE as u64 + 256
+            );
+            assert_eq!(btree.header.page_count, 1);
+            assert!(btree.iter().unwrap().next().is_none());
+            assert!(matches!(btree.search(7), Err(BTreeError::KeyNotFound(_))));
         }
 
         #[test_log::test]
-        fn write_and_read_page_roundtrip() {
-            let (mut btree, path, _original_file) = create_btree_with_file::<i64, String>(4096);
-
-            btree.insert(1, "one".to_string()).unwrap();
-            btree.insert(2, "two".to_string()).unwrap();
-            btree.insert(3, "three".to_string()).unwrap();
-
-            let root_id = btree.header.root_page_id;
+        fn tree_is_usable_after_clear() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, i64>(256);
+            for i in 0..300 {
+                btree.insert(i, i).unwrap();
+            }
+            btree.clear().unwrap();
+            for i in 0..300 {
+                btree.insert(i, -i).unwrap();
+            }
+            btree.flush().unwrap();
             drop(btree);
 
-            // Reopen and read
             let file = std::fs::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .open(&path)
                 .unwrap();
+            let mut btree = BTree::<i64, i64>::new(file, 256).unwrap();
+            for i in 0..300 {
+                assert_eq!(btree.search(i).unwrap(), -i);
+            }
+        }
 
-            let mut btree = BTree::<i64, String>::new(file, 4096).unwrap();
-            let page = btree.read_page(root_id).unwrap();
+        #[test_log::test]
+        fn clear_keeps_compression_dictionary() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
+            btree
+                .set_compression_dictionary(b"shared dictionary bytes".to_vec())
+                .unwrap();
+            btree.insert(1, "one".to_string()).unwrap();
 
-            assert_eq!(page.num_keys, 3);
+            btree.clear().unwrap();
+
+            assert!(btree.header.has_dictionary());
+            assert_eq!(
+                btree.compression_dictionary(),
+                Some(&b"shared dictionary bytes"[..])
+            );
+            btree.insert(2, "two".to_string()).unwrap();
+            assert_eq!(btree.search(2).unwrap(), "two");
         }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Error Handling Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod error_handling {
+        use super::*;
 
         #[test_log::test]
-        fn create_page_increments_count() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        fn search_returns_key_not_found_error() {
+            let mut btree = create_temp_btree::<i64, String>(4096);
 
-            let initial = btree.header.page_count;
+            btree.insert(1, "one".to_string()).unwrap();
 
-            // Force page creation via splits
-            for i in 0..50 {
-                btree.insert(i, i).unwrap();
+            match btree.search(999) {
+                Err(BTreeError::KeyNotFound(key)) => {
+                    assert_eq!(key, "999");
+                }
+                other => panic!("Expected KeyNotFound, got {:?}", other),
             }
+        }
 
-            assert!(btree.header.page_count > initial);
+        #[test_log::test]
+        fn search_nonexistent_key_with_string() {
+            let mut btree = create_temp_btree::<String, i64>(4096);
+
+            btree.insert("exists".to_string(), 1).unwrap();
+
+            match btree.search("missing".to_string()) {
+                Err(BTreeError::KeyNotFound(key)) => {
+                    assert_eq!(key, "missing");
+                }
+                other => panic!("Expected KeyNotFound, got {:?}", other),
+            }
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Edge Cases
+    // In-Page Search Tests
     // ─────────────────────────────────────────────────────────
 
-    mod edge_cases {
+    mod in_page_search {
         use super::*;
+        use rand::seq::SliceRandom;
 
         #[test_log::test]
-        fn empty_string_key() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
+        fn interpolation_finds_the_same_entries_as_binary_search() {
+            let mut keys: Vec<i64> = (-500..500).map(|i| i * 3).collect();
+            keys.shuffle(&mut rand::rng());
+
+            let mut btree = BTree::<i64, i64>::in_memory(TreeConfig::with_page_size(4096)).unwrap();
+            btree.set_interpolation_search(true);
+            for &key in &keys {
+                btree.insert(key, key * 2).unwrap();
+            }
+            for &key in keys.iter().step_by(3) {
+                btree.delete(key).unwrap();
+            }
 
-            btree.insert("".to_string(), 42).unwrap();
+            btree.verify().unwrap();
+            let deleted: HashSet<i64> = keys.iter().step_by(3).copied().collect();
+            for key in -1502i64..1502 {
+                let expected =
+                    (key % 3 == 0 && (-1500..1500).contains(&key) && !deleted.contains(&key))
+                        .then_some(key * 2);
+                assert_eq!(btree.search(key).ok(), expected, "key {}", key);
+            }
 
-            assert_eq!(btree.search("".to_string()).unwrap(), 42);
+            btree.set_interpolation_search(false);
+            let entries: Vec<(i64, i64)> = btree
+                .range(-100..100)
+                .unwrap()
+                .map(|e| e.unwrap())
+                .collect();
+            btree.set_interpolation_search(true);
+            let interpolated: Vec<(i64, i64)> = btree
+                .range(-100..100)
+                .unwrap()
+                .map(|e| e.unwrap())
+                .collect();
+            assert_eq!(interpolated, entries);
         }
 
         #[test_log::test]
-        fn empty_string_value() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
-
-            btree.insert(1, "".to_string()).unwrap();
+        fn interpolation_keeps_runs_of_duplicates_in_order() {
+            let mut btree = BTree::<u32, u32>::in_memory(TreeConfig {
+                duplicate_policy: DuplicatePolicy::KeepBoth,
+                ..TreeConfig::with_page_size(256)
+            })
+            .unwrap();
+            btree.set_interpolation_search(true);
+            for i in 0..400 {
+                btree.insert(i % 4, i).unwrap();
+            }
 
-            assert_eq!(btree.search(1).unwrap(), "");
+            for key in 0..4 {
+                let expected: Vec<u32> = (0..100).map(|n| n * 4 + key).collect();
+                assert_eq!(btree.get_all(key).unwrap(), expected);
+            }
         }
 
         #[test_log::test]
-        fn large_key() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
-
-            let large_key = "x".repeat(500);
-            btree.insert(large_key.clone(), 42).unwrap();
+        fn every_free_list_policy_keeps_every_entry() {
+            for policy in [
+                FitPolicy::BestFit,
+                FitPolicy::FirstFit,
+                FitPolicy::AddressOrdered,
+            ] {
+                let mut btree = create_temp_btree::<i64, String>(512);
+                btree.set_free_list_policy(policy, 2);
+                for i in 0..600 {
+                    btree.insert(i, format!("value-{:04}", i)).unwrap();
+                }
+                for i in (0..600).step_by(3) {
+                    btree.delete(i).unwrap();
+                }
+                for i in (0..600).step_by(6) {
+                    btree.insert(i, format!("again-{:04}", i)).unwrap();
+                }
 
-            assert_eq!(btree.search(large_key).unwrap(), 42);
+                btree.verify().unwrap();
+                for i in 0..600 {
+                    let expected = match (i % 6, i % 3) {
+                        (0, _) => Some(format!("again-{:04}", i)),
+                        (_, 0) => None,
+                        _ => Some(format!("value-{:04}", i)),
+                    };
+                    assert_eq!(btree.search(i).ok(), expected, "{:?}", policy);
+                }
+            }
         }
 
         #[test_log::test]
-        fn large_value() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn eytzinger_pages_read_back_without_the_layout() {
+            let (mut btree, path, _file) = create_btree_with_file::<i64, String>(512);
+            btree.set_eytzinger_layout(true);
+            for i in (0..500).map(|i| (i * 37) % 500) {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            btree.flush().unwrap();
+            drop(btree);
 
-            let large_value = "y".repeat(1000);
-            btree.insert(1, large_value.clone()).unwrap();
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut btree = BTree::<i64, String>::new(file, 512).unwrap();
+            // Read around `read_page`, which sets the layout this handle writes
+            let buffer = btree
+                .page_manager
+                .read_page(btree.header.root_page_id)
+                .unwrap();
+            assert!(
+                SlottedPage::<i64, String>::deserialize(&buffer, 512)
+                    .unwrap()
+                    .is_eytzinger()
+            );
 
-            assert_eq!(btree.search(1).unwrap(), large_value);
+            btree.verify().unwrap();
+            for i in 0..500 {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
+            }
+            let keys: Vec<i64> = btree.iter().unwrap().map(|e| e.unwrap().0).collect();
+            assert_eq!(keys, (0..500).collect::<Vec<_>>());
         }
+    }
 
-        #[test_log::test]
-        fn min_max_i64_keys() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+    // ─────────────────────────────────────────────────────────
+    // Value Log Tests
+    // ─────────────────────────────────────────────────────────
 
-            btree.insert(i64::MIN, "min".to_string()).unwrap();
-            btree.insert(i64::MAX, "max".to_string()).unwrap();
-            btree.insert(0, "zero".to_string()).unwrap();
+    mod value_log {
+        use super::*;
+        use crate::value_log::{ValueLogError, ValuePointer};
 
-            assert_eq!(btree.search(i64::MIN).unwrap(), "min");
-            assert_eq!(btree.search(i64::MAX).unwrap(), "max");
-            assert_eq!(btree.search(0).unwrap(), "zero");
+        fn value_log_config(threshold: u16) -> TreeConfig {
+            TreeConfig {
+                value_log_threshold: threshold,
+                ..TreeConfig::with_page_size(512)
+            }
         }
 
-        #[test_log::test]
-        fn unicode_keys() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
+        fn large_value(i: i64, len: usize) -> String {
+            format!("{:04}", i).repeat(len / 4)
+        }
 
-            btree.insert("こんにちは".to_string(), 1).unwrap();
-            btree.insert("مرحبا".to_string(), 2).unwrap();
-            btree.insert("🎉🎊🎁".to_string(), 3).unwrap();
+        // Bytes in the log of an i64 key and a `large_value` of length 1000
+        fn large_value_record() -> u64 {
+            ValuePointer {
+                segment: 0,
+                offset: 0,
+                length: 1008,
+            }
+            .record_length(8)
+        }
 
-            assert_eq!(btree.search("こんにちは".to_string()).unwrap(), 1);
-            assert_eq!(btree.search("مرحبا".to_string()).unwrap(), 2);
-            assert_eq!(btree.search("🎉🎊🎁".to_string()).unwrap(), 3);
+        #[test_log::test]
+        fn values_above_the_threshold_round_trip() {
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            // The small values serialize to as many bytes as a pointer into the log
+            for i in 0..200 {
+                btree.insert(i, large_value(i, 2000)).unwrap();
+                btree.insert(1000 + i, format!("{:04}", i)).unwrap();
+            }
+
+            btree.verify().unwrap();
+            for i in 0..200 {
+                assert_eq!(btree.search(i).unwrap(), large_value(i, 2000));
+                assert_eq!(btree.search(1000 + i).unwrap(), format!("{:04}", i));
+            }
+            let stats = btree.stats();
+            assert_eq!(stats.entries, 400);
+            assert!(stats.value_bytes > 200 * 2000);
         }
 
         #[test_log::test]
-        fn special_characters_in_keys() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
+        fn splits_only_store_the_promoted_value_again() {
+            use std::sync::atomic::{AtomicU64, Ordering};
 
-            let special_keys = vec![
-                "\n\t\r",
-                "key with spaces",
-                "key\0with\0nulls",
-                "key'with\"quotes",
-                "key\\with\\backslashes",
-            ];
+            #[derive(Default)]
+            struct SplitCounter(AtomicU64);
 
-            for (i, key) in special_keys.iter().enumerate() {
-                btree.insert(key.to_string(), i as i64).unwrap();
+            impl TreeObserver for SplitCounter {
+                fn on_split(&self, _event: &SplitEvent) {
+                    self.0.fetch_add(1, Ordering::Relaxed);
+                }
             }
 
-            for (i, key) in special_keys.iter().enumerate() {
-                assert_eq!(btree.search(key.to_string()).unwrap(), i as i64);
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            let splits = Arc::new(SplitCounter::default());
+            btree.register_observer(splits.clone());
+            for i in 0..300 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
             }
+
+            // Every value is the same size, and only the entry a split moves up to the parent
+            // is decoded and appended to the log again
+            let record_length = large_value_record();
+            let splits = splits.0.load(Ordering::Relaxed);
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert!(splits > 0);
+            assert_eq!(gc.live_bytes, 300 * record_length);
+            // Less the segment's header
+            assert_eq!(gc.total_bytes - gc.live_bytes - 8, splits * record_length);
         }
 
         #[test_log::test]
-        fn duplicate_inserts_idempotent() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn values_are_read_back_after_reopening() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            {
+                let mut btree = BTree::<i64, String>::open(&path, value_log_config(64)).unwrap();
+                for i in 0..50 {
+                    btree.insert(i, large_value(i, 1000)).unwrap();
+                }
+                btree.flush().unwrap();
+            }
+            assert!(ValueLog::path_for(&path).is_dir());
 
-            for _ in 0..100 {
-                btree.insert(1, 42).unwrap();
+            let mut btree = BTree::<i64, String>::open(&path, value_log_config(64)).unwrap();
+            for i in 0..50 {
+                assert_eq!(btree.search(i).unwrap(), large_value(i, 1000));
             }
+        }
 
-            assert_eq!(btree.search(1).unwrap(), 42);
+        #[test_log::test]
+        fn tree_without_a_log_refuses_large_values() {
+            let file = NamedTempFile::new().unwrap();
+            let mut btree =
+                BTree::<i64, String>::with_config(file.reopen().unwrap(), value_log_config(64))
+                    .unwrap();
+
+            btree.insert(1, "small".to_string()).unwrap();
+            assert!(matches!(
+                btree.insert(2, large_value(2, 1000)),
+                Err(BTreeError::ValueLog(ValueLogError::NotAttached))
+            ));
+
+            btree.set_value_log(ValueLog::in_memory());
+            btree.insert(2, large_value(2, 1000)).unwrap();
+            assert_eq!(btree.search(2).unwrap(), large_value(2, 1000));
+        }
 
-            // Should only have one entry in root
-            let root = btree.read_page(btree.header.root_page_id).unwrap();
-            assert_eq!(root.num_keys, 1);
+        #[test_log::test]
+        fn garbage_collection_removes_dead_segments() {
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+            for i in 0..40 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
+            }
+            for i in 0..40 {
+                btree.delete(i).unwrap();
+            }
+            for i in 0..40 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            let before = btree.value_log().unwrap().segments().len();
+
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert_eq!(gc.segments_removed.len(), before - 1);
+            assert_eq!(gc.live_bytes, 0);
+            assert_eq!(btree.value_log().unwrap().segments().len(), 1);
+            for i in 0..40 {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
+            }
         }
-    }
 
-    // ─────────────────────────────────────────────────────────
-    // Stress Tests
-    // ─────────────────────────────────────────────────────────
+        #[test_log::test]
+        fn live_values_keep_their_segments() {
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+            for i in 0..40 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
+            }
+            for i in (0..40).filter(|i| i % 10 != 0) {
+                btree.delete(i).unwrap();
+            }
 
-    mod stress {
-        use super::*;
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert!(!gc.segments_removed.is_empty());
+            assert!(gc.live_bytes > 0);
+            for i in (0..40).step_by(10) {
+                assert_eq!(btree.search(i).unwrap(), large_value(i, 1000));
+            }
+        }
 
         #[test_log::test]
-        fn insert_one_thousand_sequential() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+        fn snapshot_keeps_segments_it_reads() {
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+            for i in 0..40 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
+            }
+            let snapshot = btree.freeze().unwrap();
+            for i in 0..40 {
+                btree.delete(i).unwrap();
+            }
 
-            for i in 0..1000 {
-                btree.insert(i, i * 2).unwrap();
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert!(gc.segments_removed.is_empty());
+            assert_eq!(snapshot.get(&7).unwrap(), large_value(7, 1000));
+
+            drop(snapshot);
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert!(!gc.segments_removed.is_empty());
+        }
+
+        #[test_log::test]
+        fn scrub_skips_values_of_tombstones() {
+            let config = TreeConfig {
+                delete_strategy: DeleteStrategy::Tombstone,
+                ..value_log_config(64)
+            };
+            let mut btree = BTree::<i64, String>::in_memory(config).unwrap();
+            btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+            for i in 0..40 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
+            }
+            for i in 0..40 {
+                btree.delete(i).unwrap();
             }
+            assert!(
+                !btree
+                    .collect_value_log_garbage()
+                    .unwrap()
+                    .segments_removed
+                    .is_empty()
+            );
 
-            for i in 0..1000 {
-                assert_eq!(btree.search(i).unwrap(), i * 2);
+            assert!(btree.scrub(ScrubOptions::default()).unwrap().is_clean());
+        }
+
+        // Deletes two of every three values, leaving each segment mostly dead
+        fn thinned_out_tree() -> BTree<i64, String> {
+            let mut btree = BTree::<i64, String>::in_memory(value_log_config(64)).unwrap();
+            btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+            for i in 0..60 {
+                btree.insert(i, large_value(i, 1000)).unwrap();
             }
+            for i in (0..60).filter(|i| i % 3 != 0) {
+                btree.delete(i).unwrap();
+            }
+            btree
         }
 
         #[test_log::test]
-        fn insert_one_hundred_random() {
-            use rand::rng;
-            use rand::seq::SliceRandom;
-
-            let mut btree = create_temp_btree::<i64, i64>(256);
-            let mut keys: Vec<i64> = (0..100).collect();
-            keys.shuffle(&mut rng());
+        fn compaction_rewrites_mostly_dead_segments() {
+            let mut btree = thinned_out_tree();
+            let before = btree.value_log().unwrap().segments();
 
-            for &k in &keys {
-                btree.insert(k, k).unwrap();
-                println!("Insert: {:?}", k);
-                btree.print_tree();
+            let gc = btree
+                .compact_value_log(ValueLogCompactionOptions::default())
+                .unwrap();
+            assert!(!gc.segments_rewritten.is_empty());
+            assert!(gc.bytes_rewritten > 0 && gc.bytes_rewritten <= gc.live_bytes);
+            assert!(gc.segments_removed.len() >= gc.segments_rewritten.len());
+            assert_eq!(gc.live_bytes, 20 * large_value_record());
+            assert!((gc.total_bytes as f64) < gc.live_bytes as f64 * 1.5);
+            assert!(btree.value_log().unwrap().segments().len() < before.len());
+
+            btree.verify().unwrap();
+            for i in (0..60).step_by(3) {
+                assert_eq!(btree.search(i).unwrap(), large_value(i, 1000));
             }
+        }
+
+        #[test_log::test]
+        fn compaction_below_the_target_only_removes_dead_segments() {
+            let mut btree = thinned_out_tree();
+            let options = ValueLogCompactionOptions {
+                max_space_amplification: 10.0,
+                ..Default::default()
+            };
 
-            btree.print_tree();
+            let gc = btree.compact_value_log(options).unwrap();
+            assert!(gc.segments_rewritten.is_empty());
+            assert_eq!(gc.bytes_rewritten, 0);
+            assert!(gc.total_bytes > gc.live_bytes * 2);
+        }
 
-            for &k in &keys {
-                assert_eq!(btree.search(k).unwrap(), k);
-            }
+        #[test_log::test]
+        fn compaction_waits_for_snapshots() {
+            let mut btree = thinned_out_tree();
+            let snapshot = btree.freeze().unwrap();
+
+            let gc = btree
+                .compact_value_log(ValueLogCompactionOptions::default())
+                .unwrap();
+            assert!(gc.segments_rewritten.is_empty());
+            assert!(gc.segments_removed.is_empty());
+            assert_eq!(snapshot.get(&3).unwrap(), large_value(3, 1000));
         }
 
         #[test_log::test]
-        fn insert_one_thousand_random() {
-            use rand::rng;
-            use rand::seq::SliceRandom;
+        fn snapshots_holding_garbage_past_the_threshold_are_reported() {
+            use crate::events::{StaleSnapshotEvent, TreeObserver};
+            use std::sync::Mutex;
 
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-            let mut keys: Vec<i64> = (0..1000).collect();
-            keys.shuffle(&mut rng());
+            #[derive(Default)]
+            struct Alerts(Mutex<Vec<StaleSnapshotEvent>>);
 
-            for &k in &keys {
-                btree.insert(k, k).unwrap();
+            impl TreeObserver for Alerts {
+                fn on_stale_snapshot(&self, event: &StaleSnapshotEvent) {
+                    self.0.lock().unwrap().push(event.clone());
+                }
             }
-            btree.print_tree();
 
-            for &k in &keys {
-                assert_eq!(btree.search(k).unwrap(), k);
+            let mut btree = thinned_out_tree();
+            for i in (0..30).step_by(3) {
+                btree.delete(i).unwrap();
             }
+            let alerts = Arc::new(Alerts::default());
+            btree.register_observer(alerts.clone());
+            btree.set_snapshot_age_alert(Some(Duration::from_secs(3600)));
+            assert_eq!(btree.oldest_snapshot_age(), None);
+
+            let snapshot = btree.freeze().unwrap();
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert!(gc.bytes_held > 0);
+            assert!(alerts.0.lock().unwrap().is_empty());
+
+            btree.set_snapshot_age_alert(Some(Duration::ZERO));
+            btree.collect_value_log_garbage().unwrap();
+            let events = alerts.0.lock().unwrap().clone();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].snapshots, 1);
+            assert_eq!(events[0].bytes_held, gc.bytes_held);
+            assert!(btree.oldest_snapshot_age().is_some());
+
+            drop(snapshot);
+            let gc = btree.collect_value_log_garbage().unwrap();
+            assert_eq!(gc.bytes_held, 0);
+            assert!(!gc.segments_removed.is_empty());
+            assert_eq!(alerts.0.lock().unwrap().len(), 1);
         }
 
         #[test_log::test]
-        fn small_page_many_splits() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
-            for i in 0..500 {
-                btree.insert(i, i).unwrap();
+        fn compacted_values_survive_reopening() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            {
+                let mut btree = BTree::<i64, String>::open(&path, value_log_config(64)).unwrap();
+                btree.set_value_log(
+                    ValueLog::open(ValueLog::path_for(&path))
+                        .unwrap()
+                        .with_segment_size(4096),
+                );
+                for i in 0..60 {
+                    btree.insert(i, large_value(i, 1000)).unwrap();
+                }
+                for i in (0..60).filter(|i| i % 3 != 0) {
+                    btree.delete(i).unwrap();
+                }
+                let gc = btree
+                    .compact_value_log(ValueLogCompactionOptions::default())
+                    .unwrap();
+                assert!(!gc.segments_removed.is_empty());
             }
 
-            // Verify tree integrity
-            for i in 0..500 {
-                assert_eq!(btree.search(i).unwrap(), i);
+            let mut btree = BTree::<i64, String>::open(&path, value_log_config(64)).unwrap();
+            for i in (0..60).step_by(3) {
+                assert_eq!(btree.search(i).unwrap(), large_value(i, 1000));
             }
-
-            // Should have created many pages
-            assert!(btree.header.page_count > 10);
         }
+    }
 
-        #[test_log::test]
-        fn alternating_insert_and_search() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
+    // ─────────────────────────────────────────────────────────
+    // Leaf Page Size Tests
+    // ─────────────────────────────────────────────────────────
 
-            for i in 0..500 {
-                btree.insert(i, i * 10).unwrap();
+    mod leaf_page_size {
+        use super::*;
 
-                // Verify this and some previous inserts
-                assert_eq!(btree.search(i).unwrap(), i * 10);
+        fn large_leaf_config() -> TreeConfig {
+            TreeConfig {
+                leaf_page_size: 1024,
+                ..TreeConfig::with_page_size(256)
+            }
+        }
 
-                if i > 0 {
-                    assert_eq!(btree.search(i / 2).unwrap(), (i / 2) * 10);
-                }
+        // Sizes of the leaves and of the internal nodes reachable from the root
+        fn node_sizes(btree: &mut BTree<i64, String>) -> (HashSet<usize>, HashSet<usize>) {
+            let (mut leaves, mut internal) = (HashSet::new(), HashSet::new());
+            let mut pending = vec![btree.header.root_page_id];
+            while let Some(page_id) = pending.pop() {
+                let page = btree.read_page(page_id).unwrap();
+                match page.node_type {
+                    NodeType::LEAF => leaves.insert(page.page_size()),
+                    _ => internal.insert(page.page_size()),
+                };
+                pending.extend(&page.pointers);
             }
+            (leaves, internal)
         }
 
         #[test_log::test]
-        fn many_updates() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-
-            // Insert initial values
-            for i in 0..100 {
-                btree.insert(i, 0).unwrap();
+        fn leaves_span_several_pages() {
+            let file = NamedTempFile::new().unwrap();
+            let mut btree =
+                BTree::<i64, String>::with_config(file.reopen().unwrap(), large_leaf_config())
+                    .unwrap();
+            for i in (0..600).map(|i| (i * 37) % 600) {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
             }
+            btree.verify().unwrap();
 
-            // Update each key many times
-            for round in 1..=50 {
-                for i in 0..100 {
-                    btree.insert(i, round).unwrap();
-                }
-            }
+            let (leaves, internal) = node_sizes(&mut btree);
+            assert_eq!(leaves, HashSet::from([1024]));
+            assert_eq!(internal, HashSet::from([256]));
+            assert!(btree.scrub(ScrubOptions::default()).unwrap().is_clean());
+            drop(btree);
 
-            // Verify final values
-            for i in 0..100 {
-                assert_eq!(btree.search(i).unwrap(), 50);
+            // The leaf size is read from the header, whatever the caller asks for
+            let mut btree = BTree::<i64, String>::with_config(
+                file.reopen().unwrap(),
+                TreeConfig::with_page_size(256),
+            )
+            .unwrap();
+            assert_eq!(btree.config().leaf_page_size, 1024);
+            for i in 0..600 {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
             }
         }
 
         #[test_log::test]
-        #[ignore] // Run with: cargo test -- --ignored
-        fn insert_ten_thousand() {
-            let mut btree = create_temp_btree::<i64, i64>(4096);
-
-            for i in 0..10_000 {
-                btree.insert(i, i).unwrap();
+        fn merged_leaves_free_every_page() {
+            let mut btree = BTree::<i64, String>::in_memory(large_leaf_config()).unwrap();
+            for i in 0..600 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            for i in (0..600).filter(|i| i % 10 != 0) {
+                btree.delete(i).unwrap();
             }
+            btree.verify().unwrap();
+
+            let report = btree.scrub(ScrubOptions::default()).unwrap();
+            assert!(report.is_clean(), "{:?}", report.damaged);
+            assert_eq!(report.pages_scanned, btree.page_manager.page_count());
+            // Every page of a merged away leaf is marked free, not just its first
+            let free = (0..btree.page_manager.page_count())
+                .filter(|&page_id| btree.page_type(page_id).ok() == Some(NodeType::FREE))
+                .count();
+            assert!(free >= 4, "{} free pages", free);
+        }
 
-            // Spot check
-            assert_eq!(btree.search(0).unwrap(), 0);
-            assert_eq!(btree.search(5000).unwrap(), 5000);
-            assert_eq!(btree.search(9999).unwrap(), 9999);
+        #[test_log::test]
+        fn bulk_loaded_leaves_can_be_frozen_and_tiered() {
+            let mut btree = BTree::<i64, String>::in_memory(large_leaf_config()).unwrap();
+            btree
+                .bulk_load((0..800).map(|i| (i, format!("value-{:04}", i))))
+                .unwrap();
+            btree.verify().unwrap();
+            assert_eq!(node_sizes(&mut btree).0, HashSet::from([1024]));
+
+            let snapshot = btree.freeze().unwrap();
+            assert_eq!(snapshot.iter().unwrap().count(), 800);
+
+            btree.attach_cold_tier(MemoryStorage::new()).unwrap();
+            btree.page_manager.take_access_counts();
+            let report = btree.rebalance_tiers(&TieringPolicy::default()).unwrap();
+            assert!(report.demoted > 0);
+            assert!(btree.scrub(ScrubOptions::default()).unwrap().is_clean());
+            for i in (0..800).step_by(7) {
+                assert_eq!(btree.search(i).unwrap(), format!("value-{:04}", i));
+            }
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Tree Structure Tests
+    // Poisoning Tests
     // ─────────────────────────────────────────────────────────
 
-    mod tree_structure {
+    mod poisoning {
         use super::*;
+        use crate::config::WriteMode;
+        use core::cmp::Ordering;
+        use core::fmt;
+        use rand::{Rng, SeedableRng, rngs::StdRng};
+
+        // NaN is neither below nor above any key, so inserting it splits a full leaf without
+        // the key falling on either side of the one promoted
+        fn insert_nans(btree: &mut BTree<f64, String>) -> BTreeError {
+            loop {
+                if let Err(e) = btree.insert(f64::NAN, "nan".to_string()) {
+                    return e;
+                }
+            }
+        }
 
         #[test_log::test]
-        fn single_entry_stays_in_root() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn broken_invariants_poison_the_handle_and_leave_storage_alone() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("poisoned.db");
+            let config = TreeConfig {
+                write_mode: WriteMode::WriteBack,
+                on_invariant_violation: InvariantViolation::Poison,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::<f64, String>::open(&path, config).unwrap();
+            for i in 0..100 {
+                btree.insert(i as f64, format!("value-{}", i)).unwrap();
+            }
+            btree.flush().unwrap();
+            let flushed = std::fs::read(&path).unwrap();
+
+            let error = insert_nans(&mut btree);
+            assert!(matches!(error, BTreeError::Poisoned(_)), "{}", error);
+            assert!(btree.poisoned().unwrap().contains("promoted"));
+            assert!(matches!(btree.search(1.0), Err(BTreeError::Poisoned(_))));
+            assert!(matches!(btree.flush(), Err(BTreeError::Poisoned(_))));
+            assert!(matches!(btree.clear(), Err(BTreeError::Poisoned(_))));
+            drop(btree);
 
-            btree.insert(1, "one".to_string()).unwrap();
-            assert_eq!(btree.header.page_count, 1);
+            // The split may have grown the file by a zeroed page, but nothing written since the
+            // flush reached it
+            let after = std::fs::read(&path).unwrap();
+            assert_eq!(&after[..flushed.len()], &flushed[..]);
+            assert!(after[flushed.len()..].iter().all(|&b| b == 0));
+            let mut btree = BTree::<f64, String>::open(&path, config).unwrap();
+            btree.verify().unwrap();
+            assert_eq!(btree.len(), 100);
+            assert_eq!(btree.search(42.0).unwrap(), "value-42");
+        }
 
-            let root = btree.read_page(btree.header.root_page_id).unwrap();
-            assert_eq!(root.node_type, NodeType::LEAF);
-            assert_eq!(root.num_keys, 1);
+        std::thread_local! {
+            static TIERS_APART: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
         }
 
-        #[test_log::test]
-        fn tree_grows_in_height() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
+        // Orders by tier and then number, except that keys of different tiers are neither
+        // below nor above each other while `TIERS_APART` is set
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct TieredKey(u8, u32);
 
-            // Track when root changes (indicates height increase)
-            let mut root_changes = 0;
-            let mut last_root = btree.header.root_page_id;
+        impl PartialOrd for TieredKey {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                if self.0 != other.0 && TIERS_APART.with(|apart| apart.get()) {
+                    return None;
+                }
+                Some((self.0, self.1).cmp(&(other.0, other.1)))
+            }
+        }
 
-            for i in 0..200 {
-                btree.insert(i, i).unwrap();
+        impl fmt::Display for TieredKey {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}:{}", self.0, self.1)
+            }
+        }
 
-                if btree.header.root_page_id != last_root {
-                    root_changes += 1;
-                    last_root = btree.header.root_page_id;
-                }
+        #[test_log::test]
+        fn broken_invariants_write_nothing_through() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("poisoned.db");
+            let config = TreeConfig {
+                write_mode: WriteMode::WriteThrough,
+                on_invariant_violation: InvariantViolation::Poison,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::<TieredKey, String>::open(&path, config).unwrap();
+            for i in 0..50 {
+                btree.insert(TieredKey(0, i), format!("{:>30}", i)).unwrap();
             }
 
+            // Keys of the upper tier all land at the right edge, where they stay in order
+            // among themselves until one moved up meets a separator of the lower tier
+            TIERS_APART.with(|apart| apart.set(true));
+            let mut inserted = 50;
+            let error = loop {
+                match btree.insert(TieredKey(1, inserted), String::new()) {
+                    // Flushed so the stats on file count every insert that went through
+                    Ok(()) => {
+                        inserted += 1;
+                        btree.flush().unwrap();
+                    }
+                    Err(e) => break e,
+                }
+            };
+            TIERS_APART.with(|apart| apart.set(false));
+            assert!(matches!(error, BTreeError::Poisoned(_)), "{}", error);
+            assert!(btree.poisoned().unwrap().contains("internal node"));
+            drop(btree);
+
+            // None of the pages of the split that failed reached the file
+            let mut btree = BTree::<TieredKey, String>::open(&path, config).unwrap();
+            btree.verify().unwrap();
+            assert_eq!(btree.len(), inserted as u64);
+            assert_eq!(btree.search(TieredKey(0, 42)).unwrap().trim(), "42");
+        }
+
+        #[test_log::test]
+        fn entries_no_page_can_hold_are_refused_up_front() {
+            let config = TreeConfig {
+                on_invariant_violation: InvariantViolation::Poison,
+                ..TreeConfig::with_page_size(256)
+            };
+            let mut btree = BTree::<i64, String>::in_memory(config).unwrap();
+            let error = btree.insert(1, "x".repeat(230)).unwrap_err();
             assert!(
-                root_changes >= 2,
-                "Tree should have grown in height multiple times"
+                matches!(error, BTreeError::EntryTooLarge { max: 98, .. }),
+                "{}",
+                error
+            );
+
+            for i in 0..50 {
+                btree.insert(i, format!("value-{}", i)).unwrap();
+            }
+            let error = btree.insert(25, "x".repeat(230)).unwrap_err();
+            assert!(
+                matches!(error, BTreeError::EntryTooLarge { .. }),
+                "{}",
+                error
             );
+            assert!(btree.poisoned().is_none());
+            btree.verify().unwrap();
+            assert_eq!(btree.len(), 50);
+            assert_eq!(btree.search(25).unwrap(), "value-25");
         }
 
         #[test_log::test]
-        fn internal_node_has_correct_pointer_count() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
-            for i in 0..100 {
-                btree.insert(i, i).unwrap();
+        fn entries_up_to_the_limit_split_evenly() {
+            for subtree_counts in [false, true] {
+                let config = TreeConfig {
+                    subtree_counts,
+                    ..TreeConfig::with_page_size(256)
+                };
+                let mut btree = BTree::<u16, String>::in_memory(config).unwrap();
+                let max = SlottedPage::<u16, String>::max_entry_len(
+                    256,
+                    btree.header.checksums_pages(),
+                    subtree_counts,
+                );
+                // Lengths mixing entries at the limit with small ones, so splitting by count
+                // would leave one half too full for the entry being inserted. The key and the
+                // length before the value take 10 bytes.
+                let mut rng = StdRng::seed_from_u64(4996);
+                for i in 0..600u16 {
+                    let len = match rng.random_bool(0.3) {
+                        true => max - 10,
+                        false => rng.random_range(0..8),
+                    };
+                    btree
+                        .insert(rng.random_range(0..400), "x".repeat(len))
+                        .unwrap();
+                    if i % 50 == 0 {
+                        btree.verify().unwrap();
+                    }
+                }
+                btree.verify().unwrap();
             }
+        }
 
-            fn check_pointers<K, V>(btree: &mut BTree<K, V>, page_id: u64)
-            where
-                K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
-                V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
-            {
-                let page = btree.read_page(page_id).unwrap();
+        #[test]
+        #[should_panic(expected = "invariant violated")]
+        fn broken_invariants_panic_by_default() {
+            let mut btree = create_temp_btree::<f64, String>(256);
+            insert_nans(&mut btree);
+        }
+    }
 
-                if page.node_type == NodeType::INTERNAL {
-                    // Internal nodes should have num_keys + 1 pointers
-                    assert_eq!(
-                        page.pointers.len(),
-                        page.num_keys as usize + 1,
-                        "Page {} has {} keys but {} pointers",
-                        page_id,
-                        page.num_keys,
-                        page.pointers.len()
-                    );
+    // ─────────────────────────────────────────────────────────
+    // Backpressure Tests
+    // ─────────────────────────────────────────────────────────
 
-                    // Recursively check children
-                    for &ptr in &page.pointers {
-                        check_pointers(btree, ptr);
-                    }
-                } else {
-                    // Leaf nodes should have no pointers
-                    assert!(
-                        page.pointers.is_empty(),
-                        "Leaf page {} should have no pointers",
-                        page_id
-                    );
-                }
+    mod backpressure {
+        use super::*;
+        use core::time::Duration;
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct StallObserver {
+            stalls: Mutex<Vec<u64>>,
+        }
+
+        impl TreeObserver for StallObserver {
+            fn on_write_stall(&self, event: &WriteStallEvent) {
+                self.stalls.lock().unwrap().push(event.dirty_pages);
             }
+        }
 
-            let root_page_id = btree.header.root_page_id;
-            check_pointers(&mut btree, root_page_id);
+        fn limited_btree(backpressure: Backpressure) -> BTree<i64, String> {
+            let config = TreeConfig {
+                max_dirty_pages: 8,
+                backpressure,
+                ..TreeConfig::with_page_size(256)
+            };
+            BTree::in_memory(config).unwrap()
         }
 
         #[test_log::test]
-        fn all_leaves_at_same_depth() {
-            let mut btree = create_temp_btree::<i64, i64>(256);
-
+        fn writers_stall_to_flush_dirty_pages() {
+            let mut btree = limited_btree(Backpressure::Stall);
+            let observer = Arc::new(StallObserver::default());
+            btree.register_observer(observer.clone());
+
+            for i in 0..300 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+                // A single insert, splits and all, only writes a few pages past the limit
+                assert!(btree.dirty_pages() < 16, "{} dirty", btree.dirty_pages());
+            }
             for i in 0..100 {
-                btree.insert(i, i).unwrap();
+                btree.delete(i).unwrap();
             }
 
-            fn get_leaf_depths<K, V>(
-                btree: &mut BTree<K, V>,
-                page_id: u64,
-                depth: usize,
-            ) -> Vec<usize>
-            where
-                K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
-                V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
-            {
-                let page = btree.read_page(page_id).unwrap();
+            let stalls = observer.stalls.lock().unwrap();
+            assert!(!stalls.is_empty());
+            assert!(stalls.iter().all(|&dirty_pages| dirty_pages >= 8));
+            btree.verify().unwrap();
+        }
 
-                if page.node_type == NodeType::LEAF {
-                    vec![depth]
-                } else {
-                    page.pointers
-                        .iter()
-                        .flat_map(|&ptr| get_leaf_depths(btree, ptr, depth + 1))
-                        .collect()
+        #[test_log::test]
+        fn writes_are_rejected_until_flushed() {
+            let retry_after = Duration::from_millis(10);
+            let mut btree = limited_btree(Backpressure::Reject { retry_after });
+
+            let mut inserted = 0;
+            let rejected = loop {
+                match btree.insert(inserted, format!("value-{:04}", inserted)) {
+                    Ok(()) => inserted += 1,
+                    Err(e) => break e,
                 }
-            }
+            };
+            assert!(matches!(
+                rejected,
+                BTreeError::Busy { retry_after: hint } if hint == retry_after
+            ));
+            assert!(matches!(btree.delete(0), Err(BTreeError::Busy { .. })));
+            assert_eq!(btree.len(), inserted as u64);
+
+            btree.flush().unwrap();
+            assert_eq!(btree.dirty_pages(), 0);
+            btree.insert(inserted, "value".to_string()).unwrap();
+            assert_eq!(btree.delete(0).unwrap(), "value-0000");
+        }
 
-            let root_page_id = btree.header.root_page_id;
-            let depths = get_leaf_depths(&mut btree, root_page_id, 0);
+        #[test_log::test]
+        fn limit_can_be_changed_on_an_open_tree() {
+            let mut btree = limited_btree(Backpressure::Stall);
+            btree
+                .set_config(TreeConfig {
+                    max_dirty_pages: 0,
+                    ..btree.config()
+                })
+                .unwrap();
 
-            // All leaves should be at the same depth
-            let first_depth = depths[0];
-            assert!(
-                depths.iter().all(|&d| d == first_depth),
-                "Leaf depths are not uniform: {:?}",
-                depths
-            );
+            for i in 0..300 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            assert!(btree.dirty_pages() > 8);
+            assert_eq!(btree.config().backpressure, Backpressure::Stall);
         }
     }
 
     // ─────────────────────────────────────────────────────────
-    // Error Handling Tests
+    // Merkle Hash Tests
     // ─────────────────────────────────────────────────────────
 
-    mod error_handling {
+    mod merkle_hashes {
         use super::*;
+        use rand::Rng;
 
-        #[test_log::test]
-        fn search_returns_key_not_found_error() {
-            let mut btree = create_temp_btree::<i64, String>(4096);
+        fn hashed_btree() -> BTree<i64, String> {
+            let mut btree = BTree::in_memory(TreeConfig::with_page_size(256)).unwrap();
+            btree.set_merkle_hashes(true);
+            btree
+        }
 
-            btree.insert(1, "one".to_string()).unwrap();
+        // Hashes the whole tree again, ignoring any kept hashes
+        fn full_hash(btree: &mut BTree<i64, String>) -> Hash {
+            btree.set_merkle_hashes(false);
+            let hash = btree.root_hash().unwrap();
+            btree.set_merkle_hashes(true);
+            hash
+        }
 
-            match btree.search(999) {
-                Err(BTreeError::KeyNotFound(key)) => {
-                    assert_eq!(key, "999");
-                }
-                other => panic!("Expected KeyNotFound, got {:?}", other),
+        #[test_log::test]
+        fn root_hash_changes_with_every_write() {
+            let mut btree = hashed_btree();
+            for i in 0..200 {
+                btree.insert(i, format!("value-{:04}", i)).unwrap();
             }
+            let before = btree.root_hash().unwrap();
+            assert_eq!(btree.root_hash().unwrap(), before);
+
+            btree.insert(150, "value-XXXX".to_string()).unwrap();
+            let updated = btree.root_hash().unwrap();
+            assert_ne!(updated, before);
+
+            btree.insert(150, "value-0150".to_string()).unwrap();
+            assert_eq!(btree.root_hash().unwrap(), before);
+
+            btree.delete(7).unwrap();
+            assert_ne!(btree.root_hash().unwrap(), before);
         }
 
         #[test_log::test]
-        fn search_nonexistent_key_with_string() {
-            let mut btree = create_temp_btree::<String, i64>(4096);
+        fn kept_hashes_match_hashing_the_whole_tree() {
+            let mut btree = hashed_btree();
+            let mut rng = rand::rng();
+            for round in 0..20 {
+                for _ in 0..50 {
+                    let key = rng.random_range(0..500);
+                    match rng.random_bool(0.7) {
+                        true => btree.insert(key, format!("value-{:04}", round)).unwrap(),
+                        false => _ = btree.delete(key),
+                    }
+                }
+                let hash = btree.root_hash().unwrap();
+                assert_eq!(hash, full_hash(&mut btree), "round {}", round);
+            }
 
-            btree.insert("exists".to_string(), 1).unwrap();
+            btree.clear().unwrap();
+            btree.insert(1, "value-0001".to_string()).unwrap();
+            let hash = btree.root_hash().unwrap();
+            assert_eq!(hash, full_hash(&mut btree));
+        }
 
-            match btree.search("missing".to_string()) {
-                Err(BTreeError::KeyNotFound(key)) => {
-                    assert_eq!(key, "missing");
+        #[test_log::test]
+        fn child_hashes_lead_to_the_page_that_differs() {
+            let mut primary = hashed_btree();
+            let mut replica = hashed_btree();
+            for i in 0..300 {
+                primary.insert(i, format!("value-{:04}", i)).unwrap();
+                replica.insert(i, format!("value-{:04}", i)).unwrap();
+            }
+            assert_eq!(primary.root_hash().unwrap(), replica.root_hash().unwrap());
+
+            replica.insert(123, "value-XXXX".to_string()).unwrap();
+            assert_ne!(primary.root_hash().unwrap(), replica.root_hash().unwrap());
+
+            // Follow the differing child down until the page holding the change, which need
+            // not be a leaf
+            let mut page_id = primary.root_page_id();
+            loop {
+                let ours = primary.child_hashes(page_id).unwrap();
+                let theirs = replica.child_hashes(page_id).unwrap();
+                assert_eq!(ours.len(), theirs.len());
+                let differing: Vec<u64> = ours
+                    .iter()
+                    .zip(&theirs)
+                    .filter(|(a, b)| a != b)
+                    .map(|(a, _)| a.0)
+                    .collect();
+                assert!(differing.len() <= 1, "under page {}", page_id);
+                match differing.first() {
+                    Some(&child) => page_id = child,
+                    None => break,
                 }
-                other => panic!("Expected KeyNotFound, got {:?}", other),
             }
+            let page = primary.read_page(page_id).unwrap();
+            assert!(page.find_exact_key(&123).unwrap().is_some());
         }
     }
 }
@@ -0,0 +1,129 @@
+//! Where the page-sized buffers that pages are read into and serialized to come from, so
+//! embedders churning through pages can reuse them instead of allocating one per read and
+//! write.
+
+use crate::sync::Mutex;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A source of page buffers. Buffers are handed back once the page manager or tree is done
+/// with them; ones kept elsewhere, such as by the page cache or as the data of a decoded
+/// page, are not.
+pub trait BufferPool: Send + Sync {
+    /// A buffer of exactly `len` bytes. Its contents are whatever it last held, as every
+    /// caller overwrites the whole of it.
+    fn take(&self, len: usize) -> Vec<u8>;
+
+    /// Hands back a buffer taken from this pool that is no longer needed.
+    fn give_back(&self, buffer: Vec<u8>);
+
+    /// Bytes allocated for buffers handed back and kept for reuse.
+    fn retained_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// Allocates every buffer afresh and frees those handed back. The default.
+#[derive(Debug, Default)]
+pub struct FreshBuffers;
+
+impl BufferPool for FreshBuffers {
+    fn take(&self, len: usize) -> Vec<u8> {
+        vec![0; len]
+    }
+
+    fn give_back(&self, _buffer: Vec<u8>) {}
+}
+
+/// Keeps up to `capacity` buffers handed back and reuses them for later ones. A buffer is
+/// reused for any length, growing it if it is too short, so trees whose leaves are larger
+/// than their internal nodes keep the larger allocation around.
+#[derive(Debug)]
+pub struct PagePool {
+    free: Mutex<Free>,
+    capacity: usize,
+}
+
+// Counted under the same lock as the buffers, as not every target has 64-bit atomics
+#[derive(Debug)]
+struct Free {
+    buffers: Vec<Vec<u8>>,
+    reused: u64,
+}
+
+impl PagePool {
+    pub fn new(capacity: usize) -> Self {
+        PagePool {
+            free: Mutex::new(Free {
+                buffers: Vec::with_capacity(capacity),
+                reused: 0,
+            }),
+            capacity,
+        }
+    }
+
+    /// Buffers handed out that came from an earlier one rather than a new allocation.
+    pub fn reused(&self) -> u64 {
+        self.free.lock().reused
+    }
+
+    /// Buffers waiting to be reused.
+    pub fn len(&self) -> usize {
+        self.free.lock().buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl BufferPool for PagePool {
+    fn take(&self, len: usize) -> Vec<u8> {
+        let mut free = self.free.lock();
+        let Some(mut buffer) = free.buffers.pop() else {
+            return vec![0; len];
+        };
+        free.reused += 1;
+        drop(free);
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    fn give_back(&self, buffer: Vec<u8>) {
+        let mut free = self.free.lock();
+        if free.buffers.len() < self.capacity {
+            free.buffers.push(buffer);
+        }
+    }
+
+    fn retained_bytes(&self) -> usize {
+        let free = self.free.lock();
+        free.buffers.iter().map(Vec::capacity).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_reuses_buffers_up_to_its_capacity() {
+        let pool = PagePool::new(2);
+        let buffers: Vec<Vec<u8>> = (0..3).map(|_| pool.take(128)).collect();
+        let addresses: Vec<*const u8> = buffers.iter().map(|b| b.as_ptr()).collect();
+        for buffer in buffers {
+            pool.give_back(buffer);
+        }
+        assert_eq!(pool.len(), 2);
+        assert!(pool.retained_bytes() >= 2 * 128);
+
+        let reused = pool.take(64);
+        assert_eq!(reused.len(), 64);
+        assert!(addresses.contains(&reused.as_ptr()));
+        assert_eq!(pool.take(256).len(), 256);
+        assert_eq!(pool.take(128).len(), 128);
+        assert_eq!(pool.reused(), 2);
+        assert!(pool.is_empty());
+        assert_eq!(pool.retained_bytes(), 0);
+    }
+}
@@ -0,0 +1,308 @@
+//! The indexes making up a database directory, each kept in a file of its own, listed in a tree
+//! of their own so they can be found by name when the database is opened again.
+
+use crate::btree::BTree;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::path::{Path, PathBuf};
+
+/// The kinds of index a database can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexKind {
+    /// A [`BTree`], ordered by key.
+    BTree,
+    /// A [`crate::hash_index::HashIndex`], for point lookups only.
+    Hash,
+    /// A [`crate::heap_file::HeapFile`] of records without keys.
+    Heap,
+}
+
+impl IndexKind {
+    fn extension(self) -> &'static str {
+        match self {
+            IndexKind::BTree => "tree",
+            IndexKind::Hash => "hash",
+            IndexKind::Heap => "heap",
+        }
+    }
+}
+
+/// What the catalog records about an index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub name: String,
+    pub kind: IndexKind,
+    /// Data file, relative to the database directory.
+    pub file: String,
+    /// Root page of a tree as last recorded; 0 for other kinds.
+    pub root_page_id: u64,
+    /// Names of the key and value types, which a handle opening the index must match. Heaps
+    /// have no keys and record `()`. Taken from [`std::any::type_name`], so they may change
+    /// between compiler versions.
+    pub key_codec: String,
+    pub value_codec: String,
+    /// Configuration the index was created with; only the page size applies to other kinds
+    /// than trees.
+    pub config: TreeConfig,
+}
+
+impl IndexEntry {
+    /// Describes a new index of `kind` named `name`, with keys of type `K` and values of type
+    /// `V`, stored in a file named after it.
+    pub fn new<K, V>(name: &str, kind: IndexKind, config: TreeConfig) -> Self {
+        IndexEntry {
+            name: name.to_string(),
+            kind,
+            file: format!("{}.{}", name, kind.extension()),
+            root_page_id: 0,
+            key_codec: std::any::type_name::<K>().to_string(),
+            value_codec: std::any::type_name::<V>().to_string(),
+            config,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Tree(BTreeError),
+    /// Index names become file names, so they may not be empty or hold path separators.
+    InvalidName(String),
+    WrongKind {
+        name: String,
+        expected: IndexKind,
+        found: IndexKind,
+    },
+    WrongCodec {
+        name: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl std::fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CatalogError::Tree(e) => {
+                write!(f, "Catalog tree error: {}", e)
+            }
+            CatalogError::InvalidName(name) => {
+                write!(f, "Invalid index name: {:?}", name)
+            }
+            CatalogError::WrongKind {
+                name,
+                expected,
+                found,
+            } => {
+                write!(
+                    f,
+                    "Index {} is a {:?} index, not a {:?} index",
+                    name, found, expected
+                )
+            }
+            CatalogError::WrongCodec {
+                name,
+                expected,
+                found,
+            } => {
+                write!(f, "Index {} holds {}, not {}", name, found, expected)
+            }
+        }
+    }
+}
+
+impl From<BTreeError> for CatalogError {
+    fn from(err: BTreeError) -> CatalogError {
+        CatalogError::Tree(err)
+    }
+}
+
+/// Every index in a database directory, loaded in full when the catalog is opened.
+pub struct Catalog {
+    dir: PathBuf,
+    tree: BTree<String, IndexEntry>,
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+impl Catalog {
+    /// Name of the catalog's own file in the database directory.
+    pub const FILE_NAME: &str = "catalog.db";
+
+    /// Opens the catalog of the database in `dir`, creating the directory and an empty
+    /// catalog if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, CatalogError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir).map_err(BTreeError::from)?;
+        let mut tree = BTree::open(dir.join(Self::FILE_NAME), TreeConfig::default())?;
+        let entries = tree
+            .iter()?
+            .collect::<Result<BTreeMap<String, IndexEntry>, BTreeError>>()?;
+        Ok(Catalog { dir, tree, entries })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IndexEntry> {
+        self.entries.get(name)
+    }
+
+    /// Every index, in name order.
+    pub fn indexes(&self) -> Values<'_, String, IndexEntry> {
+        self.entries.values()
+    }
+
+    /// Path of the data file of `entry`.
+    pub fn path_of(&self, entry: &IndexEntry) -> PathBuf {
+        self.dir.join(&entry.file)
+    }
+
+    /// The entry for `name`, if there is one, after checking it is of `kind` and holds keys of
+    /// type `K` and values of type `V`.
+    pub fn find<K, V>(
+        &self,
+        name: &str,
+        kind: IndexKind,
+    ) -> Result<Option<&IndexEntry>, CatalogError> {
+        let Some(entry) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        if entry.kind != kind {
+            return Err(CatalogError::WrongKind {
+                name: name.to_string(),
+                expected: kind,
+                found: entry.kind,
+            });
+        }
+        for (expected, found) in [
+            (std::any::type_name::<K>(), &entry.key_codec),
+            (std::any::type_name::<V>(), &entry.value_codec),
+        ] {
+            if expected != found {
+                return Err(CatalogError::WrongCodec {
+                    name: name.to_string(),
+                    expected: expected.to_string(),
+                    found: found.clone(),
+                });
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    /// Fails with `InvalidName` unless `name` can be an index's name.
+    pub fn check_name(name: &str) -> Result<(), CatalogError> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(CatalogError::InvalidName(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Adds `entry`, or replaces the entry of the same name.
+    pub fn record(&mut self, entry: IndexEntry) -> Result<(), CatalogError> {
+        Self::check_name(&entry.name)?;
+        let name = &entry.name;
+        if self.entries.get(name) == Some(&entry) {
+            return Ok(());
+        }
+        self.tree.insert(name.clone(), entry.clone())?;
+        self.entries.insert(name.clone(), entry);
+        Ok(())
+    }
+
+    /// Drops the entry for `name`, returning it. The index's file is left for the caller.
+    pub fn remove(&mut self, name: &str) -> Result<Option<IndexEntry>, CatalogError> {
+        let Some(entry) = self.entries.remove(name) else {
+            return Ok(None);
+        };
+        self.tree.delete(name.to_string())?;
+        Ok(Some(entry))
+    }
+
+    /// Next value of the sequence `name`, kept in the catalog's own file; see
+    /// [`BTree::next_sequence`].
+    pub fn next_sequence(&mut self, name: &str) -> Result<u64, CatalogError> {
+        Ok(self.tree.next_sequence(name)?)
+    }
+
+    pub fn flush(&mut self) -> Result<(), CatalogError> {
+        Ok(self.tree.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_loaded_when_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::open(dir.path()).unwrap();
+        let mut users =
+            IndexEntry::new::<u64, String>("users", IndexKind::BTree, TreeConfig::default());
+        users.root_page_id = 12;
+        catalog.record(users.clone()).unwrap();
+        catalog
+            .record(IndexEntry::new::<String, u64>(
+                "emails",
+                IndexKind::Hash,
+                TreeConfig::with_page_size(512),
+            ))
+            .unwrap();
+        catalog.flush().unwrap();
+        drop(catalog);
+
+        let mut catalog = Catalog::open(dir.path()).unwrap();
+        let names: Vec<&str> = catalog.indexes().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["emails", "users"]);
+        assert_eq!(catalog.get("users"), Some(&users));
+        assert_eq!(catalog.path_of(&users), dir.path().join("users.tree"));
+
+        assert!(catalog.remove("emails").unwrap().is_some());
+        assert_eq!(catalog.indexes().count(), 1);
+    }
+
+    #[test]
+    fn indexes_are_found_only_as_what_they_hold() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::open(dir.path()).unwrap();
+        catalog
+            .record(IndexEntry::new::<u64, String>(
+                "users",
+                IndexKind::BTree,
+                TreeConfig::default(),
+            ))
+            .unwrap();
+
+        assert!(
+            catalog
+                .find::<u64, String>("users", IndexKind::BTree)
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            catalog
+                .find::<u64, String>("other", IndexKind::BTree)
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            catalog.find::<u64, String>("users", IndexKind::Hash),
+            Err(CatalogError::WrongKind { .. })
+        ));
+        assert!(matches!(
+            catalog.find::<u32, String>("users", IndexKind::BTree),
+            Err(CatalogError::WrongCodec { .. })
+        ));
+        assert!(matches!(
+            catalog.record(IndexEntry::new::<u64, u64>(
+                "a/b",
+                IndexKind::Heap,
+                TreeConfig::default()
+            )),
+            Err(CatalogError::InvalidName(_))
+        ));
+    }
+}
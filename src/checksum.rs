@@ -0,0 +1,48 @@
+//! CRC-32 (IEEE) used to detect torn or corrupted pages and manifests.
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+// One entry per byte value, so the checksum is computed a byte at a time rather than a bit
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (POLYNOMIAL & mask);
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc = (crc >> 8) ^ TABLE[((crc ^ byte as u32) & 0xFF) as usize];
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc32_detects_a_single_flipped_bit() {
+        let mut bytes = vec![0xA5u8; 4096];
+        let original = crc32(&bytes);
+        bytes[1234] ^= 0x10;
+        assert_ne!(crc32(&bytes), original);
+    }
+}
@@ -0,0 +1,23 @@
+//! The clock that import progress and snapshot ages are measured with. Without `std` there is
+//! none, except in the browser, so every instant is the same one and every age is zero.
+
+#[cfg(all(feature = "std", not(all(feature = "wasm", target_arch = "wasm32"))))]
+pub(crate) use std::time::Instant;
+// std's clock panics in the browser
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) use web_time::Instant;
+
+#[cfg(not(any(feature = "std", all(feature = "wasm", target_arch = "wasm32"))))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Instant;
+
+#[cfg(not(any(feature = "std", all(feature = "wasm", target_arch = "wasm32"))))]
+impl Instant {
+    pub(crate) fn now() -> Instant {
+        Instant
+    }
+
+    pub(crate) fn elapsed(&self) -> core::time::Duration {
+        core::time::Duration::ZERO
+    }
+}
@@ -0,0 +1,284 @@
+//! How the keys and values of a tree are turned into the bytes its pages store.
+//!
+//! Keys and values go through serde in the tree's [`Encoding`], chosen when the tree is created
+//! and recorded in its header, so the on-disk format is not tied to one encoding's defaults.
+//! Values can instead be handed to a [`ValueCodec`] of the caller's, for types serde describes
+//! poorly or formats fixed elsewhere; its id is recorded in the header too, and a tree written
+//! with one can only read its values while the same codec is set.
+
+use crate::error::BTreeError;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bincode::config;
+use bincode::enc::write::SizeWriter;
+use bincode::error::{DecodeError, EncodeError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// Values are encoded by the custom codec with this id, and none is set on the tree.
+    NotAttached(u8),
+    /// The tree's values are encoded by the custom codec `expected`, not `got`.
+    WrongCodec { expected: u8, got: u8 },
+    /// Custom codecs cannot use id 0, which means the tree has none.
+    ReservedId,
+    /// A custom codec failed to encode or decode a value.
+    Custom(String),
+}
+
+impl core::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            CodecError::NotAttached(id) => {
+                write!(
+                    f,
+                    "Values are encoded by custom codec {}, which is not set",
+                    id
+                )
+            }
+            CodecError::WrongCodec { expected, got } => {
+                write!(
+                    f,
+                    "Values are encoded by custom codec {}, not {}",
+                    expected, got
+                )
+            }
+            CodecError::ReservedId => {
+                write!(f, "Custom codec id 0 is reserved")
+            }
+            CodecError::Custom(msg) => {
+                write!(f, "Custom codec error: {}", msg)
+            }
+        }
+    }
+}
+
+/// Why a key or value could not be turned into bytes in the tree's [`Encoding`], or back.
+#[derive(Debug)]
+pub enum SerializationError {
+    Encode(EncodeError),
+    Decode(DecodeError),
+}
+
+impl core::fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SerializationError::Encode(e) => write!(f, "{}", e),
+            SerializationError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<EncodeError> for SerializationError {
+    fn from(err: EncodeError) -> SerializationError {
+        SerializationError::Encode(err)
+    }
+}
+
+impl From<DecodeError> for SerializationError {
+    fn from(err: DecodeError) -> SerializationError {
+        SerializationError::Decode(err)
+    }
+}
+
+/// The serde formats keys and values can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    /// bincode with fixed-width integers, as every tree was written before encodings could be
+    /// chosen.
+    #[default]
+    Bincode,
+    /// bincode with variable-length integers and lengths, so small numbers take a byte or two.
+    /// Suits keys and values made mostly of integers that rarely use their full width.
+    BincodeVarint,
+}
+
+impl Encoding {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Encoding::Bincode => 0,
+            Encoding::BincodeVarint => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Encoding::Bincode),
+            1 => Some(Encoding::BincodeVarint),
+            _ => None,
+        }
+    }
+
+    // Both write exactly the bytes bincode 1 did with the same options, which every existing
+    // tree was written with
+    pub fn serialize<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Vec<u8>, SerializationError> {
+        let bytes = match self {
+            Encoding::Bincode => bincode::serde::encode_to_vec(value, config::legacy())?,
+            Encoding::BincodeVarint => bincode::serde::encode_to_vec(value, config::standard())?,
+        };
+        Ok(bytes)
+    }
+
+    pub fn serialized_size<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<u64, SerializationError> {
+        let mut size = SizeWriter::default();
+        match self {
+            Encoding::Bincode => {
+                bincode::serde::encode_into_writer(value, &mut size, config::legacy())?
+            }
+            Encoding::BincodeVarint => {
+                bincode::serde::encode_into_writer(value, &mut size, config::standard())?
+            }
+        }
+        Ok(size.bytes_written as u64)
+    }
+
+    /// Decodes `bytes`, which borrowing types such as `&str` can point into without copying.
+    /// Fixed-width decoding ignores bytes left over after the value, and variable-length
+    /// decoding rejects them.
+    pub fn deserialize<'a, T: Deserialize<'a>>(
+        self,
+        bytes: &'a [u8],
+    ) -> Result<T, SerializationError> {
+        match self {
+            Encoding::Bincode => {
+                Ok(bincode::serde::borrow_decode_from_slice(bytes, config::legacy())?.0)
+            }
+            Encoding::BincodeVarint => {
+                let (value, read) =
+                    bincode::serde::borrow_decode_from_slice(bytes, config::standard())?;
+                if read < bytes.len() {
+                    return Err(DecodeError::Other("trailing bytes after the value").into());
+                }
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// Encodes the values of a tree in place of its [`Encoding`], set with
+/// [`BTree::set_value_codec`](crate::BTree::set_value_codec). Keys still use the encoding.
+pub trait ValueCodec<V>: Send + Sync {
+    /// Recorded in the header of trees whose values this codec encodes, so they are never read
+    /// with another. Must not be 0, and must change whenever the bytes it writes do.
+    fn id(&self) -> u8;
+
+    fn encode(&self, value: &V) -> Result<Vec<u8>, CodecError>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<V, CodecError>;
+}
+
+// What a page needs to encode and decode its keys and values
+pub(crate) struct Codecs<V> {
+    encoding: Encoding,
+    // Id of the custom codec the tree's values are encoded by, 0 for none
+    value_codec_id: u8,
+    value_codec: Option<Arc<dyn ValueCodec<V>>>,
+}
+
+impl<V> Default for Codecs<V> {
+    fn default() -> Self {
+        Codecs {
+            encoding: Encoding::default(),
+            value_codec_id: 0,
+            value_codec: None,
+        }
+    }
+}
+
+impl<V> Clone for Codecs<V> {
+    fn clone(&self) -> Self {
+        Codecs {
+            encoding: self.encoding,
+            value_codec_id: self.value_codec_id,
+            value_codec: self.value_codec.clone(),
+        }
+    }
+}
+
+impl<V> Codecs<V>
+where
+    V: Serialize + for<'de> Deserialize<'de>,
+{
+    pub(crate) fn new(
+        encoding: Encoding,
+        value_codec_id: u8,
+        value_codec: Option<Arc<dyn ValueCodec<V>>>,
+    ) -> Self {
+        Codecs {
+            encoding,
+            value_codec_id,
+            value_codec,
+        }
+    }
+
+    pub(crate) fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub(crate) fn value_codec_id(&self) -> u8 {
+        self.value_codec_id
+    }
+
+    // The custom codec values go through, if the tree has one
+    fn value_codec(&self) -> Result<Option<&dyn ValueCodec<V>>, CodecError> {
+        if self.value_codec_id == 0 {
+            return Ok(None);
+        }
+        match &self.value_codec {
+            Some(codec) => Ok(Some(codec.as_ref())),
+            None => Err(CodecError::NotAttached(self.value_codec_id)),
+        }
+    }
+
+    pub(crate) fn encode_value(&self, value: &V) -> Result<Vec<u8>, BTreeError> {
+        match self.value_codec()? {
+            Some(codec) => Ok(codec.encode(value)?),
+            None => Ok(self.encoding.serialize(value)?),
+        }
+    }
+
+    pub(crate) fn decode_value(&self, bytes: &[u8]) -> Result<V, BTreeError> {
+        match self.value_codec()? {
+            Some(codec) => Ok(codec.decode(bytes)?),
+            None => Ok(self.encoding.deserialize(bytes)?),
+        }
+    }
+
+    pub(crate) fn value_len(&self, value: &V) -> Result<u64, BTreeError> {
+        match self.value_codec()? {
+            Some(codec) => Ok(codec.encode(value)?.len() as u64),
+            None => Ok(self.encoding.serialized_size(value)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_encoding_is_smaller_for_small_integers() {
+        let value = (7u64, vec![1u32, 2, 3], "key".to_string());
+        let fixed = Encoding::Bincode.serialize(&value).unwrap();
+        let varint = Encoding::BincodeVarint.serialize(&value).unwrap();
+        assert!(varint.len() < fixed.len());
+        assert_eq!(
+            Encoding::BincodeVarint.serialized_size(&value).unwrap(),
+            varint.len() as u64
+        );
+
+        let decoded: (u64, Vec<u32>, String) =
+            Encoding::BincodeVarint.deserialize(&varint).unwrap();
+        assert_eq!(decoded, value);
+        for encoding in [Encoding::Bincode, Encoding::BincodeVarint] {
+            assert_eq!(Encoding::from_byte(encoding.to_byte()), Some(encoding));
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! Hash maps and sets from std, or from hashbrown when building without it.
+
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
@@ -0,0 +1,227 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+#[cfg(feature = "zstd")]
+use zstd::bulk::{Compressor, Decompressor};
+#[cfg(feature = "zstd")]
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+#[derive(Debug)]
+pub enum CompressionError {
+    Io(crate::io::Error),
+    MissingDictionary,
+    NotEnoughSamples {
+        expected: usize,
+        got: usize,
+    },
+    /// Built without the `zstd` feature.
+    Unsupported,
+}
+
+impl core::fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            CompressionError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            CompressionError::MissingDictionary => {
+                write!(f, "Value is compressed but no dictionary is loaded")
+            }
+            CompressionError::NotEnoughSamples { expected, got } => {
+                write!(
+                    f,
+                    "Not enough samples to train a dictionary: expected at least {}, got {}",
+                    expected, got
+                )
+            }
+            CompressionError::Unsupported => {
+                write!(f, "Value compression needs the zstd feature")
+            }
+        }
+    }
+}
+
+impl From<crate::io::Error> for CompressionError {
+    fn from(err: crate::io::Error) -> CompressionError {
+        CompressionError::Io(err)
+    }
+}
+
+/// Compresses individual values with a zstd dictionary shared by the whole tree.
+///
+/// Small values that look alike (JSON documents, serialized structs) barely compress on their
+/// own; a dictionary trained on a sample of them captures the common structure once.
+///
+/// Without the `zstd` feature a tree's dictionary is still loaded, so the tree opens, but
+/// values are written uncompressed and reading a compressed one fails with `Unsupported`.
+pub struct ValueCompressor {
+    dictionary: Vec<u8>,
+    level: i32,
+    #[cfg(feature = "zstd")]
+    encoder: EncoderDictionary<'static>,
+    #[cfg(feature = "zstd")]
+    decoder: DecoderDictionary<'static>,
+}
+
+impl ValueCompressor {
+    pub const DEFAULT_LEVEL: i32 = 3;
+    pub const MIN_SAMPLES: usize = 8;
+
+    pub fn new(dictionary: Vec<u8>, level: i32) -> Self {
+        ValueCompressor {
+            #[cfg(feature = "zstd")]
+            encoder: EncoderDictionary::copy(&dictionary, level),
+            #[cfg(feature = "zstd")]
+            decoder: DecoderDictionary::copy(&dictionary),
+            dictionary,
+            level,
+        }
+    }
+
+    /// Trains a dictionary of at most `max_dictionary_size` bytes from `samples`.
+    pub fn train<S: AsRef<[u8]>>(
+        samples: &[S],
+        max_dictionary_size: usize,
+    ) -> Result<Self, CompressionError> {
+        if samples.len() < Self::MIN_SAMPLES {
+            return Err(CompressionError::NotEnoughSamples {
+                expected: Self::MIN_SAMPLES,
+                got: samples.len(),
+            });
+        }
+
+        #[cfg(feature = "zstd")]
+        {
+            let dictionary = zstd::dict::from_samples(samples, max_dictionary_size)?;
+            Ok(Self::new(dictionary, Self::DEFAULT_LEVEL))
+        }
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = max_dictionary_size;
+            Err(CompressionError::Unsupported)
+        }
+    }
+
+    pub fn dictionary(&self) -> &[u8] {
+        &self.dictionary
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    #[cfg(feature = "zstd")]
+    pub fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut compressor = Compressor::with_prepared_dictionary(&self.encoder)?;
+        Ok(compressor.compress(bytes)?)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub fn compress(&self, _bytes: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Err(CompressionError::Unsupported)
+    }
+
+    #[cfg(feature = "zstd")]
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut decompressor = Decompressor::with_prepared_dictionary(&self.decoder)?;
+        let capacity = Decompressor::upper_bound(bytes).unwrap_or(bytes.len() * 8);
+        Ok(decompressor.decompress(bytes, capacity)?)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub fn decompress(&self, _bytes: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Err(CompressionError::Unsupported)
+    }
+
+    /// Compresses `bytes` only when doing so actually saves space, which without the `zstd`
+    /// feature is never.
+    pub fn compress_if_smaller(&self, bytes: &[u8]) -> Result<Option<Vec<u8>>, CompressionError> {
+        if cfg!(not(feature = "zstd")) {
+            return Ok(None);
+        }
+        let compressed = self.compress(bytes)?;
+        if compressed.len() < bytes.len() {
+            Ok(Some(compressed))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Debug for ValueCompressor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ValueCompressor")
+            .field("dictionary_len", &self.dictionary.len())
+            .field("level", &self.level)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::*;
+
+    fn json_samples(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| {
+                format!(
+                    "{{\"id\":{},\"name\":\"user_{}\",\"email\":\"user_{}@example.com\",\"active\":{}}}",
+                    i,
+                    i,
+                    i,
+                    i % 2 == 0
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn train_rejects_too_few_samples() {
+        let samples = json_samples(2);
+        let result = ValueCompressor::train(&samples, 1024);
+
+        assert!(matches!(
+            result,
+            Err(CompressionError::NotEnoughSamples {
+                expected: ValueCompressor::MIN_SAMPLES,
+                got: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn compress_roundtrip() {
+        let samples = json_samples(500);
+        let compressor = ValueCompressor::train(&samples, 2048).unwrap();
+
+        let value = json_samples(1000).pop().unwrap();
+        let compressed = compressor.compress(&value).unwrap();
+        let restored = compressor.decompress(&compressed).unwrap();
+
+        assert_eq!(restored, value);
+    }
+
+    #[test]
+    fn dictionary_beats_plain_compression_on_small_values() {
+        let samples = json_samples(500);
+        let compressor = ValueCompressor::train(&samples, 2048).unwrap();
+
+        let value = json_samples(1000).pop().unwrap();
+        let with_dictionary = compressor.compress(&value).unwrap();
+        let without_dictionary =
+            zstd::bulk::compress(&value, ValueCompressor::DEFAULT_LEVEL).unwrap();
+
+        assert!(with_dictionary.len() < without_dictionary.len());
+        assert!(with_dictionary.len() < value.len());
+    }
+
+    #[test]
+    fn restored_from_dictionary_bytes_decompresses() {
+        let samples = json_samples(500);
+        let compressor = ValueCompressor::train(&samples, 2048).unwrap();
+        let compressed = compressor.compress(&samples[3]).unwrap();
+
+        let reloaded = ValueCompressor::new(compressor.dictionary().to_vec(), compressor.level());
+        assert_eq!(reloaded.decompress(&compressed).unwrap(), samples[3]);
+    }
+}
@@ -0,0 +1,502 @@
+use crate::codec::Encoding;
+use crate::value_log::ValuePointer;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Per-tree tuning knobs. Everything except `cache_size` and `max_file_size` is persisted in
+/// the header, so a tree reopened later behaves the same without the caller restating it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TreeConfig {
+    pub page_size: u64,
+    /// Size of leaf pages, when they should differ from internal ones: a multiple of
+    /// `page_size`, so small internal nodes stay cached while large leaves make scans read
+    /// fewer pages. Each leaf takes that many consecutive pages of the file. 0 makes leaves
+    /// `page_size` too. Fixed when the tree is created.
+    pub leaf_page_size: u64,
+    /// Percentage of each leaf filled by `bulk_load` before starting the next one.
+    pub leaf_fill_factor: u8,
+    /// Percentage of each internal node filled by `bulk_load` before starting the next one.
+    pub internal_fill_factor: u8,
+    /// A full page whose live entries occupy at least this percentage of it is split; below
+    /// that it is compacted in place to reclaim its holes instead.
+    pub split_threshold: u8,
+    pub delete_strategy: DeleteStrategy,
+    pub duplicate_policy: DuplicatePolicy,
+    /// Keep the number of entries beneath each child of every internal node, so positional
+    /// queries and range counts need not visit every leaf. Costs 8 bytes per child pointer and
+    /// a rewrite of every ancestor on each insert or delete. Can only be changed while the
+    /// whole tree fits in its root page.
+    pub subtree_counts: bool,
+    /// Identifies the ordering of the key type, for key types whose `PartialOrd` can change
+    /// meaning, such as a collation with versions. The tree never interprets it; it only
+    /// refuses to open with a different non-zero id than it was created with, as its keys would
+    /// then be out of order. 0 leaves it unspecified. Fixed when the tree is created.
+    pub comparator_id: u16,
+    /// How pages are checked for damage when read. Fixed when the tree is created.
+    pub checksum: ChecksumAlgorithm,
+    /// Values whose serialized form is longer than this many bytes are appended to a separate
+    /// value log, leaving only a pointer in the tree, so splits and merges do not copy them
+    /// around and values larger than a page can be stored. 0 keeps every value in the tree.
+    /// Trees opened by path keep the log in a directory next to the file; others need one
+    /// attached with `BTree::set_value_log`.
+    pub value_log_threshold: u16,
+    /// How keys and values are serialized. Fixed when the tree is created.
+    pub encoding: Encoding,
+    /// Number of pages kept in memory by the page cache; 0 disables it. Only applies to the
+    /// open handle, so it is not persisted.
+    pub cache_size: usize,
+    /// Largest the tree's storage may grow to, in bytes; 0 for no limit. Operations that would
+    /// need more fail with `PageManagerError::StorageFull` before changing anything. Only
+    /// applies to the open handle, so it is not persisted.
+    pub max_file_size: u64,
+    /// Pages that may be written between flushes before writes are held back as `backpressure`
+    /// says, so unflushed pages cannot pile up into one long flush; 0 for no limit. Only
+    /// applies to the open handle, so it is not persisted.
+    pub max_dirty_pages: u64,
+    pub backpressure: Backpressure,
+    /// When written pages reach storage. Only applies to the open handle, so it is not
+    /// persisted.
+    pub write_mode: WriteMode,
+    /// What the handle does on finding one of the tree's invariants broken part way through
+    /// an operation. Only applies to the open handle, so it is not persisted.
+    pub on_invariant_violation: InvariantViolation,
+}
+
+/// How `BTree::delete` removes an entry from a leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeleteStrategy {
+    /// Free the entry's space straight away and merge pages that become under-full.
+    #[default]
+    Immediate,
+    /// Only flag the entry as deleted; its space is reclaimed when the page is next compacted,
+    /// which suits write-heavy workloads that would otherwise churn the free list.
+    Tombstone,
+}
+
+impl DeleteStrategy {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            DeleteStrategy::Immediate => 0,
+            DeleteStrategy::Tombstone => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(DeleteStrategy::Immediate),
+            1 => Some(DeleteStrategy::Tombstone),
+            _ => None,
+        }
+    }
+}
+
+/// What a write does when the pages or log records before it have yet to reach the disk and
+/// their limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Backpressure {
+    /// Hold the writer until the backlog is written out: a tree flushes before going ahead,
+    /// and a write-ahead log waits for its background thread.
+    #[default]
+    Stall,
+    /// Fail the write with a `Busy` error straight away, suggesting the caller try again
+    /// after `retry_after`.
+    Reject { retry_after: Duration },
+}
+
+/// When the pages and header a tree writes reach its storage. Either way they are only
+/// durable once `BTree::flush` has synced them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WriteMode {
+    /// Every page and header goes to storage as soon as it is written, so a flush only has to
+    /// sync them.
+    #[default]
+    WriteThrough,
+    /// Written pages and the header are kept in memory until the tree is flushed or dropped,
+    /// which writes them back together. A page rewritten many times in between reaches storage
+    /// once, and storage only ever holds the tree as it was at a flush, at the cost of keeping
+    /// every page written since in memory.
+    WriteBack,
+}
+
+/// What a tree does when it finds one of its own invariants broken part way through an
+/// operation, such as a split promoting a key equal to the one being inserted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum InvariantViolation {
+    /// Panic, possibly leaving pages of a change that spans several half written.
+    #[default]
+    Panic,
+    /// Fail the operation with `BTreeError::Poisoned` and poison the handle: every operation
+    /// after it that reads or writes the tree fails the same way, and nothing more is written
+    /// to storage, so the file is left as it was for offline inspection. Pages held back in
+    /// write-back mode are discarded, leaving storage as of the last flush.
+    Poison,
+}
+
+/// How the pages of a tree are checksummed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChecksumAlgorithm {
+    /// Pages carry no checksum, leaving 4 more bytes of each for entries; damage is only
+    /// noticed if it breaks the page's structure.
+    None,
+    /// A CRC-32 of each page, checked on every read.
+    #[default]
+    Crc32,
+}
+
+impl ChecksumAlgorithm {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ChecksumAlgorithm::None => 0,
+            ChecksumAlgorithm::Crc32 => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ChecksumAlgorithm::None),
+            1 => Some(ChecksumAlgorithm::Crc32),
+            _ => None,
+        }
+    }
+}
+
+/// How the values of a tree are compressed. Set by installing a compression dictionary rather
+/// than through [`TreeConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionAlgorithm {
+    #[default]
+    None,
+    /// zstd with a dictionary shared by the whole tree.
+    ZstdDictionary,
+}
+
+impl CompressionAlgorithm {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CompressionAlgorithm::None => 0,
+            CompressionAlgorithm::ZstdDictionary => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(CompressionAlgorithm::None),
+            1 => Some(CompressionAlgorithm::ZstdDictionary),
+            _ => None,
+        }
+    }
+}
+
+/// What `BTree::insert` does when the key is already in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DuplicatePolicy {
+    /// Replace the stored value.
+    #[default]
+    Overwrite,
+    /// Leave the tree unchanged and fail with `BTreeError::DuplicateKey`.
+    Error,
+    /// Store the new entry alongside the existing ones, making the tree a multimap. `search`
+    /// returns one of the values and `get_all` every one of them; `delete` removes one entry.
+    KeepBoth,
+    /// Store whatever the resolver set with `BTree::set_duplicate_resolver` makes of the stored
+    /// and new values. The resolver is not persisted, so each handle must set its own.
+    Resolve,
+    /// Store the new entry alongside the existing ones as `KeepBoth` does, but keep the
+    /// entries of each key sorted by value with the order set by `BTree::set_duplicate_order`
+    /// rather than in insertion order, so values can be deleted by range with
+    /// `BTree::delete_values`. Entries whose values are equal in that order stay in insertion
+    /// order. The order is not persisted, so each handle must set the same one.
+    KeepSorted,
+}
+
+impl DuplicatePolicy {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            DuplicatePolicy::Overwrite => 0,
+            DuplicatePolicy::Error => 1,
+            DuplicatePolicy::KeepBoth => 2,
+            DuplicatePolicy::Resolve => 3,
+            DuplicatePolicy::KeepSorted => 4,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(DuplicatePolicy::Overwrite),
+            1 => Some(DuplicatePolicy::Error),
+            2 => Some(DuplicatePolicy::KeepBoth),
+            3 => Some(DuplicatePolicy::Resolve),
+            4 => Some(DuplicatePolicy::KeepSorted),
+            _ => None,
+        }
+    }
+
+    /// Whether the tree can hold more than one entry under the same key.
+    pub fn keeps_duplicates(self) -> bool {
+        matches!(
+            self,
+            DuplicatePolicy::KeepBoth | DuplicatePolicy::KeepSorted
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    InvalidPercentage { field: &'static str, value: u8 },
+    ValueLogThresholdTooSmall { value: u16, minimum: u16 },
+    InvalidLeafPageSize { value: u64, page_size: u64 },
+    InvalidPageSize { value: u64 },
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ConfigError::InvalidPercentage { field, value } => {
+                write!(f, "Invalid {}: {} (must be within 1..=100)", field, value)
+            }
+            ConfigError::ValueLogThresholdTooSmall { value, minimum } => {
+                write!(
+                    f,
+                    "Invalid value_log_threshold: {} (must be 0 or at least {})",
+                    value, minimum
+                )
+            }
+            ConfigError::InvalidLeafPageSize { value, page_size } => {
+                write!(
+                    f,
+                    "Invalid leaf_page_size: {} (must be 0 or a multiple of page_size {} up to {})",
+                    value,
+                    page_size,
+                    TreeConfig::MAX_PAGE_SIZE
+                )
+            }
+            ConfigError::InvalidPageSize { value } => {
+                write!(
+                    f,
+                    "Invalid page_size: {} (must be within {}..={})",
+                    value,
+                    TreeConfig::MIN_PAGE_SIZE,
+                    TreeConfig::MAX_PAGE_SIZE
+                )
+            }
+        }
+    }
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        TreeConfig {
+            page_size: 4096,
+            leaf_page_size: 0,
+            leaf_fill_factor: 90,
+            internal_fill_factor: 90,
+            split_threshold: 70,
+            delete_strategy: DeleteStrategy::Immediate,
+            duplicate_policy: DuplicatePolicy::Overwrite,
+            subtree_counts: false,
+            comparator_id: 0,
+            checksum: ChecksumAlgorithm::Crc32,
+            value_log_threshold: 0,
+            encoding: Encoding::Bincode,
+            cache_size: 0,
+            max_file_size: 0,
+            max_dirty_pages: 0,
+            backpressure: Backpressure::Stall,
+            write_mode: WriteMode::WriteThrough,
+            on_invariant_violation: InvariantViolation::Panic,
+        }
+    }
+}
+
+impl TreeConfig {
+    /// Largest page a slotted page can address with its 2-byte offsets.
+    pub const MAX_PAGE_SIZE: u64 = u16::MAX as u64;
+
+    /// Smallest page that leaves room, after the page's prefix, header and checksum, for the
+    /// slots and entries of a few small keys and values.
+    pub const MIN_PAGE_SIZE: u64 = 128;
+
+    pub fn with_page_size(page_size: u64) -> Self {
+        TreeConfig {
+            page_size,
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(Self::MIN_PAGE_SIZE..=Self::MAX_PAGE_SIZE).contains(&self.page_size) {
+            return Err(ConfigError::InvalidPageSize {
+                value: self.page_size,
+            });
+        }
+        for (field, value) in [
+            ("leaf_fill_factor", self.leaf_fill_factor),
+            ("internal_fill_factor", self.internal_fill_factor),
+            ("split_threshold", self.split_threshold),
+        ] {
+            if value == 0 || value > 100 {
+                return Err(ConfigError::InvalidPercentage { field, value });
+            }
+        }
+        // A value no longer than a pointer gains nothing from moving to the log
+        let minimum = ValuePointer::SIZE as u16 + 1;
+        if self.value_log_threshold != 0 && self.value_log_threshold < minimum {
+            return Err(ConfigError::ValueLogThresholdTooSmall {
+                value: self.value_log_threshold,
+                minimum,
+            });
+        }
+        let leaf_page_size = self.leaf_page_size;
+        if leaf_page_size != 0
+            && (leaf_page_size.checked_rem(self.page_size) != Some(0)
+                || leaf_page_size > Self::MAX_PAGE_SIZE)
+        {
+            return Err(ConfigError::InvalidLeafPageSize {
+                value: leaf_page_size,
+                page_size: self.page_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(TreeConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn zero_percentage_is_rejected() {
+        let config = TreeConfig {
+            leaf_fill_factor: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::InvalidPercentage {
+                field: "leaf_fill_factor",
+                value: 0
+            })
+        );
+    }
+
+    #[test]
+    fn delete_strategy_byte_roundtrip() {
+        for strategy in [DeleteStrategy::Immediate, DeleteStrategy::Tombstone] {
+            assert_eq!(
+                DeleteStrategy::from_byte(strategy.to_byte()),
+                Some(strategy)
+            );
+        }
+        assert_eq!(DeleteStrategy::from_byte(7), None);
+    }
+
+    #[test]
+    fn duplicate_policy_byte_roundtrip() {
+        for policy in [
+            DuplicatePolicy::Overwrite,
+            DuplicatePolicy::Error,
+            DuplicatePolicy::KeepBoth,
+            DuplicatePolicy::Resolve,
+            DuplicatePolicy::KeepSorted,
+        ] {
+            assert_eq!(DuplicatePolicy::from_byte(policy.to_byte()), Some(policy));
+        }
+        assert_eq!(DuplicatePolicy::from_byte(5), None);
+    }
+
+    #[test]
+    fn checksum_and_compression_algorithm_byte_roundtrip() {
+        for algorithm in [ChecksumAlgorithm::None, ChecksumAlgorithm::Crc32] {
+            assert_eq!(
+                ChecksumAlgorithm::from_byte(algorithm.to_byte()),
+                Some(algorithm)
+            );
+        }
+        for algorithm in [
+            CompressionAlgorithm::None,
+            CompressionAlgorithm::ZstdDictionary,
+        ] {
+            assert_eq!(
+                CompressionAlgorithm::from_byte(algorithm.to_byte()),
+                Some(algorithm)
+            );
+        }
+        assert_eq!(ChecksumAlgorithm::from_byte(2), None);
+        assert_eq!(CompressionAlgorithm::from_byte(2), None);
+    }
+
+    #[test]
+    fn percentage_above_hundred_is_rejected() {
+        let config = TreeConfig {
+            split_threshold: 101,
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn value_log_threshold_must_exceed_a_pointer() {
+        let config = |value_log_threshold| TreeConfig {
+            value_log_threshold,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config(12).validate(),
+            Err(ConfigError::ValueLogThresholdTooSmall {
+                value: 12,
+                minimum: 13
+            })
+        );
+        assert!(config(0).validate().is_ok());
+        assert!(config(13).validate().is_ok());
+    }
+
+    #[test]
+    fn leaf_page_size_must_be_a_multiple_of_the_page_size() {
+        let config = |leaf_page_size| TreeConfig {
+            leaf_page_size,
+            ..TreeConfig::with_page_size(512)
+        };
+
+        assert_eq!(
+            config(1000).validate(),
+            Err(ConfigError::InvalidLeafPageSize {
+                value: 1000,
+                page_size: 512
+            })
+        );
+        assert!(config(65536).validate().is_err());
+        assert!(config(0).validate().is_ok());
+        assert!(config(512).validate().is_ok());
+        assert!(config(4096).validate().is_ok());
+    }
+
+    #[test]
+    fn page_size_must_fit_a_slotted_page() {
+        for page_size in [0, 16, 127, 65536, 131072] {
+            assert_eq!(
+                TreeConfig::with_page_size(page_size).validate(),
+                Err(ConfigError::InvalidPageSize { value: page_size })
+            );
+        }
+        for page_size in [128, 1000, 4096, 65535] {
+            assert!(TreeConfig::with_page_size(page_size).validate().is_ok());
+        }
+
+        // Refused before any page is laid out, rather than dividing by it
+        assert!(matches!(
+            crate::BTree::<i64, i64>::in_memory(TreeConfig::with_page_size(0)),
+            Err(crate::error::BTreeError::Config(
+                ConfigError::InvalidPageSize { value: 0 }
+            ))
+        ));
+    }
+}
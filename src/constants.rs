@@ -1 +1,28 @@
-pub const VERSION: u16 = 0;
+pub const VERSION: u16 = 16;
+
+/// First format version in which every tree page carries a checksum.
+pub const CHECKSUM_VERSION: u16 = 10;
+
+/// First format version whose header records the comparator, checksum and compression
+/// algorithms of the tree.
+pub const CONFIG_BLOCK_VERSION: u16 = 11;
+
+/// First format version whose pages can point to values kept in a value log.
+pub const VALUE_LOG_VERSION: u16 = 12;
+
+/// First format version whose header records a separate size for leaf pages, in what used to
+/// be the upper half of the page size.
+pub const LEAF_PAGE_SIZE_VERSION: u16 = 13;
+
+/// First format version whose stats page counts the writes made over the life of the tree,
+/// which number them for read-your-writes tokens.
+pub const COMMIT_LSN_VERSION: u16 = 14;
+
+/// First format version whose header records how keys and values are encoded, in what used to
+/// be the upper half of the leaf page size.
+pub const ENCODING_VERSION: u16 = 15;
+
+/// First format version whose stats page can hold named sequences after the stats. Trees are
+/// raised to it when their first sequence is created, so builds that would drop them when
+/// rewriting the stats page refuse to open the tree.
+pub const SEQUENCE_VERSION: u16 = 16;
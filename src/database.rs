@@ -0,0 +1,486 @@
+//! A database directory opened as a whole: its catalog, the indexes listed in it, the buffer
+//! pool they share and its write-ahead log, with trees handed out by name.
+
+use crate::btree::BTree;
+use crate::buffer_pool::{BufferPool, FreshBuffers};
+use crate::catalog::{Catalog, CatalogError, IndexEntry, IndexKind};
+use crate::codec::Encoding;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use crate::storage::Storage;
+use crate::wal::{Wal, WalError, WalOptions, read_log};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A tree handed out by a [`Database`], shared with every other handle on the same name.
+pub type Tree<K, V> = Arc<Mutex<BTree<K, V>>>;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Catalog(CatalogError),
+    Tree(BTreeError),
+    Wal(WalError),
+    Io(std::io::Error),
+    /// The tree is still held by handles other than the database's own.
+    TreeInUse(String),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DatabaseError::Catalog(e) => {
+                write!(f, "Catalog error: {}", e)
+            }
+            DatabaseError::Tree(e) => {
+                write!(f, "Tree error: {}", e)
+            }
+            DatabaseError::Wal(e) => {
+                write!(f, "WAL error: {}", e)
+            }
+            DatabaseError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            DatabaseError::TreeInUse(name) => {
+                write!(f, "Tree {:?} is still in use", name)
+            }
+        }
+    }
+}
+
+impl From<CatalogError> for DatabaseError {
+    fn from(err: CatalogError) -> DatabaseError {
+        DatabaseError::Catalog(err)
+    }
+}
+
+impl From<BTreeError> for DatabaseError {
+    fn from(err: BTreeError) -> DatabaseError {
+        DatabaseError::Tree(err)
+    }
+}
+
+impl From<WalError> for DatabaseError {
+    fn from(err: WalError) -> DatabaseError {
+        DatabaseError::Wal(err)
+    }
+}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(err: std::io::Error) -> DatabaseError {
+        DatabaseError::Io(err)
+    }
+}
+
+// A tree the database has opened, with its key and value types erased so trees of different
+// types can be kept together
+trait OpenTree: Send {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Flushes the tree, returning its root page.
+    fn flush(&self) -> Result<u64, BTreeError>;
+
+    /// Number of handles on the tree, the database's own included.
+    fn handles(&self) -> usize;
+}
+
+impl<K, V> OpenTree for Tree<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    K: Send + 'static,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de> + Send + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flush(&self) -> Result<u64, BTreeError> {
+        let mut tree = self.lock().unwrap();
+        tree.flush()?;
+        Ok(tree.root_page_id())
+    }
+
+    fn handles(&self) -> usize {
+        Arc::strong_count(self)
+    }
+}
+
+/// The primary way to open CloaksDB data: a directory holding a [`Catalog`] of named indexes,
+/// each in a file of its own, and a write-ahead log. Trees opened through the database share
+/// its buffer pool, are created with its default configuration unless given another, and have
+/// their root pages recorded in the catalog whenever the database is flushed.
+///
+/// Changes to the catalog are logged and synced before they are made, and replayed into the
+/// catalog when the database is opened again, so a tree created or flushed is listed with its
+/// root page even if the catalog's own file was not synced after. The entries in the trees are
+/// as durable as each tree's own write mode makes them, as when opened by path. Only one
+/// handle on a directory can be open at a time, as the catalog's own file is locked.
+pub struct Database {
+    catalog: Catalog,
+    buffers: Arc<dyn BufferPool>,
+    wal: Wal,
+    config: TreeConfig,
+    trees: HashMap<String, Box<dyn OpenTree>>,
+}
+
+// A change to the catalog, as logged before it is made
+#[derive(Debug, Serialize, Deserialize)]
+enum LogRecord {
+    Recorded(IndexEntry),
+    Dropped(String),
+}
+
+impl Database {
+    /// Name of the write-ahead log in the database directory.
+    pub const WAL_FILE_NAME: &str = "wal.log";
+
+    /// Opens the database in the directory at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DatabaseError> {
+        Self::open_with(path, TreeConfig::default(), Arc::new(FreshBuffers))
+    }
+
+    /// Opens the database at `path` with `config` for the trees it creates and `buffers` for
+    /// the pages of every tree.
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        config: TreeConfig,
+        buffers: Arc<dyn BufferPool>,
+    ) -> Result<Self, DatabaseError> {
+        config.validate().map_err(BTreeError::from)?;
+        let mut catalog = Catalog::open(path.as_ref())?;
+        let wal_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.as_ref().join(Self::WAL_FILE_NAME))?;
+        Self::replay(&mut catalog, &wal_file)?;
+        let wal = Wal::open(wal_file, WalOptions::default())?;
+        debug!(
+            "Opened database {:?} with {} indexes",
+            path.as_ref(),
+            catalog.indexes().count()
+        );
+        Ok(Database {
+            catalog,
+            buffers,
+            wal,
+            config,
+            trees: HashMap::new(),
+        })
+    }
+
+    // Applies the changes logged in `wal_file` to `catalog` and syncs it, after which the log
+    // is emptied as nothing in it is needed again
+    fn replay(catalog: &mut Catalog, wal_file: &std::fs::File) -> Result<(), DatabaseError> {
+        let records = read_log(wal_file)?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        for record in &records {
+            match Encoding::Bincode
+                .deserialize(&record.payload)
+                .map_err(BTreeError::from)?
+            {
+                LogRecord::Recorded(entry) => catalog.record(entry)?,
+                LogRecord::Dropped(name) => {
+                    catalog.remove(&name)?;
+                }
+            }
+        }
+        catalog.flush()?;
+        wal_file.set_size(0)?;
+        debug!("Replayed {} logged catalog changes", records.len());
+        Ok(())
+    }
+
+    // Logs `records` and waits for them to be durable, before they are applied to the catalog
+    fn log(&self, records: &[LogRecord]) -> Result<(), DatabaseError> {
+        let mut last = None;
+        for record in records {
+            let payload = Encoding::Bincode
+                .serialize(record)
+                .map_err(BTreeError::from)?;
+            last = Some(self.wal.append(&payload)?);
+        }
+        if let Some(lsn) = last {
+            self.wal.wait_durable(lsn)?;
+        }
+        Ok(())
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    /// The tree named `name`, creating it with the database's configuration if there is none.
+    pub fn tree<K, V>(&mut self, name: &str) -> Result<Tree<K, V>, DatabaseError>
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        K: Send + 'static,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    {
+        self.tree_with_config(name, self.config)
+    }
+
+    /// The tree named `name`, creating it with `config` if there is none. An existing tree
+    /// keeps the configuration it was created with, as when opened by path. Fails with
+    /// [`CatalogError::WrongKind`] or [`CatalogError::WrongCodec`] if `name` is another kind of
+    /// index or holds other types.
+    pub fn tree_with_config<K, V>(
+        &mut self,
+        name: &str,
+        config: TreeConfig,
+    ) -> Result<Tree<K, V>, DatabaseError>
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        K: Send + 'static,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    {
+        Catalog::check_name(name)?;
+        let entry = match self.catalog.find::<K, V>(name, IndexKind::BTree)? {
+            Some(entry) => entry.clone(),
+            None => {
+                let entry = IndexEntry::new::<K, V>(name, IndexKind::BTree, config);
+                self.log(&[LogRecord::Recorded(entry.clone())])?;
+                entry
+            }
+        };
+        if let Some(open) = self.trees.get(name) {
+            // The catalog has checked the types, so this is the tree it describes
+            let tree = open.as_any().downcast_ref::<Tree<K, V>>().unwrap();
+            return Ok(tree.clone());
+        }
+
+        let mut tree = BTree::<K, V>::open(self.catalog.path_of(&entry), entry.config)?;
+        tree.set_buffer_pool(self.buffers.clone());
+        let tree = Arc::new(Mutex::new(tree));
+        self.catalog.record(entry)?;
+        self.trees.insert(name.to_string(), Box::new(tree.clone()));
+        Ok(tree)
+    }
+
+    /// Closes the tree named `name`, removes it from the catalog and deletes its file,
+    /// returning whether there was one. Fails with [`DatabaseError::TreeInUse`] while handles
+    /// on it returned by [`Database::tree`] are still alive, and leaves it as it was.
+    pub fn drop_tree(&mut self, name: &str) -> Result<bool, DatabaseError> {
+        let Some(entry) = self.catalog.get(name).cloned() else {
+            return Ok(false);
+        };
+        if entry.kind != IndexKind::BTree {
+            return Err(CatalogError::WrongKind {
+                name: name.to_string(),
+                expected: IndexKind::BTree,
+                found: entry.kind,
+            }
+            .into());
+        }
+        if self.trees.get(name).is_some_and(|tree| tree.handles() > 1) {
+            return Err(DatabaseError::TreeInUse(name.to_string()));
+        }
+        self.trees.remove(name);
+        // Forgotten before it is deleted, so a crash in between leaves an unused file rather
+        // than an entry whose file is gone
+        self.log(&[LogRecord::Dropped(name.to_string())])?;
+        self.catalog.remove(name)?;
+        self.catalog.flush()?;
+        match std::fs::remove_file(self.catalog.path_of(&entry)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        debug!("Dropped tree {:?}", name);
+        Ok(true)
+    }
+
+    /// Next value of the database-wide sequence `name`, starting at 1, for generating IDs.
+    /// Sequences are kept in the catalog, so the values handed out are never handed out again,
+    /// even after a crash; see [`crate::sequence`].
+    pub fn next_sequence(&mut self, name: &str) -> Result<u64, DatabaseError> {
+        Ok(self.catalog.next_sequence(name)?)
+    }
+
+    /// Flushes every tree opened so far and then the catalog, with the trees' current root
+    /// pages.
+    pub fn flush(&mut self) -> Result<(), DatabaseError> {
+        let mut moved = Vec::new();
+        for (name, tree) in &self.trees {
+            let root_page_id = tree.flush()?;
+            let entry = self.catalog.get(name).unwrap();
+            if entry.root_page_id != root_page_id {
+                moved.push(IndexEntry {
+                    root_page_id,
+                    ..entry.clone()
+                });
+            }
+        }
+        let records: Vec<_> = moved.iter().cloned().map(LogRecord::Recorded).collect();
+        self.log(&records)?;
+        for entry in moved {
+            self.catalog.record(entry)?;
+        }
+        self.catalog.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to flush database on drop: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_pool::PagePool;
+
+    #[test]
+    fn trees_are_found_by_name_when_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = Arc::new(PagePool::new(8));
+        let mut db = Database::open_with(dir.path(), TreeConfig::default(), pool.clone()).unwrap();
+        let users = db.tree::<u64, String>("users").unwrap();
+        let emails = db.tree::<String, u64>("emails").unwrap();
+        for i in 0..500 {
+            users
+                .lock()
+                .unwrap()
+                .insert(i, format!("user-{}", i))
+                .unwrap();
+            emails
+                .lock()
+                .unwrap()
+                .insert(format!("{}@example.com", i), i)
+                .unwrap();
+        }
+        // Handles on the same name share one tree
+        assert_eq!(
+            db.tree::<u64, String>("users")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .len(),
+            500
+        );
+        assert!(pool.reused() > 0);
+        drop((users, emails, db));
+
+        let mut db = Database::open(dir.path()).unwrap();
+        let entry = db.catalog().get("users").unwrap();
+        assert_ne!(entry.root_page_id, 0);
+        let users = db.tree::<u64, String>("users").unwrap();
+        assert_eq!(users.lock().unwrap().search(42).unwrap(), "user-42");
+        let emails = db.tree::<String, u64>("emails").unwrap();
+        assert_eq!(emails.lock().unwrap().len(), 500);
+    }
+
+    #[test]
+    fn logged_catalog_changes_are_replayed_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Database::open(dir.path()).unwrap();
+        let users = db.tree::<u64, String>("users").unwrap();
+        users.lock().unwrap().insert(1, "one".to_string()).unwrap();
+        drop((users, db));
+
+        // As if the database stopped after logging a new tree but before recording it
+        let wal_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dir.path().join(Database::WAL_FILE_NAME))
+            .unwrap();
+        let wal = Wal::open(wal_file, WalOptions::default()).unwrap();
+        let entry = IndexEntry::new::<u64, u64>("orders", IndexKind::BTree, TreeConfig::default());
+        let payload = Encoding::Bincode
+            .serialize(&LogRecord::Recorded(entry))
+            .unwrap();
+        wal.append_durable(&payload).unwrap();
+        wal.close().unwrap();
+
+        let mut db = Database::open(dir.path()).unwrap();
+        assert!(db.catalog().get("orders").is_some());
+        // Once applied, nothing logged is needed again
+        let wal_file = std::fs::File::open(dir.path().join(Database::WAL_FILE_NAME)).unwrap();
+        assert!(read_log(&wal_file).unwrap().is_empty());
+        assert!(db.tree::<u64, String>("orders").is_err());
+        assert!(
+            db.tree::<u64, u64>("orders")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .is_empty()
+        );
+        let users = db.tree::<u64, String>("users").unwrap();
+        assert_eq!(users.lock().unwrap().search(1).unwrap(), "one");
+    }
+
+    #[test]
+    fn sequences_keep_rising_across_reopens() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Database::open(dir.path()).unwrap();
+        assert_eq!(db.next_sequence("orders").unwrap(), 1);
+        assert_eq!(db.next_sequence("orders").unwrap(), 2);
+        drop(db);
+
+        let mut db = Database::open(dir.path()).unwrap();
+        assert!(db.next_sequence("orders").unwrap() > 2);
+    }
+
+    #[test]
+    fn trees_open_only_with_the_types_they_hold() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Database::open(dir.path()).unwrap();
+        db.tree::<u64, String>("users").unwrap();
+        assert!(matches!(
+            db.tree::<String, String>("users"),
+            Err(DatabaseError::Catalog(CatalogError::WrongCodec { .. }))
+        ));
+        assert!(matches!(
+            db.tree::<u64, u64>("../users"),
+            Err(DatabaseError::Catalog(CatalogError::InvalidName(_)))
+        ));
+    }
+
+    #[test]
+    fn dropped_trees_are_gone_from_the_catalog_and_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Database::open(dir.path()).unwrap();
+        let users = db.tree::<u64, String>("users").unwrap();
+        users
+            .lock()
+            .unwrap()
+            .insert(1, "user-1".to_string())
+            .unwrap();
+        db.tree::<u64, u64>("orders").unwrap();
+        db.flush().unwrap();
+        let path = db.catalog().path_of(db.catalog().get("users").unwrap());
+        assert!(path.exists());
+
+        assert!(matches!(
+            db.drop_tree("users"),
+            Err(DatabaseError::TreeInUse(_))
+        ));
+        drop(users);
+        assert!(db.drop_tree("users").unwrap());
+        assert!(!db.drop_tree("users").unwrap());
+        assert!(db.catalog().get("users").is_none());
+        assert!(!path.exists());
+        drop(db);
+
+        let mut db = Database::open(dir.path()).unwrap();
+        assert!(db.catalog().get("users").is_none());
+        assert!(db.catalog().get("orders").is_some());
+        let users = db.tree::<u64, String>("users").unwrap();
+        assert_eq!(users.lock().unwrap().len(), 0);
+    }
+}
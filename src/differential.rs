@@ -0,0 +1,446 @@
+//! Model-based differential testing: the same random sequence of operations is applied to a
+//! [`BTree`] and to a `std::collections::BTreeMap`, and every result the tree gives is checked
+//! against the map's.
+//!
+//! Keys and values are made by functions of a random number, so the harness can be run with
+//! any key type a tree can hold, including types whose `PartialOrd` the tree relies on but
+//! which were written outside this crate. The tree is kept in a file so it can be reopened part
+//! way through, checking that what was written survives the round trip. A run stops at the
+//! first step whose result differs and returns the operations leading up to it, which
+//! [`replay`] applies again without drawing new ones.
+//!
+//! ```no_run
+//! use cloaksdb::differential::{self, DifferentialOptions};
+//!
+//! let outcome = differential::run(
+//!     DifferentialOptions::default(),
+//!     |n| format!("key-{:04}", n),
+//!     |n| vec![0u8; (n % 200) as usize],
+//! );
+//! if let Err(divergence) = outcome {
+//!     panic!("{}", divergence);
+//! }
+//! ```
+
+use crate::btree::BTree;
+use crate::config::{DuplicatePolicy, TreeConfig};
+use crate::error::BTreeError;
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+/// One step of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<K, V> {
+    /// Insert the entry, replacing the value of a key already present.
+    Insert(K, V),
+    Delete(K),
+    Get(K),
+    /// Scan the entries between the bounds.
+    Range(Bound<K>, Bound<K>),
+    /// Flush the tree, close it and open it again.
+    Reopen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DifferentialOptions {
+    /// Number of operations to draw.
+    pub steps: usize,
+    pub seed: u64,
+    /// Keys are made from numbers below this, so a smaller space makes more of the operations
+    /// land on keys already present.
+    pub key_space: u64,
+    /// The tree is created with this configuration, except that its duplicate policy is always
+    /// `DuplicatePolicy::Overwrite`, the only one a map can model.
+    pub config: TreeConfig,
+    /// A reopen is made every this many steps; 0 never reopens.
+    pub reopen_every: usize,
+    /// Every entry of the tree is compared with the map, and the tree's structure verified,
+    /// every this many steps and at the end of the run; 0 only checks at the end.
+    pub full_check_every: usize,
+}
+
+impl Default for DifferentialOptions {
+    fn default() -> Self {
+        DifferentialOptions {
+            steps: 5_000,
+            seed: 0,
+            key_space: 500,
+            config: TreeConfig::with_page_size(512),
+            reopen_every: 1_000,
+            full_check_every: 500,
+        }
+    }
+}
+
+/// The first step at which the tree and the map disagreed.
+#[derive(Debug, Clone)]
+pub struct Divergence<K, V> {
+    /// Index of the step within `history`.
+    pub step: usize,
+    /// Every operation applied up to and including the one that diverged, for `replay`.
+    pub history: Vec<Operation<K, V>>,
+    pub reason: String,
+}
+
+impl<K, V> Divergence<K, V> {
+    /// The operation that diverged; `None` if the run failed before its first step.
+    pub fn operation(&self) -> Option<&Operation<K, V>> {
+        self.history.last()
+    }
+}
+
+impl<K: Debug, V: Debug> fmt::Display for Divergence<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operation() {
+            Some(operation) => write!(
+                f,
+                "diverged at step {} ({:?}): {}",
+                self.step, operation, self.reason
+            ),
+            None => write!(f, "could not start: {}", self.reason),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> std::error::Error for Divergence<K, V> {}
+
+/// Draws `options.steps` operations, with keys made by `key` from numbers below
+/// `options.key_space` and values made by `value` from any number.
+pub fn generate<K, V>(
+    options: &DifferentialOptions,
+    key: impl Fn(u64) -> K,
+    value: impl Fn(u64) -> V,
+) -> Vec<Operation<K, V>>
+where
+    K: Ord,
+{
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let key_space = options.key_space.max(1);
+    let bound = |rng: &mut StdRng| match rng.random_range(0..3) {
+        0 => Bound::Included(key(rng.random_range(0..key_space))),
+        1 => Bound::Excluded(key(rng.random_range(0..key_space))),
+        _ => Bound::Unbounded,
+    };
+    (1..=options.steps)
+        .map(|step| {
+            if options.reopen_every > 0 && step % options.reopen_every == 0 {
+                return Operation::Reopen;
+            }
+            match rng.random_range(0..100) {
+                0..50 => {
+                    Operation::Insert(key(rng.random_range(0..key_space)), value(rng.random()))
+                }
+                50..75 => Operation::Delete(key(rng.random_range(0..key_space))),
+                75..90 => Operation::Get(key(rng.random_range(0..key_space))),
+                _ => {
+                    let start = bound(&mut rng);
+                    let end = bound(&mut rng);
+                    Operation::Range(start, end)
+                }
+            }
+        })
+        .collect()
+}
+
+/// Draws operations as [`generate`] does and applies them to a fresh tree in a temporary
+/// directory and to a map.
+pub fn run<K, V>(
+    options: DifferentialOptions,
+    key: impl Fn(u64) -> K,
+    value: impl Fn(u64) -> V,
+) -> Result<(), Divergence<K, V>>
+where
+    K: Clone + Ord + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + PartialEq + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    let operations = generate(&options, key, value);
+    let dir = tempfile::tempdir().map_err(|e| Divergence {
+        step: 0,
+        history: Vec::new(),
+        reason: format!("no directory for the tree: {}", e),
+    })?;
+    replay(
+        dir.path().join("differential.db"),
+        options.config,
+        &operations,
+        options.full_check_every,
+    )
+}
+
+/// Applies `operations` to a tree created at `path`, which must not exist yet, and to a map,
+/// comparing everything as [`run`] does.
+pub fn replay<K, V, P: AsRef<Path>>(
+    path: P,
+    config: TreeConfig,
+    operations: &[Operation<K, V>],
+    full_check_every: usize,
+) -> Result<(), Divergence<K, V>>
+where
+    K: Clone + Ord + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + PartialEq + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    let path = path.as_ref();
+    let config = TreeConfig {
+        duplicate_policy: DuplicatePolicy::Overwrite,
+        ..config
+    };
+    let diverged = |step: usize, reason: String| Divergence {
+        step,
+        history: operations[..=step].to_vec(),
+        reason,
+    };
+    if path.exists() {
+        return Err(Divergence {
+            step: 0,
+            history: Vec::new(),
+            reason: format!("{} already exists", path.display()),
+        });
+    }
+    let mut tree = BTree::<K, V>::open(path, config).map_err(|e| Divergence {
+        step: 0,
+        history: Vec::new(),
+        reason: format!("could not create the tree: {}", e),
+    })?;
+    let mut model = BTreeMap::new();
+
+    for (step, operation) in operations.iter().enumerate() {
+        let mut reopened = false;
+        match operation {
+            Operation::Insert(key, value) => {
+                tree.insert(key.clone(), value.clone())
+                    .map_err(|e| diverged(step, format!("insert failed: {}", e)))?;
+                model.insert(key.clone(), value.clone());
+            }
+            Operation::Delete(key) => {
+                let deleted = match tree.delete(key.clone()) {
+                    Ok(value) => Some(value),
+                    Err(BTreeError::KeyNotFound(_)) => None,
+                    Err(e) => return Err(diverged(step, format!("delete failed: {}", e))),
+                };
+                let expected = model.remove(key);
+                if deleted != expected {
+                    return Err(diverged(
+                        step,
+                        format!("deleted {:?}, expected {:?}", deleted, expected),
+                    ));
+                }
+            }
+            Operation::Get(key) => {
+                let found = match tree.search(key.clone()) {
+                    Ok(value) => Some(value),
+                    Err(BTreeError::KeyNotFound(_)) => None,
+                    Err(e) => return Err(diverged(step, format!("search failed: {}", e))),
+                };
+                let expected = model.get(key);
+                if found.as_ref() != expected {
+                    return Err(diverged(
+                        step,
+                        format!("found {:?}, expected {:?}", found, expected),
+                    ));
+                }
+            }
+            Operation::Range(start, end) => {
+                let range = (start.clone(), end.clone());
+                let scanned = scan(&mut tree, range.clone())
+                    .map_err(|e| diverged(step, format!("range failed: {}", e)))?;
+                // Filtered rather than taken with `BTreeMap::range`, which panics on bounds
+                // the tree accepts, such as a start past the end
+                let expected: Vec<(K, V)> = model
+                    .iter()
+                    .filter(|(key, _)| range.contains(*key))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                if let Some(reason) = compare(&scanned, &expected) {
+                    return Err(diverged(step, reason));
+                }
+            }
+            Operation::Reopen => {
+                tree.flush()
+                    .map_err(|e| diverged(step, format!("flush failed: {}", e)))?;
+                drop(tree);
+                tree = BTree::open(path, config)
+                    .map_err(|e| diverged(step, format!("reopen failed: {}", e)))?;
+                reopened = true;
+            }
+        }
+
+        if tree.len() != model.len() as u64 {
+            return Err(diverged(
+                step,
+                format!(
+                    "tree holds {} entries, expected {}",
+                    tree.len(),
+                    model.len()
+                ),
+            ));
+        }
+        let last = step + 1 == operations.len();
+        let due = full_check_every > 0 && (step + 1) % full_check_every == 0;
+        if reopened || due || last {
+            debug!("Full check at step {}", step);
+            check_everything(&mut tree, &model).map_err(|reason| diverged(step, reason))?;
+        }
+    }
+    Ok(())
+}
+
+fn scan<K, V, R>(tree: &mut BTree<K, V>, range: R) -> Result<Vec<(K, V)>, BTreeError>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    R: RangeBounds<K>,
+{
+    tree.range(range)?.collect()
+}
+
+// Compares the whole tree with the map and verifies its structure
+fn check_everything<K, V>(tree: &mut BTree<K, V>, model: &BTreeMap<K, V>) -> Result<(), String>
+where
+    K: Clone + Ord + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + PartialEq + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    let entries = scan(tree, ..).map_err(|e| format!("scan failed: {}", e))?;
+    let expected: Vec<(K, V)> = model
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if let Some(reason) = compare(&entries, &expected) {
+        return Err(reason);
+    }
+    tree.verify().map_err(|e| format!("verify failed: {}", e))
+}
+
+// Describes the first difference between the entries found and those expected
+fn compare<K, V>(found: &[(K, V)], expected: &[(K, V)]) -> Option<String>
+where
+    K: PartialEq + Debug,
+    V: PartialEq + Debug,
+{
+    let position = found
+        .iter()
+        .zip(expected)
+        .position(|(found, expected)| found != expected);
+    match position {
+        Some(i) => Some(format!(
+            "entry {} is {:?}, expected {:?}",
+            i, found[i], expected[i]
+        )),
+        None if found.len() != expected.len() => Some(format!(
+            "{} entries, expected {}",
+            found.len(),
+            expected.len()
+        )),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeleteStrategy;
+
+    #[test]
+    fn trees_agree_with_the_model_across_seeds_and_configs() {
+        let configs = [
+            TreeConfig::with_page_size(512),
+            TreeConfig {
+                delete_strategy: DeleteStrategy::Tombstone,
+                ..TreeConfig::with_page_size(512)
+            },
+            TreeConfig {
+                subtree_counts: true,
+                ..TreeConfig::with_page_size(1024)
+            },
+        ];
+        for config in configs {
+            for seed in 0..4 {
+                let options = DifferentialOptions {
+                    steps: 3_000,
+                    seed,
+                    key_space: 300,
+                    config,
+                    reopen_every: 700,
+                    full_check_every: 250,
+                };
+                // Values of mixed sizes, so overwrites move entries between pages and separators
+                // are replaced by larger entries
+                let outcome = run(
+                    options,
+                    |n| n as i64 * 3 - 400,
+                    |n| "x".repeat((n % 60) as usize),
+                );
+                if let Err(divergence) = outcome {
+                    panic!("{:?} seed {}: {}", config.delete_strategy, seed, divergence);
+                }
+            }
+        }
+    }
+
+    // Ordered one way by `Ord`, which the map uses, and the other by `PartialOrd`, which the
+    // tree uses
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct Reversed(u64);
+
+    impl Ord for Reversed {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[allow(clippy::non_canonical_partial_ord_impl)]
+    impl PartialOrd for Reversed {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(other.0.cmp(&self.0))
+        }
+    }
+
+    impl fmt::Display for Reversed {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    #[test]
+    fn divergences_carry_the_history_that_reproduces_them() {
+        let options = DifferentialOptions {
+            steps: 500,
+            ..DifferentialOptions::default()
+        };
+        let divergence = run(options, Reversed, |n| n).unwrap_err();
+        assert!(
+            matches!(divergence.operation(), Some(Operation::Range(..))),
+            "{}",
+            divergence
+        );
+        assert_eq!(divergence.history.len(), divergence.step + 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let replayed = replay(
+            dir.path().join("replay.db"),
+            options.config,
+            &divergence.history,
+            0,
+        )
+        .unwrap_err();
+        assert_eq!(replayed.step, divergence.step);
+        assert_eq!(replayed.reason, divergence.reason);
+
+        let generated = generate(
+            &DifferentialOptions {
+                steps: 10,
+                reopen_every: 5,
+                ..DifferentialOptions::default()
+            },
+            |n| n,
+            |n| n,
+        );
+        assert_eq!(generated[4], Operation::Reopen);
+        assert_eq!(generated[9], Operation::Reopen);
+    }
+}
@@ -1,6 +1,14 @@
+use crate::codec::{CodecError, SerializationError};
+use crate::compression::CompressionError;
+use crate::config::ConfigError;
 use crate::header::HeaderError;
+#[cfg(feature = "std")]
+use crate::manifest::ManifestError;
 use crate::page_manager::PageManagerError;
 use crate::slotted_page::SlottedPageError;
+use crate::value_log::ValueLogError;
+use alloc::string::String;
+use core::time::Duration;
 
 impl From<SlottedPageError> for BTreeError {
     fn from(err: SlottedPageError) -> BTreeError {
@@ -10,18 +18,67 @@ impl From<SlottedPageError> for BTreeError {
 
 #[derive(Debug)]
 pub enum BTreeError {
-    Io(std::io::Error),
-    Serialization(bincode::Error),
+    Io(crate::io::Error),
+    Serialization(SerializationError),
     Header(HeaderError),
     PageManager(PageManagerError),
     SlottedPage(SlottedPageError),
+    Compression(CompressionError),
+    Codec(CodecError),
+    Config(ConfigError),
+    #[cfg(feature = "std")]
+    Manifest(ManifestError),
+    ValueLog(ValueLogError),
     KeyNotFound(String),
     InvalidNodeType(u8),
-    PageOverflow { page_id: u64 },
+    PageOverflow {
+        page_id: u64,
+    },
+    /// An entry of `size` bytes once stored is larger than the `max` a page can hold.
+    EntryTooLarge {
+        size: usize,
+        max: usize,
+    },
+    TreeNotEmpty,
+    UnsortedBulkLoad(String),
+    DuplicateKey(String),
+    NoDuplicateResolver,
+    NoDuplicateOrder,
+    TreeModified,
+    #[cfg(feature = "std")]
+    AlreadyOpen(std::path::PathBuf),
+    Corrupted {
+        page_id: u64,
+        reason: String,
+    },
+    /// Too many pages were written since the last flush and the tree is set to reject writes
+    /// rather than flush them itself.
+    Busy {
+        retry_after: Duration,
+    },
+    /// A read asked for a tree that has applied every write up to `required`, and this one has
+    /// only applied those up to `applied`.
+    LsnNotReached {
+        required: u64,
+        applied: u64,
+    },
+    /// The operation cannot be done on this tree, for the reason given.
+    Unsupported(String),
+    /// `key` falls in a range locked by `owner`, which must release it before the key can be
+    /// written.
+    RangeLocked {
+        key: String,
+        owner: u64,
+    },
+    /// A continuation token given to `scan_page` was not one it returned for the same range.
+    InvalidContinuationToken(String),
+    /// The handle found the tree's invariants broken, for the reason given, and was poisoned
+    /// rather than panicking; see `InvariantViolation::Poison`.
+    Poisoned(String),
 }
 
-impl std::fmt::Display for BTreeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             BTreeError::Io(e) => {
                 write!(f, "IO error: {}", e)
@@ -38,6 +95,22 @@ impl std::fmt::Display for BTreeError {
             BTreeError::SlottedPage(e) => {
                 write!(f, "SlottedPage error: {}", e)
             }
+            BTreeError::Compression(e) => {
+                write!(f, "Compression error: {}", e)
+            }
+            BTreeError::Codec(e) => {
+                write!(f, "Codec error: {}", e)
+            }
+            BTreeError::Config(e) => {
+                write!(f, "Config error: {}", e)
+            }
+            #[cfg(feature = "std")]
+            BTreeError::Manifest(e) => {
+                write!(f, "Manifest error: {}", e)
+            }
+            BTreeError::ValueLog(e) => {
+                write!(f, "Value log error: {}", e)
+            }
             BTreeError::KeyNotFound(key) => {
                 write!(f, "KeyNotFound: {}", key)
             }
@@ -47,13 +120,97 @@ impl std::fmt::Display for BTreeError {
             BTreeError::PageOverflow { page_id } => {
                 write!(f, "PageOverflow: page_id={}", page_id)
             }
+            BTreeError::EntryTooLarge { size, max } => {
+                write!(
+                    f,
+                    "EntryTooLarge: {} bytes, at most {} fit in a page",
+                    size, max
+                )
+            }
+            BTreeError::TreeNotEmpty => {
+                write!(f, "TreeNotEmpty: operation requires an empty tree")
+            }
+            BTreeError::UnsortedBulkLoad(key) => {
+                write!(
+                    f,
+                    "UnsortedBulkLoad: key {} is not greater than its predecessor",
+                    key
+                )
+            }
+            BTreeError::DuplicateKey(key) => {
+                write!(f, "DuplicateKey: {}", key)
+            }
+            BTreeError::NoDuplicateResolver => {
+                write!(
+                    f,
+                    "NoDuplicateResolver: the tree resolves duplicates but no resolver is set"
+                )
+            }
+            BTreeError::NoDuplicateOrder => {
+                write!(
+                    f,
+                    "NoDuplicateOrder: the tree sorts duplicates but no order is set"
+                )
+            }
+            BTreeError::TreeModified => {
+                write!(
+                    f,
+                    "TreeModified: the tree was written to since the cursor was created"
+                )
+            }
+            #[cfg(feature = "std")]
+            BTreeError::AlreadyOpen(path) => {
+                write!(
+                    f,
+                    "AlreadyOpen: {} is already open in this process",
+                    path.display()
+                )
+            }
+            BTreeError::Corrupted { page_id, reason } => {
+                write!(f, "Corrupted: page_id={}: {}", page_id, reason)
+            }
+            BTreeError::Busy { retry_after } => {
+                write!(
+                    f,
+                    "Busy: too many unflushed pages, retry after {:?}",
+                    retry_after
+                )
+            }
+            BTreeError::LsnNotReached { required, applied } => {
+                write!(
+                    f,
+                    "LsnNotReached: read requires LSN {}, tree is at {}",
+                    required, applied
+                )
+            }
+            BTreeError::Unsupported(reason) => {
+                write!(f, "Unsupported: {}", reason)
+            }
+            BTreeError::RangeLocked { key, owner } => {
+                write!(
+                    f,
+                    "RangeLocked: key {} falls in a range locked by owner {}",
+                    key, owner
+                )
+            }
+            BTreeError::InvalidContinuationToken(reason) => {
+                write!(f, "Invalid continuation token: {}", reason)
+            }
+            BTreeError::Poisoned(reason) => {
+                write!(f, "Poisoned: {}", reason)
+            }
         }
     }
 }
 
-impl From<std::io::Error> for BTreeError {
-    fn from(err: std::io::Error) -> BTreeError {
-        BTreeError::Io(err)
+impl From<crate::io::Error> for BTreeError {
+    fn from(err: crate::io::Error) -> BTreeError {
+        match err.kind() {
+            crate::io::ErrorKind::StorageFull | crate::io::ErrorKind::QuotaExceeded => {
+                BTreeError::PageManager(PageManagerError::StorageFull)
+            }
+            _ => BTreeError::Io(err),
+        }
     }
 }
 
@@ -69,8 +226,39 @@ impl From<PageManagerError> for BTreeError {
     }
 }
 
-impl From<bincode::Error> for BTreeError {
-    fn from(err: bincode::Error) -> BTreeError {
+impl From<SerializationError> for BTreeError {
+    fn from(err: SerializationError) -> BTreeError {
         BTreeError::Serialization(err)
     }
 }
+
+impl From<CompressionError> for BTreeError {
+    fn from(err: CompressionError) -> BTreeError {
+        BTreeError::Compression(err)
+    }
+}
+
+impl From<CodecError> for BTreeError {
+    fn from(err: CodecError) -> BTreeError {
+        BTreeError::Codec(err)
+    }
+}
+
+impl From<ConfigError> for BTreeError {
+    fn from(err: ConfigError) -> BTreeError {
+        BTreeError::Config(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ManifestError> for BTreeError {
+    fn from(err: ManifestError) -> BTreeError {
+        BTreeError::Manifest(err)
+    }
+}
+
+impl From<ValueLogError> for BTreeError {
+    fn from(err: ValueLogError) -> BTreeError {
+        BTreeError::ValueLog(err)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::types::NodeType;
+use core::time::Duration;
+
+/// A page ran out of space and half of its entries moved to a newly allocated page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitEvent {
+    pub page_id: u64,
+    pub new_page_id: u64,
+    pub node_type: NodeType,
+    pub left_entries: u16,
+    pub right_entries: u16,
+}
+
+/// Two under-full sibling pages were combined into one; `removed_page_id` no longer holds entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeEvent {
+    pub page_id: u64,
+    pub removed_page_id: u64,
+    pub node_type: NodeType,
+    pub entries: u16,
+}
+
+/// A fragmented page was rewritten in place so its holes became contiguous free space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactEvent {
+    pub page_id: u64,
+    pub node_type: NodeType,
+    pub holes_reclaimed: usize,
+    pub bytes_reclaimed: usize,
+}
+
+/// Buffered state was written out and synced to disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlushEvent {
+    pub page_count: u64,
+    pub page_size: u64,
+}
+
+/// A write was held up to flush the pages written before it, as the tree's limit on dirty
+/// pages was reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteStallEvent {
+    pub dirty_pages: u64,
+}
+
+/// Value log segments holding only garbage were kept when collecting it, as a snapshot taken
+/// longer ago than the tree's alert threshold may still read them. A snapshot that is never
+/// dropped keeps every segment from then on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleSnapshotEvent {
+    /// How long the oldest snapshot has been held.
+    pub age: Duration,
+    pub snapshots: usize,
+    pub bytes_held: u64,
+}
+
+/// Receives structural events from a tree. Every method defaults to doing nothing, so observers
+/// only implement the events they care about.
+///
+/// Callbacks run synchronously on the thread performing the operation, so they should be cheap.
+pub trait TreeObserver: Send + Sync {
+    fn on_split(&self, _event: &SplitEvent) {}
+    fn on_merge(&self, _event: &MergeEvent) {}
+    fn on_compact(&self, _event: &CompactEvent) {}
+    fn on_flush(&self, _event: &FlushEvent) {}
+    fn on_write_stall(&self, _event: &WriteStallEvent) {}
+    fn on_stale_snapshot(&self, _event: &StaleSnapshotEvent) {}
+}
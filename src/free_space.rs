@@ -1,4 +1,21 @@
-use std::fmt::Debug;
+use core::fmt::Debug;
+
+/// Which of a page's free regions an entry goes in when more than one has room for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitPolicy {
+    /// The region that leaves the least room over, keeping large regions for large entries.
+    #[default]
+    BestFit,
+    /// The first region found with room, scanning from the most recently freed, which keeps
+    /// placing entries quick on pages with many regions.
+    FirstFit,
+    /// The region with room nearest the start of the page.
+    AddressOrdered,
+}
+
+/// Free regions a page keeps before it compacts to merge them all into one, unless set
+/// otherwise.
+pub const DEFAULT_MAX_FREE_REGIONS: usize = 32;
 
 #[derive(Debug)]
 pub struct FreeSpaceRegion {
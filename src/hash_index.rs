@@ -0,0 +1,400 @@
+//! An extendible hash index: point lookups that read a single bucket page, for keyspaces that
+//! are never scanned in order. Buckets are leaf [`SlottedPage`]s kept by a [`PageManager`], like
+//! the pages of a [`BTree`].
+
+use crate::btree::BTree;
+use crate::checksum::crc32;
+use crate::codec::Encoding;
+use crate::error::BTreeError;
+use crate::page_manager::PageManager;
+use crate::slotted_page::SlottedPage;
+use crate::storage::{MemoryStorage, Storage};
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use log::debug;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"CLHX";
+const VERSION: u16 = 1;
+
+// magic(4) + version(2) + global_depth(1) + reserved(1) + page_size(8) + directory_page(8)
+const HEADER_SIZE: u64 = 24;
+
+// The directory stops doubling at a million entries (8 MiB); a bucket still full by then holds
+// keys whose hashes agree on that many bits, which splitting further is unlikely to separate
+const MAX_DEPTH: u8 = 20;
+
+/// Point lookups, inserts and deletes, whichever kind of index serves them, so each keyspace
+/// can use the kind that suits it.
+pub trait PointIndex<K, V> {
+    /// Stores `value` under `key`, replacing any value already there.
+    fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError>;
+
+    /// The value under `key`, or `KeyNotFound`.
+    fn search(&mut self, key: K) -> Result<V, BTreeError>;
+
+    /// Removes the value under `key` and returns it, or fails with `KeyNotFound`.
+    fn delete(&mut self, key: K) -> Result<V, BTreeError>;
+}
+
+impl<K, V> PointIndex<K, V> for BTree<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        BTree::insert(self, key, value)
+    }
+
+    fn search(&mut self, key: K) -> Result<V, BTreeError> {
+        BTree::search(self, key)
+    }
+
+    fn delete(&mut self, key: K) -> Result<V, BTreeError> {
+        BTree::delete(self, key)
+    }
+}
+
+/// Maps keys to values through a directory of `2^global_depth` bucket pages, indexed by the
+/// low bits of each key's hash. A full bucket is split in two on the next bit of its keys'
+/// hashes, doubling the directory first if the bucket already uses every bit it has. Buckets
+/// are never merged, and directories outgrown are left behind in the file.
+pub struct HashIndex<K, V> {
+    page_manager: PageManager,
+    page_size: usize,
+    global_depth: u8,
+    // Bucket page of each hash suffix
+    directory: Vec<u64>,
+    // First of the pages the directory is written to, and how many there are
+    directory_page: u64,
+    directory_pages: u64,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<K, V> HashIndex<K, V>
+where
+    K: PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Opens the index in `storage`, creating an empty one if the storage is empty.
+    pub fn new<S: Storage + 'static>(storage: S, page_size: u64) -> Result<Self, BTreeError> {
+        Self::from_page_manager(PageManager::new(storage, page_size, HEADER_SIZE)?)
+    }
+
+    pub fn in_memory(page_size: u64) -> Result<Self, BTreeError> {
+        Self::new(MemoryStorage::new(), page_size)
+    }
+
+    /// Opens the index at `path`, creating it if needed. The file is locked like
+    /// [`PageManager::open`] locks it.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P, page_size: u64) -> Result<Self, BTreeError> {
+        Self::from_page_manager(PageManager::open(path, page_size, HEADER_SIZE)?)
+    }
+
+    fn from_page_manager(mut page_manager: PageManager) -> Result<Self, BTreeError> {
+        let page_size = page_manager.page_size;
+        let header = page_manager.read_header()?;
+        let mut index = HashIndex {
+            page_manager,
+            page_size: page_size as usize,
+            global_depth: 0,
+            directory: Vec::new(),
+            directory_page: 0,
+            directory_pages: 0,
+            _phantom: PhantomData,
+        };
+
+        // The page manager zeroes the header of new storage
+        if header.iter().all(|&b| b == 0) {
+            let bucket = index.page_manager.allocate_page()?;
+            index.write_bucket(&SlottedPage::builder(bucket, index.page_size).build())?;
+            index.directory.push(bucket);
+            index.write_directory()?;
+            return Ok(index);
+        }
+
+        let corrupted = |reason: String| BTreeError::Corrupted { page_id: 0, reason };
+        if header[0..4] != MAGIC {
+            return Err(corrupted("not a hash index".to_string()));
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        let stored_page_size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if version != VERSION || stored_page_size != page_size || header[6] > MAX_DEPTH {
+            return Err(corrupted(format!(
+                "hash index of version {} with pages of {} bytes and depth {}",
+                version, stored_page_size, header[6]
+            )));
+        }
+        index.global_depth = header[6];
+        index.directory_page = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        index.directory_pages = index.directory_pages_needed();
+        let bytes = index
+            .page_manager
+            .read_pages(index.directory_page, index.directory_pages)?;
+        index.directory = bytes
+            .chunks_exact(8)
+            .take(1 << index.global_depth)
+            .map(|entry| u64::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+        debug!(
+            "Opened hash index with depth {} and {} bucket slots",
+            index.global_depth,
+            index.directory.len()
+        );
+        Ok(index)
+    }
+
+    fn directory_pages_needed(&self) -> u64 {
+        ((8usize << self.global_depth).div_ceil(self.page_size)) as u64
+    }
+
+    // Writes the directory and a header pointing at it, moving the directory to new pages
+    // once it has outgrown the old ones
+    fn write_directory(&mut self) -> Result<(), BTreeError> {
+        let pages = self.directory_pages_needed();
+        if pages > self.directory_pages {
+            self.directory_page = self.page_manager.allocate_pages(pages)?.start;
+            self.directory_pages = pages;
+        }
+        let mut bytes = vec![0u8; pages as usize * self.page_size];
+        for (entry, page_id) in bytes.chunks_exact_mut(8).zip(&self.directory) {
+            entry.copy_from_slice(&page_id.to_le_bytes());
+        }
+        self.page_manager.write_page(self.directory_page, &bytes)?;
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = self.global_depth;
+        header[8..16].copy_from_slice(&(self.page_size as u64).to_le_bytes());
+        header[16..24].copy_from_slice(&self.directory_page.to_le_bytes());
+        self.page_manager.write_header(&header)?;
+        Ok(())
+    }
+
+    fn hash(key: &K) -> Result<u32, BTreeError> {
+        Ok(crc32(&Encoding::Bincode.serialize(key)?))
+    }
+
+    fn slot_of(&self, hash: u32) -> usize {
+        (hash as u64 & ((1u64 << self.global_depth) - 1)) as usize
+    }
+
+    fn read_bucket(&mut self, page_id: u64) -> Result<SlottedPage<K, V>, BTreeError> {
+        let buffer = self.page_manager.read_page(page_id)?;
+        Ok(SlottedPage::from_buffer(buffer)?)
+    }
+
+    fn write_bucket(&mut self, bucket: &SlottedPage<K, V>) -> Result<(), BTreeError> {
+        self.page_manager
+            .write_page(bucket.page_id, &bucket.serialize()?)?;
+        Ok(())
+    }
+
+    /// Stores `value` under `key`, replacing any value already there.
+    pub fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        let hash = Self::hash(&key)?;
+        loop {
+            let page_id = self.directory[self.slot_of(hash)];
+            let mut bucket = self.read_bucket(page_id)?;
+            if let Some(pos) = bucket.find_exact_key(&key)? {
+                bucket.delete(pos)?;
+            }
+            let (key_len, value_len) = bucket.encoded_len(&key, &value)?;
+            if !bucket.can_insert(key_len, value_len) {
+                bucket.compact()?;
+            }
+            if bucket.can_insert(key_len, value_len) {
+                bucket.insert_sorted(&key, &value)?;
+                return self.write_bucket(&bucket);
+            }
+            if bucket.is_empty() {
+                // No split could ever make room
+                return Err(BTreeError::PageOverflow { page_id });
+            }
+            // Split the bucket as stored, so failing to leaves any old value in place
+            let bucket = self.read_bucket(page_id)?;
+            self.split(bucket)?;
+        }
+    }
+
+    // Moves the entries of `bucket` whose hash has its next unused bit set to a new bucket
+    fn split(&mut self, mut bucket: SlottedPage<K, V>) -> Result<(), BTreeError> {
+        let page_id = bucket.page_id;
+        let sharing = self.directory.iter().filter(|&&id| id == page_id).count();
+        let local_depth = self.global_depth - sharing.trailing_zeros() as u8;
+        if local_depth == self.global_depth {
+            if self.global_depth == MAX_DEPTH {
+                return Err(BTreeError::PageOverflow { page_id });
+            }
+            self.directory.extend_from_within(..);
+            self.global_depth += 1;
+        }
+
+        let bit = 1u32 << local_depth;
+        let mut sibling =
+            SlottedPage::builder(self.page_manager.allocate_page()?, self.page_size).build();
+        for pos in (0..bucket.len()).rev() {
+            let key = bucket.read_key(pos)?;
+            if Self::hash(&key)? & bit != 0 {
+                sibling.insert(0, &key, &bucket.read_value(pos)?)?;
+                bucket.delete(pos)?;
+            }
+        }
+        debug!(
+            "Split hash bucket {} at depth {} into {}, {} entries moved",
+            page_id,
+            local_depth,
+            sibling.page_id,
+            sibling.len()
+        );
+
+        for (slot, entry) in self.directory.iter_mut().enumerate() {
+            if *entry == page_id && slot as u32 & bit != 0 {
+                *entry = sibling.page_id;
+            }
+        }
+        self.write_bucket(&bucket)?;
+        self.write_bucket(&sibling)?;
+        self.write_directory()
+    }
+
+    /// The value under `key`, or `KeyNotFound`.
+    pub fn search(&mut self, key: K) -> Result<V, BTreeError> {
+        let page_id = self.directory[self.slot_of(Self::hash(&key)?)];
+        let bucket = self.read_bucket(page_id)?;
+        match bucket.find_exact_key(&key)? {
+            Some(pos) => bucket.read_value(pos),
+            None => Err(BTreeError::KeyNotFound(key.to_string())),
+        }
+    }
+
+    /// Removes the value under `key` and returns it, or fails with `KeyNotFound`.
+    pub fn delete(&mut self, key: K) -> Result<V, BTreeError> {
+        let page_id = self.directory[self.slot_of(Self::hash(&key)?)];
+        let mut bucket = self.read_bucket(page_id)?;
+        let Some(pos) = bucket.find_exact_key(&key)? else {
+            return Err(BTreeError::KeyNotFound(key.to_string()));
+        };
+        let value = bucket.read_value(pos)?;
+        bucket.delete(pos)?;
+        self.write_bucket(&bucket)?;
+        Ok(value)
+    }
+
+    /// Bits of each key's hash the directory is indexed by.
+    pub fn global_depth(&self) -> u8 {
+        self.global_depth
+    }
+
+    /// Makes every change so far durable.
+    pub fn flush(&mut self) -> Result<(), BTreeError> {
+        Ok(self.page_manager.sync()?)
+    }
+}
+
+impl<K, V> PointIndex<K, V> for HashIndex<K, V>
+where
+    K: PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn insert(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        HashIndex::insert(self, key, value)
+    }
+
+    fn search(&mut self, key: K) -> Result<V, BTreeError> {
+        HashIndex::search(self, key)
+    }
+
+    fn delete(&mut self, key: K) -> Result<V, BTreeError> {
+        HashIndex::delete(self, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn splits_keep_every_key_reachable() {
+        let mut index = HashIndex::<u64, String>::in_memory(256).unwrap();
+        for i in 0..2000 {
+            index.insert(i, format!("value-{}", i)).unwrap();
+        }
+        assert!(index.global_depth() >= 6);
+
+        for i in 0..2000 {
+            assert_eq!(index.search(i).unwrap(), format!("value-{}", i));
+        }
+        assert!(matches!(
+            index.search(2000),
+            Err(BTreeError::KeyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn inserts_replace_and_deletes_remove() {
+        let mut index = HashIndex::<u64, String>::in_memory(512).unwrap();
+        for i in 0..300 {
+            index.insert(i, "short".to_string()).unwrap();
+        }
+        for i in (0..300).step_by(2) {
+            index.insert(i, "a much longer value".to_string()).unwrap();
+        }
+        for i in (0..300).step_by(3) {
+            assert_eq!(index.delete(i).unwrap().len() > 5, i % 2 == 0);
+        }
+
+        for i in 0..300 {
+            let expected = match (i % 3, i % 2) {
+                (0, _) => None,
+                (_, 0) => Some("a much longer value".to_string()),
+                _ => Some("short".to_string()),
+            };
+            assert_eq!(index.search(i).ok(), expected);
+        }
+    }
+
+    #[test]
+    fn indexes_reopen_with_their_directory() {
+        let file = NamedTempFile::new().unwrap();
+        let mut index = HashIndex::<String, u64>::new(file.reopen().unwrap(), 512).unwrap();
+        for i in 0..1000 {
+            index.insert(format!("key-{}", i), i).unwrap();
+        }
+        let depth = index.global_depth();
+        index.flush().unwrap();
+        drop(index);
+
+        let mut index = HashIndex::<String, u64>::new(file.reopen().unwrap(), 512).unwrap();
+        assert_eq!(index.global_depth(), depth);
+        for i in 0..1000 {
+            assert_eq!(index.search(format!("key-{}", i)).unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn either_index_serves_point_lookups() {
+        let indexes: Vec<Box<dyn PointIndex<u32, u32>>> = vec![
+            Box::new(BTree::temporary(512).unwrap()),
+            Box::new(HashIndex::in_memory(512).unwrap()),
+        ];
+        for mut index in indexes {
+            for i in 0..500 {
+                index.insert(i, i * 3).unwrap();
+            }
+            assert_eq!(index.delete(7).unwrap(), 21);
+            assert_eq!(index.search(100).unwrap(), 300);
+            assert!(index.search(7).is_err());
+        }
+    }
+}
@@ -1,10 +1,44 @@
+use crate::codec::Encoding;
+use crate::config::{
+    ChecksumAlgorithm, CompressionAlgorithm, DeleteStrategy, DuplicatePolicy, TreeConfig,
+};
+use crate::constants::{
+    CHECKSUM_VERSION, CONFIG_BLOCK_VERSION, ENCODING_VERSION, LEAF_PAGE_SIZE_VERSION,
+    VALUE_LOG_VERSION, VERSION,
+};
+use crate::types::NodeType;
+use alloc::format;
+use alloc::string::String;
+
 #[derive(Debug)]
 pub struct Header {
     magic_number: u16,
     pub version: u16,
     pub page_size: u64,
+    /// Size of each leaf page, a multiple of `page_size`; 0 when leaves are `page_size` too.
+    pub leaf_page_size: u64,
     pub root_page_id: u64,
+    /// Pages allocated in the primary file when the header was last written, like
+    /// `next_page_id`. Files written by earlier builds may count fewer, as it used to be kept
+    /// separately and missed pages such as the unused ones reserved by a bulk load.
     pub page_count: u64,
+    pub dictionary_page_id: u64,
+    pub leaf_fill_factor: u8,
+    pub internal_fill_factor: u8,
+    pub split_threshold: u8,
+    pub delete_strategy: DeleteStrategy,
+    pub stats_page_id: u64,
+    /// Pages allocated in the primary file, and so the ID the next allocation receives.
+    pub next_page_id: u64,
+    pub duplicate_policy: DuplicatePolicy,
+    pub subtree_counts: bool,
+    pub comparator_id: u16,
+    pub checksum: ChecksumAlgorithm,
+    pub compression: CompressionAlgorithm,
+    pub value_log_threshold: u16,
+    pub encoding: Encoding,
+    /// Id of the custom codec the values are encoded by, or 0 if they use `encoding` too.
+    pub value_codec_id: u8,
 }
 
 #[derive(Debug)]
@@ -12,10 +46,13 @@ pub enum HeaderError {
     InvalidMagicNumber(u16),
     InvalidBufferSize { expected: usize, got: usize },
     CorruptedData(String),
+    UnsupportedVersion(u16),
+    PageSizeMismatch { expected: u64, got: u64 },
+    ComparatorMismatch { expected: u16, got: u16 },
 }
 
-impl std::fmt::Display for HeaderError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             HeaderError::InvalidMagicNumber(num) => {
                 write!(f, "Invalid magic number: {} (must be > 0)", num)
@@ -26,12 +63,35 @@ impl std::fmt::Display for HeaderError {
             HeaderError::CorruptedData(msg) => {
                 write!(f, "Corrupted header data: {}", msg)
             }
+            HeaderError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Unsupported format version: {} (this build reads up to {})",
+                    version, VERSION
+                )
+            }
+            HeaderError::PageSizeMismatch { expected, got } => {
+                write!(f, "Page size mismatch: expected {}, got {}", expected, got)
+            }
+            HeaderError::ComparatorMismatch { expected, got } => {
+                write!(
+                    f,
+                    "Comparator mismatch: expected {}, but the tree was built with {}",
+                    expected, got
+                )
+            }
         }
     }
 }
 
 impl Header {
-    pub const SIZE: usize = 28;
+    pub const SIZE: usize = 64;
+
+    /// Page 0 always holds the initial root, so it can never be a dictionary page.
+    pub const NO_DICTIONARY: u64 = 0;
+
+    /// Page 0 always holds the initial root, so it can never be the stats page either.
+    pub const NO_STATS: u64 = 0;
 
     pub fn new(
         magic_number: u16,
@@ -44,34 +104,196 @@ impl Header {
             magic_number,
             version,
             page_size,
+            leaf_page_size: 0,
             root_page_id,
             page_count,
+            dictionary_page_id: Self::NO_DICTIONARY,
+            leaf_fill_factor: TreeConfig::default().leaf_fill_factor,
+            internal_fill_factor: TreeConfig::default().internal_fill_factor,
+            split_threshold: TreeConfig::default().split_threshold,
+            delete_strategy: TreeConfig::default().delete_strategy,
+            stats_page_id: Self::NO_STATS,
+            next_page_id: 0,
+            duplicate_policy: TreeConfig::default().duplicate_policy,
+            subtree_counts: TreeConfig::default().subtree_counts,
+            comparator_id: TreeConfig::default().comparator_id,
+            checksum: TreeConfig::default().checksum,
+            compression: CompressionAlgorithm::None,
+            value_log_threshold: TreeConfig::default().value_log_threshold,
+            encoding: TreeConfig::default().encoding,
+            value_codec_id: 0,
         }
     }
 
+    pub fn config(&self) -> TreeConfig {
+        TreeConfig {
+            page_size: self.page_size,
+            leaf_page_size: self.leaf_page_size,
+            leaf_fill_factor: self.leaf_fill_factor,
+            internal_fill_factor: self.internal_fill_factor,
+            split_threshold: self.split_threshold,
+            delete_strategy: self.delete_strategy,
+            duplicate_policy: self.duplicate_policy,
+            subtree_counts: self.subtree_counts,
+            comparator_id: self.comparator_id,
+            checksum: self.checksum,
+            value_log_threshold: self.value_log_threshold,
+            encoding: self.encoding,
+            cache_size: TreeConfig::default().cache_size,
+            max_file_size: TreeConfig::default().max_file_size,
+            max_dirty_pages: TreeConfig::default().max_dirty_pages,
+            backpressure: TreeConfig::default().backpressure,
+            write_mode: TreeConfig::default().write_mode,
+            on_invariant_violation: TreeConfig::default().on_invariant_violation,
+        }
+    }
+
+    /// Persists the tunable knobs of `config`. The page sizes, comparator, checksum algorithm
+    /// and encoding of an existing tree never change, so they are only taken from `config` by
+    /// `create`.
+    pub fn set_config(&mut self, config: &TreeConfig) {
+        self.leaf_fill_factor = config.leaf_fill_factor;
+        self.internal_fill_factor = config.internal_fill_factor;
+        self.split_threshold = config.split_threshold;
+        self.delete_strategy = config.delete_strategy;
+        self.duplicate_policy = config.duplicate_policy;
+        self.subtree_counts = config.subtree_counts;
+        self.value_log_threshold = config.value_log_threshold;
+    }
+
+    /// Header of a new tree built with `config`.
+    pub fn create(config: &TreeConfig) -> Self {
+        let mut header = Header::new(1, VERSION, config.page_size, 0, 0);
+        header.set_config(config);
+        header.leaf_page_size = config.leaf_page_size;
+        header.comparator_id = config.comparator_id;
+        header.checksum = config.checksum;
+        header.encoding = config.encoding;
+        header
+    }
+
+    /// Fails unless a tree built with this header may be opened by a caller ordering keys by
+    /// `comparator_id`. Either side leaving the comparator unspecified (0) accepts the other.
+    pub fn check_comparator(&self, comparator_id: u16) -> Result<(), HeaderError> {
+        if comparator_id != 0 && self.comparator_id != 0 && comparator_id != self.comparator_id {
+            return Err(HeaderError::ComparatorMismatch {
+                expected: comparator_id,
+                got: self.comparator_id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether pages written to the tree carry a checksum.
+    pub fn checksums_pages(&self) -> bool {
+        self.checksum != ChecksumAlgorithm::None
+    }
+
+    /// Size of the pages holding nodes of `node_type`; every other page is `page_size`.
+    pub fn node_size(&self, node_type: NodeType) -> u64 {
+        match node_type {
+            NodeType::LEAF if self.leaf_page_size != 0 => self.leaf_page_size,
+            _ => self.page_size,
+        }
+    }
+
+    /// Number of consecutive page IDs a node of `node_type` takes up.
+    pub fn node_pages(&self, node_type: NodeType) -> u64 {
+        self.node_size(node_type) / self.page_size
+    }
+
+    pub fn has_dictionary(&self) -> bool {
+        self.dictionary_page_id != Self::NO_DICTIONARY
+    }
+
+    pub fn has_stats(&self) -> bool {
+        self.stats_page_id != Self::NO_STATS
+    }
+
     pub fn pages_empty(&self) -> bool {
         self.page_count == 0
     }
 
-    pub fn add_root_page(&mut self, root_page_id: u64) {
-        self.root_page_id = root_page_id;
+    /// Records `pages` as the number of pages allocated in the primary file.
+    pub fn set_page_count(&mut self, pages: u64) {
+        self.page_count = pages;
+        self.next_page_id = pages;
     }
 
-    pub fn add_page(&mut self) {
-        self.page_count += 1;
+    /// Checks a header read from a file against itself: every page it points to must have been
+    /// allocated, and it cannot count more pages than were. Pages moved to a cold tier are not
+    /// counted, so the root, which never moves, is the only tree page checked.
+    pub fn check_page_counts(&self) -> Result<(), HeaderError> {
+        let allocated = self.next_page_id;
+        if self.page_count > allocated {
+            return Err(HeaderError::CorruptedData(format!(
+                "{} pages counted but only {} allocated",
+                self.page_count, allocated
+            )));
+        }
+        let pages = [
+            ("root", Some(self.root_page_id)),
+            ("stats", self.has_stats().then_some(self.stats_page_id)),
+            (
+                "dictionary",
+                self.has_dictionary().then_some(self.dictionary_page_id),
+            ),
+        ];
+        for (name, page_id) in pages {
+            if let Some(page_id) = page_id
+                && page_id >= allocated
+            {
+                return Err(HeaderError::CorruptedData(format!(
+                    "{} page {} lies past the {} allocated pages",
+                    name, page_id, allocated
+                )));
+            }
+        }
+        Ok(())
     }
 
     pub fn serialize(&self) -> [u8; Self::SIZE] {
         let mut buffer = [0u8; Self::SIZE];
         buffer[0..2].copy_from_slice(&self.magic_number.to_le_bytes());
         buffer[2..4].copy_from_slice(&self.version.to_le_bytes());
-        buffer[4..12].copy_from_slice(&self.page_size.to_le_bytes());
+        buffer[4..8].copy_from_slice(&(self.page_size as u32).to_le_bytes());
+        buffer[8..10].copy_from_slice(&(self.leaf_page_size as u16).to_le_bytes());
+        buffer[10] = self.encoding.to_byte();
+        buffer[11] = self.value_codec_id;
         buffer[12..20].copy_from_slice(&self.root_page_id.to_le_bytes());
         buffer[20..28].copy_from_slice(&self.page_count.to_le_bytes());
+        buffer[28..36].copy_from_slice(&self.dictionary_page_id.to_le_bytes());
+        buffer[36] = self.leaf_fill_factor;
+        buffer[37] = self.internal_fill_factor;
+        buffer[38] = self.split_threshold;
+        buffer[39] = self.delete_strategy.to_byte();
+        buffer[40..48].copy_from_slice(&self.stats_page_id.to_le_bytes());
+        buffer[48..56].copy_from_slice(&self.next_page_id.to_le_bytes());
+        buffer[56] = self.duplicate_policy.to_byte();
+        buffer[57] = self.subtree_counts as u8;
+        buffer[58..60].copy_from_slice(&self.comparator_id.to_le_bytes());
+        buffer[60] = self.checksum.to_byte();
+        buffer[61] = self.compression.to_byte();
+        buffer[62..64].copy_from_slice(&self.value_log_threshold.to_le_bytes());
 
         buffer
     }
 
+    /// Checks that a header read from an existing file describes a tree this build can open
+    /// with pages of `page_size` bytes.
+    pub fn validate(&self, page_size: u64) -> Result<(), HeaderError> {
+        if self.version == 0 || self.version > VERSION {
+            return Err(HeaderError::UnsupportedVersion(self.version));
+        }
+        if self.page_size != page_size {
+            return Err(HeaderError::PageSizeMismatch {
+                expected: page_size,
+                got: self.page_size,
+            });
+        }
+        Ok(())
+    }
+
     pub fn deserialize(buffer: &[u8]) -> Result<Self, HeaderError> {
         if buffer.len() < Header::SIZE {
             return Err(HeaderError::InvalidBufferSize {
@@ -86,16 +308,91 @@ impl Header {
         }
 
         let version = u16::from_le_bytes(buffer[2..4].try_into().unwrap());
-        let page_size = u64::from_le_bytes(buffer[4..12].try_into().unwrap());
+        // Page sizes always fitted in 4 bytes, so older headers left the upper half zeroed,
+        // and leaf page sizes in 2
+        let (page_size, leaf_page_size) = match version >= LEAF_PAGE_SIZE_VERSION {
+            true => (
+                u32::from_le_bytes(buffer[4..8].try_into().unwrap()) as u64,
+                u16::from_le_bytes(buffer[8..10].try_into().unwrap()) as u64,
+            ),
+            false => (u64::from_le_bytes(buffer[4..12].try_into().unwrap()), 0),
+        };
+        let (encoding, value_codec_id) = match version >= ENCODING_VERSION {
+            true => {
+                let encoding = Encoding::from_byte(buffer[10]).ok_or_else(|| {
+                    HeaderError::CorruptedData(format!("Unknown encoding: {}", buffer[10]))
+                })?;
+                (encoding, buffer[11])
+            }
+            false => (Encoding::Bincode, 0),
+        };
         let root_page_id = u64::from_le_bytes(buffer[12..20].try_into().unwrap());
         let page_count = u64::from_le_bytes(buffer[20..28].try_into().unwrap());
+        let dictionary_page_id = u64::from_le_bytes(buffer[28..36].try_into().unwrap());
+        let leaf_fill_factor = buffer[36];
+        let internal_fill_factor = buffer[37];
+        let split_threshold = buffer[38];
+        let delete_strategy = DeleteStrategy::from_byte(buffer[39]).ok_or_else(|| {
+            HeaderError::CorruptedData(format!("Unknown delete strategy: {}", buffer[39]))
+        })?;
+        let stats_page_id = u64::from_le_bytes(buffer[40..48].try_into().unwrap());
+        let next_page_id = u64::from_le_bytes(buffer[48..56].try_into().unwrap());
+        let duplicate_policy = DuplicatePolicy::from_byte(buffer[56]).ok_or_else(|| {
+            HeaderError::CorruptedData(format!("Unknown duplicate policy: {}", buffer[56]))
+        })?;
+        let subtree_counts = buffer[57] != 0;
+
+        // Older headers left these bytes zeroed, so what they describe follows from the version
+        let (comparator_id, checksum, compression) = if version >= CONFIG_BLOCK_VERSION {
+            let checksum = ChecksumAlgorithm::from_byte(buffer[60]).ok_or_else(|| {
+                HeaderError::CorruptedData(format!("Unknown checksum algorithm: {}", buffer[60]))
+            })?;
+            let compression = CompressionAlgorithm::from_byte(buffer[61]).ok_or_else(|| {
+                HeaderError::CorruptedData(format!("Unknown compression algorithm: {}", buffer[61]))
+            })?;
+            (
+                u16::from_le_bytes(buffer[58..60].try_into().unwrap()),
+                checksum,
+                compression,
+            )
+        } else {
+            let checksum = match version >= CHECKSUM_VERSION {
+                true => ChecksumAlgorithm::Crc32,
+                false => ChecksumAlgorithm::None,
+            };
+            let compression = match dictionary_page_id != Self::NO_DICTIONARY {
+                true => CompressionAlgorithm::ZstdDictionary,
+                false => CompressionAlgorithm::None,
+            };
+            (0, checksum, compression)
+        };
+        let value_log_threshold = match version >= VALUE_LOG_VERSION {
+            true => u16::from_le_bytes(buffer[62..64].try_into().unwrap()),
+            false => 0,
+        };
 
         Ok(Header {
             magic_number,
             version,
             page_size,
+            leaf_page_size,
             root_page_id,
             page_count,
+            dictionary_page_id,
+            leaf_fill_factor,
+            internal_fill_factor,
+            split_threshold,
+            delete_strategy,
+            stats_page_id,
+            next_page_id,
+            duplicate_policy,
+            subtree_counts,
+            comparator_id,
+            checksum,
+            compression,
+            value_log_threshold,
+            encoding,
+            value_codec_id,
         })
     }
 }
@@ -114,8 +411,24 @@ mod tests {
             magic_number: 1,
             version: 0,
             page_size: 4096,
+            leaf_page_size: 0,
             root_page_id: 0,
             page_count: 1,
+            dictionary_page_id: 0,
+            leaf_fill_factor: 90,
+            internal_fill_factor: 90,
+            split_threshold: 70,
+            delete_strategy: DeleteStrategy::Immediate,
+            stats_page_id: 0,
+            next_page_id: 1,
+            duplicate_policy: DuplicatePolicy::Overwrite,
+            subtree_counts: false,
+            comparator_id: 0,
+            checksum: ChecksumAlgorithm::Crc32,
+            compression: CompressionAlgorithm::None,
+            value_log_threshold: 0,
+            encoding: Encoding::Bincode,
+            value_codec_id: 0,
         };
 
         let bytes = header.serialize();
@@ -133,9 +446,25 @@ mod tests {
         let header = Header {
             magic_number: u16::MAX,
             version: u16::MAX,
-            page_size: u64::MAX,
+            page_size: u32::MAX as u64,
+            leaf_page_size: u16::MAX as u64,
             root_page_id: u64::MAX,
             page_count: u64::MAX,
+            dictionary_page_id: u64::MAX,
+            leaf_fill_factor: u8::MAX,
+            internal_fill_factor: u8::MAX,
+            split_threshold: u8::MAX,
+            delete_strategy: DeleteStrategy::Tombstone,
+            stats_page_id: u64::MAX,
+            next_page_id: u64::MAX,
+            duplicate_policy: DuplicatePolicy::Resolve,
+            subtree_counts: true,
+            comparator_id: 0xABCD,
+            checksum: ChecksumAlgorithm::None,
+            compression: CompressionAlgorithm::ZstdDictionary,
+            value_log_threshold: u16::MAX,
+            encoding: Encoding::BincodeVarint,
+            value_codec_id: u8::MAX,
         };
 
         let bytes = header.serialize();
@@ -143,9 +472,25 @@ mod tests {
 
         assert_eq!(restored.magic_number, u16::MAX);
         assert_eq!(restored.version, u16::MAX);
-        assert_eq!(restored.page_size, u64::MAX);
+        assert_eq!(restored.page_size, u32::MAX as u64);
+        assert_eq!(restored.leaf_page_size, u16::MAX as u64);
         assert_eq!(restored.root_page_id, u64::MAX);
         assert_eq!(restored.page_count, u64::MAX);
+        assert_eq!(restored.dictionary_page_id, u64::MAX);
+        assert_eq!(restored.leaf_fill_factor, u8::MAX);
+        assert_eq!(restored.internal_fill_factor, u8::MAX);
+        assert_eq!(restored.split_threshold, u8::MAX);
+        assert_eq!(restored.delete_strategy, DeleteStrategy::Tombstone);
+        assert_eq!(restored.stats_page_id, u64::MAX);
+        assert_eq!(restored.next_page_id, u64::MAX);
+        assert_eq!(restored.duplicate_policy, DuplicatePolicy::Resolve);
+        assert!(restored.subtree_counts);
+        assert_eq!(restored.comparator_id, 0xABCD);
+        assert_eq!(restored.checksum, ChecksumAlgorithm::None);
+        assert_eq!(restored.compression, CompressionAlgorithm::ZstdDictionary);
+        assert_eq!(restored.value_log_threshold, u16::MAX);
+        assert_eq!(restored.encoding, Encoding::BincodeVarint);
+        assert_eq!(restored.value_codec_id, u8::MAX);
     }
 
     #[test]
@@ -154,8 +499,24 @@ mod tests {
             magic_number: 1,
             version: 0,
             page_size: 4096,
+            leaf_page_size: 0,
             root_page_id: 0,
             page_count: 1,
+            dictionary_page_id: 0,
+            leaf_fill_factor: 90,
+            internal_fill_factor: 90,
+            split_threshold: 70,
+            delete_strategy: DeleteStrategy::Immediate,
+            stats_page_id: 0,
+            next_page_id: 1,
+            duplicate_policy: DuplicatePolicy::Overwrite,
+            subtree_counts: false,
+            comparator_id: 0,
+            checksum: ChecksumAlgorithm::Crc32,
+            compression: CompressionAlgorithm::None,
+            value_log_threshold: 0,
+            encoding: Encoding::Bincode,
+            value_codec_id: 0,
         };
 
         let bytes = header.serialize();
@@ -190,6 +551,26 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn validate_rejects_unknown_versions_and_other_page_sizes() {
+        assert!(Header::new(1, VERSION, 4096, 0, 1).validate(4096).is_ok());
+        assert!(matches!(
+            Header::new(1, VERSION + 1, 4096, 0, 1).validate(4096),
+            Err(HeaderError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+        assert!(matches!(
+            Header::new(1, 0, 4096, 0, 1).validate(4096),
+            Err(HeaderError::UnsupportedVersion(0))
+        ));
+        assert!(matches!(
+            Header::new(1, VERSION, 4096, 0, 1).validate(8192),
+            Err(HeaderError::PageSizeMismatch {
+                expected: 8192,
+                got: 4096
+            })
+        ));
+    }
+
     #[test]
     fn header_accepts_longer_buffer() {
         let mut bytes = vec![0u8; Header::SIZE + 100];
@@ -208,9 +589,25 @@ mod tests {
         let header = Header {
             magic_number: 0x1234,
             version: 0x5678,
-            page_size: 0x1111_2222_3333_4444,
+            page_size: 0x3333_4444,
+            leaf_page_size: 0x2222,
             root_page_id: 0x5555_6666_7777_8888,
             page_count: 0x9999_AAAA_BBBB_CCCC,
+            dictionary_page_id: 0xDDDD_EEEE_FFFF_0000,
+            leaf_fill_factor: 0x11,
+            internal_fill_factor: 0x22,
+            split_threshold: 0x33,
+            delete_strategy: DeleteStrategy::Tombstone,
+            stats_page_id: 0x0102_0304_0506_0708,
+            next_page_id: 0x1112_1314_1516_1718,
+            duplicate_policy: DuplicatePolicy::KeepBoth,
+            subtree_counts: true,
+            comparator_id: 0xABCD,
+            checksum: ChecksumAlgorithm::None,
+            compression: CompressionAlgorithm::ZstdDictionary,
+            value_log_threshold: 0x4321,
+            encoding: Encoding::BincodeVarint,
+            value_codec_id: 0x44,
         };
 
         let bytes = header.serialize();
@@ -219,9 +616,12 @@ mod tests {
         assert_eq!(u16::from_le_bytes(bytes[0..2].try_into().unwrap()), 0x1234);
         assert_eq!(u16::from_le_bytes(bytes[2..4].try_into().unwrap()), 0x5678);
         assert_eq!(
-            u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
-            0x1111_2222_3333_4444
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            0x3333_4444
         );
+        assert_eq!(u16::from_le_bytes(bytes[8..10].try_into().unwrap()), 0x2222);
+        assert_eq!(bytes[10], 1);
+        assert_eq!(bytes[11], 0x44);
         assert_eq!(
             u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
             0x5555_6666_7777_8888
@@ -230,5 +630,153 @@ mod tests {
             u64::from_le_bytes(bytes[20..28].try_into().unwrap()),
             0x9999_AAAA_BBBB_CCCC
         );
+        assert_eq!(
+            u64::from_le_bytes(bytes[28..36].try_into().unwrap()),
+            0xDDDD_EEEE_FFFF_0000
+        );
+        assert_eq!(bytes[36], 0x11);
+        assert_eq!(bytes[37], 0x22);
+        assert_eq!(bytes[38], 0x33);
+        assert_eq!(bytes[39], 1);
+        assert_eq!(
+            u64::from_le_bytes(bytes[40..48].try_into().unwrap()),
+            0x0102_0304_0506_0708
+        );
+        assert_eq!(
+            u64::from_le_bytes(bytes[48..56].try_into().unwrap()),
+            0x1112_1314_1516_1718
+        );
+        assert_eq!(bytes[56], 2);
+        assert_eq!(bytes[57], 1);
+        assert_eq!(
+            u16::from_le_bytes(bytes[58..60].try_into().unwrap()),
+            0xABCD
+        );
+        assert_eq!(bytes[60], 0);
+        assert_eq!(bytes[61], 1);
+        assert_eq!(
+            u16::from_le_bytes(bytes[62..64].try_into().unwrap()),
+            0x4321
+        );
+    }
+
+    #[test]
+    fn headers_before_the_config_block_derive_it_from_their_version() {
+        let mut header = Header::new(1, CHECKSUM_VERSION - 1, 4096, 0, 1);
+        header.dictionary_page_id = 3;
+        let mut bytes = header.serialize();
+        bytes[58..64].fill(0xFF);
+
+        let restored = Header::deserialize(&bytes).unwrap();
+        assert_eq!(restored.value_log_threshold, 0);
+        assert_eq!(restored.comparator_id, 0);
+        assert_eq!(restored.checksum, ChecksumAlgorithm::None);
+        assert_eq!(restored.compression, CompressionAlgorithm::ZstdDictionary);
+
+        bytes[2..4].copy_from_slice(&CHECKSUM_VERSION.to_le_bytes());
+        let restored = Header::deserialize(&bytes).unwrap();
+        assert_eq!(restored.checksum, ChecksumAlgorithm::Crc32);
+    }
+
+    #[test]
+    fn headers_before_leaf_page_sizes_read_the_whole_page_size() {
+        let mut bytes = Header::new(1, LEAF_PAGE_SIZE_VERSION - 1, 4096, 0, 1).serialize();
+        bytes[4..12].copy_from_slice(&0x1_0000_1000u64.to_le_bytes());
+
+        let restored = Header::deserialize(&bytes).unwrap();
+        assert_eq!(restored.page_size, 0x1_0000_1000);
+        assert_eq!(restored.leaf_page_size, 0);
+        assert_eq!(restored.node_size(NodeType::LEAF), restored.page_size);
+    }
+
+    #[test]
+    fn headers_before_encodings_use_fixed_width_bincode() {
+        let mut bytes = Header::new(1, ENCODING_VERSION - 1, 4096, 0, 1).serialize();
+        bytes[10] = 9;
+        bytes[11] = 3;
+
+        let restored = Header::deserialize(&bytes).unwrap();
+        assert_eq!(restored.encoding, Encoding::Bincode);
+        assert_eq!(restored.value_codec_id, 0);
+
+        bytes[2..4].copy_from_slice(&ENCODING_VERSION.to_le_bytes());
+        assert!(matches!(
+            Header::deserialize(&bytes),
+            Err(HeaderError::CorruptedData(_))
+        ));
+    }
+
+    #[test]
+    fn comparator_must_match_unless_either_is_unspecified() {
+        let config = TreeConfig {
+            comparator_id: 7,
+            ..TreeConfig::default()
+        };
+        let header = Header::create(&config);
+
+        assert!(header.check_comparator(7).is_ok());
+        assert!(header.check_comparator(0).is_ok());
+        assert!(matches!(
+            header.check_comparator(8),
+            Err(HeaderError::ComparatorMismatch {
+                expected: 8,
+                got: 7
+            })
+        ));
+        assert!(
+            Header::create(&TreeConfig::default())
+                .check_comparator(8)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn header_rejects_unknown_delete_strategy() {
+        let mut bytes = vec![0u8; Header::SIZE];
+        bytes[0..2].copy_from_slice(&1u16.to_le_bytes());
+        bytes[39] = 9;
+
+        let result = Header::deserialize(&bytes);
+        assert!(matches!(result, Err(HeaderError::CorruptedData(_))));
+    }
+
+    #[test]
+    fn header_rejects_unknown_checksum_algorithm() {
+        let mut bytes = Header::new(1, VERSION, 4096, 0, 1).serialize();
+        bytes[60] = 9;
+
+        let result = Header::deserialize(&bytes);
+        assert!(matches!(result, Err(HeaderError::CorruptedData(_))));
+    }
+
+    #[test]
+    fn header_rejects_unknown_duplicate_policy() {
+        let mut bytes = vec![0u8; Header::SIZE];
+        bytes[0..2].copy_from_slice(&1u16.to_le_bytes());
+        bytes[56] = 9;
+
+        let result = Header::deserialize(&bytes);
+        assert!(matches!(result, Err(HeaderError::CorruptedData(_))));
+    }
+
+    #[test]
+    fn page_counts_must_cover_every_page_the_header_names() {
+        let mut header = Header::new(1, VERSION, 4096, 0, 0);
+        header.set_page_count(3);
+        header.stats_page_id = 2;
+        assert!(header.check_page_counts().is_ok());
+
+        header.root_page_id = 3;
+        assert!(matches!(
+            header.check_page_counts(),
+            Err(HeaderError::CorruptedData(_))
+        ));
+
+        header.root_page_id = 0;
+        header.page_count = 4;
+        assert!(matches!(
+            header.check_page_counts(),
+            Err(HeaderError::CorruptedData(_))
+        ));
     }
 }
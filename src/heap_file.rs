@@ -0,0 +1,432 @@
+//! Unordered records kept in slotted pages, each found again by the [`RecordId`] it was stored
+//! under, so a [`BTree`](crate::BTree) mapping keys to record ids can serve as an index over
+//! them.
+
+use crate::codec::{Encoding, SerializationError};
+use crate::error::BTreeError;
+use crate::page_manager::{PageManager, PageManagerError};
+use crate::slot::Slot;
+use crate::slotted_page::SlottedPage;
+use crate::storage::{MemoryStorage, Storage};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use log::debug;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"CLHF";
+const VERSION: u16 = 1;
+
+// magic(4) + version(2) + reserved(2) + page_size(8)
+const HEADER_SIZE: u64 = 16;
+
+// Bytes each record's slot number takes in its page
+const SLOT_NUMBER_SIZE: usize = 2;
+
+/// Where a record lives: its page and its slot number within it. A record keeps its id until it
+/// is deleted, however the records around it move, after which the id may be given to a new
+/// record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RecordId {
+    pub page_id: u64,
+    pub slot: u16,
+}
+
+#[derive(Debug)]
+pub enum HeapFileError {
+    Io(crate::io::Error),
+    PageManager(PageManagerError),
+    Page(BTreeError),
+    NotAHeapFile,
+    UnsupportedVersion(u16),
+    PageSizeMismatch { expected: u64, got: u64 },
+    RecordTooLarge { size: usize, max: usize },
+}
+
+impl core::fmt::Display for HeapFileError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            HeapFileError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            HeapFileError::PageManager(e) => {
+                write!(f, "PageManager error: {}", e)
+            }
+            HeapFileError::Page(e) => {
+                write!(f, "Page error: {}", e)
+            }
+            HeapFileError::NotAHeapFile => {
+                write!(f, "Storage does not hold a heap file")
+            }
+            HeapFileError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported heap file version {}", version)
+            }
+            HeapFileError::PageSizeMismatch { expected, got } => {
+                write!(f, "Heap file has pages of {} bytes, not {}", got, expected)
+            }
+            HeapFileError::RecordTooLarge { size, max } => {
+                write!(
+                    f,
+                    "Record of {} bytes is larger than the {} a page holds",
+                    size, max
+                )
+            }
+        }
+    }
+}
+
+impl From<crate::io::Error> for HeapFileError {
+    fn from(err: crate::io::Error) -> HeapFileError {
+        HeapFileError::Io(err)
+    }
+}
+
+impl From<PageManagerError> for HeapFileError {
+    fn from(err: PageManagerError) -> HeapFileError {
+        HeapFileError::PageManager(err)
+    }
+}
+
+impl From<BTreeError> for HeapFileError {
+    fn from(err: BTreeError) -> HeapFileError {
+        HeapFileError::Page(err)
+    }
+}
+
+impl From<SerializationError> for HeapFileError {
+    fn from(err: SerializationError) -> HeapFileError {
+        HeapFileError::Page(err.into())
+    }
+}
+
+/// Records of type `V` in pages of their own, in no particular order. Each page is a leaf
+/// [`SlottedPage`] keyed by slot number, so records keep their ids as pages are compacted.
+/// A record is replaced by deleting it and inserting the new one, which may give it a new id.
+pub struct HeapFile<V> {
+    page_manager: PageManager,
+    page_size: usize,
+    // Bytes each page could still take once compacted, indexed by page id, so inserts only
+    // read pages a record fits in
+    room: Vec<usize>,
+    // Bytes of the largest record a page holds
+    max_record: usize,
+    _phantom: PhantomData<V>,
+}
+
+impl<V> HeapFile<V>
+where
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Opens the heap file in `storage`, creating an empty one if the storage is empty.
+    /// Every page is read once to learn how much room it has left.
+    pub fn new<S: Storage + 'static>(storage: S, page_size: u64) -> Result<Self, HeapFileError> {
+        Self::from_page_manager(PageManager::new(storage, page_size, HEADER_SIZE)?)
+    }
+
+    pub fn in_memory(page_size: u64) -> Result<Self, HeapFileError> {
+        Self::new(MemoryStorage::new(), page_size)
+    }
+
+    /// Opens the heap file at `path`, creating it if needed. The file is locked like
+    /// [`PageManager::open`] locks it.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P, page_size: u64) -> Result<Self, HeapFileError> {
+        Self::from_page_manager(PageManager::open(path, page_size, HEADER_SIZE)?)
+    }
+
+    fn from_page_manager(mut page_manager: PageManager) -> Result<Self, HeapFileError> {
+        let page_size = page_manager.page_size;
+        let header = page_manager.read_header()?;
+        // The page manager zeroes the header of new storage
+        match header.iter().all(|&b| b == 0) {
+            false => Self::check_header(&header, page_size)?,
+            true => {
+                let mut header = [0u8; HEADER_SIZE as usize];
+                header[0..4].copy_from_slice(&MAGIC);
+                header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+                header[8..16].copy_from_slice(&page_size.to_le_bytes());
+                page_manager.write_header(&header)?;
+            }
+        }
+
+        let empty = SlottedPage::<u16, V>::builder(0, page_size as usize).build();
+        let mut heap = HeapFile {
+            page_manager,
+            page_size: page_size as usize,
+            room: Vec::new(),
+            max_record: empty.free_space() - Slot::SIZE - SLOT_NUMBER_SIZE,
+            _phantom: PhantomData,
+        };
+        for page_id in 0..heap.page_manager.page_count() {
+            let page = heap.read_page(page_id)?;
+            heap.room.push(Self::room_in(&page));
+        }
+        debug!(
+            "Opened heap file with {} pages of {} bytes",
+            heap.room.len(),
+            page_size
+        );
+        Ok(heap)
+    }
+
+    fn check_header(header: &[u8], page_size: u64) -> Result<(), HeapFileError> {
+        if header[0..4] != MAGIC {
+            return Err(HeapFileError::NotAHeapFile);
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(HeapFileError::UnsupportedVersion(version));
+        }
+        let stored = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if stored != page_size {
+            return Err(HeapFileError::PageSizeMismatch {
+                expected: page_size,
+                got: stored,
+            });
+        }
+        Ok(())
+    }
+
+    // Bytes the page could take once compacted: everything but its header, entries and slots
+    fn room_in(page: &SlottedPage<u16, V>) -> usize {
+        (page.total_free as usize).saturating_sub(page.len() * Slot::SIZE)
+    }
+
+    fn read_page(&mut self, page_id: u64) -> Result<SlottedPage<u16, V>, HeapFileError> {
+        let buffer = self.page_manager.read_page(page_id)?;
+        Ok(SlottedPage::from_buffer(buffer).map_err(BTreeError::from)?)
+    }
+
+    fn write_page(&mut self, page: &SlottedPage<u16, V>) -> Result<(), HeapFileError> {
+        self.page_manager
+            .write_page(page.page_id, &page.serialize().map_err(BTreeError::from)?)?;
+        self.room[page.page_id as usize] = Self::room_in(page);
+        Ok(())
+    }
+
+    /// Stores `record` in the first page with room for it, or a new page if none has,
+    /// returning the id it can be read back by.
+    pub fn insert(&mut self, record: &V) -> Result<RecordId, HeapFileError> {
+        let size = Encoding::Bincode.serialized_size(record)? as usize;
+        if size > self.max_record {
+            return Err(HeapFileError::RecordTooLarge {
+                size,
+                max: self.max_record,
+            });
+        }
+        let needed = SLOT_NUMBER_SIZE + size + Slot::SIZE;
+
+        for page_id in 0..self.room.len() as u64 {
+            if self.room[page_id as usize] < needed {
+                continue;
+            }
+            let mut page = self.read_page(page_id)?;
+            if !page.can_insert(SLOT_NUMBER_SIZE, size) {
+                page.compact()?;
+            }
+            if let Some(id) = Self::place(&mut page, record)? {
+                self.write_page(&page)?;
+                return Ok(id);
+            }
+            // The estimate was off, as the page's free list still took up room
+            self.room[page_id as usize] = 0;
+        }
+
+        let page_id = self.page_manager.allocate_page()?;
+        let mut page = SlottedPage::builder(page_id, self.page_size).build();
+        self.room.push(0);
+        let id = Self::place(&mut page, record)?.expect("an empty page holds any record");
+        self.write_page(&page)?;
+        Ok(id)
+    }
+
+    // Adds `record` to `page` under the next slot number, if it fits
+    fn place(page: &mut SlottedPage<u16, V>, record: &V) -> Result<Option<RecordId>, BTreeError> {
+        let slot = match page.len() {
+            0 => 0,
+            len => match page.read_key(len - 1)? {
+                u16::MAX => Self::first_unused_slot(page)?,
+                last => last + 1,
+            },
+        };
+        match page.insert_sorted(&slot, record) {
+            Ok(_) => Ok(Some(RecordId {
+                page_id: page.page_id,
+                slot,
+            })),
+            Err(BTreeError::PageOverflow { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Slot numbers only run out once the highest has been used, and a page holds far fewer
+    // records than there are numbers, so one below it is free
+    fn first_unused_slot(page: &SlottedPage<u16, V>) -> Result<u16, BTreeError> {
+        let keys = page.read_keys()?;
+        Ok((0..).zip(keys).find(|(i, key)| i != key).unwrap().0)
+    }
+
+    /// The record stored under `id`, or `None` if there is none.
+    pub fn get(&mut self, id: RecordId) -> Result<Option<V>, HeapFileError> {
+        if id.page_id >= self.room.len() as u64 {
+            return Ok(None);
+        }
+        Ok(self.read_page(id.page_id)?.get(&id.slot)?)
+    }
+
+    /// Removes the record stored under `id`, returning whether there was one.
+    pub fn delete(&mut self, id: RecordId) -> Result<bool, HeapFileError> {
+        if id.page_id >= self.room.len() as u64 {
+            return Ok(false);
+        }
+        let mut page = self.read_page(id.page_id)?;
+        let Some(pos) = page.find_exact_key(&id.slot)? else {
+            return Ok(false);
+        };
+        page.delete(pos)?;
+        self.write_page(&page)?;
+        Ok(true)
+    }
+
+    /// Every record with its id, a page at a time, in page and then slot order.
+    pub fn scan(&mut self) -> HeapScan<'_, V> {
+        HeapScan {
+            heap: self,
+            next_page: 0,
+            records: VecDeque::new(),
+        }
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.room.len() as u64
+    }
+
+    /// Makes every insert and delete so far durable.
+    pub fn flush(&mut self) -> Result<(), HeapFileError> {
+        Ok(self.page_manager.sync()?)
+    }
+}
+
+/// Iterator over the records of a heap file, returned by [`HeapFile::scan`].
+pub struct HeapScan<'a, V> {
+    heap: &'a mut HeapFile<V>,
+    next_page: u64,
+    records: VecDeque<(RecordId, V)>,
+}
+
+impl<V> HeapScan<'_, V>
+where
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn read_next_page(&mut self) -> Result<(), HeapFileError> {
+        let page = self.heap.read_page(self.next_page)?;
+        for entry in page.iter() {
+            let (slot, record) = entry?;
+            let id = RecordId {
+                page_id: self.next_page,
+                slot,
+            };
+            self.records.push_back((id, record));
+        }
+        self.next_page += 1;
+        Ok(())
+    }
+}
+
+impl<V> Iterator for HeapScan<'_, V>
+where
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<(RecordId, V), HeapFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.records.is_empty() && self.next_page < self.heap.page_count() {
+            if let Err(e) = self.read_next_page() {
+                // Nothing more is read after an error
+                self.next_page = self.heap.page_count();
+                return Some(Err(e));
+            }
+        }
+        self.records.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTree;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn records_keep_their_ids_as_others_come_and_go() {
+        let mut heap = HeapFile::<String>::in_memory(256).unwrap();
+        let ids: Vec<RecordId> = (0..40)
+            .map(|i| heap.insert(&format!("record-{}", i)).unwrap())
+            .collect();
+        assert!(heap.page_count() > 1);
+
+        for &id in ids.iter().step_by(2) {
+            assert!(heap.delete(id).unwrap());
+        }
+        assert!(!heap.delete(ids[0]).unwrap());
+        // Refills the holes left behind, compacting pages to make room
+        let pages = heap.page_count();
+        for i in 0..20 {
+            heap.insert(&format!("again-{:02}", i)).unwrap();
+        }
+        assert_eq!(heap.page_count(), pages);
+
+        assert_eq!(heap.get(ids[0]).unwrap(), None);
+        for (i, &id) in ids.iter().enumerate().skip(1).step_by(2) {
+            assert_eq!(heap.get(id).unwrap(), Some(format!("record-{}", i)));
+        }
+        assert_eq!(heap.scan().count(), 40);
+    }
+
+    #[test]
+    fn heap_files_reopen_with_their_records() {
+        let file = NamedTempFile::new().unwrap();
+        let mut heap = HeapFile::<u64>::new(file.reopen().unwrap(), 512).unwrap();
+        let ids: Vec<RecordId> = (0..300).map(|i| heap.insert(&i).unwrap()).collect();
+        heap.flush().unwrap();
+        drop(heap);
+
+        let mut heap = HeapFile::<u64>::new(file.reopen().unwrap(), 512).unwrap();
+        let scanned: Vec<(RecordId, u64)> = heap.scan().map(|r| r.unwrap()).collect();
+        assert_eq!(scanned, ids.into_iter().zip(0..300).collect::<Vec<_>>());
+
+        assert!(matches!(
+            HeapFile::<u64>::new(file.reopen().unwrap(), 256),
+            Err(HeapFileError::PageSizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn a_tree_indexes_records_by_key() {
+        let mut heap = HeapFile::<(String, u32)>::in_memory(512).unwrap();
+        let mut index = BTree::<String, RecordId>::temporary(512).unwrap();
+        for age in 0..100 {
+            let name = format!("person-{:03}", age);
+            let id = heap.insert(&(name.clone(), age)).unwrap();
+            index.insert(name, id).unwrap();
+        }
+
+        let id = index.search("person-042".to_string()).unwrap();
+        assert_eq!(heap.get(id).unwrap(), Some(("person-042".to_string(), 42)));
+    }
+
+    #[test]
+    fn oversized_records_are_refused() {
+        let mut heap = HeapFile::<Vec<u8>>::in_memory(256).unwrap();
+        assert!(matches!(
+            heap.insert(&vec![0; 300]),
+            Err(HeapFileError::RecordTooLarge { .. })
+        ));
+        let largest = heap.max_record - 8;
+        let id = heap.insert(&vec![1; largest]).unwrap();
+        assert_eq!(heap.get(id).unwrap().unwrap().len(), largest);
+    }
+}
@@ -0,0 +1,515 @@
+//! Read-only [`Storage`] fetching a file from a web server with HTTP range requests, so a tree
+//! written by [`Snapshot::export`](crate::snapshot::Snapshot::export) and published on a static
+//! file server can be opened with
+//! [`Snapshot::open_storage`](crate::snapshot::Snapshot::open_storage) and queried without
+//! downloading the whole of it.
+//!
+//! The file is fetched in blocks of [`HttpRangeOptions::block_size`] as reads reach them, and
+//! the blocks used most recently are kept in memory, so the pages near the root stay cached
+//! while a lookup only fetches the few blocks holding its path down the tree. Requests go over
+//! a single kept-alive connection. Only plain `http://` URLs are supported, as there is no TLS;
+//! a file served over HTTPS needs a local proxy in front of it. The file must not change while
+//! it is open, since blocks fetched before and after a change would be mixed.
+
+use crate::page_cache::PageCache;
+use crate::storage::Storage;
+use log::{debug, trace};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HttpRangeOptions {
+    /// Bytes asked for per request. Reads are rounded out to whole blocks, so larger blocks
+    /// make fewer requests for scans but fetch more than a lookup needs.
+    pub block_size: u64,
+    /// Blocks kept in memory, the least recently used going first; 0 fetches every read.
+    pub cache_blocks: usize,
+    /// How long to wait to connect, and for each read from or write to the connection.
+    pub timeout: Duration,
+}
+
+impl Default for HttpRangeOptions {
+    fn default() -> Self {
+        HttpRangeOptions {
+            block_size: 64 * 1024,
+            cache_blocks: 256,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+pub struct HttpRangeStorage {
+    // `host:port`, as connected to and sent in the `Host` header
+    authority: String,
+    path: String,
+    options: HttpRangeOptions,
+    size: u64,
+    // Blocks fetched so far, by their index in the file
+    blocks: Mutex<PageCache>,
+    // Kept open between requests unless the server closes it
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+    requests: AtomicU64,
+    bytes_fetched: AtomicU64,
+}
+
+// What a range request returned
+struct RangeResponse {
+    body: Vec<u8>,
+    // Length of the whole file
+    total: u64,
+    keep_alive: bool,
+}
+
+impl HttpRangeStorage {
+    /// Opens the file at `url`, fetching its first block to learn its length. Fails if the
+    /// server does not answer range requests with partial content.
+    pub fn open(url: &str, options: HttpRangeOptions) -> io::Result<Self> {
+        if options.block_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "block size must not be 0",
+            ));
+        }
+        let (authority, path) = parse_url(url)?;
+        let mut storage = HttpRangeStorage {
+            authority,
+            path,
+            options,
+            size: 0,
+            blocks: Mutex::new(PageCache::new(options.cache_blocks)),
+            connection: Mutex::new(None),
+            requests: AtomicU64::new(0),
+            bytes_fetched: AtomicU64::new(0),
+        };
+        let (first, size) = storage.fetch(0)?;
+        storage.size = size;
+        storage.blocks.get_mut().unwrap().insert(0, &first);
+        debug!("Opened {} over HTTP: {} bytes", url, size);
+        Ok(storage)
+    }
+
+    /// Range requests made so far.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the file downloaded so far.
+    pub fn bytes_fetched(&self) -> u64 {
+        self.bytes_fetched.load(Ordering::Relaxed)
+    }
+
+    // Fetches block `index`, returning its bytes and the length of the whole file
+    fn fetch(&self, index: u64) -> io::Result<(Vec<u8>, u64)> {
+        let mut connection = self.connection.lock().unwrap();
+        // The server may have closed a kept-alive connection since it was last used, so a
+        // request failing on one is tried once more on a fresh connection
+        let reused = connection.is_some();
+        let response = match self.request(&mut connection, index) {
+            Err(e) if reused => {
+                debug!("Reconnecting to {} after: {}", self.authority, e);
+                self.request(&mut connection, index)
+            }
+            response => response,
+        }?;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_fetched
+            .fetch_add(response.body.len() as u64, Ordering::Relaxed);
+        Ok((response.body, response.total))
+    }
+
+    fn request(
+        &self,
+        connection: &mut Option<BufReader<TcpStream>>,
+        index: u64,
+    ) -> io::Result<RangeResponse> {
+        let start = index * self.options.block_size;
+        let end = start + self.options.block_size - 1;
+        trace!("Fetching bytes {}-{} of {}", start, end, self.path);
+        // Unknown until the first block has been fetched on opening
+        let known_size = (self.size > 0).then_some(self.size);
+        let response = self.connected(connection).and_then(|stream| {
+            write!(
+                stream.get_mut(),
+                "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: keep-alive\r\n\r\n",
+                self.path, self.authority, start, end
+            )?;
+            read_response(stream, start..=end, known_size)
+        });
+        if !response.as_ref().is_ok_and(|response| response.keep_alive) {
+            *connection = None;
+        }
+        response
+    }
+
+    // The open connection, connecting first if there is none
+    fn connected<'a>(
+        &self,
+        connection: &'a mut Option<BufReader<TcpStream>>,
+    ) -> io::Result<&'a mut BufReader<TcpStream>> {
+        if connection.is_none() {
+            let address =
+                self.authority.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "host has no address")
+                })?;
+            let stream = TcpStream::connect_timeout(&address, self.options.timeout)?;
+            stream.set_read_timeout(Some(self.options.timeout))?;
+            stream.set_write_timeout(Some(self.options.timeout))?;
+            stream.set_nodelay(true)?;
+            *connection = Some(BufReader::new(stream));
+        }
+        Ok(connection.as_mut().unwrap())
+    }
+
+    // Copies the bytes of block `index` from `within` on into `buf`, returning how many
+    fn copy_from_block(&self, index: u64, within: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let copy = |block: &[u8], buf: &mut [u8]| {
+            let n = block.len().saturating_sub(within).min(buf.len());
+            buf[..n].copy_from_slice(&block[within..within + n]);
+            n
+        };
+        if let Some(block) = self.blocks.lock().unwrap().get(index) {
+            return Ok(copy(block, buf));
+        }
+        let (block, _) = self.fetch(index)?;
+        let n = copy(&block, buf);
+        self.blocks.lock().unwrap().insert(index, &block);
+        Ok(n)
+    }
+}
+
+// Splits an `http://` URL into the `host:port` to connect to and the path to request
+fn parse_url(url: &str) -> io::Result<(String, String)> {
+    let invalid =
+        |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", reason, url));
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(at) => (&rest[..at], &rest[at..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid("URL has no host"));
+    }
+    // IPv6 literals are bracketed, so a port follows the last colon only after the bracket
+    let has_port = host.rfind(':').is_some_and(|at| !host[at..].contains(']'));
+    let authority = match has_port {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+    Ok((authority, path.to_string()))
+}
+
+// Reads the response to a request for the bytes in `asked`, which must be partial content
+// beginning where they do and ending no later, of a file of `known_size` bytes if that is known.
+// The body is only read once its length is checked against all of that, so a bad response
+// cannot make it allocate more than the block asked for.
+fn read_response(
+    stream: &mut BufReader<TcpStream>,
+    asked: RangeInclusive<u64>,
+    known_size: Option<u64>,
+) -> io::Result<RangeResponse> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let mut line = String::new();
+    if stream.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("malformed status line {:?}", line.trim_end())))?;
+    let http_10 = line.starts_with("HTTP/1.0");
+
+    let mut content_length = None;
+    let mut content_range = None;
+    let mut keep_alive = !http_10;
+    loop {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid(format!("malformed header {:?}", header)));
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "content-range" => content_range = Some(value.to_string()),
+            "connection" => keep_alive = value.eq_ignore_ascii_case("keep-alive"),
+            "transfer-encoding" if !value.eq_ignore_ascii_case("identity") => {
+                return Err(invalid(format!("unsupported transfer encoding {}", value)));
+            }
+            _ => {}
+        }
+    }
+
+    if status != 206 {
+        let reason = match status {
+            200 => "the server does not support range requests".to_string(),
+            status => format!("the server answered with status {}", status),
+        };
+        return Err(io::Error::other(reason));
+    }
+    let length = content_length.ok_or_else(|| invalid("no content length".to_string()))?;
+    // `bytes <first>-<last>/<total>`
+    let (first, last, total) = content_range
+        .as_deref()
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split_once('/'))
+        .and_then(|(span, total)| {
+            let (first, last) = span.split_once('-')?;
+            let first = first.parse::<u64>().ok()?;
+            Some((first, last.parse::<u64>().ok()?, total.parse::<u64>().ok()?))
+        })
+        .ok_or_else(|| invalid(format!("malformed content range {:?}", content_range)))?;
+    if first != *asked.start() || last < first || last > *asked.end() || last >= total {
+        return Err(invalid(format!(
+            "asked for bytes {}-{}, got bytes {}-{} of {}",
+            asked.start(),
+            asked.end(),
+            first,
+            last,
+            total
+        )));
+    }
+    if length != last - first + 1 {
+        return Err(invalid(format!(
+            "content length {} does not match bytes {}-{}",
+            length, first, last
+        )));
+    }
+    if let Some(size) = known_size.filter(|&size| size != total) {
+        return Err(invalid(format!(
+            "the file is now {} bytes rather than {}",
+            total, size
+        )));
+    }
+
+    let mut body = vec![0; length as usize];
+    stream.read_exact(&mut body)?;
+    Ok(RangeResponse {
+        body,
+        total,
+        keep_alive,
+    })
+}
+
+impl Storage for HttpRangeStorage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let wanted = (buf.len() as u64).min(self.size.saturating_sub(offset)) as usize;
+        let mut read = 0;
+        while read < wanted {
+            let at = offset + read as u64;
+            let index = at / self.options.block_size;
+            let within = (at % self.options.block_size) as usize;
+            match self.copy_from_block(index, within, &mut buf[read..wanted])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "files read over HTTP are read-only",
+        ))
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn set_size(&self, _size: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "files read over HTTP are read-only",
+        ))
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTree;
+    use crate::config::TreeConfig;
+    use crate::snapshot::Snapshot;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    // Serves `file` with support for range requests on kept-alive connections, the way a
+    // static file server would, counting the bytes of the file sent
+    fn serve(file: Vec<u8>) -> (String, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/reference.db", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicU64::new(0));
+        let counter = served.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                loop {
+                    let mut range = None;
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                        if let Some(value) = line.strip_prefix("Range: bytes=") {
+                            let (first, last) = value.trim_end().split_once('-').unwrap();
+                            range = Some((first.parse::<usize>().unwrap(), last.parse().unwrap()));
+                        }
+                        line.clear();
+                    }
+                    let Some((first, last)) = range else {
+                        break;
+                    };
+                    let last = std::cmp::min(last, file.len() - 1);
+                    let body = &file[first..=last];
+                    counter.fetch_add(body.len() as u64, Ordering::Relaxed);
+                    let head = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        body.len(),
+                        first,
+                        last,
+                        file.len()
+                    );
+                    let stream = stream.get_mut();
+                    stream.write_all(head.as_bytes()).unwrap();
+                    stream.write_all(body).unwrap();
+                }
+            }
+        });
+        (url, served)
+    }
+
+    #[test]
+    fn exported_snapshot_is_queried_without_downloading_it_all() {
+        let mut btree = BTree::<u64, String>::in_memory(TreeConfig::with_page_size(1024)).unwrap();
+        btree
+            .bulk_load((0..20_000).map(|i| (i, format!("value-{}", i))))
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reference.db");
+        btree.freeze().unwrap().export(&path).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let (url, served) = serve(file.clone());
+
+        let options = HttpRangeOptions {
+            block_size: 4096,
+            ..HttpRangeOptions::default()
+        };
+        let storage = HttpRangeStorage::open(&url, options).unwrap();
+        assert_eq!(storage.size().unwrap(), file.len() as u64);
+        assert!(storage.write_at(&[0], 0).is_err());
+        let snapshot = Snapshot::<u64, String>::open_storage(storage).unwrap();
+        assert_eq!(snapshot.len(), 20_000);
+        assert_eq!(snapshot.get(&12_345).unwrap(), "value-12345");
+        let found: Vec<u64> = snapshot
+            .range(500..520)
+            .unwrap()
+            .map(|e| e.unwrap().0)
+            .collect();
+        assert_eq!(found, (500..520).collect::<Vec<_>>());
+        let fetched = served.load(Ordering::Relaxed);
+        assert!(fetched < file.len() as u64 / 10);
+
+        // Cached blocks are not fetched again
+        assert_eq!(snapshot.get(&12_345).unwrap(), "value-12345");
+        assert_eq!(served.load(Ordering::Relaxed), fetched);
+
+        assert!(HttpRangeStorage::open("https://example.com/x.db", options).is_err());
+    }
+
+    // Answers the requests made to it with `responses` in turn, whatever they ask for
+    fn serve_responses(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/reference.db", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut responses = responses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                while stream.read_line(&mut line).unwrap_or(0) > 0 {
+                    if line == "\r\n" {
+                        let Some(response) = responses.next() else {
+                            return;
+                        };
+                        let _ = stream.get_mut().write_all(response.as_bytes());
+                    }
+                    line.clear();
+                }
+            }
+        });
+        url
+    }
+
+    fn partial(length: u64, range: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}\r\n\r\n{}",
+            length, range, body
+        )
+    }
+
+    #[test]
+    fn responses_that_do_not_match_the_range_asked_for_are_rejected() {
+        let options = HttpRangeOptions {
+            block_size: 8,
+            ..HttpRangeOptions::default()
+        };
+        let open = |response| HttpRangeStorage::open(&serve_responses(vec![response]), options);
+        let rejected = [
+            // A length no block could have, which must not be allocated
+            partial(u64::MAX, "0-7/16", ""),
+            partial(4, "0-7/16", "abcd"),
+            partial(16, "0-15/16", "abcdefghijklmnop"),
+            partial(8, "1-8/16", "abcdefgh"),
+            partial(8, "0-7/4", "abcdefgh"),
+        ];
+        for response in rejected {
+            let error = open(response.clone()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", response);
+        }
+
+        // The last block of a file may be short
+        let storage = open(partial(5, "0-4/5", "abcde")).unwrap();
+        assert_eq!(storage.size().unwrap(), 5);
+
+        // A file whose length changes after it was opened is refused rather than mixed, also
+        // when asked again on a fresh connection
+        let changed = partial(8, "8-15/24", "ijklmnop");
+        let url = serve_responses(vec![
+            partial(8, "0-7/16", "abcdefgh"),
+            changed.clone(),
+            changed,
+        ]);
+        let storage = HttpRangeStorage::open(&url, options).unwrap();
+        let mut buf = [0; 8];
+        let error = storage.read_at(&mut buf, 8).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn urls_split_into_authority_and_path() {
+        let split = |url| parse_url(url).unwrap();
+        assert_eq!(
+            split("http://host/a/b.db"),
+            ("host:80".into(), "/a/b.db".into())
+        );
+        assert_eq!(split("http://host:8080"), ("host:8080".into(), "/".into()));
+        assert_eq!(split("http://[::1]/x"), ("[::1]:80".into(), "/x".into()));
+        assert_eq!(split("http://[::1]:81/x"), ("[::1]:81".into(), "/x".into()));
+        assert!(parse_url("ftp://host/x").is_err());
+        assert!(parse_url("http:///x").is_err());
+    }
+}
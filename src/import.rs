@@ -0,0 +1,52 @@
+//! Options and progress of [`BTree::import`](crate::BTree::import), which bulk loads in batches
+//! that are each made durable before the next, so an interrupted import picks up where it left
+//! off.
+
+use core::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    /// Entries loaded between flushes. Each flush makes the entries so far durable and reports
+    /// progress.
+    pub batch_size: u64,
+    /// Entries the source holds in all, including any already imported, if known, so progress
+    /// can estimate the time left.
+    pub total_entries: Option<u64>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            batch_size: 100_000,
+            total_entries: None,
+        }
+    }
+}
+
+/// How far an import has got, reported after each batch and returned once it finishes.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportProgress {
+    /// Entries loaded by this call.
+    pub entries_loaded: u64,
+    /// Entries at the start of the source that an earlier, interrupted import already loaded.
+    pub entries_skipped: u64,
+    /// Bytes of the keys and values loaded by this call, as serialized.
+    pub bytes_loaded: u64,
+    pub elapsed: Duration,
+    /// Time left at the rate so far, when `ImportOptions::total_entries` is given.
+    pub eta: Option<Duration>,
+}
+
+impl ImportProgress {
+    pub(crate) fn estimate_eta(&mut self, total_entries: Option<u64>) {
+        let left = total_entries
+            .map(|total| total.saturating_sub(self.entries_skipped + self.entries_loaded));
+        self.eta = match (left, self.entries_loaded) {
+            (Some(0), _) => Some(Duration::ZERO),
+            (Some(left), loaded) if loaded > 0 => {
+                Some(self.elapsed.mul_f64(left as f64 / loaded as f64))
+            }
+            _ => None,
+        };
+    }
+}
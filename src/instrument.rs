@@ -0,0 +1,149 @@
+//! Spans around tree operations, emitted through `tracing` when the `tracing` feature is on.
+//!
+//! Without the feature `op_span!` expands to an empty guard and its field expressions are never
+//! evaluated, so the instrumentation costs nothing.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// An entered span. Dropping it records how long it was open in its `duration_us` field.
+pub(crate) struct OpSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl OpSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(span: tracing::Span) -> Self {
+        OpSpan {
+            span: span.entered(),
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new() -> Self {
+        OpSpan {}
+    }
+}
+
+/// Fills in the `page_id` of the innermost open span once the operation knows which page it
+/// ended on.
+pub(crate) fn record_page(_page_id: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("page_id", _page_id);
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for OpSpan {
+    fn drop(&mut self) {
+        self.span
+            .record("duration_us", self.start.elapsed().as_micros() as u64);
+    }
+}
+
+/// Opens a debug-level span named `$name` with the given fields plus `page_id` and
+/// `duration_us`, and returns its [`OpSpan`] guard.
+#[cfg(feature = "tracing")]
+macro_rules! op_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        $crate::instrument::OpSpan::new(tracing::debug_span!(
+            $name,
+            $($field = $value,)*
+            page_id = tracing::field::Empty,
+            duration_us = tracing::field::Empty,
+        ))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! op_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        $crate::instrument::OpSpan::new()
+    };
+}
+
+pub(crate) use op_span;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    // Collects every span and each field recorded after it was created
+    #[derive(Default, Clone)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<&'static Metadata<'static>>>>,
+        entered: Arc<Mutex<Vec<Id>>>,
+        records: Arc<Mutex<Vec<(u64, String)>>>,
+    }
+
+    struct FieldNames(Vec<String>);
+
+    impl Visit for FieldNames {
+        fn record_debug(&mut self, field: &Field, _value: &dyn std::fmt::Debug) {
+            self.0.push(field.name().to_string());
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata());
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut names = FieldNames(Vec::new());
+            values.record(&mut names);
+            let mut records = self.records.lock().unwrap();
+            records.extend(names.0.into_iter().map(|name| (span.into_u64(), name)));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, span: &Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.entered.lock().unwrap().last() {
+                Some(id) => {
+                    let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1];
+                    Current::new(id.clone(), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn span_records_page_and_duration() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let _span = op_span!("search", key_size = 8);
+            super::record_page(3);
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name(), "search");
+        assert_eq!(
+            *recorder.records.lock().unwrap(),
+            vec![(1, "page_id".to_string()), (1, "duration_us".to_string())]
+        );
+    }
+}
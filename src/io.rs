@@ -0,0 +1,119 @@
+//! The I/O error that [`Storage`](crate::storage::Storage) backends return and the crate's
+//! error types carry. With `std` these are `std::io`'s own; without it, a stand-in holding the
+//! same kinds and an optional message, so a backend written for a bare-metal block device
+//! reports failures the same way a file does.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::alloc_only::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod alloc_only {
+    use alloc::boxed::Box;
+    use core::fmt;
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The kinds of `std::io::ErrorKind` that this crate and its backends tell apart.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        NotFound,
+        PermissionDenied,
+        AlreadyExists,
+        WouldBlock,
+        InvalidInput,
+        InvalidData,
+        TimedOut,
+        WriteZero,
+        Interrupted,
+        Unsupported,
+        UnexpectedEof,
+        StorageFull,
+        QuotaExceeded,
+        Other,
+    }
+
+    impl ErrorKind {
+        fn as_str(self) -> &'static str {
+            match self {
+                ErrorKind::NotFound => "entity not found",
+                ErrorKind::PermissionDenied => "permission denied",
+                ErrorKind::AlreadyExists => "entity already exists",
+                ErrorKind::WouldBlock => "operation would block",
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::TimedOut => "timed out",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::Unsupported => "unsupported",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::StorageFull => "no storage space",
+                ErrorKind::QuotaExceeded => "quota exceeded",
+                ErrorKind::Other => "other error",
+            }
+        }
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        error: Option<Box<dyn core::error::Error + Send + Sync>>,
+    }
+
+    impl Error {
+        pub fn new<E>(kind: ErrorKind, error: E) -> Error
+        where
+            E: Into<Box<dyn core::error::Error + Send + Sync>>,
+        {
+            Error {
+                kind,
+                error: Some(error.into()),
+            }
+        }
+
+        pub fn other<E>(error: E) -> Error
+        where
+            E: Into<Box<dyn core::error::Error + Send + Sync>>,
+        {
+            Error::new(ErrorKind::Other, error)
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+
+        pub fn get_ref(&self) -> Option<&(dyn core::error::Error + Send + Sync + 'static)> {
+            self.error.as_deref()
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error { kind, error: None }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match &self.error {
+                Some(error) => write!(f, "{}", error),
+                None => write!(f, "{}", self.kind),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {
+        fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+            self.error.as_deref().and_then(|error| error.source())
+        }
+    }
+}
@@ -1,15 +1,88 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod buffer_pool;
+#[cfg(feature = "std")]
+pub mod catalog;
+pub mod checksum;
+mod clock;
+pub mod codec;
+mod collections;
+pub mod compression;
+pub mod config;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod differential;
 pub mod error;
+pub mod events;
 pub mod free_space;
+pub mod hash_index;
 pub mod header;
+pub mod heap_file;
+#[cfg(feature = "std")]
+pub mod http_range;
+pub mod import;
+mod instrument;
+pub mod io;
+#[cfg(feature = "std")]
+pub mod maintenance;
+#[cfg(feature = "std")]
+pub mod manifest;
+pub mod memory;
+pub mod merkle;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod opfs;
 
+pub mod page_cache;
 pub mod page_manager;
+pub mod pagination;
+pub mod quarantine;
+pub mod queue;
+pub mod range_lock;
+pub mod raw;
+pub mod recovery;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "server")]
+pub mod resp;
+#[cfg(feature = "std")]
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod scrub;
+pub mod search;
+pub mod sequence;
 
+#[cfg(feature = "server")]
+pub mod server;
 pub mod slot;
 pub mod slotted_page;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod stats;
+pub mod storage;
+mod sync;
+pub mod tiering;
+pub mod time_series;
 
 pub mod types;
+pub mod value_log;
+#[cfg(feature = "std")]
+pub mod wal;
+pub mod watch;
 
 pub mod btree;
 pub mod constants;
 
 pub use btree::BTree;
+#[cfg(feature = "std")]
+pub use database::Database;
+
+/// On-disk format version this crate writes. Files written with earlier versions can still be
+/// opened; `tests/fixtures` holds one for each.
+pub fn format_version() -> u16 {
+    constants::VERSION
+}
@@ -1,10 +1,171 @@
 use cloaksdb::BTree;
+use cloaksdb::bench::{self, BenchOptions};
+use cloaksdb::config::TreeConfig;
+use cloaksdb::scrub::ScrubOptions;
 use rand::Rng;
 
+const BENCH_USAGE: &str = "Usage: cloaksdb bench [--benchmarks LIST] [--num N] [--reads N] [--value-size BYTES] [--page-size BYTES] [--cache-size PAGES] [--threads N] [--seed N] [--db FILE]
+  LIST is a comma-separated list of fillseq, fillrandom, overwrite, readrandom, readseq and
+  readwhilewriting, whose N reader threads share the tree with one writer";
+
+const SCRUB_USAGE: &str = "Usage: cloaksdb scrub FILE [--page-size BYTES] [--pages-per-second N]
+  Checks every page of FILE for damage without changing it; exits with 1 if any is found";
+
+fn parse_bench_args(args: impl Iterator<Item = String>) -> Result<BenchOptions, String> {
+    let mut options = BenchOptions::default();
+
+    let mut iter = args;
+    while let Some(flag) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("Missing value for {}", flag));
+        fn number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .parse()
+                .map_err(|e| format!("Invalid {}: {}", flag, e))
+        }
+
+        match flag.as_str() {
+            "--benchmarks" => {
+                options.workloads = value()?
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?
+            }
+            "--num" => options.num = number(&flag, value()?)?,
+            "--reads" => options.reads = Some(number(&flag, value()?)?),
+            "--value-size" => options.value_size = number(&flag, value()?)?,
+            "--page-size" => options.page_size = number(&flag, value()?)?,
+            "--cache-size" => options.cache_size = number(&flag, value()?)?,
+            "--threads" => options.threads = number(&flag, value()?)?,
+            "--seed" => options.seed = number(&flag, value()?)?,
+            "--db" => options.path = Some(value()?.into()),
+            "--help" | "-h" => return Err(BENCH_USAGE.to_string()),
+            other => return Err(format!("Unknown argument: {}\n{}", other, BENCH_USAGE)),
+        }
+    }
+    Ok(options)
+}
+
+fn run_bench(args: impl Iterator<Item = String>) {
+    let options = parse_bench_args(args).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(2);
+    });
+
+    println!(
+        "Keys: 16 bytes, values: {} bytes, entries: {}, page size: {}, cache: {} pages",
+        options.value_size, options.num, options.page_size, options.cache_size
+    );
+    match bench::run(options) {
+        Ok(results) => results.iter().for_each(|result| println!("{}", result)),
+        Err(e) => {
+            eprintln!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_scrub_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(String, u64, ScrubOptions), String> {
+    let mut path = None;
+    let mut page_size = TreeConfig::default().page_size;
+    // Keys and values are not decoded, as their types are not known here
+    let mut options = ScrubOptions {
+        decode_entries: false,
+        ..ScrubOptions::default()
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--page-size" => {
+                page_size = value()?
+                    .parse()
+                    .map_err(|e| format!("Invalid --page-size: {}", e))?
+            }
+            "--pages-per-second" => {
+                options.pages_per_second = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("Invalid --pages-per-second: {}", e))?,
+                )
+            }
+            "--help" | "-h" => return Err(SCRUB_USAGE.to_string()),
+            other if other.starts_with('-') || path.is_some() => {
+                return Err(format!("Unknown argument: {}\n{}", other, SCRUB_USAGE));
+            }
+            other => path = Some(other.to_string()),
+        }
+    }
+    let path = path.ok_or_else(|| SCRUB_USAGE.to_string())?;
+    Ok((path, page_size, options))
+}
+
+fn run_scrub(args: impl Iterator<Item = String>) {
+    let (path, page_size, options) = parse_scrub_args(args).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(2);
+    });
+
+    let opened = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            BTree::<String, String>::from_file(file, TreeConfig::with_page_size(page_size))
+                .map_err(|e| e.to_string())
+        });
+    let mut btree = opened.unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {}", path, e);
+        std::process::exit(2);
+    });
+
+    match btree.scrub(options) {
+        Ok(report) => {
+            for damaged in &report.damaged {
+                println!("page {}: {}", damaged.page_id, damaged.reason);
+            }
+            println!(
+                "Scanned {} pages: {} checksummed, {} without checksums, {} damaged",
+                report.pages_scanned,
+                report.pages_checksummed,
+                report.pages_without_checksum,
+                report.damaged.len()
+            );
+            if !report.is_clean() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Scrub failed: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
 
-    let index_dir = format!("out/database/index");
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        match command.as_str() {
+            "bench" => return run_bench(args),
+            "scrub" => return run_scrub(args),
+            other => {
+                eprintln!(
+                    "Unknown command: {}\n{}\n{}",
+                    other, BENCH_USAGE, SCRUB_USAGE
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let index_dir = "out/database/index".to_string();
     std::fs::create_dir_all(&index_dir).expect("Failed to create_dir");
 
     let index_filename = format!("{}/index_0", index_dir);
@@ -0,0 +1,416 @@
+use crate::btree::BTree;
+use crate::value_log::ValueLogCompactionOptions;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+
+/// When the background maintenance thread flushes and compacts a tree and its value log.
+///
+/// Each interval is stretched by a random amount of up to `jitter`, so several trees started
+/// together do not all hit the disk at the same moment.
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenancePolicy {
+    /// Flush at least this often while there are unflushed writes.
+    pub flush_interval: Option<Duration>,
+    /// Flush as soon as this many writes have accumulated, regardless of the interval.
+    pub flush_after_writes: Option<u64>,
+    /// Compact fragmented pages this often.
+    pub compaction_interval: Option<Duration>,
+    /// Compact the value log this often, as `BTree::compact_value_log` with
+    /// `value_log_compaction`. Each run reads every page of the tree.
+    pub value_log_compaction_interval: Option<Duration>,
+    pub value_log_compaction: ValueLogCompactionOptions,
+    pub jitter: Duration,
+    /// How often the thread wakes up to check whether anything is due.
+    pub check_interval: Duration,
+}
+
+impl Default for MaintenancePolicy {
+    fn default() -> Self {
+        MaintenancePolicy {
+            flush_interval: Some(Duration::from_secs(1)),
+            flush_after_writes: Some(1000),
+            compaction_interval: Some(Duration::from_secs(60)),
+            value_log_compaction_interval: Some(Duration::from_secs(300)),
+            value_log_compaction: ValueLogCompactionOptions::default(),
+            jitter: Duration::from_millis(100),
+            check_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MaintenanceStats {
+    pub flushes: u64,
+    pub compactions: u64,
+    pub pages_compacted: u64,
+    pub value_log_compactions: u64,
+    /// Bytes of value log segments removed.
+    pub value_log_bytes_reclaimed: u64,
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct State {
+    pauses: usize,
+    stopped: bool,
+    stats: MaintenanceStats,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+/// Runs flushes and compactions for a shared tree on a background thread, so callers do not
+/// have to schedule them themselves. The thread stops when the scheduler is dropped.
+pub struct MaintenanceScheduler {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceScheduler {
+    pub fn start<K, V>(tree: Arc<Mutex<BTree<K, V>>>, policy: MaintenancePolicy) -> Self
+    where
+        K: Clone
+            + PartialOrd
+            + Debug
+            + Serialize
+            + for<'de> Deserialize<'de>
+            + ToString
+            + Send
+            + 'static,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let worker_shared = shared.clone();
+        let worker = std::thread::spawn(move || run(tree, policy, worker_shared));
+
+        MaintenanceScheduler {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// Suspends maintenance until a matching `resume`. Pauses nest, and work already running
+    /// finishes first.
+    pub fn pause(&self) {
+        self.lock_state().pauses += 1;
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.lock_state();
+        state.pauses = state.pauses.saturating_sub(1);
+        self.shared.wake.notify_all();
+    }
+
+    /// Pauses maintenance until the returned guard is dropped, e.g. around a latency-critical
+    /// batch of operations.
+    pub fn pause_guard(&self) -> MaintenancePause<'_> {
+        self.pause();
+        MaintenancePause { scheduler: self }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock_state().pauses > 0
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        self.lock_state().stats
+    }
+
+    /// Stops the background thread and waits for it to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.lock_state().stopped = true;
+        self.shared.wake.notify_all();
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            error!("Maintenance thread panicked");
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for MaintenanceScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+pub struct MaintenancePause<'a> {
+    scheduler: &'a MaintenanceScheduler,
+}
+
+impl Drop for MaintenancePause<'_> {
+    fn drop(&mut self) {
+        self.scheduler.resume();
+    }
+}
+
+fn jittered(interval: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return interval;
+    }
+    let extra = rand::rng().random_range(0..=jitter.as_nanos() as u64);
+    interval + Duration::from_nanos(extra)
+}
+
+fn run<K, V>(tree: Arc<Mutex<BTree<K, V>>>, policy: MaintenancePolicy, shared: Arc<Shared>)
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    let schedule = |interval: Option<Duration>| {
+        interval.map(|interval| Instant::now() + jittered(interval, policy.jitter))
+    };
+    let mut next_flush = schedule(policy.flush_interval);
+    let mut next_compaction = schedule(policy.compaction_interval);
+    let mut next_value_log_compaction = schedule(policy.value_log_compaction_interval);
+
+    loop {
+        let state = shared.state.lock().unwrap();
+        let (state, _) = shared
+            .wake
+            .wait_timeout(state, policy.check_interval)
+            .unwrap();
+        if state.stopped {
+            break;
+        }
+        if state.pauses > 0 {
+            continue;
+        }
+        drop(state);
+
+        let Ok(mut tree) = tree.lock() else {
+            error!("Tree mutex poisoned, stopping maintenance");
+            break;
+        };
+        let now = Instant::now();
+        let mut stats = MaintenanceStats::default();
+
+        let pending = tree.writes_since_flush();
+        let interval_due = next_flush.is_some_and(|at| now >= at);
+        let threshold_due = policy.flush_after_writes.is_some_and(|n| pending >= n);
+        if pending > 0 && (interval_due || threshold_due) {
+            debug!("Maintenance flush: pending_writes={}", pending);
+            match tree.flush() {
+                Ok(()) => stats.flushes += 1,
+                Err(e) => {
+                    error!("Maintenance flush failed: {}", e);
+                    stats.errors += 1;
+                }
+            }
+        }
+        if interval_due || threshold_due {
+            next_flush = schedule(policy.flush_interval);
+        }
+
+        if next_compaction.is_some_and(|at| now >= at) {
+            match tree.compact_fragmented_pages() {
+                Ok(pages) => {
+                    debug!("Maintenance compaction: pages={}", pages);
+                    stats.compactions += 1;
+                    stats.pages_compacted += pages as u64;
+                }
+                Err(e) => {
+                    error!("Maintenance compaction failed: {}", e);
+                    stats.errors += 1;
+                }
+            }
+            next_compaction = schedule(policy.compaction_interval);
+        }
+
+        if next_value_log_compaction.is_some_and(|at| now >= at) {
+            match tree.compact_value_log(policy.value_log_compaction) {
+                Ok(gc) => {
+                    debug!(
+                        "Maintenance value log compaction: rewritten={:?} removed={:?}",
+                        gc.segments_rewritten, gc.segments_removed
+                    );
+                    stats.value_log_compactions += 1;
+                    stats.value_log_bytes_reclaimed += gc.bytes_reclaimed;
+                }
+                Err(e) => {
+                    error!("Maintenance value log compaction failed: {}", e);
+                    stats.errors += 1;
+                }
+            }
+            next_value_log_compaction = schedule(policy.value_log_compaction_interval);
+        }
+        drop(tree);
+
+        let mut state = shared.state.lock().unwrap();
+        state.stats.flushes += stats.flushes;
+        state.stats.compactions += stats.compactions;
+        state.stats.pages_compacted += stats.pages_compacted;
+        state.stats.value_log_compactions += stats.value_log_compactions;
+        state.stats.value_log_bytes_reclaimed += stats.value_log_bytes_reclaimed;
+        state.stats.errors += stats.errors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TreeConfig;
+    use crate::value_log::ValueLog;
+    use tempfile::NamedTempFile;
+
+    fn shared_tree() -> (Arc<Mutex<BTree<i64, i64>>>, NamedTempFile) {
+        let file = NamedTempFile::new().unwrap();
+        let btree = BTree::new(file.reopen().unwrap(), 4096).unwrap();
+        (Arc::new(Mutex::new(btree)), file)
+    }
+
+    fn fast_policy() -> MaintenancePolicy {
+        MaintenancePolicy {
+            flush_interval: Some(Duration::from_millis(20)),
+            flush_after_writes: None,
+            compaction_interval: None,
+            value_log_compaction_interval: None,
+            value_log_compaction: ValueLogCompactionOptions::default(),
+            jitter: Duration::from_millis(5),
+            check_interval: Duration::from_millis(5),
+        }
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test_log::test]
+    fn flushes_pending_writes_on_interval() {
+        let (tree, _file) = shared_tree();
+        let scheduler = MaintenanceScheduler::start(tree.clone(), fast_policy());
+
+        tree.lock().unwrap().insert(1, 1).unwrap();
+
+        assert!(wait_for(|| scheduler.stats().flushes >= 1));
+        assert_eq!(tree.lock().unwrap().writes_since_flush(), 0);
+    }
+
+    #[test_log::test]
+    fn idle_tree_is_not_flushed() {
+        let (tree, _file) = shared_tree();
+        let scheduler = MaintenanceScheduler::start(tree, fast_policy());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(scheduler.stats().flushes, 0);
+    }
+
+    #[test_log::test]
+    fn write_threshold_triggers_flush() {
+        let (tree, _file) = shared_tree();
+        let policy = MaintenancePolicy {
+            flush_interval: None,
+            flush_after_writes: Some(10),
+            ..fast_policy()
+        };
+        let scheduler = MaintenanceScheduler::start(tree.clone(), policy);
+
+        for i in 0..5 {
+            tree.lock().unwrap().insert(i, i).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(scheduler.stats().flushes, 0);
+
+        for i in 5..10 {
+            tree.lock().unwrap().insert(i, i).unwrap();
+        }
+        assert!(wait_for(|| scheduler.stats().flushes == 1));
+    }
+
+    #[test_log::test]
+    fn paused_scheduler_does_no_work() {
+        let (tree, _file) = shared_tree();
+        let scheduler = MaintenanceScheduler::start(tree.clone(), fast_policy());
+
+        {
+            let _pause = scheduler.pause_guard();
+            assert!(scheduler.is_paused());
+            tree.lock().unwrap().insert(1, 1).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            assert_eq!(scheduler.stats().flushes, 0);
+        }
+
+        assert!(!scheduler.is_paused());
+        assert!(wait_for(|| scheduler.stats().flushes >= 1));
+    }
+
+    #[test_log::test]
+    fn compaction_runs_on_interval() {
+        let (tree, _file) = shared_tree();
+        let policy = MaintenancePolicy {
+            compaction_interval: Some(Duration::from_millis(10)),
+            ..fast_policy()
+        };
+        let scheduler = MaintenanceScheduler::start(tree, policy);
+
+        assert!(wait_for(|| scheduler.stats().compactions >= 1));
+        scheduler.stop();
+    }
+
+    #[test_log::test]
+    fn value_log_compaction_reclaims_dead_segments() {
+        let config = TreeConfig {
+            value_log_threshold: 64,
+            ..TreeConfig::with_page_size(512)
+        };
+        let mut btree = BTree::<i64, String>::in_memory(config).unwrap();
+        btree.set_value_log(ValueLog::in_memory().with_segment_size(4096));
+        for i in 0..40 {
+            btree.insert(i, "x".repeat(1000)).unwrap();
+        }
+        for i in 0..40 {
+            btree.delete(i).unwrap();
+        }
+        let tree = Arc::new(Mutex::new(btree));
+
+        let policy = MaintenancePolicy {
+            value_log_compaction_interval: Some(Duration::from_millis(10)),
+            ..fast_policy()
+        };
+        let scheduler = MaintenanceScheduler::start(tree.clone(), policy);
+
+        assert!(wait_for(|| scheduler.stats().value_log_compactions >= 1));
+        assert!(scheduler.stats().value_log_bytes_reclaimed > 0);
+        let btree = tree.lock().unwrap();
+        assert_eq!(btree.value_log().unwrap().segments().len(), 1);
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let interval = Duration::from_millis(100);
+        let jitter = Duration::from_millis(10);
+
+        for _ in 0..100 {
+            let delay = jittered(interval, jitter);
+            assert!(delay >= interval && delay <= interval + jitter);
+        }
+        assert_eq!(jittered(interval, Duration::ZERO), interval);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::checksum::crc32;
+use crate::constants::VERSION;
+use crate::header::Header;
+use crate::storage;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Small file written next to a database opened by path, describing it so tools and recovery
+/// can find out what makes up the database without inferring it from file sizes.
+///
+/// It is rewritten atomically (to a temporary file that is then renamed) on every flush, and
+/// carries a CRC-32 of its contents so a torn or corrupted manifest is detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Name of the data file, relative to the directory holding the manifest.
+    pub data_file: String,
+    /// On-disk format version of the data file.
+    pub format_version: u16,
+    pub page_size: u64,
+    pub page_count: u64,
+    pub root_page_id: u64,
+    /// Number of the flush that wrote this manifest, counting up from 1 over the database's
+    /// life.
+    pub checkpoint: u64,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(std::io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    ChecksumMismatch { expected: u32, got: u32 },
+}
+
+impl std::fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ManifestError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            ManifestError::InvalidMagic => {
+                write!(f, "Not a manifest file")
+            }
+            ManifestError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported manifest version: {}", version)
+            }
+            ManifestError::Truncated => {
+                write!(f, "Manifest is truncated")
+            }
+            ManifestError::ChecksumMismatch { expected, got } => {
+                write!(
+                    f,
+                    "Manifest checksum mismatch: expected {:#010x}, got {:#010x}",
+                    expected, got
+                )
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ManifestError {
+    fn from(err: std::io::Error) -> ManifestError {
+        ManifestError::Io(err)
+    }
+}
+
+impl Manifest {
+    const MAGIC: [u8; 4] = *b"CLMF";
+    pub const VERSION: u16 = 1;
+    // magic, version, format version, four u64 fields and the data file name length
+    const FIXED_SIZE: usize = 4 + 2 + 2 + 4 * 8 + 2;
+
+    /// Describes the database in `data_path` as of `header`.
+    pub fn new(data_path: &Path, header: &Header, checkpoint: u64) -> Self {
+        Manifest {
+            data_file: data_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            format_version: VERSION,
+            page_size: header.page_size,
+            page_count: header.page_count,
+            root_page_id: header.root_page_id,
+            checkpoint,
+        }
+    }
+
+    /// Where the manifest of the database in `data_path` lives: alongside it, with
+    /// `.manifest` appended to its name.
+    pub fn path_for(data_path: &Path) -> PathBuf {
+        let mut name = data_path.as_os_str().to_owned();
+        name.push(".manifest");
+        PathBuf::from(name)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(Self::FIXED_SIZE + self.data_file.len() + 4);
+        buffer.extend_from_slice(&Self::MAGIC);
+        buffer.extend_from_slice(&Self::VERSION.to_le_bytes());
+        buffer.extend_from_slice(&self.format_version.to_le_bytes());
+        buffer.extend_from_slice(&self.page_size.to_le_bytes());
+        buffer.extend_from_slice(&self.page_count.to_le_bytes());
+        buffer.extend_from_slice(&self.root_page_id.to_le_bytes());
+        buffer.extend_from_slice(&self.checkpoint.to_le_bytes());
+        buffer.extend_from_slice(&(self.data_file.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(self.data_file.as_bytes());
+
+        let checksum = crc32(&buffer);
+        buffer.extend_from_slice(&checksum.to_le_bytes());
+        buffer
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, ManifestError> {
+        if buffer.len() < 4 || buffer[0..4] != Self::MAGIC {
+            return Err(ManifestError::InvalidMagic);
+        }
+        if buffer.len() < Self::FIXED_SIZE + 4 {
+            return Err(ManifestError::Truncated);
+        }
+
+        let version = u16::from_le_bytes(buffer[4..6].try_into().unwrap());
+        if version != Self::VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        let name_length =
+            u16::from_le_bytes(buffer[40..Self::FIXED_SIZE].try_into().unwrap()) as usize;
+        let body_length = Self::FIXED_SIZE + name_length;
+        if buffer.len() < body_length + 4 {
+            return Err(ManifestError::Truncated);
+        }
+
+        let expected = u32::from_le_bytes(buffer[body_length..body_length + 4].try_into().unwrap());
+        let got = crc32(&buffer[..body_length]);
+        if expected != got {
+            return Err(ManifestError::ChecksumMismatch { expected, got });
+        }
+
+        let read_u64 = |at: usize| u64::from_le_bytes(buffer[at..at + 8].try_into().unwrap());
+        Ok(Manifest {
+            data_file: String::from_utf8_lossy(&buffer[Self::FIXED_SIZE..body_length]).into_owned(),
+            format_version: u16::from_le_bytes(buffer[6..8].try_into().unwrap()),
+            page_size: read_u64(8),
+            page_count: read_u64(16),
+            root_page_id: read_u64(24),
+            checkpoint: read_u64(32),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self, ManifestError> {
+        Self::deserialize(&fs::read(path)?)
+    }
+
+    /// Replaces the manifest at `path` so that readers see either the old or the new one in
+    /// full, and the new one after a crash once this returns.
+    pub fn write(&self, path: &Path) -> Result<(), ManifestError> {
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&self.serialize())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // Makes the rename itself durable
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            storage::sync_dir(dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> Manifest {
+        Manifest {
+            data_file: "test.db".to_string(),
+            format_version: VERSION,
+            page_size: 4096,
+            page_count: 12,
+            root_page_id: 3,
+            checkpoint: 7,
+        }
+    }
+
+    #[test]
+    fn manifest_roundtrip() {
+        let manifest = manifest();
+        assert_eq!(
+            Manifest::deserialize(&manifest.serialize()).unwrap(),
+            manifest
+        );
+    }
+
+    #[test]
+    fn corrupted_manifest_is_rejected() {
+        let mut bytes = manifest().serialize();
+        bytes[20] ^= 0xFF;
+
+        assert!(matches!(
+            Manifest::deserialize(&bytes),
+            Err(ManifestError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn truncated_manifest_is_rejected() {
+        let bytes = manifest().serialize();
+
+        assert!(matches!(
+            Manifest::deserialize(&bytes[..bytes.len() - 1]),
+            Err(ManifestError::Truncated)
+        ));
+        assert!(matches!(
+            Manifest::deserialize(b"nope"),
+            Err(ManifestError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn write_replaces_existing_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Manifest::path_for(&dir.path().join("test.db"));
+        assert!(path.ends_with("test.db.manifest"));
+
+        manifest().write(&path).unwrap();
+        let newer = Manifest {
+            checkpoint: 8,
+            ..manifest()
+        };
+        newer.write(&path).unwrap();
+
+        assert_eq!(Manifest::read(&path).unwrap(), newer);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
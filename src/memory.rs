@@ -0,0 +1,23 @@
+/// Approximate heap memory held by an open tree, in bytes.
+///
+/// Figures count the bytes of buffered pages and the entries of the bookkeeping maps; allocator
+/// overhead and spare capacity are not included, so treat them as lower bounds.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    /// Pages held by the page cache, and written pages held back in write-back mode.
+    pub page_cache: usize,
+    /// Buffers kept for reuse by the tree's buffer pool. A pool shared between trees is
+    /// counted in full by each of them. Open iterators borrow the tree, so the pages they hold
+    /// are not counted here; use `Range::memory_usage` while one is open.
+    pub buffer_pool: usize,
+    /// The compression dictionary, including the prepared encoder and decoder copies.
+    pub compression: usize,
+    /// Per-page read counts kept for tiering, and other bookkeeping.
+    pub auxiliary: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.page_cache + self.buffer_pool + self.compression + self.auxiliary
+    }
+}
@@ -0,0 +1,127 @@
+//! Hashes of the entries beneath each page, combined up to a root hash that changes with any
+//! change to the tree's contents, so two copies of a tree can be compared without reading them
+//! in full.
+
+use crate::codec::{Encoding, SerializationError};
+use crate::collections::HashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a subtree: its page's entries and the hashes of its children, in key order.
+pub type Hash = [u8; 32];
+
+/// Builds the hash of one page from its entries and its children's hashes. Entries are hashed
+/// as serialized keys and values rather than as stored bytes, so the hash does not depend on
+/// how the page is laid out, compressed or which values live in a value log.
+pub(crate) struct PageHasher {
+    hasher: Sha256,
+}
+
+impl PageHasher {
+    pub(crate) fn new(is_leaf: bool) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update([u8::from(is_leaf)]);
+        PageHasher { hasher }
+    }
+
+    pub(crate) fn child(&mut self, hash: &Hash) {
+        self.hasher.update(hash);
+    }
+
+    pub(crate) fn entry<K: Serialize, V: Serialize>(
+        &mut self,
+        key: &K,
+        value: &V,
+    ) -> Result<(), SerializationError> {
+        let encoding = Encoding::Bincode;
+        for bytes in [encoding.serialize(key)?, encoding.serialize(value)?] {
+            self.hasher.update((bytes.len() as u64).to_le_bytes());
+            self.hasher.update(&bytes);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Hash {
+        self.hasher.finalize().into()
+    }
+}
+
+/// Subtree hashes from when they were last computed. A page written since then loses its hash
+/// along with those of its ancestors, and only those are computed again.
+#[derive(Default)]
+pub(crate) struct MerkleCache {
+    hashes: HashMap<u64, Hash>,
+    // Parent of each page when its hash was computed. A page moved under another parent is
+    // only moved by writing both parents, which clears the old path before the new one is
+    // recorded.
+    parents: HashMap<u64, u64>,
+}
+
+impl MerkleCache {
+    pub(crate) fn get(&self, page_id: u64) -> Option<Hash> {
+        self.hashes.get(&page_id).copied()
+    }
+
+    pub(crate) fn insert(&mut self, page_id: u64, hash: Hash, children: &[u64]) {
+        self.hashes.insert(page_id, hash);
+        for &child in children {
+            self.parents.insert(child, page_id);
+        }
+    }
+
+    /// Drops the hashes of `page_id` and its ancestors. The ancestors of a page without a hash
+    /// have none either, so the walk stops at the first one already dropped.
+    pub(crate) fn invalidate(&mut self, page_id: u64) {
+        let mut page_id = Some(page_id);
+        while let Some(id) = page_id {
+            if self.hashes.remove(&id).is_none() {
+                return;
+            }
+            page_id = self.parents.get(&id).copied();
+        }
+    }
+
+    /// Forgets every hash, for when page ids start over.
+    pub(crate) fn clear(&mut self) {
+        self.hashes.clear();
+        self.parents.clear();
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.hashes.len() * size_of::<(u64, Hash)>() + self.parents.len() * size_of::<(u64, u64)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidating_a_page_drops_its_ancestors() {
+        let mut cache = MerkleCache::default();
+        cache.insert(3, [3; 32], &[]);
+        cache.insert(4, [4; 32], &[]);
+        cache.insert(1, [1; 32], &[3, 4]);
+        cache.insert(0, [0; 32], &[1]);
+
+        cache.invalidate(3);
+        assert_eq!(cache.get(3), None);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.get(4), Some([4; 32]));
+    }
+
+    #[test]
+    fn page_hashes_depend_on_entry_boundaries() {
+        let hash = |entries: &[(&str, &str)]| {
+            let mut hasher = PageHasher::new(true);
+            for (key, value) in entries {
+                hasher.entry(key, value).unwrap();
+            }
+            hasher.finish()
+        };
+        assert_eq!(hash(&[("a", "bc")]), hash(&[("a", "bc")]));
+        assert_ne!(hash(&[("a", "bc")]), hash(&[("ab", "c")]));
+        assert_ne!(hash(&[]), hash(&[("", "")]));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::io;
+use crate::storage::Storage;
+use alloc::format;
+use web_sys::wasm_bindgen::JsValue;
+use web_sys::{FileSystemReadWriteOptions, FileSystemSyncAccessHandle};
+
+/// Stores pages in a file of the browser's Origin Private File System.
+///
+/// Sync access handles are only available inside a dedicated worker, and creating one is
+/// asynchronous, so the caller opens the handle (`FileSystemFileHandle.createSyncAccessHandle()`)
+/// and passes it in. The handle holds an exclusive lock on the file until it is dropped.
+pub struct OpfsStorage {
+    handle: FileSystemSyncAccessHandle,
+}
+
+// wasm32-unknown-unknown runs the tree on a single thread, so the JS handle is never actually
+// sent anywhere; `Storage` requires `Send` so native trees can move between threads. Builds
+// with threads (the `atomics` target feature) could send it, so there it is not `Send`.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for OpfsStorage {}
+
+impl OpfsStorage {
+    pub fn new(handle: FileSystemSyncAccessHandle) -> Self {
+        OpfsStorage { handle }
+    }
+}
+
+impl Drop for OpfsStorage {
+    fn drop(&mut self) {
+        self.handle.close();
+    }
+}
+
+fn to_io_error(err: JsValue) -> io::Error {
+    io::Error::other(format!("OPFS error: {:?}", err))
+}
+
+fn at(offset: u64) -> FileSystemReadWriteOptions {
+    let options = FileSystemReadWriteOptions::new();
+    options.set_at(offset as f64);
+    options
+}
+
+impl Storage for OpfsStorage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let read = self
+            .handle
+            .read_with_u8_array_and_options(buf, &at(offset))
+            .map_err(to_io_error)?;
+        Ok(read as usize)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let written = self
+            .handle
+            .write_with_u8_array_and_options(buf, &at(offset))
+            .map_err(to_io_error)?;
+        if (written as usize) < buf.len() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let size = self.handle.get_size().map_err(to_io_error)?;
+        Ok(size as u64)
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.handle
+            .truncate_with_f64(size as f64)
+            .map_err(to_io_error)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.handle.flush().map_err(to_io_error)
+    }
+}
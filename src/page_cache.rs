@@ -0,0 +1,266 @@
+use crate::collections::HashMap;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Least-recently-used cache of raw page bytes, bounded by a number of pages.
+///
+/// The cache is write-through: callers store every page they write, so a cached page is never
+/// newer than the one in storage and evicting it needs no write back.
+///
+/// Pinned pages are never evicted, so a working set can stay cached whatever else is read. The
+/// cache holds more pages than its capacity while pins keep it from shrinking back.
+#[derive(Debug, Default)]
+pub struct PageCache {
+    capacity: usize,
+    // page_id -> (last use, bytes)
+    pages: HashMap<u64, (u64, Vec<u8>)>,
+    // last use -> page_id, oldest first
+    recency: BTreeMap<u64, u64>,
+    // page_id -> times pinned and not yet unpinned
+    pins: HashMap<u64, usize>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl PageCache {
+    /// A cache holding up to `capacity` pages; a capacity of 0 caches nothing.
+    pub fn new(capacity: usize) -> Self {
+        PageCache {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, evicting the least recently used pages if it shrank.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.shrink_to_capacity();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Bytes of cached pages plus the cost of indexing each of them.
+    pub fn memory_usage(&self) -> usize {
+        let per_page = size_of::<(u64, (u64, Vec<u8>))>() + 2 * size_of::<u64>();
+        self.pages
+            .values()
+            .map(|(_, bytes)| bytes.len() + per_page)
+            .sum()
+    }
+
+    fn touch(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    pub fn get(&mut self, page_id: u64) -> Option<&[u8]> {
+        if !self.pages.contains_key(&page_id) {
+            if self.capacity > 0 {
+                self.misses += 1;
+            }
+            return None;
+        }
+
+        self.hits += 1;
+        let now = self.touch();
+        let (last_used, bytes) = self.pages.get_mut(&page_id).unwrap();
+        self.recency.remove(last_used);
+        self.recency.insert(now, page_id);
+        *last_used = now;
+        Some(bytes)
+    }
+
+    pub fn insert(&mut self, page_id: u64, bytes: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = self.touch();
+        if let Some((last_used, _)) = self.pages.insert(page_id, (now, bytes.to_vec())) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(now, page_id);
+        self.shrink_to_capacity();
+    }
+
+    /// Keeps `page_id` cached once read or written, until it has been unpinned as many times
+    /// as it was pinned. Pinning a page that is not cached yet does not read it.
+    pub fn pin(&mut self, page_id: u64) {
+        *self.pins.entry(page_id).or_insert(0) += 1;
+    }
+
+    /// Undoes one `pin` of `page_id`, letting it be evicted again once none are left.
+    pub fn unpin(&mut self, page_id: u64) {
+        if let Some(count) = self.pins.get_mut(&page_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.pins.remove(&page_id);
+                self.shrink_to_capacity();
+            }
+        }
+    }
+
+    /// Whether `page_id` is cached, without counting it as a use.
+    pub fn contains(&self, page_id: u64) -> bool {
+        self.pages.contains_key(&page_id)
+    }
+
+    pub fn is_pinned(&self, page_id: u64) -> bool {
+        self.pins.contains_key(&page_id)
+    }
+
+    /// Number of distinct pages pinned, whether cached or not.
+    pub fn pinned(&self) -> usize {
+        self.pins.len()
+    }
+
+    pub fn remove(&mut self, page_id: u64) {
+        if let Some((last_used, _)) = self.pages.remove(&page_id) {
+            self.recency.remove(&last_used);
+        }
+    }
+
+    /// Drops every cached page. Pins are kept, for pages cached again later.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+    }
+
+    fn shrink_to_capacity(&mut self) {
+        while self.pages.len() > self.capacity && self.evict_oldest() {}
+    }
+
+    // Evicts the least recently used page that is not pinned, returning false if every cached
+    // page is pinned
+    fn evict_oldest(&mut self) -> bool {
+        let Some((&last_used, &page_id)) = self
+            .recency
+            .iter()
+            .find(|(_, page_id)| !self.pins.contains_key(*page_id))
+        else {
+            return false;
+        };
+        self.recency.remove(&last_used);
+        self.pages.remove(&page_id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cache = PageCache::new(0);
+        cache.insert(1, b"page");
+
+        assert!(cache.get(1).is_none());
+        assert!(cache.is_empty());
+        assert_eq!(cache.misses(), 0);
+    }
+
+    #[test]
+    fn returns_latest_bytes() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, b"old");
+        cache.insert(1, b"new");
+
+        assert_eq!(cache.get(1), Some(&b"new"[..]));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, b"one");
+        cache.insert(2, b"two");
+
+        // Reading page 1 makes page 2 the oldest
+        assert!(cache.get(1).is_some());
+        cache.insert(3, b"three");
+
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn pinned_pages_outlive_other_traffic() {
+        let mut cache = PageCache::new(2);
+        cache.pin(1);
+        cache.pin(1);
+        cache.insert(1, b"one");
+        for page_id in 2..10 {
+            cache.insert(page_id, b"page");
+        }
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(9).is_some());
+
+        // Pinned pages may hold the cache past its capacity until they are unpinned
+        cache.pin(10);
+        cache.pin(11);
+        cache.insert(10, b"ten");
+        cache.insert(11, b"eleven");
+        assert_eq!(cache.len(), 3);
+
+        cache.unpin(1);
+        assert!(cache.is_pinned(1));
+        cache.unpin(1);
+        cache.unpin(10);
+        assert_eq!(cache.pinned(), 1);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(1));
+        assert!(cache.contains(10) && cache.contains(11));
+    }
+
+    #[test]
+    fn memory_usage_tracks_cached_bytes() {
+        let mut cache = PageCache::new(2);
+        assert_eq!(cache.memory_usage(), 0);
+
+        cache.insert(1, &[0u8; 100]);
+        let one_page = cache.memory_usage();
+        assert!(one_page >= 100);
+
+        cache.insert(2, &[0u8; 100]);
+        assert_eq!(cache.memory_usage(), 2 * one_page);
+
+        cache.clear();
+        assert_eq!(cache.memory_usage(), 0);
+    }
+
+    #[test]
+    fn shrinking_evicts_oldest_pages() {
+        let mut cache = PageCache::new(3);
+        for page_id in 0..3 {
+            cache.insert(page_id, b"page");
+        }
+
+        cache.set_capacity(1);
+
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(2).is_some());
+    }
+}
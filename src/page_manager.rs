@@ -1,82 +1,695 @@
-use crate::header::Header;
-use std::fs::File;
-use std::io::{Read, Seek, Write};
+use crate::buffer_pool::{BufferPool, FreshBuffers};
+use crate::collections::{HashMap, HashSet};
+use crate::config::WriteMode;
+use crate::page_cache::PageCache;
+use crate::storage::Storage;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::fs::{File, OpenOptions, TryLockError};
+#[cfg(feature = "std")]
+use std::path::Path;
 
 #[derive(Debug)]
 pub enum PageManagerError {
-    Io(std::io::Error),
-    HeaderNotWritten,
+    Io(crate::io::Error),
+    StorageTooShort {
+        expected: u64,
+        got: u64,
+    },
+    ColdTierNotAttached,
+    Locked,
+    PageOutOfBounds {
+        page_id: u64,
+        page_count: u64,
+    },
+    ShortRead {
+        page_id: u64,
+        expected: usize,
+        got: usize,
+    },
+    /// Growing the storage would pass the size limit, or the filesystem is out of space.
+    StorageFull,
+    NotAFile,
+    ReadOnly,
+    WriteOnly,
 }
 
-impl std::fmt::Display for PageManagerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for PageManagerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             PageManagerError::Io(e) => {
                 write!(f, "IO error: {}", e)
             }
-            PageManagerError::HeaderNotWritten {} => {
-                write!(f, "Header has not been written")
+            PageManagerError::StorageTooShort { expected, got } => {
+                write!(
+                    f,
+                    "Storage is too short for its allocated pages: expected at least {} bytes, got {}",
+                    expected, got
+                )
+            }
+            PageManagerError::ColdTierNotAttached => {
+                write!(
+                    f,
+                    "Cold tier page requested but no cold tier file is attached"
+                )
+            }
+            PageManagerError::Locked => {
+                write!(f, "Database file is locked by another handle")
+            }
+            PageManagerError::NotAFile => {
+                write!(f, "Storage is not a regular file")
+            }
+            PageManagerError::ReadOnly => {
+                write!(f, "File is open read-only")
+            }
+            PageManagerError::WriteOnly => {
+                write!(f, "File is open write-only")
+            }
+            PageManagerError::StorageFull => {
+                write!(f, "Storage is full")
+            }
+            PageManagerError::PageOutOfBounds {
+                page_id,
+                page_count,
+            } => {
+                write!(
+                    f,
+                    "Page {} is out of bounds: only {} pages exist",
+                    page_id, page_count
+                )
+            }
+            PageManagerError::ShortRead {
+                page_id,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "Short read of page {}: expected {} bytes, got {}",
+                    page_id, expected, got
+                )
             }
         }
     }
 }
 
-impl From<std::io::Error> for PageManagerError {
-    fn from(err: std::io::Error) -> PageManagerError {
-        PageManagerError::Io(err)
+impl From<crate::io::Error> for PageManagerError {
+    fn from(err: crate::io::Error) -> PageManagerError {
+        match err.kind() {
+            crate::io::ErrorKind::StorageFull | crate::io::ErrorKind::QuotaExceeded => {
+                PageManagerError::StorageFull
+            }
+            _ => PageManagerError::Io(err),
+        }
+    }
+}
+
+/// Opens `path` for reading and writing, creating it if needed. On Windows the file is opened
+/// with read/write sharing so that the exclusive lock, not the share mode, is what rejects a
+/// second writer, and with delete sharing so a rebuilt file can be renamed over it while it is
+/// open; that keeps the behaviour identical to Unix.
+#[cfg(feature = "std")]
+fn open_shared(path: &Path) -> crate::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_SHARE_READ: u32 = 0x1;
+        const FILE_SHARE_WRITE: u32 = 0x2;
+        const FILE_SHARE_DELETE: u32 = 0x4;
+        options.share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE);
+    }
+
+    options.open(path)
+}
+
+#[cfg(feature = "std")]
+fn lock(file: &File) -> Result<(), PageManagerError> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(PageManagerError::Locked),
+        Err(TryLockError::Error(e)) => Err(PageManagerError::Io(e)),
     }
 }
 
+/// Page IDs with this bit set live in the cold tier file rather than the primary file.
+pub const COLD_TIER_BIT: u64 = 1 << 63;
+
 pub struct PageManager {
-    file: File,
+    storage: Box<dyn Storage>,
+    cold_storage: Option<Box<dyn Storage>>,
+    // Reads per page since the counts were last taken; only tracked while a cold tier exists
+    access_counts: HashMap<u64, u64>,
+    cache: PageCache,
+    // Pages pinned for the operation in progress, while one is
+    operation_pins: Option<HashSet<u64>>,
+    // Pages allocated so far in each tier, which is also the next page ID to hand out. Reads
+    // beyond these are rejected. The file length is only a fallback for the primary tier until
+    // the tree restores its count from the header.
+    page_count: u64,
+    cold_page_count: u64,
+    // Pages the tree has freed, in either tier, which allocations take before growing it
+    free_pages: BTreeSet<u64>,
+    // Largest the primary storage may grow to, in bytes; 0 for no limit
+    max_size: u64,
+    // Pages written since the storage was last synced
+    dirty: HashSet<u64>,
+    // Dirty pages past which the tree holds back writes; 0 for no limit
+    max_dirty_pages: u64,
+    // Pages written since `take_written` was last called, while tracking is on
+    written: Option<HashSet<u64>>,
+    // Writes so far, and the one that last wrote each page written since the pages were last
+    // discarded; pages missing here have `base_version`
+    write_version: u64,
+    page_versions: HashMap<u64, u64>,
+    base_version: u64,
+    write_mode: WriteMode,
+    // Pages and header written but held back from storage until `write_back`, in write-back
+    // mode; each page on its own, even those written together as a longer run
+    held_pages: BTreeMap<u64, Vec<u8>>,
+    held_header: Option<Vec<u8>>,
+    // Where page buffers are taken from and handed back to
+    buffers: Arc<dyn BufferPool>,
     pub page_size: u64,
     pub header_size: u64,
 }
 
 impl PageManager {
-    pub fn new(file: File, page_size: u64, header_size: u64) -> Self {
-        let mut file_clone = file.try_clone().unwrap();
-        let file_length = file_clone.seek(std::io::SeekFrom::End(0)).unwrap();
-        if file_length < header_size {
+    /// Takes over `storage`, writing an empty header into it first if it is shorter than one.
+    pub fn new<S: Storage + 'static>(
+        storage: S,
+        page_size: u64,
+        header_size: u64,
+    ) -> Result<Self, PageManagerError> {
+        let storage_length = storage.size()?;
+        if storage_length < header_size {
             let header_buffer = vec![0u8; header_size as usize];
-            file_clone.write_all(&header_buffer).unwrap();
+            storage.write_at(&header_buffer, 0)?;
         }
 
-        PageManager {
-            file,
+        Ok(PageManager {
+            storage: Box::new(storage),
+            cold_storage: None,
+            access_counts: HashMap::new(),
+            cache: PageCache::new(0),
+            operation_pins: None,
+            page_count: storage_length.saturating_sub(header_size) / page_size,
+            cold_page_count: 0,
+            free_pages: BTreeSet::new(),
+            max_size: 0,
+            dirty: HashSet::new(),
+            max_dirty_pages: 0,
+            written: None,
+            write_version: 0,
+            page_versions: HashMap::new(),
+            base_version: 0,
+            write_mode: WriteMode::default(),
+            held_pages: BTreeMap::new(),
+            held_header: None,
+            buffers: Arc::new(FreshBuffers),
             page_size,
             header_size,
+        })
+    }
+
+    /// Opens the database file at `path` and takes an exclusive advisory lock on it, which is
+    /// held until the page manager is dropped. Fails with `Locked` if another handle (in this
+    /// or another process) already holds it.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        page_size: u64,
+        header_size: u64,
+    ) -> Result<Self, PageManagerError> {
+        let file = open_shared(path.as_ref())?;
+        lock(&file)?;
+
+        Self::new(file, page_size, header_size)
+    }
+
+    /// Takes over a file opened elsewhere, such as a descriptor passed in by another process.
+    /// The file must be a regular file open for both reading and writing, at least as long as
+    /// the header unless it is empty, and not locked by another handle; it is locked like
+    /// `open` locks. Its cursor position is irrelevant, since all access is positional.
+    #[cfg(feature = "std")]
+    pub fn from_file(
+        file: File,
+        page_size: u64,
+        header_size: u64,
+    ) -> Result<Self, PageManagerError> {
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(PageManagerError::NotAFile);
+        }
+        match crate::storage::probe_access(&file) {
+            (true, true) => {}
+            (true, false) => return Err(PageManagerError::ReadOnly),
+            (false, _) => return Err(PageManagerError::WriteOnly),
+        }
+        lock(&file)?;
+
+        let length = metadata.len();
+        if length > 0 && length < header_size {
+            return Err(PageManagerError::StorageTooShort {
+                expected: header_size,
+                got: length,
+            });
         }
+        Self::new(file, page_size, header_size)
     }
 
-    fn from_pageid(&self, page_id: u64) -> u64 {
-        (page_id * self.page_size) + self.header_size
+    /// Keeps up to `pages` recently read or written pages in memory; 0 turns the cache off.
+    pub fn set_cache_capacity(&mut self, pages: usize) {
+        self.cache.set_capacity(pages);
+    }
+
+    pub fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    /// Keeps `page_id` in the page cache until it is unpinned as many times, as
+    /// [`PageCache::pin`] does.
+    pub fn pin_page(&mut self, page_id: u64) {
+        self.cache.pin(page_id);
+    }
+
+    pub fn unpin_page(&mut self, page_id: u64) {
+        self.cache.unpin(page_id);
+    }
+
+    /// Pins every page read or written from now on until `end_pinned_operation`, so an
+    /// operation that comes back to pages it has already visited finds them cached. Ends any
+    /// operation still pinning pages first.
+    pub fn begin_pinned_operation(&mut self) {
+        self.end_pinned_operation();
+        self.operation_pins = Some(HashSet::new());
+    }
+
+    /// Unpins the pages pinned since `begin_pinned_operation`.
+    pub fn end_pinned_operation(&mut self) {
+        for page_id in self.operation_pins.take().unwrap_or_default() {
+            self.cache.unpin(page_id);
+        }
+    }
+
+    fn pin_for_operation(&mut self, page_id: u64) {
+        if let Some(pins) = &mut self.operation_pins
+            && pins.insert(page_id)
+        {
+            self.cache.pin(page_id);
+        }
+    }
+
+    /// Takes the buffers pages are read into from `buffers`, for reads and writes from now on.
+    pub fn set_buffer_pool(&mut self, buffers: Arc<dyn BufferPool>) {
+        self.buffers = buffers;
+    }
+
+    pub fn buffer_pool(&self) -> &Arc<dyn BufferPool> {
+        &self.buffers
+    }
+
+    /// A buffer of `len` bytes from the buffer pool, with unspecified contents.
+    pub fn take_buffer(&self, len: usize) -> Vec<u8> {
+        self.buffers.take(len)
+    }
+
+    /// Hands a buffer returned by a read, or taken with `take_buffer`, back to the pool.
+    pub fn give_back(&self, buffer: Vec<u8>) {
+        self.buffers.give_back(buffer);
+    }
+
+    /// Bytes held by the per-page read counts kept for tiering.
+    pub fn access_counts_memory_usage(&self) -> usize {
+        self.access_counts.len() * 2 * size_of::<u64>()
+    }
+
+    /// Bytes held by the per-page versions behind `page_version`.
+    pub fn page_versions_memory_usage(&self) -> usize {
+        self.page_versions.len() * 2 * size_of::<u64>()
+    }
+
+    pub fn is_cold(page_id: u64) -> bool {
+        page_id & COLD_TIER_BIT != 0
+    }
+
+    /// Uses `storage` as the secondary (slower/cheaper) tier. The cold tier has no header; cold
+    /// page `n` lives at byte `n * page_size`.
+    pub fn attach_cold_tier<S: Storage + 'static>(
+        &mut self,
+        storage: S,
+    ) -> Result<(), PageManagerError> {
+        self.cold_page_count = storage.size()? / self.page_size;
+        // Pages freed in a tier attached before say nothing about this one
+        self.free_pages.retain(|&page_id| !Self::is_cold(page_id));
+        self.cold_storage = Some(Box::new(storage));
+        Ok(())
+    }
+
+    /// Number of pages allocated in the primary file; the next allocation gets this ID.
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// Bytes of primary storage taken by the header and the pages allocated so far.
+    pub fn size(&self) -> u64 {
+        self.pageid_to_offset(self.page_count)
+    }
+
+    /// Refuses allocations that would grow the primary storage past `bytes`; 0 removes the
+    /// limit. Pages already allocated are kept even if they pass it.
+    pub fn set_max_size(&mut self, bytes: u64) {
+        self.max_size = bytes;
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Number of pages written since the last `sync`.
+    pub fn dirty_pages(&self) -> u64 {
+        self.dirty.len() as u64
+    }
+
+    /// Sets how many dirty pages the tree lets build up before holding back writes; 0 removes
+    /// the limit. The page manager only keeps count; the tree enforces it.
+    pub fn set_max_dirty_pages(&mut self, pages: u64) {
+        self.max_dirty_pages = pages;
+    }
+
+    pub fn max_dirty_pages(&self) -> u64 {
+        self.max_dirty_pages
+    }
+
+    /// Starts or stops recording which pages are written, for `take_written`.
+    pub fn track_written(&mut self, enabled: bool) {
+        self.written = enabled.then(HashSet::new);
+    }
+
+    /// Pages written since tracking started or this was last called, by the ID each write
+    /// started at.
+    pub fn take_written(&mut self) -> HashSet<u64> {
+        self.written
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Number of page writes so far. A copy of a page read when this was `v` is stale once
+    /// `page_version` of the page is above `v`.
+    pub fn write_version(&self) -> u64 {
+        self.write_version
+    }
+
+    /// The `write_version` of the last write of `page_id`.
+    pub fn page_version(&self, page_id: u64) -> u64 {
+        self.page_versions
+            .get(&page_id)
+            .copied()
+            .unwrap_or(self.base_version)
+    }
+
+    fn bump_version(&mut self, page_id: u64) {
+        self.write_version += 1;
+        self.page_versions.insert(page_id, self.write_version);
+    }
+
+    /// Switches between writing pages to storage straight away and holding them back until
+    /// `write_back`. Leaving write-back mode writes back whatever is held.
+    pub fn set_write_mode(&mut self, mode: WriteMode) -> Result<(), PageManagerError> {
+        if mode == WriteMode::WriteThrough {
+            self.write_back()?;
+        }
+        self.write_mode = mode;
+        Ok(())
+    }
+
+    pub fn write_mode(&self) -> WriteMode {
+        self.write_mode
+    }
+
+    /// Bytes of the pages and header held back in write-back mode.
+    pub fn held_memory_usage(&self) -> usize {
+        let pages: usize = self.held_pages.values().map(Vec::len).sum();
+        pages + self.held_header.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Writes the pages held back in write-back mode to storage in page order, and then the
+    /// header, without syncing them.
+    pub fn write_back(&mut self) -> Result<(), PageManagerError> {
+        while let Some((page_id, data)) = self.held_pages.pop_first() {
+            let (storage, offset) = self.locate_page(page_id)?;
+            if let Err(e) = storage.write_at(&data, offset) {
+                // Kept so a later write-back can try again
+                self.held_pages.insert(page_id, data);
+                return Err(e.into());
+            }
+        }
+        if let Some(header) = self.held_header.take() {
+            self.storage.write_at(&header, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the pages and header held back in write-back mode without writing them.
+    pub fn discard_held(&mut self) {
+        self.held_pages.clear();
+        self.held_header = None;
+    }
+
+    /// Fails with `StorageFull` unless `n` more pages can be allocated within the size limit.
+    pub fn ensure_room(&self, n: u64) -> Result<(), PageManagerError> {
+        if self.max_size != 0 && self.pageid_to_offset(self.page_count + n) > self.max_size {
+            return Err(PageManagerError::StorageFull);
+        }
+        Ok(())
+    }
+
+    /// Pages that can still be allocated within the size limit, or `u64::MAX` without one.
+    pub fn pages_left(&self) -> u64 {
+        match self.max_size {
+            0 => u64::MAX,
+            max => max.saturating_sub(self.size()) / self.page_size,
+        }
+    }
+
+    /// Takes the allocated page count from the tree's own metadata rather than the file
+    /// length, which preallocation or a torn extension can make disagree. Fails if the storage
+    /// is too short to hold that many pages.
+    pub fn restore_page_count(&mut self, page_count: u64) -> Result<(), PageManagerError> {
+        let expected = self.pageid_to_offset(page_count);
+        let got = self.storage.size()?;
+        if got < expected {
+            return Err(PageManagerError::StorageTooShort { expected, got });
+        }
+        self.page_count = page_count;
+        Ok(())
+    }
+
+    /// Number of pages allocated in the cold tier, if one is attached.
+    pub fn cold_page_count(&self) -> u64 {
+        self.cold_page_count
+    }
+
+    pub fn has_cold_tier(&self) -> bool {
+        self.cold_storage.is_some()
+    }
+
+    pub fn allocate_cold_page(&mut self) -> Result<u64, PageManagerError> {
+        Ok(self.allocate_cold_pages(1)?.start)
+    }
+
+    /// Reserves `n` consecutive zeroed pages in the cold tier and returns their IDs, reusing
+    /// freed ones where a long enough run of them is free.
+    pub fn allocate_cold_pages(&mut self, n: u64) -> Result<Range<u64>, PageManagerError> {
+        let page_size = self.page_size;
+        if self.cold_storage.is_none() {
+            return Err(PageManagerError::ColdTierNotAttached);
+        }
+        if let Some(pages) = self.take_free_run(COLD_TIER_BIT..u64::MAX, n)? {
+            return Ok(pages);
+        }
+        let cold_storage = self
+            .cold_storage
+            .as_ref()
+            .ok_or(PageManagerError::ColdTierNotAttached)?;
+
+        let index = self.cold_page_count;
+        let length = (n * page_size).try_into().unwrap();
+        cold_storage.write_at(&vec![0u8; length], index * page_size)?;
+
+        let pages = (index | COLD_TIER_BIT)..((index + n) | COLD_TIER_BIT);
+        self.cold_page_count = index + n;
+        for page_id in pages.clone() {
+            self.cache.remove(page_id);
+        }
+        Ok(pages)
+    }
+
+    /// Records pages the tree no longer refers to, so that allocations in their tier take them
+    /// before growing it.
+    pub fn release_pages(&mut self, pages: Range<u64>) {
+        self.free_pages.extend(pages);
+    }
+
+    // Takes the first run of `n` consecutive free pages among `ids`, zeroing them as a fresh
+    // allocation would be. Written like any other page, so held back in write-back mode.
+    fn take_free_run(
+        &mut self,
+        ids: Range<u64>,
+        n: u64,
+    ) -> Result<Option<Range<u64>>, PageManagerError> {
+        let mut run: Option<Range<u64>> = None;
+        for &page_id in self.free_pages.range(ids) {
+            run = match run {
+                Some(run) if run.end == page_id => Some(run.start..page_id + 1),
+                _ => Some(page_id..page_id + 1),
+            };
+            if run.as_ref().is_some_and(|run| run.end - run.start == n) {
+                break;
+            }
+        }
+        let Some(run) = run.filter(|run| run.end - run.start == n) else {
+            return Ok(None);
+        };
+        for page_id in run.clone() {
+            self.free_pages.remove(&page_id);
+        }
+        let zeroes = vec![0u8; (n * self.page_size).try_into().unwrap()];
+        self.write_page(run.start, &zeroes)?;
+        Ok(Some(run))
+    }
+
+    /// Returns the per-page read counts gathered since the last call and starts counting afresh.
+    pub fn take_access_counts(&mut self) -> HashMap<u64, u64> {
+        core::mem::take(&mut self.access_counts)
+    }
+
+    fn locate_page(&self, page_id: u64) -> Result<(&dyn Storage, u64), PageManagerError> {
+        if Self::is_cold(page_id) {
+            let offset = (page_id & !COLD_TIER_BIT) * self.page_size;
+            let cold_storage = self
+                .cold_storage
+                .as_deref()
+                .ok_or(PageManagerError::ColdTierNotAttached)?;
+            return Ok((cold_storage, offset));
+        }
+
+        Ok((self.storage.as_ref(), self.pageid_to_offset(page_id)))
+    }
+
+    // Pages written without being allocated first still extend their tier
+    fn note_written(&mut self, page_id: u64, pages: u64) {
+        if Self::is_cold(page_id) {
+            let index = page_id & !COLD_TIER_BIT;
+            self.cold_page_count = self.cold_page_count.max(index + pages);
+        } else {
+            self.page_count = self.page_count.max(page_id + pages);
+        }
     }
 
-    fn to_pageid(&self, byte_offset: u64) -> u64 {
-        (byte_offset - self.header_size) / self.page_size
+    fn check_bounds(&self, page_id: u64) -> Result<(), PageManagerError> {
+        let (index, page_count) = match Self::is_cold(page_id) {
+            true => (page_id & !COLD_TIER_BIT, self.cold_page_count),
+            false => (page_id, self.page_count),
+        };
+        if index >= page_count {
+            return Err(PageManagerError::PageOutOfBounds {
+                page_id,
+                page_count,
+            });
+        }
+        Ok(())
+    }
+
+    fn pageid_to_offset(&self, page_id: u64) -> u64 {
+        (page_id * self.page_size) + self.header_size
     }
 
     pub fn allocate_page(&mut self) -> Result<u64, PageManagerError> {
-        self.file.seek(std::io::SeekFrom::End(0))?;
+        Ok(self.allocate_pages(1)?.start)
+    }
 
-        let byte_offset = self.file.seek(std::io::SeekFrom::Current(0))?;
-        if byte_offset < Header::SIZE as u64 {
-            return Err(PageManagerError::HeaderNotWritten);
+    /// Reserves `n` consecutive zeroed pages and returns their IDs, reusing freed ones where a
+    /// long enough run of them is free. Otherwise the storage grows once for the whole range
+    /// rather than once per page.
+    pub fn allocate_pages(&mut self, n: u64) -> Result<Range<u64>, PageManagerError> {
+        let pages = self.page_count..self.page_count + n;
+        if n == 0 {
+            return Ok(pages);
+        }
+        if let Some(pages) = self.take_free_run(0..COLD_TIER_BIT, n)? {
+            return Ok(pages);
         }
+        self.ensure_room(n)?;
 
-        let page_id = self.to_pageid(byte_offset);
+        let start = self.pageid_to_offset(pages.start);
+        let end = self.pageid_to_offset(pages.end);
+        if self.storage.size()? <= start {
+            // Extending the storage fills the new pages with zeroes
+            self.storage.set_size(end)?;
+        } else {
+            // Space past the last allocated page may hold stale bytes
+            self.storage
+                .write_at(&vec![0u8; (end - start).try_into().unwrap()], start)?;
+        }
+
+        self.page_count = pages.end;
+        for page_id in pages.clone() {
+            self.cache.remove(page_id);
+        }
+        Ok(pages)
+    }
 
-        self.file
-            .write(&vec![0u8; self.page_size.try_into().unwrap()])?;
+    /// Discards every page, keeping only the header, and empties the cold tier if one is
+    /// attached. Page IDs are handed out from 0 again afterwards.
+    pub fn truncate(&mut self) -> Result<(), crate::io::Error> {
+        self.storage.set_size(self.header_size)?;
+        if let Some(cold_storage) = &self.cold_storage {
+            cold_storage.set_size(0)?;
+        }
+        self.cache.clear();
+        self.access_counts.clear();
+        self.dirty.clear();
+        self.held_pages.clear();
+        self.write_version += 1;
+        self.base_version = self.write_version;
+        self.page_versions.clear();
+        self.page_count = 0;
+        self.cold_page_count = 0;
+        self.free_pages.clear();
+        Ok(())
+    }
 
-        return Ok(page_id);
+    /// Gives back the pages from `page_count` on, which nothing may refer to any more, such as
+    /// ones allocated ahead of need and left free.
+    pub fn shrink_to(&mut self, page_count: u64) -> Result<(), crate::io::Error> {
+        if page_count >= self.page_count {
+            return Ok(());
+        }
+        for page_id in page_count..self.page_count {
+            self.cache.remove(page_id);
+            self.dirty.remove(&page_id);
+            self.held_pages.remove(&page_id);
+            self.free_pages.remove(&page_id);
+        }
+        self.storage.set_size(self.pageid_to_offset(page_count))?;
+        self.page_count = page_count;
+        Ok(())
     }
 
-    pub fn write_header(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+    pub fn write_header(&mut self, data: &[u8]) -> Result<(), crate::io::Error> {
         if data.len() > self.header_size as usize {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
+            return Err(crate::io::Error::new(
+                crate::io::ErrorKind::InvalidData,
                 format!(
                     "Buffer too large: expected {} got {}",
                     self.header_size,
@@ -85,33 +698,423 @@ impl PageManager {
             ));
         }
 
-        let _ = self.file.seek(std::io::SeekFrom::Start(0))?;
-        self.file.write_all(data)?;
-        Ok(())
+        match self.write_mode {
+            WriteMode::WriteThrough => self.storage.write_at(data, 0),
+            WriteMode::WriteBack => {
+                self.held_header = Some(data.to_vec());
+                Ok(())
+            }
+        }
     }
 
-    pub fn read_header(&mut self) -> Result<Vec<u8>, std::io::Error> {
+    pub fn read_header(&mut self) -> Result<Vec<u8>, crate::io::Error> {
         let mut buffer = vec![0u8; self.header_size as usize];
-        let _ = self.file.seek(std::io::SeekFrom::Start(0))?;
-        self.file.read(&mut buffer)?;
+        self.storage.read_exact_at(&mut buffer, 0)?;
+        if let Some(header) = &self.held_header {
+            buffer[..header.len()].copy_from_slice(header);
+        }
         Ok(buffer)
     }
 
-    pub fn write_page(&mut self, page_id: u64, data: &[u8]) -> Result<(), std::io::Error> {
-        self.file
-            .seek(std::io::SeekFrom::Start(self.from_pageid(page_id)))?;
+    /// Writes back anything held in write-back mode and syncs the storage, making every write
+    /// so far durable.
+    pub fn sync(&mut self) -> Result<(), PageManagerError> {
+        self.write_back()?;
+        self.storage.sync()?;
+        if let Some(cold_storage) = &self.cold_storage {
+            cold_storage.sync()?;
+        }
+        self.dirty.clear();
+        Ok(())
+    }
 
-        self.file.write_all(data)?;
+    /// Writes `data` at page `page_id`, or holds it back until `write_back` in write-back mode.
+    /// Data longer than a page, such as a node spanning several pages, runs on into the pages
+    /// after it.
+    pub fn write_page(&mut self, page_id: u64, data: &[u8]) -> Result<(), PageManagerError> {
+        let (storage, offset) = self.locate_page(page_id)?;
+        match self.write_mode {
+            WriteMode::WriteThrough => storage.write_at(data, offset)?,
+            WriteMode::WriteBack => {
+                for (i, page) in data.chunks(self.page_size as usize).enumerate() {
+                    self.held_pages.insert(page_id + i as u64, page.to_vec());
+                }
+            }
+        }
+        let pages = (data.len() as u64).div_ceil(self.page_size);
+        self.note_written(page_id, pages);
+        self.dirty.extend(page_id..page_id + pages);
+        if let Some(written) = &mut self.written {
+            written.insert(page_id);
+        }
+        self.pin_for_operation(page_id);
+        self.bump_version(page_id);
+        self.cache.insert(page_id, data);
         Ok(())
     }
 
-    pub fn read_page(&mut self, page_id: u64) -> Result<(Box<Vec<u8>>, usize), std::io::Error> {
-        self.file
-            .seek(std::io::SeekFrom::Start(self.from_pageid(page_id)))?;
+    /// Reads page `page_id` in full. Fails with `PageOutOfBounds` for a page that was never
+    /// allocated and with `ShortRead` if the file ends partway through the page.
+    pub fn read_page(&mut self, page_id: u64) -> Result<Vec<u8>, PageManagerError> {
+        self.locate_page(page_id)?;
+        self.check_bounds(page_id)?;
+        if self.cold_storage.is_some() {
+            *self.access_counts.entry(page_id).or_insert(0) += 1;
+        }
+        self.read_pages(page_id, 1)
+    }
+
+    /// Reads the `n` consecutive pages starting at `page_id` as one buffer, as written by a
+    /// single `write_page`, and caches them together under `page_id`. The access is not
+    /// counted, as the first page is read with `read_page` to learn how many pages follow it.
+    pub fn read_pages(&mut self, page_id: u64, n: u64) -> Result<Vec<u8>, PageManagerError> {
+        self.locate_page(page_id)?;
+        self.check_bounds(page_id + n - 1)?;
+
+        let buffer_size: usize = (n * self.page_size).try_into().unwrap();
+        self.pin_for_operation(page_id);
+        // Only the first page of a longer run may be cached, if it was read on its own
+        if let Some(cached) = self
+            .cache
+            .get(page_id)
+            .filter(|cached| n == 1 || cached.len() >= buffer_size)
+        {
+            let mut buffer = self.buffers.take(buffer_size);
+            let len = cached.len().min(buffer_size);
+            buffer[..len].copy_from_slice(&cached[..len]);
+            buffer[len..].fill(0);
+            return Ok(buffer);
+        }
+
+        let buffer = self.read_from_storage(page_id, n)?;
+        self.cache.insert(page_id, &buffer);
+        Ok(buffer)
+    }
+
+    /// Reads page `page_id` from storage even if it is cached, without caching it or counting
+    /// the access, so checking a page does not change which pages are kept or tiered.
+    pub fn read_page_uncached(&mut self, page_id: u64) -> Result<Vec<u8>, PageManagerError> {
+        self.read_pages_uncached(page_id, 1)
+    }
+
+    /// Like `read_page_uncached`, for the `n` consecutive pages starting at `page_id`.
+    pub fn read_pages_uncached(
+        &mut self,
+        page_id: u64,
+        n: u64,
+    ) -> Result<Vec<u8>, PageManagerError> {
+        self.locate_page(page_id)?;
+        self.check_bounds(page_id + n - 1)?;
+        self.read_from_storage(page_id, n)
+    }
+
+    fn read_from_storage(&self, page_id: u64, n: u64) -> Result<Vec<u8>, PageManagerError> {
+        if self.held_pages.range(page_id..page_id + n).next().is_some() {
+            return self.read_held(page_id, n);
+        }
+        let buffer_size: usize = (n * self.page_size).try_into().unwrap();
+        let mut buffer = self.buffers.take(buffer_size);
+        let (storage, offset) = self.locate_page(page_id)?;
+        let bytes_read = storage.read_at(&mut buffer, offset)?;
+        if bytes_read < buffer_size {
+            self.buffers.give_back(buffer);
+            return Err(PageManagerError::ShortRead {
+                page_id,
+                expected: buffer_size,
+                got: bytes_read,
+            });
+        }
+        Ok(buffer)
+    }
+
+    // Reads pages some of which are held back, taking those from memory and the rest from
+    // storage one at a time
+    fn read_held(&self, page_id: u64, n: u64) -> Result<Vec<u8>, PageManagerError> {
+        let page_size = self.page_size as usize;
+        let mut buffer = self.buffers.take(n as usize * page_size);
+        for (i, page) in buffer.chunks_mut(page_size).enumerate() {
+            let id = page_id + i as u64;
+            match self.held_pages.get(&id) {
+                Some(data) => {
+                    page[..data.len()].copy_from_slice(data);
+                    page[data.len()..].fill(0);
+                }
+                None => {
+                    let stored = self.read_from_storage(id, 1)?;
+                    page.copy_from_slice(&stored);
+                    self.buffers.give_back(stored);
+                }
+            }
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Header;
+    use crate::storage::MemoryStorage;
+
+    const PAGE_SIZE: u64 = 128;
+    const HEADER_SIZE: u64 = Header::SIZE as u64;
+
+    #[test]
+    fn reading_unallocated_page_is_out_of_bounds() {
+        let mut page_manager =
+            PageManager::new(MemoryStorage::new(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        let page_id = page_manager.allocate_page().unwrap();
+
+        assert!(page_manager.read_page(page_id).is_ok());
+        assert!(matches!(
+            page_manager.read_page(page_id + 1),
+            Err(PageManagerError::PageOutOfBounds {
+                page_id: 1,
+                page_count: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_page_is_a_short_read() {
+        let file = tempfile::tempfile().unwrap();
+        let mut page_manager =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        page_manager.allocate_page().unwrap();
+        let page_id = page_manager.allocate_page().unwrap();
+
+        file.set_len(HEADER_SIZE + PAGE_SIZE + 10).unwrap();
+
+        assert!(matches!(
+            page_manager.read_page(page_id),
+            Err(PageManagerError::ShortRead {
+                page_id: 1,
+                expected: 128,
+                got: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn storage_that_cannot_take_a_header_fails_to_open() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let read_only = std::fs::File::open(file.path()).unwrap();
+
+        assert!(matches!(
+            PageManager::new(read_only, PAGE_SIZE, HEADER_SIZE),
+            Err(PageManagerError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn page_count_is_recovered_from_storage_size() {
+        let file = tempfile::tempfile().unwrap();
+        let mut page_manager =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        for _ in 0..3 {
+            page_manager.allocate_page().unwrap();
+        }
+
+        let mut reopened = PageManager::new(file, PAGE_SIZE, HEADER_SIZE).unwrap();
+        assert_eq!(reopened.page_count(), 3);
+        assert!(reopened.read_page(2).is_ok());
+        assert!(reopened.read_page(3).is_err());
+    }
+
+    #[test]
+    fn allocate_pages_reserves_a_zeroed_range() {
+        let file = tempfile::tempfile().unwrap();
+        let mut page_manager =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        page_manager.allocate_page().unwrap();
+
+        let pages = page_manager.allocate_pages(4).unwrap();
+
+        assert_eq!(pages, 1..5);
+        assert_eq!(page_manager.page_count(), 5);
+        assert_eq!(file.metadata().unwrap().len(), HEADER_SIZE + 5 * PAGE_SIZE);
+        for page_id in pages {
+            assert!(
+                page_manager
+                    .read_page(page_id)
+                    .unwrap()
+                    .iter()
+                    .all(|&b| b == 0)
+            );
+        }
+        assert_eq!(page_manager.allocate_page().unwrap(), 5);
+        assert_eq!(page_manager.allocate_pages(0).unwrap(), 6..6);
+    }
+
+    #[test]
+    fn allocate_pages_zeroes_preallocated_space() {
+        let file = tempfile::tempfile().unwrap();
+        let mut page_manager =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        page_manager.allocate_page().unwrap();
+        page_manager
+            .write_page(0, &[7u8; PAGE_SIZE as usize])
+            .unwrap();
+        // Stale bytes past the last allocated page, as left by preallocation
+        Storage::write_at(
+            &file,
+            &[9u8; 3 * PAGE_SIZE as usize],
+            HEADER_SIZE + PAGE_SIZE,
+        )
+        .unwrap();
+
+        let pages = page_manager.allocate_pages(2).unwrap();
+
+        assert_eq!(pages, 1..3);
+        for page_id in pages {
+            assert!(
+                page_manager
+                    .read_page(page_id)
+                    .unwrap()
+                    .iter()
+                    .all(|&b| b == 0)
+            );
+        }
+        assert_eq!(
+            page_manager.read_page(0).unwrap(),
+            vec![7u8; PAGE_SIZE as usize]
+        );
+    }
+
+    #[test]
+    fn restored_page_count_drives_allocation() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(HEADER_SIZE + 10 * PAGE_SIZE).unwrap();
+        let mut page_manager = PageManager::new(file, PAGE_SIZE, HEADER_SIZE).unwrap();
+
+        page_manager.restore_page_count(2).unwrap();
+
+        assert_eq!(page_manager.allocate_page().unwrap(), 2);
+        assert!(matches!(
+            page_manager.restore_page_count(11),
+            Err(PageManagerError::StorageTooShort { .. })
+        ));
+    }
+
+    #[test]
+    fn allocation_past_max_size_is_refused() {
+        let mut page_manager =
+            PageManager::new(MemoryStorage::new(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        page_manager.set_max_size(HEADER_SIZE + 3 * PAGE_SIZE);
+        page_manager.allocate_page().unwrap();
+
+        assert_eq!(page_manager.pages_left(), 2);
+        assert!(page_manager.ensure_room(2).is_ok());
+        assert!(matches!(
+            page_manager.allocate_pages(3),
+            Err(PageManagerError::StorageFull)
+        ));
+        assert_eq!(page_manager.page_count(), 1);
+
+        page_manager.allocate_pages(2).unwrap();
+        assert_eq!(page_manager.pages_left(), 0);
+        assert_eq!(page_manager.size(), HEADER_SIZE + 3 * PAGE_SIZE);
+
+        page_manager.set_max_size(0);
+        assert_eq!(page_manager.pages_left(), u64::MAX);
+        assert!(page_manager.allocate_page().is_ok());
+    }
+
+    #[test]
+    fn cold_pages_are_bounds_checked() {
+        let mut page_manager =
+            PageManager::new(MemoryStorage::new(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        assert!(matches!(
+            page_manager.read_page(COLD_TIER_BIT),
+            Err(PageManagerError::ColdTierNotAttached)
+        ));
+
+        page_manager.attach_cold_tier(MemoryStorage::new()).unwrap();
+        let page_id = page_manager.allocate_cold_page().unwrap();
+
+        assert!(page_manager.read_page(page_id).is_ok());
+        assert!(matches!(
+            page_manager.read_page(page_id + 1),
+            Err(PageManagerError::PageOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn pages_written_together_read_back_as_one_run() {
+        let mut page_manager =
+            PageManager::new(MemoryStorage::new(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        page_manager.set_cache_capacity(4);
+        let pages = page_manager.allocate_pages(3).unwrap();
+        let run: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+        page_manager.write_page(pages.start, &run).unwrap();
+
+        // The first page alone is a prefix of the cached run
+        assert_eq!(
+            page_manager.read_page(0).unwrap(),
+            run[..PAGE_SIZE as usize]
+        );
+        assert_eq!(page_manager.read_pages(0, 3).unwrap(), run);
+        assert_eq!(page_manager.read_pages_uncached(0, 3).unwrap(), run);
+        assert!(matches!(
+            page_manager.read_pages(1, 3),
+            Err(PageManagerError::PageOutOfBounds { page_id: 3, .. })
+        ));
+
+        page_manager.attach_cold_tier(MemoryStorage::new()).unwrap();
+        let cold = page_manager.allocate_cold_pages(3).unwrap();
+        page_manager.write_page(cold.start, &run).unwrap();
+        assert_eq!(page_manager.cold_page_count(), 3);
+        assert_eq!(page_manager.read_pages(cold.start, 3).unwrap(), run);
+    }
+
+    #[test]
+    fn held_pages_are_read_over_stored_ones() {
+        let file = tempfile::tempfile().unwrap();
+        let mut page_manager =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        let pages = page_manager.allocate_pages(3).unwrap();
+        let run: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+        page_manager.write_page(pages.start, &run).unwrap();
+
+        page_manager.set_write_mode(WriteMode::WriteBack).unwrap();
+        let middle = vec![0xAB; PAGE_SIZE as usize];
+        page_manager.write_page(1, &middle).unwrap();
+        page_manager.write_header(&[7; 8]).unwrap();
+        assert_eq!(page_manager.read_header().unwrap()[..8], [7; 8]);
+
+        let mut expected = run.clone();
+        expected[PAGE_SIZE as usize..2 * PAGE_SIZE as usize].copy_from_slice(&middle);
+        assert_eq!(page_manager.read_pages_uncached(0, 3).unwrap(), expected);
+        assert_eq!(file.metadata().unwrap().len(), HEADER_SIZE + 3 * PAGE_SIZE);
+
+        // Nothing held reaches the file until written back
+        let mut stored =
+            PageManager::new(file.try_clone().unwrap(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        assert_eq!(stored.read_pages_uncached(0, 3).unwrap(), run);
+        page_manager
+            .set_write_mode(WriteMode::WriteThrough)
+            .unwrap();
+        assert_eq!(stored.read_pages_uncached(0, 3).unwrap(), expected);
+        assert_eq!(stored.read_header().unwrap()[..8], [7; 8]);
+    }
+
+    #[test]
+    fn written_pages_are_dirty_until_synced() {
+        let mut page_manager =
+            PageManager::new(MemoryStorage::new(), PAGE_SIZE, HEADER_SIZE).unwrap();
+        let pages = page_manager.allocate_pages(4).unwrap();
+        page_manager
+            .write_page(pages.start, &[1u8; 2 * PAGE_SIZE as usize])
+            .unwrap();
+        page_manager
+            .write_page(pages.start, &[2u8; PAGE_SIZE as usize])
+            .unwrap();
+        page_manager
+            .write_page(pages.start + 3, &[3u8; PAGE_SIZE as usize])
+            .unwrap();
 
-        let buffer_size: usize = self.page_size.try_into().unwrap();
-        let mut buffer = vec![0u8; buffer_size];
-        let bytes_read = self.file.read(&mut buffer)?;
-        Ok((Box::new(buffer), bytes_read))
+        assert_eq!(page_manager.dirty_pages(), 3);
+        page_manager.sync().unwrap();
+        assert_eq!(page_manager.dirty_pages(), 0);
     }
 }
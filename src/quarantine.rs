@@ -0,0 +1,81 @@
+//! Taking damaged leaves out of a tree so the rest of it stays usable, and rebuilding them
+//! from a backup where one is kept.
+
+use crate::btree::BTree;
+use crate::error::BTreeError;
+#[cfg(feature = "std")]
+use crate::snapshot::Snapshot;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::ops::Bound;
+use serde::{Deserialize, Serialize};
+
+/// A leaf replaced by an empty one, and the keys it covered: those strictly between `lower`
+/// and `upper`, where `None` is unbounded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedPage<K> {
+    pub page_id: u64,
+    pub lower: Option<K>,
+    pub upper: Option<K>,
+}
+
+/// What [`BTree::quarantine`] or [`BTree::recover`] took out of the tree and put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantineReport<K> {
+    pub pages: Vec<QuarantinedPage<K>>,
+    /// Pages asked for that the tree does not reach, such as free pages, which are left alone.
+    pub unreachable: Vec<u64>,
+    /// Entries the tree held before and no longer does, once any restored ones are back.
+    pub entries_lost: u64,
+    /// Entries put back from a backup.
+    pub entries_restored: u64,
+}
+
+/// An earlier copy of a tree that quarantined leaves can be rebuilt from.
+pub trait Backup<K, V> {
+    /// Entries with keys strictly between `lower` and `upper`, where `None` is unbounded, in
+    /// key order.
+    fn entries_between(
+        &mut self,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> Result<Vec<(K, V)>, BTreeError>;
+}
+
+fn bounds<K: Clone>(lower: Option<&K>, upper: Option<&K>) -> (Bound<K>, Bound<K>) {
+    (
+        lower.map_or(Bound::Unbounded, |key| Bound::Excluded(key.clone())),
+        upper.map_or(Bound::Unbounded, |key| Bound::Excluded(key.clone())),
+    )
+}
+
+/// A copy of the tree's file, such as a backup, opened as a tree of its own.
+impl<K, V> Backup<K, V> for BTree<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn entries_between(
+        &mut self,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> Result<Vec<(K, V)>, BTreeError> {
+        self.range(bounds(lower, upper))?.collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Backup<K, V> for Snapshot<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    fn entries_between(
+        &mut self,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> Result<Vec<(K, V)>, BTreeError> {
+        self.range(bounds(lower, upper))?.collect()
+    }
+}
@@ -0,0 +1,146 @@
+//! A persistent first-in, first-out queue kept in a [`BTree`], as a tree keyed by position.
+//!
+//! Values are stored under keys drawn from a [sequence](crate::sequence) of the tree, so each
+//! is stored after every value pushed before it, even across a crash, and the front of the
+//! queue is always the tree's first entry. Popping deletes entries from the front, whose
+//! pages are merged away as they empty; once the queue is empty the tree is cleared, which
+//! hands its pages back to the filesystem.
+
+use crate::btree::BTree;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use log::debug;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Name of the sequence a queue draws its keys from.
+pub const QUEUE_SEQUENCE: &str = "queue";
+
+pub struct Queue<V> {
+    tree: BTree<u64, V>,
+}
+
+impl<V> Queue<V>
+where
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Keeps the queue in `tree`, which must only ever have been written to as a queue.
+    pub fn new(tree: BTree<u64, V>) -> Self {
+        Queue { tree }
+    }
+
+    /// Opens the queue kept in the tree at `path`, creating it if needed.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P, config: TreeConfig) -> Result<Self, BTreeError> {
+        Ok(Queue::new(BTree::open(path, config)?))
+    }
+
+    pub fn in_memory(config: TreeConfig) -> Result<Self, BTreeError> {
+        Ok(Queue::new(BTree::in_memory(config)?))
+    }
+
+    /// Adds `value` at the back of the queue, returning its position: the key it is stored
+    /// under, greater than that of every value pushed before it.
+    pub fn push_back(&mut self, value: V) -> Result<u64, BTreeError> {
+        let position = self.tree.next_sequence(QUEUE_SEQUENCE)?;
+        self.tree.insert(position, value)?;
+        Ok(position)
+    }
+
+    /// The value at the front of the queue, without removing it.
+    pub fn peek(&mut self) -> Result<Option<V>, BTreeError> {
+        self.tree
+            .iter()?
+            .next()
+            .transpose()
+            .map(|entry| entry.map(|(_, value)| value))
+    }
+
+    /// Removes and returns the value at the front of the queue.
+    pub fn pop_front(&mut self) -> Result<Option<V>, BTreeError> {
+        Ok(self.pop_front_batch(1)?.pop())
+    }
+
+    /// Removes and returns up to `max` values from the front of the queue, oldest first. The
+    /// front is read a page of values at a time rather than looked up again for each one.
+    pub fn pop_front_batch(&mut self, max: usize) -> Result<Vec<V>, BTreeError> {
+        let values = self
+            .tree
+            .drain()
+            .take(max)
+            .map(|entry| entry.map(|(_, value)| value))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !values.is_empty() && self.tree.is_empty() {
+            debug!("Queue emptied, clearing its tree");
+            self.tree.clear()?;
+        }
+        Ok(values)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Makes every push and pop so far durable.
+    pub fn flush(&mut self) -> Result<(), BTreeError> {
+        self.tree.flush()
+    }
+
+    /// The tree holding the queue, for its stats or maintenance.
+    pub fn tree(&mut self) -> &mut BTree<u64, V> {
+        &mut self.tree
+    }
+
+    pub fn into_tree(self) -> BTree<u64, V> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_come_out_in_the_order_pushed_across_reopens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.db");
+        let config = TreeConfig::with_page_size(512);
+
+        let mut queue = Queue::<String>::open(&path, config).unwrap();
+        for i in 0..300 {
+            queue.push_back(format!("job-{}", i)).unwrap();
+        }
+        assert_eq!(queue.pop_front().unwrap(), Some("job-0".to_string()));
+        assert_eq!(queue.peek().unwrap(), Some("job-1".to_string()));
+        drop(queue);
+
+        let mut queue = Queue::<String>::open(&path, config).unwrap();
+        assert_eq!(queue.len(), 299);
+        let position = queue.push_back("job-300".to_string()).unwrap();
+        assert!(position > 300);
+        let batch = queue.pop_front_batch(100).unwrap();
+        assert_eq!(batch.len(), 100);
+        assert_eq!(batch[0], "job-1");
+        assert_eq!(batch[99], "job-100");
+
+        // Draining the queue gives its space back
+        let size_before = queue.tree().stats().file_size;
+        let rest = queue.pop_front_batch(usize::MAX).unwrap();
+        assert_eq!(rest.len(), 200);
+        assert_eq!(rest.last().unwrap(), "job-300");
+        assert!(queue.is_empty());
+        assert!(queue.tree().stats().file_size < size_before);
+        assert_eq!(queue.pop_front().unwrap(), None);
+        assert_eq!(queue.peek().unwrap(), None);
+
+        // Positions keep rising after the clear
+        assert!(queue.push_back("job-301".to_string()).unwrap() > position);
+    }
+}
@@ -0,0 +1,130 @@
+//! Key range locks, for transactions layered over a tree that need serializable scans.
+//!
+//! A transaction locks each range it scans with [`BTree::lock_range`](crate::BTree::lock_range).
+//! The lock covers the gaps between the keys in the range as well as the keys themselves, so
+//! until it is released no other writer can insert a key into the range or delete one from
+//! it, and scanning the range again returns the same entries: there are no phantoms. Locks are
+//! shared, so any number of owners can scan the same range; writes only conflict with the
+//! locks of other owners, and the rest of the tree stays open to writers.
+
+use alloc::vec::Vec;
+use core::ops::{Bound, RangeBounds};
+
+/// Whoever holds a lock, such as a transaction, named by an ID the caller chooses.
+pub type LockOwner = u64;
+
+/// A range of keys locked by [`BTree::lock_range`](crate::BTree::lock_range), held until given
+/// to [`BTree::unlock_range`](crate::BTree::unlock_range) or its owner's locks are released
+/// with [`BTree::unlock_owner`](crate::BTree::unlock_owner).
+#[derive(Debug, PartialEq, Eq)]
+pub struct RangeLock {
+    id: u64,
+    owner: LockOwner,
+}
+
+impl RangeLock {
+    pub fn owner(&self) -> LockOwner {
+        self.owner
+    }
+}
+
+struct Lock<K> {
+    id: u64,
+    owner: LockOwner,
+    start: Bound<K>,
+    end: Bound<K>,
+}
+
+/// The range locks held on a tree.
+pub(crate) struct RangeLocks<K> {
+    locks: Vec<Lock<K>>,
+    next_id: u64,
+}
+
+impl<K> Default for RangeLocks<K> {
+    fn default() -> Self {
+        RangeLocks {
+            locks: Vec::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<K: PartialOrd> RangeLocks<K> {
+    pub(crate) fn lock(&mut self, owner: LockOwner, start: Bound<K>, end: Bound<K>) -> RangeLock {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.locks.push(Lock {
+            id,
+            owner,
+            start,
+            end,
+        });
+        RangeLock { id, owner }
+    }
+
+    pub(crate) fn unlock(&mut self, lock: RangeLock) {
+        self.locks.retain(|l| l.id != lock.id);
+    }
+
+    /// Releases every lock of `owner`, returning how many there were.
+    pub(crate) fn unlock_owner(&mut self, owner: LockOwner) -> usize {
+        let before = self.locks.len();
+        self.locks.retain(|l| l.owner != owner);
+        before - self.locks.len()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    /// The owner of a lock covering `key` other than `writer`, which a write of `key` by
+    /// `writer` must wait for. Writes without an owner conflict with every lock.
+    pub(crate) fn conflict(&self, writer: Option<LockOwner>, key: &K) -> Option<LockOwner> {
+        self.locks
+            .iter()
+            .find(|l| Some(l.owner) != writer && (l.start.as_ref(), l.end.as_ref()).contains(key))
+            .map(|l| l.owner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BTree;
+    use crate::error::BTreeError;
+
+    #[test]
+    fn locked_ranges_reject_writes_of_other_owners() {
+        let mut btree = BTree::<u64, String>::temporary(512).unwrap();
+        for i in (0..100).step_by(10) {
+            btree.insert(i, format!("value-{}", i)).unwrap();
+        }
+        let _scan = btree.lock_range(1, 20..50);
+        let other = btree.lock_range(2, 40..=40);
+
+        // Both a new key in a gap and an existing one are covered
+        assert!(matches!(
+            btree.insert_as(2, 25, "phantom".to_string()),
+            Err(BTreeError::RangeLocked { owner: 1, .. })
+        ));
+        assert!(matches!(
+            btree.delete(30),
+            Err(BTreeError::RangeLocked { owner: 1, .. })
+        ));
+        assert!(matches!(btree.search(25), Err(BTreeError::KeyNotFound(_))));
+
+        // The owner writes within its own range, and anyone outside it
+        btree.insert_as(1, 25, "own".to_string()).unwrap();
+        btree.insert(50, "after".to_string()).unwrap();
+        assert!(matches!(
+            btree.delete_as(1, 40),
+            Err(BTreeError::RangeLocked { owner: 2, .. })
+        ));
+
+        btree.unlock_range(other);
+        btree.delete_as(1, 40).unwrap();
+        assert_eq!(btree.unlock_owner(1), 1);
+        assert_eq!(btree.range_lock_count(), 0);
+        btree.insert(35, "free".to_string()).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Views of entries as the bytes the tree stores for them, returned by
+//! [`BTree::get_raw`](crate::BTree::get_raw) and [`BTree::scan_raw`](crate::BTree::scan_raw).
+//!
+//! The structs are `#[repr(C)]` with a fixed layout, so they can be passed across a language
+//! boundary as they are, and another language can decode the bytes itself instead of going
+//! through a serde round trip in Rust first. Their layout is part of the stable API: fields are
+//! only ever added in place of `reserved`.
+//!
+//! | struct         | field            | offset | size |
+//! |----------------|------------------|--------|------|
+//! | `RawBytes`     | `ptr`            | 0      | 8    |
+//! |                | `len`            | 8      | 8    |
+//! | `RawEntryMeta` | `page_id`        | 0      | 8    |
+//! |                | `slot`           | 8      | 4    |
+//! |                | `encoding`       | 12     | 1    |
+//! |                | `value_codec_id` | 13     | 1    |
+//! |                | `flags`          | 14     | 1    |
+//! |                | `reserved`       | 15     | 1    |
+//! | `RawEntry`     | `key`            | 0      | 16   |
+//! |                | `value`          | 16     | 16   |
+//! |                | `meta`           | 32     | 16   |
+//!
+//! Offsets are for 64-bit targets; pointers and lengths are 4 bytes each on 32-bit ones.
+
+use crate::slotted_page::SlottedPage;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use serde::{Deserialize, Serialize};
+
+/// Bytes borrowed from the tree for as long as the view is, as a pointer and a length. The
+/// fields are private so they always describe a valid slice.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RawBytes<'a> {
+    ptr: *const u8,
+    len: usize,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> RawBytes<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        RawBytes {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &'a [u8] {
+        // SAFETY: the fields are only ever set by `new`, from a slice living for 'a
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+/// Where an entry was read from and how its bytes are encoded.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawEntryMeta {
+    pub page_id: u64,
+    /// Position of the entry among those of its page.
+    pub slot: u32,
+    /// [`Encoding::to_byte`](crate::codec::Encoding::to_byte) of the encoding of the key, and of
+    /// the value unless `value_codec_id` is set.
+    pub encoding: u8,
+    /// Id of the custom [`ValueCodec`](crate::codec::ValueCodec) that encoded the value, or 0.
+    pub value_codec_id: u8,
+    /// `COMPRESSED` and `EXTERNAL`, describing how the value is stored. Either way the value
+    /// bytes of the view are the encoded value itself.
+    pub flags: u8,
+    /// Always 0.
+    pub reserved: u8,
+}
+
+impl RawEntryMeta {
+    /// The value is stored compressed with the tree's dictionary.
+    pub const COMPRESSED: u8 = 0x01;
+    /// The value is kept in the tree's value log.
+    pub const EXTERNAL: u8 = 0x02;
+}
+
+/// An entry as the bytes of its key and value.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RawEntry<'a> {
+    pub key: RawBytes<'a>,
+    pub value: RawBytes<'a>,
+    pub meta: RawEntryMeta,
+}
+
+impl<'a> RawEntry<'a> {
+    // The entry at `index` of `page`, whose value reads as `value`
+    pub(crate) fn from_page<K, V>(
+        page: &'a SlottedPage<K, V>,
+        index: usize,
+        value: &'a [u8],
+    ) -> Self
+    where
+        K: PartialOrd + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>,
+        V: Debug + Serialize + for<'de> Deserialize<'de>,
+    {
+        let slot = &page.slots[index];
+        let mut flags = 0;
+        if slot.compressed {
+            flags |= RawEntryMeta::COMPRESSED;
+        }
+        if slot.external {
+            flags |= RawEntryMeta::EXTERNAL;
+        }
+        RawEntry {
+            key: RawBytes::new(page.key_bytes(index)),
+            value: RawBytes::new(value),
+            meta: RawEntryMeta {
+                page_id: page.page_id,
+                slot: index as u32,
+                encoding: page.codecs().encoding().to_byte(),
+                value_codec_id: page.codecs().value_codec_id(),
+                flags,
+                reserved: 0,
+            },
+        }
+    }
+
+    pub fn key(&self) -> &'a [u8] {
+        self.key.as_slice()
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::{offset_of, size_of};
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn layout_matches_the_documented_one() {
+        assert_eq!(size_of::<RawBytes>(), 16);
+        assert_eq!(offset_of!(RawBytes, len), 8);
+
+        assert_eq!(size_of::<RawEntryMeta>(), 16);
+        assert_eq!(offset_of!(RawEntryMeta, slot), 8);
+        assert_eq!(offset_of!(RawEntryMeta, encoding), 12);
+        assert_eq!(offset_of!(RawEntryMeta, value_codec_id), 13);
+        assert_eq!(offset_of!(RawEntryMeta, flags), 14);
+        assert_eq!(offset_of!(RawEntryMeta, reserved), 15);
+
+        assert_eq!(size_of::<RawEntry>(), 48);
+        assert_eq!(offset_of!(RawEntry, value), 16);
+        assert_eq!(offset_of!(RawEntry, meta), 32);
+    }
+}
@@ -0,0 +1,35 @@
+//! Rebuilding the header of a tree whose header cannot be read from a scan of its pages, so
+//! the entries under it are not orphaned by a fresh one.
+//!
+//! Trees are only ever rebuilt when opened with
+//! [`BTree::open_with_recovery`](crate::BTree::open_with_recovery); every other way of opening
+//! one refuses a header it cannot read, leaving the file as it was.
+//!
+//! Pages say what they are in their prefix and point to their children, so the root is the
+//! tree page nothing else points to, and the pages allocated are those in the file. What pages
+//! do not record, such as fill factors, the duplicate policy, the comparator and the encoding,
+//! is taken from the configuration the tree is opened with.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// How a header that could not be read was rebuilt, returned by
+/// [`BTree::header_repair`](crate::BTree::header_repair).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderRepair {
+    /// Why the header could not be read.
+    pub reason: String,
+    /// The page taken as the root: of the tree pages no other page points to, the one with
+    /// the most pages beneath it.
+    pub root_page_id: u64,
+    /// Pages found in the file, all of which now count as allocated.
+    pub page_count: u64,
+    /// The other tree pages nothing points to, such as the roots of pages written by an
+    /// operation cut short. They are left as they are.
+    pub orphaned_pages: Vec<u64>,
+    /// Pages that are neither zeroed nor readable as the kind their prefix records, which the
+    /// scan skipped.
+    pub damaged_pages: Vec<u64>,
+    /// Entries counted beneath the root, which the tree's stats are reset to.
+    pub entries: u64,
+}
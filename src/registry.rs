@@ -0,0 +1,178 @@
+//! Record of the trees this process has opened by path, so a second handle on the same file is
+//! refused up front instead of relying on the file lock, which some filesystems ignore.
+
+use crate::error::BTreeError;
+use std::any::Any;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, Weak};
+
+enum Handle {
+    // Opened through `BTree::open`; nothing else may open the path
+    Exclusive,
+    // Opened through `BTree::open_shared`, which hands out clones of this
+    Shared(Weak<dyn Any + Send + Sync>),
+}
+
+struct Entry {
+    // Tells registrations of the same path apart, so a stale one never removes a newer entry
+    token: u64,
+    handle: Handle,
+}
+
+impl Entry {
+    fn is_live(&self) -> bool {
+        match &self.handle {
+            Handle::Exclusive => true,
+            Handle::Shared(weak) => weak.strong_count() > 0,
+        }
+    }
+}
+
+static OPEN_TREES: LazyLock<Mutex<HashMap<PathBuf, Entry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+fn open_trees() -> MutexGuard<'static, HashMap<PathBuf, Entry>> {
+    // The map is left consistent even if a holder panicked
+    OPEN_TREES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps the path of an open tree registered until dropped.
+pub(crate) struct Registration {
+    path: PathBuf,
+    token: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut trees = open_trees();
+        if trees.get(&self.path).is_some_and(|e| e.token == self.token) {
+            trees.remove(&self.path);
+        }
+    }
+}
+
+/// The path every spelling of `path` resolves to. A file that does not exist yet is resolved
+/// through its directory.
+pub(crate) fn canonical_path(path: &Path) -> std::io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let Some(name) = path.file_name() else {
+                return Err(e);
+            };
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            Ok(parent.canonicalize()?.join(name))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Registers `path` as opened by a single handle, failing with `AlreadyOpen` if this process
+/// already has it open.
+pub(crate) fn register(path: &Path) -> Result<Registration, BTreeError> {
+    let path = canonical_path(path)?;
+    let mut trees = open_trees();
+    if trees.get(&path).is_some_and(Entry::is_live) {
+        return Err(BTreeError::AlreadyOpen(path));
+    }
+
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    trees.insert(
+        path.clone(),
+        Entry {
+            token,
+            handle: Handle::Exclusive,
+        },
+    );
+    Ok(Registration { path, token })
+}
+
+/// Returns the shared handle on `path` if one is open, or else opens it with `open` and
+/// shares that. `attach` hands the new handle its registration, which it must keep for as
+/// long as it is open.
+///
+/// Fails with `AlreadyOpen` if the path is open through a handle that is not shared, or was
+/// shared as a different type.
+pub(crate) fn open_shared<T, O, A>(
+    path: &Path,
+    open: O,
+    attach: A,
+) -> Result<Arc<Mutex<T>>, BTreeError>
+where
+    T: Send + 'static,
+    O: FnOnce() -> Result<T, BTreeError>,
+    A: FnOnce(&mut T, Registration),
+{
+    let path = canonical_path(path)?;
+    let mut trees = open_trees();
+
+    // An upgraded handle is only dropped once the map is unlocked, since dropping the last one
+    // unregisters it
+    let existing = match trees.get(&path) {
+        Some(Entry {
+            handle: Handle::Shared(weak),
+            ..
+        }) => weak.upgrade(),
+        Some(entry) if entry.is_live() => return Err(BTreeError::AlreadyOpen(path)),
+        _ => None,
+    };
+    if let Some(existing) = existing {
+        drop(trees);
+        return existing
+            .downcast::<Mutex<T>>()
+            .map_err(|_| BTreeError::AlreadyOpen(path));
+    }
+
+    // Opened while the map is locked so that racing callers all get the same handle
+    let mut tree = open()?;
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    attach(
+        &mut tree,
+        Registration {
+            path: path.clone(),
+            token,
+        },
+    );
+    let shared = Arc::new(Mutex::new(tree));
+    let weak: Weak<dyn Any + Send + Sync> = Arc::downgrade(&shared) as _;
+    trees.insert(
+        path,
+        Entry {
+            token,
+            handle: Handle::Shared(weak),
+        },
+    );
+    Ok(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_path_resolves_missing_files_through_their_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let direct = canonical_path(&dir.path().join("tree.db")).unwrap();
+        let indirect = canonical_path(&dir.path().join(".").join("tree.db")).unwrap();
+
+        assert_eq!(direct, indirect);
+        assert_eq!(direct.parent().unwrap(), dir.path().canonicalize().unwrap());
+    }
+
+    #[test]
+    fn registration_is_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+
+        let first = register(&path).unwrap();
+        assert!(matches!(register(&path), Err(BTreeError::AlreadyOpen(_))));
+        drop(first);
+        assert!(register(&path).is_ok());
+    }
+}
@@ -0,0 +1,471 @@
+use crate::error::BTreeError;
+use crate::server::ServerTree;
+use std::io::{BufRead, Write};
+
+// Same bound as the binary protocol: no single argument may exceed this
+const MAX_BULK_LENGTH: usize = 64 * 1024 * 1024;
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+
+const DEFAULT_SCAN_COUNT: usize = 10;
+// Larger COUNTs are walked in steps of this many keys, continuing from the cursor returned
+const MAX_SCAN_COUNT: usize = 10_000;
+
+/// A RESP2 reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<RespValue>),
+}
+
+impl RespValue {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            RespValue::Simple(s) => write!(writer, "+{}\r\n", s),
+            RespValue::Error(e) => write!(writer, "-{}\r\n", e),
+            RespValue::Integer(n) => write!(writer, ":{}\r\n", n),
+            RespValue::Bulk(bytes) => {
+                write!(writer, "${}\r\n", bytes.len())?;
+                writer.write_all(bytes)?;
+                writer.write_all(b"\r\n")
+            }
+            RespValue::Null => writer.write_all(b"$-1\r\n"),
+            RespValue::Array(items) => {
+                write!(writer, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write_to(writer))
+            }
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\r\n") {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    line.truncate(line.len() - 2);
+    Ok(line)
+}
+
+fn parse_length(line: &[u8], max: usize) -> std::io::Result<usize> {
+    let length: usize = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("Protocol error: invalid length"))?;
+    if length > max {
+        return Err(invalid("Protocol error: length too large"));
+    }
+    Ok(length)
+}
+
+/// Reads one command: either an array of bulk strings, as sent by clients, or an inline
+/// command of space-separated words, as typed into a raw connection.
+pub fn read_command<R: BufRead>(reader: &mut R) -> std::io::Result<Vec<Vec<u8>>> {
+    let line = read_line(reader)?;
+    let Some((&b'*', count)) = line.split_first() else {
+        return Ok(line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect());
+    };
+
+    let count = parse_length(count, MAX_ARGUMENTS)?;
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let header = read_line(reader)?;
+        let Some((&b'$', length)) = header.split_first() else {
+            return Err(invalid("Protocol error: expected bulk string"));
+        };
+        let length = parse_length(length, MAX_BULK_LENGTH)?;
+
+        let mut arg = vec![0u8; length + 2];
+        reader.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(invalid("Protocol error: bulk string not terminated"));
+        }
+        arg.truncate(length);
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn key_arg(arg: &[u8]) -> Result<String, RespValue> {
+    String::from_utf8(arg.to_vec()).map_err(|_| RespValue::Error("ERR keys must be UTF-8".into()))
+}
+
+fn wrong_arity(command: &str) -> RespValue {
+    RespValue::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command.to_lowercase()
+    ))
+}
+
+fn tree_error(e: BTreeError) -> RespValue {
+    RespValue::Error(format!("ERR {}", e))
+}
+
+/// Outcome of a command: the reply, and whether the client asked to close the connection.
+pub struct Reply {
+    pub value: RespValue,
+    pub close: bool,
+}
+
+impl From<RespValue> for Reply {
+    fn from(value: RespValue) -> Self {
+        Reply {
+            value,
+            close: false,
+        }
+    }
+}
+
+/// Runs a command against `tree`. Supported: PING, ECHO, GET, SET, DEL, EXISTS, SCAN, QUIT,
+/// and an empty COMMAND reply so `redis-cli` starts cleanly.
+pub fn execute(tree: &mut ServerTree, args: &[Vec<u8>]) -> Reply {
+    let Some((name, args)) = args.split_first() else {
+        return RespValue::Error("ERR empty command".into()).into();
+    };
+    let name = String::from_utf8_lossy(name).to_uppercase();
+
+    match (name.as_str(), args) {
+        ("PING", []) => RespValue::Simple("PONG".into()).into(),
+        ("PING", [message]) | ("ECHO", [message]) => RespValue::Bulk(message.clone()).into(),
+        ("QUIT", _) => Reply {
+            value: RespValue::Simple("OK".into()),
+            close: true,
+        },
+        ("COMMAND", _) => RespValue::Array(Vec::new()).into(),
+        ("GET", [key]) => get(tree, key).unwrap_or_else(|e| e).into(),
+        ("SET", [key, value]) => set(tree, key, value).unwrap_or_else(|e| e).into(),
+        ("SET", [_, _, ..]) => RespValue::Error("ERR SET options are not supported".into()).into(),
+        ("DEL", [_, ..]) => del(tree, args).unwrap_or_else(|e| e).into(),
+        ("EXISTS", [_, ..]) => exists(tree, args).unwrap_or_else(|e| e).into(),
+        ("SCAN", [cursor, options @ ..]) => {
+            scan(tree, cursor, options).unwrap_or_else(|e| e).into()
+        }
+        ("PING" | "ECHO" | "GET" | "SET" | "DEL" | "EXISTS" | "SCAN", _) => {
+            wrong_arity(&name).into()
+        }
+        _ => RespValue::Error(format!("ERR unknown command '{}'", name.to_lowercase())).into(),
+    }
+}
+
+fn get(tree: &mut ServerTree, key: &[u8]) -> Result<RespValue, RespValue> {
+    match tree.search(key_arg(key)?) {
+        Ok(value) => Ok(RespValue::Bulk(value)),
+        Err(BTreeError::KeyNotFound(_)) => Ok(RespValue::Null),
+        Err(e) => Err(tree_error(e)),
+    }
+}
+
+fn set(tree: &mut ServerTree, key: &[u8], value: &[u8]) -> Result<RespValue, RespValue> {
+    tree.insert(key_arg(key)?, value.to_vec())
+        .map_err(tree_error)?;
+    Ok(RespValue::Simple("OK".into()))
+}
+
+fn del(tree: &mut ServerTree, keys: &[Vec<u8>]) -> Result<RespValue, RespValue> {
+    let mut deleted = 0;
+    for key in keys {
+        match tree.delete(key_arg(key)?) {
+            Ok(_) => deleted += 1,
+            Err(BTreeError::KeyNotFound(_)) => {}
+            Err(e) => return Err(tree_error(e)),
+        }
+    }
+    Ok(RespValue::Integer(deleted))
+}
+
+fn exists(tree: &mut ServerTree, keys: &[Vec<u8>]) -> Result<RespValue, RespValue> {
+    let mut found = 0;
+    for key in keys {
+        match tree.search(key_arg(key)?) {
+            Ok(_) => found += 1,
+            Err(BTreeError::KeyNotFound(_)) => {}
+            Err(e) => return Err(tree_error(e)),
+        }
+    }
+    Ok(RespValue::Integer(found))
+}
+
+// The cursor is the number of keys already walked past, so a scan visits every key present
+// for its whole duration exactly once, but skipping to the cursor costs a walk from the start.
+fn scan(tree: &mut ServerTree, cursor: &[u8], options: &[Vec<u8>]) -> Result<RespValue, RespValue> {
+    let cursor: usize = std::str::from_utf8(cursor)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| RespValue::Error("ERR invalid cursor".into()))?;
+
+    let mut count = DEFAULT_SCAN_COUNT;
+    let mut pattern: Option<Vec<u8>> = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| RespValue::Error("ERR syntax error".into()))?;
+        match option.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                count = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| {
+                        RespValue::Error("ERR value is not an integer or out of range".into())
+                    })?
+                    .min(MAX_SCAN_COUNT);
+            }
+            b"MATCH" => pattern = Some(value.clone()),
+            _ => return Err(RespValue::Error("ERR syntax error".into())),
+        }
+    }
+
+    let mut walked = Vec::with_capacity(count);
+    for entry in tree.iter().map_err(tree_error)?.skip(cursor).take(count) {
+        let (key, _) = entry.map_err(tree_error)?;
+        walked.push(key);
+    }
+
+    // Like Redis, MATCH filters the keys walked rather than walking until COUNT keys match
+    let next_cursor = if walked.len() < count {
+        0
+    } else {
+        cursor + walked.len()
+    };
+    let keys = walked
+        .into_iter()
+        .filter(|key| {
+            pattern
+                .as_ref()
+                .is_none_or(|p| glob_match(p, key.as_bytes()))
+        })
+        .map(|key| RespValue::Bulk(key.into_bytes()))
+        .collect();
+
+    Ok(RespValue::Array(vec![
+        RespValue::Bulk(next_cursor.to_string().into_bytes()),
+        RespValue::Array(keys),
+    ]))
+}
+
+/// Redis-style glob: `*` matches any run of bytes, `?` any single byte, `\` escapes.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the most recent `*` has to absorb one more byte
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(b'\\') if pattern.get(p + 1) == Some(&text[t]) => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some(&c) if c != b'\\' && c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match backtrack {
+            Some((star_p, star_t)) => {
+                backtrack = Some((star_p, star_t + 1));
+                p = star_p;
+                t = star_t + 1;
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::BTree;
+    use crate::config::TreeConfig;
+    use crate::storage::MemoryStorage;
+
+    fn memory_tree() -> ServerTree {
+        BTree::with_config(MemoryStorage::new(), TreeConfig::with_page_size(512)).unwrap()
+    }
+
+    fn run(tree: &mut ServerTree, command: &str) -> RespValue {
+        let args: Vec<Vec<u8>> = command
+            .split_whitespace()
+            .map(|w| w.as_bytes().to_vec())
+            .collect();
+        execute(tree, &args).value
+    }
+
+    fn encoded(value: &RespValue) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn encodes_replies() {
+        assert_eq!(encoded(&RespValue::Simple("OK".into())), b"+OK\r\n");
+        assert_eq!(encoded(&RespValue::Error("ERR x".into())), b"-ERR x\r\n");
+        assert_eq!(encoded(&RespValue::Integer(-3)), b":-3\r\n");
+        assert_eq!(encoded(&RespValue::Bulk(b"hi".to_vec())), b"$2\r\nhi\r\n");
+        assert_eq!(encoded(&RespValue::Null), b"$-1\r\n");
+        assert_eq!(
+            encoded(&RespValue::Array(vec![
+                RespValue::Integer(1),
+                RespValue::Null
+            ])),
+            b"*2\r\n:1\r\n$-1\r\n"
+        );
+    }
+
+    #[test]
+    fn parses_array_and_inline_commands() {
+        let mut input: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\na\r\nb\r\nGET  k\r\n";
+
+        assert_eq!(
+            read_command(&mut input).unwrap(),
+            vec![b"SET".to_vec(), b"k".to_vec(), b"a\r\nb".to_vec()]
+        );
+        assert_eq!(
+            read_command(&mut input).unwrap(),
+            vec![b"GET".to_vec(), b"k".to_vec()]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        let mut not_bulk: &[u8] = b"*1\r\n:1\r\n";
+        assert!(read_command(&mut not_bulk).is_err());
+
+        let mut bad_length: &[u8] = b"*1\r\n$x\r\n";
+        assert!(read_command(&mut bad_length).is_err());
+
+        let mut unterminated: &[u8] = b"*1\r\n$1\r\nab";
+        assert!(read_command(&mut unterminated).is_err());
+    }
+
+    #[test]
+    fn get_set_del_exists() {
+        let mut tree = memory_tree();
+
+        assert_eq!(run(&mut tree, "GET a"), RespValue::Null);
+        assert_eq!(run(&mut tree, "SET a 1"), RespValue::Simple("OK".into()));
+        assert_eq!(run(&mut tree, "SET b 2"), RespValue::Simple("OK".into()));
+        assert_eq!(run(&mut tree, "GET a"), RespValue::Bulk(b"1".to_vec()));
+        assert_eq!(run(&mut tree, "EXISTS a b c"), RespValue::Integer(2));
+        assert_eq!(run(&mut tree, "DEL a c"), RespValue::Integer(1));
+        assert_eq!(run(&mut tree, "GET a"), RespValue::Null);
+    }
+
+    #[test]
+    fn errors_for_unknown_commands_and_bad_arity() {
+        let mut tree = memory_tree();
+
+        assert!(matches!(run(&mut tree, "FLUSHALL"), RespValue::Error(_)));
+        assert!(matches!(run(&mut tree, "GET"), RespValue::Error(_)));
+        assert!(matches!(
+            run(&mut tree, "SET a 1 EX 10"),
+            RespValue::Error(_)
+        ));
+        assert!(matches!(run(&mut tree, "SCAN abc"), RespValue::Error(_)));
+    }
+
+    #[test]
+    fn scan_visits_every_key_once() {
+        let mut tree = memory_tree();
+        for i in 0..25 {
+            run(&mut tree, &format!("SET key{:02} v", i));
+        }
+
+        let mut cursor = "0".to_string();
+        let mut seen = Vec::new();
+        loop {
+            let RespValue::Array(reply) = run(&mut tree, &format!("SCAN {} COUNT 7", cursor))
+            else {
+                panic!("SCAN should reply with an array");
+            };
+            let [RespValue::Bulk(next), RespValue::Array(keys)] = reply.as_slice() else {
+                panic!("Unexpected SCAN reply: {:?}", reply);
+            };
+            seen.extend(keys.iter().cloned());
+            cursor = String::from_utf8(next.clone()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        let expected: Vec<RespValue> = (0..25)
+            .map(|i| RespValue::Bulk(format!("key{:02}", i).into_bytes()))
+            .collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn scan_match_filters_keys() {
+        let mut tree = memory_tree();
+        for key in ["user:1", "user:2", "order:1"] {
+            run(&mut tree, &format!("SET {} v", key));
+        }
+
+        assert_eq!(
+            run(&mut tree, "SCAN 0 MATCH user:* COUNT 100"),
+            RespValue::Array(vec![
+                RespValue::Bulk(b"0".to_vec()),
+                RespValue::Array(vec![
+                    RespValue::Bulk(b"user:1".to_vec()),
+                    RespValue::Bulk(b"user:2".to_vec()),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn scan_count_is_capped() {
+        let mut tree = memory_tree();
+        run(&mut tree, "SET a 1");
+
+        assert_eq!(
+            run(&mut tree, &format!("SCAN 0 COUNT {}", usize::MAX)),
+            RespValue::Array(vec![
+                RespValue::Bulk(b"0".to_vec()),
+                RespValue::Array(vec![RespValue::Bulk(b"a".to_vec())]),
+            ])
+        );
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"user:*", b"user:42"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"*llo", b"hello"));
+        assert!(glob_match(b"a*b*c", b"axxbyyc"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"x"));
+        assert!(!glob_match(b"user:*", b"order:1"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+    }
+}
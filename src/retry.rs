@@ -0,0 +1,423 @@
+//! Retries for storage operations that fail for reasons expected to pass, such as an
+//! interrupted system call or a remote backend that is briefly unavailable.
+
+use crate::storage::Storage;
+use log::{debug, warn};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How `RetryStorage` retries failed operations and when it stops trying altogether.
+///
+/// Each retry waits twice as long as the one before, starting at `initial_backoff` and never
+/// longer than `max_backoff`. Once `failure_threshold` operations in a row have failed even
+/// after retrying, the circuit opens: every operation fails straight away with [`CircuitOpen`]
+/// until `cooldown` has passed, after which operations are let through again and the first
+/// success closes the circuit.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts per operation, including the first; 1 disables retrying.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Operations failing in a row that open the circuit; 0 never opens it.
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+    /// Decides which errors are worth retrying.
+    pub is_transient: fn(&std::io::Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(5),
+            is_transient,
+        }
+    }
+}
+
+/// The errors `RetryPolicy::default` retries: interrupted calls, operations that would block
+/// and timeouts. Remote backends should report server errors that may pass, such as an HTTP
+/// 503, as `TimedOut` or supply their own classifier.
+pub fn is_transient(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::Interrupted
+            | std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Error returned, wrapped in an `std::io::Error`, for operations refused while the circuit is
+/// open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitOpen;
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Storage circuit is open after repeated failures")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RetryStats {
+    /// Attempts made after an operation's first.
+    pub retries: u64,
+    /// Operations that succeeded after being retried.
+    pub recovered: u64,
+    /// Operations that still failed with a transient error once out of attempts.
+    pub failures: u64,
+    /// Operations refused without being attempted because the circuit was open.
+    pub rejected: u64,
+    /// Times the circuit has opened.
+    pub trips: u64,
+}
+
+#[derive(Default)]
+struct State {
+    stats: RetryStats,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Shared view of a `RetryStorage`'s counters and circuit, which stays usable after the storage
+/// has been handed to a tree.
+#[derive(Clone, Default)]
+pub struct RetryMetrics {
+    state: Arc<Mutex<State>>,
+}
+
+impl RetryMetrics {
+    pub fn stats(&self) -> RetryStats {
+        self.lock_state().stats
+    }
+
+    /// Whether operations are currently being refused.
+    pub fn is_open(&self) -> bool {
+        self.lock_state()
+            .open_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Wraps another store, retrying its operations according to a [`RetryPolicy`].
+///
+/// Reads, writes and resizes are retried, since repeating them at the same offset has the
+/// same effect as doing them once. `sync` never is: after a failed sync the kernel may already
+/// have dropped the dirty pages it could not write, so a retry that succeeds would report data
+/// as durable when it is not.
+pub struct RetryStorage<S> {
+    inner: S,
+    policy: RetryPolicy,
+    metrics: RetryMetrics,
+}
+
+impl<S: Storage> RetryStorage<S> {
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        RetryStorage {
+            inner,
+            policy,
+            metrics: RetryMetrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> RetryMetrics {
+        self.metrics.clone()
+    }
+
+    fn run<T>(&self, op: &str, mut f: impl FnMut(&S) -> std::io::Result<T>) -> std::io::Result<T> {
+        self.check_circuit()?;
+
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match f(&self.inner) {
+                Ok(value) => {
+                    self.record_success(attempt);
+                    return Ok(value);
+                }
+                Err(e) if !(self.policy.is_transient)(&e) => return Err(e),
+                Err(e) if attempt >= self.policy.max_attempts => {
+                    warn!("Storage {} failed after {} attempts: {}", op, attempt, e);
+                    self.record_failure();
+                    return Err(e);
+                }
+                Err(e) => {
+                    debug!("Retrying storage {} in {:?}: {}", op, backoff, e);
+                    self.metrics.lock_state().stats.retries += 1;
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.policy.max_backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn check_circuit(&self) -> std::io::Result<()> {
+        let mut state = self.metrics.lock_state();
+        match state.open_until {
+            Some(until) if Instant::now() < until => {
+                state.stats.rejected += 1;
+                Err(std::io::Error::other(CircuitOpen))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_success(&self, attempts: u32) {
+        let mut state = self.metrics.lock_state();
+        if attempts > 1 {
+            state.stats.recovered += 1;
+        }
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.metrics.lock_state();
+        state.stats.failures += 1;
+        state.consecutive_failures += 1;
+        // Past the cooldown a single failure is enough to open the circuit again
+        let threshold = self.policy.failure_threshold;
+        if threshold != 0 && state.consecutive_failures >= threshold {
+            warn!(
+                "Opening storage circuit for {:?} after {} failures in a row",
+                self.policy.cooldown, state.consecutive_failures
+            );
+            state.stats.trips += 1;
+            state.open_until = Some(Instant::now() + self.policy.cooldown);
+        }
+    }
+}
+
+impl<S: Storage> Storage for RetryStorage<S> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.run("read", |inner| inner.read_at(buf, offset))
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        self.run("write", |inner| inner.write_at(buf, offset))
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        self.run("size", |inner| inner.size())
+    }
+
+    fn set_size(&self, size: u64) -> std::io::Result<()> {
+        self.run("set_size", |inner| inner.set_size(size))
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.check_circuit()?;
+        self.inner.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::BTree;
+    use crate::config::TreeConfig;
+    use crate::storage::MemoryStorage;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fails the next `failures` operations with `kind`, and after that every `every`th one if
+    // set, otherwise behaving like memory storage
+    #[derive(Default)]
+    struct FlakyStorage {
+        inner: MemoryStorage,
+        failures: Arc<AtomicU32>,
+        every: Option<u32>,
+        kind: Option<std::io::ErrorKind>,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl FlakyStorage {
+        fn failing(kind: std::io::ErrorKind, failures: u32) -> Self {
+            FlakyStorage {
+                failures: Arc::new(AtomicU32::new(failures)),
+                kind: Some(kind),
+                ..FlakyStorage::default()
+            }
+        }
+
+        fn intermittent(kind: std::io::ErrorKind, every: u32) -> Self {
+            FlakyStorage {
+                every: Some(every),
+                kind: Some(kind),
+                ..FlakyStorage::default()
+            }
+        }
+
+        fn attempt(&self) -> std::io::Result<()> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let failing = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+                || self.every.is_some_and(|every| call % every == every - 1);
+            match (failing, self.kind) {
+                (true, Some(kind)) => Err(kind.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl Storage for FlakyStorage {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+            self.attempt()?;
+            self.inner.read_at(buf, offset)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+            self.attempt()?;
+            self.inner.write_at(buf, offset)
+        }
+
+        fn size(&self) -> std::io::Result<u64> {
+            self.attempt()?;
+            self.inner.size()
+        }
+
+        fn set_size(&self, size: u64) -> std::io::Result<()> {
+            self.attempt()?;
+            self.inner.set_size(size)
+        }
+
+        fn sync(&self) -> std::io::Result<()> {
+            self.attempt()
+        }
+    }
+
+    fn quick_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(60),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let flaky = FlakyStorage::failing(std::io::ErrorKind::Interrupted, 2);
+        let calls = flaky.calls.clone();
+        let storage = RetryStorage::new(flaky, quick_policy());
+
+        storage.write_at(b"abc", 0).unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let stats = storage.metrics().stats();
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.recovered, 1);
+        assert_eq!(stats.failures, 0);
+
+        let mut buf = [0u8; 3];
+        storage.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"abc");
+    }
+
+    #[test]
+    fn other_errors_fail_immediately() {
+        let flaky = FlakyStorage::failing(std::io::ErrorKind::PermissionDenied, 1);
+        let calls = flaky.calls.clone();
+        let storage = RetryStorage::new(flaky, quick_policy());
+
+        let err = storage.write_at(b"abc", 0).unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(storage.metrics().stats(), RetryStats::default());
+    }
+
+    #[test]
+    fn sync_is_never_retried() {
+        let flaky = FlakyStorage::failing(std::io::ErrorKind::Interrupted, 1);
+        let calls = flaky.calls.clone();
+        let storage = RetryStorage::new(flaky, quick_policy());
+
+        assert!(storage.sync().is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn persistent_failures_open_the_circuit() {
+        let flaky = FlakyStorage::failing(std::io::ErrorKind::TimedOut, u32::MAX);
+        let calls = flaky.calls.clone();
+        let storage = RetryStorage::new(flaky, quick_policy());
+        let metrics = storage.metrics();
+
+        for _ in 0..2 {
+            let err = storage.size().unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        }
+        assert!(metrics.is_open());
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        // Refused without reaching the store
+        let err = storage.size().unwrap_err();
+        assert!(
+            err.get_ref()
+                .is_some_and(|inner| inner.downcast_ref::<CircuitOpen>().is_some())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        let stats = metrics.stats();
+        assert_eq!(stats.failures, 2);
+        assert_eq!(stats.trips, 1);
+        assert_eq!(stats.rejected, 1);
+    }
+
+    #[test]
+    fn circuit_closes_once_an_operation_succeeds_after_cooldown() {
+        let flaky = FlakyStorage::failing(std::io::ErrorKind::WouldBlock, 6);
+        let storage = RetryStorage::new(
+            flaky,
+            RetryPolicy {
+                cooldown: Duration::ZERO,
+                ..quick_policy()
+            },
+        );
+        let metrics = storage.metrics();
+
+        assert!(storage.size().is_err());
+        assert!(storage.size().is_err());
+        assert_eq!(metrics.stats().trips, 1);
+
+        storage.size().unwrap();
+        assert!(!metrics.is_open());
+        assert_eq!(metrics.stats().rejected, 0);
+    }
+
+    #[test]
+    fn tree_works_over_flaky_storage() {
+        let flaky = FlakyStorage::intermittent(std::io::ErrorKind::Interrupted, 3);
+        let storage = RetryStorage::new(flaky, quick_policy());
+        let metrics = storage.metrics();
+        let mut btree =
+            BTree::<i64, i64>::with_config(storage, TreeConfig::with_page_size(256)).unwrap();
+
+        for i in 0..200 {
+            btree.insert(i, i * 2).unwrap();
+        }
+
+        for i in 0..200 {
+            assert_eq!(btree.search(i).unwrap(), i * 2);
+        }
+        let stats = metrics.stats();
+        assert!(stats.retries > 0);
+        assert_eq!(stats.recovered, stats.retries);
+        assert_eq!(stats.failures, 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Reads every page of a tree's files looking for damage, without changing anything.
+
+use crate::btree::BTree;
+use crate::error::BTreeError;
+use crate::page_manager::COLD_TIER_BIT;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScrubOptions {
+    /// Pages read per second at most, so a scrub of a tree in use leaves it room for other
+    /// work; `None` reads as fast as the storage allows.
+    pub pages_per_second: Option<u64>,
+    /// Pages checked between pauses, and by `Scrubber::run_shared` before the tree is unlocked.
+    pub batch_size: u64,
+    /// Also decode every key and value, checking the keys of each page are in order. Needs the
+    /// tree opened with the key and value types it was written with.
+    pub decode_entries: bool,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        ScrubOptions {
+            pages_per_second: None,
+            batch_size: 64,
+            decode_entries: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedPage {
+    pub page_id: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub pages_scanned: u64,
+    /// Tree pages whose checksum matched.
+    pub pages_checksummed: u64,
+    /// Tree pages written before checksums were added, whose structure alone was checked.
+    pub pages_without_checksum: u64,
+    pub damaged: Vec<DamagedPage>,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+/// What checking a single page found.
+pub(crate) enum PageScrub {
+    Checksummed,
+    WithoutChecksum,
+    // Free, metadata and never-written pages, which carry no checksum
+    Other,
+    Damaged(String),
+}
+
+/// A scrub in progress, which can be advanced a few pages at a time so the tree stays usable
+/// in between. Pages allocated after the scrub started are checked too if it has not yet
+/// passed them.
+pub struct Scrubber {
+    options: ScrubOptions,
+    // Primary pages come first, then those of the cold tier
+    next: u64,
+    report: ScrubReport,
+    started: Instant,
+}
+
+impl Scrubber {
+    pub fn new(options: ScrubOptions) -> Self {
+        Scrubber {
+            options,
+            next: 0,
+            report: ScrubReport::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Checks up to `max_pages` more pages of `tree`, returning whether every page has now been
+    /// checked. Damage is recorded in the report; only failing to read the storage is an error.
+    pub fn step<K, V>(&mut self, tree: &mut BTree<K, V>, max_pages: u64) -> Result<bool, BTreeError>
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    {
+        let (primary, cold) = tree.scrub_page_counts();
+        for _ in 0..max_pages {
+            let page_id = match self.next {
+                next if next < primary => next,
+                next if next < primary + cold => COLD_TIER_BIT | (next - primary),
+                _ => return Ok(true),
+            };
+            let (found, pages) = tree.scrub_page(page_id, self.options.decode_entries)?;
+            self.next += pages;
+            self.report.pages_scanned += pages;
+            match found {
+                PageScrub::Checksummed => self.report.pages_checksummed += 1,
+                PageScrub::WithoutChecksum => self.report.pages_without_checksum += 1,
+                PageScrub::Other => {}
+                PageScrub::Damaged(reason) => {
+                    warn!("Scrub found page {} damaged: {}", page_id, reason);
+                    self.report.damaged.push(DamagedPage { page_id, reason });
+                }
+            }
+        }
+        Ok(self.next >= primary + cold)
+    }
+
+    pub fn report(&self) -> &ScrubReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> ScrubReport {
+        self.report
+    }
+
+    /// Scrubs a tree shared with other threads, holding its lock for one batch at a time and
+    /// sleeping between batches to keep to the page rate.
+    pub fn run_shared<K, V>(mut self, tree: &Mutex<BTree<K, V>>) -> Result<ScrubReport, BTreeError>
+    where
+        K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+        V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+    {
+        loop {
+            let done = {
+                let mut tree = tree.lock().unwrap_or_else(|e| e.into_inner());
+                self.step(&mut tree, self.options.batch_size.max(1))?
+            };
+            if done {
+                return Ok(self.report);
+            }
+            self.throttle();
+        }
+    }
+
+    /// Sleeps for as long as the scrub is ahead of its page rate.
+    pub fn throttle(&self) {
+        let Some(rate) = self.options.pages_per_second.filter(|&rate| rate > 0) else {
+            return;
+        };
+        let due = Duration::from_secs_f64(self.report.pages_scanned as f64 / rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
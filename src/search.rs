@@ -0,0 +1,273 @@
+//! Finding a key among the sorted slots of a page.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Keys that map onto `u64` in the same order, so a page can guess where a key lies from its
+/// value instead of always probing the middle of the keys left to search.
+pub trait InterpolationKey {
+    /// The key's place on a line ordered like the keys themselves.
+    fn interpolation_position(&self) -> u64;
+}
+
+macro_rules! unsigned_interpolation_key {
+    ($($ty:ty),*) => {$(
+        impl InterpolationKey for $ty {
+            fn interpolation_position(&self) -> u64 {
+                *self as u64
+            }
+        }
+    )*};
+}
+
+macro_rules! signed_interpolation_key {
+    ($($ty:ty),*) => {$(
+        impl InterpolationKey for $ty {
+            // Flipping the sign bit puts negative keys below positive ones
+            fn interpolation_position(&self) -> u64 {
+                (*self as i64 as u64) ^ (1 << 63)
+            }
+        }
+    )*};
+}
+
+unsigned_interpolation_key!(u8, u16, u32, u64, usize);
+signed_interpolation_key!(i8, i16, i32, i64, isize);
+
+/// Index of the first of `len` sorted keys that `key` `goes_before`, or `len` if none.
+pub(crate) fn binary_search<K, E>(
+    len: usize,
+    key: &K,
+    read_key: impl Fn(usize) -> Result<K, E>,
+    goes_before: impl Fn(&K, &K) -> bool,
+) -> Result<usize, E> {
+    let mut left = 0;
+    let mut right = len;
+
+    while left < right {
+        let mid = left + (right - left) / 2;
+        if goes_before(key, &read_key(mid)?) {
+            right = mid;
+        } else {
+            left = mid + 1;
+        }
+    }
+
+    Ok(left)
+}
+
+/// Like [`binary_search`], but probes where `key` would lie if the keys were spread evenly
+/// between the nearest ones already read. A probe that fails to halve the keys left is
+/// followed by a binary one, so skewed keys cost at most about twice as many probes.
+pub(crate) fn interpolation_search<K, E>(
+    len: usize,
+    key: &K,
+    position: fn(&K) -> u64,
+    read_key: impl Fn(usize) -> Result<K, E>,
+    goes_before: impl Fn(&K, &K) -> bool,
+) -> Result<usize, E> {
+    if len == 0 {
+        return Ok(0);
+    }
+    let first = read_key(0)?;
+    if goes_before(key, &first) {
+        return Ok(0);
+    }
+    let last = read_key(len - 1)?;
+    if !goes_before(key, &last) {
+        return Ok(len);
+    }
+
+    // The key goes after the one at `left - 1` and before the one at `right`
+    let target = position(key);
+    let (mut left, mut right) = (1, len - 1);
+    let (mut low, mut high) = (position(&first), position(&last));
+    let mut interpolate = true;
+
+    while left < right {
+        let width = right - left;
+        let mid = match interpolate && low < high {
+            true => {
+                let offset = target.clamp(low, high) - low;
+                let span = (width + 1) as u128 * offset as u128 / (high - low) as u128;
+                (left - 1 + span as usize).clamp(left, right - 1)
+            }
+            false => left + width / 2,
+        };
+
+        let mid_key = read_key(mid)?;
+        if goes_before(key, &mid_key) {
+            right = mid;
+            high = position(&mid_key);
+        } else {
+            left = mid + 1;
+            low = position(&mid_key);
+        }
+        interpolate = !interpolate || right - left <= width / 2;
+    }
+
+    Ok(left)
+}
+
+/// Where each key of an eytzinger layout of `len` sorted keys comes from: the keys laid out
+/// breadth first as a binary search tree whose node `i` has children `2i + 1` and `2i + 2`,
+/// so the first levels a search visits sit next to each other.
+pub(crate) fn eytzinger_order(len: usize) -> Vec<usize> {
+    let mut order = vec![0; len];
+    let mut stack = Vec::new();
+    let (mut node, mut next) = (0, 0);
+    loop {
+        while node < len {
+            stack.push(node);
+            node = 2 * node + 1;
+        }
+        let Some(visited) = stack.pop() else {
+            return order;
+        };
+        order[visited] = next;
+        next += 1;
+        node = 2 * visited + 2;
+    }
+}
+
+/// Like [`binary_search`], but walks the keys in the layout given by [`eytzinger_order`], where
+/// each step goes to a child whose position is known without a branch on the comparison.
+pub(crate) fn eytzinger_search<K, E>(
+    order: &[usize],
+    key: &K,
+    read_key: impl Fn(usize) -> Result<K, E>,
+    goes_before: impl Fn(&K, &K) -> bool,
+) -> Result<usize, E> {
+    let (mut node, mut found) = (0, order.len());
+    while node < order.len() {
+        let index = order[node];
+        let before = goes_before(key, &read_key(index)?);
+        found = if before { index } else { found };
+        node = 2 * node + 1 + usize::from(!before);
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use rand::Rng;
+
+    // Position of the first key not below `key`, and how many keys were read to find it
+    fn search(keys: &[i64], key: i64, interpolate: bool) -> (usize, usize) {
+        let probes = Cell::new(0);
+        let read_key = |idx: usize| {
+            probes.set(probes.get() + 1);
+            Ok::<_, ()>(keys[idx])
+        };
+        let position = match interpolate {
+            true => interpolation_search(
+                keys.len(),
+                &key,
+                i64::interpolation_position,
+                read_key,
+                |a, b| a <= b,
+            ),
+            false => binary_search(keys.len(), &key, read_key, |a, b| a <= b),
+        };
+        (position.unwrap(), probes.get())
+    }
+
+    #[test]
+    fn eytzinger_order_is_breadth_first() {
+        assert_eq!(eytzinger_order(7), vec![3, 1, 5, 0, 2, 4, 6]);
+        assert_eq!(eytzinger_order(5), vec![3, 1, 4, 0, 2]);
+        assert!(eytzinger_order(0).is_empty());
+    }
+
+    #[test]
+    fn eytzinger_search_finds_the_same_positions_as_binary_search() {
+        let mut rng = rand::rng();
+        for _ in 0..200 {
+            let len = rng.random_range(0..64);
+            let mut keys: Vec<i64> = (0..len).map(|_| rng.random_range(-50..50)).collect();
+            keys.sort();
+            let order = eytzinger_order(keys.len());
+
+            for key in -52..52 {
+                let read_key = |idx: usize| Ok::<_, ()>(keys[idx]);
+                assert_eq!(
+                    eytzinger_search(&order, &key, read_key, |a, b| a <= b),
+                    Ok(keys.partition_point(|&k| k < key))
+                );
+                assert_eq!(
+                    eytzinger_search(&order, &key, read_key, |a, b| a < b),
+                    Ok(keys.partition_point(|&k| k <= key))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn signed_positions_keep_key_order() {
+        let keys = [i64::MIN, -1000, -1, 0, 1, 1000, i64::MAX];
+        for pair in keys.windows(2) {
+            assert!(pair[0].interpolation_position() < pair[1].interpolation_position());
+        }
+        assert!((-1i8).interpolation_position() < 0i8.interpolation_position());
+    }
+
+    #[test]
+    fn interpolation_finds_the_same_positions_as_binary_search() {
+        let mut rng = rand::rng();
+        for _ in 0..200 {
+            let len = rng.random_range(0..64);
+            let mut keys: Vec<i64> = (0..len).map(|_| rng.random_range(-50..50)).collect();
+            keys.sort();
+
+            for key in -52..52 {
+                assert_eq!(
+                    search(&keys, key, true).0,
+                    search(&keys, key, false).0,
+                    "key {} in {:?}",
+                    key,
+                    keys
+                );
+                let upper = interpolation_search(
+                    keys.len(),
+                    &key,
+                    i64::interpolation_position,
+                    |idx| Ok::<_, ()>(keys[idx]),
+                    |a, b| a < b,
+                );
+                assert_eq!(upper, Ok(keys.partition_point(|&k| k <= key)));
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_probes_less_on_evenly_spread_keys() {
+        let keys: Vec<i64> = (0..500).map(|i| i * 10).collect();
+        let probes = |interpolate| -> usize {
+            (0..5000)
+                .step_by(7)
+                .map(|key| search(&keys, key, interpolate).1)
+                .sum()
+        };
+
+        let (interpolated, binary) = (probes(true), probes(false));
+        assert!(
+            interpolated * 2 < binary,
+            "{} probes interpolating, {} binary",
+            interpolated,
+            binary
+        );
+    }
+
+    #[test]
+    fn skewed_keys_take_at_most_twice_the_binary_probes() {
+        let keys: Vec<i64> = (0..500).map(|i| i * i * i).collect();
+        for key in [1, 27, 1000, 125_000_000, keys[499] - 1] {
+            let (position, probes) = search(&keys, key, true);
+            assert_eq!(position, keys.partition_point(|&k| k < key));
+            // Two more for the first and last keys
+            assert!(probes <= 2 * 9 + 2, "{} probes for {}", probes, key);
+        }
+    }
+}
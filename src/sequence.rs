@@ -0,0 +1,174 @@
+//! Named sequences handing out increasing IDs with [`BTree::next_sequence`](crate::BTree::next_sequence),
+//! kept in the tree's stats page so they are committed along with the rest of the tree.
+//!
+//! Values are reserved in blocks of [`SEQUENCE_BLOCK`]. The end of a block is flushed before
+//! any value in it is handed out, so after a crash, or once the tree is reopened, a sequence
+//! carries on from the end of its last block: a value is never handed out twice, but the rest
+//! of that block is skipped.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Values of a sequence reserved at a time, each reservation costing a flush of the tree.
+pub const SEQUENCE_BLOCK: u64 = 1024;
+
+/// Longest name a sequence can have, in bytes.
+pub const MAX_SEQUENCE_NAME: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sequence {
+    next: u64,
+    // End of the block reserved last, and so the first value not yet durably reserved
+    reserved: u64,
+}
+
+/// The sequences of a tree. They are stored after the stats as a count of sequences (u16),
+/// then for each its name length (u8), its name and the end of its reserved block (u64), with
+/// the rest of the page zeroed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Sequences {
+    sequences: BTreeMap<String, Sequence>,
+}
+
+impl Sequences {
+    /// Hands out the next value of `name`, or `None` if it has none reserved left.
+    pub(crate) fn next(&mut self, name: &str) -> Option<u64> {
+        let sequence = self.sequences.get_mut(name)?;
+        if sequence.next == sequence.reserved {
+            return None;
+        }
+        sequence.next += 1;
+        Some(sequence.next - 1)
+    }
+
+    /// Reserves another block of values for `name`, starting it at 1 if it is new.
+    pub(crate) fn reserve(&mut self, name: &str) {
+        let sequence = self.sequences.entry(name.to_string()).or_insert(Sequence {
+            next: 1,
+            reserved: 1,
+        });
+        sequence.reserved += SEQUENCE_BLOCK;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    pub(crate) fn serialized_size(&self) -> usize {
+        2 + self
+            .sequences
+            .keys()
+            .map(|name| 1 + name.len() + 8)
+            .sum::<usize>()
+    }
+
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.serialized_size());
+        buffer.extend_from_slice(&(self.sequences.len() as u16).to_le_bytes());
+        for (name, sequence) in &self.sequences {
+            buffer.push(name.len() as u8);
+            buffer.extend_from_slice(name.as_bytes());
+            buffer.extend_from_slice(&sequence.reserved.to_le_bytes());
+        }
+        buffer
+    }
+
+    /// Reads the sequences stored at the start of `buffer`, each carrying on from the end of
+    /// its block. `None` unless they are well formed and followed only by zeroes, so a page
+    /// holding anything else is not taken for a stats page. Stats pages written before
+    /// sequences were kept hold none.
+    pub(crate) fn deserialize(buffer: &[u8]) -> Option<Self> {
+        let count = u16::from_le_bytes(buffer.get(0..2)?.try_into().unwrap());
+        let mut sequences = BTreeMap::new();
+        let mut at = 2;
+        for _ in 0..count {
+            let len = *buffer.get(at)? as usize;
+            let name = core::str::from_utf8(buffer.get(at + 1..at + 1 + len)?).ok()?;
+            at += 1 + len;
+            let reserved = u64::from_le_bytes(buffer.get(at..at + 8)?.try_into().unwrap());
+            at += 8;
+            let sequence = Sequence {
+                next: reserved,
+                reserved,
+            };
+            sequences.insert(name.to_string(), sequence);
+        }
+        buffer[at..]
+            .iter()
+            .all(|&b| b == 0)
+            .then_some(Sequences { sequences })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTree;
+    use crate::constants::SEQUENCE_VERSION;
+
+    #[test]
+    fn sequences_carry_on_past_their_reserved_block_when_reopened() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_path_buf();
+        let reopen = || {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            BTree::<u64, String>::new(file, 512).unwrap()
+        };
+
+        let mut btree = reopen();
+        btree.insert(1, "one".to_string()).unwrap();
+        for expected in 1..=SEQUENCE_BLOCK + 10 {
+            assert_eq!(btree.next_sequence("orders").unwrap(), expected);
+        }
+        assert_eq!(btree.next_sequence("users").unwrap(), 1);
+        assert!(btree.format_version() >= SEQUENCE_VERSION);
+        // A crash loses what was handed out of the current blocks, not the blocks themselves
+        core::mem::forget(btree);
+
+        let mut btree = reopen();
+        assert_eq!(
+            btree.next_sequence("orders").unwrap(),
+            2 * SEQUENCE_BLOCK + 1
+        );
+        assert_eq!(btree.next_sequence("users").unwrap(), SEQUENCE_BLOCK + 1);
+        assert_eq!(btree.search(1).unwrap(), "one");
+
+        // Clearing the tree keeps its sequences
+        btree.clear().unwrap();
+        btree.flush().unwrap();
+        drop(btree);
+        let mut btree = reopen();
+        assert_eq!(
+            btree.next_sequence("orders").unwrap(),
+            3 * SEQUENCE_BLOCK + 1
+        );
+        assert!(btree.next_sequence("").is_err());
+    }
+
+    #[test]
+    fn sequences_roundtrip_and_reject_trailing_bytes() {
+        let mut sequences = Sequences::default();
+        sequences.reserve("a");
+        sequences.reserve("b");
+        sequences.reserve("b");
+        assert_eq!(sequences.next("a"), Some(1));
+
+        let mut bytes = sequences.serialize();
+        assert_eq!(bytes.len(), sequences.serialized_size());
+        bytes.resize(64, 0);
+        let mut read = Sequences::deserialize(&bytes).unwrap();
+        assert_eq!(read.next("a"), None);
+        assert_eq!(read.next("c"), None);
+        read.reserve("b");
+        assert_eq!(read.next("b"), Some(2 * SEQUENCE_BLOCK + 1));
+
+        assert_eq!(Sequences::deserialize(&[0; 40]), Some(Sequences::default()));
+        bytes[63] = 1;
+        assert_eq!(Sequences::deserialize(&bytes), None);
+    }
+}
@@ -0,0 +1,740 @@
+use crate::btree::BTree;
+use crate::error::BTreeError;
+use crate::resp;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+
+/// The tree served over the network: UTF-8 keys mapped to opaque byte values.
+pub type ServerTree = BTree<String, Vec<u8>>;
+
+// Largest key, value or error message accepted in a frame, so a corrupt length prefix cannot
+// make the server allocate unbounded memory
+const MAX_FIELD_LENGTH: u32 = 64 * 1024 * 1024;
+
+// How often idle connections and the accept loop check for shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const OP_GET: u8 = 1;
+const OP_PUT: u8 = 2;
+const OP_DELETE: u8 = 3;
+const OP_SCAN: u8 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_VALUE: u8 = 1;
+const STATUS_NOT_FOUND: u8 = 2;
+const STATUS_ENTRIES: u8 = 3;
+const STATUS_ERROR: u8 = 4;
+
+const SCAN_HAS_START: u8 = 0b01;
+const SCAN_HAS_END: u8 = 0b10;
+
+/// A request frame. Every frame starts with a one-byte opcode; keys and values follow as a
+/// big-endian `u32` length and that many bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Get {
+        key: String,
+    },
+    Put {
+        key: String,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: String,
+    },
+    /// Entries with `start <= key < end`, at most `limit` of them. Missing bounds are open.
+    Scan {
+        start: Option<String>,
+        end: Option<String>,
+        limit: u32,
+    },
+}
+
+/// A response frame, starting with a one-byte status.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Ok,
+    Value(Vec<u8>),
+    NotFound,
+    Entries(Vec<(String, Vec<u8>)>),
+    Error(String),
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let len = read_u32(reader)?;
+    if len > MAX_FIELD_LENGTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Field too large: {} bytes", len),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+impl Request {
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Request> {
+        match read_u8(reader)? {
+            OP_GET => Ok(Request::Get {
+                key: read_string(reader)?,
+            }),
+            OP_PUT => Ok(Request::Put {
+                key: read_string(reader)?,
+                value: read_bytes(reader)?,
+            }),
+            OP_DELETE => Ok(Request::Delete {
+                key: read_string(reader)?,
+            }),
+            OP_SCAN => {
+                let flags = read_u8(reader)?;
+                let start = match flags & SCAN_HAS_START {
+                    0 => None,
+                    _ => Some(read_string(reader)?),
+                };
+                let end = match flags & SCAN_HAS_END {
+                    0 => None,
+                    _ => Some(read_string(reader)?),
+                };
+                let limit = read_u32(reader)?;
+                Ok(Request::Scan { start, end, limit })
+            }
+            op => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unknown opcode: {}", op),
+            )),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Request::Get { key } => {
+                writer.write_all(&[OP_GET])?;
+                write_bytes(writer, key.as_bytes())
+            }
+            Request::Put { key, value } => {
+                writer.write_all(&[OP_PUT])?;
+                write_bytes(writer, key.as_bytes())?;
+                write_bytes(writer, value)
+            }
+            Request::Delete { key } => {
+                writer.write_all(&[OP_DELETE])?;
+                write_bytes(writer, key.as_bytes())
+            }
+            Request::Scan { start, end, limit } => {
+                let mut flags = 0;
+                if start.is_some() {
+                    flags |= SCAN_HAS_START;
+                }
+                if end.is_some() {
+                    flags |= SCAN_HAS_END;
+                }
+                writer.write_all(&[OP_SCAN, flags])?;
+                for bound in [start, end].into_iter().flatten() {
+                    write_bytes(writer, bound.as_bytes())?;
+                }
+                writer.write_all(&limit.to_be_bytes())
+            }
+        }
+    }
+}
+
+impl Response {
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Response> {
+        match read_u8(reader)? {
+            STATUS_OK => Ok(Response::Ok),
+            STATUS_VALUE => Ok(Response::Value(read_bytes(reader)?)),
+            STATUS_NOT_FOUND => Ok(Response::NotFound),
+            STATUS_ENTRIES => {
+                let count = read_u32(reader)?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push((read_string(reader)?, read_bytes(reader)?));
+                }
+                Ok(Response::Entries(entries))
+            }
+            STATUS_ERROR => Ok(Response::Error(read_string(reader)?)),
+            status => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unknown status: {}", status),
+            )),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Response::Ok => writer.write_all(&[STATUS_OK]),
+            Response::Value(value) => {
+                writer.write_all(&[STATUS_VALUE])?;
+                write_bytes(writer, value)
+            }
+            Response::NotFound => writer.write_all(&[STATUS_NOT_FOUND]),
+            Response::Entries(entries) => {
+                writer.write_all(&[STATUS_ENTRIES])?;
+                writer.write_all(&(entries.len() as u32).to_be_bytes())?;
+                for (key, value) in entries {
+                    write_bytes(writer, key.as_bytes())?;
+                    write_bytes(writer, value)?;
+                }
+                Ok(())
+            }
+            Response::Error(message) => {
+                writer.write_all(&[STATUS_ERROR])?;
+                write_bytes(writer, message.as_bytes())
+            }
+        }
+    }
+}
+
+/// Runs `request` against `tree`. Tree errors are reported to the client rather than ending
+/// the connection.
+pub fn execute(tree: &mut ServerTree, request: Request) -> Response {
+    let result = match request {
+        Request::Get { key } => tree.search(key).map(Response::Value),
+        Request::Put { key, value } => tree.insert(key, value).map(|_| Response::Ok),
+        Request::Delete { key } => tree.delete(key).map(|_| Response::Ok),
+        Request::Scan { start, end, limit } => scan(tree, start, end, limit),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(BTreeError::KeyNotFound(_)) => Response::NotFound,
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
+
+fn scan(
+    tree: &mut ServerTree,
+    start: Option<String>,
+    end: Option<String>,
+    limit: u32,
+) -> Result<Response, BTreeError> {
+    use std::ops::Bound;
+
+    let start = start.map_or(Bound::Unbounded, Bound::Included);
+    let end = end.map_or(Bound::Unbounded, Bound::Excluded);
+    let entries = tree
+        .range((start, end))?
+        .take(limit as usize)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Response::Entries(entries))
+}
+
+/// Stops a running [`Server`] from another thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopping: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Asks the server to stop accepting connections. Requests already being handled are
+    /// answered first; `Server::run` then flushes the tree and returns.
+    pub fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+/// Wire protocol spoken by a [`Server`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The length-prefixed frames described by [`Request`] and [`Response`].
+    Binary,
+    /// A subset of the Redis protocol, so existing Redis clients can connect.
+    Resp,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(Protocol::Binary),
+            "resp" => Ok(Protocol::Resp),
+            other => Err(format!(
+                "Unknown protocol: {} (expected binary or resp)",
+                other
+            )),
+        }
+    }
+}
+
+/// Serves a tree over TCP, one thread per connection.
+pub struct Server {
+    listener: TcpListener,
+    tree: Arc<Mutex<ServerTree>>,
+    shutdown: ShutdownHandle,
+    protocol: Protocol,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, tree: ServerTree) -> std::io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        // Accept without blocking so the loop notices a shutdown request
+        listener.set_nonblocking(true)?;
+
+        Ok(Server {
+            listener,
+            tree: Arc::new(Mutex::new(tree)),
+            shutdown: ShutdownHandle {
+                stopping: Arc::new(AtomicBool::new(false)),
+            },
+            protocol: Protocol::Binary,
+        })
+    }
+
+    pub fn with_protocol(mut self, protocol: Protocol) -> Server {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts connections until shut down, then waits for open connections to finish their
+    /// current request and flushes the tree.
+    pub fn run(self) -> Result<(), BTreeError> {
+        info!("Listening on {:?}", self.listener.local_addr());
+        let mut connections: Vec<JoinHandle<()>> = Vec::new();
+
+        while !self.shutdown.is_shutting_down() {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    debug!("Accepted connection from {}", peer);
+                    let tree = self.tree.clone();
+                    let shutdown = self.shutdown.clone();
+                    let protocol = self.protocol;
+                    connections.push(std::thread::spawn(move || {
+                        if let Err(e) = serve_connection(stream, tree, shutdown, protocol) {
+                            warn!("Connection from {} failed: {}", peer, e);
+                        }
+                        debug!("Closed connection from {}", peer);
+                    }));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(e) => error!("Failed to accept connection: {}", e),
+            }
+            connections.retain(|c| !c.is_finished());
+        }
+
+        info!(
+            "Shutting down: waiting for {} connections",
+            connections.len()
+        );
+        for connection in connections {
+            if connection.join().is_err() {
+                error!("Connection thread panicked");
+            }
+        }
+
+        let mut tree = self
+            .tree
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        tree.flush()
+    }
+}
+
+// Reads into `buf`, treating read timeouts as a chance to check for shutdown. Returns false
+// if the server is stopping or the peer closed the connection before sending anything.
+fn read_or_stop(
+    stream: &mut BufReader<TcpStream>,
+    buf: &mut [u8],
+    shutdown: &ShutdownHandle,
+) -> std::io::Result<bool> {
+    loop {
+        match stream.read(buf) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                if shutdown.is_shutting_down() {
+                    return Ok(false);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    tree: Arc<Mutex<ServerTree>>,
+    shutdown: ShutdownHandle,
+    protocol: Protocol,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        // Wait for the first byte, then read the rest of the request without checking for
+        // shutdown
+        let mut first = [0u8; 1];
+        if !read_or_stop(&mut reader, &mut first, &shutdown)? {
+            return Ok(());
+        }
+        reader.get_mut().set_read_timeout(None)?;
+        let keep_open = match protocol {
+            Protocol::Binary => serve_binary(&mut first.chain(&mut reader), &mut writer, &tree),
+            Protocol::Resp => serve_resp(&mut first.chain(&mut reader), &mut writer, &tree),
+        }?;
+        writer.flush()?;
+        if !keep_open {
+            return Ok(());
+        }
+        reader.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    }
+}
+
+fn lock_tree(tree: &Mutex<ServerTree>) -> std::sync::MutexGuard<'_, ServerTree> {
+    tree.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn serve_binary<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    tree: &Mutex<ServerTree>,
+) -> std::io::Result<bool> {
+    match Request::read_from(reader) {
+        Ok(request) => {
+            debug!("Request: {:?}", request);
+            let response = execute(&mut lock_tree(tree), request);
+            response.write_to(writer)?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            // The rest of the stream cannot be trusted to be framed correctly
+            Response::Error(e.to_string()).write_to(writer)?;
+            writer.flush()?;
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn serve_resp<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    tree: &Mutex<ServerTree>,
+) -> std::io::Result<bool> {
+    match resp::read_command(reader) {
+        Ok(args) if args.is_empty() => Ok(true),
+        Ok(args) => {
+            debug!("RESP command: {} args", args.len());
+            let reply = resp::execute(&mut lock_tree(tree), &args);
+            reply.value.write_to(writer)?;
+            Ok(!reply.close)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            resp::RespValue::Error(format!("ERR {}", e)).write_to(writer)?;
+            writer.flush()?;
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Minimal blocking client for the server protocol.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    pub fn send(&mut self, request: &Request) -> std::io::Result<Response> {
+        request.write_to(&mut self.writer)?;
+        self.writer.flush()?;
+        Response::read_from(&mut self.reader)
+    }
+
+    pub fn get(&mut self, key: &str) -> std::io::Result<Response> {
+        self.send(&Request::Get {
+            key: key.to_string(),
+        })
+    }
+
+    pub fn put(&mut self, key: &str, value: &[u8]) -> std::io::Result<Response> {
+        self.send(&Request::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+        })
+    }
+
+    pub fn delete(&mut self, key: &str) -> std::io::Result<Response> {
+        self.send(&Request::Delete {
+            key: key.to_string(),
+        })
+    }
+
+    pub fn scan(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: u32,
+    ) -> std::io::Result<Response> {
+        self.send(&Request::Scan {
+            start: start.map(str::to_string),
+            end: end.map(str::to_string),
+            limit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TreeConfig;
+    use crate::storage::MemoryStorage;
+
+    fn memory_tree() -> ServerTree {
+        BTree::with_config(MemoryStorage::new(), TreeConfig::with_page_size(512)).unwrap()
+    }
+
+    fn start_server() -> (
+        SocketAddr,
+        ShutdownHandle,
+        JoinHandle<Result<(), BTreeError>>,
+    ) {
+        start_server_with(Protocol::Binary)
+    }
+
+    fn start_server_with(
+        protocol: Protocol,
+    ) -> (
+        SocketAddr,
+        ShutdownHandle,
+        JoinHandle<Result<(), BTreeError>>,
+    ) {
+        let server = Server::bind("127.0.0.1:0", memory_tree())
+            .unwrap()
+            .with_protocol(protocol);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = std::thread::spawn(move || server.run());
+        (addr, handle, thread)
+    }
+
+    fn roundtrip(request: Request) {
+        let mut buf = Vec::new();
+        request.write_to(&mut buf).unwrap();
+        assert_eq!(Request::read_from(&mut buf.as_slice()).unwrap(), request);
+    }
+
+    #[test]
+    fn request_frames_roundtrip() {
+        roundtrip(Request::Get { key: "k".into() });
+        roundtrip(Request::Put {
+            key: "k".into(),
+            value: vec![0, 1, 255],
+        });
+        roundtrip(Request::Delete { key: "".into() });
+        roundtrip(Request::Scan {
+            start: None,
+            end: Some("z".into()),
+            limit: 10,
+        });
+        roundtrip(Request::Scan {
+            start: Some("a".into()),
+            end: None,
+            limit: u32::MAX,
+        });
+    }
+
+    #[test]
+    fn response_frames_roundtrip() {
+        for response in [
+            Response::Ok,
+            Response::Value(b"value".to_vec()),
+            Response::NotFound,
+            Response::Entries(vec![("a".into(), vec![1]), ("b".into(), vec![])]),
+            Response::Error("boom".into()),
+        ] {
+            let mut buf = Vec::new();
+            response.write_to(&mut buf).unwrap();
+            assert_eq!(Response::read_from(&mut buf.as_slice()).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn oversized_field_is_rejected() {
+        let mut frame = vec![OP_GET];
+        frame.extend_from_slice(&(MAX_FIELD_LENGTH + 1).to_be_bytes());
+
+        let err = Request::read_from(&mut frame.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn execute_get_put_delete_scan() {
+        let mut tree = memory_tree();
+
+        assert_eq!(
+            execute(&mut tree, Request::Get { key: "a".into() }),
+            Response::NotFound
+        );
+        for key in ["a", "b", "c", "d"] {
+            let request = Request::Put {
+                key: key.into(),
+                value: key.as_bytes().to_vec(),
+            };
+            assert_eq!(execute(&mut tree, request), Response::Ok);
+        }
+        assert_eq!(
+            execute(&mut tree, Request::Delete { key: "b".into() }),
+            Response::Ok
+        );
+        assert_eq!(
+            execute(&mut tree, Request::Delete { key: "b".into() }),
+            Response::NotFound
+        );
+
+        let scan = Request::Scan {
+            start: Some("a".into()),
+            end: Some("d".into()),
+            limit: 10,
+        };
+        assert_eq!(
+            execute(&mut tree, scan),
+            Response::Entries(vec![
+                ("a".into(), b"a".to_vec()),
+                ("c".into(), b"c".to_vec())
+            ])
+        );
+    }
+
+    #[test_log::test]
+    fn serves_clients_over_tcp() {
+        let (addr, shutdown, thread) = start_server();
+
+        let mut client = Client::connect(addr).unwrap();
+        assert_eq!(client.put("hello", b"world").unwrap(), Response::Ok);
+        assert_eq!(
+            client.get("hello").unwrap(),
+            Response::Value(b"world".to_vec())
+        );
+
+        // A second connection sees the first one's writes
+        let mut other = Client::connect(addr).unwrap();
+        assert_eq!(
+            other.scan(None, None, 10).unwrap(),
+            Response::Entries(vec![("hello".into(), b"world".to_vec())])
+        );
+        assert_eq!(other.delete("hello").unwrap(), Response::Ok);
+        assert_eq!(client.get("hello").unwrap(), Response::NotFound);
+
+        shutdown.shutdown();
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test_log::test]
+    fn shutdown_closes_idle_connections() {
+        let (addr, shutdown, thread) = start_server();
+        let mut client = Client::connect(addr).unwrap();
+        assert_eq!(client.put("k", b"v").unwrap(), Response::Ok);
+
+        shutdown.shutdown();
+        thread.join().unwrap().unwrap();
+
+        // The server side of the connection is gone
+        assert!(client.get("k").is_err());
+    }
+
+    #[test_log::test]
+    fn malformed_frame_gets_error_response() {
+        let (addr, shutdown, thread) = start_server();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&[0xEE]).unwrap();
+        let response = Response::read_from(&mut stream).unwrap();
+        assert!(matches!(response, Response::Error(_)));
+
+        shutdown.shutdown();
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test_log::test]
+    fn serves_resp_clients() {
+        let (addr, shutdown, thread) = start_server_with(Protocol::Resp);
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut send = |command: &[u8], expected: &[u8]| {
+            writer.write_all(command).unwrap();
+            let mut reply = vec![0u8; expected.len()];
+            reader.read_exact(&mut reply).unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&reply),
+                String::from_utf8_lossy(expected)
+            );
+        };
+
+        send(b"PING\r\n", b"+PONG\r\n");
+        send(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\n", b"+OK\r\n");
+        send(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n", b"$5\r\nvalue\r\n");
+        send(
+            b"*2\r\n$4\r\nSCAN\r\n$1\r\n0\r\n",
+            b"*2\r\n$1\r\n0\r\n*1\r\n$1\r\nk\r\n",
+        );
+        send(b"*2\r\n$3\r\nDEL\r\n$1\r\nk\r\n", b":1\r\n");
+        send(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n", b"$-1\r\n");
+        send(b"QUIT\r\n", b"+OK\r\n");
+
+        shutdown.shutdown();
+        thread.join().unwrap().unwrap();
+    }
+}
@@ -1,17 +1,34 @@
-use std::fmt::Debug;
+use core::fmt::Debug;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Slot {
     pub offset: u16,
     pub key_length: u16,
     pub value_length: u16,
+    /// The value is compressed with the tree's dictionary.
+    pub compressed: bool,
+    /// The entry has been deleted but its bytes stay in place until the page is compacted.
+    pub tombstone: bool,
+    /// The value bytes in the page refer to a chain of overflow pages holding the real value.
+    pub overflow: bool,
+    /// The value bytes in the page are a `ValuePointer` to the value in the tree's value log.
+    pub external: bool,
 }
 
 impl Slot {
-    pub const SIZE: usize = 6;
+    pub const SIZE: usize = 7;
 
-    pub fn total_length(&self) -> u16 {
-        self.key_length + self.value_length
+    const TOMBSTONE_FLAG: u8 = 0x01;
+    const OVERFLOW_FLAG: u8 = 0x02;
+    const COMPRESSED_FLAG: u8 = 0x04;
+    const EXTERNAL_FLAG: u8 = 0x08;
+
+    pub const MAX_VALUE_LENGTH: u16 = u16::MAX;
+
+    /// Bytes the key and value take up in the page, in usize as the lengths read from a
+    /// damaged page can sum past `u16::MAX`.
+    pub fn total_length(&self) -> usize {
+        self.key_length as usize + self.value_length as usize
     }
 
     pub fn serialize(&self) -> [u8; Self::SIZE] {
@@ -19,27 +36,37 @@ impl Slot {
         buffer[0..2].copy_from_slice(&self.offset.to_le_bytes());
         buffer[2..4].copy_from_slice(&self.key_length.to_le_bytes());
         buffer[4..6].copy_from_slice(&self.value_length.to_le_bytes());
+        buffer[6] = self.flags();
 
         buffer
     }
 
+    fn flags(&self) -> u8 {
+        [
+            (self.tombstone, Self::TOMBSTONE_FLAG),
+            (self.overflow, Self::OVERFLOW_FLAG),
+            (self.compressed, Self::COMPRESSED_FLAG),
+            (self.external, Self::EXTERNAL_FLAG),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag)
+    }
+
     pub fn deserialize(buffer: &[u8]) -> Self {
         let offset = u16::from_le_bytes(buffer[0..2].try_into().unwrap());
         let key_length = u16::from_le_bytes(buffer[2..4].try_into().unwrap());
         let value_length = u16::from_le_bytes(buffer[4..6].try_into().unwrap());
+        let flags = buffer[6];
 
         Slot {
-            offset: offset,
-            key_length: key_length,
-            value_length: value_length,
-        }
-    }
-
-    pub fn clone(&self) -> Self {
-        Slot {
-            offset: self.offset,
-            key_length: self.key_length,
-            value_length: self.value_length,
+            offset,
+            key_length,
+            value_length,
+            compressed: flags & Self::COMPRESSED_FLAG != 0,
+            tombstone: flags & Self::TOMBSTONE_FLAG != 0,
+            overflow: flags & Self::OVERFLOW_FLAG != 0,
+            external: flags & Self::EXTERNAL_FLAG != 0,
         }
     }
 }
@@ -54,6 +81,10 @@ mod tests {
             offset: 100,
             key_length: 50,
             value_length: 200,
+            compressed: false,
+            tombstone: false,
+            overflow: false,
+            external: false,
         };
 
         let bytes = slot.serialize();
@@ -69,7 +100,11 @@ mod tests {
         let slot = Slot {
             offset: u16::MAX,
             key_length: u16::MAX,
-            value_length: u16::MAX,
+            value_length: Slot::MAX_VALUE_LENGTH,
+            compressed: true,
+            tombstone: false,
+            overflow: false,
+            external: false,
         };
 
         let bytes = slot.serialize();
@@ -77,7 +112,8 @@ mod tests {
 
         assert_eq!(restored.offset, u16::MAX);
         assert_eq!(restored.key_length, u16::MAX);
-        assert_eq!(restored.value_length, u16::MAX);
+        assert_eq!(restored.value_length, Slot::MAX_VALUE_LENGTH);
+        assert!(restored.compressed);
     }
 
     #[test]
@@ -86,6 +122,10 @@ mod tests {
             offset: 0,
             key_length: 0,
             value_length: 0,
+            compressed: false,
+            tombstone: false,
+            overflow: false,
+            external: false,
         };
 
         let bytes = slot.serialize();
@@ -96,16 +136,111 @@ mod tests {
         assert_eq!(restored.value_length, 0);
     }
 
+    #[test]
+    fn slot_compressed_flag_does_not_leak_into_length() {
+        let slot = Slot {
+            offset: 12,
+            key_length: 8,
+            value_length: 300,
+            compressed: true,
+            tombstone: false,
+            overflow: false,
+            external: false,
+        };
+
+        let bytes = slot.serialize();
+        let restored = Slot::deserialize(&bytes);
+
+        assert_eq!(restored.value_length, 300);
+        assert!(restored.compressed);
+        assert_eq!(restored.total_length(), 308);
+    }
+
+    #[test]
+    fn slot_tombstone_roundtrip() {
+        let slot = Slot {
+            offset: 40,
+            key_length: 8,
+            value_length: 16,
+            compressed: true,
+            tombstone: true,
+            overflow: false,
+            external: false,
+        };
+
+        let restored = Slot::deserialize(&slot.serialize());
+
+        assert!(restored.tombstone);
+        assert!(restored.compressed);
+        assert_eq!(restored.value_length, 16);
+        assert!(!Slot::deserialize(&[0u8; Slot::SIZE]).tombstone);
+    }
+
+    #[test]
+    fn slot_flags_are_independent() {
+        let slot = Slot {
+            offset: 0,
+            key_length: 1,
+            value_length: 2,
+            compressed: false,
+            tombstone: false,
+            overflow: true,
+            external: false,
+        };
+
+        let bytes = slot.serialize();
+        let restored = Slot::deserialize(&bytes);
+
+        assert_eq!(bytes[6], 0x02);
+        assert!(restored.overflow);
+        assert!(!restored.compressed);
+        assert!(!restored.tombstone);
+        assert!(!restored.external);
+
+        let external = Slot::deserialize(
+            &Slot {
+                overflow: false,
+                external: true,
+                ..slot
+            }
+            .serialize(),
+        );
+        assert!(external.external);
+        assert!(!external.overflow);
+    }
+
+    #[test]
+    fn slot_value_length_uses_all_bits() {
+        let slot = Slot {
+            offset: 0,
+            key_length: 0,
+            value_length: 0x8001,
+            compressed: false,
+            tombstone: false,
+            overflow: false,
+            external: false,
+        };
+
+        let restored = Slot::deserialize(&slot.serialize());
+
+        assert_eq!(restored.value_length, 0x8001);
+        assert!(!restored.compressed);
+    }
+
     #[test]
     fn slot_size_is_correct() {
         let slot = Slot {
             offset: 0,
             key_length: 0,
             value_length: 0,
+            compressed: false,
+            tombstone: false,
+            overflow: false,
+            external: false,
         };
 
         assert_eq!(slot.serialize().len(), Slot::SIZE);
-        assert_eq!(Slot::SIZE, 6);
+        assert_eq!(Slot::SIZE, 7);
     }
 
     #[test]
@@ -114,6 +249,10 @@ mod tests {
             offset: 0,
             key_length: 10,
             value_length: 20,
+            compressed: false,
+            tombstone: false,
+            overflow: false,
+            external: false,
         };
 
         assert_eq!(slot.total_length(), 30);
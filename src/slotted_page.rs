@@ -1,21 +1,55 @@
-use std::marker::PhantomData;
-
-use crate::free_space::FreeSpaceRegion;
+//! The page format every tree page is stored in: a header and a directory of slots growing
+//! from the start of the page, and the entries they point at growing from its end.
+//!
+//! Pages can also be used on their own, for other structures wanting the same format. Build
+//! one with [`SlottedPage::builder`], add entries in key order with
+//! [`SlottedPage::insert_sorted`], look them up with [`SlottedPage::get`] or walk them with
+//! [`SlottedPage::iter`], and store it with [`SlottedPage::serialize`] and
+//! [`SlottedPage::from_buffer`]. Page ids and child pointers mean nothing to the page itself,
+//! and a page read back needs any compressor or value log its values use set again.
+
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use core::marker::PhantomData;
+
+use crate::checksum::crc32;
+use crate::codec::{Codecs, Encoding, SerializationError};
+use crate::compression::{CompressionError, ValueCompressor};
+use crate::error::BTreeError;
+use crate::free_space::{DEFAULT_MAX_FREE_REGIONS, FitPolicy, FreeSpaceRegion};
+use crate::search;
 use crate::slot::Slot;
 use crate::types::NodeType;
-use crate::{error::BTreeError, header::Header};
+use crate::value_log::{ValueLog, ValueLogError, ValuePointer};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
 use log::trace;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
 
 #[derive(Debug)]
 pub enum SlottedPageError {
-    Io(std::io::Error),
-    Serialization(bincode::Error),
-    InvalidBufferSize { expected: usize, got: usize },
+    Io(crate::io::Error),
+    Serialization(SerializationError),
+    InvalidBufferSize {
+        expected: usize,
+        got: usize,
+    },
+    OverflowValue {
+        page_id: u64,
+        index: usize,
+    },
+    CorruptedData(String),
+    ChecksumMismatch {
+        page_id: u64,
+        expected: u32,
+        got: u32,
+    },
 }
-impl std::fmt::Display for SlottedPageError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for SlottedPageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             SlottedPageError::Io(e) => {
                 write!(f, "IO error: {}", e)
@@ -26,16 +60,54 @@ impl std::fmt::Display for SlottedPageError {
             SlottedPageError::InvalidBufferSize { expected, got } => {
                 write!(f, "Invalid buffer size: expected {}, got {}", expected, got)
             }
+            SlottedPageError::OverflowValue { page_id, index } => {
+                write!(
+                    f,
+                    "Value at slot {} of page {} is stored in overflow pages, which this version cannot read",
+                    index, page_id
+                )
+            }
+            SlottedPageError::CorruptedData(reason) => {
+                write!(f, "Corrupted page: {}", reason)
+            }
+            SlottedPageError::ChecksumMismatch {
+                page_id,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "Checksum mismatch on page {}: expected {:#010x}, got {:#010x}",
+                    page_id, expected, got
+                )
+            }
         }
     }
 }
 
-impl From<std::io::Error> for SlottedPageError {
-    fn from(err: std::io::Error) -> SlottedPageError {
+impl From<crate::io::Error> for SlottedPageError {
+    fn from(err: crate::io::Error) -> SlottedPageError {
         SlottedPageError::Io(err)
     }
 }
 
+// A value as it will be stored in a page
+enum EncodedValue {
+    // The bytes to store and whether they are compressed
+    Inline(Vec<u8>, bool),
+    // The serialized value, to be appended to the value log with a pointer stored in its place
+    External(Vec<u8>),
+}
+
+impl EncodedValue {
+    fn stored_len(&self) -> usize {
+        match self {
+            EncodedValue::Inline(bytes, _) => bytes.len(),
+            EncodedValue::External(_) => ValuePointer::SIZE,
+        }
+    }
+}
+
 pub struct SlottedPage<K, V> {
     pub page_id: u64,
     pub node_type: NodeType,
@@ -45,8 +117,30 @@ pub struct SlottedPage<K, V> {
     pub total_free: u16, // total free bytes (contiguous + holes)
     pub slots: Vec<Slot>,
     pub pointers: Vec<u64>,
+    /// Entries beneath each child in `pointers`, kept only by internal nodes of trees with
+    /// subtree counts enabled.
+    pub counts: Vec<u64>,
+    counted: bool,
+    // Pages written before checksums were added have none, and keep the shorter header
+    checksummed: bool,
     data: Vec<u8>,
     page_size: usize,
+    codecs: Codecs<V>,
+    compressor: Option<Arc<ValueCompressor>>,
+    value_log: Option<Arc<ValueLog>>,
+    // Serialized values longer than this go to the value log; 0 keeps every value in the page
+    value_log_threshold: usize,
+    // Maps keys onto integers in key order, for finding them by interpolation search
+    interpolator: Option<fn(&K) -> u64>,
+    // Whether the slot directory is written in eytzinger order
+    eytzinger: bool,
+    // The eytzinger order the slots were read in, searched while the page is unchanged
+    eytzinger_order: Vec<usize>,
+    // Which free region new entries go in, and how many regions are kept before compacting
+    fit_policy: FitPolicy,
+    max_free_regions: usize,
+    // Whether the page differs from what was last read from disk
+    dirty: bool,
 
     _phantom_data: PhantomData<(K, V)>,
 }
@@ -57,8 +151,16 @@ where
     V: Debug + Serialize + for<'de> Deserialize<'de>,
 {
     // page_id(8) + node_type(1) + num_keys(2) + free_space_end(2) + free_list_count(2) +
-    // total_free(2)
-    const HEADER_SIZE: usize = 17;
+    // total_free(2) + flags(1), followed by the checksum on pages that have one
+    const HEADER_SIZE: usize = 18;
+
+    const COUNTED_FLAG: u8 = 0x01;
+    const CHECKSUM_FLAG: u8 = 0x02;
+    const EYTZINGER_FLAG: u8 = 0x04;
+
+    // CRC-32 of the whole page, computed with these bytes zeroed, stored after the header of
+    // pages that carry the checksum flag
+    const CHECKSUM_SIZE: usize = 4;
 
     pub fn new(page_id: u64, node_type: NodeType, page_size: usize) -> Self {
         SlottedPage {
@@ -67,17 +169,224 @@ where
             num_keys: 0,
             free_space_end: page_size as u16,
             free_list: Vec::new(),
-            total_free: page_size as u16 - Self::HEADER_SIZE as u16,
+            total_free: (page_size - Self::HEADER_SIZE - Self::CHECKSUM_SIZE) as u16,
             slots: Vec::new(),
             pointers: Vec::new(),
+            counts: Vec::new(),
+            counted: false,
+            checksummed: true,
             data: vec![0; page_size],
-            page_size: page_size,
+            page_size,
+            codecs: Codecs::default(),
+            compressor: None,
+            value_log: None,
+            value_log_threshold: 0,
+            interpolator: None,
+            eytzinger: false,
+            eytzinger_order: Vec::new(),
+            fit_policy: FitPolicy::default(),
+            max_free_regions: DEFAULT_MAX_FREE_REGIONS,
+            dirty: true,
             _phantom_data: PhantomData,
         }
     }
 
+    // How keys and values are encoded, which must be what they were written with to read them
+    pub(crate) fn set_codecs(&mut self, codecs: Codecs<V>) {
+        self.codecs = codecs;
+    }
+
+    pub(crate) fn codecs(&self) -> &Codecs<V> {
+        &self.codecs
+    }
+
+    /// Values inserted from now on are compressed with `compressor` when that makes them smaller.
+    /// Values already compressed in this page can only be read while a compressor is set.
+    pub fn set_compressor(&mut self, compressor: Option<Arc<ValueCompressor>>) {
+        self.compressor = compressor;
+    }
+
+    /// Values inserted from now on whose serialized form is longer than `threshold` bytes are
+    /// appended to `value_log`, leaving only a pointer in the page; a threshold of 0 keeps every
+    /// value in the page. Values already in the log can only be read while it is set.
+    pub fn set_value_log(&mut self, value_log: Option<Arc<ValueLog>>, threshold: u16) {
+        self.value_log = value_log;
+        self.value_log_threshold = threshold as usize;
+    }
+
+    /// Finds keys by interpolating between them using `interpolator`, which must map keys onto
+    /// integers in the same order, rather than by binary search.
+    pub fn set_interpolator(&mut self, interpolator: Option<fn(&K) -> u64>) {
+        self.interpolator = interpolator;
+    }
+
+    /// Writes the slot directory breadth first, as a binary search tree over the keys, rather
+    /// than in key order. Reading such a page back restores key order, and searches of it
+    /// follow the tree until it is changed. Builds from before this layout misread such pages.
+    pub fn set_eytzinger(&mut self, eytzinger: bool) {
+        self.eytzinger = eytzinger;
+    }
+
+    pub fn is_eytzinger(&self) -> bool {
+        self.eytzinger
+    }
+
+    /// Places new entries in free regions by `policy`, and compacts the page once freeing
+    /// space would leave it with more than `max_regions` regions, each of which takes up room
+    /// in the page as well as time to search.
+    pub fn set_free_list_policy(&mut self, policy: FitPolicy, max_regions: usize) {
+        self.fit_policy = policy;
+        self.max_free_regions = max_regions;
+    }
+
+    /// Whether the page has changed since it was deserialized. New pages start out dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Bytes the page takes up once serialized.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Records a change made through the public fields, such as `pointers` or `page_id`, which
+    /// the page cannot see for itself.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Whether this page stores the number of entries beneath each of its children.
+    pub fn is_counted(&self) -> bool {
+        self.counted
+    }
+
+    /// Whether the page carries a checksum of its contents. Every page created by this version
+    /// does; pages read from files written before checksums were added do not.
+    pub fn is_checksummed(&self) -> bool {
+        self.checksummed
+    }
+
+    /// Starts or stops checksumming the page. Only an empty page can change, as the checksum
+    /// takes up room at the start of the page.
+    pub fn set_checksummed(&mut self, checksummed: bool) {
+        debug_assert!(self.slots.is_empty() && self.pointers.is_empty());
+        if self.checksummed != checksummed {
+            self.checksummed = checksummed;
+            self.total_free = (self.free_space_end as usize - self.header_size()) as u16;
+            self.dirty = true;
+        }
+        self.audited();
+    }
+
+    // Bytes before the slots, including the checksum if the page has one
+    fn header_size(&self) -> usize {
+        match self.checksummed {
+            true => Self::HEADER_SIZE + Self::CHECKSUM_SIZE,
+            false => Self::HEADER_SIZE,
+        }
+    }
+
+    /// Starts or stops keeping subtree counts. Counts are only kept by internal nodes; any
+    /// added here start at zero for the caller to fill in.
+    pub fn set_counted(&mut self, counted: bool) {
+        self.counted = counted && self.node_type == NodeType::INTERNAL;
+        self.counts
+            .resize(if self.counted { self.pointers.len() } else { 0 }, 0);
+        self.dirty = true;
+    }
+
+    /// Live entries in this page and, for a counted internal node, every subtree beneath it.
+    pub fn subtree_entries(&self) -> u64 {
+        let live = self.slots.iter().filter(|slot| !slot.tombstone).count() as u64;
+        live + self.counts.iter().sum::<u64>()
+    }
+
+    /// Inserts `child` at `idx` of `pointers`, with `entries` as its count if counts are kept.
+    pub fn insert_pointer(&mut self, idx: usize, child: u64, entries: u64) {
+        self.pointers.insert(idx, child);
+        if self.counted {
+            self.counts.insert(idx, entries);
+        }
+        self.dirty = true;
+    }
+
+    /// Removes the child pointer at `idx`, returning it with its count (0 if none is kept).
+    pub fn remove_pointer(&mut self, idx: usize) -> (u64, u64) {
+        self.dirty = true;
+        let child = self.pointers.remove(idx);
+        match self.counted {
+            true => (child, self.counts.remove(idx)),
+            false => (child, 0),
+        }
+    }
+
+    /// Changes the count of the child at `idx` by `delta`. Does nothing if counts are not kept.
+    pub fn adjust_count(&mut self, idx: usize, delta: i64) {
+        if self.counted && delta != 0 {
+            self.counts[idx] = self.counts[idx].saturating_add_signed(delta);
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the count of the child at `idx`. Does nothing if counts are not kept.
+    pub fn set_count(&mut self, idx: usize, entries: u64) {
+        if self.counted && self.counts[idx] != entries {
+            self.counts[idx] = entries;
+            self.dirty = true;
+        }
+    }
+
+    // Bytes taken by each child pointer, including its count if one is kept
+    fn pointer_size(&self) -> usize {
+        match self.counted {
+            true => 16,
+            false => 8,
+        }
+    }
+
+    /// Largest entry, key and stored value together, of which an empty internal node of
+    /// `page_size` bytes can hold two alongside the pointers between them. Splits move an
+    /// entry up into the parent, and an internal node must keep an entry either side of the
+    /// one it promotes, so no larger entry can be stored.
+    pub fn max_entry_len(page_size: usize, checksummed: bool, counted: bool) -> usize {
+        let header_size = match checksummed {
+            true => Self::HEADER_SIZE + Self::CHECKSUM_SIZE,
+            false => Self::HEADER_SIZE,
+        };
+        let pointer_size = match counted {
+            true => 16,
+            false => 8,
+        };
+        page_size.saturating_sub(header_size + 2 * Slot::SIZE + 3 * pointer_size) / 2
+    }
+
     pub fn should_compact(&self) -> bool {
-        self.fragmentation_ratio() > 0.3
+        self.has_tombstones() || self.fragmentation_ratio() > 0.3
+    }
+
+    pub fn has_tombstones(&self) -> bool {
+        self.slots.iter().any(|slot| slot.tombstone)
+    }
+
+    /// Bytes held by tombstoned entries, which the next compaction gives back.
+    pub fn tombstoned_bytes(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.tombstone)
+            .map(|slot| Slot::SIZE + slot.total_length())
+            .sum()
+    }
+
+    pub fn is_tombstoned(&self, index: usize) -> bool {
+        self.slots[index].tombstone
+    }
+
+    /// Marks the entry at `index` deleted without freeing its space. Only leaf entries may be
+    /// tombstoned: an internal entry also separates two children.
+    pub fn tombstone(&mut self, index: usize) {
+        debug_assert_eq!(self.node_type, NodeType::LEAF);
+        self.slots[index].tombstone = true;
+        self.dirty = true;
     }
 
     pub fn fragmentation_ratio(&self) -> f32 {
@@ -95,26 +404,214 @@ where
         hole_space as f32 / total_free as f32
     }
 
-    fn get_free_space(&self) -> usize {
-        let used_at_start =
-            Self::HEADER_SIZE + (self.slots.len() * Slot::SIZE) + (self.pointers.len() * 8);
-        let used_at_end = self.page_size - self.free_space_end as usize;
-        self.page_size.saturating_sub(used_at_start + used_at_end)
+    // Every byte of a page is in exactly one of the metadata at its start (the header, slots,
+    // pointers with their counts and the free list), the entries at its end, the free regions
+    // between those entries, or the contiguous gap in the middle. Entries may go in the gap
+    // or a free region, but the metadata can only grow into the gap.
+
+    // Bytes the metadata takes up, all of which are written before the data
+    fn metadata_size(&self) -> usize {
+        self.header_size()
+            + self.slots.len() * Slot::SIZE
+            + self.pointers.len() * self.pointer_size()
+            + self.free_list.len() * FreeSpaceRegion::SIZE
     }
 
-    pub fn can_insert(&self, key_len: usize, value_len: usize) -> bool {
-        let needed = Slot::SIZE + key_len + value_len;
-        let needed = match self.node_type {
-            NodeType::LEAF => needed,
-            NodeType::INTERNAL => needed + 8, // child pointer
+    // Bytes the metadata grows by with one more entry: its slot and, on an internal node, the
+    // pointers still owed for it and the keys already there, which are added after the key
+    fn entry_overhead(&self) -> usize {
+        let owed_pointers = match self.node_type {
+            NodeType::INTERNAL => (self.slots.len() + 2).saturating_sub(self.pointers.len()),
+            _ => 0,
+        };
+        Slot::SIZE + owed_pointers * self.pointer_size()
+    }
+
+    /// Bytes free between the slot directory and the entries, which a new entry and its slot
+    /// must fit in unless they fit a hole left by an earlier one.
+    pub fn free_space(&self) -> usize {
+        (self.free_space_end as usize).saturating_sub(self.metadata_size())
+    }
+
+    /// Recomputes the page's free space from its slots and checks it against the counters kept
+    /// as entries come and go: the entries and free regions must tile the data area below the
+    /// page end without overlapping, with `total_free` counting the gap and the free regions
+    /// and the metadata ending before the data starts.
+    #[cfg(any(test, feature = "audit"))]
+    pub fn audit_free_space(&self) -> Result<(), SlottedPageError> {
+        let fail = |reason: String| {
+            Err(SlottedPageError::CorruptedData(format!(
+                "page {}: {}",
+                self.page_id, reason
+            )))
         };
+        if self.num_keys as usize != self.slots.len() {
+            return fail(format!(
+                "{} keys counted for {} slots",
+                self.num_keys,
+                self.slots.len()
+            ));
+        }
+
+        let mut regions: Vec<(usize, usize)> = self
+            .slots
+            .iter()
+            .map(|slot| (slot.offset as usize, slot.total_length()))
+            .chain(
+                self.free_list
+                    .iter()
+                    .map(|region| (region.offset as usize, region.length as usize)),
+            )
+            .collect();
+        regions.sort_unstable();
+        let mut expected_start = self.free_space_end as usize;
+        for (offset, length) in regions {
+            if offset != expected_start {
+                return fail(format!(
+                    "data area has a gap or overlap at {}, expected {}",
+                    offset, expected_start
+                ));
+            }
+            expected_start = offset + length;
+        }
+        if expected_start != self.page_size {
+            return fail(format!(
+                "data area ends at {} rather than the page end {}",
+                expected_start, self.page_size
+            ));
+        }
+
+        let holes: usize = self.free_list.iter().map(|r| r.length as usize).sum();
+        let gap = (self.free_space_end as usize).checked_sub(self.header_size());
+        if gap.map(|gap| gap + holes) != Some(self.total_free as usize) {
+            return fail(format!(
+                "total_free is {} but the free space ends at {} with {} bytes of holes",
+                self.total_free, self.free_space_end, holes
+            ));
+        }
+
+        let metadata_end = self.metadata_size();
+        if metadata_end > self.free_space_end as usize {
+            return fail(format!(
+                "metadata runs to {}, past the data start {}",
+                metadata_end, self.free_space_end
+            ));
+        }
+        Ok(())
+    }
+
+    // Run after every change to a page's entries in tests and with the `audit` feature
+    #[cfg(any(test, feature = "audit"))]
+    fn audited(&self) {
+        if let Err(e) = self.audit_free_space() {
+            panic!("free space audit failed: {}", e);
+        }
+    }
+
+    #[cfg(not(any(test, feature = "audit")))]
+    fn audited(&self) {}
+
+    /// Whether an entry of this size fits in the contiguous free space, along with the
+    /// metadata it brings. Free regions are left out, as an entry only fits in one if it is
+    /// small enough; compacting the page merges them into the contiguous space.
+    pub fn can_insert(&self, key_len: usize, value_len: usize) -> bool {
+        self.free_space() >= key_len + value_len + self.entry_overhead()
+    }
+
+    fn encode_value(&self, value: &V) -> Result<EncodedValue, BTreeError> {
+        let value_bytes = self.codecs.encode_value(value)?;
+        if self.value_log_threshold > 0 && value_bytes.len() > self.value_log_threshold {
+            return Ok(EncodedValue::External(value_bytes));
+        }
+        if let Some(compressor) = &self.compressor
+            && let Some(compressed) = compressor.compress_if_smaller(&value_bytes)?
+        {
+            return Ok(EncodedValue::Inline(compressed, true));
+        }
+        Ok(EncodedValue::Inline(value_bytes, false))
+    }
+
+    // The bytes to store in the page for `value`, appending it to the value log first if it
+    // goes there, and whether they are compressed and a pointer into the log
+    fn store_value(
+        &self,
+        key_bytes: &[u8],
+        value: EncodedValue,
+    ) -> Result<(Vec<u8>, bool, bool), BTreeError> {
+        match value {
+            EncodedValue::Inline(bytes, compressed) => Ok((bytes, compressed, false)),
+            EncodedValue::External(bytes) => {
+                let value_log = self.value_log.as_ref().ok_or(ValueLogError::NotAttached)?;
+                let pointer = value_log.append(key_bytes, &bytes)?;
+                Ok((pointer.to_bytes().to_vec(), false, true))
+            }
+        }
+    }
+
+    /// Returns the number of bytes `key` and `value` will occupy once stored in this page.
+    pub fn encoded_len(&self, key: &K, value: &V) -> Result<(usize, usize), BTreeError> {
+        let key_len = self.codecs.encoding().serialized_size(key)? as usize;
+        Ok((key_len, self.encode_value(value)?.stored_len()))
+    }
+
+    /// Percentage of the usable page occupied by live entries, their slots and pointers,
+    /// ignoring holes left behind by deletes and shrinking updates, and tombstoned entries.
+    pub fn live_occupancy(&self) -> u8 {
+        let live: usize = self
+            .slots
+            .iter()
+            .filter(|slot| !slot.tombstone)
+            .map(|slot| Slot::SIZE + slot.total_length())
+            .sum::<usize>()
+            + self.pointers.len() * self.pointer_size();
+        let usable = self.page_size - self.header_size();
+        (live * 100 / usable).min(100) as u8
+    }
+
+    /// Whether the entry at `pos` can be replaced by one of this size, once the page is
+    /// compacted if need be.
+    pub fn can_replace(&self, pos: usize, key_len: usize, value_len: usize) -> bool {
+        let reclaimable = self
+            .free_list
+            .iter()
+            .map(|region| region.length as usize)
+            .sum::<usize>()
+            + self.tombstoned_bytes();
+        key_len + value_len <= self.slots[pos].total_length() + self.free_space() + reclaimable
+    }
+
+    /// Whether an entry of this size fits while keeping the page at most `fill_factor` percent
+    /// full. An empty page always accepts an entry that fits at all.
+    pub fn fits_within(&self, key_len: usize, value_len: usize, fill_factor: u8) -> bool {
+        if !self.can_insert(key_len, value_len) {
+            return false;
+        }
+        if self.num_keys == 0 {
+            return true;
+        }
 
-        let free_space = self.get_free_space();
-        free_space >= needed
+        let needed = key_len + value_len + self.entry_overhead();
+        let usable = self.page_size - self.header_size();
+        let used = usable - self.free_space() + needed;
+        used * 100 <= usable * fill_factor as usize
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>, SlottedPageError> {
         let mut buffer = vec![0u8; self.page_size];
+        self.serialize_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Like `serialize`, but writes into `buffer`, which must be `page_size` bytes long and
+    /// may hold anything beforehand.
+    pub fn serialize_into(&self, buffer: &mut [u8]) -> Result<(), SlottedPageError> {
+        if buffer.len() != self.page_size {
+            return Err(SlottedPageError::InvalidBufferSize {
+                expected: self.page_size,
+                got: buffer.len(),
+            });
+        }
+        buffer.fill(0);
         let mut offset = 0;
 
         // header
@@ -136,8 +633,31 @@ where
         buffer[offset..offset + 2].copy_from_slice(&self.total_free.to_le_bytes());
         offset += 2;
 
-        self.slots.iter().for_each(|slot| {
-            buffer[offset..offset + Slot::SIZE].copy_from_slice(&slot.serialize());
+        let mut flags = 0;
+        if self.counted {
+            flags |= Self::COUNTED_FLAG;
+        }
+        if self.checksummed {
+            flags |= Self::CHECKSUM_FLAG;
+        }
+        if self.eytzinger {
+            flags |= Self::EYTZINGER_FLAG;
+        }
+        buffer[offset] = flags;
+        offset += 1;
+
+        // Filled in once the rest of the page is written
+        let checksum_offset = offset;
+        if self.checksummed {
+            offset += Self::CHECKSUM_SIZE;
+        }
+
+        let order = match self.eytzinger {
+            true => search::eytzinger_order(self.slots.len()),
+            false => (0..self.slots.len()).collect(),
+        };
+        order.iter().for_each(|&index| {
+            buffer[offset..offset + Slot::SIZE].copy_from_slice(&self.slots[index].serialize());
             offset += Slot::SIZE;
         });
 
@@ -146,6 +666,15 @@ where
             offset += 8
         });
 
+        debug_assert_eq!(
+            self.counts.len(),
+            if self.counted { self.pointers.len() } else { 0 }
+        );
+        self.counts.iter().for_each(|count| {
+            buffer[offset..offset + 8].copy_from_slice(&count.to_le_bytes());
+            offset += 8
+        });
+
         self.free_list.iter().for_each(|r| {
             buffer[offset..offset + FreeSpaceRegion::SIZE].copy_from_slice(&r.serialize());
             offset += FreeSpaceRegion::SIZE;
@@ -168,60 +697,179 @@ where
 
         buffer[data_start..].copy_from_slice(&self.data[data_start..]);
 
-        Ok(buffer)
+        if self.checksummed {
+            let checksum = crc32(buffer);
+            buffer[checksum_offset..checksum_offset + Self::CHECKSUM_SIZE]
+                .copy_from_slice(&checksum.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Decodes a page written by `serialize`. A page with a checksum that does not match its
+    /// contents is reported as `ChecksumMismatch`. Every offset and length the page records is
+    /// also checked against `page_size`, so a damaged page is reported as `CorruptedData` rather
+    /// than read out of bounds later.
+    pub fn deserialize(buffer: &[u8], page_size: usize) -> Result<Self, SlottedPageError> {
+        if buffer.len() != page_size {
+            return Err(SlottedPageError::InvalidBufferSize {
+                expected: page_size.max(Self::HEADER_SIZE + Self::CHECKSUM_SIZE),
+                got: buffer.len(),
+            });
+        }
+        Self::from_buffer(buffer.to_vec())
     }
 
-    pub fn deserialize(buffer: &[u8], page_size: usize) -> Self {
+    /// Like `deserialize`, but keeps `buffer`, a whole page, as the page's data instead of
+    /// copying it, for callers that are done with the buffer once it is decoded.
+    pub fn from_buffer(mut data: Vec<u8>) -> Result<Self, SlottedPageError> {
+        let page_size = data.len();
+        let min_size = Self::HEADER_SIZE + Self::CHECKSUM_SIZE;
+        if page_size < min_size {
+            return Err(SlottedPageError::InvalidBufferSize {
+                expected: min_size,
+                got: page_size,
+            });
+        }
+        let corrupted = |reason: String| SlottedPageError::CorruptedData(reason);
         let mut offset = 0;
 
         // header
-        let page_id = u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap());
+        let page_id = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
         offset += 8;
 
-        let node_type = NodeType::from(buffer[offset]);
+        let node_type = NodeType::from_byte(data[offset])
+            .ok_or_else(|| corrupted(format!("unknown node type {}", data[offset])))?;
         offset += 1;
 
-        let num_keys = u16::from_le_bytes(buffer[offset..offset + 2].try_into().unwrap());
+        let num_keys = u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
         offset += 2;
 
-        let free_space_end = u16::from_le_bytes(buffer[offset..offset + 2].try_into().unwrap());
+        let free_space_end = u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
         offset += 2;
 
-        let free_list_count = u16::from_le_bytes(buffer[offset..offset + 2].try_into().unwrap());
+        let free_list_count = u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
         offset += 2;
 
-        let total_free = u16::from_le_bytes(buffer[offset..offset + 2].try_into().unwrap());
+        let total_free = u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
         offset += 2;
 
-        let mut slots = Vec::new();
-        for _ in 0..num_keys {
-            slots.push(Slot::deserialize(&buffer[offset..offset + Slot::SIZE]));
-            offset += Slot::SIZE;
+        let counted = data[offset] & Self::COUNTED_FLAG != 0 && node_type == NodeType::INTERNAL;
+        let checksummed = data[offset] & Self::CHECKSUM_FLAG != 0;
+        let eytzinger = data[offset] & Self::EYTZINGER_FLAG != 0;
+        offset += 1;
+
+        let header_size = match checksummed {
+            true => Self::HEADER_SIZE + Self::CHECKSUM_SIZE,
+            false => Self::HEADER_SIZE,
+        };
+        if checksummed {
+            let field = offset..offset + Self::CHECKSUM_SIZE;
+            let expected = u32::from_le_bytes(data[field.clone()].try_into().unwrap());
+            data[field].fill(0);
+            let got = crc32(&data);
+            if got != expected {
+                return Err(SlottedPageError::ChecksumMismatch {
+                    page_id,
+                    expected,
+                    got,
+                });
+            }
+            offset += Self::CHECKSUM_SIZE;
         }
 
-        let mut pointers = Vec::new();
         let num_pointers = match node_type {
-            NodeType::LEAF => 0,
-            NodeType::INTERNAL => num_keys + 1,
+            NodeType::INTERNAL => num_keys as usize + 1,
+            _ => 0,
+        };
+        let pointer_size = if counted { 16 } else { 8 };
+        let metadata_end = header_size
+            + num_keys as usize * Slot::SIZE
+            + num_pointers * pointer_size
+            + free_list_count as usize * FreeSpaceRegion::SIZE;
+        let data_start = free_space_end as usize;
+        if metadata_end > data_start || data_start > page_size {
+            return Err(corrupted(format!(
+                "page {} has {} bytes of metadata but its data starts at {}",
+                page_id, metadata_end, data_start
+            )));
+        }
+        if total_free as usize > page_size - header_size {
+            return Err(corrupted(format!(
+                "page {} claims {} free bytes",
+                page_id, total_free
+            )));
+        }
+        // Entries and free regions all live between the end of the free space and the page end
+        let in_data = |start: u16, length: usize| {
+            start as usize >= data_start && start as usize + length <= page_size
+        };
+
+        let mut slots = Vec::with_capacity(num_keys as usize);
+        for index in 0..num_keys {
+            let slot = Slot::deserialize(&data[offset..offset + Slot::SIZE]);
+            if !in_data(slot.offset, slot.total_length()) {
+                return Err(corrupted(format!(
+                    "slot {} of page {} lies outside the page data",
+                    index, page_id
+                )));
+            }
+            if slot.external && slot.value_length as usize != ValuePointer::SIZE {
+                return Err(corrupted(format!(
+                    "slot {} of page {} has a malformed value log pointer",
+                    index, page_id
+                )));
+            }
+            slots.push(slot);
+            offset += Slot::SIZE;
+        }
+        let eytzinger_order = match eytzinger {
+            true => search::eytzinger_order(slots.len()),
+            false => Vec::new(),
         };
+        if eytzinger {
+            let mut placed: Vec<(usize, Slot)> =
+                eytzinger_order.iter().copied().zip(slots).collect();
+            placed.sort_by_key(|&(index, _)| index);
+            slots = placed.into_iter().map(|(_, slot)| slot).collect();
+        }
+
+        let mut pointers = Vec::with_capacity(num_pointers);
         for _ in 0..num_pointers {
             pointers.push(u64::from_le_bytes(
-                buffer[offset..offset + 8].try_into().unwrap(),
+                data[offset..offset + 8].try_into().unwrap(),
             ));
             offset += 8;
         }
 
+        let mut counts = Vec::new();
+        if counted {
+            for _ in 0..num_pointers {
+                counts.push(u64::from_le_bytes(
+                    data[offset..offset + 8].try_into().unwrap(),
+                ));
+                offset += 8;
+            }
+        }
+
         let mut free_list = Vec::with_capacity(free_list_count as usize);
         for _ in 0..free_list_count {
-            free_list.push(FreeSpaceRegion::deserialize(
-                &buffer[offset..offset + FreeSpaceRegion::SIZE]
+            let region = FreeSpaceRegion::deserialize(
+                &data[offset..offset + FreeSpaceRegion::SIZE]
                     .try_into()
                     .unwrap(),
-            ));
+            );
+            if !in_data(region.offset, region.length as usize) {
+                return Err(corrupted(format!(
+                    "free region at {} of page {} lies outside the page data",
+                    region.offset, page_id
+                )));
+            }
+            free_list.push(region);
             offset += FreeSpaceRegion::SIZE;
         }
 
-        SlottedPage {
+        Ok(SlottedPage {
             page_id,
             node_type,
             num_keys,
@@ -230,10 +878,23 @@ where
             total_free,
             slots,
             pointers,
-            data: buffer.to_vec(),
-            page_size: page_size,
+            counts,
+            counted,
+            checksummed,
+            data,
+            page_size,
+            codecs: Codecs::default(),
+            compressor: None,
+            value_log: None,
+            value_log_threshold: 0,
+            interpolator: None,
+            eytzinger,
+            eytzinger_order,
+            fit_policy: FitPolicy::default(),
+            max_free_regions: DEFAULT_MAX_FREE_REGIONS,
+            dirty: false,
             _phantom_data: PhantomData,
-        }
+        })
     }
 
     pub fn find_exact_key(&self, key: &K) -> Result<Option<usize>, BTreeError> {
@@ -247,79 +908,120 @@ where
         Ok(None)
     }
 
+    /// Position of the first entry stored under `key` that has not been tombstoned.
+    pub fn find_live_key(&self, key: &K) -> Result<Option<usize>, BTreeError> {
+        for pos in self.find_key_position(key)?..self.slots.len() {
+            if &self.read_key(pos)? != key {
+                break;
+            }
+            if !self.is_tombstoned(pos) {
+                return Ok(Some(pos));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn find_key_position(&self, key: &K) -> Result<usize, BTreeError>
     where
         K: PartialOrd + for<'de> Deserialize<'de>,
     {
-        let mut left = 0;
-        let mut right = self.slots.len();
+        self.search(key, |key, mid_key| key <= mid_key)
+    }
 
-        while left < right {
-            let mid = left + (right - left) / 2;
-            let mid_key: K = self.read_key(mid)?;
+    /// Position of the first entry whose key is greater than `key`, so after any entries equal
+    /// to it.
+    pub fn find_upper_position(&self, key: &K) -> Result<usize, BTreeError> {
+        self.search(key, |key, mid_key| key < mid_key)
+    }
 
-            if key <= &mid_key {
-                right = mid;
-            } else {
-                left = mid + 1;
+    fn search(&self, key: &K, goes_before: fn(&K, &K) -> bool) -> Result<usize, BTreeError> {
+        let read_key = |idx| self.read_key(idx);
+        // The order read is only known to match the slots until the page changes
+        if !self.dirty && !self.slots.is_empty() && self.eytzinger_order.len() == self.slots.len() {
+            return search::eytzinger_search(&self.eytzinger_order, key, read_key, goes_before);
+        }
+        match self.interpolator {
+            Some(position) => {
+                search::interpolation_search(self.slots.len(), key, position, read_key, goes_before)
             }
+            None => search::binary_search(self.slots.len(), key, read_key, goes_before),
         }
-
-        Ok(left)
     }
 
     pub fn get_pointer(&self, key: &K) -> Result<u64, BTreeError> {
-        let pos = self.find_key_position(&key)?;
+        let pos = self.find_key_position(key)?;
         Ok(self.pointers[pos])
     }
 
-    fn header_region_end(&self) -> usize {
-        let pointer_count = match self.node_type {
-            NodeType::LEAF => self.pointers.len(),
-            NodeType::INTERNAL => self.pointers.len() + 1,
+    // Where an entry of `length` bytes goes, and the free region it is taken from if any,
+    // leaving the metadata room to grow by the entry's overhead
+    fn find_space_for(&self, length: usize) -> Option<(u16, Option<usize>)> {
+        let metadata_end = self.metadata_size() + self.entry_overhead();
+        let free_space_end = self.free_space_end as usize;
+        // A region used up entirely leaves the free list, giving its bytes to the metadata
+        let mut fits = self.free_list.iter().enumerate().filter(|(_, r)| {
+            let region_length = r.length as usize;
+            match region_length == length {
+                true => metadata_end <= free_space_end + FreeSpaceRegion::SIZE,
+                false => region_length > length && metadata_end <= free_space_end,
+            }
+        });
+        let region = match self.fit_policy {
+            // The least waste left, stopping early at a perfect fit
+            FitPolicy::BestFit => fits
+                .clone()
+                .find(|(_, r)| r.length as usize == length)
+                .or_else(|| fits.min_by_key(|(_, r)| r.length as usize - length)),
+            FitPolicy::FirstFit => fits.next(),
+            FitPolicy::AddressOrdered => fits.min_by_key(|(_, r)| r.offset),
         };
 
-        Self::HEADER_SIZE
-            + (self.slots.len() * Slot::SIZE)
-            + (pointer_count * 8)
-            + (self.free_list.len() * FreeSpaceRegion::SIZE)
+        // Otherwise, use contiguous space
+        region.map(|(i, r)| (r.offset, Some(i))).or_else(|| {
+            free_space_end
+                .checked_sub(length)
+                .filter(|&o| o >= metadata_end)
+                .map(|o| (o as u16, None))
+        })
     }
 
-    fn find_space_for(&self, length: usize) -> Option<(u16, Option<usize>)> {
-        // Find perfect fit
-        if let Some((index, region)) = self
-            .free_list
-            .iter()
-            .enumerate()
-            .find(|(_, r)| r.length as usize == length)
+    pub fn insert(&mut self, pos: usize, key: &K, value: &V) -> Result<(), BTreeError> {
+        let key_bytes = self.codecs.encoding().serialize(key)?;
+        let value = self.encode_value(value)?;
+        // Checked before a value bound for the log is appended to it
+        if self
+            .find_space_for(key_bytes.len() + value.stored_len())
+            .is_none()
         {
-            return Some((region.offset, Some(index)));
+            return Err(BTreeError::PageOverflow {
+                page_id: self.page_id,
+            });
         }
+        let (value_bytes, compressed, external) = self.store_value(&key_bytes, value)?;
 
-        // Find best fit (least waste left) if it exists
-        // otherwise, use contiguous space
-        self.free_list
-            .iter()
-            .enumerate()
-            .filter(|(_, r)| r.length as usize >= length)
-            .min_by_key(|(_, r)| r.length as usize - length)
-            .map(|(i, r)| (r.offset, Some(i)))
-            .or_else(|| {
-                (self.free_space_end as usize)
-                    .checked_sub(length)
-                    .filter(|&o| o >= self.header_region_end() + Slot::SIZE)
-                    .map(|o| (o as u16, None))
-            })
+        let slot = Slot {
+            offset: 0,
+            key_length: key_bytes.len() as u16,
+            value_length: value_bytes.len() as u16,
+            compressed,
+            tombstone: false,
+            overflow: false,
+            external,
+        };
+        self.insert_stored(pos, slot, &key_bytes, &value_bytes)
     }
 
-    pub fn insert(&mut self, pos: usize, key: &K, value: &V) -> Result<(), BTreeError> {
-        let key_bytes = bincode::serialize(key)?;
+    // Places an entry whose bytes are already encoded as slot `pos`, keeping the flags of
+    // `slot` and giving it a new offset
+    fn insert_stored(
+        &mut self,
+        pos: usize,
+        slot: Slot,
+        key_bytes: &[u8],
+        value_bytes: &[u8],
+    ) -> Result<(), BTreeError> {
         let key_bytes_len = key_bytes.len();
-
-        let value_bytes = bincode::serialize(value)?;
-        let value_bytes_len = value_bytes.len();
-
-        let total_len = key_bytes_len + value_bytes_len;
+        let total_len = key_bytes_len + value_bytes.len();
 
         let (offset, free_list_idx) =
             self.find_space_for(total_len)
@@ -327,9 +1029,10 @@ where
                     page_id: self.page_id,
                 })?;
         let offset = offset as usize;
+        self.dirty = true;
 
-        self.data[offset..offset + key_bytes_len].copy_from_slice(&key_bytes);
-        self.data[offset + key_bytes_len..offset + total_len].copy_from_slice(&value_bytes);
+        self.data[offset..offset + key_bytes_len].copy_from_slice(key_bytes);
+        self.data[offset + key_bytes_len..offset + total_len].copy_from_slice(value_bytes);
 
         match free_list_idx {
             Some(free_list_idx) => {
@@ -359,11 +1062,11 @@ where
 
         let slot = Slot {
             offset: offset as u16,
-            key_length: key_bytes_len as u16,
-            value_length: value_bytes_len as u16,
+            ..slot
         };
         self.slots.insert(pos, slot);
         self.num_keys += 1;
+        self.audited();
 
         Ok(())
     }
@@ -384,6 +1087,8 @@ where
 
         if region.offset + region.length == self.free_space_end {
             self.free_space_end = region.offset;
+        } else if self.fit_policy == FitPolicy::FirstFit {
+            self.free_list.insert(0, region);
         } else {
             let insert_pos = self
                 .free_list
@@ -394,12 +1099,27 @@ where
         }
     }
 
+    // Merges the free regions into the contiguous free space once there are too many of them,
+    // or once the free list no longer fits before the data
+    fn limit_free_list(&mut self) {
+        if self.free_list.len() > self.max_free_regions
+            || self.metadata_size() > self.free_space_end as usize
+        {
+            trace!(
+                "Compacting page {} with {} free regions",
+                self.page_id,
+                self.free_list.len()
+            );
+            self.defragment();
+        }
+    }
+
     pub fn update(&mut self, pos: usize, key: &K, value: &V) -> Result<(), BTreeError> {
-        let key_bytes = bincode::serialize(key)?;
+        let key_bytes = self.codecs.encoding().serialize(key)?;
         let key_bytes_len = key_bytes.len();
 
-        let value_bytes = bincode::serialize(value)?;
-        let value_bytes_len = value_bytes.len();
+        let encoded = self.encode_value(value)?;
+        let value_bytes_len = encoded.stored_len();
 
         let total_len = key_bytes_len + value_bytes_len;
 
@@ -408,12 +1128,18 @@ where
         let old_value_bytes_len = slot.value_length as usize;
 
         if value_bytes_len <= old_value_bytes_len {
+            let (value_bytes, compressed, external) = self.store_value(&key_bytes, encoded)?;
+            self.dirty = true;
             self.data[offset..offset + key_bytes_len].copy_from_slice(&key_bytes);
             self.data[offset + key_bytes_len..offset + key_bytes_len + value_bytes_len]
                 .copy_from_slice(&value_bytes);
 
             self.slots[pos].key_length = key_bytes_len as u16;
             self.slots[pos].value_length = value_bytes_len as u16;
+            self.slots[pos].compressed = compressed;
+            self.slots[pos].tombstone = false;
+            self.slots[pos].overflow = false;
+            self.slots[pos].external = external;
 
             let leftover = old_value_bytes_len - value_bytes_len;
             if leftover > 0 {
@@ -423,12 +1149,14 @@ where
                     length: leftover as u16,
                 });
                 self.total_free += leftover as u16;
+                self.limit_free_list();
             }
+            self.audited();
             Ok(())
         } else {
             // Will not fit, therefore delete and reinsert
             self.delete(pos)?;
-            self.insert(pos, &key, &value)?;
+            self.insert(pos, key, value)?;
             Ok(())
         }
     }
@@ -440,6 +1168,7 @@ where
 
         let slot = self.slots.remove(pos);
         self.num_keys -= 1;
+        self.dirty = true;
 
         let freed_length = slot.key_length + slot.value_length;
         self.total_free += freed_length;
@@ -448,28 +1177,88 @@ where
             offset: slot.offset,
             length: freed_length,
         });
+        self.limit_free_list();
+        self.audited();
 
         Ok(())
     }
 
+    // Entry to move up in a split: the one straddling the middle of the bytes the entries and
+    // their pointers take up, so neither half is left with more than half of them and either
+    // can take one more entry of up to `max_entry_len`. An internal node keeps an entry on
+    // each side of it where it has enough.
+    fn split_index(&self) -> usize {
+        let pointer_size = match self.node_type {
+            NodeType::INTERNAL => self.pointer_size(),
+            _ => 0,
+        };
+        let weights: Vec<usize> = self
+            .slots
+            .iter()
+            .map(|slot| slot.total_length() + Slot::SIZE + pointer_size)
+            .collect();
+        let half = weights.iter().sum::<usize>() / 2;
+        let mut taken = 0;
+        let straddling = weights
+            .iter()
+            .position(|weight| {
+                taken += weight;
+                taken > half
+            })
+            .unwrap_or(weights.len() - 1);
+        let last = match self.node_type {
+            NodeType::INTERNAL if weights.len() > 2 => weights.len() - 2,
+            _ => weights.len() - 1,
+        };
+        straddling.clamp(1, last)
+    }
+
     pub fn split(&mut self, new_page_id: u64) -> Result<(K, V, SlottedPage<K, V>), BTreeError> {
-        let mid_index: usize = self.num_keys as usize / 2;
+        // With fewer than two entries there is nothing to leave on the left of the one moved up
+        if self.num_keys < 2 {
+            return Err(BTreeError::PageOverflow {
+                page_id: self.page_id,
+            });
+        }
+        let mid_index = self.split_index();
         let mid_key = self.read_key(mid_index)?;
         let mid_value = self.read_value(mid_index)?;
 
         let mut right = SlottedPage::new(new_page_id, self.node_type, self.page_size);
+        right.set_checksummed(self.checksummed);
+        right.codecs = self.codecs.clone();
+        right.set_compressor(self.compressor.clone());
+        right.value_log = self.value_log.clone();
+        right.value_log_threshold = self.value_log_threshold;
+        right.interpolator = self.interpolator;
+        right.eytzinger = self.eytzinger;
+        right.fit_policy = self.fit_policy;
+        right.max_free_regions = self.max_free_regions;
+        right.counted = self.counted;
+        // Entries are moved as raw bytes, so values in the value log are not appended again
         for i in (mid_index + 1)..self.slots.len() {
-            let key: K = self.read_key(i)?;
-            let value: V = self.read_value(i)?;
-            right.insert(right.slots.len(), &key, &value)?;
+            let slot = self.slots[i].clone();
+            let start = slot.offset as usize;
+            let value_start = start + slot.key_length as usize;
+            let end = start + slot.total_length();
+            right.insert_stored(
+                right.slots.len(),
+                slot,
+                &self.data[start..value_start],
+                &self.data[value_start..end],
+            )?;
         }
 
         if self.node_type == NodeType::INTERNAL && self.pointers.len() > mid_index + 1 {
             right.pointers = self.pointers.split_off(mid_index + 1);
+            if self.counted {
+                right.counts = self.counts.split_off(mid_index + 1);
+            }
         }
 
         let removed_slots: Vec<Slot> = self.slots.drain(mid_index..).collect();
         self.num_keys = mid_index as u16;
+        self.dirty = true;
 
         removed_slots.iter().for_each(|slot| {
             self.add_to_free_list(FreeSpaceRegion {
@@ -478,90 +1267,292 @@ where
             });
             self.total_free += slot.key_length + slot.value_length;
         });
+        self.limit_free_list();
+        self.audited();
+        right.audited();
 
         Ok((mid_key, mid_value, right))
     }
 
+    /// Moves every entry to the end of the page so all free space is contiguous. Tombstoned
+    /// entries are dropped.
     pub fn compact(&mut self) -> Result<(), BTreeError> {
-        let mut pairs: Vec<(K, V)> = Vec::with_capacity(self.slots.len());
-        for idx in 0..self.slots.len() {
-            pairs.push(self.read_key_value(idx)?);
-        }
+        self.slots.retain(|slot| !slot.tombstone);
+        self.defragment();
+        Ok(())
+    }
+
+    // Moves every entry, tombstoned or not, to the end of the page, leaving no free regions
+    fn defragment(&mut self) {
+        // Entries are moved as raw bytes so compressed values stay compressed
+        let entries: Vec<(Slot, Vec<u8>)> = self
+            .slots
+            .iter()
+            .map(|slot| {
+                let start = slot.offset as usize;
+                let end = start + slot.total_length();
+                (slot.clone(), self.data[start..end].to_vec())
+            })
+            .collect();
 
         self.free_space_end = self.page_size as u16;
-        self.total_free = self.free_space_end - Header::SIZE as u16;
+        self.total_free = self.free_space_end - self.header_size() as u16;
         self.slots.clear();
 
-        for (key, value) in pairs.iter() {
-            let key_bytes = bincode::serialize(key)?;
-            let value_bytes = bincode::serialize(value)?;
-
-            let total_len = key_bytes.len() + value_bytes.len();
+        for (slot, bytes) in entries.into_iter() {
+            let total_len = bytes.len();
             let new_offset: usize = self.free_space_end as usize - total_len;
 
-            self.data[new_offset..new_offset + key_bytes.len()].copy_from_slice(&key_bytes);
-            self.data[new_offset + key_bytes.len()..new_offset + total_len]
-                .copy_from_slice(&value_bytes);
+            self.data[new_offset..new_offset + total_len].copy_from_slice(&bytes);
 
             self.free_space_end = new_offset as u16;
             self.total_free -= total_len as u16;
 
             self.slots.push(Slot {
                 offset: self.free_space_end,
-                key_length: key_bytes.len() as u16,
-                value_length: value_bytes.len() as u16,
+                ..slot
             });
         }
 
         self.free_list.clear();
-
-        Ok(())
+        self.num_keys = self.slots.len() as u16;
+        self.dirty = true;
+        self.audited();
     }
 
     pub fn read_key_value(&self, index: usize) -> Result<(K, V), BTreeError> {
+        Ok((self.read_key(index)?, self.read_value(index)?))
+    }
+
+    /// The serialized key at `index`, as stored in the page.
+    pub fn key_bytes(&self, index: usize) -> &[u8] {
         let slot = &self.slots[index];
         let offset = slot.offset as usize;
-        let key_length = slot.key_length as usize;
-        let key: K = bincode::deserialize(&self.data[offset..offset + key_length])?;
-
-        let offset = offset + key_length;
-        let value_length = slot.value_length as usize;
-        let value: V = bincode::deserialize(&self.data[offset..offset + value_length])?;
-
-        Ok((key, value))
+        &self.data[offset..offset + slot.key_length as usize]
     }
 
     pub fn read_key(&self, index: usize) -> Result<K, BTreeError> {
         let slot = &self.slots[index];
         let offset = slot.offset as usize;
         let key_length = slot.key_length as usize;
-        let key: K = bincode::deserialize(&self.data[offset..offset + key_length])?;
+        let key: K = self
+            .codecs
+            .encoding()
+            .deserialize(&self.data[offset..offset + key_length])?;
         Ok(key)
     }
 
     pub fn read_value(&self, index: usize) -> Result<V, BTreeError> {
+        self.codecs.decode_value(&self.value_bytes(index)?)
+    }
+
+    /// Returns the serialized value at `index`, decompressed if it was stored compressed.
+    pub fn read_value_bytes(&self, index: usize) -> Result<Vec<u8>, BTreeError> {
+        Ok(self.value_bytes(index)?.into_owned())
+    }
+
+    /// Like `read_value_bytes`, but borrows the bytes from the page unless they have to be
+    /// decompressed.
+    pub fn value_bytes(&self, index: usize) -> Result<Cow<'_, [u8]>, BTreeError> {
         let slot = &self.slots[index];
+        if slot.overflow {
+            return Err(SlottedPageError::OverflowValue {
+                page_id: self.page_id,
+                index,
+            }
+            .into());
+        }
         let key_length = slot.key_length as usize;
         let value_length = slot.value_length as usize;
         let offset = slot.offset as usize + key_length;
+        let stored = &self.data[offset..offset + value_length];
 
-        let value: V = bincode::deserialize(&self.data[offset..offset + value_length])?;
-        Ok(value)
-    }
+        if slot.external {
+            let pointer = self.value_pointer(index).unwrap();
+            let value_log = self.value_log.as_ref().ok_or(ValueLogError::NotAttached)?;
+            return Ok(Cow::Owned(value_log.read(&pointer)?));
+        }
+        if !slot.compressed {
+            return Ok(Cow::Borrowed(stored));
+        }
 
-    pub fn read_keys(&self) -> Result<Vec<K>, BTreeError> {
-        (0..self.num_keys)
-            .map(|idx| self.read_key(idx.into()))
-            .collect::<Result<Vec<K>, BTreeError>>()
+        let compressor = self
+            .compressor
+            .as_ref()
+            .ok_or(CompressionError::MissingDictionary)?;
+        Ok(Cow::Owned(compressor.decompress(stored)?))
+    }
+
+    /// Where the value at `index` lives in the value log, if it was moved there.
+    pub fn value_pointer(&self, index: usize) -> Option<ValuePointer> {
+        let slot = &self.slots[index];
+        if !slot.external {
+            return None;
+        }
+        let offset = slot.offset as usize + slot.key_length as usize;
+        ValuePointer::from_bytes(&self.data[offset..offset + slot.value_length as usize])
+    }
+
+    /// Copies the value at `index` to the head of the value log and points the entry at the
+    /// copy, returning where it was written, or `None` if the value is kept in the page.
+    pub fn rewrite_external_value(
+        &mut self,
+        index: usize,
+    ) -> Result<Option<ValuePointer>, BTreeError> {
+        let Some(pointer) = self.value_pointer(index) else {
+            return Ok(None);
+        };
+        let value_log = self.value_log.as_ref().ok_or(ValueLogError::NotAttached)?;
+        let value = value_log.read(&pointer)?;
+
+        let start = self.slots[index].offset as usize;
+        let value_start = start + self.slots[index].key_length as usize;
+        let copy = value_log.append(&self.data[start..value_start], &value)?;
+        self.data[value_start..value_start + ValuePointer::SIZE].copy_from_slice(&copy.to_bytes());
+        self.dirty = true;
+        Ok(Some(copy))
+    }
+
+    /// Length of the serialized value at `index`, found without reading it from the value log.
+    pub fn value_len(&self, index: usize) -> Result<usize, BTreeError> {
+        match self.value_pointer(index) {
+            Some(pointer) => Ok(pointer.length as usize),
+            None => Ok(self.value_bytes(index)?.len()),
+        }
+    }
+
+    pub fn read_keys(&self) -> Result<Vec<K>, BTreeError> {
+        (0..self.num_keys)
+            .map(|idx| self.read_key(idx.into()))
+            .collect::<Result<Vec<K>, BTreeError>>()
+    }
+
+    /// Entries in the page, tombstones included.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Inserts an entry in key order, after any with an equal key, returning its position.
+    /// Fails with `PageOverflow` if it does not fit; compacting may make room.
+    pub fn insert_sorted(&mut self, key: &K, value: &V) -> Result<usize, BTreeError> {
+        let pos = self.find_upper_position(key)?;
+        self.insert(pos, key, value)?;
+        Ok(pos)
+    }
+
+    /// The value of the first live entry stored under `key`.
+    pub fn get(&self, key: &K) -> Result<Option<V>, BTreeError> {
+        match self.find_live_key(key)? {
+            Some(pos) => Ok(Some(self.read_value(pos)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The live entries in key order.
+    pub fn iter(&self) -> Entries<'_, K, V> {
+        Entries {
+            page: self,
+            next: 0,
+        }
+    }
+
+    /// Starts building an empty leaf page, checksummed like every page a tree creates.
+    pub fn builder(page_id: u64, page_size: usize) -> SlottedPageBuilder<K, V> {
+        SlottedPageBuilder {
+            page: SlottedPage::new(page_id, NodeType::LEAF, page_size),
+        }
+    }
+}
+
+/// Iterator over the live entries of a page, returned by [`SlottedPage::iter`].
+pub struct Entries<'a, K, V> {
+    page: &'a SlottedPage<K, V>,
+    next: usize,
+}
+
+impl<K, V> Iterator for Entries<'_, K, V>
+where
+    K: PartialOrd + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>,
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<(K, V), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.page.len() && self.page.is_tombstoned(self.next) {
+            self.next += 1;
+        }
+        if self.next == self.page.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(self.page.read_key_value(self.next - 1))
+    }
+}
+
+/// Sets up a page before any entries go in, returned by [`SlottedPage::builder`].
+pub struct SlottedPageBuilder<K, V> {
+    page: SlottedPage<K, V>,
+}
+
+impl<K, V> SlottedPageBuilder<K, V>
+where
+    K: PartialOrd + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>,
+    V: Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Makes an internal node, whose child pointers the caller keeps with
+    /// [`SlottedPage::insert_pointer`], one more than its keys.
+    pub fn with_node_type(mut self, node_type: NodeType) -> Self {
+        self.page.node_type = node_type;
+        self
+    }
+
+    /// Leaves out the checksum, for the layout of pages from before checksums were added.
+    pub fn with_checksum(mut self, checksummed: bool) -> Self {
+        self.page.set_checksummed(checksummed);
+        self
+    }
+
+    /// Encodes keys and values with `encoding` rather than the default fixed-width bincode.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.page.set_codecs(Codecs::new(encoding, 0, None));
+        self
+    }
+
+    pub fn with_compressor(mut self, compressor: Arc<ValueCompressor>) -> Self {
+        self.page.set_compressor(Some(compressor));
+        self
+    }
+
+    pub fn with_value_log(mut self, value_log: Arc<ValueLog>, threshold: u16) -> Self {
+        self.page.set_value_log(Some(value_log), threshold);
+        self
+    }
+
+    pub fn with_eytzinger_layout(mut self, eytzinger: bool) -> Self {
+        self.page.set_eytzinger(eytzinger);
+        self
+    }
+
+    pub fn with_free_list_policy(mut self, policy: FitPolicy, max_regions: usize) -> Self {
+        self.page.set_free_list_policy(policy, max_regions);
+        self
+    }
+
+    pub fn build(self) -> SlottedPage<K, V> {
+        self.page
     }
 }
 
-impl<K, V> std::fmt::Debug for SlottedPage<K, V>
+impl<K, V> core::fmt::Debug for SlottedPage<K, V>
 where
     K: PartialOrd + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>,
     V: Debug + Serialize + for<'de> Deserialize<'de>,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("SlottedPage")
             .field("page_id", &self.page_id)
             .field("num_keys", &self.num_keys)
@@ -603,13 +1594,13 @@ mod tests {
         let mut used_regions: Vec<(u16, u16, &str)> = Vec::new();
 
         // Add slot data regions
-        for (_, slot) in page.slots.iter().enumerate() {
+        for slot in page.slots.iter() {
             let len = slot.key_length + slot.value_length;
             used_regions.push((slot.offset, len, "slot"));
         }
 
         // Add free list regions
-        for (_, region) in page.free_list.iter().enumerate() {
+        for region in page.free_list.iter() {
             used_regions.push((region.offset, region.length, "free"));
         }
 
@@ -621,8 +1612,7 @@ mod tests {
             let (offset1, len1, type1) = used_regions[i];
             let end1 = offset1 + len1;
 
-            for j in (i + 1)..used_regions.len() {
-                let (offset2, len2, type2) = used_regions[j];
+            for &(offset2, len2, type2) in used_regions.iter().skip(i + 1) {
                 let end2 = offset2 + len2;
 
                 // Check if regions overlap
@@ -669,7 +1659,7 @@ mod tests {
         println!("free_space_end: {}", page.free_space_end);
         println!("num_keys: {}", page.num_keys);
         println!("total_free: {}", page.total_free);
-        println!("free_space(): {}", page.get_free_space());
+        println!("free_space(): {}", page.free_space());
 
         println!("\nSlots:");
         for (i, slot) in page.slots.iter().enumerate() {
@@ -1009,6 +1999,20 @@ mod tests {
     mod update {
         use super::*;
 
+        #[test]
+        fn deserialized_page_is_clean_until_modified() {
+            let mut page = create_page(4096);
+            assert!(page.is_dirty());
+            page.insert(0, &1i64, &"aaaa".to_string()).unwrap();
+
+            let mut page: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&page.serialize().unwrap(), 4096).unwrap();
+            assert!(!page.is_dirty());
+
+            page.update(0, &1i64, &"bbbb".to_string()).unwrap();
+            assert!(page.is_dirty());
+        }
+
         #[test]
         fn update_same_size_value() {
             let mut page = create_page(4096);
@@ -1249,6 +2253,70 @@ mod tests {
         }
     }
 
+    // ─────────────────────────────────────────────────────────
+    // Tombstone Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod tombstone {
+        use super::*;
+
+        #[test]
+        fn tombstone_keeps_bytes_until_compaction() {
+            let mut page = create_page(4096);
+            for i in 0..5i64 {
+                page.insert(i as usize, &i, &format!("value-{}", i))
+                    .unwrap();
+            }
+            let free_before = page.total_free;
+            let occupancy_before = page.live_occupancy();
+
+            page.tombstone(1);
+            page.tombstone(3);
+
+            assert_eq!(page.total_free, free_before);
+            assert_eq!(page.num_keys, 5);
+            assert!(page.is_tombstoned(1));
+            assert!(page.should_compact());
+            assert!(page.live_occupancy() < occupancy_before);
+            assert!(page.tombstoned_bytes() > 0);
+
+            page.compact().unwrap();
+            verify_page_integrity(&page).unwrap();
+
+            assert_eq!(page.num_keys, 3);
+            assert!(!page.has_tombstones());
+            assert!(page.total_free > free_before);
+            assert_eq!(page.read_keys().unwrap(), vec![0, 2, 4]);
+        }
+
+        #[test]
+        fn tombstone_survives_serialization() {
+            let mut page = create_page(4096);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.insert(1, &2i64, &"two".to_string()).unwrap();
+            page.tombstone(0);
+
+            let bytes = page.serialize().unwrap();
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&bytes, 4096).unwrap();
+
+            assert!(restored.is_tombstoned(0));
+            assert!(!restored.is_tombstoned(1));
+        }
+
+        #[test]
+        fn update_revives_tombstoned_entry() {
+            let mut page = create_page(4096);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.tombstone(0);
+
+            page.update(0, &1i64, &"uno".to_string()).unwrap();
+
+            assert!(!page.is_tombstoned(0));
+            assert_eq!(page.read_value(0).unwrap(), "uno");
+        }
+    }
+
     // ─────────────────────────────────────────────────────────
     // Data Corruption Detection Tests
     // ─────────────────────────────────────────────────────────
@@ -1256,6 +2324,143 @@ mod tests {
     mod corruption_detection {
         use super::*;
 
+        // Recomputes the checksum of a page edited byte by byte, so the edit is caught by the
+        // structural checks rather than the checksum
+        fn reseal(bytes: &mut [u8]) {
+            bytes[18..22].fill(0);
+            let checksum = crc32(bytes);
+            bytes[18..22].copy_from_slice(&checksum.to_le_bytes());
+        }
+
+        // A page laid out as files written before checksums were added
+        fn legacy_page(page_size: usize) -> SlottedPage<i64, String> {
+            let mut page = create_page(page_size);
+            page.checksummed = false;
+            page.total_free += SlottedPage::<i64, String>::CHECKSUM_SIZE as u16;
+            page
+        }
+
+        #[test]
+        fn new_pages_carry_a_checksum_that_survives_a_roundtrip() {
+            let mut page = create_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            assert!(page.is_checksummed());
+
+            let restored =
+                SlottedPage::<i64, String>::deserialize(&page.serialize().unwrap(), 256).unwrap();
+            assert!(restored.is_checksummed());
+            assert_eq!(restored.read_value(0).unwrap(), "one");
+        }
+
+        #[test]
+        fn pages_decoded_from_an_owned_buffer_match_copied_ones() {
+            let mut page = create_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            let bytes = page.serialize().unwrap();
+
+            let owned = SlottedPage::<i64, String>::from_buffer(bytes.clone()).unwrap();
+            let copied = SlottedPage::<i64, String>::deserialize(&bytes, 256).unwrap();
+            assert_eq!(owned.serialize().unwrap(), copied.serialize().unwrap());
+            assert_eq!(owned.read_value(0).unwrap(), "one");
+            assert!(matches!(
+                SlottedPage::<i64, String>::from_buffer(vec![0; 8]),
+                Err(SlottedPageError::InvalidBufferSize { got: 8, .. })
+            ));
+        }
+
+        #[test]
+        fn serialize_into_overwrites_whatever_the_buffer_held() {
+            let mut page = create_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+
+            let mut buffer = vec![0xAA; 256];
+            page.serialize_into(&mut buffer).unwrap();
+            assert_eq!(buffer, page.serialize().unwrap());
+            assert!(page.serialize_into(&mut [0; 128]).is_err());
+        }
+
+        #[test]
+        fn deserialize_rejects_a_flipped_bit_in_the_data() {
+            let mut page = create_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            let mut bytes = page.serialize().unwrap();
+            bytes[255] ^= 0x01;
+
+            let result = SlottedPage::<i64, String>::deserialize(&bytes, 256);
+            assert!(matches!(
+                result,
+                Err(SlottedPageError::ChecksumMismatch { page_id: 0, .. })
+            ));
+        }
+
+        #[test]
+        fn legacy_pages_without_a_checksum_still_read() {
+            let mut page = legacy_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.insert(1, &2i64, &"two".to_string()).unwrap();
+            let bytes = page.serialize().unwrap();
+
+            let restored = SlottedPage::<i64, String>::deserialize(&bytes, 256).unwrap();
+            assert!(!restored.is_checksummed());
+            assert_eq!(restored.read_value(1).unwrap(), "two");
+            assert_eq!(restored.free_space(), page.free_space());
+        }
+
+        #[test]
+        fn deserialize_rejects_slot_past_page_end() {
+            let mut page = create_page(256);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.slots[0].value_length = 300;
+
+            let result = SlottedPage::<i64, String>::deserialize(&page.serialize().unwrap(), 256);
+            assert!(matches!(result, Err(SlottedPageError::CorruptedData(_))));
+        }
+
+        #[test]
+        fn deserialize_rejects_metadata_past_free_space() {
+            let mut bytes = create_page(256).serialize().unwrap();
+            // num_keys far beyond what fits ahead of the data
+            bytes[9..11].copy_from_slice(&200u16.to_le_bytes());
+            reseal(&mut bytes);
+
+            let result = SlottedPage::<i64, String>::deserialize(&bytes, 256);
+            assert!(matches!(result, Err(SlottedPageError::CorruptedData(_))));
+        }
+
+        #[test]
+        fn deserialize_rejects_unknown_node_type_and_wrong_size() {
+            let mut bytes = create_page(256).serialize().unwrap();
+            assert!(matches!(
+                SlottedPage::<i64, String>::deserialize(&bytes[..100], 256),
+                Err(SlottedPageError::InvalidBufferSize { .. })
+            ));
+
+            bytes[8] = 0xEE;
+            assert!(matches!(
+                SlottedPage::<i64, String>::deserialize(&bytes, 256),
+                Err(SlottedPageError::CorruptedData(_))
+            ));
+        }
+
+        #[test]
+        fn overflow_value_is_rejected() {
+            let mut page = create_page(4096);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.slots[0].overflow = true;
+
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&page.serialize().unwrap(), 4096).unwrap();
+
+            assert_eq!(restored.read_key(0).unwrap(), 1);
+            assert!(matches!(
+                restored.read_value(0),
+                Err(BTreeError::SlottedPage(SlottedPageError::OverflowValue {
+                    page_id: 0,
+                    index: 0
+                }))
+            ));
+        }
+
         #[test]
         fn detect_overlapping_slot_regions() {
             let mut page = create_page(4096);
@@ -1365,7 +2570,8 @@ mod tests {
             let total_free = page.total_free;
 
             let bytes = page.serialize().unwrap();
-            let restored: SlottedPage<i64, String> = SlottedPage::deserialize(&bytes, 4096);
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&bytes, 4096).unwrap();
 
             assert_eq!(restored.free_list.len(), free_list_len);
             assert_eq!(restored.total_free, total_free);
@@ -1385,7 +2591,8 @@ mod tests {
             page.insert(1, &2i64, &"TWO".to_string()).unwrap();
 
             let bytes = page.serialize().unwrap();
-            let restored: SlottedPage<i64, String> = SlottedPage::deserialize(&bytes, 4096);
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&bytes, 4096).unwrap();
 
             verify_page_integrity(&restored).unwrap();
 
@@ -1394,4 +2601,429 @@ mod tests {
             assert_eq!(restored.read_value(2).unwrap(), "three");
         }
     }
+
+    // ─────────────────────────────────────────────────────────
+    // Eytzinger Layout Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod eytzinger_layout {
+        use super::*;
+
+        fn eytzinger_page(keys: i64) -> SlottedPage<i64, String> {
+            let mut page = create_page(4096);
+            page.set_eytzinger(true);
+            for i in 0..keys {
+                page.insert(i as usize, &(i * 2), &format!("value-{:04}", i))
+                    .unwrap();
+            }
+            page
+        }
+
+        #[test]
+        fn roundtrip_restores_key_order() {
+            let page = eytzinger_page(20);
+            let bytes = page.serialize().unwrap();
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&bytes, 4096).unwrap();
+
+            assert!(restored.is_eytzinger());
+            verify_page_integrity(&restored).unwrap();
+            assert_eq!(restored.read_keys().unwrap(), page.read_keys().unwrap());
+            for i in 0..20 {
+                assert_eq!(restored.read_value(i).unwrap(), format!("value-{:04}", i));
+            }
+            // The first slot written is the middle key, the root of the search tree
+            let first = Slot::deserialize(&bytes[SlottedPage::<i64, String>::HEADER_SIZE + 4..]);
+            assert_eq!(first.offset, restored.slots[12].offset);
+        }
+
+        #[test]
+        fn read_pages_are_searched_in_eytzinger_order() {
+            let page = eytzinger_page(31);
+            let mut restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&page.serialize().unwrap(), 4096).unwrap();
+
+            let keys = page.read_keys().unwrap();
+            for key in -1..63 {
+                assert_eq!(
+                    restored.find_key_position(&key).unwrap(),
+                    keys.partition_point(|&k| k < key)
+                );
+                assert_eq!(
+                    restored.find_upper_position(&key).unwrap(),
+                    keys.partition_point(|&k| k <= key)
+                );
+            }
+
+            // Once changed, the page falls back to binary search
+            restored.insert(31, &62, &"value-0031".to_string()).unwrap();
+            assert_eq!(restored.find_key_position(&62).unwrap(), 31);
+            assert_eq!(restored.find_key_position(&63).unwrap(), 32);
+        }
+
+        #[test]
+        fn pages_without_the_flag_keep_key_order() {
+            let mut page = eytzinger_page(10);
+            page.set_eytzinger(false);
+            let bytes = page.serialize().unwrap();
+            let first = Slot::deserialize(&bytes[SlottedPage::<i64, String>::HEADER_SIZE + 4..]);
+
+            assert_eq!(first.offset, page.slots[0].offset);
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&bytes, 4096).unwrap();
+            assert!(!restored.is_eytzinger());
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Subtree Count Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod subtree_counts {
+        use super::*;
+
+        fn create_counted_internal(page_size: usize) -> SlottedPage<i64, String> {
+            let mut page = SlottedPage::new(0, NodeType::INTERNAL, page_size);
+            page.set_counted(true);
+            page.insert_pointer(0, 10, 5);
+            page
+        }
+
+        #[test]
+        fn roundtrip_preserves_counts() {
+            let mut page = create_counted_internal(4096);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+            page.insert_pointer(1, 11, 7);
+            page.adjust_count(0, -2);
+
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&page.serialize().unwrap(), 4096).unwrap();
+
+            assert!(restored.is_counted());
+            assert_eq!(restored.pointers, vec![10, 11]);
+            assert_eq!(restored.counts, vec![3, 7]);
+            assert_eq!(restored.subtree_entries(), 11);
+        }
+
+        #[test]
+        fn leaves_never_keep_counts() {
+            let mut page = create_page(4096);
+            page.set_counted(true);
+            page.insert(0, &1i64, &"one".to_string()).unwrap();
+
+            let restored: SlottedPage<i64, String> =
+                SlottedPage::deserialize(&page.serialize().unwrap(), 4096).unwrap();
+            assert!(!restored.is_counted());
+            assert_eq!(restored.subtree_entries(), 1);
+        }
+
+        #[test]
+        fn counts_take_room_from_entries() {
+            let mut plain: SlottedPage<i64, String> = SlottedPage::new(0, NodeType::INTERNAL, 256);
+            plain.insert_pointer(0, 10, 0);
+            let mut counted = create_counted_internal(256);
+
+            let fill = |page: &mut SlottedPage<i64, String>| {
+                let mut i = 0;
+                while page.can_insert(8, 12) {
+                    page.insert(i, &(i as i64), &"value".to_string()).unwrap();
+                    page.insert_pointer(i + 1, 10 + i as u64, 1);
+                    i += 1;
+                }
+                i
+            };
+            assert!(fill(&mut counted) < fill(&mut plain));
+        }
+
+        #[test]
+        fn split_divides_counts_with_pointers() {
+            let mut page = create_counted_internal(4096);
+            for i in 0..5 {
+                page.insert(i, &(i as i64), &"value".to_string()).unwrap();
+                page.insert_pointer(i + 1, 11 + i as u64, i as u64 + 1);
+            }
+
+            let (_, _, right) = page.split(99).unwrap();
+            assert!(right.is_counted());
+            assert_eq!(page.counts.len(), page.pointers.len());
+            assert_eq!(right.counts.len(), right.pointers.len());
+            assert_eq!(page.counts, vec![5, 1, 2]);
+            assert_eq!(right.counts, vec![3, 4, 5]);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Free Space Audit Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod free_space_audit {
+        use super::*;
+        use rand::Rng;
+
+        // Every mutation audits the page itself in tests, so this only has to drive them
+        #[test]
+        fn random_mutations_keep_counters_consistent() {
+            let mut rng = rand::rng();
+            let mut page = create_page(4096);
+            for _ in 0..2000 {
+                let len = page.num_keys as usize;
+                let value = "x".repeat(rng.random_range(0..40));
+                match rng.random_range(0..10) {
+                    0..=4 if page.can_insert(8, value.len() + 8) => {
+                        let pos = rng.random_range(0..=len);
+                        page.insert(pos, &(pos as i64), &value).unwrap();
+                    }
+                    5 | 6 if len > 0 => page.delete(rng.random_range(0..len)).unwrap(),
+                    7 if len > 0 => {
+                        let pos = rng.random_range(0..len);
+                        let key = page.read_key(pos).unwrap();
+                        // An update that does not fit is refused without touching the page
+                        let _ = page.update(pos, &key, &value);
+                        page.audit_free_space().unwrap();
+                    }
+                    8 => page.compact().unwrap(),
+                    9 if len > 1 => {
+                        let (_, _, mut right) = page.split(1).unwrap();
+                        right.compact().unwrap();
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        #[test]
+        fn audit_reports_counters_that_drift() {
+            let mut page = create_page(4096);
+            for i in 0..4 {
+                page.insert(i, &(i as i64), &"value".to_string()).unwrap();
+            }
+            page.delete(1).unwrap();
+            page.audit_free_space().unwrap();
+
+            page.total_free += 1;
+            assert!(matches!(
+                page.audit_free_space(),
+                Err(SlottedPageError::CorruptedData(_))
+            ));
+            page.total_free -= 1;
+
+            page.free_list[0].length -= 1;
+            assert!(matches!(
+                page.audit_free_space(),
+                Err(SlottedPageError::CorruptedData(_))
+            ));
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Free List Policy Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod free_list_policy {
+        use super::*;
+
+        // A page with holes of 40, 20 and 30 value bytes, from the end of the page down,
+        // freed lowest first, and the offsets of each
+        fn page_with_holes(policy: FitPolicy) -> (SlottedPage<i64, String>, [u16; 3]) {
+            let mut page = create_page(4096);
+            page.set_free_list_policy(policy, DEFAULT_MAX_FREE_REGIONS);
+            for (i, len) in [40, 5, 20, 5, 30, 5, 5].into_iter().enumerate() {
+                page.insert(i, &(i as i64), &"x".repeat(len)).unwrap();
+            }
+            let offsets = [0, 2, 4].map(|i| page.slots[i].offset);
+            for i in [4, 2, 0] {
+                page.delete(i).unwrap();
+            }
+            (page, offsets)
+        }
+
+        #[test]
+        fn policies_choose_different_regions() {
+            for (policy, hole) in [
+                (FitPolicy::BestFit, 1),
+                (FitPolicy::FirstFit, 0),
+                (FitPolicy::AddressOrdered, 2),
+            ] {
+                let (mut page, offsets) = page_with_holes(policy);
+                page.insert(0, &-1, &"y".repeat(5)).unwrap();
+                assert_eq!(page.slots[0].offset, offsets[hole], "{:?}", policy);
+                assert_eq!(page.read_value(0).unwrap(), "y".repeat(5));
+            }
+        }
+
+        #[test]
+        fn too_many_regions_compact_the_page() {
+            let mut page = create_page(4096);
+            page.set_free_list_policy(FitPolicy::BestFit, 2);
+            for i in 0..10 {
+                page.insert(i, &(i as i64), &format!("value_{:03}", i))
+                    .unwrap();
+            }
+            for i in [7, 5, 3] {
+                page.delete(i).unwrap();
+                assert!(page.free_list.len() <= 2);
+            }
+            assert!(page.free_list.is_empty());
+
+            let keys: Vec<i64> = (0..page.num_keys as usize)
+                .map(|i| page.read_key(i).unwrap())
+                .collect();
+            assert_eq!(keys, vec![0, 1, 2, 4, 6, 8, 9]);
+            assert_eq!(page.read_value(3).unwrap(), "value_004");
+        }
+
+        #[test]
+        fn free_regions_take_room_from_new_entries() {
+            let mut page = create_page(4096);
+            for i in 0..4 {
+                page.insert(i, &(i as i64), &"value".to_string()).unwrap();
+            }
+            let before = page.free_space();
+            page.delete(1).unwrap();
+            assert_eq!(page.free_list.len(), 1);
+            assert_eq!(
+                page.free_space(),
+                before + Slot::SIZE - FreeSpaceRegion::SIZE
+            );
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Space Accounting Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod space_accounting {
+        use super::*;
+
+        #[test]
+        fn shrinking_every_entry_of_a_full_page_keeps_it_writable() {
+            let mut page = create_page(512);
+            page.set_free_list_policy(FitPolicy::BestFit, usize::MAX);
+            while page.can_insert(8, 8 + 12) {
+                let pos = page.num_keys as usize;
+                page.insert(pos, &(pos as i64), &"x".repeat(12)).unwrap();
+            }
+            let keys = page.num_keys as usize;
+            // Each leaves a hole that merges with no other, so the free list would grow over
+            // the data if the page did not compact once it runs out of gap
+            for i in 0..keys {
+                page.update(i, &(i as i64), &"y".to_string()).unwrap();
+                assert!(page.metadata_size() <= page.free_space_end as usize);
+            }
+
+            let restored =
+                SlottedPage::<i64, String>::deserialize(&page.serialize().unwrap(), 512).unwrap();
+            for i in 0..keys {
+                assert_eq!(restored.read_value(i).unwrap(), "y");
+            }
+        }
+
+        #[test]
+        fn a_free_region_is_not_used_without_room_for_the_slot() {
+            let mut page = create_page(512);
+            for i in 0..4 {
+                page.insert(i, &(i as i64), &"x".repeat(40)).unwrap();
+            }
+            page.update(0, &0, &String::new()).unwrap();
+            assert_eq!(page.free_list[0].length, 40);
+
+            // Key, value length and slot, leaving a gap too small for another slot
+            let gap = page.free_space();
+            page.insert(4, &4, &"v".repeat(gap - 8 - 8 - Slot::SIZE - 5))
+                .unwrap();
+            assert_eq!(page.free_space(), 5);
+
+            let free_before = page.total_free;
+            let result = page.insert(0, &-1, &"z".repeat(10));
+            assert!(matches!(result, Err(BTreeError::PageOverflow { .. })));
+            assert_eq!(page.total_free, free_before);
+
+            // Filling the region exactly takes it off the free list, which makes room
+            page.insert(0, &-1, &"z".repeat(24)).unwrap();
+            assert!(page.free_list.is_empty());
+            page.serialize().unwrap();
+        }
+
+        #[test]
+        fn replacing_a_key_of_a_full_internal_node_needs_no_pointer_room() {
+            let mut page = SlottedPage::<i64, String>::new(0, NodeType::INTERNAL, 512);
+            page.insert_pointer(0, 10, 0);
+            let mut i = 0;
+            while page.can_insert(8, 8 + 12) {
+                page.insert(i, &(i as i64), &"x".repeat(12)).unwrap();
+                page.insert_pointer(i + 1, 11 + i as u64, 0);
+                i += 1;
+            }
+
+            page.delete(0).unwrap();
+            page.insert(0, &0, &"w".repeat(12)).unwrap();
+            assert_eq!(page.pointers.len(), page.slots.len() + 1);
+            assert!(!page.can_insert(8, 8 + 12));
+            page.serialize().unwrap();
+        }
+
+        #[test]
+        fn split_needs_two_entries() {
+            let mut page = create_page(256);
+            assert!(matches!(
+                page.split(99),
+                Err(BTreeError::PageOverflow { page_id: 0 })
+            ));
+            page.insert(0, &1, &"value".to_string()).unwrap();
+            assert!(matches!(
+                page.split(99),
+                Err(BTreeError::PageOverflow { page_id: 0 })
+            ));
+            assert_eq!(page.num_keys, 1);
+        }
+    }
+
+    // ─────────────────────────────────────────────────────────
+    // Standalone Page Tests
+    // ─────────────────────────────────────────────────────────
+
+    mod standalone {
+        use super::*;
+
+        #[test]
+        fn sorted_inserts_are_found_and_iterated_in_order() {
+            let mut page = SlottedPage::<i64, String>::builder(3, 512).build();
+            for key in [5, 1, 3, 1] {
+                page.insert_sorted(&key, &format!("v{}", key)).unwrap();
+            }
+            page.tombstone(2);
+
+            assert_eq!(page.len(), 4);
+            assert_eq!(page.get(&1).unwrap(), Some("v1".to_string()));
+            assert_eq!(page.get(&3).unwrap(), None);
+            assert_eq!(page.get(&4).unwrap(), None);
+            let entries: Vec<(i64, String)> = page.iter().map(|e| e.unwrap()).collect();
+            assert_eq!(
+                entries,
+                vec![(1, "v1".into()), (1, "v1".into()), (5, "v5".into())]
+            );
+        }
+
+        #[test]
+        fn built_pages_keep_their_settings_when_read_back() {
+            let mut page = SlottedPage::<i64, String>::builder(9, 256)
+                .with_node_type(NodeType::INTERNAL)
+                .with_checksum(false)
+                .with_eytzinger_layout(true)
+                .build();
+            page.insert_pointer(0, 100, 0);
+            for key in 0..4 {
+                page.insert_sorted(&key, &"value".to_string()).unwrap();
+                page.insert_pointer(key as usize + 1, 101 + key as u64, 0);
+            }
+
+            let read = SlottedPage::<i64, String>::from_buffer(page.serialize().unwrap()).unwrap();
+            assert_eq!(read.page_id, 9);
+            assert_eq!(read.node_type, NodeType::INTERNAL);
+            assert!(!read.is_checksummed());
+            assert!(read.is_eytzinger());
+            assert_eq!(read.pointers, vec![100, 101, 102, 103, 104]);
+            assert_eq!(read.iter().count(), 4);
+        }
+    }
 }
@@ -0,0 +1,744 @@
+use crate::btree::{BTree, Cursor};
+use crate::codec::{Codecs, ValueCodec};
+use crate::compression::ValueCompressor;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use crate::header::Header;
+use crate::slotted_page::SlottedPage;
+use crate::stats::TreeStats;
+use crate::storage::{self, Storage};
+use crate::types::{self, NodeType, PAGE_PREFIX_SIZE};
+use crate::value_log::ValueLogPin;
+use log::info;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::ops::{Range, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
+
+/// How an entry differs between two snapshots, as found by [`Snapshot::diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<K, V> {
+    Added { key: K, value: V },
+    Removed { key: K, value: V },
+    Updated { key: K, old: V, new: V },
+}
+
+/// Immutable copy of a tree taken by [`BTree::freeze`](crate::BTree::freeze), or opened from
+/// a file written by [`Snapshot::export`].
+///
+/// The tree's pages are copied into a private temporary file that is memory-mapped, so reads
+/// take `&self`, need no locking and never touch the live tree's page cache. A snapshot can be
+/// shared between threads while the tree it came from keeps accepting writes.
+pub struct Snapshot<K, V> {
+    pages: Pages,
+    root_page_id: u64,
+    stats: TreeStats,
+    // Configuration of the tree the snapshot was taken of, which exports are written with
+    config: TreeConfig,
+    codecs: Codecs<V>,
+    compressor: Option<Arc<ValueCompressor>>,
+    // Keeps the value log segments the snapshot's pages point into from being removed
+    value_log: Option<ValueLogPin>,
+    _phantom: PhantomData<fn() -> (K, V)>,
+}
+
+// Where a snapshot reads its pages from
+enum Pages {
+    // Pages copied by `freeze`. Where each lies within `map` is recorded as it is copied, as
+    // leaves may be longer than other pages
+    Copied {
+        map: Mmap,
+        extents: HashMap<u64, Range<usize>>,
+    },
+    // Pages of an exported tree file, laid out as the page manager lays them out and read as
+    // they are needed
+    Stored {
+        storage: Box<dyn Storage + Sync>,
+        header: Header,
+        size: u64,
+    },
+}
+
+impl<K, V> Snapshot<K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    pub(crate) fn new(
+        file: File,
+        extents: HashMap<u64, Range<usize>>,
+        header: &Header,
+        stats: TreeStats,
+        value_codec: Option<Arc<dyn ValueCodec<V>>>,
+        compressor: Option<Arc<ValueCompressor>>,
+        value_log: Option<ValueLogPin>,
+    ) -> Result<Self, BTreeError> {
+        // SAFETY: `file` is an unnamed temporary file that nothing else can open, and the
+        // snapshot never writes to it, so the mapped bytes cannot change underneath us.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Snapshot {
+            pages: Pages::Copied { map, extents },
+            root_page_id: header.root_page_id,
+            stats,
+            config: header.config(),
+            codecs: Codecs::new(header.encoding, header.value_codec_id, value_codec),
+            compressor,
+            value_log,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Opens a file written by [`export`](Self::export) as a snapshot. The file is only ever
+    /// read and never locked, so any number of processes can open it at once, but it must not
+    /// be modified while open.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BTreeError> {
+        Self::open_storage(File::open(path.as_ref())?)
+    }
+
+    /// Opens an exported tree file held in `storage` as a snapshot, such as one published on
+    /// a web server and read through an [`HttpRangeStorage`](crate::http_range::HttpRangeStorage).
+    /// Only the header and stats are read up front; every other page is read from `storage`
+    /// each time it is needed, so `storage` should cache what it reads if that is slow.
+    pub fn open_storage<S: Storage + Sync + 'static>(storage: S) -> Result<Self, BTreeError> {
+        let size = storage.size()?;
+        let mut buffer = [0; Header::SIZE];
+        storage.read_exact_at(&mut buffer, 0)?;
+        let header = Header::deserialize(&buffer)?;
+        header.validate(header.page_size)?;
+        if header.value_codec_id != 0 || header.has_dictionary() {
+            return Err(BTreeError::Unsupported(
+                "only files written by Snapshot::export can be opened as snapshots".to_string(),
+            ));
+        }
+        let stats_page_id = header.has_stats().then_some(header.stats_page_id);
+        let mut snapshot = Snapshot {
+            root_page_id: header.root_page_id,
+            stats: TreeStats::default(),
+            config: header.config(),
+            codecs: Codecs::new(header.encoding, 0, None),
+            compressor: None,
+            value_log: None,
+            pages: Pages::Stored {
+                storage: Box::new(storage),
+                header,
+                size,
+            },
+            _phantom: PhantomData,
+        };
+        if let Some(page_id) = stats_page_id {
+            let page = snapshot.stored_pages(page_id, 1)?;
+            snapshot.stats = TreeStats::deserialize(&page[PAGE_PREFIX_SIZE..]);
+        }
+        info!(
+            "Opened snapshot of {} entries from {} bytes of storage",
+            snapshot.stats.entries, size
+        );
+        Ok(snapshot)
+    }
+
+    /// Writes the snapshot to `path` as a compacted tree file of its own, replacing any file
+    /// there, to be opened read-only with [`open`](Self::open) on this machine or another.
+    ///
+    /// The entries are bulk loaded into full pages, so the file holds no free space, and every
+    /// value is stored in the pages uncompressed and encoded with the tree's encoding alone,
+    /// so the file needs neither a value log, a compression dictionary nor a value codec
+    /// beside it. Fails if a value does not fit in a page without the value log. The file is
+    /// built beside `path` and renamed into place once durable.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Result<(), BTreeError> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let temp = tempfile::Builder::new()
+            .prefix(".exporting-")
+            .tempfile_in(dir)?;
+        let config = TreeConfig {
+            leaf_fill_factor: 100,
+            internal_fill_factor: 100,
+            value_log_threshold: 0,
+            ..self.config
+        };
+        let mut export = BTree::<K, V>::with_config(temp.reopen()?, config)?;
+        let mut error = None;
+        let entries = self
+            .iter()?
+            .map_while(|entry| entry.map_err(|e| error = Some(e)).ok());
+        let loaded = export.bulk_load(entries)?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        export.trim_free_pages()?;
+        export.flush()?;
+        drop(export);
+
+        temp.persist(path).map_err(|e| e.error)?;
+        storage::sync_dir(dir)?;
+        info!("Exported {} entries to {:?}", loaded, path);
+        Ok(())
+    }
+
+    fn page_bytes(&self, page_id: u64) -> Result<Cow<'_, [u8]>, BTreeError> {
+        match &self.pages {
+            Pages::Copied { map, extents } => {
+                let extent = extents.get(&page_id).cloned().ok_or_else(|| {
+                    BTreeError::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("Page {} is not part of the snapshot", page_id),
+                    ))
+                })?;
+                Ok(Cow::Borrowed(&map[extent]))
+            }
+            Pages::Stored { header, .. } => {
+                let mut bytes = self.stored_pages(page_id, 1)?;
+                let (_, type_byte) = types::read_page_prefix(&bytes);
+                let pages = NodeType::from_byte(type_byte).map_or(1, |t| header.node_pages(t));
+                if pages > 1 {
+                    bytes = self.stored_pages(page_id, pages)?;
+                }
+                Ok(Cow::Owned(bytes))
+            }
+        }
+    }
+
+    // Reads the `n` pages of an exported file starting at `page_id`
+    fn stored_pages(&self, page_id: u64, n: u64) -> Result<Vec<u8>, BTreeError> {
+        let Pages::Stored {
+            storage, header, ..
+        } = &self.pages
+        else {
+            unreachable!("only exported files are read page by page");
+        };
+        let offset = page_id
+            .checked_mul(header.page_size)
+            .and_then(|offset| offset.checked_add(Header::SIZE as u64))
+            .ok_or_else(|| BTreeError::Corrupted {
+                page_id,
+                reason: "page lies past any possible end of the file".to_string(),
+            })?;
+        let mut bytes = vec![0; (n * header.page_size) as usize];
+        storage.read_exact_at(&mut bytes, offset)?;
+        Ok(bytes)
+    }
+
+    fn read_page(&self, page_id: u64) -> Result<SlottedPage<K, V>, BTreeError> {
+        let bytes = self.page_bytes(page_id)?;
+        let mut node = SlottedPage::deserialize(&bytes, bytes.len())?;
+        node.set_codecs(self.codecs.clone());
+        node.set_compressor(self.compressor.clone());
+        node.set_value_log(self.value_log.as_ref().map(|pin| pin.log().clone()), 0);
+        Ok(node)
+    }
+
+    pub fn get(&self, key: &K) -> Result<V, BTreeError> {
+        let mut page_id = self.root_page_id;
+        loop {
+            let node = self.read_page(page_id)?;
+            match node.node_type {
+                NodeType::INTERNAL => match node.find_exact_key(key)? {
+                    Some(key_pos) => return node.read_value(key_pos),
+                    None => page_id = node.get_pointer(key)?,
+                },
+                NodeType::LEAF => {
+                    let key_pos = node
+                        .find_live_key(key)?
+                        .ok_or(BTreeError::KeyNotFound(key.to_string()))?;
+                    return node.read_value(key_pos);
+                }
+                other => return Err(BTreeError::InvalidNodeType(other as u8)),
+            }
+        }
+    }
+
+    /// Iterates over the entries whose keys fall within `range`, in key order.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> Result<SnapshotRange<'_, K, V>, BTreeError> {
+        let cursor = Cursor::new(
+            self.root_page_id,
+            &range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            &mut |id| self.read_page(id),
+        )?;
+        Ok(SnapshotRange {
+            snapshot: self,
+            cursor,
+        })
+    }
+
+    /// Iterates over every entry in key order.
+    pub fn iter(&self) -> Result<SnapshotRange<'_, K, V>, BTreeError> {
+        self.range(..)
+    }
+
+    /// Number of entries in the tree when the snapshot was taken.
+    pub fn len(&self) -> u64 {
+        self.stats.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stats.entries == 0
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        self.stats
+    }
+
+    /// Bytes of tree pages copied into the snapshot, or of the file it was opened from.
+    pub fn size(&self) -> usize {
+        match &self.pages {
+            Pages::Copied { map, .. } => map.len(),
+            Pages::Stored { size, .. } => *size as usize,
+        }
+    }
+
+    /// Entries that differ between this snapshot and `newer`, a later one of the same tree, in
+    /// key order.
+    ///
+    /// Pages are compared as stored before any entries are decoded: a page identical in both
+    /// holds the same entries, and an internal node's children then cover the same keys. Where
+    /// two internal nodes differ, their children are paired up between the separators the two
+    /// share and compared in turn, so only leaves that changed, and children whose bounds
+    /// moved in a split or merge, are decoded and compared entry by entry.
+    pub fn diff(&self, newer: &Snapshot<K, V>) -> Result<Vec<Change<K, V>>, BTreeError>
+    where
+        V: PartialEq,
+    {
+        let mut changes = Vec::new();
+        self.diff_subtrees(newer, self.root_page_id, newer.root_page_id, &mut changes)?;
+        Ok(changes)
+    }
+
+    fn diff_subtrees(
+        &self,
+        newer: &Snapshot<K, V>,
+        old_page_id: u64,
+        new_page_id: u64,
+        changes: &mut Vec<Change<K, V>>,
+    ) -> Result<(), BTreeError>
+    where
+        V: PartialEq,
+    {
+        if self.page_bytes(old_page_id)? == newer.page_bytes(new_page_id)? {
+            // Same entries and children, though the children themselves may have changed
+            for child in self.read_page(old_page_id)?.pointers {
+                self.diff_subtrees(newer, child, child, changes)?;
+            }
+            return Ok(());
+        }
+
+        let old = self.read_page(old_page_id)?;
+        let new = newer.read_page(new_page_id)?;
+        if old.node_type != NodeType::INTERNAL || new.node_type != NodeType::INTERNAL {
+            return self.diff_ranges(
+                newer,
+                &old,
+                0..old.slots.len(),
+                &new,
+                0..new.slots.len(),
+                changes,
+            );
+        }
+
+        // Separators with the same key bound the same keys on both sides, so the children
+        // between each pair of them can be compared on their own
+        let (mut old_from, mut new_from) = (0, 0);
+        let (mut old_idx, mut new_idx) = (0, 0);
+        while old_idx < old.slots.len() && new_idx < new.slots.len() {
+            let old_key = old.read_key(old_idx)?;
+            let new_key = new.read_key(new_idx)?;
+            match old_key.partial_cmp(&new_key).unwrap_or(Ordering::Equal) {
+                Ordering::Less => old_idx += 1,
+                Ordering::Greater => new_idx += 1,
+                Ordering::Equal => {
+                    self.diff_ranges(
+                        newer,
+                        &old,
+                        old_from..old_idx,
+                        &new,
+                        new_from..new_idx,
+                        changes,
+                    )?;
+                    let old_entry = (!old.is_tombstoned(old_idx))
+                        .then(|| old.read_key_value(old_idx))
+                        .transpose()?;
+                    let new_entry = (!new.is_tombstoned(new_idx))
+                        .then(|| new.read_key_value(new_idx))
+                        .transpose()?;
+                    diff_entries(
+                        old_entry.into_iter().collect(),
+                        new_entry.into_iter().collect(),
+                        changes,
+                    );
+                    old_idx += 1;
+                    new_idx += 1;
+                    (old_from, new_from) = (old_idx, new_idx);
+                }
+            }
+        }
+        self.diff_ranges(
+            newer,
+            &old,
+            old_from..old.slots.len(),
+            &new,
+            new_from..new.slots.len(),
+            changes,
+        )
+    }
+
+    // Compares the entries of `old` and `new` at the indexes in `old_range` and `new_range`
+    // along with the children around them, pairing the two children up when each range holds
+    // no entries and only decoding them otherwise
+    fn diff_ranges(
+        &self,
+        newer: &Snapshot<K, V>,
+        old: &SlottedPage<K, V>,
+        old_range: Range<usize>,
+        new: &SlottedPage<K, V>,
+        new_range: Range<usize>,
+        changes: &mut Vec<Change<K, V>>,
+    ) -> Result<(), BTreeError>
+    where
+        V: PartialEq,
+    {
+        if old_range.is_empty()
+            && new_range.is_empty()
+            && let (Some(&old_child), Some(&new_child)) = (
+                old.pointers.get(old_range.start),
+                new.pointers.get(new_range.start),
+            )
+        {
+            return self.diff_subtrees(newer, old_child, new_child, changes);
+        }
+
+        let mut old_entries = Vec::new();
+        self.collect_range(old, old_range, &mut old_entries)?;
+        let mut new_entries = Vec::new();
+        newer.collect_range(new, new_range, &mut new_entries)?;
+        diff_entries(old_entries, new_entries, changes);
+        Ok(())
+    }
+
+    // Appends the live entries beneath `page_id` to `entries` in key order
+    fn collect_entries(&self, page_id: u64, entries: &mut Vec<(K, V)>) -> Result<(), BTreeError> {
+        let node = self.read_page(page_id)?;
+        self.collect_range(&node, 0..node.slots.len(), entries)
+    }
+
+    // Appends the live entries of `node` at the indexes in `range`, and those beneath the
+    // children around them, to `entries` in key order
+    fn collect_range(
+        &self,
+        node: &SlottedPage<K, V>,
+        range: Range<usize>,
+        entries: &mut Vec<(K, V)>,
+    ) -> Result<(), BTreeError> {
+        for idx in range.clone() {
+            if let Some(&child) = node.pointers.get(idx) {
+                self.collect_entries(child, entries)?;
+            }
+            if !node.is_tombstoned(idx) {
+                entries.push(node.read_key_value(idx)?);
+            }
+        }
+        if let Some(&last) = node.pointers.get(range.end) {
+            self.collect_entries(last, entries)?;
+        }
+        Ok(())
+    }
+}
+
+// Merges two runs of entries sorted by key into the changes that turn `old` into `new`
+fn diff_entries<K: PartialOrd, V: PartialEq>(
+    old: Vec<(K, V)>,
+    new: Vec<(K, V)>,
+    changes: &mut Vec<Change<K, V>>,
+) {
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    loop {
+        // Which side holds the smaller key next; the other side has none left to compare with
+        let order = match (old.peek(), new.peek()) {
+            (None, None) => return,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((old_key, _)), Some((new_key, _))) => {
+                old_key.partial_cmp(new_key).unwrap_or(Ordering::Equal)
+            }
+        };
+        match order {
+            Ordering::Less => {
+                let (key, value) = old.next().unwrap();
+                changes.push(Change::Removed { key, value });
+            }
+            Ordering::Greater => {
+                let (key, value) = new.next().unwrap();
+                changes.push(Change::Added { key, value });
+            }
+            Ordering::Equal => {
+                let (key, old_value) = old.next().unwrap();
+                let (_, new_value) = new.next().unwrap();
+                if old_value != new_value {
+                    changes.push(Change::Updated {
+                        key,
+                        old: old_value,
+                        new: new_value,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over a key range of a [`Snapshot`], returned by [`Snapshot::range`].
+pub struct SnapshotRange<'a, K, V> {
+    snapshot: &'a Snapshot<K, V>,
+    cursor: Cursor<K, V>,
+}
+
+impl<K, V> Iterator for SnapshotRange<'_, K, V>
+where
+    K: Clone + PartialOrd + Debug + Serialize + for<'de> Deserialize<'de> + ToString,
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    type Item = Result<(K, V), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let snapshot = self.snapshot;
+        match self.cursor.advance(&mut |id| snapshot.read_page(id)) {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.cursor.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTree;
+    use crate::config::TreeConfig;
+    use crate::error::BTreeError;
+
+    fn filled_tree(entries: i64) -> BTree<i64, String> {
+        let mut btree = BTree::in_memory(TreeConfig::with_page_size(256)).unwrap();
+        for i in 0..entries {
+            btree.insert(i, format!("value-{}", i)).unwrap();
+        }
+        btree
+    }
+
+    // Pages reachable from the root of `snapshot`
+    fn tree_pages(snapshot: &Snapshot<i64, String>) -> usize {
+        let mut pending = vec![snapshot.root_page_id];
+        let mut pages = 0;
+        while let Some(page_id) = pending.pop() {
+            pending.extend(snapshot.read_page(page_id).unwrap().pointers);
+            pages += 1;
+        }
+        pages
+    }
+
+    #[test]
+    fn snapshot_ignores_later_writes() {
+        let mut btree = filled_tree(300);
+        let snapshot = btree.freeze().unwrap();
+
+        for i in 0..300 {
+            btree.insert(i, "changed".to_string()).unwrap();
+        }
+        btree.delete(7).unwrap();
+        btree.insert(1000, "new".to_string()).unwrap();
+
+        assert_eq!(snapshot.len(), 300);
+        assert_eq!(snapshot.get(&7).unwrap(), "value-7");
+        assert!(matches!(
+            snapshot.get(&1000),
+            Err(BTreeError::KeyNotFound(_))
+        ));
+        assert_eq!(btree.search(8).unwrap(), "changed");
+    }
+
+    #[test]
+    fn snapshot_scans_in_key_order() {
+        let mut btree = filled_tree(300);
+        let snapshot = btree.freeze().unwrap();
+
+        let keys: Vec<i64> = snapshot.iter().unwrap().map(|e| e.unwrap().0).collect();
+        assert_eq!(keys, (0..300).collect::<Vec<_>>());
+
+        let values: Vec<String> = snapshot
+            .range(10..13)
+            .unwrap()
+            .map(|e| e.unwrap().1)
+            .collect();
+        assert_eq!(values, vec!["value-10", "value-11", "value-12"]);
+    }
+
+    #[test]
+    fn snapshot_can_be_read_from_other_threads() {
+        let mut btree = filled_tree(200);
+        let snapshot = std::sync::Arc::new(btree.freeze().unwrap());
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let snapshot = snapshot.clone();
+                std::thread::spawn(move || {
+                    for i in (t..200).step_by(4) {
+                        assert_eq!(snapshot.get(&i).unwrap(), format!("value-{}", i));
+                    }
+                    snapshot.iter().unwrap().count()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 200);
+        }
+    }
+
+    #[test]
+    fn export_is_a_compact_single_file_that_opens_read_only() {
+        let mut btree = BTree::<i64, String>::in_memory(TreeConfig {
+            value_log_threshold: 64,
+            ..TreeConfig::with_page_size(512)
+        })
+        .unwrap();
+        for i in 0..500 {
+            let value = match i % 50 {
+                0 => "x".repeat(100),
+                _ => format!("value-{}", i),
+            };
+            btree.insert(i, value).unwrap();
+        }
+        for i in (0..500).step_by(3) {
+            btree.delete(i).unwrap();
+        }
+        let snapshot = btree.freeze().unwrap();
+        btree.insert(1000, "after".to_string()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reference.db");
+        snapshot.export(&path).unwrap();
+        // Nothing else is needed beside the file
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let exported = Snapshot::<i64, String>::open(&path).unwrap();
+        assert_eq!(exported.len(), snapshot.len());
+        let entries =
+            |s: &Snapshot<i64, String>| s.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<_>>();
+        assert_eq!(entries(&exported), entries(&snapshot));
+        assert_eq!(exported.get(&50).unwrap(), "x".repeat(100));
+        assert!(exported.get(&1000).is_err());
+        // Pages are packed, and the file holds nothing but them and the stats page
+        let file_size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert_eq!(file_size, Header::SIZE + 512 * (tree_pages(&exported) + 1));
+        assert!(tree_pages(&exported) < tree_pages(&snapshot));
+
+        // Exporting again replaces the file
+        filled_tree(10).freeze().unwrap().export(&path).unwrap();
+        assert_eq!(Snapshot::<i64, String>::open(&path).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn snapshot_of_empty_tree() {
+        let mut btree = filled_tree(0);
+        let snapshot = btree.freeze().unwrap();
+
+        assert!(snapshot.is_empty());
+        assert!(snapshot.iter().unwrap().next().is_none());
+        assert_eq!(snapshot.size(), 256);
+    }
+
+    #[test]
+    fn diff_finds_added_removed_and_updated_keys() {
+        let mut btree = filled_tree(300);
+        let before = btree.freeze().unwrap();
+        btree.insert(5, "changed".to_string()).unwrap();
+        btree.insert(250, "value-250".to_string()).unwrap();
+        btree.delete(120).unwrap();
+        btree.insert(1000, "new".to_string()).unwrap();
+        let after = btree.freeze().unwrap();
+
+        assert_eq!(
+            before.diff(&after).unwrap(),
+            vec![
+                Change::Updated {
+                    key: 5,
+                    old: "value-5".to_string(),
+                    new: "changed".to_string()
+                },
+                Change::Removed {
+                    key: 120,
+                    value: "value-120".to_string()
+                },
+                Change::Added {
+                    key: 1000,
+                    value: "new".to_string()
+                },
+            ]
+        );
+        assert!(after.diff(&after).unwrap().is_empty());
+        assert_eq!(after.diff(&before).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn diff_matches_a_full_comparison() {
+        let mut btree = filled_tree(500);
+        let before = btree.freeze().unwrap();
+        for i in (0..500).step_by(37) {
+            btree.delete(i).unwrap();
+        }
+        for i in 600..700 {
+            btree.insert(i, format!("value-{}", i)).unwrap();
+        }
+        let after = btree.freeze().unwrap();
+
+        let old: Vec<_> = before.iter().unwrap().map(|e| e.unwrap()).collect();
+        let new: Vec<_> = after.iter().unwrap().map(|e| e.unwrap()).collect();
+        let mut expected = Vec::new();
+        diff_entries(old, new, &mut expected);
+        assert_eq!(before.diff(&after).unwrap(), expected);
+        assert_eq!(expected.len(), 14 + 100);
+    }
+
+    #[test]
+    fn diff_pairs_up_children_of_rewritten_nodes() {
+        // Counts in internal nodes change with every write below them
+        let mut btree = BTree::in_memory(TreeConfig {
+            subtree_counts: true,
+            ..TreeConfig::with_page_size(256)
+        })
+        .unwrap();
+        for i in 0..500 {
+            btree.insert(i * 2, format!("value-{}", i)).unwrap();
+        }
+        let before = btree.freeze().unwrap();
+        btree.insert(101, "new".to_string()).unwrap();
+        btree.insert(400, "changed".to_string()).unwrap();
+        btree.delete(802).unwrap();
+        // Enough inserts in one place to split nodes, moving separators
+        for i in 0..40 {
+            btree.insert(601 + i * 2, format!("split-{}", i)).unwrap();
+        }
+        let after = btree.freeze().unwrap();
+
+        let old: Vec<_> = before.iter().unwrap().map(|e| e.unwrap()).collect();
+        let new: Vec<_> = after.iter().unwrap().map(|e| e.unwrap()).collect();
+        let mut expected = Vec::new();
+        diff_entries(old, new, &mut expected);
+        assert_eq!(expected.len(), 3 + 40);
+        assert_eq!(before.diff(&after).unwrap(), expected);
+        assert_eq!(after.diff(&before).unwrap().len(), expected.len());
+    }
+}
@@ -0,0 +1,87 @@
+/// Running totals describing the contents of a tree, kept in its stats page so they survive a
+/// reopen without scanning every leaf.
+///
+/// Sizes are of the serialized keys and values before compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    pub entries: u64,
+    pub key_bytes: u64,
+    pub value_bytes: u64,
+    /// Successful deletes over the life of the tree, including those made by draining.
+    pub deletes: u64,
+    /// Writes committed over the life of the tree, which is the LSN of the latest one.
+    pub commits: u64,
+    /// Bytes of storage the tree currently takes up. Filled in by `BTree::stats` rather than
+    /// persisted.
+    pub file_size: u64,
+    /// Size limit of the open handle, or 0 without one. Not persisted either.
+    pub max_file_size: u64,
+}
+
+impl TreeStats {
+    pub const SIZE: usize = 40;
+
+    pub fn add_entry(&mut self, key_size: u64, value_size: u64) {
+        self.entries += 1;
+        self.key_bytes += key_size;
+        self.value_bytes += value_size;
+    }
+
+    pub fn remove_entry(&mut self, key_size: u64, value_size: u64) {
+        self.entries = self.entries.saturating_sub(1);
+        self.key_bytes = self.key_bytes.saturating_sub(key_size);
+        self.value_bytes = self.value_bytes.saturating_sub(value_size);
+    }
+
+    pub fn serialize(&self) -> [u8; Self::SIZE] {
+        let mut buffer = [0u8; Self::SIZE];
+        buffer[0..8].copy_from_slice(&self.entries.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.key_bytes.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.value_bytes.to_le_bytes());
+        buffer[24..32].copy_from_slice(&self.deletes.to_le_bytes());
+        buffer[32..40].copy_from_slice(&self.commits.to_le_bytes());
+        buffer
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Self {
+        let read = |at: usize| u64::from_le_bytes(buffer[at..at + 8].try_into().unwrap());
+        TreeStats {
+            entries: read(0),
+            key_bytes: read(8),
+            value_bytes: read(16),
+            deletes: read(24),
+            // Zero in stats pages from before it was counted, which were zeroed past the deletes
+            commits: read(32),
+            ..TreeStats::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_roundtrip() {
+        let stats = TreeStats {
+            entries: 3,
+            key_bytes: 24,
+            value_bytes: u64::MAX,
+            deletes: 7,
+            commits: 12,
+            ..TreeStats::default()
+        };
+
+        assert_eq!(TreeStats::deserialize(&stats.serialize()), stats);
+    }
+
+    #[test]
+    fn removing_never_underflows() {
+        let mut stats = TreeStats::default();
+        stats.add_entry(8, 10);
+        stats.remove_entry(8, 10);
+        stats.remove_entry(8, 10);
+
+        assert_eq!(stats, TreeStats::default());
+    }
+}
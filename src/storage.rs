@@ -0,0 +1,236 @@
+use crate::io;
+use crate::sync::{Mutex, MutexGuard};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+
+/// Byte-addressed backing store for a tree's pages.
+///
+/// All access is positional, so implementations do not keep a cursor and a store can be shared
+/// without one reader moving another's position. Writes go through `&self` for the same reason;
+/// implementations that need it provide their own interior mutability. Failures are reported
+/// as [`io::Error`](crate::io::Error), which without `std` is an alloc-only stand-in, so a
+/// block device on a target without an OS can be a store too.
+pub trait Storage: Send {
+    /// Reads into `buf` starting at `offset`, returning fewer bytes than requested only when the
+    /// end of the store is reached.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Writes all of `buf` at `offset`, growing the store if needed.
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Current size of the store in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    fn set_size(&self, size: u64) -> io::Result<()>;
+
+    /// Makes every completed write durable.
+    fn sync(&self) -> io::Result<()>;
+
+    /// Reads exactly `buf.len()` bytes at `offset`.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if self.read_at(buf, offset)? < buf.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+// Windows' seek_read/seek_write still move the cursor, so nothing here may rely on its position.
+#[cfg(all(feature = "std", unix))]
+fn file_read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "std", windows))]
+fn file_read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(all(feature = "std", unix))]
+fn file_write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "std", windows))]
+fn file_write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
+
+/// Makes a rename or creation of a file in `dir` durable. Only Unix needs, or lets, a directory
+/// be synced: Windows refuses to open one as a file and records renames with the file itself.
+#[cfg(feature = "std")]
+pub(crate) fn sync_dir(dir: &std::path::Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Whether `file` was opened for reading and for writing, found by asking for empty transfers,
+/// which the OS refuses on a descriptor without that access without touching the file.
+#[cfg(feature = "std")]
+pub(crate) fn probe_access(file: &File) -> (bool, bool) {
+    let readable = file_read_at(file, &mut [], 0).is_ok();
+    let writable = file_write_at(file, &[], 0).is_ok();
+    (readable, writable)
+}
+
+#[cfg(feature = "std")]
+impl Storage for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            match file_read_at(self, &mut buf[total..], offset + total as u64) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
+    }
+
+    fn write_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match file_write_at(self, buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Keeps every page in memory. Nothing survives the store being dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    bytes: Mutex<Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bytes(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.bytes.lock()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.bytes();
+        let start = (offset as usize).min(bytes.len());
+        let end = (start + buf.len()).min(bytes.len());
+        buf[..end - start].copy_from_slice(&bytes[start..end]);
+        Ok(end - start)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut bytes = self.bytes();
+        let start = offset as usize;
+        let end = start + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.bytes().len() as u64)
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.bytes().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn roundtrip(storage: &dyn Storage) {
+        storage.write_at(b"hello", 10).unwrap();
+        assert_eq!(storage.size().unwrap(), 15);
+
+        let mut buf = [0u8; 5];
+        storage.read_exact_at(&mut buf, 10).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Bytes skipped over by a write read back as zeros
+        let mut gap = [0xFFu8; 10];
+        storage.read_exact_at(&mut gap, 0).unwrap();
+        assert_eq!(gap, [0u8; 10]);
+    }
+
+    fn short_read_at_end(storage: &dyn Storage) {
+        storage.write_at(b"abc", 0).unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(storage.read_at(&mut buf, 1).unwrap(), 2);
+        assert_eq!(&buf[..2], b"bc");
+        assert!(storage.read_exact_at(&mut buf, 0).is_err());
+    }
+
+    #[test]
+    fn file_storage_roundtrip() {
+        let file = NamedTempFile::new().unwrap();
+        roundtrip(file.as_file());
+    }
+
+    #[test]
+    fn memory_storage_roundtrip() {
+        roundtrip(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn file_storage_short_read() {
+        let file = NamedTempFile::new().unwrap();
+        short_read_at_end(file.as_file());
+    }
+
+    #[test]
+    fn memory_storage_short_read() {
+        short_read_at_end(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn set_size_truncates_and_extends() {
+        let storage = MemoryStorage::new();
+        storage.write_at(b"abcdef", 0).unwrap();
+
+        storage.set_size(3).unwrap();
+        assert_eq!(storage.size().unwrap(), 3);
+
+        storage.set_size(6).unwrap();
+        let mut buf = [0xFFu8; 6];
+        storage.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"abc\0\0\0");
+    }
+}
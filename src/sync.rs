@@ -0,0 +1,26 @@
+//! The mutex shared structures are locked with: std's where there is one and a spin lock
+//! otherwise. A lock whose holder panicked is taken anyway, as everything guarded by one is
+//! left consistent between statements.
+
+#[cfg(not(feature = "std"))]
+pub(crate) use spin::{Mutex, MutexGuard};
+
+#[cfg(feature = "std")]
+pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+
+#[cfg(feature = "std")]
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Mutex(std::sync::Mutex::new(value))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
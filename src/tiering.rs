@@ -0,0 +1,36 @@
+/// Decides which leaf pages live in the cold tier, based on how often each leaf was read since
+/// the previous rebalance.
+///
+/// Internal nodes always stay in the primary file since every descent passes through them.
+#[derive(Debug, Clone, Copy)]
+pub struct TieringPolicy {
+    /// Hot leaves read at most this many times are moved to the cold tier.
+    pub max_cold_accesses: u64,
+    /// Cold leaves read at least this many times are moved back to the primary file.
+    pub min_hot_accesses: u64,
+}
+
+impl Default for TieringPolicy {
+    fn default() -> Self {
+        TieringPolicy {
+            max_cold_accesses: 0,
+            min_hot_accesses: 4,
+        }
+    }
+}
+
+impl TieringPolicy {
+    pub fn should_demote(&self, accesses: u64) -> bool {
+        accesses <= self.max_cold_accesses
+    }
+
+    pub fn should_promote(&self, accesses: u64) -> bool {
+        accesses >= self.min_hot_accesses
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TieringReport {
+    pub demoted: usize,
+    pub promoted: usize,
+}
@@ -0,0 +1,225 @@
+//! A thin time-series layer over a [`BTree`], keeping the points of many series in one tree
+//! under composite [`SeriesKey`]s.
+//!
+//! Keys order by series and then by timestamp, so the points of a series are stored together
+//! in time order: a query for a span of time is a range scan, and dropping the points a
+//! retention policy no longer keeps is a range delete from the start of the series.
+//! Timestamps are plain integers, in whatever unit the caller picks; retention ages are in the
+//! same unit.
+
+use crate::btree::BTree;
+use crate::collections::HashMap;
+use crate::config::TreeConfig;
+use crate::error::BTreeError;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::ops::{Bound, RangeBounds};
+use log::debug;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Identifies a series among those kept in the same tree.
+pub type SeriesId = u64;
+
+/// Key of a point: its series, then its timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SeriesKey {
+    pub series: SeriesId,
+    pub timestamp: i64,
+}
+
+impl SeriesKey {
+    pub fn new(series: SeriesId, timestamp: i64) -> Self {
+        SeriesKey { series, timestamp }
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.series, self.timestamp)
+    }
+}
+
+/// How long the points of a series are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Points whose timestamp is more than this much older than the time retention is
+    /// enforced at are deleted.
+    pub max_age: i64,
+}
+
+pub struct TimeSeries<V> {
+    tree: BTree<SeriesKey, V>,
+    // Retention of series without a policy of their own
+    default_retention: Option<Retention>,
+    retention: HashMap<SeriesId, Retention>,
+}
+
+impl<V> TimeSeries<V>
+where
+    V: Clone + Debug + Serialize + for<'de> Deserialize<'de>,
+{
+    pub fn new(tree: BTree<SeriesKey, V>) -> Self {
+        TimeSeries {
+            tree,
+            default_retention: None,
+            retention: HashMap::new(),
+        }
+    }
+
+    /// Opens the series kept in the tree at `path`, creating it if needed.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P, config: TreeConfig) -> Result<Self, BTreeError> {
+        Ok(TimeSeries::new(BTree::open(path, config)?))
+    }
+
+    pub fn in_memory(config: TreeConfig) -> Result<Self, BTreeError> {
+        Ok(TimeSeries::new(BTree::in_memory(config)?))
+    }
+
+    /// Records `value` for `series` at `timestamp`, replacing any value already recorded at
+    /// that timestamp.
+    pub fn append(&mut self, series: SeriesId, timestamp: i64, value: V) -> Result<(), BTreeError> {
+        self.tree.insert(SeriesKey::new(series, timestamp), value)
+    }
+
+    /// The points of `series` whose timestamps fall within `range`, in time order.
+    pub fn query<R: RangeBounds<i64>>(
+        &mut self,
+        series: SeriesId,
+        range: R,
+    ) -> Result<Vec<(i64, V)>, BTreeError> {
+        self.tree
+            .range(Self::key_range(series, range))?
+            .map(|entry| entry.map(|(key, value)| (key.timestamp, value)))
+            .collect()
+    }
+
+    /// Deletes the points of `series` timestamped before `timestamp`, returning how many there
+    /// were.
+    pub fn delete_before(&mut self, series: SeriesId, timestamp: i64) -> Result<u64, BTreeError> {
+        let range = Self::key_range(series, ..timestamp);
+        let mut deleted = 0;
+        for entry in self.tree.drain_range(range) {
+            entry?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Keeps the points of `series` for `retention`, or for the default retention if `None`.
+    /// Policies are held by this handle only, so they must be set again after reopening.
+    pub fn set_retention(&mut self, series: SeriesId, retention: Option<Retention>) {
+        match retention {
+            Some(retention) => self.retention.insert(series, retention),
+            None => self.retention.remove(&series),
+        };
+    }
+
+    /// Keeps the points of every series without a policy of its own for `retention`, or
+    /// forever if `None`.
+    pub fn set_default_retention(&mut self, retention: Option<Retention>) {
+        self.default_retention = retention;
+    }
+
+    /// Deletes the points each series' retention no longer keeps as of `now`, returning how
+    /// many were deleted in all.
+    pub fn enforce_retention(&mut self, now: i64) -> Result<u64, BTreeError> {
+        let mut deleted = 0;
+        for series in self.series()? {
+            let retention = self
+                .retention
+                .get(&series)
+                .or(self.default_retention.as_ref());
+            if let Some(retention) = retention.copied() {
+                deleted += self.delete_before(series, now.saturating_sub(retention.max_age))?;
+            }
+        }
+        debug!("Retention deleted {} points", deleted);
+        Ok(deleted)
+    }
+
+    /// Every series holding at least one point, in order. Each is found by seeking past the
+    /// one before it, so this reads one path down the tree per series rather than every point.
+    pub fn series(&mut self) -> Result<Vec<SeriesId>, BTreeError> {
+        let mut series = Vec::new();
+        let mut start = Bound::Unbounded;
+        while let Some(entry) = self.tree.range((start, Bound::Unbounded))?.next() {
+            let id = entry?.0.series;
+            series.push(id);
+            let Some(next) = id.checked_add(1) else {
+                break;
+            };
+            start = Bound::Included(SeriesKey::new(next, i64::MIN));
+        }
+        Ok(series)
+    }
+
+    /// Makes every point appended or deleted so far durable.
+    pub fn flush(&mut self) -> Result<(), BTreeError> {
+        self.tree.flush()
+    }
+
+    /// The tree holding the series.
+    pub fn tree(&mut self) -> &mut BTree<SeriesKey, V> {
+        &mut self.tree
+    }
+
+    // The keys of `series` whose timestamps fall within `range`
+    fn key_range<R: RangeBounds<i64>>(
+        series: SeriesId,
+        range: R,
+    ) -> (Bound<SeriesKey>, Bound<SeriesKey>) {
+        let key = |timestamp: &i64| SeriesKey::new(series, *timestamp);
+        let start = match range.start_bound() {
+            Bound::Included(timestamp) => Bound::Included(key(timestamp)),
+            Bound::Excluded(timestamp) => Bound::Excluded(key(timestamp)),
+            Bound::Unbounded => Bound::Included(SeriesKey::new(series, i64::MIN)),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(timestamp) => Bound::Included(key(timestamp)),
+            Bound::Excluded(timestamp) => Bound::Excluded(key(timestamp)),
+            Bound::Unbounded => Bound::Included(SeriesKey::new(series, i64::MAX)),
+        };
+        (start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_and_retention_stay_within_their_series() {
+        let mut points = TimeSeries::<f64>::in_memory(TreeConfig::with_page_size(512)).unwrap();
+        for ts in 0..200 {
+            for series in [1, 2, 3] {
+                points
+                    .append(series, ts * 10, (series * 1000) as f64 + ts as f64)
+                    .unwrap();
+            }
+        }
+        assert_eq!(points.series().unwrap(), [1, 2, 3]);
+
+        let span = points.query(2, 100..=130).unwrap();
+        assert_eq!(
+            span,
+            [(100, 2010.0), (110, 2011.0), (120, 2012.0), (130, 2013.0)]
+        );
+        assert_eq!(points.query(3, ..).unwrap().len(), 200);
+        assert!(points.query(4, ..).unwrap().is_empty());
+
+        // Series 1 keeps its own, shorter history, series 3 the default, series 2 everything
+        points.set_default_retention(Some(Retention { max_age: 1000 }));
+        points.set_retention(1, Some(Retention { max_age: 500 }));
+        points.set_retention(2, Some(Retention { max_age: i64::MAX }));
+        let deleted = points.enforce_retention(2000).unwrap();
+        assert_eq!(deleted, 150 + 100);
+        assert_eq!(points.query(1, ..).unwrap()[0].0, 1500);
+        assert_eq!(points.query(2, ..).unwrap().len(), 200);
+        assert_eq!(points.query(3, ..).unwrap()[0].0, 1000);
+        assert_eq!(points.tree().len(), 50 + 200 + 100);
+        points.tree().verify().unwrap();
+    }
+}
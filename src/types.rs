@@ -1,17 +1,92 @@
 use serde::{Deserialize, Serialize};
 
+/// The kind of a page, stored in every page's prefix so any page in the file can be classified
+/// without following references to it.
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum NodeType {
     INTERNAL = 0,
     LEAF = 1,
+    /// Tree-wide metadata, such as the first page of the compression dictionary.
+    META = 2,
+    /// Continuation of data too large for the page that starts it.
+    OVERFLOW = 3,
+    /// No longer referenced by the tree.
+    FREE = 4,
+}
+
+impl NodeType {
+    pub fn from_byte(value: u8) -> Option<NodeType> {
+        match value {
+            0 => Some(NodeType::INTERNAL),
+            1 => Some(NodeType::LEAF),
+            2 => Some(NodeType::META),
+            3 => Some(NodeType::OVERFLOW),
+            4 => Some(NodeType::FREE),
+            _ => None,
+        }
+    }
+
+    /// Whether pages of this type hold tree entries.
+    pub fn is_tree_node(&self) -> bool {
+        matches!(self, NodeType::INTERNAL | NodeType::LEAF)
+    }
 }
 
 impl From<u8> for NodeType {
     fn from(value: u8) -> NodeType {
-        match value {
-            0 => NodeType::INTERNAL,
-            1 => NodeType::LEAF,
-            _ => panic!("Invalid node type"),
+        NodeType::from_byte(value).expect("Invalid node type")
+    }
+}
+
+/// Every page starts with its id (8 bytes) followed by its type (1 byte).
+pub const PAGE_PREFIX_SIZE: usize = 9;
+
+pub fn write_page_prefix(buffer: &mut [u8], page_id: u64, node_type: NodeType) {
+    buffer[0..8].copy_from_slice(&page_id.to_le_bytes());
+    buffer[8] = node_type as u8;
+}
+
+/// Returns the page id and type byte recorded at the start of `buffer`.
+pub fn read_page_prefix(buffer: &[u8]) -> (u64, u8) {
+    let page_id = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
+    (page_id, buffer[8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_type_byte_roundtrip() {
+        for node_type in [
+            NodeType::INTERNAL,
+            NodeType::LEAF,
+            NodeType::META,
+            NodeType::OVERFLOW,
+            NodeType::FREE,
+        ] {
+            assert_eq!(NodeType::from_byte(node_type as u8), Some(node_type));
         }
+        assert_eq!(NodeType::from_byte(5), None);
+    }
+
+    #[test]
+    fn only_internal_and_leaf_are_tree_nodes() {
+        assert!(NodeType::INTERNAL.is_tree_node());
+        assert!(NodeType::LEAF.is_tree_node());
+        assert!(!NodeType::META.is_tree_node());
+        assert!(!NodeType::OVERFLOW.is_tree_node());
+        assert!(!NodeType::FREE.is_tree_node());
+    }
+
+    #[test]
+    fn page_prefix_roundtrip() {
+        let mut buffer = [0u8; 32];
+        write_page_prefix(&mut buffer, 0xABCD, NodeType::OVERFLOW);
+
+        assert_eq!(
+            read_page_prefix(&buffer),
+            (0xABCD, NodeType::OVERFLOW as u8)
+        );
     }
 }
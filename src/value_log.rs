@@ -0,0 +1,626 @@
+//! Log of large values kept outside the tree's pages. An entry whose value is larger than the
+//! tree's threshold stores a [`ValuePointer`] instead, so splits and merges move a few bytes
+//! rather than the value itself.
+//!
+//! The log is split into segments that are only ever appended to. A value that is overwritten
+//! or deleted stays in its segment as garbage until the whole segment can be removed.
+
+use crate::checksum::crc32;
+use crate::clock::Instant;
+use crate::storage::{MemoryStorage, Storage};
+use crate::sync::{Mutex, MutexGuard};
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::debug;
+
+/// Where a value lives in the log: stored in the page in place of the value's bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValuePointer {
+    pub segment: u32,
+    /// Offset of the value's record within the segment.
+    pub offset: u32,
+    /// Length of the serialized value.
+    pub length: u32,
+}
+
+impl ValuePointer {
+    pub const SIZE: usize = 12;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buffer = [0u8; Self::SIZE];
+        buffer[0..4].copy_from_slice(&self.segment.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buffer
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() != Self::SIZE {
+            return None;
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(buffer[at..at + 4].try_into().unwrap());
+        Some(ValuePointer {
+            segment: read_u32(0),
+            offset: read_u32(4),
+            length: read_u32(8),
+        })
+    }
+
+    /// Bytes the value's record takes up in its segment, `key_length` being the length of the
+    /// serialized key stored alongside it.
+    pub fn record_length(&self, key_length: usize) -> u64 {
+        (RECORD_HEADER_SIZE + key_length) as u64 + self.length as u64
+    }
+}
+
+#[derive(Debug)]
+pub enum ValueLogError {
+    Io(crate::io::Error),
+    InvalidMagic {
+        segment: u32,
+    },
+    UnsupportedVersion {
+        segment: u32,
+        version: u16,
+    },
+    MissingSegment(u32),
+    Corrupted {
+        segment: u32,
+        offset: u32,
+    },
+    ValueTooLarge(usize),
+    /// The tree keeps large values in a value log, but none is attached to this handle.
+    NotAttached,
+}
+
+impl core::fmt::Display for ValueLogError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ValueLogError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            ValueLogError::InvalidMagic { segment } => {
+                write!(f, "Segment {} is not a value log segment", segment)
+            }
+            ValueLogError::UnsupportedVersion { segment, version } => {
+                write!(
+                    f,
+                    "Segment {} has unsupported value log version {}",
+                    segment, version
+                )
+            }
+            ValueLogError::MissingSegment(segment) => {
+                write!(f, "Value log segment {} does not exist", segment)
+            }
+            ValueLogError::Corrupted { segment, offset } => {
+                write!(
+                    f,
+                    "Value log record at offset {} of segment {} is corrupted",
+                    offset, segment
+                )
+            }
+            ValueLogError::ValueTooLarge(size) => {
+                write!(f, "Value of {} bytes is too large for the value log", size)
+            }
+            ValueLogError::NotAttached => {
+                write!(
+                    f,
+                    "The tree stores values in a value log, but none is attached"
+                )
+            }
+        }
+    }
+}
+
+impl From<crate::io::Error> for ValueLogError {
+    fn from(err: crate::io::Error) -> ValueLogError {
+        ValueLogError::Io(err)
+    }
+}
+
+/// Size of a segment of the value log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub id: u32,
+    pub size: u64,
+}
+
+/// What [`BTree::collect_value_log_garbage`](crate::BTree::collect_value_log_garbage) or
+/// [`BTree::compact_value_log`](crate::BTree::compact_value_log) found and removed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValueLogGc {
+    /// Segments whose live values were copied to the head of the log first.
+    pub segments_rewritten: Vec<u32>,
+    /// Bytes of the records copied.
+    pub bytes_rewritten: u64,
+    pub segments_removed: Vec<u32>,
+    pub bytes_reclaimed: u64,
+    /// Bytes of segments with no live values that were kept, as a snapshot may still read them.
+    pub bytes_held: u64,
+    /// Bytes of the records the tree still refers to.
+    pub live_bytes: u64,
+    /// Bytes of every segment left, live or not.
+    pub total_bytes: u64,
+}
+
+/// When [`BTree::compact_value_log`](crate::BTree::compact_value_log) rewrites the live values
+/// of mostly dead segments so the segments can be removed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueLogCompactionOptions {
+    /// How many times the bytes of live values the log may take up before segments are
+    /// rewritten. Below it, only segments with no live values left are removed.
+    pub max_space_amplification: f64,
+    /// Segments whose live values make up less than this fraction of them are rewritten.
+    pub max_live_ratio: f64,
+}
+
+impl Default for ValueLogCompactionOptions {
+    fn default() -> Self {
+        ValueLogCompactionOptions {
+            max_space_amplification: 2.0,
+            max_live_ratio: 0.5,
+        }
+    }
+}
+
+const MAGIC: [u8; 4] = *b"CLVL";
+const VERSION: u16 = 1;
+// magic, version and two reserved bytes
+const SEGMENT_HEADER_SIZE: u64 = 8;
+// CRC-32 of the rest of the record, key length and value length
+const RECORD_HEADER_SIZE: usize = 12;
+
+enum Backing {
+    Memory,
+    #[cfg(feature = "std")]
+    Directory(PathBuf),
+}
+
+struct Segments {
+    open: BTreeMap<u32, Box<dyn Storage>>,
+    // Segment appended to, created with the first append to the log
+    head: Option<(u32, u64)>,
+    // Segments written to since the last sync
+    unsynced: Vec<u32>,
+}
+
+/// Append-only store of values too large to keep in the tree's pages.
+pub struct ValueLog {
+    backing: Backing,
+    segment_size: u64,
+    segments: Mutex<Segments>,
+    // Snapshots that may still read values the tree no longer refers to, by when each was
+    // taken
+    pins: Mutex<BTreeMap<u64, Instant>>,
+}
+
+impl ValueLog {
+    /// A new segment is started once the current one would grow past this.
+    pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+    /// A log kept in memory, for trees created with `BTree::in_memory`.
+    pub fn in_memory() -> Self {
+        Self::with_backing(Backing::Memory, BTreeMap::new())
+    }
+
+    /// Where the value log of the database in `data_path` lives: a directory alongside it, with
+    /// `.vlog` appended to its name.
+    #[cfg(feature = "std")]
+    pub fn path_for(data_path: &Path) -> PathBuf {
+        let mut name = data_path.as_os_str().to_owned();
+        name.push(".vlog");
+        PathBuf::from(name)
+    }
+
+    /// Opens the log in directory `dir`, which is created with the first append if it does not
+    /// exist yet.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, ValueLogError> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut open: BTreeMap<u32, Box<dyn Storage>> = BTreeMap::new();
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let name = entry?.file_name();
+                let Some(id) = name
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".vlog"))
+                    .and_then(|id| id.parse().ok())
+                else {
+                    continue;
+                };
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(Self::segment_path(&dir, id))?;
+                check_segment_header(&file, id)?;
+                open.insert(id, Box::new(file));
+            }
+        }
+        debug!("Opened value log {:?} with {} segments", dir, open.len());
+        Ok(Self::with_backing(Backing::Directory(dir), open))
+    }
+
+    fn with_backing(backing: Backing, open: BTreeMap<u32, Box<dyn Storage>>) -> Self {
+        let head = open.iter().next_back().map(|(&id, segment)| {
+            let size = segment.size().unwrap_or(SEGMENT_HEADER_SIZE);
+            (id, size)
+        });
+        ValueLog {
+            backing,
+            segment_size: Self::DEFAULT_SEGMENT_SIZE,
+            segments: Mutex::new(Segments {
+                open,
+                head,
+                unsynced: Vec::new(),
+            }),
+            pins: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Starts new segments once the current one would grow past `segment_size` bytes, at most
+    /// 4 GiB.
+    pub fn with_segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size.min(u32::MAX as u64);
+        self
+    }
+
+    #[cfg(feature = "std")]
+    fn segment_path(dir: &Path, id: u32) -> PathBuf {
+        dir.join(format!("{:08}.vlog", id))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Segments> {
+        self.segments.lock()
+    }
+
+    fn create_segment(&self, id: u32) -> Result<Box<dyn Storage>, ValueLogError> {
+        let segment: Box<dyn Storage> = match &self.backing {
+            Backing::Memory => Box::new(MemoryStorage::new()),
+            #[cfg(feature = "std")]
+            Backing::Directory(dir) => {
+                fs::create_dir_all(dir)?;
+                Box::new(
+                    OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create_new(true)
+                        .open(Self::segment_path(dir, id))?,
+                )
+            }
+        };
+        let mut header = [0u8; SEGMENT_HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        segment.write_at(&header, 0)?;
+        debug!("Created value log segment {}", id);
+        Ok(segment)
+    }
+
+    /// Appends `value` along with the `key` it belongs to, which lets the log be checked
+    /// against the tree, and returns where it was written.
+    pub fn append(&self, key: &[u8], value: &[u8]) -> Result<ValuePointer, ValueLogError> {
+        if value.len() > u32::MAX as usize || key.len() > u32::MAX as usize {
+            return Err(ValueLogError::ValueTooLarge(value.len()));
+        }
+        let record_length = (RECORD_HEADER_SIZE + key.len() + value.len()) as u64;
+        if SEGMENT_HEADER_SIZE + record_length > u32::MAX as u64 {
+            return Err(ValueLogError::ValueTooLarge(value.len()));
+        }
+
+        let mut segments = self.lock();
+        let (id, size) = match segments.head {
+            Some((id, size))
+                if size == SEGMENT_HEADER_SIZE || size + record_length <= self.segment_size =>
+            {
+                (id, size)
+            }
+            head => {
+                let id = head.map_or(1, |(id, _)| id + 1);
+                let segment = self.create_segment(id)?;
+                segments.open.insert(id, segment);
+                debug!("Started value log segment {}", id);
+                (id, SEGMENT_HEADER_SIZE)
+            }
+        };
+
+        let mut record = Vec::with_capacity(record_length as usize);
+        record.extend_from_slice(&[0u8; 4]);
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        record.extend_from_slice(key);
+        record.extend_from_slice(value);
+        let checksum = crc32(&record[4..]);
+        record[0..4].copy_from_slice(&checksum.to_le_bytes());
+
+        segments.open[&id].write_at(&record, size)?;
+        segments.head = Some((id, size + record_length));
+        if !segments.unsynced.contains(&id) {
+            segments.unsynced.push(id);
+        }
+        Ok(ValuePointer {
+            segment: id,
+            offset: size as u32,
+            length: value.len() as u32,
+        })
+    }
+
+    /// Reads back the value `pointer` refers to.
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Vec<u8>, ValueLogError> {
+        let segments = self.lock();
+        let segment = segments
+            .open
+            .get(&pointer.segment)
+            .ok_or(ValueLogError::MissingSegment(pointer.segment))?;
+        let corrupted = ValueLogError::Corrupted {
+            segment: pointer.segment,
+            offset: pointer.offset,
+        };
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        segment
+            .read_exact_at(&mut header, pointer.offset as u64)
+            .map_err(|_| ValueLogError::Corrupted {
+                segment: pointer.segment,
+                offset: pointer.offset,
+            })?;
+        let key_length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let value_length = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if value_length != pointer.length {
+            return Err(corrupted);
+        }
+
+        let mut body = vec![0u8; key_length + value_length as usize];
+        if segment
+            .read_exact_at(
+                &mut body,
+                (pointer.offset as usize + RECORD_HEADER_SIZE) as u64,
+            )
+            .is_err()
+        {
+            return Err(corrupted);
+        }
+        let checksum = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let mut hashed = header[4..].to_vec();
+        hashed.extend_from_slice(&body);
+        if crc32(&hashed) != checksum {
+            return Err(corrupted);
+        }
+        Ok(body.split_off(key_length))
+    }
+
+    /// Makes every value appended so far durable.
+    pub fn sync(&self) -> Result<(), ValueLogError> {
+        let mut segments = self.lock();
+        let unsynced = core::mem::take(&mut segments.unsynced);
+        for id in unsynced {
+            if let Some(segment) = segments.open.get(&id) {
+                segment.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        let segments = self.lock();
+        segments
+            .open
+            .iter()
+            .map(|(&id, segment)| SegmentInfo {
+                id,
+                size: match segments.head {
+                    Some((head, size)) if head == id => size,
+                    _ => segment.size().unwrap_or(0),
+                },
+            })
+            .collect()
+    }
+
+    /// The segment values are currently appended to, which is never removed.
+    pub fn head_segment(&self) -> Option<u32> {
+        self.lock().head.map(|(id, _)| id)
+    }
+
+    /// Deletes segment `id`, whose values nothing may refer to any more. The head segment is
+    /// kept, since later values go there.
+    pub(crate) fn remove_segment(&self, id: u32) -> Result<bool, ValueLogError> {
+        let mut segments = self.lock();
+        if segments.head.is_some_and(|(head, _)| head == id) {
+            return Ok(false);
+        }
+        if segments.open.remove(&id).is_none() {
+            return Ok(false);
+        }
+        segments.unsynced.retain(|&unsynced| unsynced != id);
+        #[cfg(feature = "std")]
+        if let Backing::Directory(dir) = &self.backing {
+            fs::remove_file(Self::segment_path(dir, id))?;
+        }
+        debug!("Removed value log segment {}", id);
+        Ok(true)
+    }
+
+    /// Keeps segments from being removed until the returned pin is dropped, for readers of an
+    /// older version of the tree.
+    #[cfg(feature = "std")]
+    pub(crate) fn pin(self: &Arc<Self>) -> ValueLogPin {
+        let mut pins = self.lock_pins();
+        // Numbered past every pin still held, so they stay in the order they were taken
+        let id = pins.last_key_value().map_or(0, |(&id, _)| id + 1);
+        pins.insert(id, Instant::now());
+        drop(pins);
+        ValueLogPin {
+            log: self.clone(),
+            id,
+        }
+    }
+
+    fn lock_pins(&self) -> MutexGuard<'_, BTreeMap<u64, Instant>> {
+        self.pins.lock()
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        !self.lock_pins().is_empty()
+    }
+
+    /// Snapshots keeping segments from being removed.
+    pub fn pin_count(&self) -> usize {
+        self.lock_pins().len()
+    }
+
+    /// How long the oldest snapshot has kept segments from being removed.
+    pub fn oldest_pin_age(&self) -> Option<Duration> {
+        let pins = self.lock_pins();
+        pins.first_key_value().map(|(_, taken)| taken.elapsed())
+    }
+}
+
+#[cfg(feature = "std")]
+fn check_segment_header(file: &File, id: u32) -> Result<(), ValueLogError> {
+    let mut header = [0u8; SEGMENT_HEADER_SIZE as usize];
+    if file.read_exact_at(&mut header, 0).is_err() || header[0..4] != MAGIC {
+        return Err(ValueLogError::InvalidMagic { segment: id });
+    }
+    let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+    if version != VERSION {
+        return Err(ValueLogError::UnsupportedVersion {
+            segment: id,
+            version,
+        });
+    }
+    Ok(())
+}
+
+/// Held by a snapshot so the values it can see are not removed from under it.
+#[cfg(feature = "std")]
+pub(crate) struct ValueLogPin {
+    log: Arc<ValueLog>,
+    id: u64,
+}
+
+#[cfg(feature = "std")]
+impl ValueLogPin {
+    pub(crate) fn log(&self) -> &Arc<ValueLog> {
+        &self.log
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for ValueLogPin {
+    fn drop(&mut self) {
+        self.log.lock_pins().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_read_back() {
+        let log = ValueLog::in_memory();
+        let first = log.append(b"a", &[1u8; 1000]).unwrap();
+        let second = log.append(b"b", b"second").unwrap();
+
+        assert_eq!(log.read(&first).unwrap(), vec![1u8; 1000]);
+        assert_eq!(log.read(&second).unwrap(), b"second");
+        assert_eq!(ValuePointer::from_bytes(&second.to_bytes()), Some(second));
+    }
+
+    #[test]
+    fn full_segments_roll_over() {
+        let log = ValueLog::in_memory().with_segment_size(256);
+        let pointers: Vec<ValuePointer> = (0..10u8)
+            .map(|i| log.append(&[i], &[i; 100]).unwrap())
+            .collect();
+
+        assert!(log.segments().len() >= 5);
+        assert!(log.segments().iter().all(|segment| segment.size <= 256));
+        for (i, pointer) in pointers.iter().enumerate() {
+            assert_eq!(log.read(pointer).unwrap(), vec![i as u8; 100]);
+        }
+    }
+
+    #[test]
+    fn value_larger_than_a_segment_gets_its_own() {
+        let log = ValueLog::in_memory().with_segment_size(64);
+        let small = log.append(b"k", b"small").unwrap();
+        let large = log.append(b"k", &[7u8; 500]).unwrap();
+
+        assert_ne!(small.segment, large.segment);
+        assert_eq!(log.read(&large).unwrap().len(), 500);
+    }
+
+    #[test]
+    fn corrupted_record_is_detected() {
+        let log = ValueLog::in_memory();
+        let pointer = log.append(b"key", b"value").unwrap();
+        {
+            let segments = log.lock();
+            let segment = &segments.open[&pointer.segment];
+            segment.write_at(b"X", pointer.offset as u64 + 16).unwrap();
+        }
+
+        assert!(matches!(
+            log.read(&pointer),
+            Err(ValueLogError::Corrupted { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn segments_survive_reopening() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("vlog");
+        let (first, second) = {
+            let log = ValueLog::open(&path).unwrap().with_segment_size(128);
+            let first = log.append(b"a", &[1u8; 100]).unwrap();
+            let second = log.append(b"b", &[2u8; 100]).unwrap();
+            log.sync().unwrap();
+            (first, second)
+        };
+
+        let log = ValueLog::open(&path).unwrap();
+        assert_eq!(log.segments().len(), 2);
+        assert_eq!(log.read(&first).unwrap(), vec![1u8; 100]);
+        assert_eq!(log.read(&second).unwrap(), vec![2u8; 100]);
+        let third = log.append(b"c", b"more").unwrap();
+        assert_eq!(third.segment, second.segment);
+    }
+
+    #[test]
+    fn head_segment_is_never_removed() {
+        let log = Arc::new(ValueLog::in_memory().with_segment_size(64));
+        let old = log.append(b"a", &[1u8; 60]).unwrap();
+        let head = log.append(b"b", &[2u8; 60]).unwrap();
+
+        assert!(!log.remove_segment(head.segment).unwrap());
+        assert!(log.remove_segment(old.segment).unwrap());
+        assert!(matches!(
+            log.read(&old),
+            Err(ValueLogError::MissingSegment(_))
+        ));
+
+        let first = log.pin();
+        std::thread::sleep(Duration::from_millis(20));
+        let second = log.pin();
+        assert!(log.is_pinned());
+        assert_eq!(log.pin_count(), 2);
+        let oldest = log.oldest_pin_age().unwrap();
+        assert!(oldest >= Duration::from_millis(20));
+        drop(first);
+        assert!(log.oldest_pin_age().unwrap() < oldest);
+        drop(second);
+        assert!(!log.is_pinned());
+        assert_eq!(log.oldest_pin_age(), None);
+    }
+}
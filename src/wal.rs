@@ -0,0 +1,694 @@
+//! Write-ahead log whose appends are buffered in memory and written and synced by a background
+//! thread, so a caller chooses per record whether to wait for it to be durable.
+
+use crate::checksum::crc32;
+use crate::config::Backpressure;
+use crate::storage::Storage;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error};
+
+/// Log sequence number: the offset in the log just past the end of a record. A record is durable
+/// once the durable LSN has reached its own.
+pub type Lsn = u64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WalOptions {
+    /// How long the background thread waits after the first record of a batch arrives before
+    /// writing it, so records appended close together share a sync. Records appended while a
+    /// sync is in progress are batched regardless.
+    pub group_commit_delay: Duration,
+    /// Appends are held back once this many bytes are waiting to be written, so writers
+    /// cannot run arbitrarily far ahead of the disk.
+    pub max_buffered_bytes: usize,
+    /// Whether a held back append blocks until there is room or fails with `Busy`.
+    pub backpressure: Backpressure,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        WalOptions {
+            group_commit_delay: Duration::ZERO,
+            max_buffered_bytes: 4 * 1024 * 1024,
+            backpressure: Backpressure::Stall,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WalError {
+    Io(std::io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    RecordTooLarge(usize),
+    /// A background write or sync failed, so no record past the durable LSN will become
+    /// durable. The log has to be reopened.
+    Failed(std::io::Error),
+    Closed,
+    /// The buffer was full and the log is set to reject appends rather than wait.
+    Busy {
+        retry_after: Duration,
+    },
+}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WalError::Io(e) => {
+                write!(f, "IO error: {}", e)
+            }
+            WalError::InvalidMagic => {
+                write!(f, "Not a write-ahead log")
+            }
+            WalError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported write-ahead log version: {}", version)
+            }
+            WalError::RecordTooLarge(size) => {
+                write!(f, "Log record of {} bytes is too large", size)
+            }
+            WalError::Failed(e) => {
+                write!(f, "Log writer failed: {}", e)
+            }
+            WalError::Closed => {
+                write!(f, "Log is closed")
+            }
+            WalError::Busy { retry_after } => {
+                write!(f, "Log buffer is full, retry after {:?}", retry_after)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for WalError {
+    fn from(err: std::io::Error) -> WalError {
+        WalError::Io(err)
+    }
+}
+
+/// A record read back from the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    pub lsn: Lsn,
+    pub payload: Vec<u8>,
+}
+
+const MAGIC: [u8; 4] = *b"CLWL";
+const VERSION: u16 = 1;
+// magic, version and two reserved bytes
+const LOG_HEADER_SIZE: u64 = 8;
+// payload length and CRC-32 of the payload
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Reads every complete record of the log in `storage`, in the order they were appended. Reading
+/// stops at the first record that is cut short or fails its checksum, which is where a crash
+/// during a write leaves the log.
+pub fn read_log<S: Storage + ?Sized>(storage: &S) -> Result<Vec<WalRecord>, WalError> {
+    Ok(scan(storage)?.0)
+}
+
+// The records, and the offset past the last of them
+fn scan<S: Storage + ?Sized>(storage: &S) -> Result<(Vec<WalRecord>, Lsn), WalError> {
+    let size = storage.size()?;
+    if size == 0 {
+        return Ok((Vec::new(), LOG_HEADER_SIZE));
+    }
+    let mut header = [0u8; LOG_HEADER_SIZE as usize];
+    if size < LOG_HEADER_SIZE {
+        return Err(WalError::InvalidMagic);
+    }
+    storage.read_exact_at(&mut header, 0)?;
+    if header[0..4] != MAGIC {
+        return Err(WalError::InvalidMagic);
+    }
+    let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+    if version != VERSION {
+        return Err(WalError::UnsupportedVersion(version));
+    }
+
+    let mut records = Vec::new();
+    let mut offset = LOG_HEADER_SIZE;
+    while offset + RECORD_HEADER_SIZE as u64 <= size {
+        let mut record_header = [0u8; RECORD_HEADER_SIZE];
+        storage.read_exact_at(&mut record_header, offset)?;
+        let length = u32::from_le_bytes(record_header[0..4].try_into().unwrap()) as u64;
+        let checksum = u32::from_le_bytes(record_header[4..8].try_into().unwrap());
+        let end = offset + RECORD_HEADER_SIZE as u64 + length;
+        if end > size {
+            break;
+        }
+
+        let mut payload = vec![0u8; length as usize];
+        storage.read_exact_at(&mut payload, offset + RECORD_HEADER_SIZE as u64)?;
+        if crc32(&payload) != checksum {
+            break;
+        }
+        records.push(WalRecord { lsn: end, payload });
+        offset = end;
+    }
+    Ok((records, offset))
+}
+
+struct State {
+    // Framed records appended but not yet handed to the background thread
+    buffer: Vec<u8>,
+    appended: Lsn,
+    durable: Lsn,
+    failed: Option<(ErrorKind, String)>,
+    closing: bool,
+    wakers: Vec<Waker>,
+}
+
+impl State {
+    fn failure(&self) -> Option<WalError> {
+        self.failed
+            .as_ref()
+            .map(|(kind, reason)| WalError::Failed(std::io::Error::new(*kind, reason.clone())))
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Wakes the background thread when there is something to write or the log is closing
+    work: Condvar,
+    // Wakes callers when the durable LSN moves, buffer space frees up or the writer fails
+    progress: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Appends records to a log, writing and syncing them on a background thread.
+///
+/// `append` returns as soon as the record is buffered, with the LSN it will have; the caller can
+/// then block on `wait_durable`, await `durable`, or not wait at all and check `durable_lsn`
+/// later. Records become durable in the order they were appended. Dropping the log writes and
+/// syncs whatever is still buffered.
+pub struct Wal {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+    options: WalOptions,
+}
+
+impl Wal {
+    /// Opens the log in `storage`, creating it if the storage is empty. A partly written record
+    /// left at the end by a crash is cut off, and new records are appended after the last
+    /// complete one. Read the existing records with [`read_log`] before opening.
+    pub fn open<S: Storage + 'static>(storage: S, options: WalOptions) -> Result<Wal, WalError> {
+        let size = storage.size()?;
+        let (_, end) = scan(&storage)?;
+        if size == 0 {
+            let mut header = [0u8; LOG_HEADER_SIZE as usize];
+            header[0..4].copy_from_slice(&MAGIC);
+            header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+            storage.write_at(&header, 0)?;
+            storage.sync()?;
+        } else if size > end {
+            debug!("Dropping {} bytes of torn log tail", size - end);
+            storage.set_size(end)?;
+            storage.sync()?;
+        }
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                buffer: Vec::new(),
+                appended: end,
+                durable: end,
+                failed: None,
+                closing: false,
+                wakers: Vec::new(),
+            }),
+            work: Condvar::new(),
+            progress: Condvar::new(),
+        });
+        let worker_shared = shared.clone();
+        let worker = std::thread::spawn(move || run(storage, options, worker_shared));
+
+        Ok(Wal {
+            shared,
+            worker: Some(worker),
+            options,
+        })
+    }
+
+    /// Buffers `payload` as the next record and returns its LSN without waiting for it to be
+    /// written. Blocks only while the buffer is full, or fails with `Busy` instead if the log
+    /// rejects appends under backpressure.
+    pub fn append(&self, payload: &[u8]) -> Result<Lsn, WalError> {
+        if payload.len() > u32::MAX as usize {
+            return Err(WalError::RecordTooLarge(payload.len()));
+        }
+        let framed = RECORD_HEADER_SIZE + payload.len();
+
+        let mut state = self.shared.lock();
+        // A record larger than the whole buffer still goes in once the buffer is empty
+        while state.failed.is_none()
+            && !state.closing
+            && !state.buffer.is_empty()
+            && state.buffer.len() + framed > self.options.max_buffered_bytes
+        {
+            if let Backpressure::Reject { retry_after } = self.options.backpressure {
+                return Err(WalError::Busy { retry_after });
+            }
+            state = self
+                .shared
+                .progress
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if let Some(failure) = state.failure() {
+            return Err(failure);
+        }
+        if state.closing {
+            return Err(WalError::Closed);
+        }
+
+        state
+            .buffer
+            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        state
+            .buffer
+            .extend_from_slice(&crc32(payload).to_le_bytes());
+        state.buffer.extend_from_slice(payload);
+        state.appended += framed as u64;
+        let lsn = state.appended;
+        self.shared.work.notify_one();
+        Ok(lsn)
+    }
+
+    /// Appends `payload` and blocks until it is durable.
+    pub fn append_durable(&self, payload: &[u8]) -> Result<Lsn, WalError> {
+        let lsn = self.append(payload)?;
+        self.wait_durable(lsn)?;
+        Ok(lsn)
+    }
+
+    /// Blocks until every record up to `lsn`, which must have been returned by `append`, is
+    /// durable.
+    pub fn wait_durable(&self, lsn: Lsn) -> Result<(), WalError> {
+        let mut state = self.shared.lock();
+        while state.durable < lsn {
+            if let Some(failure) = state.failure() {
+                return Err(failure);
+            }
+            state = self
+                .shared
+                .progress
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        Ok(())
+    }
+
+    /// Resolves once every record up to `lsn`, which must have been returned by `append`, is
+    /// durable. Works with any executor; the background thread wakes the task.
+    pub fn durable(&self, lsn: Lsn) -> Durable<'_> {
+        Durable { wal: self, lsn }
+    }
+
+    /// LSN up to which every record has been written and synced.
+    pub fn durable_lsn(&self) -> Lsn {
+        self.shared.lock().durable
+    }
+
+    /// LSN of the last record appended, durable or not.
+    pub fn appended_lsn(&self) -> Lsn {
+        self.shared.lock().appended
+    }
+
+    /// Bytes appended but not yet durable.
+    pub fn backlog(&self) -> u64 {
+        let state = self.shared.lock();
+        state.appended - state.durable
+    }
+
+    /// Writes and syncs everything still buffered, stops the background thread and returns the
+    /// final durable LSN.
+    pub fn close(mut self) -> Result<Lsn, WalError> {
+        self.shutdown();
+        let state = self.shared.lock();
+        match state.failure() {
+            Some(failure) => Err(failure),
+            None => Ok(state.durable),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.shared.lock().closing = true;
+        self.shared.work.notify_all();
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            error!("Log writer thread panicked");
+        }
+    }
+}
+
+impl Drop for Wal {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Future returned by [`Wal::durable`].
+pub struct Durable<'a> {
+    wal: &'a Wal,
+    lsn: Lsn,
+}
+
+impl Future for Durable<'_> {
+    type Output = Result<(), WalError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.wal.shared.lock();
+        if state.durable >= self.lsn {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(failure) = state.failure() {
+            return Poll::Ready(Err(failure));
+        }
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+fn run<S: Storage>(storage: S, options: WalOptions, shared: Arc<Shared>) {
+    loop {
+        let mut state = shared.lock();
+        while state.buffer.is_empty() && !state.closing {
+            state = shared
+                .work
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if state.buffer.is_empty() {
+            break;
+        }
+        if !state.closing && !options.group_commit_delay.is_zero() {
+            drop(state);
+            std::thread::sleep(options.group_commit_delay);
+            state = shared.lock();
+        }
+
+        let batch = std::mem::take(&mut state.buffer);
+        let offset = state.durable;
+        // Appends blocked on a full buffer can go ahead while this batch is written
+        shared.progress.notify_all();
+        drop(state);
+
+        let result = storage
+            .write_at(&batch, offset)
+            .and_then(|()| storage.sync());
+
+        let mut state = shared.lock();
+        match result {
+            Ok(()) => state.durable = offset + batch.len() as u64,
+            Err(e) => {
+                error!("Log write at offset {} failed: {}", offset, e);
+                state.failed = Some((e.kind(), e.to_string()));
+            }
+        }
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        shared.progress.notify_all();
+        if state.failed.is_some() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    use tempfile::NamedTempFile;
+
+    // Runs a future to completion on the current thread, parking between polls
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    // Storage whose writes start failing once `fail` is set
+    struct FailingStorage {
+        inner: MemoryStorage,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl Storage for FailingStorage {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+            self.inner.read_at(buf, offset)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("disk gone"));
+            }
+            self.inner.write_at(buf, offset)
+        }
+
+        fn size(&self) -> std::io::Result<u64> {
+            self.inner.size()
+        }
+
+        fn set_size(&self, size: u64) -> std::io::Result<()> {
+            self.inner.set_size(size)
+        }
+
+        fn sync(&self) -> std::io::Result<()> {
+            self.inner.sync()
+        }
+    }
+
+    fn payloads(records: &[WalRecord]) -> Vec<Vec<u8>> {
+        records
+            .iter()
+            .map(|record| record.payload.clone())
+            .collect()
+    }
+
+    #[test_log::test]
+    fn records_read_back_in_append_order() {
+        let file = NamedTempFile::new().unwrap();
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+
+        let mut lsns = Vec::new();
+        for i in 0..50u32 {
+            lsns.push(wal.append(&i.to_le_bytes()).unwrap());
+        }
+        wal.append_durable(b"last").unwrap();
+        assert!(lsns.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let records = read_log(&file.reopen().unwrap()).unwrap();
+        assert_eq!(records.len(), 51);
+        assert_eq!(records[7].payload, 7u32.to_le_bytes());
+        assert_eq!(records[7].lsn, lsns[7]);
+        assert_eq!(records[50].payload, b"last");
+    }
+
+    #[test_log::test]
+    fn fire_and_forget_records_become_durable() {
+        let file = NamedTempFile::new().unwrap();
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+
+        let mut last = 0;
+        for i in 0..100u32 {
+            last = wal.append(&i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(wal.appended_lsn(), last);
+
+        wal.wait_durable(last).unwrap();
+        assert!(wal.durable_lsn() >= last);
+        assert_eq!(wal.backlog(), 0);
+    }
+
+    #[test_log::test]
+    fn durable_future_resolves_once_synced() {
+        let file = NamedTempFile::new().unwrap();
+        let options = WalOptions {
+            group_commit_delay: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let wal = Wal::open(file.reopen().unwrap(), options).unwrap();
+
+        let lsn = wal.append(b"record").unwrap();
+        block_on(wal.durable(lsn)).unwrap();
+        assert!(wal.durable_lsn() >= lsn);
+    }
+
+    #[test_log::test]
+    fn concurrent_appends_are_all_logged() {
+        let file = NamedTempFile::new().unwrap();
+        let options = WalOptions {
+            max_buffered_bytes: 256,
+            ..Default::default()
+        };
+        let wal = Wal::open(file.reopen().unwrap(), options).unwrap();
+
+        std::thread::scope(|scope| {
+            for thread in 0..4u8 {
+                let wal = &wal;
+                scope.spawn(move || {
+                    for i in 0..100u8 {
+                        let lsn = wal.append(&[thread, i]).unwrap();
+                        if i % 10 == 0 {
+                            wal.wait_durable(lsn).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        wal.close().unwrap();
+
+        let records = read_log(&file.reopen().unwrap()).unwrap();
+        assert_eq!(records.len(), 400);
+        for thread in 0..4u8 {
+            let own: Vec<u8> = records
+                .iter()
+                .filter(|record| record.payload[0] == thread)
+                .map(|record| record.payload[1])
+                .collect();
+            assert_eq!(own, (0..100u8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test_log::test]
+    fn closing_writes_buffered_records() {
+        let file = NamedTempFile::new().unwrap();
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+        for i in 0..20u32 {
+            wal.append(&i.to_le_bytes()).unwrap();
+        }
+        let lsn = wal.appended_lsn();
+        assert_eq!(wal.close().unwrap(), lsn);
+
+        assert_eq!(read_log(&file.reopen().unwrap()).unwrap().len(), 20);
+    }
+
+    #[test_log::test]
+    fn torn_tail_is_dropped_on_reopen() {
+        let file = NamedTempFile::new().unwrap();
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+        wal.append(b"first").unwrap();
+        let end = wal.append_durable(b"second").unwrap();
+        drop(wal);
+
+        // Half of a third record, as a crash mid-write would leave it
+        let storage = file.reopen().unwrap();
+        storage.write_at(&100u32.to_le_bytes(), end).unwrap();
+        storage.write_at(b"thi", end + 8).unwrap();
+        assert_eq!(read_log(&storage).unwrap().len(), 2);
+
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+        assert_eq!(wal.durable_lsn(), end);
+        wal.append_durable(b"third").unwrap();
+        drop(wal);
+
+        let records = read_log(&file.reopen().unwrap()).unwrap();
+        assert_eq!(
+            payloads(&records),
+            vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]
+        );
+    }
+
+    #[test_log::test]
+    fn corrupted_record_ends_the_log() {
+        let file = NamedTempFile::new().unwrap();
+        let wal = Wal::open(file.reopen().unwrap(), WalOptions::default()).unwrap();
+        let first = wal.append(b"first").unwrap();
+        wal.append(b"second").unwrap();
+        wal.close().unwrap();
+
+        let storage = file.reopen().unwrap();
+        storage.write_at(b"X", first + 8).unwrap();
+        assert_eq!(
+            payloads(&read_log(&storage).unwrap()),
+            vec![b"first".to_vec()]
+        );
+    }
+
+    #[test_log::test]
+    fn write_failure_is_reported_to_waiters_and_later_appends() {
+        let fail = Arc::new(AtomicBool::new(false));
+        let storage = FailingStorage {
+            inner: MemoryStorage::new(),
+            fail: fail.clone(),
+        };
+        let wal = Wal::open(storage, WalOptions::default()).unwrap();
+        let durable = wal.append_durable(b"kept").unwrap();
+
+        fail.store(true, Ordering::SeqCst);
+        let lsn = wal.append(b"lost").unwrap();
+        assert!(matches!(wal.wait_durable(lsn), Err(WalError::Failed(_))));
+        assert!(matches!(
+            block_on(wal.durable(lsn)),
+            Err(WalError::Failed(_))
+        ));
+        assert!(matches!(wal.append(b"more"), Err(WalError::Failed(_))));
+        assert_eq!(wal.durable_lsn(), durable);
+        assert!(wal.close().is_err());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let storage = MemoryStorage::new();
+        storage.write_at(b"not a log file", 0).unwrap();
+        assert!(matches!(read_log(&storage), Err(WalError::InvalidMagic)));
+        assert!(matches!(
+            Wal::open(storage, WalOptions::default()),
+            Err(WalError::InvalidMagic)
+        ));
+    }
+
+    #[test_log::test]
+    fn full_buffer_rejects_appends_under_backpressure() {
+        let file = NamedTempFile::new().unwrap();
+        let retry_after = Duration::from_millis(5);
+        let options = WalOptions {
+            group_commit_delay: Duration::from_millis(200),
+            max_buffered_bytes: 64,
+            backpressure: Backpressure::Reject { retry_after },
+        };
+        let wal = Wal::open(file.reopen().unwrap(), options).unwrap();
+
+        // The first record waits out the group commit delay in the buffer
+        let lsn = wal.append(&[1u8; 40]).unwrap();
+        assert!(matches!(
+            wal.append(&[2u8; 40]),
+            Err(WalError::Busy { retry_after: hint }) if hint == retry_after
+        ));
+
+        wal.wait_durable(lsn).unwrap();
+        let lsn = wal.append(&[3u8; 40]).unwrap();
+        wal.wait_durable(lsn).unwrap();
+        let records = read_log(&file.reopen().unwrap()).unwrap();
+        assert_eq!(payloads(&records), vec![vec![1u8; 40], vec![3u8; 40]]);
+    }
+}
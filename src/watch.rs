@@ -0,0 +1,257 @@
+//! Subscriptions to changes of a key, a range of keys or a prefix, delivered over a channel
+//! once each write has been applied, so a configuration store can react to changes without a
+//! pub/sub system of its own.
+
+use crate::btree::Lsn;
+#[cfg(not(feature = "std"))]
+use crate::sync::Mutex;
+#[cfg(not(feature = "std"))]
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ops::{Bound, RangeBounds};
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// A change to watched keys, tagged with the LSN of the write that made it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent<K, V> {
+    /// `key` was inserted with `value`, or its value was replaced by it.
+    Put { lsn: Lsn, key: K, value: V },
+    /// `key` was deleted; `value` is what it held.
+    Deleted { lsn: Lsn, key: K, value: V },
+    /// Keys were removed without being listed one by one, by `clear` or `quarantine`, so
+    /// whatever is watched should be read again.
+    Reset { lsn: Lsn },
+}
+
+/// Receives the changes to the keys it was created for by
+/// [`BTree::watch`](crate::BTree::watch) and its siblings. Events queue up until received;
+/// dropping the handle ends the subscription. Without `std` there is nothing to block on, so
+/// only the methods that don't wait are there.
+pub struct Watch<K, V> {
+    #[cfg(feature = "std")]
+    receiver: Receiver<WatchEvent<K, V>>,
+    #[cfg(not(feature = "std"))]
+    queue: Arc<Queue<K, V>>,
+}
+
+// Without std's channels, events are pushed onto a queue the handle owns and the tree only
+// holds weakly, so dropping the handle still ends the subscription
+#[cfg(not(feature = "std"))]
+type Queue<K, V> = Mutex<VecDeque<WatchEvent<K, V>>>;
+
+impl<K, V> Watch<K, V> {
+    /// Waits for the next change, or returns `None` once the tree has been dropped.
+    #[cfg(feature = "std")]
+    pub fn recv(&self) -> Option<WatchEvent<K, V>> {
+        self.receiver.recv().ok()
+    }
+
+    /// The next change if one is waiting.
+    pub fn try_recv(&self) -> Option<WatchEvent<K, V>> {
+        #[cfg(feature = "std")]
+        return self.receiver.try_recv().ok();
+        #[cfg(not(feature = "std"))]
+        return self.queue.lock().pop_front();
+    }
+
+    /// Waits up to `timeout` for the next change.
+    #[cfg(feature = "std")]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<WatchEvent<K, V>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Every change waiting, without blocking.
+    pub fn pending(&self) -> Vec<WatchEvent<K, V>> {
+        #[cfg(feature = "std")]
+        return self.receiver.try_iter().collect();
+        #[cfg(not(feature = "std"))]
+        return self.queue.lock().drain(..).collect();
+    }
+}
+
+pub(crate) enum KeyFilter<K> {
+    Range(Bound<K>, Bound<K>),
+    // Matched against the key's `to_string`, which for string keys is the key itself
+    Prefix(String),
+}
+
+impl<K: PartialOrd + ToString> KeyFilter<K> {
+    fn matches(&self, key: &K) -> bool {
+        match self {
+            KeyFilter::Range(start, end) => (start.as_ref(), end.as_ref()).contains(key),
+            KeyFilter::Prefix(prefix) => key.to_string().starts_with(prefix.as_str()),
+        }
+    }
+}
+
+struct Watcher<K, V> {
+    filter: KeyFilter<K>,
+    #[cfg(feature = "std")]
+    sender: Sender<WatchEvent<K, V>>,
+    #[cfg(not(feature = "std"))]
+    queue: Weak<Queue<K, V>>,
+}
+
+impl<K, V> Watcher<K, V> {
+    // Whether the event was delivered, rather than the handle having been dropped
+    #[cfg(feature = "std")]
+    fn deliver(&self, event: WatchEvent<K, V>) -> bool {
+        self.sender.send(event).is_ok()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn deliver(&self, event: WatchEvent<K, V>) -> bool {
+        let Some(queue) = self.queue.upgrade() else {
+            return false;
+        };
+        queue.lock().push_back(event);
+        true
+    }
+}
+
+/// The open watches of a tree. Watches whose handle has been dropped are forgotten the next
+/// time a change is sent to them.
+pub(crate) struct Watchers<K, V> {
+    watchers: Vec<Watcher<K, V>>,
+}
+
+impl<K, V> Default for Watchers<K, V> {
+    fn default() -> Self {
+        Watchers {
+            watchers: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> Watchers<K, V>
+where
+    K: Clone + PartialOrd + ToString,
+    V: Clone,
+{
+    pub(crate) fn add(&mut self, filter: KeyFilter<K>) -> Watch<K, V> {
+        #[cfg(feature = "std")]
+        {
+            let (sender, receiver) = mpsc::channel();
+            self.watchers.push(Watcher { filter, sender });
+            Watch { receiver }
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let weak = Arc::downgrade(&queue);
+            self.watchers.push(Watcher {
+                filter,
+                queue: weak,
+            });
+            Watch { queue }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.watchers.len()
+    }
+
+    /// Whether a change to `key` would be sent anywhere, so callers only keep copies of the
+    /// keys and values of writes someone watches.
+    pub(crate) fn watching(&self, key: &K) -> bool {
+        self.watchers.iter().any(|w| w.filter.matches(key))
+    }
+
+    pub(crate) fn put(&mut self, lsn: Lsn, key: K, value: V) {
+        self.send(WatchEvent::Put { lsn, key, value });
+    }
+
+    pub(crate) fn deleted(&mut self, lsn: Lsn, key: K, value: V) {
+        self.send(WatchEvent::Deleted { lsn, key, value });
+    }
+
+    pub(crate) fn reset(&mut self, lsn: Lsn) {
+        self.send(WatchEvent::Reset { lsn });
+    }
+
+    // Sends `event` to the watchers of its key, or to every watcher if it has none
+    fn send(&mut self, event: WatchEvent<K, V>) {
+        let key = match &event {
+            WatchEvent::Put { key, .. } | WatchEvent::Deleted { key, .. } => Some(key),
+            WatchEvent::Reset { .. } => None,
+        };
+        self.watchers.retain(|watcher| {
+            if key.is_some_and(|key| !watcher.filter.matches(key)) {
+                return true;
+            }
+            watcher.deliver(event.clone())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTree;
+
+    #[test]
+    fn watches_see_only_the_keys_they_cover() {
+        let mut btree = BTree::<u64, String>::temporary(512).unwrap();
+        let key = btree.watch(7);
+        let range = btree.watch_range(10..20);
+        for i in 0..30 {
+            btree.insert(i, format!("value-{}", i)).unwrap();
+        }
+        btree.delete(15).unwrap();
+
+        assert_eq!(
+            key.pending(),
+            [WatchEvent::Put {
+                lsn: 8,
+                key: 7,
+                value: "value-7".to_string()
+            }]
+        );
+        let events = range.pending();
+        assert_eq!(events.len(), 11);
+        assert_eq!(
+            events[10],
+            WatchEvent::Deleted {
+                lsn: 31,
+                key: 15,
+                value: "value-15".to_string()
+            }
+        );
+
+        btree.clear().unwrap();
+        assert_eq!(key.pending(), [WatchEvent::Reset { lsn: 32 }]);
+    }
+
+    #[test]
+    fn dropped_watches_are_forgotten() {
+        let mut btree = BTree::<String, u64>::temporary(512).unwrap();
+        let config = btree.watch_prefix("config/");
+        let dropped = btree.watch_prefix("config/");
+        drop(dropped);
+
+        btree
+            .bulk_load([("a".to_string(), 0), ("config/x".to_string(), 1)])
+            .unwrap();
+        btree.insert("config/y".to_string(), 2).unwrap();
+        btree.insert("other".to_string(), 3).unwrap();
+        assert_eq!(btree.watch_count(), 1);
+
+        let keys: Vec<String> = config
+            .pending()
+            .into_iter()
+            .map(|event| match event {
+                WatchEvent::Put { key, .. } => key,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(keys, ["config/x", "config/y"]);
+        drop(btree);
+        assert_eq!(config.recv(), None);
+    }
+}
@@ -2,8 +2,7 @@ use cloaksdb::BTree;
 use tempfile::NamedTempFile; // Uses public API only
 
 fn create_temp_btree() -> BTree<i64, String> {
-    let file = NamedTempFile::new().unwrap();
-    BTree::new(file.reopen().unwrap(), 4096).unwrap()
+    BTree::temporary(4096).unwrap()
 }
 
 #[test]
@@ -25,6 +24,7 @@ fn persistence_across_reopen() {
     {
         let f = std::fs::OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&path)
@@ -0,0 +1,142 @@
+use bincode1::Options;
+use cloaksdb::BTree;
+use cloaksdb::codec::Encoding;
+use cloaksdb::config::TreeConfig;
+use cloaksdb::error::BTreeError;
+use cloaksdb::header::Header;
+use cloaksdb::slotted_page::SlottedPage;
+use cloaksdb::types::NodeType;
+use proptest::prelude::*;
+use std::collections::BTreeMap; // Uses public API only
+
+const PAGE_SIZE: usize = 256;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u16, String),
+    Delete(u16),
+    Flush,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0..500u16, "[a-z]{0,40}").prop_map(|(key, value)| Op::Insert(key, value)),
+        2 => (0..500u16).prop_map(Op::Delete),
+        1 => Just(Op::Flush),
+    ]
+}
+
+// A serialized page holding `entries`, with each of `damage` then written over it
+fn damaged_page(entries: &[(i64, String)], damage: &[(usize, u8)]) -> Vec<u8> {
+    let mut page: SlottedPage<i64, String> = SlottedPage::new(7, NodeType::LEAF, PAGE_SIZE);
+    for (key, value) in entries {
+        let pos = page.find_key_position(key).unwrap();
+        if page.insert(pos, key, value).is_err() {
+            break;
+        }
+    }
+    let mut bytes = page.serialize().unwrap();
+    for &(at, byte) in damage {
+        bytes[at % PAGE_SIZE] = byte;
+    }
+    bytes
+}
+
+// Every shape of value a tree stores, to hold each encoding to the bytes bincode 1 wrote
+type Record = (i64, u16, bool, String, Option<Vec<u8>>, Vec<(u32, String)>);
+
+fn record() -> impl Strategy<Value = Record> {
+    (
+        any::<i64>(),
+        any::<u16>(),
+        any::<bool>(),
+        "[a-z]{0,40}",
+        proptest::option::of(proptest::collection::vec(any::<u8>(), 0..40)),
+        proptest::collection::vec((any::<u32>(), "[a-z]{0,8}"), 0..6),
+    )
+}
+
+proptest! {
+    #[test]
+    fn encodings_write_the_bytes_bincode_1_wrote(record in record()) {
+        let fixed = bincode1::serialize(&record).unwrap();
+        prop_assert_eq!(Encoding::Bincode.serialize(&record).unwrap(), fixed.clone());
+        prop_assert_eq!(Encoding::Bincode.serialized_size(&record).unwrap(), fixed.len() as u64);
+        prop_assert_eq!(Encoding::Bincode.deserialize::<Record>(&fixed).unwrap(), record.clone());
+
+        let varint = bincode1::DefaultOptions::new().serialize(&record).unwrap();
+        prop_assert_eq!(Encoding::BincodeVarint.serialize(&record).unwrap(), varint.clone());
+        prop_assert_eq!(Encoding::BincodeVarint.deserialize::<Record>(&varint).unwrap(), record);
+    }
+
+    #[test]
+    fn header_decodes_arbitrary_bytes_without_panicking(
+        bytes in proptest::collection::vec(any::<u8>(), 0..2 * Header::SIZE)
+    ) {
+        if let Ok(header) = Header::deserialize(&bytes) {
+            let again = Header::deserialize(&header.serialize()).unwrap();
+            prop_assert_eq!(format!("{:?}", again), format!("{:?}", header));
+        }
+    }
+
+    #[test]
+    fn page_decodes_arbitrary_bytes_without_panicking(
+        bytes in proptest::collection::vec(any::<u8>(), PAGE_SIZE)
+    ) {
+        let _ = SlottedPage::<i64, String>::deserialize(&bytes, PAGE_SIZE);
+    }
+
+    #[test]
+    fn damaged_page_is_rejected_or_readable(
+        entries in proptest::collection::vec((any::<i64>(), "[a-z]{0,16}"), 0..12),
+        damage in proptest::collection::vec((any::<usize>(), any::<u8>()), 1..6),
+    ) {
+        let bytes = damaged_page(&entries, &damage);
+        let Ok(page) = SlottedPage::<i64, String>::deserialize(&bytes, PAGE_SIZE) else {
+            return Ok(());
+        };
+
+        // Every entry of a page that decodes can be read, even if only to an error
+        for idx in 0..page.slots.len() {
+            let _ = page.read_key_value(idx);
+        }
+        if let Ok(bytes) = page.serialize() {
+            prop_assert!(SlottedPage::<i64, String>::deserialize(&bytes, PAGE_SIZE).is_ok());
+        }
+    }
+
+    #[test]
+    fn tree_matches_a_map_after_random_operations(
+        ops in proptest::collection::vec(op(), 1..300),
+        subtree_counts in any::<bool>(),
+    ) {
+        let config = TreeConfig {
+            subtree_counts,
+            ..TreeConfig::with_page_size(PAGE_SIZE as u64)
+        };
+        let mut btree = BTree::<u16, String>::in_memory(config).unwrap();
+        let mut model = BTreeMap::new();
+
+        for op in ops {
+            match op {
+                Op::Insert(key, value) => {
+                    btree.insert(key, value.clone()).unwrap();
+                    model.insert(key, value);
+                }
+                Op::Delete(key) => match (btree.delete(key), model.remove(&key)) {
+                    (Ok(got), Some(expected)) => prop_assert_eq!(got, expected),
+                    (Err(BTreeError::KeyNotFound(_)), None) => {}
+                    (got, expected) => {
+                        prop_assert!(false, "delete {} gave {:?}, expected {:?}", key, got, expected)
+                    }
+                },
+                Op::Flush => btree.flush().unwrap(),
+            }
+        }
+
+        btree.verify().unwrap();
+        prop_assert_eq!(btree.len(), model.len() as u64);
+        let entries: Vec<(u16, String)> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+        prop_assert_eq!(entries, model.into_iter().collect::<Vec<_>>());
+    }
+}
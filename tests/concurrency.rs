@@ -0,0 +1,189 @@
+//! Readers and a writer sharing one tree from several threads, checked against a model of
+//! what the writer has done. Slow, so only built with the `concurrency-tests` feature:
+//!
+//!     cargo test --release --features concurrency-tests --test concurrency -- --nocapture
+
+use cloaksdb::BTree;
+use cloaksdb::bench::{self, BenchOptions, Workload};
+use cloaksdb::config::TreeConfig;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tempfile::TempDir; // Uses public API only
+
+const KEYS: u64 = 500;
+const WRITES: u64 = 20_000;
+const READERS: usize = 4;
+
+type Shared = Arc<Mutex<BTree<u64, String>>>;
+
+// Each key holds the version the writer last wrote to it. The writer alone changes a key, and
+// announces each version in `started` before writing it and in `completed` once written, so a
+// read of a key that linearizes sees a version between what was completed when the read began
+// and what was started when it ended.
+struct Model {
+    started: Vec<AtomicU64>,
+    completed: Vec<AtomicU64>,
+}
+
+impl Model {
+    fn new() -> Self {
+        let versions = || (0..KEYS).map(|_| AtomicU64::new(0)).collect();
+        Model {
+            started: versions(),
+            completed: versions(),
+        }
+    }
+}
+
+// Values are padded to a size that varies with the version, so overwrites move entries around
+// and split and merge pages
+fn value(version: u64) -> String {
+    format!("{:08}{}", version, "x".repeat((version % 40) as usize))
+}
+
+fn version_of(value: &str) -> u64 {
+    value[..8].parse().unwrap()
+}
+
+fn shared_tree() -> (Shared, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shared.db");
+    let tree = BTree::open_shared(&path, TreeConfig::with_page_size(512)).unwrap();
+    {
+        let mut tree = tree.lock().unwrap();
+        for key in 0..KEYS {
+            tree.insert(key, value(0)).unwrap();
+        }
+    }
+    (tree, dir)
+}
+
+// Overwrites random keys with rising versions until `WRITES` are done, then sets `done`
+fn write(tree: &Shared, model: &Model, done: &AtomicBool) {
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..WRITES {
+        let key = rng.random_range(0..KEYS) as usize;
+        let version = model.completed[key].load(Ordering::SeqCst) + 1;
+        // Completed before the lock is released, so whoever takes it next sees the model
+        // match the tree
+        let mut tree = tree.lock().unwrap();
+        model.started[key].store(version, Ordering::SeqCst);
+        tree.insert(key as u64, value(version)).unwrap();
+        model.completed[key].store(version, Ordering::SeqCst);
+    }
+    done.store(true, Ordering::SeqCst);
+}
+
+#[test]
+fn point_reads_are_linearizable_with_one_writer() {
+    let (tree, _dir) = shared_tree();
+    let model = Arc::new(Model::new());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers: Vec<_> = (0..READERS)
+        .map(|reader| {
+            let (tree, model, done) = (tree.clone(), model.clone(), done.clone());
+            thread::spawn(move || {
+                let mut rng = StdRng::seed_from_u64(reader as u64);
+                let mut reads = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let key = rng.random_range(0..KEYS) as usize;
+                    let low = model.completed[key].load(Ordering::SeqCst);
+                    let read = tree.lock().unwrap().search(key as u64);
+                    let high = model.started[key].load(Ordering::SeqCst);
+                    let version = version_of(&read.unwrap());
+                    assert!(
+                        (low..=high).contains(&version),
+                        "key {} read version {}, outside {}..={}",
+                        key,
+                        version,
+                        low,
+                        high
+                    );
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+    write(&tree, &model, &done);
+    let reads: u64 = readers.into_iter().map(|r| r.join().unwrap()).sum();
+    assert!(reads > 0);
+
+    let mut tree = tree.lock().unwrap();
+    tree.verify().unwrap();
+    for key in 0..KEYS {
+        let completed = model.completed[key as usize].load(Ordering::SeqCst);
+        assert_eq!(version_of(&tree.search(key).unwrap()), completed);
+    }
+}
+
+#[test]
+fn snapshots_hold_the_versions_written_before_they_were_taken() {
+    let (tree, _dir) = shared_tree();
+    let model = Arc::new(Model::new());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let (tree, model, done) = (tree.clone(), model.clone(), done.clone());
+            thread::spawn(move || {
+                let mut snapshots = 0;
+                while !done.load(Ordering::SeqCst) {
+                    // The writer cannot be part way through a write while the tree is locked
+                    let (snapshot, expected) = {
+                        let mut tree = tree.lock().unwrap();
+                        let expected: Vec<u64> = model
+                            .completed
+                            .iter()
+                            .map(|version| version.load(Ordering::SeqCst))
+                            .collect();
+                        (tree.freeze().unwrap(), expected)
+                    };
+                    // Read while the writer carries on
+                    let versions: Vec<u64> = snapshot
+                        .iter()
+                        .unwrap()
+                        .map(|entry| version_of(&entry.unwrap().1))
+                        .collect();
+                    assert_eq!(versions, expected);
+                    snapshots += 1;
+                }
+                snapshots
+            })
+        })
+        .collect();
+    write(&tree, &model, &done);
+    let snapshots: u64 = readers.into_iter().map(|r| r.join().unwrap()).sum();
+    assert!(snapshots > 0);
+}
+
+#[test]
+fn reads_scale_with_reader_threads() {
+    let mut last: Option<f64> = None;
+    for threads in [1, 2, 4, 8] {
+        let options = BenchOptions {
+            workloads: vec![Workload::FillSeq, Workload::ReadWhileWriting],
+            num: 5_000,
+            reads: Some(40_000),
+            value_size: 100,
+            threads,
+            ..BenchOptions::default()
+        };
+        let results = bench::run(options).unwrap();
+        let result = &results[1];
+        assert_eq!(result.found, result.ops, "{}", result);
+        // Readers and the writer take turns at the tree's lock, so reads only scale as far as
+        // they crowd out the writer. Timings vary too much between machines to assert on, so
+        // the throughput at each count is reported instead
+        println!("{} reader(s): {}", threads, result);
+        if let Some(last) = last {
+            let ratio = result.ops_per_sec() / last;
+            println!("  {:.2}x the throughput of half as many", ratio);
+        }
+        last = Some(result.ops_per_sec());
+    }
+}
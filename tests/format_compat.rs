@@ -0,0 +1,154 @@
+use cloaksdb::BTree;
+use cloaksdb::config::TreeConfig;
+use cloaksdb::scrub::ScrubOptions;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tempfile::NamedTempFile; // Uses public API only
+
+// Every fixture is written with this page size, so it can be opened without reading the header
+const PAGE_SIZE: u64 = 512;
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn fixture_path(version: u16) -> PathBuf {
+    fixtures_dir().join(format!("v{}.db", version))
+}
+
+fn canonical_config() -> TreeConfig {
+    TreeConfig {
+        subtree_counts: true,
+        ..TreeConfig::with_page_size(PAGE_SIZE)
+    }
+}
+
+// Inserted out of order with every fifth key deleted again, so the tree has several levels,
+// counts and free space to describe
+fn canonical_entries() -> BTreeMap<i64, String> {
+    (0..400)
+        .filter(|i| i % 5 != 0)
+        .map(|i| (i, format!("value-{:04}", i * 7)))
+        .collect()
+}
+
+fn write_canonical(file: std::fs::File) {
+    let mut btree = BTree::<i64, String>::with_config(file, canonical_config()).unwrap();
+    for i in (0..400).map(|i| (i * 37) % 400) {
+        btree.insert(i, format!("value-{:04}", i * 7)).unwrap();
+    }
+    for i in (0..400).step_by(5) {
+        btree.delete(i).unwrap();
+    }
+    btree.flush().unwrap();
+}
+
+// Copies the fixture so opening it cannot change the checked-in file
+fn open_fixture(version: u16) -> (BTree<i64, String>, NamedTempFile) {
+    let copy = NamedTempFile::new().unwrap();
+    std::fs::copy(fixture_path(version), copy.path()).unwrap();
+    let btree = BTree::with_config(copy.reopen().unwrap(), canonical_config()).unwrap();
+    (btree, copy)
+}
+
+fn fixture_versions() -> Vec<u16> {
+    let mut versions: Vec<u16> = std::fs::read_dir(fixtures_dir())
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().ok()?;
+            name.strip_prefix('v')?.strip_suffix(".db")?.parse().ok()
+        })
+        .collect();
+    versions.sort();
+    versions
+}
+
+/// Writes the fixture for the current format version. Run it after bumping the version:
+/// `cargo test --test format_compat -- --ignored write_current_fixture`
+#[test]
+#[ignore]
+fn write_current_fixture() {
+    let path = fixture_path(cloaksdb::format_version());
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    write_canonical(file);
+}
+
+#[test]
+fn current_version_has_a_fixture() {
+    let version = cloaksdb::format_version();
+    assert!(
+        fixture_path(version).exists(),
+        "no fixture for format version {}; run write_current_fixture",
+        version
+    );
+}
+
+#[test]
+fn current_version_writes_its_fixture_byte_for_byte() {
+    let file = NamedTempFile::new().unwrap();
+    write_canonical(file.reopen().unwrap());
+
+    let written = std::fs::read(file.path()).unwrap();
+    let fixture = std::fs::read(fixture_path(cloaksdb::format_version())).unwrap();
+    assert!(
+        written == fixture,
+        "the on-disk format changed without a new format version"
+    );
+}
+
+#[test]
+fn every_fixture_opens_and_reads_back() {
+    let versions = fixture_versions();
+    assert!(!versions.is_empty());
+
+    for version in versions {
+        let (mut btree, _copy) = open_fixture(version);
+        assert_eq!(btree.format_version(), version);
+        btree.verify().unwrap();
+
+        let entries: BTreeMap<i64, String> = btree.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, canonical_entries(), "fixture v{}", version);
+        assert_eq!(btree.len(), entries.len() as u64);
+    }
+}
+
+#[test]
+fn every_fixture_accepts_writes() {
+    for version in fixture_versions() {
+        let (mut btree, _copy) = open_fixture(version);
+        for i in 400..500 {
+            btree.insert(i, format!("value-{:04}", i * 7)).unwrap();
+        }
+        btree.delete(1).unwrap();
+
+        btree.verify().unwrap();
+        assert_eq!(btree.search(450).unwrap(), "value-3150");
+        assert!(btree.search(1).is_err());
+    }
+}
+
+#[test]
+fn every_fixture_scrubs_clean() {
+    for version in fixture_versions() {
+        let (mut btree, _copy) = open_fixture(version);
+        let report = btree.scrub(ScrubOptions::default()).unwrap();
+
+        assert!(
+            report.is_clean(),
+            "fixture v{}: {:?}",
+            version,
+            report.damaged
+        );
+        // Pages of fixtures from before checksums can only have their structure checked
+        match version < 10 {
+            true => assert_eq!(report.pages_checksummed, 0, "fixture v{}", version),
+            false => assert_eq!(report.pages_without_checksum, 0, "fixture v{}", version),
+        }
+    }
+}
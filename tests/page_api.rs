@@ -0,0 +1,26 @@
+use cloaksdb::free_space::FitPolicy;
+use cloaksdb::slotted_page::SlottedPage; // Uses public API only
+
+// A page used as a tiny sorted table of its own, with no tree around it
+#[test]
+fn pages_work_without_a_tree() {
+    let mut page = SlottedPage::<u32, String>::builder(1, 1024)
+        .with_free_list_policy(FitPolicy::FirstFit, 8)
+        .build();
+    let mut stored = 0;
+    while page
+        .insert_sorted(&(stored * 7 % 50), &format!("row-{}", stored))
+        .is_ok()
+    {
+        stored += 1;
+    }
+    assert!(stored > 10);
+    assert!(page.free_space() < 32);
+
+    let bytes = page.serialize().unwrap();
+    let read = SlottedPage::<u32, String>::from_buffer(bytes).unwrap();
+    let keys: Vec<u32> = read.iter().map(|e| e.unwrap().0).collect();
+    assert_eq!(keys.len(), stored as usize);
+    assert!(keys.is_sorted());
+    assert_eq!(read.get(&7).unwrap(), Some("row-1".to_string()));
+}